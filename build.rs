@@ -0,0 +1,26 @@
+//! Writes the C header of the `capi` functions.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "capi")]
+    capi_header();
+}
+
+/// Write `include/myox_tcp.h` from `src/capi.rs`, as set up in `cbindgen.toml`. The file is
+/// only touched when its contents change.
+#[cfg(feature = "capi")]
+fn capi_header() {
+    let root = std::path::PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let config_path = root.join("cbindgen.toml");
+    let source = root.join("src").join("capi.rs");
+    println!("cargo:rerun-if-changed={}", config_path.display());
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let config = cbindgen::Config::from_file(&config_path).expect("cbindgen.toml is invalid");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(source)
+        .generate()
+        .expect("src/capi.rs could not be turned into a header")
+        .write_to_file(root.join("include").join("myox_tcp.h"));
+}
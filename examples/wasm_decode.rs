@@ -0,0 +1,165 @@
+//! Decode hex-pasted Ethernet frames in the browser.
+//!
+//! Only the parsers are used, so this builds for `wasm32-unknown-unknown`:
+//!
+//!     cargo build --release --target wasm32-unknown-unknown --example wasm_decode
+//!     cp target/wasm32-unknown-unknown/release/examples/wasm_decode.wasm examples/
+//!
+//! then serve the `examples` directory and open `wasm_decode.html`. The page writes the
+//! pasted text into the buffer returned by `input`, calls `decode` with its length and
+//! reads back as many bytes of text from `output`.
+
+use myox_tcp::{
+    arp::{
+        arp_new::ArpPacket,
+        ether::{EtherTypes, EthernetPacket, Packet},
+    },
+    ipv4::{self, ChecksumMode, ChecksumStatus},
+};
+use std::{cell::RefCell, fmt::Write, net::Ipv4Addr};
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+thread_local! {
+    static INPUT: RefCell<Vec<u8>> = RefCell::new(Vec::new());
+    static OUTPUT: RefCell<String> = RefCell::new(String::new());
+}
+
+/// A buffer of `len` bytes for the text to decode.
+#[no_mangle]
+pub extern "C" fn input(len: usize) -> *mut u8 {
+    INPUT.with(|input| {
+        let mut input = input.borrow_mut();
+        input.clear();
+        input.resize(len, 0);
+        input.as_mut_ptr()
+    })
+}
+
+/// Decode the first `len` bytes of the input buffer, returning the length of the text
+/// written to the output buffer.
+#[no_mangle]
+pub extern "C" fn decode(len: usize) -> usize {
+    let text = INPUT.with(|input| {
+        let input = input.borrow();
+        String::from_utf8_lossy(&input[..len.min(input.len())]).into_owned()
+    });
+    let description = match parse_hex(&text) {
+        Ok(frame) => describe(&frame),
+        Err(e) => e,
+    };
+    OUTPUT.with(|output| {
+        *output.borrow_mut() = description;
+        output.borrow().len()
+    })
+}
+
+/// The text written by the last call to `decode`.
+#[no_mangle]
+pub extern "C" fn output() -> *const u8 {
+    OUTPUT.with(|output| output.borrow().as_ptr())
+}
+
+// hex digits, ignoring whitespace and the separators of common dump formats
+fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .ok_or_else(|| format!("not a hex digit: {:?}", c))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits".to_owned());
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+fn describe(frame: &[u8]) -> String {
+    let mut out = String::new();
+    let ethernet = match EthernetPacket::new(frame) {
+        Some(ethernet) => ethernet,
+        None => return format!("{} bytes, too short for Ethernet", frame.len()),
+    };
+    let ethertype = ethernet.get_ethertype();
+    let _ = writeln!(
+        out,
+        "ethernet {} > {}, {} ({:#06x}), {} bytes",
+        ethernet.get_source(),
+        ethernet.get_destination(),
+        ethertype,
+        ethertype.value(),
+        frame.len()
+    );
+    let payload = ethernet.payload();
+    if ethertype == EtherTypes::Arp {
+        match ArpPacket::new(payload) {
+            Some(arp) => {
+                let _ = writeln!(
+                    out,
+                    "arp operation {}, {} at {} asks for {} at {}",
+                    arp.get_operation().0,
+                    arp.get_sender_proto_addr(),
+                    arp.get_sender_hw_addr(),
+                    arp.get_target_proto_addr(),
+                    arp.get_target_hw_addr()
+                );
+            }
+            None => out.push_str("arp truncated\n"),
+        }
+    } else if ethertype == EtherTypes::Ipv4 {
+        describe_ipv4(payload, &mut out);
+    }
+    out
+}
+
+fn describe_ipv4(packet: &[u8], out: &mut String) {
+    let header_len = match ipv4::header_len(packet) {
+        Ok(len) => len,
+        Err(e) => {
+            let _ = writeln!(out, "ip {:?}", e);
+            return;
+        }
+    };
+    let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+    let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+    let _ = writeln!(
+        out,
+        "ip {} > {}, ttl {}, protocol {}, header {} bytes",
+        source, destination, packet[8], packet[9], header_len
+    );
+    if let Ok(options) = ipv4::options(packet) {
+        for option in options {
+            let _ = writeln!(out, "ip option {:?}", option);
+        }
+    }
+    match ipv4::check_checksums(packet, ChecksumMode::OffloadAware) {
+        Ok(ChecksumStatus::Valid) => out.push_str("checksums ok\n"),
+        Ok(ChecksumStatus::PossiblyOffloaded(layer)) => {
+            let _ = writeln!(out, "{} checksum possibly offloaded", layer);
+        }
+        Err(e) => {
+            let _ = writeln!(out, "checksums {:?}", e);
+        }
+    }
+    let transport = &packet[header_len..];
+    if (packet[9] == IPPROTO_TCP || packet[9] == IPPROTO_UDP) && transport.len() >= 4 {
+        let _ = writeln!(
+            out,
+            "{} port {} > {}",
+            if packet[9] == IPPROTO_TCP {
+                "tcp"
+            } else {
+                "udp"
+            },
+            u16::from_be_bytes([transport[0], transport[1]]),
+            u16::from_be_bytes([transport[2], transport[3]])
+        );
+    }
+}
@@ -0,0 +1,111 @@
+/* C interface of the myox_tcp parsers, built with the `capi` feature.
+ *
+ * Generated from src/capi.rs by build.rs with cbindgen, don't edit. */
+
+#ifndef MYOX_TCP_H
+#define MYOX_TCP_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+// A null pointer was passed.
+#define MYOX_ERR_NULL -1
+
+// The buffer ends before the header does.
+#define MYOX_ERR_TRUNCATED -2
+
+// A header field has an impossible value.
+#define MYOX_ERR_MALFORMED -3
+
+// The buffer does not hold this kind of header.
+#define MYOX_ERR_UNRECOGNIZED -4
+
+// An Ethernet II header.
+typedef struct MyoxEthernet {
+    uint8_t destination[6];
+    uint8_t source[6];
+    uint16_t ethertype;
+    size_t payload_offset;
+} MyoxEthernet;
+
+// An Ethernet/IPv4 ARP packet.
+typedef struct MyoxArp {
+    uint16_t hardware_type;
+    uint16_t protocol_type;
+    uint16_t operation;
+    uint8_t sender_mac[6];
+    uint8_t sender_ip[4];
+    uint8_t target_mac[6];
+    uint8_t target_ip[4];
+} MyoxArp;
+
+// An IPv4 header. Options are left in the buffer, between byte 20 and `header_len`.
+typedef struct MyoxIpv4 {
+    uint8_t header_len;
+    uint8_t dscp;
+    uint8_t ecn;
+    uint16_t total_len;
+    uint16_t identification;
+    // Reserved, don't fragment and more fragments bits, in this order
+    uint8_t flags;
+    uint16_t fragment_offset;
+    uint8_t ttl;
+    uint8_t protocol;
+    uint16_t checksum;
+    uint8_t source[4];
+    uint8_t destination[4];
+    size_t payload_offset;
+} MyoxIpv4;
+
+// A TCP header. Options are left in the buffer, between byte 20 and `header_len`.
+typedef struct MyoxTcp {
+    uint16_t source_port;
+    uint16_t destination_port;
+    uint32_t sequence;
+    uint32_t acknowledgment;
+    uint8_t header_len;
+    // CWR, ECE, URG, ACK, PSH, RST, SYN and FIN, from the most significant bit
+    uint8_t flags;
+    uint16_t window;
+    uint16_t checksum;
+    uint16_t urgent_pointer;
+    size_t payload_offset;
+} MyoxTcp;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parse the Ethernet header at the start of the `len` bytes at `data`.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` to a writable `MyoxEthernet`.
+int myox_parse_ethernet(const uint8_t *data, size_t len, struct MyoxEthernet *out);
+
+// Parse the ARP packet at the start of the `len` bytes at `data`.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` to a writable `MyoxArp`.
+int myox_parse_arp(const uint8_t *data, size_t len, struct MyoxArp *out);
+
+// Parse the IPv4 header at the start of the `len` bytes at `data`.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` to a writable `MyoxIpv4`.
+int myox_parse_ipv4(const uint8_t *data, size_t len, struct MyoxIpv4 *out);
+
+// Parse the TCP header at the start of the `len` bytes at `data`.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` to a writable `MyoxTcp`.
+int myox_parse_tcp(const uint8_t *data, size_t len, struct MyoxTcp *out);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* MYOX_TCP_H */
@@ -0,0 +1,139 @@
+//! Addresses of the stack API.
+//!
+//! An [`Endpoint`] is where a transport protocol sends from or to, an IP address and a
+//! port, and converts to and from `std::net::SocketAddr`. A [`HardwareAddress`] is the
+//! link-layer address of an interface, which layer 3 interfaces don't have.
+
+use crate::arp::network_interface::MacAddr;
+use std::{
+    fmt,
+    net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    str::FromStr,
+};
+
+/// An IP address and a port.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Endpoint {
+    /// The address, unspecified to match any of them
+    pub addr: IpAddr,
+    /// The port, 0 to match any of them
+    pub port: u16,
+}
+
+impl Endpoint {
+    /// Construct a new Endpoint
+    pub fn new(addr: IpAddr, port: u16) -> Endpoint {
+        Endpoint { addr, port }
+    }
+
+    /// Whether both the address and the port are given.
+    pub fn is_specified(&self) -> bool {
+        !self.addr.is_unspecified() && self.port != 0
+    }
+}
+
+impl Default for Endpoint {
+    fn default() -> Endpoint {
+        Endpoint::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0)
+    }
+}
+
+impl From<SocketAddr> for Endpoint {
+    fn from(addr: SocketAddr) -> Endpoint {
+        Endpoint::new(addr.ip(), addr.port())
+    }
+}
+
+impl From<SocketAddrV4> for Endpoint {
+    fn from(addr: SocketAddrV4) -> Endpoint {
+        Endpoint::new(IpAddr::V4(*addr.ip()), addr.port())
+    }
+}
+
+impl From<SocketAddrV6> for Endpoint {
+    fn from(addr: SocketAddrV6) -> Endpoint {
+        Endpoint::new(IpAddr::V6(*addr.ip()), addr.port())
+    }
+}
+
+impl From<(IpAddr, u16)> for Endpoint {
+    fn from((addr, port): (IpAddr, u16)) -> Endpoint {
+        Endpoint::new(addr, port)
+    }
+}
+
+impl From<(Ipv4Addr, u16)> for Endpoint {
+    fn from((addr, port): (Ipv4Addr, u16)) -> Endpoint {
+        Endpoint::new(IpAddr::V4(addr), port)
+    }
+}
+
+impl From<(Ipv6Addr, u16)> for Endpoint {
+    fn from((addr, port): (Ipv6Addr, u16)) -> Endpoint {
+        Endpoint::new(IpAddr::V6(addr), port)
+    }
+}
+
+impl From<Endpoint> for SocketAddr {
+    fn from(endpoint: Endpoint) -> SocketAddr {
+        SocketAddr::new(endpoint.addr, endpoint.port)
+    }
+}
+
+impl fmt::Display for Endpoint {
+    /// Written like a `SocketAddr`, with IPv6 addresses in brackets.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        SocketAddr::from(*self).fmt(f)
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Endpoint, AddrParseError> {
+        s.parse::<SocketAddr>().map(Endpoint::from)
+    }
+}
+
+/// The link-layer address of an interface.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum HardwareAddress {
+    /// Interfaces carrying IP packets without a link-layer header, like tun devices and
+    /// layer 3 channels
+    Ip,
+    /// An Ethernet MAC address
+    Ethernet(MacAddr),
+}
+
+impl HardwareAddress {
+    /// The MAC address, if the interface has one.
+    pub fn mac(&self) -> Option<MacAddr> {
+        match self {
+            HardwareAddress::Ip => None,
+            HardwareAddress::Ethernet(mac) => Some(*mac),
+        }
+    }
+}
+
+impl From<MacAddr> for HardwareAddress {
+    fn from(mac: MacAddr) -> HardwareAddress {
+        HardwareAddress::Ethernet(mac)
+    }
+}
+
+impl From<Option<MacAddr>> for HardwareAddress {
+    /// An interface without a MAC address, like `NetworkInterface::mac` of a tun device,
+    /// is a layer 3 one.
+    fn from(mac: Option<MacAddr>) -> HardwareAddress {
+        mac.map_or(HardwareAddress::Ip, HardwareAddress::Ethernet)
+    }
+}
+
+impl fmt::Display for HardwareAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HardwareAddress::Ip => f.write_str("none"),
+            HardwareAddress::Ethernet(mac) => mac.fmt(f),
+        }
+    }
+}
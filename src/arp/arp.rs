@@ -1,5 +1,11 @@
 use byteorder::{BigEndian, ByteOrder};
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr},
+};
+
+/// Log target of ARP packet construction.
+const LOG_TARGET: &str = "myox::arp::packet";
 
 macro_rules! enum_with_unknown {
     (
@@ -113,6 +119,7 @@ enum_with_unknown! {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     /// An operation cannot proceed because a buffer is empty or full.
     Exhausted,
@@ -125,9 +132,14 @@ pub enum Error {
 
     /// An incoming packet could not be parsed because some of its fields were out of bounds
     /// of the received data.
-    Truncated,
-    /// An incoming packet had an incorrect checksum and was dropped.
-    Checksum,
+    Truncated {
+        /// The field that didn't fit
+        field: &'static str,
+        /// Offset of the field in the data parsed
+        offset: usize,
+    },
+    /// An incoming packet had an incorrect checksum in the given layer and was dropped.
+    Checksum(crate::checksum::Layer),
     /// An incoming packet could not be recognized and was dropped.
     /// E.g. an Ethernet packet with an unknown EtherType.
     Unrecognized,
@@ -136,7 +148,12 @@ pub enum Error {
     Fragmented,
     /// An incoming packet was recognized but was self-contradictory.
     /// E.g. a TCP packet with both SYN and FIN flags set.
-    Malformed,
+    Malformed {
+        /// The field that failed validation
+        field: &'static str,
+        /// Offset of the field in the data parsed
+        offset: usize,
+    },
     /// An incoming packet was recognized but contradicted internal state.
     /// E.g. a TCP packet addressed to a socket that doesn't exist.
     Dropped,
@@ -147,6 +164,61 @@ pub enum Error {
 
 pub type Result<T> = core::result::Result<T, Error>;
 
+impl Error {
+    /// A `Truncated` error for `field` at `offset`.
+    pub fn truncated(field: &'static str, offset: usize) -> Error {
+        Error::Truncated { field, offset }
+    }
+
+    /// A `Malformed` error for `field` at `offset`.
+    pub fn malformed(field: &'static str, offset: usize) -> Error {
+        Error::Malformed { field, offset }
+    }
+
+    /// The field and offset a `Truncated` or `Malformed` error is about.
+    pub fn location(&self) -> Option<(&'static str, usize)> {
+        match *self {
+            Error::Truncated { field, offset } | Error::Malformed { field, offset } => {
+                Some((field, offset))
+            }
+            _ => None,
+        }
+    }
+
+    /// The error with its offset moved `by` bytes, for errors found parsing part of a
+    /// packet that starts `by` bytes in.
+    pub fn shifted(self, by: usize) -> Error {
+        match self {
+            Error::Truncated { field, offset } => Error::truncated(field, offset + by),
+            Error::Malformed { field, offset } => Error::malformed(field, offset + by),
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Exhausted => write!(f, "buffer space exhausted"),
+            Error::Illegal => write!(f, "illegal operation"),
+            Error::Unaddressable => write!(f, "unaddressable destination"),
+            Error::Truncated { field, offset } => {
+                write!(f, "truncated packet: {} at byte {}", field, offset)
+            }
+            Error::Checksum(layer) => write!(f, "bad {} checksum", layer),
+            Error::Unrecognized => write!(f, "unrecognized packet"),
+            Error::Fragmented => write!(f, "fragmented packet"),
+            Error::Malformed { field, offset } => {
+                write!(f, "malformed packet: {} at byte {}", field, offset)
+            }
+            Error::Dropped => write!(f, "dropped by socket"),
+            Error::__Nonexhaustive => unreachable!(),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 pub type Field = ::core::ops::Range<usize>;
 
 impl<T: AsRef<[u8]>> Packet<T> {
@@ -166,7 +238,8 @@ impl<T: AsRef<[u8]>> Packet<T> {
     }
 
     /// Ensure that no accessor method will panic if called.
-    /// Returns `Err(Error::Truncated)` if the buffer is too short.
+    /// Returns `Err(Error::Truncated)` naming the first field past the end if the buffer is
+    /// too short.
     ///
     /// The result of this check is invalidated by calling [set_hardware_len] or
     /// [set_protocol_len].
@@ -176,11 +249,18 @@ impl<T: AsRef<[u8]>> Packet<T> {
     pub fn check_len(&self) -> Result<()> {
         let len = self.buffer.as_ref().len();
         if len < OPER.end {
-            Err(Error::Truncated)
-        } else if len < TPA(self.hardware_len(), self.protocol_len()).end {
-            Err(Error::Truncated)
-        } else {
-            Ok(())
+            return Err(Error::truncated("operation", OPER.start));
+        }
+        let (hardware_len, protocol_len) = (self.hardware_len(), self.protocol_len());
+        let fields = [
+            ("sender hardware address", SHA(hardware_len, protocol_len)),
+            ("sender protocol address", SPA(hardware_len, protocol_len)),
+            ("target hardware address", THA(hardware_len, protocol_len)),
+            ("target protocol address", TPA(hardware_len, protocol_len)),
+        ];
+        match fields.iter().find(|(_, field)| len < field.end) {
+            Some((name, field)) => Err(Error::truncated(name, field.start)),
+            None => Ok(()),
         }
     }
 
@@ -348,7 +428,7 @@ pub fn create(mac: &[u8], ip: IpAddr) -> Option<Packet<Vec<u8>>> {
             Some(packet)
         }
         _ => {
-            println!("skipped");
+            log::debug!(target: LOG_TARGET, "no ARP request for {}, only IPv4 is supported", ip);
 
             None
         }
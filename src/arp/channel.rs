@@ -1,13 +1,50 @@
+#[cfg(unix)]
+use super::network_interface::CSocket;
 use super::{
-    ether::{network_addr_to_sockaddr, EtherType, Ethernet, EthernetPacket, Packet},
-    network_interface::{CSocket, NetworkInterface},
+    ether::{EtherType, Ethernet, EthernetPacket, Packet},
+    network_interface::{MacAddr, NetworkInterface},
 };
-use std::{io, iter::repeat, mem, ptr};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::filter::{self, Filter};
+use crate::{
+    error::Result,
+    pool::{Buffer, BufferPool},
+};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::{
+    io,
+    time::{Duration, SystemTime},
+};
+
+#[cfg(target_os = "linux")]
+mod afpacket;
+mod backend;
+#[cfg(target_os = "macos")]
+mod bpf;
+#[cfg(windows)]
+pub(crate) mod npcap;
+
+#[cfg(target_os = "linux")]
+pub use self::afpacket::{rx_queues, Layer3Receiver, Layer3Sender};
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+pub use self::afpacket::{AsyncReceiver, AsyncSender};
+#[cfg(target_os = "linux")]
+pub use self::backend::AfPacket;
+#[cfg(target_os = "macos")]
+pub use self::backend::Bpf;
+#[cfg(windows)]
+pub use self::backend::Npcap;
+pub use self::backend::{DatalinkBackend, Native};
 
 pub enum Channel {
     /// A datalink channel which sends and receives Ethernet packets
     Ethernet(Box<EthernetDataLinkSender>, Box<EthernetDataLinkReceiver>),
 
+    /// A cooked channel, for `ChannelType::Layer3`
+    #[cfg(target_os = "linux")]
+    Layer3(Layer3Sender, Layer3Receiver),
+
     /// This variant should never be used
     ///
     /// Including it allows new variants to be added to `Channel` without breaking existing code.
@@ -22,12 +59,101 @@ pub enum ChannelType {
     Layer3(EtherType),
 }
 
+/// How a fanout group spreads packets among its sockets.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FanoutMode {
+    /// By flow hash, keeping the packets of a flow on one socket
+    Hash,
+    /// Round robin
+    LoadBalance,
+    /// By the CPU the packet arrived on
+    Cpu,
+    /// To the first socket with room, moving on when it falls behind
+    Rollover,
+    /// To a random socket
+    Random,
+    /// By the receive queue of the NIC
+    QueueMapping,
+}
+
+/// A `PACKET_FANOUT` group sharing the packets of an interface among several channels.
+///
+/// All channels joining a group must use the same id and mode.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Fanout {
+    /// Group id, unique per network namespace
+    pub id: u16,
+    /// How packets are spread
+    pub mode: FanoutMode,
+    /// Reassemble IP fragments before hashing, so that they reach the same channel
+    pub defrag: bool,
+}
+
+/// Who a received frame was addressed to, from the kernel's point of view.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PacketType {
+    /// To this host
+    Host,
+    /// To the broadcast address
+    Broadcast,
+    /// To a multicast group
+    Multicast,
+    /// To another host, seen in promiscuous mode
+    OtherHost,
+    /// Sent by this host
+    Outgoing,
+    /// A `PACKET_*` type without a variant
+    Other(u8),
+}
+
+/// What the kernel knows about a received frame.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RxMeta {
+    /// When the frame arrived
+    pub timestamp: SystemTime,
+    /// Index of the interface it arrived on
+    pub ifindex: u32,
+    /// Tag control information of a VLAN tag the NIC stripped
+    pub vlan_tci: Option<u16>,
+    /// Who it was addressed to
+    pub pkt_type: PacketType,
+    /// Length on the wire, larger than the frame when it was truncated
+    pub original_len: usize,
+}
+
+impl RxMeta {
+    /// What can be told from the frame alone, for receivers the kernel tells nothing: it
+    /// arrived now on an unknown interface and was not truncated.
+    pub fn from_frame(packet: &EthernetPacket) -> RxMeta {
+        let destination = packet.get_destination();
+        let pkt_type = if destination == MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff) {
+            PacketType::Broadcast
+        } else if destination.0 & 0x01 != 0 {
+            PacketType::Multicast
+        } else {
+            PacketType::Host
+        };
+        RxMeta {
+            timestamp: SystemTime::now(),
+            ifindex: 0,
+            vlan_tci: None,
+            pkt_type,
+            original_len: packet.packet().len(),
+        }
+    }
+}
+
+/// Read buffer size fitting the largest frames segmentation offload hands to packet
+/// sockets: a 64 KiB IP packet behind an Ethernet header with a VLAN tag.
+pub const GSO_READ_BUFFER_SIZE: usize = 65536 + 18;
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub struct Config {
     /// The size of buffer to use when writing packets. Defaults to 4096
     pub write_buffer_size: usize,
 
-    /// The size of buffer to use when reading packets. Defaults to 4096
+    /// The size of buffer to use when reading packets; longer frames are truncated. Use
+    /// `GSO_READ_BUFFER_SIZE` on interfaces with segmentation offload. Defaults to 4096
     pub read_buffer_size: usize,
 
     /// The read timeout. Defaults to None.
@@ -36,10 +162,43 @@ pub struct Config {
     /// The write timeout. Defaults to None.
     pub write_timeout: Option<std::time::Duration>,
 
-    /// Specifies whether to read packets at the datalink layer or network layer.
-    /// NOTE FIXME Currently ignored
-    /// Defaults to Layer2
+    /// Specifies whether to read packets at the datalink layer or network layer; `Layer3`
+    /// opens a `Channel::Layer3`, which doesn't support rings. Defaults to Layer2
     pub channel_type: ChannelType,
+
+    /// Fanout group to join, None for none. Defaults to None
+    pub fanout: Option<Fanout>,
+
+    /// Put the interface into promiscuous mode while the channel is open. Defaults to true
+    pub promiscuous: bool,
+
+    /// Receive through a memory-mapped `PACKET_RX_RING` instead of a system call per frame.
+    /// `read_buffer_size` doesn't apply then. Defaults to None
+    pub rx_ring: Option<RxRing>,
+
+    /// Send through a memory-mapped `PACKET_TX_RING`, which lets `enqueue` queue frames
+    /// for a single system call on `flush`. Defaults to None
+    pub tx_ring: Option<TxRing>,
+
+    /// Size of the kernel receive buffer (`SO_RCVBUF`), None to keep the system default.
+    /// The kernel doubles it and caps it at `net.core.rmem_max`. Defaults to None
+    pub recv_buffer_size: Option<usize>,
+
+    /// Size of the kernel send buffer (`SO_SNDBUF`), None to keep the system default.
+    /// The kernel doubles it and caps it at `net.core.wmem_max`. Defaults to None
+    pub send_buffer_size: Option<usize>,
+
+    /// Also bind the socket to the interface with `SO_BINDTODEVICE`. Defaults to false
+    pub bind_to_device: bool,
+
+    /// Send past the traffic control layer (`PACKET_QDISC_BYPASS`), which is faster but
+    /// skips queueing disciplines and drops frames when the device queue is full. Defaults
+    /// to false
+    pub qdisc_bypass: bool,
+
+    /// Fail with `WouldBlock` instead of waiting when no frame can be received or sent
+    /// right away; the timeouts don't apply then. Defaults to false
+    pub nonblocking: bool,
 }
 
 impl Default for Config {
@@ -50,173 +209,319 @@ impl Default for Config {
             read_timeout: None,
             write_timeout: None,
             channel_type: ChannelType::Layer2,
+            fanout: None,
+            promiscuous: true,
+            rx_ring: None,
+            tx_ring: None,
+            recv_buffer_size: None,
+            send_buffer_size: None,
+            bind_to_device: false,
+            qdisc_bypass: false,
+            nonblocking: false,
         }
     }
 }
 
-#[inline]
-pub fn channel(network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
-    let eth_p_all = 0x0003;
-    let (typ, proto) = match config.channel_type {
-        Layer2 => (libc::SOCK_RAW, eth_p_all),
-        ChannelType::Layer3(EtherType(proto)) => (libc::SOCK_DGRAM, proto),
-    };
-    let socket = unsafe { libc::socket(libc::AF_PACKET, typ, proto.to_be() as i32) };
-    if socket == -1 {
-        return Err(io::Error::last_os_error());
+impl Config {
+    /// A builder starting from the defaults.
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder {
+            config: Config::default(),
+        }
     }
-    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
-    let len = network_addr_to_sockaddr(network_interface, &mut addr, proto as i32);
+}
 
-    let send_addr = (&addr as *const libc::sockaddr_storage) as *const libc::sockaddr;
+/// Builds a channel [`Config`]; see its fields for the defaults.
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigBuilder {
+    config: Config,
+}
 
-    // Bind to interface
-    if unsafe { libc::bind(socket, send_addr, len as libc::socklen_t) } == -1 {
-        let err = io::Error::last_os_error();
-        unsafe {
-            sockets::close(socket);
+impl ConfigBuilder {
+    /// Write frames through a buffer of `size` bytes.
+    pub fn write_buffer_size(mut self, size: usize) -> ConfigBuilder {
+        self.config.write_buffer_size = size;
+        self
+    }
+
+    /// Read frames into a buffer of `size` bytes; longer frames are truncated.
+    pub fn read_buffer_size(mut self, size: usize) -> ConfigBuilder {
+        self.config.read_buffer_size = size;
+        self
+    }
+
+    /// Give up receiving after `timeout`, None to wait for ever.
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> ConfigBuilder {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// Give up sending after `timeout`, None to wait for ever.
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> ConfigBuilder {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// Send and receive at the layer of `channel_type`.
+    pub fn channel_type(mut self, channel_type: ChannelType) -> ConfigBuilder {
+        self.config.channel_type = channel_type;
+        self
+    }
+
+    /// Join the fanout group `fanout`.
+    pub fn fanout(mut self, fanout: Fanout) -> ConfigBuilder {
+        self.config.fanout = Some(fanout);
+        self
+    }
+
+    /// Put the interface into promiscuous mode while the channel is open, or not.
+    pub fn promiscuous(mut self, promiscuous: bool) -> ConfigBuilder {
+        self.config.promiscuous = promiscuous;
+        self
+    }
+
+    /// Receive through a memory-mapped ring laid out as `ring`.
+    pub fn rx_ring(mut self, ring: RxRing) -> ConfigBuilder {
+        self.config.rx_ring = Some(ring);
+        self
+    }
+
+    /// Send through a memory-mapped ring laid out as `ring`.
+    pub fn tx_ring(mut self, ring: TxRing) -> ConfigBuilder {
+        self.config.tx_ring = Some(ring);
+        self
+    }
+
+    /// Ask for a kernel receive buffer of `size` bytes.
+    pub fn recv_buffer_size(mut self, size: usize) -> ConfigBuilder {
+        self.config.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Ask for a kernel send buffer of `size` bytes.
+    pub fn send_buffer_size(mut self, size: usize) -> ConfigBuilder {
+        self.config.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Bind the socket to the interface with `SO_BINDTODEVICE`, or not.
+    pub fn bind_to_device(mut self, bind: bool) -> ConfigBuilder {
+        self.config.bind_to_device = bind;
+        self
+    }
+
+    /// Bypass the queueing disciplines of the interface when sending, or not.
+    pub fn qdisc_bypass(mut self, bypass: bool) -> ConfigBuilder {
+        self.config.qdisc_bypass = bypass;
+        self
+    }
+
+    /// Fail with `WouldBlock` instead of waiting, or not.
+    pub fn nonblocking(mut self, nonblocking: bool) -> ConfigBuilder {
+        self.config.nonblocking = nonblocking;
+        self
+    }
+
+    /// The configuration built.
+    pub fn build(self) -> Config {
+        self.config
+    }
+
+    /// Open a channel on `network_interface` with the configuration built.
+    pub fn open(self, network_interface: &NetworkInterface) -> Result<Channel> {
+        channel(network_interface, self.config)
+    }
+}
+
+/// Layout of a `TPACKET_V3` transmit ring shared with the kernel.
+///
+/// Frames are copied into slots of `frame_size` bytes, which the kernel sends from when
+/// flushed and hands back once sent.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TxRing {
+    /// Bytes per slot, a multiple of 16 holding a 48 byte header and the frame. Defaults to
+    /// 2048
+    pub frame_size: usize,
+
+    /// Number of slots. Defaults to 1024
+    pub frame_count: usize,
+}
+
+impl Default for TxRing {
+    fn default() -> TxRing {
+        TxRing {
+            frame_size: 2048,
+            frame_count: 1024,
         }
-        return Err(err);
-    }
-
-    let mut pmr: linux::packet_mreq = unsafe { mem::zeroed() };
-    pmr.mr_ifindex = network_interface.index as i32;
-    pmr.mr_type = linux::PACKET_MR_PROMISC as u16;
-
-    // Enable promiscuous capture
-    if unsafe {
-        libc::setsockopt(
-            socket,
-            linux::SOL_PACKET,
-            linux::PACKET_ADD_MEMBERSHIP,
-            (&pmr as *const linux::packet_mreq) as *const libc::c_void,
-            mem::size_of::<linux::packet_mreq>() as u32,
-        )
-    } == -1
-    {
-        let err = io::Error::last_os_error();
-        unsafe {
-            sockets::close(socket);
+    }
+}
+
+/// Layout of a `TPACKET_V3` receive ring shared with the kernel.
+///
+/// The kernel copies frames back to back into a block and hands it over once full, or
+/// `block_timeout` after its first frame. The receiver reads them in place and hands the
+/// block back when asked for a frame past its last one.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RxRing {
+    /// Bytes per block, a multiple of the page size. Longer frames are truncated. Defaults
+    /// to 1 MiB
+    pub block_size: usize,
+
+    /// Number of blocks. Defaults to 64
+    pub block_count: usize,
+
+    /// How long a block that isn't full is held back. Defaults to 10 milliseconds
+    pub block_timeout: Duration,
+}
+
+impl Default for RxRing {
+    fn default() -> RxRing {
+        RxRing {
+            block_size: 1 << 20,
+            block_count: 64,
+            block_timeout: Duration::from_millis(10),
         }
-        return Err(err);
     }
+}
+
+/// Open a channel on `network_interface` through the native backend: a packet socket on
+/// Linux, a BPF device on macOS and an npcap device on Windows.
+#[inline]
+pub fn channel(network_interface: &NetworkInterface, config: Config) -> Result<Channel> {
+    Ok(Native::default().channel(network_interface, config)?)
+}
 
-    // Enable nonblocking
-    if unsafe { libc::fcntl(socket, libc::F_SETFL, libc::O_NONBLOCK) } == -1 {
+/// A packet received by a cooked channel: the network layer packet, without the link
+/// layer header it arrived with.
+#[derive(Clone, Copy, Debug)]
+pub struct CookedPacket<'a> {
+    /// Ethertype of the packet
+    pub protocol: EtherType,
+    /// Link layer address of the sender
+    pub source: MacAddr,
+    /// What the kernel knows about the frame it arrived in
+    pub meta: RxMeta,
+    /// The packet, truncated to the read buffer
+    pub payload: &'a [u8],
+}
+
+/// Wait up to `timeout`, or for good if None, for any of `fds` to become readable, and
+/// return those that did; none if the timeout expired.
+///
+/// Lets one thread serve the sockets of several channels, from `raw_fd`, where the
+/// iterators would each wait on their own.
+#[cfg(unix)]
+pub fn poll_readable(fds: &[RawFd], timeout: Option<Duration>) -> Result<Vec<RawFd>> {
+    Ok(poll_fds(fds, libc::POLLIN, timeout)?)
+}
+
+/// Wait up to `timeout`, or for good if None, for any of `fds` to become writable, and
+/// return those that did; none if the timeout expired.
+#[cfg(unix)]
+pub fn poll_writable(fds: &[RawFd], timeout: Option<Duration>) -> Result<Vec<RawFd>> {
+    Ok(poll_fds(fds, libc::POLLOUT, timeout)?)
+}
+
+#[cfg(unix)]
+fn poll_fds(
+    fds: &[RawFd],
+    events: libc::c_short,
+    timeout: Option<Duration>,
+) -> io::Result<Vec<RawFd>> {
+    let mut polls: Vec<libc::pollfd> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events,
+            revents: 0,
+        })
+        .collect();
+    // rounded up, so that a short timeout doesn't turn into a busy loop
+    let millis = timeout.map_or(-1, |to| {
+        let millis = (to.as_nanos() + 999_999) / 1_000_000;
+        millis.min(libc::c_int::MAX as u128) as libc::c_int
+    });
+    let ready = loop {
+        let ready = unsafe { libc::poll(polls.as_mut_ptr(), polls.len() as libc::nfds_t, millis) };
+        if ready != -1 {
+            break ready;
+        }
         let err = io::Error::last_os_error();
-        unsafe {
-            sockets::close(socket);
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
         }
-        return Err(err);
-    }
-
-    let fd = std::sync::Arc::new(FileDesc { fd: socket });
-    let mut sender = Box::new(DataLinkSenderImpl {
-        socket: fd.clone(),
-        fd_set: unsafe { mem::zeroed() },
-        write_buffer: repeat(0u8).take(config.write_buffer_size).collect(),
-        _channel_type: config.channel_type,
-        send_addr: unsafe { *(send_addr as *const libc::sockaddr_ll) },
-        send_addr_len: len,
-        timeout: config
-            .write_timeout
-            .map(|to| internal::duration_to_timespec(to)),
-    });
-    unsafe {
-        libc::FD_ZERO(&mut sender.fd_set as *mut libc::fd_set);
-        libc::FD_SET(fd.fd, &mut sender.fd_set as *mut libc::fd_set);
-    }
-    let mut receiver = Box::new(DataLinkReceiverImpl {
-        socket: fd.clone(),
-        fd_set: unsafe { mem::zeroed() },
-        read_buffer: repeat(0u8).take(config.read_buffer_size).collect(),
-        _channel_type: config.channel_type,
-        timeout: config
-            .read_timeout
-            .map(|to| internal::duration_to_timespec(to)),
-    });
-    unsafe {
-        libc::FD_ZERO(&mut receiver.fd_set as *mut libc::fd_set);
-        libc::FD_SET(fd.fd, &mut receiver.fd_set as *mut libc::fd_set);
+    };
+    if ready == 0 {
+        return Ok(Vec::new());
     }
-
-    Ok(Channel::Ethernet(sender, receiver))
+    // errors and hangups count as ready, the next read or write reports them
+    Ok(polls
+        .iter()
+        .filter(|poll| poll.revents & (events | libc::POLLERR | libc::POLLHUP) != 0)
+        .map(|poll| poll.fd)
+        .collect())
 }
 
+#[cfg(unix)]
 pub struct FileDesc {
     pub fd: CSocket,
 }
 
+#[cfg(unix)]
+impl AsRawFd for FileDesc {
+    fn as_raw_fd(&self) -> CSocket {
+        self.fd
+    }
+}
+
+#[cfg(unix)]
 impl Drop for FileDesc {
     fn drop(&mut self) {
         unsafe {
-            sockets::close(self.fd);
+            libc::close(self.fd);
         }
     }
 }
 
-struct DataLinkSenderImpl {
-    socket: std::sync::Arc<FileDesc>,
-    fd_set: libc::fd_set,
-    write_buffer: Vec<u8>,
-    _channel_type: ChannelType,
-    send_addr: libc::sockaddr_ll,
-    send_addr_len: usize,
-    timeout: Option<libc::timespec>,
-}
-
 pub trait EthernetDataLinkSender: Send {
     fn send_to(
         &mut self,
         packet: &EthernetPacket,
         dst: Option<NetworkInterface>,
     ) -> Option<io::Result<()>>;
-}
 
-impl EthernetDataLinkSender for DataLinkSenderImpl {
-    #[inline]
-    fn send_to(
-        &mut self,
-        packet: &EthernetPacket,
-        _dst: Option<NetworkInterface>,
-    ) -> Option<io::Result<()>> {
-        let ret = unsafe {
-            libc::pselect(
-                self.socket.fd + 1,
-                ptr::null_mut(),
-                &mut self.fd_set as *mut libc::fd_set,
-                ptr::null_mut(),
-                self.timeout
-                    .as_ref()
-                    .map(|to| to as *const libc::timespec)
-                    .unwrap_or(ptr::null()),
-                ptr::null(),
-            )
-        };
-        if ret == -1 {
-            Some(Err(io::Error::last_os_error()))
-        } else if ret == 0 {
-            Some(Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out")))
-        } else {
-            match internal::send_to(
-                self.socket.fd,
-                packet.packet(),
-                (&self.send_addr as *const libc::sockaddr_ll) as *const _,
-                self.send_addr_len as libc::socklen_t,
-            ) {
-                Err(e) => Some(Err(e)),
-                Ok(_) => Some(Ok(())),
-            }
+    /// Queue `packet` to be sent on `flush`.
+    ///
+    /// Senders with a transmit ring copy it into the ring and send everything queued with
+    /// one system call; others send it right away.
+    fn enqueue(&mut self, packet: &EthernetPacket) -> io::Result<()> {
+        self.send_to(packet, None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+    }
+
+    /// Send the packets queued.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Send `packets` at once, returning how many were sent.
+    ///
+    /// Senders writing to a socket send them with a single system call, which may take
+    /// fewer than all. Others send one at a time.
+    fn send_batch(&mut self, packets: &[EthernetPacket]) -> io::Result<usize> {
+        for packet in packets {
+            self.enqueue(packet)?;
         }
+        self.flush()?;
+        Ok(packets.len())
     }
-}
 
-struct DataLinkReceiverImpl {
-    socket: std::sync::Arc<FileDesc>,
-    fd_set: libc::fd_set,
-    read_buffer: Vec<u8>,
-    _channel_type: ChannelType,
-    timeout: Option<libc::timespec>,
+    /// The socket sent through, for multiplexing with epoll or `poll_writable`. None for
+    /// senders without one.
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
 }
 
 // ($recv_name:ident, $iter_name:ident, $packet:ident) => {
@@ -227,6 +532,19 @@ pub trait EthernetDataLinkReceiver: Send {
     /// This will likely be removed once other layer two types are supported.
     #[inline]
     fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator + 'a>;
+
+    /// The RX queue (or CPU) all received packets came from, for channels opened by
+    /// `queue_channels`.
+    fn queue(&self) -> Option<u16> {
+        None
+    }
+
+    /// The socket received from, for multiplexing with epoll or `poll_readable`. None for
+    /// receivers without one.
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
 }
 
 /// An iterator over data link layer packets
@@ -234,173 +552,78 @@ pub trait EthernetDataLinkChannelIterator<'a> {
     /// Get the next EthernetPacket in the channel
     #[inline]
     fn next(&mut self) -> io::Result<EthernetPacket>;
-}
-
-struct DataLinkChannelIteratorImpl<'a> {
-    pc: &'a mut DataLinkReceiverImpl,
-}
-
-impl<'a> EthernetDataLinkChannelIterator<'a> for DataLinkChannelIteratorImpl<'a> {
-    fn next(&mut self) -> io::Result<EthernetPacket> {
-        let mut caddr: libc::sockaddr_storage = unsafe { mem::zeroed() };
-        let ret = unsafe {
-            libc::pselect(
-                self.pc.socket.fd + 1,
-                &mut self.pc.fd_set as *mut libc::fd_set,
-                ptr::null_mut(),
-                ptr::null_mut(),
-                self.pc
-                    .timeout
-                    .as_ref()
-                    .map(|to| to as *const libc::timespec)
-                    .unwrap_or(ptr::null()),
-                ptr::null(),
-            )
-        };
-        if ret == -1 {
-            Err(io::Error::last_os_error())
-        } else if ret == 0 {
-            Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out"))
-        } else {
-            let res = internal::recv_from(self.pc.socket.fd, &mut self.pc.read_buffer, &mut caddr);
-            match res {
-                Ok(len) => Ok(EthernetPacket::new(&self.pc.read_buffer[0..len]).unwrap()),
-                Err(e) => Err(e),
-            }
-        }
-    }
-}
-
-impl EthernetDataLinkReceiver for DataLinkReceiverImpl {
-    // FIXME Layer 3
-    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator + 'a> {
-        Box::new(DataLinkChannelIteratorImpl { pc: self })
-    }
-}
-
-mod internal {
-    use super::sockets;
-    use crate::mine::network_interface::{
-        Buf, BufLen, CSocket, MutBuf, SockAddr, SockAddrStorage, SockLen,
-    };
-    use std::mem;
 
-    fn errno() -> i32 {
-        std::io::Error::last_os_error().raw_os_error().unwrap()
+    /// Get up to `max` EthernetPackets at once, waiting only for the first.
+    ///
+    /// Receivers reading from a socket get them with a single system call. Others return
+    /// one packet per call, and filtering receivers may return none.
+    fn next_batch(&mut self, max: usize) -> io::Result<Vec<EthernetPacket>> {
+        let _ = max;
+        Ok(vec![self.next()?])
     }
 
-    #[inline]
-    pub fn retry<F>(f: &mut F) -> libc::ssize_t
-    where
-        F: FnMut() -> libc::ssize_t,
-    {
-        loop {
-            let minus1 = -1;
-            let ret = f();
-            if ret != minus1 || errno() as isize != libc::EINTR as isize {
-                return ret;
-            }
+    /// Receive up to one frame per buffer, waiting only for the first, and return how many
+    /// buffers were filled.
+    ///
+    /// Receivers reading from a socket read straight into the buffers, which they grow to
+    /// their capacity or the read buffer size first; others copy the packets of `next_batch`.
+    fn recv_batch(&mut self, buffers: &mut [Buffer]) -> io::Result<usize> {
+        if buffers.is_empty() {
+            return Ok(0);
         }
+        let packets = self.next_batch(buffers.len())?;
+        Ok(copy_batch(&packets, buffers))
     }
-
-    pub fn send_to(
-        socket: CSocket,
-        buffer: &[u8],
-        dst: *const SockAddr,
-        slen: SockLen,
-    ) -> std::io::Result<usize> {
-        let send_len = retry(&mut || unsafe {
-            sockets::sendto(
-                socket,
-                buffer.as_ptr() as Buf,
-                buffer.len() as BufLen,
-                0,
-                dst,
-                slen,
-            )
-        });
-
-        if send_len < 0 {
-            Err(std::io::Error::last_os_error())
-        } else {
-            Ok(send_len as usize)
-        }
+    /// Get the next EthernetPacket with what the kernel knows about it.
+    ///
+    /// Receivers without kernel metadata fill in `RxMeta::from_frame`.
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let packet = self.next()?;
+        let meta = RxMeta::from_frame(&packet);
+        Ok((packet, meta))
     }
 
-    pub fn recv_from(
-        socket: CSocket,
-        buffer: &mut [u8],
-        caddr: *mut SockAddrStorage,
-    ) -> std::io::Result<usize> {
-        let mut caddrlen = mem::size_of::<SockAddrStorage>() as SockLen;
-        let len = retry(&mut || unsafe {
-            sockets::recvfrom(
-                socket,
-                buffer.as_ptr() as MutBuf,
-                buffer.len() as BufLen,
-                0,
-                caddr as *mut SockAddr,
-                &mut caddrlen,
-            )
-        });
-
-        if len < 0 {
-            Err(std::io::Error::last_os_error())
-        } else {
-            Ok(len as usize)
-        }
+    /// Get the next EthernetPacket in a buffer of its own, so that it can be kept past the
+    /// next call or handed to another thread.
+    fn next_owned(&mut self) -> io::Result<EthernetPacket<'static>> {
+        let packet = self.next()?;
+        EthernetPacket::owned(packet.packet().to_vec())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))
     }
 
-    pub fn duration_to_timespec(dur: std::time::Duration) -> libc::timespec {
-        libc::timespec {
-            tv_sec: dur.as_secs() as libc::time_t,
-            tv_nsec: dur.subsec_nanos() as libc::c_long,
-        }
+    /// Like `next_owned`, in a buffer leased from `pool` that goes back to it when the
+    /// packet is dropped.
+    ///
+    /// The packet of `next` is copied, so that buffers only grow past the pool's size for
+    /// frames that are larger, not to the read buffer size.
+    fn next_pooled(&mut self, pool: &BufferPool) -> io::Result<EthernetPacket<'static>> {
+        let packet = self.next()?;
+        let mut buffer = pool.lease();
+        buffer.extend_from_slice(packet.packet());
+        EthernetPacket::pooled(buffer)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))
     }
 }
 
-mod sockets {
-    use crate::mine::network_interface::{
-        Buf, BufLen, CSocket, CouldFail, MutBuf, SockAddr, SockLen,
-    };
-
-    pub unsafe fn close(sock: CSocket) {
-        let _ = libc::close(sock);
-    }
-
-    pub unsafe fn sendto(
-        socket: CSocket,
-        buf: Buf,
-        len: BufLen,
-        flags: libc::c_int,
-        addr: *const SockAddr,
-        addrlen: SockLen,
-    ) -> CouldFail {
-        libc::sendto(socket, buf, len, flags, addr, addrlen)
-    }
-
-    pub unsafe fn recvfrom(
-        socket: CSocket,
-        buf: MutBuf,
-        len: BufLen,
-        flags: libc::c_int,
-        addr: *mut SockAddr,
-        addrlen: *mut SockLen,
-    ) -> CouldFail {
-        libc::recvfrom(socket, buf, len, flags, addr, addrlen)
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> dyn EthernetDataLinkChannelIterator<'a> + 'a {
+    /// Only yield the packets satisfying `filter`, checked in userspace.
+    ///
+    /// Errors, timeouts included, are passed on. A batch may come back with fewer packets
+    /// than were received, or none.
+    pub fn filtered(
+        self: Box<Self>,
+        filter: &'a Filter,
+    ) -> Box<dyn EthernetDataLinkChannelIterator<'a> + 'a> {
+        filter::filter_iter(self, filter)
     }
 }
 
-mod linux {
-    pub const SOL_PACKET: libc::c_int = 263;
-    pub const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
-    pub const PACKET_MR_PROMISC: libc::c_int = 1;
-
-    // man 7 packet
-    pub struct packet_mreq {
-        pub mr_ifindex: libc::c_int,
-        pub mr_type: libc::c_ushort,
-        pub mr_alen: libc::c_ushort,
-        pub mr_address: [libc::c_uchar; 8],
+// fill `buffers` with the frames of `packets`, returning how many were
+fn copy_batch(packets: &[EthernetPacket], buffers: &mut [Buffer]) -> usize {
+    for (buffer, packet) in buffers.iter_mut().zip(packets) {
+        buffer.clear();
+        buffer.extend_from_slice(packet.packet());
     }
+    packets.len().min(buffers.len())
 }
@@ -0,0 +1,1693 @@
+//! `AF_PACKET` channels, the datalink backend of Linux.
+//!
+//! A packet socket bound to the interface sends and receives whole frames, or network
+//! layer packets for cooked channels. Sockets can join a `PACKET_FANOUT` group, map
+//! `TPACKET_V3` rings and read batches with `recvmmsg`.
+
+use super::{
+    copy_batch, Channel, ChannelType, Config, CookedPacket, EthernetDataLinkChannelIterator,
+    EthernetDataLinkReceiver, EthernetDataLinkSender, Fanout, FanoutMode, FileDesc, PacketType,
+    RxMeta, RxRing, TxRing,
+};
+use crate::{
+    arp::{
+        ether::{EtherType, EthernetPacket, Packet},
+        network_interface::{network_addr_to_sockaddr, CSocket, MacAddr, NetworkInterface},
+    },
+    error::Result,
+    pool::Buffer,
+};
+#[cfg(feature = "tokio")]
+use std::os::unix::io::AsRawFd;
+use std::{
+    fs, io,
+    iter::repeat,
+    mem,
+    os::unix::io::RawFd,
+    ptr,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Number of receive queues of an interface, from sysfs.
+pub fn rx_queues(network_interface: &NetworkInterface) -> Result<usize> {
+    let path = format!("/sys/class/net/{}/queues", network_interface.name);
+    let mut queues = 0;
+    for entry in fs::read_dir(path)? {
+        if entry?.file_name().to_string_lossy().starts_with("rx-") {
+            queues += 1;
+        }
+    }
+    Ok(queues.max(1))
+}
+
+/// The channels of `AfPacket::queue_channels`.
+pub(super) fn queue_channels(
+    network_interface: &NetworkInterface,
+    fanout: Fanout,
+    config: Config,
+) -> io::Result<Vec<Channel>> {
+    let count = match fanout.mode {
+        FanoutMode::QueueMapping => rx_queues(network_interface)?,
+        FanoutMode::Cpu => crate::pipeline::online_cpus()?,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Fanout mode does not map to queues",
+            ))
+        }
+    };
+    let config = Config {
+        fanout: Some(fanout),
+        ..config
+    };
+    // the group hands out queue i modulo the member count to the i-th member to join, so
+    // the channels have to be opened in order
+    (0..count.min(usize::from(u16::MAX)))
+        .map(|queue| open(network_interface, config, Some(queue as u16)))
+        .collect()
+}
+
+pub(super) fn open(
+    network_interface: &NetworkInterface,
+    config: Config,
+    queue: Option<u16>,
+) -> io::Result<Channel> {
+    if let ChannelType::Layer3(_) = config.channel_type {
+        if config.rx_ring.is_some() || config.tx_ring.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Rings are not supported by cooked channels",
+            ));
+        }
+        let (sender, receiver) = open_halves(network_interface, config, queue)?;
+        return Ok(Channel::Layer3(
+            Layer3Sender { inner: sender },
+            Layer3Receiver { inner: receiver },
+        ));
+    }
+    let (sender, receiver) = open_halves(network_interface, config, queue)?;
+    Ok(Channel::Ethernet(sender, receiver))
+}
+
+fn open_halves(
+    network_interface: &NetworkInterface,
+    config: Config,
+    queue: Option<u16>,
+) -> io::Result<(Box<DataLinkSenderImpl>, Box<DataLinkReceiverImpl>)> {
+    let _span = trace_span!(
+        DEBUG,
+        "channel",
+        interface = %network_interface.name,
+        index = network_interface.index
+    );
+    let eth_p_all = 0x0003;
+    let (typ, proto) = match config.channel_type {
+        ChannelType::Layer2 => (libc::SOCK_RAW, eth_p_all),
+        ChannelType::Layer3(ethertype) => (libc::SOCK_DGRAM, ethertype.value()),
+    };
+    crate::privileges::require(&[crate::privileges::Capability::NetRaw])?;
+    let socket = unsafe { libc::socket(libc::AF_PACKET, typ, proto.to_be() as i32) };
+    if socket == -1 {
+        let err = io::Error::last_os_error();
+        trace_event!(warn, error = %err, "opening packet socket failed");
+        return Err(err);
+    }
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let len = network_addr_to_sockaddr(network_interface, &mut addr, proto as i32);
+
+    let send_addr = (&addr as *const libc::sockaddr_storage) as *const libc::sockaddr;
+
+    // Bind to interface
+    if unsafe { libc::bind(socket, send_addr, len as libc::socklen_t) } == -1 {
+        let err = io::Error::last_os_error();
+        trace_event!(warn, error = %err, "binding to the interface failed");
+        unsafe {
+            sockets::close(socket);
+        }
+        return Err(err);
+    }
+
+    // Enable promiscuous capture
+    let promiscuous = if config.promiscuous {
+        match promiscuous::Membership::acquire(network_interface.index) {
+            Ok(membership) => Some(std::sync::Arc::new(membership)),
+            Err(err) => {
+                trace_event!(warn, error = %err, "enabling promiscuous mode failed");
+                unsafe {
+                    sockets::close(socket);
+                }
+                return Err(err);
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(fanout) = config.fanout {
+        let mut mode = fanout.mode.to_raw();
+        if fanout.defrag {
+            mode |= linux::PACKET_FANOUT_FLAG_DEFRAG;
+        }
+        let arg: libc::c_int = i32::from(fanout.id) | i32::from(mode) << 16;
+        if unsafe {
+            libc::setsockopt(
+                socket,
+                linux::SOL_PACKET,
+                linux::PACKET_FANOUT,
+                (&arg as *const libc::c_int) as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as u32,
+            )
+        } == -1
+        {
+            let err = io::Error::last_os_error();
+            trace_event!(warn, error = %err, "joining the fanout group failed");
+            unsafe {
+                sockets::close(socket);
+            }
+            return Err(err);
+        }
+    }
+
+    if let Err(err) = set_options(socket, network_interface, &config) {
+        trace_event!(warn, error = %err, "setting socket options failed");
+        unsafe {
+            sockets::close(socket);
+        }
+        return Err(err);
+    }
+
+    // Ask for the metadata of `next_with_meta`
+    for &(level, name) in &[
+        (linux::SOL_PACKET, linux::PACKET_AUXDATA),
+        (libc::SOL_SOCKET, libc::SO_TIMESTAMPNS),
+    ] {
+        let on: libc::c_int = 1;
+        if unsafe {
+            libc::setsockopt(
+                socket,
+                level,
+                name,
+                (&on as *const libc::c_int) as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as u32,
+            )
+        } == -1
+        {
+            let err = io::Error::last_os_error();
+            trace_event!(warn, error = %err, "enabling receive metadata failed");
+            unsafe {
+                sockets::close(socket);
+            }
+            return Err(err);
+        }
+    }
+
+    let (rx_ring, tx_ring) = match ring::map(socket, config.rx_ring, config.tx_ring) {
+        Ok(rings) => rings,
+        Err(err) => {
+            trace_event!(warn, error = %err, "setting up the packet rings failed");
+            unsafe {
+                sockets::close(socket);
+            }
+            return Err(err);
+        }
+    };
+
+    // Enable nonblocking
+    if unsafe { libc::fcntl(socket, libc::F_SETFL, libc::O_NONBLOCK) } == -1 {
+        let err = io::Error::last_os_error();
+        trace_event!(warn, error = %err, "enabling non-blocking mode failed");
+        unsafe {
+            sockets::close(socket);
+        }
+        return Err(err);
+    }
+
+    let fd = std::sync::Arc::new(FileDesc { fd: socket });
+    let mut sender = Box::new(DataLinkSenderImpl {
+        socket: fd.clone(),
+        fd_set: unsafe { mem::zeroed() },
+        write_buffer: repeat(0u8).take(config.write_buffer_size).collect(),
+        ring: tx_ring,
+        _channel_type: config.channel_type,
+        send_addr: unsafe { *(send_addr as *const libc::sockaddr_ll) },
+        send_addr_len: len,
+        _promiscuous: promiscuous.clone(),
+        timeout: timeout(&config, config.write_timeout),
+    });
+    unsafe {
+        libc::FD_ZERO(&mut sender.fd_set as *mut libc::fd_set);
+        libc::FD_SET(fd.fd, &mut sender.fd_set as *mut libc::fd_set);
+    }
+    let mut receiver = Box::new(DataLinkReceiverImpl {
+        socket: fd.clone(),
+        fd_set: unsafe { mem::zeroed() },
+        read_buffer: repeat(0u8).take(config.read_buffer_size).collect(),
+        batch_buffer: Vec::new(),
+        ring: rx_ring,
+        _channel_type: config.channel_type,
+        queue,
+        _promiscuous: promiscuous,
+        timeout: timeout(&config, config.read_timeout),
+    });
+    unsafe {
+        libc::FD_ZERO(&mut receiver.fd_set as *mut libc::fd_set);
+        libc::FD_SET(fd.fd, &mut receiver.fd_set as *mut libc::fd_set);
+    }
+
+    trace_event!(debug, fd = fd.fd, fanout = ?config.fanout, queue = ?queue, "channel open");
+    Ok((sender, receiver))
+}
+
+/// Apply the socket options of `config` that have no other bearing on the channel.
+fn set_options(
+    socket: CSocket,
+    network_interface: &NetworkInterface,
+    config: &Config,
+) -> io::Result<()> {
+    if let Some(size) = config.recv_buffer_size {
+        let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+        sockets::set_option(socket, libc::SOL_SOCKET, libc::SO_RCVBUF, &size)?;
+    }
+    if let Some(size) = config.send_buffer_size {
+        let size = size.min(libc::c_int::MAX as usize) as libc::c_int;
+        sockets::set_option(socket, libc::SOL_SOCKET, libc::SO_SNDBUF, &size)?;
+    }
+    if config.bind_to_device {
+        let name = network_interface.name.as_bytes();
+        if unsafe {
+            libc::setsockopt(
+                socket,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                name.as_ptr() as *const libc::c_void,
+                name.len() as libc::socklen_t,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    if config.qdisc_bypass {
+        let on: libc::c_int = 1;
+        sockets::set_option(socket, linux::SOL_PACKET, linux::PACKET_QDISC_BYPASS, &on)?;
+    }
+    Ok(())
+}
+
+/// The timeout of the waits of a channel: none at all when it doesn't block.
+fn timeout(config: &Config, timeout: Option<Duration>) -> Option<libc::timespec> {
+    if config.nonblocking {
+        Some(libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        })
+    } else {
+        timeout.map(internal::duration_to_timespec)
+    }
+}
+
+impl FanoutMode {
+    fn to_raw(self) -> u16 {
+        match self {
+            FanoutMode::Hash => linux::PACKET_FANOUT_HASH,
+            FanoutMode::LoadBalance => linux::PACKET_FANOUT_LB,
+            FanoutMode::Cpu => linux::PACKET_FANOUT_CPU,
+            FanoutMode::Rollover => linux::PACKET_FANOUT_ROLLOVER,
+            FanoutMode::Random => linux::PACKET_FANOUT_RND,
+            FanoutMode::QueueMapping => linux::PACKET_FANOUT_QM,
+        }
+    }
+}
+
+impl PacketType {
+    fn from_raw(raw: u8) -> PacketType {
+        match raw {
+            0 => PacketType::Host,
+            1 => PacketType::Broadcast,
+            2 => PacketType::Multicast,
+            3 => PacketType::OtherHost,
+            4 => PacketType::Outgoing,
+            raw => PacketType::Other(raw),
+        }
+    }
+}
+
+/// The sending half of a cooked channel, which adds the link layer header itself.
+pub struct Layer3Sender {
+    inner: Box<DataLinkSenderImpl>,
+}
+
+impl Layer3Sender {
+    /// Send the network layer packet `payload` to the link layer address `destination`.
+    /// Its ethertype is that of the channel.
+    pub fn send_to(&mut self, payload: &[u8], destination: MacAddr) -> io::Result<()> {
+        let MacAddr(a, b, c, d, e, f) = destination;
+        let mut addr = self.inner.send_addr;
+        addr.sll_addr = [a, b, c, d, e, f, 0, 0];
+        addr.sll_halen = 6;
+        self.inner.wait()?;
+        let _len = internal::send_to(
+            self.inner.socket.fd,
+            payload,
+            (&addr as *const libc::sockaddr_ll) as *const _,
+            self.inner.send_addr_len as libc::socklen_t,
+        )?;
+        trace_event!(trace, len = _len, "sent cooked packet");
+        Ok(())
+    }
+
+    /// The socket of the channel.
+    pub fn raw_fd(&self) -> RawFd {
+        self.inner.socket.fd
+    }
+}
+
+/// The receiving half of a cooked channel, which strips the link layer header.
+pub struct Layer3Receiver {
+    inner: Box<DataLinkReceiverImpl>,
+}
+
+impl Layer3Receiver {
+    /// Wait for the next packet, as long as the read timeout allows.
+    pub fn recv(&mut self) -> io::Result<CookedPacket<'_>> {
+        DataLinkChannelIteratorImpl {
+            pc: &mut self.inner,
+        }
+        .wait()?;
+        let (meta, addr) =
+            internal::recv_msg_from(self.inner.socket.fd, &mut self.inner.read_buffer)?;
+        let [a, b, c, d, e, f, _, _] = addr.sll_addr;
+        let len = meta.original_len.min(self.inner.read_buffer.len());
+        Ok(CookedPacket {
+            protocol: EtherType::new(u16::from_be(addr.sll_protocol)),
+            source: MacAddr(a, b, c, d, e, f),
+            meta,
+            payload: &self.inner.read_buffer[..len],
+        })
+    }
+
+    /// The socket of the channel.
+    pub fn raw_fd(&self) -> RawFd {
+        self.inner.socket.fd
+    }
+}
+
+/// The error of a wait that ended without the socket being ready.
+fn timed_out(timeout: Option<&libc::timespec>) -> io::Error {
+    match timeout {
+        Some(to) if to.tv_sec == 0 && to.tv_nsec == 0 => {
+            io::Error::new(io::ErrorKind::WouldBlock, "Would block")
+        }
+        _ => io::Error::new(io::ErrorKind::TimedOut, "Timed out"),
+    }
+}
+
+/// The channel of `AfPacket::async_channel`.
+#[cfg(feature = "tokio")]
+pub(super) fn async_channel(
+    network_interface: &NetworkInterface,
+    config: Config,
+) -> io::Result<(AsyncSender, AsyncReceiver)> {
+    if config.rx_ring.is_some() || config.tx_ring.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Rings are not supported by async channels",
+        ));
+    }
+    if config.channel_type != ChannelType::Layer2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cooked async channels are not supported",
+        ));
+    }
+    let (sender, receiver) = open_halves(network_interface, config, None)?;
+    let io = std::sync::Arc::new(tokio::io::unix::AsyncFd::new(sender.socket.clone())?);
+    Ok((
+        AsyncSender {
+            io: io.clone(),
+            inner: sender,
+        },
+        AsyncReceiver {
+            io,
+            inner: receiver,
+        },
+    ))
+}
+
+/// The sending half of an `AfPacket::async_channel`.
+#[cfg(feature = "tokio")]
+pub struct AsyncSender {
+    io: std::sync::Arc<tokio::io::unix::AsyncFd<std::sync::Arc<FileDesc>>>,
+    inner: Box<DataLinkSenderImpl>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncSender {
+    /// Send `packet` once the socket has room for it.
+    pub async fn send(&mut self, packet: &EthernetPacket<'_>) -> io::Result<()> {
+        loop {
+            let mut guard = self.io.writable().await?;
+            match internal::send_to(
+                self.inner.socket.fd,
+                packet.packet(),
+                (&self.inner.send_addr as *const libc::sockaddr_ll) as *const _,
+                self.inner.send_addr_len as libc::socklen_t,
+            ) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) => {
+                    trace_event!(debug, error = %e, "send failed");
+                    return Err(e);
+                }
+                Ok(_len) => {
+                    trace_event!(trace, len = _len, "sent frame");
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsRawFd for AsyncSender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.socket.fd
+    }
+}
+
+/// The receiving half of an `AfPacket::async_channel`.
+#[cfg(feature = "tokio")]
+pub struct AsyncReceiver {
+    io: std::sync::Arc<tokio::io::unix::AsyncFd<std::sync::Arc<FileDesc>>>,
+    inner: Box<DataLinkReceiverImpl>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncReceiver {
+    /// Wait for the next frame.
+    pub async fn recv(&mut self) -> io::Result<EthernetPacket<'_>> {
+        let len = loop {
+            let mut guard = self.io.readable().await?;
+            let mut caddr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+            match internal::recv_from(
+                self.inner.socket.fd,
+                &mut self.inner.read_buffer,
+                &mut caddr,
+            ) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) => {
+                    trace_event!(debug, error = %e, "receive failed");
+                    return Err(e);
+                }
+                Ok(len) => break len,
+            }
+        };
+        trace_event!(trace, len, "received frame");
+        let len = len.min(self.inner.read_buffer.len());
+        EthernetPacket::new(&self.inner.read_buffer[0..len])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))
+    }
+
+    /// Wait for the next frame, with what the kernel knows about it.
+    pub async fn recv_with_meta(&mut self) -> io::Result<(EthernetPacket<'_>, RxMeta)> {
+        let meta = loop {
+            let mut guard = self.io.readable().await?;
+            match internal::recv_msg(self.inner.socket.fd, &mut self.inner.read_buffer) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => guard.clear_ready(),
+                Err(e) => {
+                    trace_event!(debug, error = %e, "receive failed");
+                    return Err(e);
+                }
+                Ok(meta) => break meta,
+            }
+        };
+        trace_event!(trace, len = meta.original_len, "received frame");
+        let len = meta.original_len.min(self.inner.read_buffer.len());
+        let packet = EthernetPacket::new(&self.inner.read_buffer[0..len])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))?;
+        Ok((packet, meta))
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsRawFd for AsyncReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.socket.fd
+    }
+}
+
+struct DataLinkSenderImpl {
+    socket: std::sync::Arc<FileDesc>,
+    fd_set: libc::fd_set,
+    write_buffer: Vec<u8>,
+    ring: Option<ring::Tx>,
+    _channel_type: ChannelType,
+    send_addr: libc::sockaddr_ll,
+    send_addr_len: usize,
+    _promiscuous: Option<std::sync::Arc<promiscuous::Membership>>,
+    timeout: Option<libc::timespec>,
+}
+
+impl DataLinkSenderImpl {
+    /// Wait until the socket is writable or the timeout expired.
+    fn wait(&mut self) -> io::Result<()> {
+        let ret = unsafe {
+            libc::pselect(
+                self.socket.fd + 1,
+                ptr::null_mut(),
+                &mut self.fd_set as *mut libc::fd_set,
+                ptr::null_mut(),
+                self.timeout
+                    .as_ref()
+                    .map(|to| to as *const libc::timespec)
+                    .unwrap_or(ptr::null()),
+                ptr::null(),
+            )
+        };
+        if ret == -1 {
+            Err(io::Error::last_os_error())
+        } else if ret == 0 {
+            trace_event!(debug, "send timed out");
+            Err(timed_out(self.timeout.as_ref()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl EthernetDataLinkSender for DataLinkSenderImpl {
+    #[inline]
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        _dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        if self.ring.is_some() {
+            return Some(self.enqueue(packet).and_then(|()| self.flush()));
+        }
+        if let Err(e) = self.wait() {
+            return Some(Err(e));
+        }
+        match internal::send_to(
+            self.socket.fd,
+            packet.packet(),
+            (&self.send_addr as *const libc::sockaddr_ll) as *const _,
+            self.send_addr_len as libc::socklen_t,
+        ) {
+            Err(e) => {
+                trace_event!(debug, error = %e, "send failed");
+                Some(Err(e))
+            }
+            Ok(_len) => {
+                trace_event!(trace, len = _len, "sent frame");
+                Some(Ok(()))
+            }
+        }
+    }
+
+    fn enqueue(&mut self, packet: &EthernetPacket) -> io::Result<()> {
+        match self.ring {
+            Some(ref mut ring) => {
+                ring.enqueue(self.socket.fd, packet.packet(), self.timeout.as_ref())
+            }
+            None => self
+                .send_to(packet, None)
+                .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent"))),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.ring {
+            Some(ref mut ring) => ring.flush(self.socket.fd),
+            None => Ok(()),
+        }
+    }
+
+    fn send_batch(&mut self, packets: &[EthernetPacket]) -> io::Result<usize> {
+        if self.ring.is_some() {
+            for packet in packets {
+                self.enqueue(packet)?;
+            }
+            self.flush()?;
+            return Ok(packets.len());
+        }
+        if packets.is_empty() {
+            return Ok(0);
+        }
+        self.wait()?;
+        let frames: Vec<&[u8]> = packets.iter().map(|packet| packet.packet()).collect();
+        let sent = internal::send_batch(
+            self.socket.fd,
+            &frames,
+            (&self.send_addr as *const libc::sockaddr_ll) as *const _,
+            self.send_addr_len as libc::socklen_t,
+        )?;
+        trace_event!(trace, frames = sent, "sent batch");
+        Ok(sent)
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.socket.fd)
+    }
+}
+
+impl Drop for DataLinkSenderImpl {
+    fn drop(&mut self) {
+        // frames queued but not flushed are still sent
+        let _ = self.flush();
+    }
+}
+
+struct DataLinkReceiverImpl {
+    socket: std::sync::Arc<FileDesc>,
+    fd_set: libc::fd_set,
+    read_buffer: Vec<u8>,
+    // one slot of `read_buffer.len()` bytes per packet of a batch
+    batch_buffer: Vec<u8>,
+    // replaces both buffers when set
+    ring: Option<ring::Rx>,
+    _channel_type: ChannelType,
+    queue: Option<u16>,
+    _promiscuous: Option<std::sync::Arc<promiscuous::Membership>>,
+    timeout: Option<libc::timespec>,
+}
+
+struct DataLinkChannelIteratorImpl<'a> {
+    pc: &'a mut DataLinkReceiverImpl,
+}
+
+impl<'a> DataLinkChannelIteratorImpl<'a> {
+    /// Wait until the socket is readable or the timeout expired.
+    fn wait(&mut self) -> io::Result<()> {
+        let ret = unsafe {
+            libc::pselect(
+                self.pc.socket.fd + 1,
+                &mut self.pc.fd_set as *mut libc::fd_set,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                self.pc
+                    .timeout
+                    .as_ref()
+                    .map(|to| to as *const libc::timespec)
+                    .unwrap_or(ptr::null()),
+                ptr::null(),
+            )
+        };
+        if ret == -1 {
+            Err(io::Error::last_os_error())
+        } else if ret == 0 {
+            Err(timed_out(self.pc.timeout.as_ref()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The next frame of the receive ring.
+    fn next_mapped(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let pc = &mut *self.pc;
+        let ring = pc.ring.as_mut().expect("channel without a receive ring");
+        let frame = ring.next(pc.socket.fd, pc.timeout.as_ref())?;
+        let (data, meta) = ring.frame(frame);
+        trace_event!(trace, len = meta.original_len, "received frame");
+        let packet = EthernetPacket::new(data)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))?;
+        Ok((packet, meta))
+    }
+
+    /// Up to `max` frames of the receive ring, only from the block held, which handing over
+    /// the next one would give back.
+    fn next_batch_mapped(&mut self, max: usize) -> io::Result<Vec<EthernetPacket>> {
+        let pc = &mut *self.pc;
+        let ring = pc.ring.as_mut().expect("channel without a receive ring");
+        let mut frames = vec![ring.next(pc.socket.fd, pc.timeout.as_ref())?];
+        while frames.len() < max {
+            match ring.take() {
+                Some(frame) => frames.push(frame),
+                None => break,
+            }
+        }
+        trace_event!(trace, frames = frames.len(), "received batch");
+        let ring = &*ring;
+        Ok(frames
+            .into_iter()
+            .filter_map(|frame| EthernetPacket::new(ring.frame(frame).0))
+            .collect())
+    }
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for DataLinkChannelIteratorImpl<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        if self.pc.ring.is_some() {
+            return self.next_mapped().map(|(packet, _)| packet);
+        }
+        let mut caddr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        self.wait()?;
+        let res = internal::recv_from(self.pc.socket.fd, &mut self.pc.read_buffer, &mut caddr);
+        match res {
+            Ok(len) => {
+                trace_event!(trace, len, "received frame");
+                if len > self.pc.read_buffer.len() {
+                    trace_event!(
+                        debug,
+                        len,
+                        buffer = self.pc.read_buffer.len(),
+                        "frame truncated"
+                    );
+                }
+                let len = len.min(self.pc.read_buffer.len());
+                Ok(EthernetPacket::new(&self.pc.read_buffer[0..len]).unwrap())
+            }
+            Err(e) => {
+                trace_event!(debug, error = %e, "receive failed");
+                Err(e)
+            }
+        }
+    }
+
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        if self.pc.ring.is_some() {
+            return self.next_mapped();
+        }
+        self.wait()?;
+        let received = internal::recv_msg(self.pc.socket.fd, &mut self.pc.read_buffer);
+        match received {
+            Ok(meta) => {
+                trace_event!(trace, len = meta.original_len, "received frame");
+                let len = meta.original_len.min(self.pc.read_buffer.len());
+                let packet = EthernetPacket::new(&self.pc.read_buffer[0..len])
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))?;
+                Ok((packet, meta))
+            }
+            Err(e) => {
+                trace_event!(debug, error = %e, "receive failed");
+                Err(e)
+            }
+        }
+    }
+
+    fn next_batch(&mut self, max: usize) -> io::Result<Vec<EthernetPacket>> {
+        if self.pc.ring.is_some() {
+            return self.next_batch_mapped(max);
+        }
+        self.wait()?;
+        let slot = self.pc.read_buffer.len().max(1);
+        let max = max.max(1);
+        if self.pc.batch_buffer.len() < slot * max {
+            self.pc.batch_buffer.resize(slot * max, 0);
+        }
+        let lengths = internal::recv_batch(
+            self.pc.socket.fd,
+            self.pc.batch_buffer.chunks_mut(slot).take(max),
+        )?;
+        trace_event!(trace, frames = lengths.len(), "received batch");
+        let buffer = &self.pc.batch_buffer;
+        Ok(lengths
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, len)| EthernetPacket::new(&buffer[i * slot..i * slot + len]))
+            .collect())
+    }
+
+    fn recv_batch(&mut self, buffers: &mut [Buffer]) -> io::Result<usize> {
+        if buffers.is_empty() {
+            return Ok(0);
+        }
+        if self.pc.ring.is_some() {
+            let packets = self.next_batch_mapped(buffers.len())?;
+            return Ok(copy_batch(&packets, buffers));
+        }
+        self.wait()?;
+        let size = self.pc.read_buffer.len();
+        for buffer in buffers.iter_mut() {
+            let len = buffer.capacity().max(size);
+            buffer.resize(len, 0);
+        }
+        let lengths = internal::recv_batch(
+            self.pc.socket.fd,
+            buffers.iter_mut().map(|buffer| &mut buffer[..]),
+        )?;
+        trace_event!(trace, frames = lengths.len(), "received batch");
+        for (i, buffer) in buffers.iter_mut().enumerate() {
+            buffer.truncate(lengths.get(i).copied().unwrap_or(0));
+        }
+        Ok(lengths.len())
+    }
+}
+
+impl EthernetDataLinkReceiver for DataLinkReceiverImpl {
+    fn iter<'a>(&'a mut self) -> Box<EthernetDataLinkChannelIterator + 'a> {
+        Box::new(DataLinkChannelIteratorImpl { pc: self })
+    }
+
+    fn queue(&self) -> Option<u16> {
+        self.queue
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.socket.fd)
+    }
+}
+
+mod internal {
+    use super::sockets;
+    use crate::arp::network_interface::{
+        Buf, BufLen, CSocket, MutBuf, SockAddr, SockAddrStorage, SockLen,
+    };
+    use std::{mem, ptr};
+
+    fn errno() -> i32 {
+        std::io::Error::last_os_error().raw_os_error().unwrap()
+    }
+
+    #[inline]
+    pub fn retry<F>(f: &mut F) -> libc::ssize_t
+    where
+        F: FnMut() -> libc::ssize_t,
+    {
+        loop {
+            let minus1 = -1;
+            let ret = f();
+            if ret != minus1 || errno() as isize != libc::EINTR as isize {
+                return ret;
+            }
+        }
+    }
+
+    pub fn send_to(
+        socket: CSocket,
+        buffer: &[u8],
+        dst: *const SockAddr,
+        slen: SockLen,
+    ) -> std::io::Result<usize> {
+        let send_len = retry(&mut || unsafe {
+            sockets::sendto(
+                socket,
+                buffer.as_ptr() as Buf,
+                buffer.len() as BufLen,
+                0,
+                dst,
+                slen,
+            )
+        });
+
+        if send_len < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(send_len as usize)
+        }
+    }
+
+    /// Receive a packet into `buffer`, returning its length, which exceeds the length of
+    /// `buffer` if the packet was truncated.
+    pub fn recv_from(
+        socket: CSocket,
+        buffer: &mut [u8],
+        caddr: *mut SockAddrStorage,
+    ) -> std::io::Result<usize> {
+        let mut caddrlen = mem::size_of::<SockAddrStorage>() as SockLen;
+        let len = retry(&mut || unsafe {
+            sockets::recvfrom(
+                socket,
+                buffer.as_ptr() as MutBuf,
+                buffer.len() as BufLen,
+                libc::MSG_TRUNC,
+                caddr as *mut SockAddr,
+                &mut caddrlen,
+            )
+        });
+
+        if len < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(len as usize)
+        }
+    }
+
+    /// Receive a packet into `buffer` with its metadata. `original_len` exceeds the
+    /// length of `buffer` if the packet was truncated.
+    pub fn recv_msg(socket: CSocket, buffer: &mut [u8]) -> std::io::Result<super::RxMeta> {
+        recv_msg_from(socket, buffer).map(|(meta, _)| meta)
+    }
+
+    /// `recv_msg`, also returning the address of the sender.
+    pub fn recv_msg_from(
+        socket: CSocket,
+        buffer: &mut [u8],
+    ) -> std::io::Result<(super::RxMeta, libc::sockaddr_ll)> {
+        let mut addr: libc::sockaddr_ll = unsafe { mem::zeroed() };
+        // room for a timestamp and the packet auxdata
+        let mut control = [0u64; 16];
+        let mut iovec = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        let mut header: libc::msghdr = unsafe { mem::zeroed() };
+        header.msg_name = (&mut addr as *mut libc::sockaddr_ll) as *mut libc::c_void;
+        header.msg_namelen = mem::size_of::<libc::sockaddr_ll>() as SockLen;
+        header.msg_iov = &mut iovec;
+        header.msg_iovlen = 1;
+        header.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        header.msg_controllen = mem::size_of_val(&control) as _;
+        let len = retry(&mut || unsafe { libc::recvmsg(socket, &mut header, libc::MSG_TRUNC) });
+        if len < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut meta = super::RxMeta {
+            timestamp: std::time::SystemTime::now(),
+            ifindex: addr.sll_ifindex as u32,
+            vlan_tci: None,
+            pkt_type: super::PacketType::from_raw(addr.sll_pkttype),
+            original_len: len as usize,
+        };
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&header);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                        let ts = ptr::read_unaligned(data as *const libc::timespec);
+                        meta.timestamp = super::UNIX_EPOCH
+                            + super::Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+                    }
+                    (super::linux::SOL_PACKET, super::linux::PACKET_AUXDATA) => {
+                        let aux = ptr::read_unaligned(data as *const super::linux::tpacket_auxdata);
+                        meta.original_len = aux.tp_len as usize;
+                        if aux.tp_status & super::linux::TP_STATUS_VLAN_VALID != 0 {
+                            meta.vlan_tci = Some(aux.tp_vlan_tci);
+                        }
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(&header, cmsg);
+            }
+        }
+        Ok((meta, addr))
+    }
+
+    /// Receive a packet into each of `buffers`, as many as are waiting, returning their
+    /// lengths.
+    pub fn recv_batch<'b, I>(socket: CSocket, buffers: I) -> std::io::Result<Vec<usize>>
+    where
+        I: Iterator<Item = &'b mut [u8]>,
+    {
+        let mut iovecs: Vec<libc::iovec> = buffers
+            .map(|chunk| libc::iovec {
+                iov_base: chunk.as_mut_ptr() as *mut libc::c_void,
+                iov_len: chunk.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+        let received = retry(&mut || unsafe {
+            libc::recvmmsg(
+                socket,
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                libc::MSG_DONTWAIT,
+                std::ptr::null_mut(),
+            ) as libc::ssize_t
+        });
+
+        if received < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            // frames larger than their buffer are truncated
+            Ok(headers[..received as usize]
+                .iter()
+                .zip(&iovecs)
+                .map(|(header, iovec)| (header.msg_len as usize).min(iovec.iov_len))
+                .collect())
+        }
+    }
+
+    /// Send `frames` to `dst` with a single system call, returning how many were sent.
+    pub fn send_batch(
+        socket: CSocket,
+        frames: &[&[u8]],
+        dst: *const SockAddr,
+        slen: SockLen,
+    ) -> std::io::Result<usize> {
+        let mut iovecs: Vec<libc::iovec> = frames
+            .iter()
+            .map(|frame| libc::iovec {
+                iov_base: frame.as_ptr() as *mut libc::c_void,
+                iov_len: frame.len(),
+            })
+            .collect();
+        let mut headers: Vec<libc::mmsghdr> = iovecs
+            .iter_mut()
+            .map(|iovec| {
+                let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+                header.msg_hdr.msg_name = dst as *mut libc::c_void;
+                header.msg_hdr.msg_namelen = slen;
+                header.msg_hdr.msg_iov = iovec;
+                header.msg_hdr.msg_iovlen = 1;
+                header
+            })
+            .collect();
+        let sent = retry(&mut || unsafe {
+            libc::sendmmsg(
+                socket,
+                headers.as_mut_ptr(),
+                headers.len() as libc::c_uint,
+                0,
+            ) as libc::ssize_t
+        });
+
+        if sent < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(sent as usize)
+        }
+    }
+
+    pub fn duration_to_timespec(dur: std::time::Duration) -> libc::timespec {
+        libc::timespec {
+            tv_sec: dur.as_secs() as libc::time_t,
+            tv_nsec: dur.subsec_nanos() as libc::c_long,
+        }
+    }
+}
+
+mod sockets {
+    use crate::arp::network_interface::{
+        Buf, BufLen, CSocket, CouldFail, MutBuf, SockAddr, SockLen,
+    };
+    use std::{io, mem};
+
+    pub unsafe fn close(sock: CSocket) {
+        let _ = libc::close(sock);
+    }
+
+    pub fn set_option<T>(
+        socket: CSocket,
+        level: libc::c_int,
+        name: libc::c_int,
+        value: &T,
+    ) -> io::Result<()> {
+        if unsafe {
+            libc::setsockopt(
+                socket,
+                level,
+                name,
+                (value as *const T) as *const libc::c_void,
+                mem::size_of::<T>() as libc::socklen_t,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub unsafe fn sendto(
+        socket: CSocket,
+        buf: Buf,
+        len: BufLen,
+        flags: libc::c_int,
+        addr: *const SockAddr,
+        addrlen: SockLen,
+    ) -> CouldFail {
+        libc::sendto(socket, buf, len, flags, addr, addrlen)
+    }
+
+    pub unsafe fn recvfrom(
+        socket: CSocket,
+        buf: MutBuf,
+        len: BufLen,
+        flags: libc::c_int,
+        addr: *mut SockAddr,
+        addrlen: *mut SockLen,
+    ) -> CouldFail {
+        libc::recvfrom(socket, buf, len, flags, addr, addrlen)
+    }
+}
+
+/// Promiscuous mode shared by all channels of the process on an interface.
+///
+/// Only the first channel on an interface adds a `PACKET_MR_PROMISC` membership, on a
+/// socket of its own, and the last one to close drops it again, so that the interface
+/// leaves promiscuous mode as soon as nothing captures on it rather than when the process
+/// exits.
+mod promiscuous {
+    use super::{linux, FileDesc};
+    use std::{io, mem, sync::Mutex};
+
+    struct Entry {
+        index: u32,
+        socket: FileDesc,
+        channels: usize,
+    }
+
+    static INTERFACES: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+    /// One channel's share of the promiscuous mode of an interface.
+    #[derive(Debug)]
+    pub struct Membership {
+        index: u32,
+    }
+
+    impl Membership {
+        pub fn acquire(index: u32) -> io::Result<Membership> {
+            let mut interfaces = INTERFACES.lock().unwrap();
+            if let Some(entry) = interfaces.iter_mut().find(|entry| entry.index == index) {
+                entry.channels += 1;
+                return Ok(Membership { index });
+            }
+            let socket = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+            if socket == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let socket = FileDesc { fd: socket };
+            membership(&socket, index, linux::PACKET_ADD_MEMBERSHIP)?;
+            trace_event!(debug, index, "promiscuous mode on");
+            interfaces.push(Entry {
+                index,
+                socket,
+                channels: 1,
+            });
+            Ok(Membership { index })
+        }
+    }
+
+    impl Drop for Membership {
+        fn drop(&mut self) {
+            let mut interfaces = INTERFACES.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(position) = interfaces.iter().position(|e| e.index == self.index) {
+                interfaces[position].channels -= 1;
+                if interfaces[position].channels == 0 {
+                    let entry = interfaces.swap_remove(position);
+                    // closing the socket would drop it too, but not if it leaked into a child
+                    let _ = membership(&entry.socket, entry.index, linux::PACKET_DROP_MEMBERSHIP);
+                    trace_event!(debug, index = entry.index, "promiscuous mode off");
+                }
+            }
+        }
+    }
+
+    fn membership(socket: &FileDesc, index: u32, op: libc::c_int) -> io::Result<()> {
+        let mut pmr: linux::packet_mreq = unsafe { mem::zeroed() };
+        pmr.mr_ifindex = index as i32;
+        pmr.mr_type = linux::PACKET_MR_PROMISC as u16;
+        if unsafe {
+            libc::setsockopt(
+                socket.fd,
+                linux::SOL_PACKET,
+                op,
+                (&pmr as *const linux::packet_mreq) as *const libc::c_void,
+                mem::size_of::<linux::packet_mreq>() as u32,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// `TPACKET_V3` rings mapped from a packet socket.
+mod ring {
+    use super::{linux, CSocket, Duration, PacketType, RxMeta, RxRing, TxRing, UNIX_EPOCH};
+    use std::{
+        io, mem, ptr, slice,
+        sync::{
+            atomic::{fence, Ordering},
+            Arc,
+        },
+    };
+
+    /// Frame size for the kernel's checks; version 3 packs received frames of any length
+    /// into blocks.
+    const RX_FRAME_SIZE: usize = 2048;
+
+    /// Offset of the `sockaddr_ll` following the header of each received frame, and of the
+    /// data in each transmit slot.
+    const ADDRESS_OFFSET: usize = (mem::size_of::<linux::tpacket3_hdr>() + 15) & !15;
+
+    /// The rings of a socket, which the kernel has mapped together, receive ring first.
+    struct Mapping {
+        map: *mut u8,
+        size: usize,
+    }
+
+    // the receive and transmit rings take disjoint parts
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            unsafe {
+                libc::munmap(self.map as *mut libc::c_void, self.size);
+            }
+        }
+    }
+
+    /// Set up the rings of `socket` asked for and map them.
+    pub fn map(
+        socket: CSocket,
+        rx: Option<RxRing>,
+        tx: Option<TxRing>,
+    ) -> io::Result<(Option<Rx>, Option<Tx>)> {
+        if rx.is_none() && tx.is_none() {
+            return Ok((None, None));
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "Invalid ring layout");
+        set_option(socket, linux::PACKET_VERSION, &linux::TPACKET_V3)?;
+
+        let mut rx_size = 0;
+        if let Some(layout) = rx {
+            if layout.block_size < RX_FRAME_SIZE || layout.block_count == 0 {
+                return Err(invalid());
+            }
+            rx_size = layout
+                .block_size
+                .checked_mul(layout.block_count)
+                .ok_or_else(invalid)?;
+            let request = linux::tpacket_req3 {
+                tp_block_size: layout.block_size as u32,
+                tp_block_nr: layout.block_count as u32,
+                tp_frame_size: RX_FRAME_SIZE as u32,
+                tp_frame_nr: (layout.block_size / RX_FRAME_SIZE * layout.block_count) as u32,
+                tp_retire_blk_tov: layout.block_timeout.as_millis().min(u128::from(u32::MAX))
+                    as u32,
+                tp_sizeof_priv: 0,
+                tp_feature_req_word: 0,
+            };
+            set_option(socket, linux::PACKET_RX_RING, &request)?;
+        }
+
+        // transmit slots are laid out in blocks of whole pages
+        let mut tx_layout = (0, 0, 0);
+        if let Some(layout) = tx {
+            if layout.frame_size % 16 != 0
+                || layout.frame_size <= ADDRESS_OFFSET
+                || layout.frame_count == 0
+            {
+                return Err(invalid());
+            }
+            let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            let block_size = layout.frame_size.div_ceil(page) * page;
+            let per_block = block_size / layout.frame_size;
+            let block_count = layout.frame_count.div_ceil(per_block);
+            block_size.checked_mul(block_count).ok_or_else(invalid)?;
+            let request = linux::tpacket_req3 {
+                tp_block_size: block_size as u32,
+                tp_block_nr: block_count as u32,
+                tp_frame_size: layout.frame_size as u32,
+                tp_frame_nr: (per_block * block_count) as u32,
+                tp_retire_blk_tov: 0,
+                tp_sizeof_priv: 0,
+                tp_feature_req_word: 0,
+            };
+            set_option(socket, linux::PACKET_TX_RING, &request)?;
+            tx_layout = (block_size, block_count, per_block);
+        }
+
+        let (block_size, block_count, per_block) = tx_layout;
+        let size = rx_size
+            .checked_add(block_size * block_count)
+            .ok_or_else(invalid)?;
+        let map = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                socket,
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let mapping = Arc::new(Mapping {
+            map: map as *mut u8,
+            size,
+        });
+        let rx = rx.map(|layout| Rx {
+            _mapping: Arc::clone(&mapping),
+            map: mapping.map,
+            block_size: layout.block_size,
+            block_count: layout.block_count,
+            block: 0,
+            held: false,
+            remaining: 0,
+            offset: 0,
+        });
+        let tx = tx.map(|layout| Tx {
+            map: unsafe { mapping.map.add(rx_size) },
+            _mapping: Arc::clone(&mapping),
+            frame_size: layout.frame_size,
+            block_size,
+            per_block,
+            frame_count: per_block * block_count,
+            frame: 0,
+            queued: 0,
+        });
+        Ok((rx, tx))
+    }
+
+    /// A receive ring, read block by block in the order the kernel fills them.
+    pub struct Rx {
+        _mapping: Arc<Mapping>,
+        map: *mut u8,
+        block_size: usize,
+        block_count: usize,
+        /// The block read, or waited for
+        block: usize,
+        /// Whether the block is ours until handed back
+        held: bool,
+        /// Frames of the block not read yet
+        remaining: u32,
+        /// Offset of the next of them in the block
+        offset: usize,
+    }
+
+    // the kernel only writes to blocks it owns
+    unsafe impl Send for Rx {}
+
+    impl Rx {
+        fn descriptor(&self) -> *mut linux::tpacket_block_desc {
+            unsafe { self.map.add(self.block * self.block_size) as *mut linux::tpacket_block_desc }
+        }
+
+        /// The next frame of the block held, if any.
+        pub fn take(&mut self) -> Option<*const linux::tpacket3_hdr> {
+            if !self.held || self.remaining == 0 {
+                return None;
+            }
+            let frame = unsafe { self.map.add(self.block * self.block_size + self.offset) }
+                as *const linux::tpacket3_hdr;
+            self.offset += unsafe { (*frame).tp_next_offset } as usize;
+            self.remaining -= 1;
+            Some(frame)
+        }
+
+        /// Hand the block read back and hold the next one if the kernel is done with it.
+        fn advance(&mut self) -> bool {
+            let descriptor = self.descriptor();
+            unsafe {
+                if self.held {
+                    fence(Ordering::Release);
+                    ptr::write_volatile(
+                        &mut (*descriptor).hdr.block_status,
+                        linux::TP_STATUS_KERNEL,
+                    );
+                    self.held = false;
+                    self.block = (self.block + 1) % self.block_count;
+                    return self.advance();
+                }
+                if ptr::read_volatile(&(*descriptor).hdr.block_status) & linux::TP_STATUS_USER == 0
+                {
+                    return false;
+                }
+                fence(Ordering::Acquire);
+                self.held = true;
+                self.remaining = (*descriptor).hdr.num_pkts;
+                self.offset = (*descriptor).hdr.offset_to_first_pkt as usize;
+            }
+            true
+        }
+
+        /// The next frame, waiting up to `timeout` for the kernel to hand a block over.
+        pub fn next(
+            &mut self,
+            socket: CSocket,
+            timeout: Option<&libc::timespec>,
+        ) -> io::Result<*const linux::tpacket3_hdr> {
+            loop {
+                if let Some(frame) = self.take() {
+                    return Ok(frame);
+                }
+                if self.advance() {
+                    continue;
+                }
+                wait(socket, libc::POLLIN, timeout)?;
+            }
+        }
+
+        /// The bytes and metadata of a frame taken from this ring.
+        pub fn frame(&self, frame: *const linux::tpacket3_hdr) -> (&[u8], RxMeta) {
+            unsafe {
+                let header = &*frame;
+                let address =
+                    &*((frame as *const u8).add(ADDRESS_OFFSET) as *const libc::sockaddr_ll);
+                let data = slice::from_raw_parts(
+                    (frame as *const u8).add(usize::from(header.tp_mac)),
+                    header.tp_snaplen as usize,
+                );
+                let meta = RxMeta {
+                    timestamp: UNIX_EPOCH + Duration::new(u64::from(header.tp_sec), header.tp_nsec),
+                    ifindex: address.sll_ifindex as u32,
+                    vlan_tci: if header.tp_status & linux::TP_STATUS_VLAN_VALID != 0 {
+                        Some(header.hv1.tp_vlan_tci as u16)
+                    } else {
+                        None
+                    },
+                    pkt_type: PacketType::from_raw(address.sll_pkttype),
+                    original_len: header.tp_len as usize,
+                };
+                (data, meta)
+            }
+        }
+    }
+
+    /// A transmit ring, filled slot by slot in the order the kernel sends them.
+    pub struct Tx {
+        _mapping: Arc<Mapping>,
+        map: *mut u8,
+        frame_size: usize,
+        block_size: usize,
+        /// Slots per block
+        per_block: usize,
+        frame_count: usize,
+        /// The slot filled next
+        frame: usize,
+        /// Frames filled in since the last flush
+        queued: usize,
+    }
+
+    // the kernel only reads slots handed to it
+    unsafe impl Send for Tx {}
+
+    impl Tx {
+        fn slot(&self, index: usize) -> *mut linux::tpacket3_hdr {
+            let offset =
+                index / self.per_block * self.block_size + index % self.per_block * self.frame_size;
+            unsafe { self.map.add(offset) as *mut linux::tpacket3_hdr }
+        }
+
+        /// Copy `frame` into the next slot for the next flush. If the kernel hasn't sent
+        /// what the slot held yet, flush and wait up to `timeout` for it.
+        pub fn enqueue(
+            &mut self,
+            socket: CSocket,
+            frame: &[u8],
+            timeout: Option<&libc::timespec>,
+        ) -> io::Result<()> {
+            if frame.len() > self.frame_size - ADDRESS_OFFSET {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "Frame too long for the transmit ring",
+                ));
+            }
+            let slot = self.slot(self.frame);
+            loop {
+                let status = unsafe { ptr::read_volatile(&(*slot).tp_status) };
+                if status & (linux::TP_STATUS_SEND_REQUEST | linux::TP_STATUS_SENDING) == 0 {
+                    break;
+                }
+                self.flush(socket)?;
+                wait(socket, libc::POLLOUT, timeout)?;
+            }
+            fence(Ordering::Acquire);
+            unsafe {
+                ptr::copy_nonoverlapping(
+                    frame.as_ptr(),
+                    (slot as *mut u8).add(ADDRESS_OFFSET),
+                    frame.len(),
+                );
+                (*slot).tp_next_offset = 0;
+                (*slot).tp_len = frame.len() as u32;
+                (*slot).tp_snaplen = frame.len() as u32;
+                fence(Ordering::Release);
+                ptr::write_volatile(&mut (*slot).tp_status, linux::TP_STATUS_SEND_REQUEST);
+            }
+            self.frame = (self.frame + 1) % self.frame_count;
+            self.queued += 1;
+            Ok(())
+        }
+
+        /// Have the kernel send the frames queued, with a single system call.
+        pub fn flush(&mut self, socket: CSocket) -> io::Result<()> {
+            if self.queued == 0 {
+                return Ok(());
+            }
+            let sent = super::internal::retry(&mut || unsafe {
+                libc::sendto(socket, ptr::null(), 0, 0, ptr::null(), 0)
+            });
+            if sent < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            trace_event!(trace, frames = self.queued, "flushed transmit ring");
+            self.queued = 0;
+            Ok(())
+        }
+    }
+
+    /// Wait up to `timeout` for `events` on `socket`.
+    fn wait(
+        socket: CSocket,
+        events: libc::c_short,
+        timeout: Option<&libc::timespec>,
+    ) -> io::Result<()> {
+        let mut poll = libc::pollfd {
+            fd: socket,
+            events: events | libc::POLLERR,
+            revents: 0,
+        };
+        let ready = unsafe {
+            libc::ppoll(
+                &mut poll,
+                1,
+                timeout.map_or(ptr::null(), |to| to as *const libc::timespec),
+                ptr::null(),
+            )
+        };
+        if ready == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err);
+            }
+        } else if ready == 0 {
+            return Err(super::timed_out(timeout));
+        }
+        Ok(())
+    }
+
+    fn set_option<T>(socket: CSocket, name: libc::c_int, value: &T) -> io::Result<()> {
+        if unsafe {
+            libc::setsockopt(
+                socket,
+                linux::SOL_PACKET,
+                name,
+                (value as *const T) as *const libc::c_void,
+                mem::size_of::<T>() as u32,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+mod linux {
+    pub const SOL_PACKET: libc::c_int = 263;
+    pub const PACKET_ADD_MEMBERSHIP: libc::c_int = 1;
+    pub const PACKET_DROP_MEMBERSHIP: libc::c_int = 2;
+    pub const PACKET_MR_PROMISC: libc::c_int = 1;
+    pub const PACKET_AUXDATA: libc::c_int = 8;
+    pub const PACKET_RX_RING: libc::c_int = 5;
+    pub const PACKET_VERSION: libc::c_int = 10;
+    pub const PACKET_TX_RING: libc::c_int = 13;
+    pub const PACKET_FANOUT: libc::c_int = 18;
+    pub const PACKET_QDISC_BYPASS: libc::c_int = 20;
+    pub const TPACKET_V3: libc::c_int = 2;
+    pub const TP_STATUS_KERNEL: u32 = 0;
+    pub const TP_STATUS_USER: u32 = 0x1;
+    pub const TP_STATUS_SEND_REQUEST: u32 = 0x1;
+    pub const TP_STATUS_SENDING: u32 = 0x2;
+    pub const TP_STATUS_VLAN_VALID: u32 = 0x10;
+
+    pub const PACKET_FANOUT_HASH: u16 = 0;
+    pub const PACKET_FANOUT_LB: u16 = 1;
+    pub const PACKET_FANOUT_CPU: u16 = 2;
+    pub const PACKET_FANOUT_ROLLOVER: u16 = 3;
+    pub const PACKET_FANOUT_RND: u16 = 4;
+    pub const PACKET_FANOUT_QM: u16 = 5;
+    pub const PACKET_FANOUT_FLAG_DEFRAG: u16 = 0x8000;
+
+    // man 7 packet
+    pub struct packet_mreq {
+        pub mr_ifindex: libc::c_int,
+        pub mr_type: libc::c_ushort,
+        pub mr_alen: libc::c_ushort,
+        pub mr_address: [libc::c_uchar; 8],
+    }
+
+    #[repr(C)]
+    pub struct tpacket_auxdata {
+        pub tp_status: u32,
+        pub tp_len: u32,
+        pub tp_snaplen: u32,
+        pub tp_mac: u16,
+        pub tp_net: u16,
+        pub tp_vlan_tci: u16,
+        pub tp_vlan_tpid: u16,
+    }
+
+    #[repr(C)]
+    pub struct tpacket_req3 {
+        pub tp_block_size: u32,
+        pub tp_block_nr: u32,
+        pub tp_frame_size: u32,
+        pub tp_frame_nr: u32,
+        pub tp_retire_blk_tov: u32,
+        pub tp_sizeof_priv: u32,
+        pub tp_feature_req_word: u32,
+    }
+
+    #[repr(C)]
+    pub struct tpacket_bd_ts {
+        pub ts_sec: u32,
+        pub ts_nsec: u32,
+    }
+
+    #[repr(C)]
+    pub struct tpacket_hdr_v1 {
+        pub block_status: u32,
+        pub num_pkts: u32,
+        pub offset_to_first_pkt: u32,
+        pub blk_len: u32,
+        pub seq_num: u64,
+        pub ts_first_pkt: tpacket_bd_ts,
+        pub ts_last_pkt: tpacket_bd_ts,
+    }
+
+    #[repr(C)]
+    pub struct tpacket_block_desc {
+        pub version: u32,
+        pub offset_to_priv: u32,
+        pub hdr: tpacket_hdr_v1,
+    }
+
+    #[repr(C)]
+    pub struct tpacket_hdr_variant1 {
+        pub tp_rxhash: u32,
+        pub tp_vlan_tci: u32,
+        pub tp_vlan_tpid: u16,
+        pub tp_padding: u16,
+    }
+
+    #[repr(C)]
+    pub struct tpacket3_hdr {
+        pub tp_next_offset: u32,
+        pub tp_sec: u32,
+        pub tp_nsec: u32,
+        pub tp_snaplen: u32,
+        pub tp_len: u32,
+        pub tp_status: u32,
+        pub tp_mac: u16,
+        pub tp_net: u16,
+        pub hv1: tpacket_hdr_variant1,
+        pub tp_padding: [u8; 8],
+    }
+}
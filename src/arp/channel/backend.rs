@@ -0,0 +1,116 @@
+//! Datalink backends, the ways channels are opened.
+//!
+//! [`channel`](super::channel) opens channels through the [`Native`] backend of the
+//! platform. Code opening its own channels, like a `Resolver` or a `Sniffer`, takes the
+//! backend to open them through, so that tests and tools can hand it an in-memory device
+//! or a capture file instead.
+
+#[cfg(target_os = "linux")]
+use super::Fanout;
+use super::{Channel, Config};
+use crate::arp::network_interface::{try_get_interfaces, NetworkInterface};
+use std::io;
+
+/// A way of opening channels.
+pub trait DatalinkBackend: Send + Sync {
+    /// Open a channel on `network_interface`.
+    fn channel(&self, network_interface: &NetworkInterface, config: Config) -> io::Result<Channel>;
+
+    /// The interfaces channels can be opened on. Defaults to those of the host.
+    fn interfaces(&self) -> io::Result<Vec<NetworkInterface>> {
+        Ok(try_get_interfaces()?)
+    }
+
+    /// Open one channel per receive queue of a multi-queue NIC, all in the `fanout` group.
+    ///
+    /// With `FanoutMode::QueueMapping` the channel at index `i` receives the packets of RX
+    /// queue `i`; with `FanoutMode::Cpu` those that arrived on CPU `i`, which is the same
+    /// when every queue interrupts its own CPU. Either way RSS keeps each flow on one
+    /// channel, and the receivers report the index from `EthernetDataLinkReceiver::queue`.
+    /// Other modes don't map to queues and are rejected, as is the call by backends
+    /// without fanout.
+    #[cfg(target_os = "linux")]
+    fn queue_channels(
+        &self,
+        network_interface: &NetworkInterface,
+        fanout: Fanout,
+        config: Config,
+    ) -> io::Result<Vec<Channel>> {
+        let _ = (network_interface, fanout, config);
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Queue channels are not supported by the backend",
+        ))
+    }
+}
+
+/// `AF_PACKET` sockets, the backend of Linux, with their rings, fanout and cooked mode.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AfPacket;
+
+#[cfg(target_os = "linux")]
+impl DatalinkBackend for AfPacket {
+    fn channel(&self, network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+        super::afpacket::open(network_interface, config, None)
+    }
+
+    fn queue_channels(
+        &self,
+        network_interface: &NetworkInterface,
+        fanout: Fanout,
+        config: Config,
+    ) -> io::Result<Vec<Channel>> {
+        super::afpacket::queue_channels(network_interface, fanout, config)
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+impl AfPacket {
+    /// Open a channel driven by the tokio reactor instead of `pselect`.
+    ///
+    /// The halves wait for the socket through an `AsyncFd` registered with the runtime of
+    /// the caller, so this has to be called from within one. Timeouts of `config` don't
+    /// apply, use `tokio::time::timeout`; neither do rings, which are rejected.
+    pub fn async_channel(
+        &self,
+        network_interface: &NetworkInterface,
+        config: Config,
+    ) -> io::Result<(super::AsyncSender, super::AsyncReceiver)> {
+        super::afpacket::async_channel(network_interface, config)
+    }
+}
+
+/// `/dev/bpf` devices, the backend of macOS.
+#[cfg(target_os = "macos")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bpf;
+
+#[cfg(target_os = "macos")]
+impl DatalinkBackend for Bpf {
+    fn channel(&self, network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+        super::bpf::channel(network_interface, config)
+    }
+}
+
+/// npcap devices, the backend of Windows.
+#[cfg(windows)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Npcap;
+
+#[cfg(windows)]
+impl DatalinkBackend for Npcap {
+    fn channel(&self, network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+        super::npcap::channel(network_interface, config)
+    }
+}
+
+/// The backend of the platform.
+#[cfg(target_os = "linux")]
+pub type Native = AfPacket;
+/// The backend of the platform.
+#[cfg(target_os = "macos")]
+pub type Native = Bpf;
+/// The backend of the platform.
+#[cfg(windows)]
+pub type Native = Npcap;
@@ -0,0 +1,286 @@
+//! `/dev/bpf` channels, the datalink backend of macOS.
+//!
+//! A BPF device is attached to an interface with `BIOCSETIF`. Each read hands over
+//! whatever the kernel buffered, every frame behind a `bpf_hdr` and aligned to a word, so
+//! the receiver keeps the rest of a read for the next calls. Frames are written whole, the
+//! source address included thanks to `BIOCSHDRCMPLT`.
+//!
+//! Fanout, rings and cooked channels have no BPF counterpart and are rejected.
+
+use super::{
+    Channel, ChannelType, Config, EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+    EthernetDataLinkSender, FileDesc, RxMeta,
+};
+use crate::arp::{
+    ether::{EthernetPacket, Packet},
+    network_interface::NetworkInterface,
+};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::io::RawFd,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Open a channel on `network_interface` through the first free BPF device.
+pub(super) fn channel(network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+    if config.channel_type != ChannelType::Layer2
+        || config.fanout.is_some()
+        || config.rx_ring.is_some()
+        || config.tx_ring.is_some()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Only plain Layer2 channels are supported by BPF",
+        ));
+    }
+    let fd = Arc::new(open_device()?);
+    let read_len = attach(fd.fd, network_interface, &config)?;
+    trace_event!(debug, fd = fd.fd, interface = %network_interface.name, "bpf channel open");
+
+    let timeout = |timeout| {
+        if config.nonblocking {
+            Some(Duration::from_secs(0))
+        } else {
+            timeout
+        }
+    };
+    let sender = Box::new(BpfSender {
+        fd: fd.clone(),
+        timeout: timeout(config.write_timeout),
+    });
+    let receiver = Box::new(BpfReceiver {
+        fd,
+        buffer: vec![0; read_len],
+        start: 0,
+        end: 0,
+        timeout: timeout(config.read_timeout),
+    });
+    Ok(Channel::Ethernet(sender, receiver))
+}
+
+// devices are numbered from 0 and busy while another process has them open
+fn open_device() -> io::Result<FileDesc> {
+    for index in 0..256 {
+        let path = CString::new(format!("/dev/bpf{}", index)).unwrap();
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDWR) };
+        if fd != -1 {
+            return Ok(FileDesc { fd });
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EBUSY) {
+            return Err(err);
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::Other, "No free BPF device"))
+}
+
+/// Attach `fd` to the interface, returning the length reads have to use.
+fn attach(fd: RawFd, network_interface: &NetworkInterface, config: &Config) -> io::Result<usize> {
+    // the buffer length has to be set before attaching, and is the one reads must use
+    let mut len = config.read_buffer_size.max(sys::MIN_BUFFER_LEN) as libc::c_uint;
+    ioctl(fd, sys::BIOCSBLEN, &mut len)?;
+
+    let mut request: sys::ifreq = unsafe { std::mem::zeroed() };
+    let name = network_interface.name.as_bytes();
+    if name.len() >= request.ifr_name.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Interface name too long",
+        ));
+    }
+    for (dst, src) in request.ifr_name.iter_mut().zip(name) {
+        *dst = *src as libc::c_char;
+    }
+    ioctl(fd, sys::BIOCSETIF, &mut request)?;
+
+    let mut link_type: libc::c_uint = 0;
+    ioctl(fd, sys::BIOCGDLT, &mut link_type)?;
+    if link_type != sys::DLT_EN10MB {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Not an Ethernet interface",
+        ));
+    }
+
+    // hand frames over as they arrive rather than once the buffer is full
+    let mut on: libc::c_uint = 1;
+    ioctl(fd, sys::BIOCIMMEDIATE, &mut on)?;
+    ioctl(fd, sys::BIOCSHDRCMPLT, &mut on)?;
+    if config.promiscuous {
+        ioctl(fd, sys::BIOCPROMISC, &mut ())?;
+    }
+    ioctl(fd, sys::BIOCGBLEN, &mut len)?;
+    Ok(len as usize)
+}
+
+fn ioctl<T>(fd: RawFd, request: libc::c_ulong, arg: &mut T) -> io::Result<()> {
+    if unsafe { libc::ioctl(fd, request, arg as *mut T) } == -1 {
+        let err = io::Error::last_os_error();
+        trace_event!(warn, error = %err, request, "bpf ioctl failed");
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Wait up to `timeout` for `events` on `fd`.
+fn wait(fd: RawFd, events: libc::c_short, timeout: Option<Duration>) -> io::Result<()> {
+    let mut poll = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    let millis = timeout.map_or(-1, |to| {
+        to.as_millis().min(libc::c_int::MAX as u128) as libc::c_int
+    });
+    match unsafe { libc::poll(&mut poll, 1, millis) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 if millis == 0 => Err(io::Error::new(io::ErrorKind::WouldBlock, "Would block")),
+        0 => Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out")),
+        _ => Ok(()),
+    }
+}
+
+struct BpfSender {
+    fd: Arc<FileDesc>,
+    timeout: Option<Duration>,
+}
+
+impl EthernetDataLinkSender for BpfSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        _dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        if let Err(e) = wait(self.fd.fd, libc::POLLOUT, self.timeout) {
+            return Some(Err(e));
+        }
+        let frame = packet.packet();
+        let written = unsafe {
+            libc::write(
+                self.fd.fd,
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+            )
+        };
+        if written == -1 {
+            let err = io::Error::last_os_error();
+            trace_event!(debug, error = %err, "send failed");
+            return Some(Err(err));
+        }
+        trace_event!(trace, len = written, "sent frame");
+        Some(Ok(()))
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.fd.fd)
+    }
+}
+
+struct BpfReceiver {
+    fd: Arc<FileDesc>,
+    buffer: Vec<u8>,
+    // the frames of the last read not handed over yet
+    start: usize,
+    end: usize,
+    timeout: Option<Duration>,
+}
+
+impl EthernetDataLinkReceiver for BpfReceiver {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(BpfChannelIterator { pc: self })
+    }
+
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.fd.fd)
+    }
+}
+
+struct BpfChannelIterator<'a> {
+    pc: &'a mut BpfReceiver,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for BpfChannelIterator<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        self.next_with_meta().map(|(packet, _)| packet)
+    }
+
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let pc = &mut *self.pc;
+        if pc.start >= pc.end {
+            wait(pc.fd.fd, libc::POLLIN, pc.timeout)?;
+            let len = unsafe {
+                libc::read(
+                    pc.fd.fd,
+                    pc.buffer.as_mut_ptr() as *mut libc::c_void,
+                    pc.buffer.len(),
+                )
+            };
+            if len == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            pc.start = 0;
+            pc.end = len as usize;
+        }
+
+        let header = &pc.buffer[pc.start..pc.end];
+        if header.len() < sys::BPF_HDR_LEN {
+            pc.start = pc.end;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Truncated BPF header",
+            ));
+        }
+        let word = |at: usize| {
+            u32::from_ne_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+        };
+        let seconds = word(0) as u64;
+        let micros = word(4);
+        let caplen = word(8) as usize;
+        let datalen = word(12) as usize;
+        let hdrlen = usize::from(u16::from_ne_bytes([header[16], header[17]]));
+
+        let frame_start = pc.start + hdrlen;
+        let frame_end = (frame_start + caplen).min(pc.end);
+        pc.start += sys::word_align(hdrlen + caplen);
+        let packet = EthernetPacket::new(&pc.buffer[frame_start..frame_end])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))?;
+        let mut meta = RxMeta::from_frame(&packet);
+        meta.timestamp = UNIX_EPOCH + Duration::new(seconds, micros * 1000);
+        meta.original_len = datalen;
+        Ok((packet, meta))
+    }
+}
+
+/// The parts of `<net/bpf.h>` and `<net/if.h>` used here.
+mod sys {
+    pub const BIOCGBLEN: libc::c_ulong = 0x4004_4266;
+    pub const BIOCSBLEN: libc::c_ulong = 0xc004_4266;
+    pub const BIOCPROMISC: libc::c_ulong = 0x2000_4269;
+    pub const BIOCGDLT: libc::c_ulong = 0x4004_426a;
+    pub const BIOCSETIF: libc::c_ulong = 0x8020_426c;
+    pub const BIOCIMMEDIATE: libc::c_ulong = 0x8004_4270;
+    pub const BIOCSHDRCMPLT: libc::c_ulong = 0x8004_4275;
+    pub const DLT_EN10MB: libc::c_uint = 1;
+
+    /// Smallest buffer length the kernel accepts.
+    pub const MIN_BUFFER_LEN: usize = 4096;
+
+    /// Length of `bpf_hdr` up to its padding: a 32 bit `timeval`, the captured and
+    /// original lengths, and the header length.
+    pub const BPF_HDR_LEN: usize = 18;
+
+    const BPF_ALIGNMENT: usize = 4;
+
+    /// `BPF_WORDALIGN`
+    pub fn word_align(len: usize) -> usize {
+        (len + BPF_ALIGNMENT - 1) & !(BPF_ALIGNMENT - 1)
+    }
+
+    #[repr(C)]
+    pub struct ifreq {
+        pub ifr_name: [libc::c_char; 16],
+        pub ifr_ifru: [u8; 16],
+    }
+}
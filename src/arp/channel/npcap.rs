@@ -0,0 +1,359 @@
+//! npcap channels, the datalink backend of Windows.
+//!
+//! Frames are captured and sent through `wpcap.dll` of the npcap (or WinPcap) SDK, which
+//! has to be on the library path at link time. Senders and receivers open a handle each,
+//! since a pcap handle may only be used from one thread at a time.
+//!
+//! Interfaces are the devices of `pcap_findalldevs`, named `\Device\NPF_{GUID}`. pcap
+//! doesn't tell their MAC addresses, MTUs or link types, so `mac` and `mtu` are None and
+//! `link_type` is `LinkType::VOID`.
+//!
+//! Fanout, rings and cooked channels have no npcap counterpart and are rejected; write
+//! timeouts don't apply.
+
+use super::{
+    Channel, ChannelType, Config, EthernetDataLinkChannelIterator, EthernetDataLinkReceiver,
+    EthernetDataLinkSender, RxMeta,
+};
+use crate::arp::{
+    ether::{EthernetPacket, Packet},
+    network_interface::{LinkType, NetworkInterface, OperState},
+};
+use crate::cidr::IpCidr;
+use std::{
+    ffi::{CStr, CString},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    os::raw::{c_char, c_int, c_uchar},
+    ptr, slice,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Open a channel on `network_interface`, a device of [`get_interfaces`].
+pub(super) fn channel(network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+    if config.channel_type != ChannelType::Layer2
+        || config.fanout.is_some()
+        || config.rx_ring.is_some()
+        || config.tx_ring.is_some()
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Only plain Layer2 channels are supported by npcap",
+        ));
+    }
+    // 0 waits for ever, so a timeout rounding down to it waits a millisecond instead
+    let read_timeout = config.read_timeout.map_or(0, |to| {
+        to.as_millis().max(1).min(c_int::MAX as u128) as c_int
+    });
+    let receiver = Handle::open(
+        &network_interface.name,
+        config.read_buffer_size,
+        config.promiscuous,
+        read_timeout,
+    )?;
+    if config.nonblocking {
+        receiver.set_nonblocking()?;
+    }
+    let sender = Handle::open(&network_interface.name, 0, false, 0)?;
+    trace_event!(debug, interface = %network_interface.name, "npcap channel open");
+
+    Ok(Channel::Ethernet(
+        Box::new(NpcapSender { handle: sender }),
+        Box::new(NpcapReceiver {
+            handle: receiver,
+            nonblocking: config.nonblocking,
+        }),
+    ))
+}
+
+/// The capture devices of the host, empty if they can't be listed; see
+/// `try_get_interfaces`.
+pub fn get_interfaces() -> Vec<NetworkInterface> {
+    try_get_interfaces().unwrap_or_default()
+}
+
+/// The capture devices of the host, failing if `pcap_findalldevs` does. Their index is
+/// their position in the list, from 1.
+pub fn try_get_interfaces() -> crate::error::Result<Vec<NetworkInterface>> {
+    let mut errbuf = [0 as c_char; sys::PCAP_ERRBUF_SIZE];
+    let mut devices: *mut sys::pcap_if_t = ptr::null_mut();
+    if unsafe { sys::pcap_findalldevs(&mut devices, errbuf.as_mut_ptr()) } == -1 {
+        return Err(pcap_error(errbuf.as_ptr()).into());
+    }
+
+    let mut interfaces = Vec::new();
+    let mut device = devices;
+    while !device.is_null() {
+        let dev = unsafe { &*device };
+        let (mut ips, mut addresses) = (Vec::new(), Vec::new());
+        let mut address = dev.addresses;
+        while !address.is_null() {
+            let addr = unsafe { &*address };
+            if let Some(ip) = unsafe { sockaddr_to_ip(addr.addr) } {
+                let prefix_len = match unsafe { sockaddr_to_ip(addr.netmask) } {
+                    Some(IpAddr::V4(mask)) => u32::from(mask).count_ones(),
+                    Some(IpAddr::V6(mask)) => u128::from(mask).count_ones(),
+                    None if ip.is_ipv4() => 32,
+                    None => 128,
+                };
+                ips.push(ip);
+                addresses.push(IpCidr::new(ip, prefix_len as u8));
+            }
+            address = addr.next;
+        }
+        let oper_state = match dev.flags & sys::PCAP_IF_CONNECTION_STATUS {
+            sys::PCAP_IF_CONNECTION_STATUS_CONNECTED => OperState::Up,
+            sys::PCAP_IF_CONNECTION_STATUS_DISCONNECTED => OperState::Down,
+            _ => OperState::Unknown,
+        };
+        interfaces.push(NetworkInterface {
+            name: unsafe { CStr::from_ptr(dev.name) }
+                .to_string_lossy()
+                .into_owned(),
+            index: interfaces.len() as u32 + 1,
+            mac: None,
+            ips: Some(ips),
+            flags: dev.flags,
+            mtu: None,
+            oper_state,
+            link_type: LinkType::VOID,
+            addresses,
+        });
+        device = dev.next;
+    }
+    unsafe { sys::pcap_freealldevs(devices) };
+    Ok(interfaces)
+}
+
+unsafe fn sockaddr_to_ip(addr: *const sys::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    match c_int::from((*addr).sa_family) {
+        sys::AF_INET => {
+            let addr = &*(addr as *const sys::sockaddr_in);
+            Some(IpAddr::V4(Ipv4Addr::from(addr.sin_addr)))
+        }
+        sys::AF_INET6 => {
+            let addr = &*(addr as *const sys::sockaddr_in6);
+            Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr)))
+        }
+        _ => None,
+    }
+}
+
+fn pcap_error(message: *const c_char) -> io::Error {
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    io::Error::new(io::ErrorKind::Other, message.into_owned())
+}
+
+/// An open `pcap_t`.
+struct Handle(*mut sys::pcap_t);
+
+// a handle is used by the one half owning it
+unsafe impl Send for Handle {}
+
+impl Handle {
+    fn open(
+        name: &str,
+        snaplen: usize,
+        promiscuous: bool,
+        timeout_ms: c_int,
+    ) -> io::Result<Handle> {
+        let name = CString::new(name)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid interface name"))?;
+        let mut errbuf = [0 as c_char; sys::PCAP_ERRBUF_SIZE];
+        let handle = unsafe {
+            sys::pcap_open_live(
+                name.as_ptr(),
+                snaplen.min(c_int::MAX as usize) as c_int,
+                promiscuous as c_int,
+                timeout_ms,
+                errbuf.as_mut_ptr(),
+            )
+        };
+        if handle.is_null() {
+            let err = pcap_error(errbuf.as_ptr());
+            trace_event!(warn, error = %err, "opening npcap device failed");
+            return Err(err);
+        }
+        Ok(Handle(handle))
+    }
+
+    fn set_nonblocking(&self) -> io::Result<()> {
+        let mut errbuf = [0 as c_char; sys::PCAP_ERRBUF_SIZE];
+        if unsafe { sys::pcap_setnonblock(self.0, 1, errbuf.as_mut_ptr()) } == -1 {
+            return Err(pcap_error(errbuf.as_ptr()));
+        }
+        Ok(())
+    }
+
+    /// The error of the last call that failed on the handle.
+    fn error(&self) -> io::Error {
+        pcap_error(unsafe { sys::pcap_geterr(self.0) })
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { sys::pcap_close(self.0) }
+    }
+}
+
+struct NpcapSender {
+    handle: Handle,
+}
+
+impl EthernetDataLinkSender for NpcapSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        _dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        let frame = packet.packet();
+        if unsafe { sys::pcap_sendpacket(self.handle.0, frame.as_ptr(), frame.len() as c_int) }
+            == -1
+        {
+            let err = self.handle.error();
+            trace_event!(debug, error = %err, "send failed");
+            return Some(Err(err));
+        }
+        trace_event!(trace, len = frame.len(), "sent frame");
+        Some(Ok(()))
+    }
+}
+
+struct NpcapReceiver {
+    handle: Handle,
+    nonblocking: bool,
+}
+
+impl EthernetDataLinkReceiver for NpcapReceiver {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(NpcapChannelIterator { pc: self })
+    }
+}
+
+struct NpcapChannelIterator<'a> {
+    pc: &'a mut NpcapReceiver,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for NpcapChannelIterator<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        self.next_with_meta().map(|(packet, _)| packet)
+    }
+
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let mut header: *mut sys::pcap_pkthdr = ptr::null_mut();
+        let mut data: *const c_uchar = ptr::null();
+        match unsafe { sys::pcap_next_ex(self.pc.handle.0, &mut header, &mut data) } {
+            1 => {}
+            0 if self.pc.nonblocking => {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "Would block"))
+            }
+            0 => return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out")),
+            _ => return Err(self.pc.handle.error()),
+        }
+        // valid until the next call on the handle, which borrows the receiver
+        let header = unsafe { &*header };
+        let frame = unsafe { slice::from_raw_parts(data, header.caplen as usize) };
+        let packet = EthernetPacket::new(frame)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))?;
+        let mut meta = RxMeta::from_frame(&packet);
+        meta.timestamp =
+            UNIX_EPOCH + Duration::new(header.ts.tv_sec as u64, header.ts.tv_usec as u32 * 1000);
+        meta.original_len = header.len as usize;
+        Ok((packet, meta))
+    }
+}
+
+/// The parts of `pcap.h` and `winsock2.h` used here.
+#[allow(non_camel_case_types)]
+mod sys {
+    use std::os::raw::{c_char, c_int, c_long, c_uchar, c_uint, c_ushort};
+
+    pub const PCAP_ERRBUF_SIZE: usize = 256;
+    pub const AF_INET: c_int = 2;
+    pub const AF_INET6: c_int = 23;
+    pub const PCAP_IF_CONNECTION_STATUS: c_uint = 0x30;
+    pub const PCAP_IF_CONNECTION_STATUS_CONNECTED: c_uint = 0x10;
+    pub const PCAP_IF_CONNECTION_STATUS_DISCONNECTED: c_uint = 0x20;
+
+    pub enum pcap_t {}
+
+    #[repr(C)]
+    pub struct pcap_if_t {
+        pub next: *mut pcap_if_t,
+        pub name: *mut c_char,
+        pub description: *mut c_char,
+        pub addresses: *mut pcap_addr,
+        pub flags: c_uint,
+    }
+
+    #[repr(C)]
+    pub struct pcap_addr {
+        pub next: *mut pcap_addr,
+        pub addr: *mut sockaddr,
+        pub netmask: *mut sockaddr,
+        pub broadaddr: *mut sockaddr,
+        pub dstaddr: *mut sockaddr,
+    }
+
+    #[repr(C)]
+    pub struct sockaddr {
+        pub sa_family: c_ushort,
+        pub sa_data: [c_char; 14],
+    }
+
+    #[repr(C)]
+    pub struct sockaddr_in {
+        pub sin_family: c_ushort,
+        pub sin_port: c_ushort,
+        pub sin_addr: [u8; 4],
+        pub sin_zero: [u8; 8],
+    }
+
+    #[repr(C)]
+    pub struct sockaddr_in6 {
+        pub sin6_family: c_ushort,
+        pub sin6_port: c_ushort,
+        pub sin6_flowinfo: c_uint,
+        pub sin6_addr: [u8; 16],
+        pub sin6_scope_id: c_uint,
+    }
+
+    #[repr(C)]
+    pub struct timeval {
+        pub tv_sec: c_long,
+        pub tv_usec: c_long,
+    }
+
+    #[repr(C)]
+    pub struct pcap_pkthdr {
+        pub ts: timeval,
+        pub caplen: c_uint,
+        pub len: c_uint,
+    }
+
+    #[link(name = "wpcap")]
+    extern "C" {
+        pub fn pcap_findalldevs(alldevs: *mut *mut pcap_if_t, errbuf: *mut c_char) -> c_int;
+        pub fn pcap_freealldevs(alldevs: *mut pcap_if_t);
+        pub fn pcap_open_live(
+            device: *const c_char,
+            snaplen: c_int,
+            promisc: c_int,
+            to_ms: c_int,
+            errbuf: *mut c_char,
+        ) -> *mut pcap_t;
+        pub fn pcap_setnonblock(p: *mut pcap_t, nonblock: c_int, errbuf: *mut c_char) -> c_int;
+        pub fn pcap_next_ex(
+            p: *mut pcap_t,
+            header: *mut *mut pcap_pkthdr,
+            data: *mut *const c_uchar,
+        ) -> c_int;
+        pub fn pcap_sendpacket(p: *mut pcap_t, buf: *const c_uchar, size: c_int) -> c_int;
+        pub fn pcap_geterr(p: *mut pcap_t) -> *mut c_char;
+        pub fn pcap_close(p: *mut pcap_t);
+    }
+}
@@ -1,7 +1,9 @@
-use super::network_interface::{MacAddr, NetworkInterface};
+use super::network_interface::MacAddr;
+use crate::pool::Buffer;
 use std::{
-    mem,
+    collections::BTreeMap,
     ops::{Deref, DerefMut, Index, IndexMut, Range, RangeFrom, RangeFull, RangeTo},
+    sync::RwLock,
 };
 
 /// Represents a generic network packet.
@@ -81,6 +83,8 @@ pub enum PacketData<'p> {
     Owned(Vec<u8>),
     /// A packet borrows its contents.
     Borrowed(&'p [u8]),
+    /// A packet holds a buffer leased from a pool.
+    Pooled(Buffer),
 }
 
 impl<'p> PacketData<'p> {
@@ -89,6 +93,7 @@ impl<'p> PacketData<'p> {
         match self {
             &PacketData::Owned(ref data) => data.deref(),
             &PacketData::Borrowed(ref data) => data,
+            &PacketData::Pooled(ref data) => data,
         }
     }
 
@@ -204,6 +209,49 @@ impl PrimitiveValues for ::std::net::Ipv6Addr {
     }
 }
 
+/// Length of the ARP or IP packet at the start of `payload`, or the length of `payload`
+/// for other protocols and inconsistent lengths.
+fn network_len(ethertype: EtherType, payload: &[u8]) -> usize {
+    let len = match ethertype {
+        // hardware and protocol address lengths
+        EtherTypes::Arp if payload.len() >= 8 => {
+            8 + 2 * (payload[4] as usize + payload[5] as usize)
+        }
+        EtherTypes::Ipv4 if payload.len() >= 20 => {
+            let total_len = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+            // offloaded packets may not have their length filled in
+            if total_len >= (payload[0] & 0x0f) as usize * 4 {
+                total_len
+            } else {
+                payload.len()
+            }
+        }
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            match u16::from_be_bytes([payload[4], payload[5]]) as usize {
+                // jumbograms and offloaded packets
+                0 => payload.len(),
+                payload_len => 40 + payload_len,
+            }
+        }
+        _ => payload.len(),
+    };
+    len.min(payload.len())
+}
+
+/// `frame` without the padding, or trailer, following a short ARP or IP packet, also
+/// behind a single VLAN tag.
+pub fn strip_trailer(frame: &[u8]) -> &[u8] {
+    let (ethertype, header_len) = match EthernetPacket::new(frame) {
+        Some(ethernet) if ethernet.get_ethertype() == EtherTypes::Vlan && frame.len() >= 18 => (
+            EtherType::new(u16::from_be_bytes([frame[16], frame[17]])),
+            18,
+        ),
+        Some(ethernet) => (ethernet.get_ethertype(), 14),
+        None => return frame,
+    };
+    &frame[..header_len + network_len(ethertype, &frame[header_len..])]
+}
+
 pub struct EthernetPacket<'p> {
     packet: PacketData<'p>,
 }
@@ -237,6 +285,17 @@ impl<'a> EthernetPacket<'a> {
             None
         }
     }
+    /// Constructs a new EthernetPacket holding a pooled buffer, which goes back to its pool
+    /// when the EthernetPacket is dropped. Returns None if the buffer is too short.
+    pub fn pooled(packet: Buffer) -> Option<EthernetPacket<'static>> {
+        if packet.len() >= EthernetPacket::minimum_packet_size() {
+            Some(EthernetPacket {
+                packet: PacketData::Pooled(packet),
+            })
+        } else {
+            None
+        }
+    }
     /// Maps from a EthernetPacket to a EthernetPacket
     #[inline]
     pub fn to_immutable<'p>(&'p self) -> EthernetPacket<'p> {
@@ -391,6 +450,12 @@ impl<'a> EthernetPacket<'a> {
         }
         EtherType::new(get_arg0(&self))
     }
+    /// The payload without the padding, or trailer, that follows short ARP and IP packets,
+    /// cut to the length their header gives. Other payloads are returned whole.
+    pub fn payload_without_trailer(&self) -> &[u8] {
+        let payload = self.payload();
+        &payload[..network_len(self.get_ethertype(), payload)]
+    }
 }
 impl<'a> MutableEthernetPacket<'a> {
     /// Constructs a new MutableEthernetPacket. If the provided buffer is less than the minimum required
@@ -881,123 +946,209 @@ pub struct Ethernet {
     pub payload: Vec<u8>,
 }
 
+enum_with_unknown! {
+    /// Represents the `Ethernet::ethertype` field.
+    #[derive(Hash)]
+    pub doc enum EtherType(u16) {
+        /// Internet Protocol version 4 (IPv4) [RFC7042].
+        Ipv4 = 0x0800,
+        /// Address Resolution Protocol (ARP) [RFC7042].
+        Arp = 0x0806,
+        /// Wake on Lan.
+        WakeOnLan = 0x0842,
+        /// IETF TRILL Protocol [IEEE].
+        Trill = 0x22f3,
+        /// DECnet Phase IV.
+        DECnet = 0x6003,
+        /// Reverse Address Resolution Protocol (RARP) [RFC903].
+        Rarp = 0x8035,
+        /// AppleTalk - EtherTalk [Apple].
+        AppleTalk = 0x809b,
+        /// AppleTalk Address Resolution Protocol (AARP) [Apple].
+        Aarp = 0x80f3,
+        /// IPX [Xerox].
+        Ipx = 0x8137,
+        /// QNX Qnet [QNX Software Systems].
+        Qnx = 0x8204,
+        /// Internet Protocol version 6 (IPv6) [RFC7042].
+        Ipv6 = 0x86dd,
+        /// Ethernet Flow Control [IEEE 802.3x].
+        FlowControl = 0x8808,
+        /// CobraNet [CobraNet].
+        CobraNet = 0x8819,
+        /// MPLS Unicast [RFC 3032].
+        Mpls = 0x8847,
+        /// MPLS Multicast [RFC 5332].
+        MplsMcast = 0x8848,
+        /// PPPOE Discovery Stage [RFC 2516].
+        PppoeDiscovery = 0x8863,
+        /// PPPoE Session Stage [RFC 2516].
+        PppoeSession = 0x8864,
+        /// VLAN-tagged frame (IEEE 802.1Q).
+        Vlan = 0x8100,
+        /// Provider Bridging [IEEE 802.1ad / IEEE 802.1aq].
+        PBridge = 0x88a8,
+        /// Link Layer Discovery Protocol (LLDP) [IEEE 802.1AB].
+        Lldp = 0x88cc,
+        /// Precision Time Protocol (PTP) over Ethernet [IEEE 1588].
+        Ptp = 0x88f7,
+        /// CFM / Y.1731 [IEEE 802.1ag].
+        Cfm = 0x8902,
+        /// Q-in-Q Vlan Tagging [IEEE 802.1Q].
+        QinQ = 0x9100
+    }
+}
+
+/// The known ethertypes under their former constant names.
 #[allow(non_snake_case)]
 #[allow(non_upper_case_globals)]
 pub mod EtherTypes {
     use super::EtherType;
 
-    // use ethernet::EtherType;
     /// Internet Protocol version 4 (IPv4) [RFC7042].
-    pub const Ipv4: EtherType = EtherType(2048);
+    pub const Ipv4: EtherType = EtherType::Ipv4;
     /// Address Resolution Protocol (ARP) [RFC7042].
-    pub const Arp: EtherType = EtherType(2054);
+    pub const Arp: EtherType = EtherType::Arp;
     /// Wake on Lan.
-    pub const WakeOnLan: EtherType = EtherType(2114);
+    pub const WakeOnLan: EtherType = EtherType::WakeOnLan;
     /// IETF TRILL Protocol [IEEE].
-    pub const Trill: EtherType = EtherType(8947);
+    pub const Trill: EtherType = EtherType::Trill;
     /// DECnet Phase IV.
-    pub const DECnet: EtherType = EtherType(24579);
+    pub const DECnet: EtherType = EtherType::DECnet;
     /// Reverse Address Resolution Protocol (RARP) [RFC903].
-    pub const Rarp: EtherType = EtherType(32821);
+    pub const Rarp: EtherType = EtherType::Rarp;
     /// AppleTalk - EtherTalk [Apple].
-    pub const AppleTalk: EtherType = EtherType(32923);
+    pub const AppleTalk: EtherType = EtherType::AppleTalk;
     /// AppleTalk Address Resolution Protocol (AARP) [Apple].
-    pub const Aarp: EtherType = EtherType(33011);
+    pub const Aarp: EtherType = EtherType::Aarp;
     /// IPX [Xerox].
-    pub const Ipx: EtherType = EtherType(33079);
+    pub const Ipx: EtherType = EtherType::Ipx;
     /// QNX Qnet [QNX Software Systems].
-    pub const Qnx: EtherType = EtherType(33284);
+    pub const Qnx: EtherType = EtherType::Qnx;
     /// Internet Protocol version 6 (IPv6) [RFC7042].
-    pub const Ipv6: EtherType = EtherType(34525);
+    pub const Ipv6: EtherType = EtherType::Ipv6;
     /// Ethernet Flow Control [IEEE 802.3x].
-    pub const FlowControl: EtherType = EtherType(34824);
+    pub const FlowControl: EtherType = EtherType::FlowControl;
     /// CobraNet [CobraNet].
-    pub const CobraNet: EtherType = EtherType(34841);
+    pub const CobraNet: EtherType = EtherType::CobraNet;
     /// MPLS Unicast [RFC 3032].
-    pub const Mpls: EtherType = EtherType(34887);
+    pub const Mpls: EtherType = EtherType::Mpls;
     /// MPLS Multicast [RFC 5332].
-    pub const MplsMcast: EtherType = EtherType(34888);
+    pub const MplsMcast: EtherType = EtherType::MplsMcast;
     /// PPPOE Discovery Stage [RFC 2516].
-    pub const PppoeDiscovery: EtherType = EtherType(34915);
+    pub const PppoeDiscovery: EtherType = EtherType::PppoeDiscovery;
     /// PPPoE Session Stage [RFC 2516].
-    pub const PppoeSession: EtherType = EtherType(34916);
+    pub const PppoeSession: EtherType = EtherType::PppoeSession;
     /// VLAN-tagged frame (IEEE 802.1Q).
-    pub const Vlan: EtherType = EtherType(33024);
+    pub const Vlan: EtherType = EtherType::Vlan;
     /// Provider Bridging [IEEE 802.1ad / IEEE 802.1aq].
-    pub const PBridge: EtherType = EtherType(34984);
+    pub const PBridge: EtherType = EtherType::PBridge;
     /// Link Layer Discovery Protocol (LLDP) [IEEE 802.1AB].
-    pub const Lldp: EtherType = EtherType(35020);
+    pub const Lldp: EtherType = EtherType::Lldp;
     /// Precision Time Protocol (PTP) over Ethernet [IEEE 1588].
-    pub const Ptp: EtherType = EtherType(35063);
+    pub const Ptp: EtherType = EtherType::Ptp;
     /// CFM / Y.1731 [IEEE 802.1ag].
-    pub const Cfm: EtherType = EtherType(35074);
+    pub const Cfm: EtherType = EtherType::Cfm;
     /// Q-in-Q Vlan Tagging [IEEE 802.1Q].
-    pub const QinQ: EtherType = EtherType(37120);
+    pub const QinQ: EtherType = EtherType::QinQ;
+}
+
+// names given to private ethertypes with `register_ethertype`
+static REGISTRY: RwLock<BTreeMap<u16, String>> = RwLock::new(BTreeMap::new());
+
+/// Name `value` in the Display output of [`EtherType::Unknown`], e.g. for an ethertype
+/// used by an in-house protocol. Registering a value again replaces its name.
+pub fn register_ethertype(value: u16, name: &str) {
+    REGISTRY.write().unwrap().insert(value, name.to_string());
+}
+
+/// Forget the name registered for `value`.
+pub fn unregister_ethertype(value: u16) {
+    REGISTRY.write().unwrap().remove(&value);
 }
-/// Represents the `Ethernet::ethertype` field.
-#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Clone, Copy)]
-pub struct EtherType(pub u16);
+
+/// The name registered for `value`, if any.
+pub fn registered_ethertype(value: u16) -> Option<String> {
+    REGISTRY.read().unwrap().get(&value).cloned()
+}
+
 impl EtherType {
     /// Construct a new `EtherType` instance.
     pub fn new(val: u16) -> EtherType {
-        EtherType(val)
+        EtherType::from(val)
+    }
+
+    /// The numeric value of the ethertype, like `u16::from` but usable in constants.
+    pub const fn value(self) -> u16 {
+        match self {
+            EtherType::Ipv4 => 0x0800,
+            EtherType::Arp => 0x0806,
+            EtherType::WakeOnLan => 0x0842,
+            EtherType::Trill => 0x22f3,
+            EtherType::DECnet => 0x6003,
+            EtherType::Rarp => 0x8035,
+            EtherType::AppleTalk => 0x809b,
+            EtherType::Aarp => 0x80f3,
+            EtherType::Ipx => 0x8137,
+            EtherType::Qnx => 0x8204,
+            EtherType::Ipv6 => 0x86dd,
+            EtherType::FlowControl => 0x8808,
+            EtherType::CobraNet => 0x8819,
+            EtherType::Mpls => 0x8847,
+            EtherType::MplsMcast => 0x8848,
+            EtherType::PppoeDiscovery => 0x8863,
+            EtherType::PppoeSession => 0x8864,
+            EtherType::Vlan => 0x8100,
+            EtherType::PBridge => 0x88a8,
+            EtherType::Lldp => 0x88cc,
+            EtherType::Ptp => 0x88f7,
+            EtherType::Cfm => 0x8902,
+            EtherType::QinQ => 0x9100,
+            EtherType::Unknown(value) => value,
+        }
     }
 }
+
 impl PrimitiveValues for EtherType {
     type T = (u16,);
     fn to_primitive_values(&self) -> (u16,) {
-        (self.0,)
+        (u16::from(*self),)
     }
 }
+
 impl std::fmt::Display for EtherType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                &EtherTypes::Ipv4 => "Ipv4",
-                &EtherTypes::Arp => "Arp",
-                &EtherTypes::WakeOnLan => "WakeOnLan",
-                &EtherTypes::Trill => "Trill",
-                &EtherTypes::DECnet => "DECnet",
-                &EtherTypes::Rarp => "Rarp",
-                &EtherTypes::AppleTalk => "AppleTalk",
-                &EtherTypes::Aarp => "Aarp",
-                &EtherTypes::Ipx => "Ipx",
-                &EtherTypes::Qnx => "Qnx",
-                &EtherTypes::Ipv6 => "Ipv6",
-                &EtherTypes::FlowControl => "FlowControl",
-                &EtherTypes::CobraNet => "CobraNet",
-                &EtherTypes::Mpls => "Mpls",
-                &EtherTypes::MplsMcast => "MplsMcast",
-                &EtherTypes::PppoeDiscovery => "PppoeDiscovery",
-                &EtherTypes::PppoeSession => "PppoeSession",
-                &EtherTypes::Vlan => "Vlan",
-                &EtherTypes::PBridge => "PBridge",
-                &EtherTypes::Lldp => "Lldp",
-                &EtherTypes::Ptp => "Ptp",
-                &EtherTypes::Cfm => "Cfm",
-                &EtherTypes::QinQ => "QinQ",
-                _ => "unknown",
+        let name = match *self {
+            EtherType::Ipv4 => "Ipv4",
+            EtherType::Arp => "Arp",
+            EtherType::WakeOnLan => "WakeOnLan",
+            EtherType::Trill => "Trill",
+            EtherType::DECnet => "DECnet",
+            EtherType::Rarp => "Rarp",
+            EtherType::AppleTalk => "AppleTalk",
+            EtherType::Aarp => "Aarp",
+            EtherType::Ipx => "Ipx",
+            EtherType::Qnx => "Qnx",
+            EtherType::Ipv6 => "Ipv6",
+            EtherType::FlowControl => "FlowControl",
+            EtherType::CobraNet => "CobraNet",
+            EtherType::Mpls => "Mpls",
+            EtherType::MplsMcast => "MplsMcast",
+            EtherType::PppoeDiscovery => "PppoeDiscovery",
+            EtherType::PppoeSession => "PppoeSession",
+            EtherType::Vlan => "Vlan",
+            EtherType::PBridge => "PBridge",
+            EtherType::Lldp => "Lldp",
+            EtherType::Ptp => "Ptp",
+            EtherType::Cfm => "Cfm",
+            EtherType::QinQ => "QinQ",
+            EtherType::Unknown(value) => {
+                return match registered_ethertype(value) {
+                    Some(name) => f.write_str(&name),
+                    None => f.write_str("unknown"),
+                };
             }
-        )
-    }
-}
-
-pub fn network_addr_to_sockaddr(
-    ni: &NetworkInterface,
-    storage: *mut libc::sockaddr_storage,
-    proto: libc::c_int,
-) -> usize {
-    unsafe {
-        let sll: *mut libc::sockaddr_ll = mem::transmute(storage);
-        (*sll).sll_family = libc::AF_PACKET as libc::sa_family_t;
-        if let Some(MacAddr(a, b, c, d, e, f)) = ni.mac {
-            (*sll).sll_addr = [a, b, c, d, e, f, 0, 0];
-        }
-        (*sll).sll_protocol = (proto as u16).to_be();
-        (*sll).sll_halen = 6;
-        (*sll).sll_ifindex = ni.index as i32;
-        mem::size_of::<libc::sockaddr_ll>()
+        };
+        f.write_str(name)
     }
 }
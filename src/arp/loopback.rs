@@ -0,0 +1,516 @@
+use super::{
+    channel::{
+        Channel, ChannelType, Config, DatalinkBackend, EthernetDataLinkChannelIterator,
+        EthernetDataLinkReceiver, EthernetDataLinkSender,
+    },
+    ether::{EthernetPacket, Packet},
+    network_interface::{LinkType, MacAddr, NetworkInterface, OperState, FLAG_LOOPBACK, FLAG_UP},
+};
+use crate::{cidr::IpCidr, rng::Rng};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io,
+    net::IpAddr,
+    sync::{mpsc, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Name reported by the loopback device's `NetworkInterface`.
+pub const LOOPBACK_NAME: &str = "myox-lo";
+
+/// Longest a reordered frame is held back waiting for the frame to deliver before it.
+pub const REORDER_HOLD: Duration = Duration::from_millis(10);
+
+/// Impairments a `Loopback` device applies to the frames it delivers.
+///
+/// Each frame is judged separately for every receiver, from a seeded generator so that a
+/// run can be repeated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Faults {
+    /// Probability in `[0, 1]` that a frame is lost. Defaults to 0
+    pub loss: f64,
+
+    /// Probability in `[0, 1]` that a frame is delivered twice. Defaults to 0
+    pub duplicate: f64,
+
+    /// Probability in `[0, 1]` that a frame is held back and delivered after the next
+    /// one, or after [`REORDER_HOLD`] or at the read timeout if none comes. Defaults to 0
+    pub reorder: f64,
+
+    /// Probability in `[0, 1]` that a random bit of a frame is flipped. Defaults to 0
+    pub corrupt: f64,
+
+    /// Time between sending a frame and it being received. Defaults to zero
+    pub latency: Duration,
+
+    /// Upper bound of a random delay added to the latency of each frame, which reorders
+    /// frames sent closer together than that. Defaults to zero
+    pub jitter: Duration,
+
+    /// Seed of the random decisions. Defaults to 1
+    pub seed: u64,
+}
+
+impl Default for Faults {
+    fn default() -> Faults {
+        Faults {
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            corrupt: 0.0,
+            latency: Duration::from_secs(0),
+            jitter: Duration::from_secs(0),
+            seed: 1,
+        }
+    }
+}
+
+/// Counters of the faults a `Loopback` device injected.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct FaultStats {
+    /// Frames dropped
+    pub lost: u64,
+    /// Extra copies delivered
+    pub duplicated: u64,
+    /// Frames held back behind the next one
+    pub reordered: u64,
+    /// Frames with a flipped bit
+    pub corrupted: u64,
+}
+
+/// An in-process loopback device.
+///
+/// Every frame sent on a channel opened on the device is delivered to the receivers of all
+/// channels opened on it, including the sending one, the same way a packet socket bound to
+/// `lo` sees its own traffic. No TAP device or privileges are needed. Loss, duplication,
+/// reordering, corruption and delays can be injected with [`Faults`].
+///
+/// As a [`DatalinkBackend`] the device stands in for every interface, so that code opening
+/// its own channels, like a `Resolver` opened with `open_with_backend`, can be tested on it
+/// as it is.
+#[derive(Clone)]
+pub struct Loopback {
+    interface: NetworkInterface,
+    shared: Arc<Mutex<Shared>>,
+}
+
+struct Shared {
+    endpoints: Vec<Endpoint>,
+    faults: Faults,
+    rng: Rng,
+    sequence: u64,
+    stats: FaultStats,
+}
+
+struct Endpoint {
+    queue: mpsc::Sender<Delivery>,
+    // a reordered frame went out and the next one releases it
+    holding: bool,
+}
+
+// ordered by due time, then by sending order
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Delivery {
+    due: Instant,
+    sequence: u64,
+    frame: Vec<u8>,
+    // to be delivered after the next frame
+    reordered: bool,
+}
+
+impl Loopback {
+    /// Create a new loopback device.
+    pub fn new() -> Loopback {
+        Loopback::with_faults(Default::default())
+    }
+
+    /// Create a new loopback device injecting `faults`.
+    pub fn with_faults(faults: Faults) -> Loopback {
+        Loopback {
+            interface: NetworkInterface {
+                name: LOOPBACK_NAME.to_owned(),
+                index: 0,
+                mac: Some(MacAddr::new(0, 0, 0, 0, 0, 0)),
+                ips: None,
+                flags: FLAG_UP | FLAG_LOOPBACK,
+                mtu: None,
+                oper_state: OperState::Up,
+                link_type: LinkType::LOOPBACK,
+                addresses: Vec::new(),
+            },
+            shared: Arc::new(Mutex::new(Shared {
+                endpoints: Vec::new(),
+                faults,
+                rng: Rng::new(faults.seed),
+                sequence: 0,
+                stats: Default::default(),
+            })),
+        }
+    }
+
+    /// Describe the device as having the address `mac` and the IP addresses `ips`, which
+    /// code sending from the addresses of the interface uses. Defaults to the all-zero
+    /// address and no IP addresses.
+    pub fn with_address(mut self, mac: MacAddr, ips: Vec<IpAddr>) -> Loopback {
+        self.interface.mac = Some(mac);
+        // a host route each, as the device has no networks
+        self.interface.addresses = ips.iter().map(|ip| IpCidr::host(*ip)).collect();
+        self.interface.ips = Some(ips);
+        self
+    }
+
+    /// Return the interface description of the device.
+    pub fn interface(&self) -> &NetworkInterface {
+        &self.interface
+    }
+
+    /// Inject `faults` into the frames sent from now on, reseeding the generator.
+    pub fn set_faults(&self, faults: Faults) {
+        let mut shared = self.shared.lock().unwrap();
+        shared.faults = faults;
+        shared.rng = Rng::new(faults.seed);
+    }
+
+    /// Counters of the faults injected so far.
+    pub fn fault_stats(&self) -> FaultStats {
+        self.shared.lock().unwrap().stats
+    }
+
+    /// Open a new channel on the device.
+    ///
+    /// Only `read_buffer_size` and `read_timeout` of `config` are used: received frames are
+    /// truncated to the read buffer size like on a real socket. Cooked channels are
+    /// rejected.
+    pub fn channel(&self, config: Config) -> io::Result<Channel> {
+        if config.channel_type != ChannelType::Layer2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only Layer2 channels are supported by the loopback device",
+            ));
+        }
+        let (tx, rx) = mpsc::channel();
+        self.shared.lock().unwrap().endpoints.push(Endpoint {
+            queue: tx,
+            holding: false,
+        });
+
+        let sender = Box::new(LoopbackSender {
+            shared: self.shared.clone(),
+        });
+        let receiver = Box::new(LoopbackReceiver {
+            queue: rx,
+            pending: BinaryHeap::new(),
+            held: None,
+            released: None,
+            read_buffer: Vec::with_capacity(config.read_buffer_size),
+            read_buffer_size: config.read_buffer_size,
+            timeout: config.read_timeout,
+        });
+
+        Ok(Channel::Ethernet(sender, receiver))
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Loopback {
+        Loopback::new()
+    }
+}
+
+impl DatalinkBackend for Loopback {
+    /// Open a channel on the device, whatever `network_interface` is.
+    fn channel(
+        &self,
+        _network_interface: &NetworkInterface,
+        config: Config,
+    ) -> io::Result<Channel> {
+        Loopback::channel(self, config)
+    }
+
+    /// The device alone.
+    fn interfaces(&self) -> io::Result<Vec<NetworkInterface>> {
+        Ok(vec![self.interface.clone()])
+    }
+}
+
+impl Shared {
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.next_f64() < probability
+    }
+
+    fn delivery(&mut self, frame: Vec<u8>, reordered: bool, sent: Instant) -> Delivery {
+        let mut due = sent + self.faults.latency;
+        if self.faults.jitter > Duration::from_secs(0) {
+            due += self.faults.jitter.mul_f64(self.rng.next_f64());
+        }
+        self.sequence += 1;
+        Delivery {
+            due,
+            sequence: self.sequence,
+            frame,
+            reordered,
+        }
+    }
+
+    /// The copies of `frame` endpoint `index` gets after faults, and whether the receiver
+    /// holds them back.
+    fn impair(&mut self, index: usize, frame: &[u8]) -> Vec<(Vec<u8>, bool)> {
+        if self.chance(self.faults.loss) {
+            self.stats.lost += 1;
+            return Vec::new();
+        }
+        let mut frame = frame.to_vec();
+        if !frame.is_empty() && self.chance(self.faults.corrupt) {
+            let bit = (self.rng.next_f64() * (frame.len() * 8) as f64) as usize;
+            frame[bit / 8] ^= 1 << (bit % 8);
+            self.stats.corrupted += 1;
+        }
+        let mut copies = vec![(frame, false)];
+        if self.chance(self.faults.duplicate) {
+            copies.push(copies[0].clone());
+            self.stats.duplicated += 1;
+        }
+        // the receiver holds the frame back, so that it goes out even if nothing follows;
+        // a frame can only be held if none is
+        let endpoint = &mut self.endpoints[index];
+        if endpoint.holding {
+            endpoint.holding = false;
+        } else if self.chance(self.faults.reorder) {
+            self.endpoints[index].holding = true;
+            copies[0].1 = true;
+            self.stats.reordered += 1;
+        }
+        copies
+    }
+}
+
+struct LoopbackSender {
+    shared: Arc<Mutex<Shared>>,
+}
+
+impl EthernetDataLinkSender for LoopbackSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        _dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        let now = Instant::now();
+        let mut shared = self.shared.lock().unwrap();
+        let mut index = 0;
+        // receivers that were dropped are pruned on the way
+        while index < shared.endpoints.len() {
+            let mut connected = true;
+            for (frame, reordered) in shared.impair(index, packet.packet()) {
+                let delivery = shared.delivery(frame, reordered, now);
+                connected &= shared.endpoints[index].queue.send(delivery).is_ok();
+            }
+            if connected {
+                index += 1;
+            } else {
+                shared.endpoints.remove(index);
+            }
+        }
+        Some(Ok(()))
+    }
+}
+
+struct LoopbackReceiver {
+    queue: mpsc::Receiver<Delivery>,
+    // frames received from the queue but not due yet
+    pending: BinaryHeap<Reverse<Delivery>>,
+    // a reordered frame waiting for the next one, and when it goes out anyway
+    held: Option<(Vec<u8>, Instant)>,
+    // a held frame to deliver right after the one just delivered
+    released: Option<Vec<u8>>,
+    read_buffer: Vec<u8>,
+    read_buffer_size: usize,
+    timeout: Option<Duration>,
+}
+
+impl LoopbackReceiver {
+    /// The next due frame, waiting for at most the read timeout.
+    fn next_due(&mut self) -> io::Result<Vec<u8>> {
+        if let Some(frame) = self.released.take() {
+            return Ok(frame);
+        }
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut disconnected = false;
+        loop {
+            while let Ok(delivery) = self.queue.try_recv() {
+                self.pending.push(Reverse(delivery));
+            }
+            let now = Instant::now();
+            let due = self.pending.peek().map(|Reverse(delivery)| delivery.due);
+            if due.map_or(false, |due| due <= now) {
+                let delivery = self.pending.pop().unwrap().0;
+                let held = self.held.take().map(|(frame, _)| frame);
+                if delivery.reordered && held.is_none() {
+                    self.held = Some((delivery.frame, delivery.due + REORDER_HOLD));
+                    continue;
+                }
+                self.released = held;
+                return Ok(delivery.frame);
+            }
+            // a held frame goes out when nothing followed it in time
+            let release = self.held.as_ref().map(|&(_, release)| release);
+            if release.map_or(false, |release| release <= now)
+                || deadline.map_or(false, |deadline| deadline <= now)
+            {
+                return match self.held.take() {
+                    Some((frame, _)) => Ok(frame),
+                    None => Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out")),
+                };
+            }
+            let wake = [due, release, deadline].iter().flatten().min().copied();
+            if disconnected {
+                match wake {
+                    Some(wake) => thread::sleep(wake - now),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::BrokenPipe,
+                            "Loopback device closed",
+                        ))
+                    }
+                }
+                continue;
+            }
+            let received = match wake {
+                Some(wake) => self.queue.recv_timeout(wake - now),
+                None => self
+                    .queue
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(delivery) => self.pending.push(Reverse(delivery)),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => disconnected = true,
+            }
+        }
+    }
+}
+
+impl EthernetDataLinkReceiver for LoopbackReceiver {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(LoopbackChannelIterator { pc: self })
+    }
+}
+
+struct LoopbackChannelIterator<'a> {
+    pc: &'a mut LoopbackReceiver,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for LoopbackChannelIterator<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        let frame = self.pc.next_due()?;
+
+        let len = frame.len().min(self.pc.read_buffer_size);
+        self.pc.read_buffer.clear();
+        self.pc.read_buffer.extend_from_slice(&frame[..len]);
+
+        EthernetPacket::new(&self.pc.read_buffer[..])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(
+        device: &Loopback,
+        read_timeout: Option<Duration>,
+    ) -> (
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        let config = Config {
+            read_timeout,
+            ..Default::default()
+        };
+        match device.channel(config).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        }
+    }
+
+    fn frame(number: u16) -> Vec<u8> {
+        let mut frame = vec![0u8; 16];
+        frame[14..].copy_from_slice(&number.to_be_bytes());
+        frame
+    }
+
+    fn number(frame: &EthernetPacket) -> u16 {
+        u16::from_be_bytes([frame.packet()[14], frame.packet()[15]])
+    }
+
+    // the numbers of the frames received back, until the device is quiet
+    fn run(faults: Faults, count: u16) -> (Vec<u16>, FaultStats) {
+        let device = Loopback::with_faults(faults);
+        let (mut tx, mut rx) = open(&device, Some(Duration::from_millis(50)));
+        for n in 0..count {
+            tx.send_to(&EthernetPacket::new(&frame(n)).unwrap(), None);
+        }
+        let mut iter = rx.iter();
+        let mut received = Vec::new();
+        loop {
+            match iter.next() {
+                Ok(frame) => received.push(number(&frame)),
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+                    return (received, device.fault_stats());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn injects_faults_reproducibly() {
+        let faults = Faults {
+            loss: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            seed: 7,
+            ..Default::default()
+        };
+        let (received, stats) = run(faults, 500);
+        assert!(stats.lost > 0 && stats.duplicated > 0 && stats.reordered > 0);
+        assert_eq!(stats.corrupted, 0);
+        assert_eq!(received.len() as u64, 500 - stats.lost + stats.duplicated);
+        let mut distinct = received.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len() as u64, 500 - stats.lost);
+        // a reordered frame that was also duplicated comes right after its copy
+        let late = received.windows(2).filter(|pair| pair[0] > pair[1]).count() as u64;
+        assert!(late > 0 && late <= stats.reordered);
+
+        assert_eq!(run(faults, 500), (received, stats));
+    }
+
+    #[test]
+    fn releases_held_frames() {
+        let faults = Faults {
+            reorder: 1.0,
+            ..Default::default()
+        };
+        let device = Loopback::with_faults(faults);
+        let (mut tx, mut rx) = open(&device, None);
+        let mut send = |n| tx.send_to(&EthernetPacket::new(&frame(n)).unwrap(), None);
+        send(0);
+        let mut iter = rx.iter();
+        let sent = Instant::now();
+        assert_eq!(number(&iter.next().unwrap()), 0);
+        assert!(sent.elapsed() >= REORDER_HOLD / 2);
+
+        // the frame after a held one releases it, and can't be held itself
+        send(1);
+        send(2);
+        send(3);
+        let received: Vec<_> = (0..3).map(|_| number(&iter.next().unwrap())).collect();
+        assert_eq!(received, [1, 3, 2]);
+        assert_eq!(device.fault_stats().reordered, 2);
+    }
+}
@@ -1,38 +1,20 @@
-use super::ether::PrimitiveValues;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4};
-use std::os::raw::c_char;
-use std::{
-    ffi::{CStr, CString},
-    str::from_utf8_unchecked,
-};
-
-pub type CSocket = libc::c_int;
-pub type Buf = *const libc::c_void;
-pub type MutBuf = *mut libc::c_void;
-pub type BufLen = libc::size_t;
-pub type CouldFail = libc::ssize_t;
-pub type SockLen = libc::socklen_t;
-pub type SockAddr = libc::sockaddr;
-pub type SockAddrIn = libc::sockaddr_in;
-pub type SockAddrIn6 = libc::sockaddr_in6;
-pub type SockAddrStorage = libc::sockaddr_storage;
-pub type SockAddrFamily = libc::sa_family_t;
-pub type SockAddrFamily6 = libc::sa_family_t;
-pub type InAddr = libc::in_addr;
-pub type In6Addr = libc::in6_addr;
-
-pub const AF_INET: libc::c_int = libc::AF_INET;
-pub const AF_INET6: libc::c_int = libc::AF_INET6;
-pub const SOCK_RAW: libc::c_int = libc::SOCK_RAW;
-
-pub const IPPROTO_IP: libc::c_int = libc::IPPROTO_IP;
-pub const IP_HDRINCL: libc::c_int = libc::IP_HDRINCL;
-
-pub const IFF_LOOPBACK: libc::c_int = libc::IFF_LOOPBACK;
-
-pub const INVALID_SOCKET: CSocket = -1;
-
-#[derive(PartialEq, Eq, Clone, Copy, Hash)]
+use crate::cidr::IpCidr;
+use std::net::IpAddr;
+
+#[cfg(target_os = "linux")]
+pub(crate) mod netlink;
+#[cfg(all(not(target_arch = "wasm32"), not(windows)))]
+mod sys;
+
+#[cfg(target_os = "linux")]
+pub use self::netlink::{addresses, AddressFlags, InterfaceAddress};
+#[cfg(all(not(target_arch = "wasm32"), not(windows)))]
+pub use self::sys::*;
+// npcap lists the devices it captures on
+#[cfg(windows)]
+pub use super::channel::npcap::{get_interfaces, try_get_interfaces};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct MacAddr(pub u8, pub u8, pub u8, pub u8, pub u8, pub u8);
 
 impl MacAddr {
@@ -52,6 +34,26 @@ impl std::fmt::Display for MacAddr {
     }
 }
 
+impl std::str::FromStr for MacAddr {
+    type Err = std::num::ParseIntError;
+
+    /// Parse a MAC address written as six colon separated hex octets.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0u8; 6];
+        let mut parts = s.split(':');
+        for octet in octets.iter_mut() {
+            *octet = u8::from_str_radix(parts.next().unwrap_or(""), 16)?;
+        }
+        match parts.next() {
+            // reuse the integer parser's error for trailing garbage
+            Some(_) => Err(u8::from_str_radix("", 16).unwrap_err()),
+            None => Ok(MacAddr(
+                octets[0], octets[1], octets[2], octets[3], octets[4], octets[5],
+            )),
+        }
+    }
+}
+
 impl std::fmt::Debug for MacAddr {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
         std::fmt::Display::fmt(self, fmt)
@@ -66,150 +68,105 @@ pub struct NetworkInterface {
     pub index: u32,
     /// A MAC address for the interface
     pub mac: Option<MacAddr>,
-    /// An IP addresses for the interface
+    /// The IP addresses of the interface, aliases included
     pub ips: Option<Vec<IpAddr>>,
     /// Operating system specific flags for the interface
     pub flags: u32,
+    /// The largest packet the link carries, headers of the link layer left out
+    pub mtu: Option<u32>,
+    /// Whether the link is usable
+    pub oper_state: OperState,
+    /// The hardware type of the link
+    pub link_type: LinkType,
+    /// The IP addresses of the interface with the prefix lengths of their networks;
+    /// `addresses` tells their states too
+    pub addresses: Vec<IpCidr>,
 }
 
-pub fn get_interfaces() -> Vec<NetworkInterface> {
-    fn merge(old: &mut NetworkInterface, new: &NetworkInterface) {
-        old.mac = match new.mac {
-            None => old.mac,
-            _ => new.mac,
-        };
-        match (&mut old.ips, &new.ips) {
-            (&mut Some(ref mut old_ips), &Some(ref new_ips)) => {
-                old_ips.extend_from_slice(&new_ips[..])
-            }
-            (&mut ref mut old_ips @ None, &Some(ref new_ips)) => *old_ips = Some(new_ips.clone()),
-            _ => {}
-        };
-        old.flags = old.flags | new.flags;
+// the `flags` bits of interfaces that are up and of loopbacks; npcap reports its own
+// `PCAP_IF_*` bits in place of the `IFF_*` ones
+#[cfg(not(windows))]
+pub(crate) const FLAG_UP: u32 = libc::IFF_UP as u32;
+#[cfg(not(windows))]
+pub(crate) const FLAG_LOOPBACK: u32 = libc::IFF_LOOPBACK as u32;
+#[cfg(windows)]
+pub(crate) const FLAG_UP: u32 = 0x2;
+#[cfg(windows)]
+pub(crate) const FLAG_LOOPBACK: u32 = 0x1;
+
+impl NetworkInterface {
+    /// Whether the interface is administratively up.
+    pub fn is_up(&self) -> bool {
+        self.flags & FLAG_UP != 0
     }
 
-    let mut ifaces: Vec<NetworkInterface> = Vec::new();
-    unsafe {
-        let mut addrs: *mut libc::ifaddrs = std::mem::uninitialized();
-        if libc::getifaddrs(&mut addrs) != 0 {
-            return ifaces;
-        }
-        let mut addr = addrs;
-        while !addr.is_null() {
-            let c_str = (*addr).ifa_name as *const c_char;
-            let bytes = CStr::from_ptr(c_str).to_bytes();
-            let name = from_utf8_unchecked(bytes).to_owned();
-            let (mac, ip) = sockaddr_to_network_addr((*addr).ifa_addr as *const libc::sockaddr);
-            let ni = NetworkInterface {
-                name: name.clone(),
-                index: 0,
-                mac: mac,
-                ips: ip.map(|ip| [ip].to_vec()),
-                flags: (*addr).ifa_flags,
-            };
-            let mut found: bool = false;
-            for iface in &mut ifaces {
-                if name == iface.name {
-                    merge(iface, &ni);
-                    found = true;
-                }
-            }
-            if !found {
-                ifaces.push(ni);
-            }
-
-            addr = (*addr).ifa_next;
-        }
-        libc::freeifaddrs(addrs);
-
-        for iface in &mut ifaces {
-            let name = CString::new(iface.name.as_bytes());
-            iface.index = libc::if_nametoindex(name.unwrap().as_ptr());
-        }
-
-        ifaces
+    /// Whether the interface is a loopback.
+    pub fn is_loopback(&self) -> bool {
+        self.flags & FLAG_LOOPBACK != 0
     }
 }
 
-fn sockaddr_to_network_addr(sa: *const libc::sockaddr) -> (Option<MacAddr>, Option<IpAddr>) {
-    use std::mem;
-    use std::net::{IpAddr, SocketAddr};
-
-    unsafe {
-        if sa.is_null() {
-            (None, None)
-        } else if (*sa).sa_family as libc::c_int == libc::AF_PACKET {
-            let sll: *const libc::sockaddr_ll = mem::transmute(sa);
-            let mac = MacAddr(
-                (*sll).sll_addr[0],
-                (*sll).sll_addr[1],
-                (*sll).sll_addr[2],
-                (*sll).sll_addr[3],
-                (*sll).sll_addr[4],
-                (*sll).sll_addr[5],
-            );
-
-            (Some(mac), None)
-        } else {
-            let addr =
-                sockaddr_to_addr(mem::transmute(sa), mem::size_of::<libc::sockaddr_storage>());
-
-            match addr {
-                Ok(SocketAddr::V4(sa)) => (None, Some(IpAddr::V4(*sa.ip()))),
-                Ok(SocketAddr::V6(sa)) => (None, Some(IpAddr::V6(*sa.ip()))),
-                Err(_) => (None, None),
-            }
-        }
-    }
+/// The operational state of a link, as of RFC 2863.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum OperState {
+    /// The driver doesn't tell, as is common for virtual devices
+    Unknown,
+    /// A component of the link is missing
+    NotPresent,
+    /// The link is down
+    Down,
+    /// The link is down because a device it sits on is
+    LowerLayerDown,
+    /// The link is in a test mode
+    Testing,
+    /// The link is up but waits for an external event, like 802.1X authentication
+    Dormant,
+    /// The link is up and can pass packets
+    Up,
 }
 
-pub fn sockaddr_to_addr(storage: &SockAddrStorage, len: usize) -> std::io::Result<SocketAddr> {
-    use std::mem;
-
-    match storage.ss_family as libc::c_int {
-        AF_INET => {
-            assert!(len as usize >= mem::size_of::<SockAddrIn>());
-            let storage: &SockAddrIn = unsafe { mem::transmute(storage) };
-            let ip = ipv4_addr(storage.sin_addr);
-            let a = (ip >> 24) as u8;
-            let b = (ip >> 16) as u8;
-            let c = (ip >> 8) as u8;
-            let d = ip as u8;
-            let sockaddrv4 = SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), ntohs(storage.sin_port));
-            Ok(SocketAddr::V4(sockaddrv4))
-        }
-        // AF_INET6 => {
-        //     assert!(len as usize >= mem::size_of::<sockets::SockAddrIn6>());
-        //     let storage: &sockets::SockAddrIn6 = unsafe { mem::transmute(storage) };
-        //     let arr: [u16; 8] = unsafe { mem::transmute(storage.sin6_addr.s6_addr) };
-        //     let a = ntohs(arr[0]);
-        //     let b = ntohs(arr[1]);
-        //     let c = ntohs(arr[2]);
-        //     let d = ntohs(arr[3]);
-        //     let e = ntohs(arr[4]);
-        //     let f = ntohs(arr[5]);
-        //     let g = ntohs(arr[6]);
-        //     let h = ntohs(arr[7]);
-        //     let ip = Ipv6Addr::new(a, b, c, d, e, f, g, h);
-        //     Ok(SocketAddr::V6(SocketAddrV6::new(
-        //         ip,
-        //         ntohs(storage.sin6_port),
-        //         u32::from_be(storage.sin6_flowinfo),
-        //         u32::from_be(storage.sin6_scope_id),
-        //     )))
-        // }
-        _ => Err(std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "expected IPv4 or IPv6 socket",
-        )),
+impl Default for OperState {
+    fn default() -> OperState {
+        OperState::Unknown
     }
 }
 
-fn ntohs(u: u16) -> u16 {
-    u16::from_be(u)
+impl From<u8> for OperState {
+    /// Convert an `IF_OPER_*` value.
+    fn from(state: u8) -> OperState {
+        match state {
+            1 => OperState::NotPresent,
+            2 => OperState::Down,
+            3 => OperState::LowerLayerDown,
+            4 => OperState::Testing,
+            5 => OperState::Dormant,
+            6 => OperState::Up,
+            _ => OperState::Unknown,
+        }
+    }
 }
 
-#[inline(always)]
-pub fn ipv4_addr(addr: InAddr) -> u32 {
-    (addr.s_addr as u32).to_be()
+/// The `ARPHRD_*` hardware type of a link.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LinkType(pub u16);
+
+impl LinkType {
+    /// Ethernet, and what looks like it, e.g. Wi-Fi and bridges
+    pub const ETHER: LinkType = LinkType(1);
+    /// Point-to-point protocol
+    pub const PPP: LinkType = LinkType(512);
+    /// IP in IP tunnel
+    pub const TUNNEL: LinkType = LinkType(768);
+    /// IPv6 in IPv6 tunnel
+    pub const TUNNEL6: LinkType = LinkType(769);
+    /// The loopback device
+    pub const LOOPBACK: LinkType = LinkType(772);
+    /// IPv6 in IPv4 tunnel
+    pub const SIT: LinkType = LinkType(776);
+    /// 802.11 frames behind a radiotap header, as of monitor mode
+    pub const IEEE80211_RADIOTAP: LinkType = LinkType(803);
+    /// No link layer header, as of TUN devices
+    pub const NONE: LinkType = LinkType(0xfffe);
+    /// Nothing is known about the link
+    pub const VOID: LinkType = LinkType(0xffff);
 }
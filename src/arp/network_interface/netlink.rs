@@ -0,0 +1,412 @@
+//! Route netlink queries.
+//!
+//! `getifaddrs` leaves out what the kernel knows about an address beyond the address
+//! itself. [`addresses`] dumps them over `NETLINK_ROUTE` instead, with prefix lengths,
+//! labels and the IPv6 states that matter when picking a source address. [`interfaces`]
+//! dumps the links the same way, and [`routes`] the main routing table.
+
+use super::{super::channel::FileDesc, LinkType, MacAddr, NetworkInterface, OperState};
+use crate::{
+    cidr::{IpCidr, Ipv4Cidr, Ipv6Cidr},
+    routes::Route,
+};
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+};
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x01;
+const NLM_F_DUMP: u16 = 0x300;
+const NLMSG_HDRLEN: usize = 16;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_GETLINK: u16 = 18;
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+const RTM_NEWROUTE: u16 = 24;
+const RTM_GETROUTE: u16 = 26;
+
+const IFLA_ADDRESS: u16 = 1;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_OPERSTATE: u16 = 16;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFA_LABEL: u16 = 3;
+const IFA_FLAGS: u16 = 8;
+
+const RTA_DST: u16 = 1;
+const RTA_OIF: u16 = 4;
+const RTA_GATEWAY: u16 = 5;
+const RTA_PRIORITY: u16 = 6;
+const RTA_TABLE: u16 = 15;
+
+const RT_TABLE_MAIN: u32 = 254;
+const RTN_UNICAST: u8 = 1;
+
+/// A message of a netlink dump.
+pub(crate) struct Message {
+    pub kind: u16,
+    /// The payload after the `nlmsghdr`
+    pub body: Vec<u8>,
+}
+
+/// Send a dump request of `kind` with `payload` after the header and collect the replies.
+pub(crate) fn dump(kind: u16, payload: &[u8]) -> io::Result<Vec<Message>> {
+    let socket = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if socket == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = FileDesc { fd: socket };
+
+    let len = NLMSG_HDRLEN + payload.len();
+    let mut request = Vec::with_capacity(len);
+    request.extend_from_slice(&(len as u32).to_ne_bytes());
+    request.extend_from_slice(&kind.to_ne_bytes());
+    request.extend_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    // sequence number and port id, the kernel fills in the latter
+    request.extend_from_slice(&1u32.to_ne_bytes());
+    request.extend_from_slice(&0u32.to_ne_bytes());
+    request.extend_from_slice(payload);
+
+    let mut kernel: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    kernel.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    if unsafe {
+        libc::sendto(
+            socket.fd,
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+            (&kernel as *const libc::sockaddr_nl) as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut messages = Vec::new();
+    let mut buffer = vec![0u8; 32768];
+    loop {
+        let len = unsafe {
+            libc::recv(
+                socket.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if len == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut rest = &buffer[..len as usize];
+        while rest.len() >= NLMSG_HDRLEN {
+            let message_len = u32::from_ne_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            if message_len < NLMSG_HDRLEN || message_len > rest.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Truncated netlink message",
+                ));
+            }
+            let kind = u16::from_ne_bytes([rest[4], rest[5]]);
+            let body = &rest[NLMSG_HDRLEN..message_len];
+            match kind {
+                NLMSG_DONE => return Ok(messages),
+                NLMSG_ERROR => {
+                    let errno = if body.len() >= 4 {
+                        i32::from_ne_bytes([body[0], body[1], body[2], body[3]])
+                    } else {
+                        0
+                    };
+                    if errno != 0 {
+                        return Err(io::Error::from_raw_os_error(-errno));
+                    }
+                }
+                _ => messages.push(Message {
+                    kind,
+                    body: body.to_vec(),
+                }),
+            }
+            rest = &rest[align(message_len).min(rest.len())..];
+        }
+    }
+}
+
+/// The route attributes in `data` as type and payload.
+pub(crate) fn attributes(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        if len < 4 || len > data.len() {
+            return None;
+        }
+        // the top bits are the nested and byte order flags
+        let kind = u16::from_ne_bytes([data[2], data[3]]) & 0x3fff;
+        let payload = &data[4..len];
+        data = &data[align(len).min(data.len())..];
+        Some((kind, payload))
+    })
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// The `IFA_F_*` flags of an address.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct AddressFlags(pub u32);
+
+impl AddressFlags {
+    /// An IPv4 address besides the primary one of its subnet, e.g. an alias
+    pub const SECONDARY: AddressFlags = AddressFlags(0x01);
+    /// Duplicate address detection is off
+    pub const NODAD: AddressFlags = AddressFlags(0x02);
+    /// Usable while duplicate address detection runs
+    pub const OPTIMISTIC: AddressFlags = AddressFlags(0x04);
+    /// Duplicate address detection found the address in use
+    pub const DADFAILED: AddressFlags = AddressFlags(0x08);
+    /// Mobile IPv6 home address
+    pub const HOMEADDRESS: AddressFlags = AddressFlags(0x10);
+    /// Past its preferred lifetime, kept for existing connections only
+    pub const DEPRECATED: AddressFlags = AddressFlags(0x20);
+    /// Duplicate address detection hasn't finished
+    pub const TENTATIVE: AddressFlags = AddressFlags(0x40);
+    /// Configured rather than autoconfigured
+    pub const PERMANENT: AddressFlags = AddressFlags(0x80);
+
+    /// Whether all of `flags` are set.
+    pub fn contains(&self, flags: AddressFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+}
+
+/// An address of an interface.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct InterfaceAddress {
+    /// Index of the interface
+    pub index: u32,
+    /// The label, like `eth0:1` for an alias; IPv6 addresses have none
+    pub label: Option<String>,
+    /// The address and the prefix length of its network
+    pub cidr: IpCidr,
+    /// State flags
+    pub flags: AddressFlags,
+}
+
+impl InterfaceAddress {
+    /// Whether this is a secondary IPv4 address.
+    pub fn is_secondary(&self) -> bool {
+        self.flags.contains(AddressFlags::SECONDARY)
+    }
+
+    /// Whether duplicate address detection hasn't finished.
+    pub fn is_tentative(&self) -> bool {
+        self.flags.contains(AddressFlags::TENTATIVE)
+    }
+
+    /// Whether the address is past its preferred lifetime.
+    pub fn is_deprecated(&self) -> bool {
+        self.flags.contains(AddressFlags::DEPRECATED)
+    }
+
+    /// Whether new traffic may be sent from this address: it is neither tentative (unless
+    /// optimistic), deprecated nor a duplicate.
+    pub fn is_preferred_source(&self) -> bool {
+        !(self.is_tentative() && !self.flags.contains(AddressFlags::OPTIMISTIC))
+            && !self.is_deprecated()
+            && !self.flags.contains(AddressFlags::DADFAILED)
+    }
+}
+
+/// Every address of every interface, aliases and IPv6 addresses in any state included.
+pub fn addresses() -> io::Result<Vec<InterfaceAddress>> {
+    // an ifaddrmsg for any family
+    let messages = dump(RTM_GETADDR, &[0u8; 8])?;
+    Ok(messages
+        .iter()
+        .filter(|message| message.kind == RTM_NEWADDR && message.body.len() >= 8)
+        .filter_map(|message| parse_address(&message.body))
+        .collect())
+}
+
+fn parse_address(body: &[u8]) -> Option<InterfaceAddress> {
+    let family = i32::from(body[0]);
+    let prefix_len = body[1];
+    let mut flags = u32::from(body[2]);
+    let index = u32::from_ne_bytes([body[4], body[5], body[6], body[7]]);
+    let (mut address, mut local, mut label) = (None, None, None);
+    for (kind, payload) in attributes(&body[8..]) {
+        match kind {
+            IFA_ADDRESS => address = Some(payload),
+            IFA_LOCAL => local = Some(payload),
+            IFA_LABEL => {
+                let name = payload.split(|b| *b == 0).next().unwrap_or(&[]);
+                label = Some(String::from_utf8_lossy(name).into_owned());
+            }
+            // the flags that don't fit in the header byte
+            IFA_FLAGS if payload.len() >= 4 => {
+                flags = u32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]])
+            }
+            _ => {}
+        }
+    }
+    // on point-to-point links IFA_ADDRESS is the peer
+    let address = local.or(address)?;
+    let cidr = match family {
+        libc::AF_INET if address.len() == 4 => IpCidr::V4(Ipv4Cidr::new(
+            Ipv4Addr::new(address[0], address[1], address[2], address[3]),
+            prefix_len.min(32),
+        )),
+        libc::AF_INET6 if address.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(address);
+            IpCidr::V6(Ipv6Cidr::new(Ipv6Addr::from(octets), prefix_len.min(128)))
+        }
+        _ => return None,
+    };
+    Some(InterfaceAddress {
+        index,
+        label,
+        cidr,
+        flags: AddressFlags(flags),
+    })
+}
+
+/// Every interface of the host with all its addresses.
+pub(crate) fn interfaces() -> io::Result<Vec<NetworkInterface>> {
+    // an ifinfomsg for any family
+    let messages = dump(RTM_GETLINK, &[0u8; 16])?;
+    let mut interfaces: Vec<NetworkInterface> = messages
+        .iter()
+        .filter(|message| message.kind == RTM_NEWLINK && message.body.len() >= 16)
+        .filter_map(|message| parse_link(&message.body))
+        .collect();
+    for address in addresses()? {
+        if let Some(interface) = interfaces
+            .iter_mut()
+            .find(|interface| interface.index == address.index)
+        {
+            interface
+                .ips
+                .get_or_insert_with(Vec::new)
+                .push(address.cidr.address());
+            interface.addresses.push(address.cidr);
+        }
+    }
+    Ok(interfaces)
+}
+
+fn parse_link(body: &[u8]) -> Option<NetworkInterface> {
+    let link_type = u16::from_ne_bytes([body[2], body[3]]);
+    let index = u32::from_ne_bytes([body[4], body[5], body[6], body[7]]);
+    let flags = u32::from_ne_bytes([body[8], body[9], body[10], body[11]]);
+    let (mut name, mut mac, mut mtu, mut oper_state) = (None, None, None, OperState::Unknown);
+    for (kind, payload) in attributes(&body[16..]) {
+        match kind {
+            IFLA_IFNAME => {
+                let bytes = payload.split(|b| *b == 0).next().unwrap_or(&[]);
+                name = Some(String::from_utf8_lossy(bytes).into_owned());
+            }
+            // links without 6 byte hardware addresses, like tunnels, have no MAC
+            IFLA_ADDRESS if payload.len() == 6 => {
+                mac = Some(MacAddr(
+                    payload[0], payload[1], payload[2], payload[3], payload[4], payload[5],
+                ))
+            }
+            IFLA_MTU if payload.len() >= 4 => {
+                mtu = Some(u32::from_ne_bytes([
+                    payload[0], payload[1], payload[2], payload[3],
+                ]))
+            }
+            IFLA_OPERSTATE if !payload.is_empty() => oper_state = OperState::from(payload[0]),
+            _ => {}
+        }
+    }
+    Some(NetworkInterface {
+        name: name?,
+        index,
+        mac,
+        ips: None,
+        flags,
+        mtu,
+        oper_state,
+        link_type: LinkType(link_type),
+        addresses: Vec::new(),
+    })
+}
+
+/// The unicast routes of the main table, with the name of the interface `name_of` gives
+/// for their output interface index. Routes through interfaces without a name are left out.
+pub(crate) fn routes<F>(name_of: F) -> io::Result<Vec<Route>>
+where
+    F: Fn(u32) -> Option<String>,
+{
+    // an rtmsg for any family
+    let messages = dump(RTM_GETROUTE, &[0u8; 12])?;
+    Ok(messages
+        .iter()
+        .filter(|message| message.kind == RTM_NEWROUTE && message.body.len() >= 12)
+        .filter_map(|message| parse_route(&message.body, &name_of))
+        .collect())
+}
+
+fn parse_route<F>(body: &[u8], name_of: F) -> Option<Route>
+where
+    F: Fn(u32) -> Option<String>,
+{
+    let family = i32::from(body[0]);
+    let prefix_len = body[1];
+    let mut table = u32::from(body[4]);
+    if body[7] != RTN_UNICAST {
+        return None;
+    }
+    let (mut destination, mut gateway, mut index, mut metric) = (None, None, None, 0);
+    for (kind, payload) in attributes(&body[12..]) {
+        let word = || u32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        match kind {
+            RTA_DST => destination = address(family, payload),
+            RTA_GATEWAY => gateway = address(family, payload),
+            RTA_OIF if payload.len() >= 4 => index = Some(word()),
+            RTA_PRIORITY if payload.len() >= 4 => metric = word(),
+            // tables past 255 only fit here
+            RTA_TABLE if payload.len() >= 4 => table = word(),
+            _ => {}
+        }
+    }
+    if table != RT_TABLE_MAIN {
+        return None;
+    }
+    // default routes carry no destination
+    let destination = match (family, destination) {
+        (_, Some(destination)) => destination,
+        (libc::AF_INET, None) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (libc::AF_INET6, None) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        _ => return None,
+    };
+    let max_len = if destination.is_ipv4() { 32 } else { 128 };
+    Some(Route {
+        destination: IpCidr::new(destination, prefix_len.min(max_len)),
+        gateway,
+        interface: name_of(index?)?,
+        metric,
+    })
+}
+
+fn address(family: i32, payload: &[u8]) -> Option<IpAddr> {
+    match family {
+        libc::AF_INET if payload.len() == 4 => Some(IpAddr::V4(Ipv4Addr::new(
+            payload[0], payload[1], payload[2], payload[3],
+        ))),
+        libc::AF_INET6 if payload.len() == 16 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(payload);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,268 @@
+//! Interface enumeration and socket address conversions.
+
+#[cfg(not(target_os = "linux"))]
+use super::{LinkType, OperState};
+use super::{MacAddr, NetworkInterface};
+#[cfg(not(target_os = "linux"))]
+use crate::cidr::IpCidr;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+#[cfg(not(target_os = "linux"))]
+use std::{
+    ffi::{CStr, CString},
+    io,
+    net::{IpAddr, Ipv6Addr},
+    ptr,
+};
+
+pub type CSocket = libc::c_int;
+pub type Buf = *const libc::c_void;
+pub type MutBuf = *mut libc::c_void;
+pub type BufLen = libc::size_t;
+pub type CouldFail = libc::ssize_t;
+pub type SockLen = libc::socklen_t;
+pub type SockAddr = libc::sockaddr;
+pub type SockAddrIn = libc::sockaddr_in;
+pub type SockAddrIn6 = libc::sockaddr_in6;
+pub type SockAddrStorage = libc::sockaddr_storage;
+pub type SockAddrFamily = libc::sa_family_t;
+pub type SockAddrFamily6 = libc::sa_family_t;
+pub type InAddr = libc::in_addr;
+pub type In6Addr = libc::in6_addr;
+
+pub const AF_INET: libc::c_int = libc::AF_INET;
+pub const AF_INET6: libc::c_int = libc::AF_INET6;
+pub const SOCK_RAW: libc::c_int = libc::SOCK_RAW;
+
+pub const IPPROTO_IP: libc::c_int = libc::IPPROTO_IP;
+pub const IP_HDRINCL: libc::c_int = libc::IP_HDRINCL;
+
+pub const IFF_LOOPBACK: libc::c_int = libc::IFF_LOOPBACK;
+
+pub const INVALID_SOCKET: CSocket = -1;
+
+/// The interfaces of the host, empty if they can't be listed; see `try_get_interfaces`.
+pub fn get_interfaces() -> Vec<NetworkInterface> {
+    try_get_interfaces().unwrap_or_default()
+}
+
+/// The interface named `name`.
+pub fn interface_by_name(name: &str) -> crate::error::Result<NetworkInterface> {
+    try_get_interfaces()?
+        .into_iter()
+        .find(|interface| interface.name == name)
+        .ok_or_else(|| crate::error::Error::InterfaceNotFound(name.to_owned()))
+}
+
+/// The interfaces of the host with their links and addresses as of `NETLINK_ROUTE`,
+/// failing if it can't be queried.
+#[cfg(target_os = "linux")]
+pub fn try_get_interfaces() -> crate::error::Result<Vec<NetworkInterface>> {
+    Ok(super::netlink::interfaces()?)
+}
+
+/// The interfaces of the host, failing if `getifaddrs` does.
+///
+/// `getifaddrs` lists the addresses of an interface one by one, and on macOS its link
+/// with the MAC address, MTU and type. Links count as up while running.
+#[cfg(not(target_os = "linux"))]
+pub fn try_get_interfaces() -> crate::error::Result<Vec<NetworkInterface>> {
+    let mut interfaces: Vec<NetworkInterface> = Vec::new();
+    let mut addrs: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut addr = addrs;
+    while !addr.is_null() {
+        let ifa = unsafe { &*addr };
+        addr = ifa.ifa_next;
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }.to_string_lossy();
+        // IPv4 aliases are listed under their label, like `en0:1`
+        let name = name.split(':').next().unwrap_or(&name).to_owned();
+        let position = match interfaces.iter().position(|i| i.name == name) {
+            Some(position) => position,
+            None => {
+                // names come from C strings, so they hold no NUL
+                let index = CString::new(name.as_bytes())
+                    .map_or(0, |name| unsafe { libc::if_nametoindex(name.as_ptr()) });
+                interfaces.push(NetworkInterface {
+                    name,
+                    index,
+                    mac: None,
+                    ips: None,
+                    flags: 0,
+                    mtu: None,
+                    oper_state: OperState::Unknown,
+                    link_type: LinkType::VOID,
+                    addresses: Vec::new(),
+                });
+                interfaces.len() - 1
+            }
+        };
+        let interface = &mut interfaces[position];
+        interface.flags |= ifa.ifa_flags as u32;
+        match unsafe { sockaddr_to_ip(ifa.ifa_addr) } {
+            Some(ip) => {
+                let prefix_len = unsafe { prefix_len(ifa.ifa_netmask, ip) };
+                interface.ips.get_or_insert_with(Vec::new).push(ip);
+                interface.addresses.push(IpCidr::new(ip, prefix_len));
+            }
+            #[cfg(target_os = "macos")]
+            None => unsafe { link(interface, ifa) },
+            #[cfg(not(target_os = "macos"))]
+            None => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+
+    let running = (libc::IFF_UP | libc::IFF_RUNNING) as u32;
+    for interface in &mut interfaces {
+        interface.oper_state = if interface.flags & running == running {
+            OperState::Up
+        } else {
+            OperState::Down
+        };
+    }
+    Ok(interfaces)
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn sockaddr_to_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    match i32::from((*addr).sa_family) {
+        libc::AF_INET => {
+            let addr = &*(addr as *const libc::sockaddr_in);
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                addr.sin_addr.s_addr,
+            ))))
+        }
+        libc::AF_INET6 => {
+            let addr = &*(addr as *const libc::sockaddr_in6);
+            Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+/// The prefix length of the network `mask` of `ip` describes, the whole address without
+/// one.
+#[cfg(not(target_os = "linux"))]
+unsafe fn prefix_len(mask: *const libc::sockaddr, ip: IpAddr) -> u8 {
+    if mask.is_null() {
+        return if ip.is_ipv4() { 32 } else { 128 };
+    }
+    // the family of masks isn't always set, the address tells it
+    let ones = match ip {
+        IpAddr::V4(_) => {
+            let mask = &*(mask as *const libc::sockaddr_in);
+            u32::from_be(mask.sin_addr.s_addr).count_ones()
+        }
+        IpAddr::V6(_) => {
+            let mask = &*(mask as *const libc::sockaddr_in6);
+            u128::from_be_bytes(mask.sin6_addr.s6_addr).count_ones()
+        }
+    };
+    ones as u8
+}
+
+/// Fill in the MAC address, MTU and type of `interface` from its `AF_LINK` entry.
+#[cfg(target_os = "macos")]
+unsafe fn link(interface: &mut NetworkInterface, ifa: &libc::ifaddrs) {
+    const IFT_ETHER: u8 = 0x06;
+    const IFT_PPP: u8 = 0x17;
+    const IFT_LOOP: u8 = 0x18;
+
+    if ifa.ifa_addr.is_null() || i32::from((*ifa.ifa_addr).sa_family) != libc::AF_LINK {
+        return;
+    }
+    let sdl = &*(ifa.ifa_addr as *const libc::sockaddr_dl);
+    if sdl.sdl_alen == 6 {
+        // the address follows the name, possibly past the end of `sdl_data`
+        let data = (sdl.sdl_data.as_ptr() as *const u8).add(usize::from(sdl.sdl_nlen));
+        let mac = std::slice::from_raw_parts(data, 6);
+        interface.mac = Some(MacAddr(mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]));
+    }
+    if !ifa.ifa_data.is_null() {
+        let data = &*(ifa.ifa_data as *const libc::if_data);
+        interface.mtu = Some(data.ifi_mtu);
+        interface.link_type = match data.ifi_type {
+            IFT_ETHER => LinkType::ETHER,
+            IFT_PPP => LinkType::PPP,
+            IFT_LOOP => LinkType::LOOPBACK,
+            _ => LinkType::VOID,
+        };
+    }
+}
+
+pub fn sockaddr_to_addr(storage: &SockAddrStorage, len: usize) -> std::io::Result<SocketAddr> {
+    use std::mem;
+
+    match storage.ss_family as libc::c_int {
+        AF_INET => {
+            assert!(len as usize >= mem::size_of::<SockAddrIn>());
+            let storage: &SockAddrIn = unsafe { mem::transmute(storage) };
+            let ip = ipv4_addr(storage.sin_addr);
+            let a = (ip >> 24) as u8;
+            let b = (ip >> 16) as u8;
+            let c = (ip >> 8) as u8;
+            let d = ip as u8;
+            let sockaddrv4 = SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), ntohs(storage.sin_port));
+            Ok(SocketAddr::V4(sockaddrv4))
+        }
+        // AF_INET6 => {
+        //     assert!(len as usize >= mem::size_of::<sockets::SockAddrIn6>());
+        //     let storage: &sockets::SockAddrIn6 = unsafe { mem::transmute(storage) };
+        //     let arr: [u16; 8] = unsafe { mem::transmute(storage.sin6_addr.s6_addr) };
+        //     let a = ntohs(arr[0]);
+        //     let b = ntohs(arr[1]);
+        //     let c = ntohs(arr[2]);
+        //     let d = ntohs(arr[3]);
+        //     let e = ntohs(arr[4]);
+        //     let f = ntohs(arr[5]);
+        //     let g = ntohs(arr[6]);
+        //     let h = ntohs(arr[7]);
+        //     let ip = Ipv6Addr::new(a, b, c, d, e, f, g, h);
+        //     Ok(SocketAddr::V6(SocketAddrV6::new(
+        //         ip,
+        //         ntohs(storage.sin6_port),
+        //         u32::from_be(storage.sin6_flowinfo),
+        //         u32::from_be(storage.sin6_scope_id),
+        //     )))
+        // }
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "expected IPv4 or IPv6 socket",
+        )),
+    }
+}
+
+fn ntohs(u: u16) -> u16 {
+    u16::from_be(u)
+}
+
+#[inline(always)]
+pub fn ipv4_addr(addr: InAddr) -> u32 {
+    (addr.s_addr as u32).to_be()
+}
+
+/// Fill `storage` with the `sockaddr_ll` packet sockets bind to `ni` with.
+#[cfg(target_os = "linux")]
+pub fn network_addr_to_sockaddr(
+    ni: &NetworkInterface,
+    storage: *mut libc::sockaddr_storage,
+    proto: libc::c_int,
+) -> usize {
+    unsafe {
+        let sll: *mut libc::sockaddr_ll = mem::transmute(storage);
+        (*sll).sll_family = libc::AF_PACKET as libc::sa_family_t;
+        if let Some(MacAddr(a, b, c, d, e, f)) = ni.mac {
+            (*sll).sll_addr = [a, b, c, d, e, f, 0, 0];
+        }
+        (*sll).sll_protocol = (proto as u16).to_be();
+        (*sll).sll_halen = 6;
+        (*sll).sll_ifindex = ni.index as i32;
+        mem::size_of::<libc::sockaddr_ll>()
+    }
+}
@@ -1,12 +1,18 @@
 use super::{
-    arp_new::{ArpHardwareTypes, ArpOperation, MutableArpPacket},
-    channel::channel,
-    ether::{EtherTypes, MutableEthernetPacket, MutablePacket},
+    arp_new::{ArpHardwareTypes, ArpOperation, ArpOperations, MutableArpPacket},
+    channel::EthernetDataLinkSender,
+    ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, MutablePacket},
     network_interface::{MacAddr, NetworkInterface},
 };
-use std::net::Ipv4Addr;
-use std::thread;
-use std::time::Duration;
+use crate::{
+    error::Result,
+    neighbor::{self, Neighbor, NeighborCache},
+};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Log target of ARP frames sent from here.
+const LOG_TARGET: &str = "myox::arp";
 
 // use pnet::datalink::Channel;
 // use pnet::datalink::{self, NetworkInterface};
@@ -17,21 +23,17 @@ use std::time::Duration;
 // use pnet::packet::MutablePacket;
 // use pnet::util::MacAddr;
 
+/// Send an ARP packet from `source_mac`/`source_ip` to `target_ip` through `tx`.
+///
+/// Requests are broadcast; other operations go to `target_mac` directly.
 pub fn send_arp_packet(
-    interface: NetworkInterface,
+    tx: &mut dyn EthernetDataLinkSender,
     source_ip: Ipv4Addr,
     source_mac: MacAddr,
     target_ip: Ipv4Addr,
     target_mac: MacAddr,
-    // arp_operation: ArpOperation,
-    // mut p: &mut [u8],
-) {
-    let (mut tx, _) = match channel(&interface, Default::default()) {
-        Ok(crate::mine::channel::Channel::Ethernet(tx, rx)) => (tx, rx),
-        Ok(_) => panic!("Unknown channel type"),
-        Err(e) => panic!("Error happened {}", e),
-    };
-
+    arp_operation: ArpOperation,
+) -> Result<()> {
     /// ethernet_packet = Ethernet {
     ///     destination: [0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
     ///     source: [0x28, 0xef, 0xf9, 0x5f, 0x8e, 0x2b],
@@ -51,10 +53,48 @@ pub fn send_arp_packet(
     ///     target_proto_addr: [0xc0, 0xa8, 0x00, 0x65], // Ipv4(192.168.0.101)
     ///     payload: [],
     /// }
+    let destination = if arp_operation == ArpOperations::Request {
+        MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff)
+    } else {
+        target_mac
+    };
+    let ethernet_buffer = build_arp_packet(
+        destination,
+        source_mac,
+        source_ip,
+        target_mac,
+        target_ip,
+        arp_operation,
+    );
+    let ethernet_packet = EthernetPacket::new(&ethernet_buffer[..]).unwrap();
+
+    tx.send_to(&ethernet_packet, None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+    log::debug!(
+        target: LOG_TARGET,
+        "sent ARP operation {} for {}",
+        arp_operation.0,
+        target_ip
+    );
+    Ok(())
+}
+
+/// Build an Ethernet frame carrying an Ethernet/IPv4 ARP packet.
+///
+/// `destination` is the Ethernet destination, usually the broadcast address for requests
+/// and the target's address for replies.
+pub fn build_arp_packet(
+    destination: MacAddr,
+    source_mac: MacAddr,
+    source_ip: Ipv4Addr,
+    target_mac: MacAddr,
+    target_ip: Ipv4Addr,
+    operation: ArpOperation,
+) -> [u8; 42] {
     let mut ethernet_buffer = [0u8; 42];
     let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
 
-    ethernet_packet.set_destination(target_mac);
+    ethernet_packet.set_destination(destination);
     ethernet_packet.set_source(source_mac);
     ethernet_packet.set_ethertype(EtherTypes::Arp);
 
@@ -65,28 +105,55 @@ pub fn send_arp_packet(
     arp_packet.set_protocol_type(EtherTypes::Ipv4);
     arp_packet.set_hw_addr_len(6);
     arp_packet.set_proto_addr_len(4);
-    arp_packet.set_operation(ArpOperation(1));
+    arp_packet.set_operation(operation);
     arp_packet.set_sender_hw_addr(source_mac);
     arp_packet.set_sender_proto_addr(source_ip);
     arp_packet.set_target_hw_addr(target_mac);
     arp_packet.set_target_proto_addr(target_ip);
 
     ethernet_packet.set_payload(arp_packet.packet_mut());
+    ethernet_buffer
+}
 
-    // println!("c: {:?}", ethernet_packet.packet_mut());
-
-    // NIC
-    // i.send(ethernet_packet.packet_mut());
-    // let a = tx.send_to(
-    //     &MutableEthernetPacket::new(&mut p).unwrap().to_immutable(),
-    //     Some(interface),
-    // );
-
-    let a = tx.send_to(&ethernet_packet.to_immutable().into(), Some(interface));
-
-    if let Some(v) = a {
-        if v.is_ok() {
-            println!("send");
+/// Send an ARP request for every IPv4 binding of `cache` due for a refresh, returning how
+/// many were sent. The replies have to be fed back into the cache.
+pub fn refresh_arp_cache(
+    tx: &mut dyn EthernetDataLinkSender,
+    cache: &NeighborCache,
+    source_ip: Ipv4Addr,
+    source_mac: MacAddr,
+) -> Result<usize> {
+    let mut sent = 0;
+    for ip in cache.refresh_due() {
+        if let IpAddr::V4(ip) = ip {
+            send_arp_packet(
+                tx,
+                source_ip,
+                source_mac,
+                ip,
+                MacAddr::new(0, 0, 0, 0, 0, 0),
+                ArpOperations::Request,
+            )?;
+            sent += 1;
         }
     }
+    Ok(sent)
+}
+
+/// The MAC address of `target_ip` on the network of `interface`, asked for with ARP
+/// requests from the first IPv4 address of the interface.
+///
+/// Gives up with `TimedOut` after three requests a second apart; see [`resolve_with`] to
+/// change that.
+pub fn resolve(interface: &NetworkInterface, target_ip: Ipv4Addr) -> Result<MacAddr> {
+    resolve_with(interface, target_ip, neighbor::Config::default())
+}
+
+/// [`resolve`] with the timeout and number of requests of `config`.
+pub fn resolve_with(
+    interface: &NetworkInterface,
+    target_ip: Ipv4Addr,
+    config: neighbor::Config,
+) -> Result<MacAddr> {
+    Ok(neighbor::Resolver::open(interface, config)?.resolve(IpAddr::V4(target_ip))?)
 }
@@ -0,0 +1,126 @@
+//! Answering ARP requests for addresses this host doesn't own.
+//!
+//! An [`ArpResponder`] makes neighbors believe the addresses it is given are reachable at
+//! the MAC addresses they are bound to, e.g. for a user space stack behind a tap device.
+
+use super::{
+    arp_new::{ArpOperations, ArpPacket},
+    channel::{channel, Channel, Config, EthernetDataLinkReceiver, EthernetDataLinkSender},
+    ether::{EtherTypes, EthernetPacket, Packet},
+    network_interface::{MacAddr, NetworkInterface},
+    other::build_arp_packet,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Log target of answered requests.
+const LOG_TARGET: &str = "myox::arp";
+
+/// The reply to `frame` if it is an ARP request for an address of `bindings`.
+pub fn reply_to(bindings: &HashMap<Ipv4Addr, MacAddr>, frame: &EthernetPacket) -> Option<[u8; 42]> {
+    if frame.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+    let request = ArpPacket::new(frame.payload())?;
+    if request.get_operation() != ArpOperations::Request {
+        return None;
+    }
+    let target = request.get_target_proto_addr();
+    let mac = *bindings.get(&target)?;
+    let requester = request.get_sender_hw_addr();
+    Some(build_arp_packet(
+        requester,
+        mac,
+        target,
+        requester,
+        request.get_sender_proto_addr(),
+        ArpOperations::Reply,
+    ))
+}
+
+/// Answers ARP requests for a set of IP to MAC bindings.
+pub struct ArpResponder {
+    tx: Box<dyn EthernetDataLinkSender>,
+    rx: Box<dyn EthernetDataLinkReceiver>,
+    bindings: HashMap<Ipv4Addr, MacAddr>,
+}
+
+impl ArpResponder {
+    /// Open a channel on `interface` to answer requests on.
+    pub fn open(interface: &NetworkInterface) -> io::Result<ArpResponder> {
+        let config = Config {
+            // wake up regularly to notice the stop flag
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        match channel(interface, config)? {
+            Channel::Ethernet(tx, rx) => Ok(ArpResponder::new(tx, rx)),
+            _ => Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+        }
+    }
+
+    /// Answer requests over an already open channel.
+    pub fn new(
+        tx: Box<dyn EthernetDataLinkSender>,
+        rx: Box<dyn EthernetDataLinkReceiver>,
+    ) -> ArpResponder {
+        ArpResponder {
+            tx,
+            rx,
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Answer requests for `ip` with `mac`.
+    pub fn bind(mut self, ip: Ipv4Addr, mac: MacAddr) -> ArpResponder {
+        self.insert(ip, mac);
+        self
+    }
+
+    /// Answer requests for `ip` with `mac` from now on, replacing any previous binding.
+    pub fn insert(&mut self, ip: Ipv4Addr, mac: MacAddr) {
+        self.bindings.insert(ip, mac);
+    }
+
+    /// Stop answering requests for `ip`.
+    pub fn remove(&mut self, ip: Ipv4Addr) {
+        self.bindings.remove(&ip);
+    }
+
+    /// The addresses answered for and the MAC addresses given out.
+    pub fn bindings(&self) -> &HashMap<Ipv4Addr, MacAddr> {
+        &self.bindings
+    }
+
+    /// Answer requests until `stop` is set, returning the number of replies sent.
+    pub fn run(&mut self, stop: &AtomicBool) -> io::Result<u64> {
+        let mut answered = 0;
+        let mut iter = self.rx.iter();
+        while !stop.load(Ordering::SeqCst) {
+            let frame = match iter.next() {
+                Ok(frame) => frame,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(reply) = reply_to(&self.bindings, &frame) {
+                self.tx
+                    .send_to(&EthernetPacket::new(&reply).unwrap(), None)
+                    .unwrap_or_else(|| {
+                        Err(io::Error::new(io::ErrorKind::Other, "Frame not sent"))
+                    })?;
+                answered += 1;
+                log::debug!(
+                    target: LOG_TARGET,
+                    "answered ARP request from {}",
+                    frame.get_source()
+                );
+            }
+        }
+        Ok(answered)
+    }
+}
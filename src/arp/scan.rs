@@ -0,0 +1,184 @@
+//! Sweeping a subnet with ARP requests.
+//!
+//! [`arp_scan`] sends a request to every host address of a network from one thread while
+//! collecting the replies on another, so that slow answers don't hold back the sweep.
+
+use super::{
+    arp_new::{ArpOperations, ArpPacket},
+    channel::{channel, Channel, Config as ChannelConfig, EthernetDataLinkSender},
+    ether::{EtherTypes, EthernetPacket, Packet},
+    network_interface::{MacAddr, NetworkInterface},
+    other::build_arp_packet,
+};
+use crate::cidr::{Ipv4Cidr, Ipv4Hosts};
+use std::{
+    collections::BTreeMap,
+    io,
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Scan parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// ARP requests per second. Defaults to 500
+    pub rate: f64,
+
+    /// Time to keep collecting replies after the last request. Defaults to 1 s
+    pub wait: Duration,
+
+    /// Largest number of addresses swept. Defaults to 65536
+    pub max_addresses: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rate: 500.0,
+            wait: Duration::from_secs(1),
+            max_addresses: 65536,
+        }
+    }
+}
+
+/// The hosts of `cidr` answering ARP requests on `interface`, ordered by address.
+pub fn arp_scan(
+    interface: &NetworkInterface,
+    cidr: Ipv4Cidr,
+) -> io::Result<Vec<(Ipv4Addr, MacAddr)>> {
+    arp_scan_with(interface, cidr, &Config::default())
+}
+
+/// [`arp_scan`] with the rate and limits of `config`.
+///
+/// Requests are sent from the address of `interface` in `cidr`, or its first IPv4 address
+/// if it has none there.
+pub fn arp_scan_with(
+    interface: &NetworkInterface,
+    cidr: Ipv4Cidr,
+    config: &Config,
+) -> io::Result<Vec<(Ipv4Addr, MacAddr)>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    let mac = interface
+        .mac
+        .ok_or_else(|| invalid(format!("interface {} has no MAC address", interface.name)))?;
+    let ips = interface.ips.iter().flatten().filter_map(|ip| match ip {
+        IpAddr::V4(ip) => Some(*ip),
+        IpAddr::V6(_) => None,
+    });
+    let ip = ips
+        .clone()
+        .find(|ip| cidr.contains(*ip))
+        .or_else(|| ips.clone().next())
+        .ok_or_else(|| invalid(format!("interface {} has no IPv4 address", interface.name)))?;
+    let hosts = cidr.hosts();
+    if hosts.remaining() > u64::from(config.max_addresses) {
+        return Err(invalid(format!(
+            "{} has more than {} addresses",
+            cidr, config.max_addresses
+        )));
+    }
+
+    let channel_config = ChannelConfig {
+        // wake up regularly to notice the end of the sweep
+        read_timeout: Some(Duration::from_millis(10)),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match channel(interface, channel_config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let done = Arc::new(AtomicBool::new(false));
+    let sender = {
+        let (stop, done, rate) = (stop.clone(), done.clone(), config.rate);
+        thread::spawn(move || {
+            let result = sweep(&mut *tx, hosts, mac, ip, rate, &stop);
+            done.store(true, Ordering::SeqCst);
+            result
+        })
+    };
+
+    let mut found = BTreeMap::new();
+    let mut done_at = None;
+    let mut iter = rx.iter();
+    loop {
+        if done_at.is_none() && done.load(Ordering::SeqCst) {
+            done_at = Some(Instant::now());
+        }
+        if done_at.map_or(false, |done_at| done_at.elapsed() >= config.wait) {
+            break;
+        }
+        match iter.next() {
+            Ok(frame) => {
+                if let Some((address, mac)) = reply(&frame) {
+                    if cidr.contains(address) {
+                        found.insert(address, mac);
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => {
+                stop.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+    }
+    sender
+        .join()
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, "ARP sweep panicked"))??;
+    Ok(found.into_iter().collect())
+}
+
+/// Send a request to every address of `hosts`, `rate` a second.
+fn sweep(
+    tx: &mut dyn EthernetDataLinkSender,
+    hosts: Ipv4Hosts,
+    mac: MacAddr,
+    ip: Ipv4Addr,
+    rate: f64,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let rate = rate.max(0.001);
+    let start = Instant::now();
+    for (sent, target) in hosts.enumerate() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        // pace against the start time so that sleep overshoot doesn't accumulate
+        let due = start + Duration::from_secs_f64(sent as f64 / rate);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        let request = build_arp_packet(
+            MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+            mac,
+            ip,
+            MacAddr(0, 0, 0, 0, 0, 0),
+            target,
+            ArpOperations::Request,
+        );
+        tx.send_to(&EthernetPacket::new(&request).unwrap(), None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+    }
+    Ok(())
+}
+
+/// The sender binding of an ARP reply.
+fn reply(frame: &EthernetPacket) -> Option<(Ipv4Addr, MacAddr)> {
+    if frame.get_ethertype() != EtherTypes::Arp {
+        return None;
+    }
+    let arp = ArpPacket::new(frame.payload())?;
+    if arp.get_operation() != ArpOperations::Reply {
+        return None;
+    }
+    Some((arp.get_sender_proto_addr(), arp.get_sender_hw_addr()))
+}
@@ -0,0 +1,181 @@
+//! The `tun0` tap bootstrap loop.
+
+use super::{
+    arp_new::{ArpOperations, ArpPacket},
+    channel,
+    ether::{EtherTypes, EthernetPacket, Packet},
+    network_interface::{interface_by_name, MacAddr},
+    other, responder,
+};
+use crate::{
+    cidr::IpCidr, error::Result, ipv4::Ipv4Packet, ipv6::Ipv6Packet, neighbor::NeighborCache,
+    routes::RoutingTable, sniff::Sniffer,
+};
+use std::{
+    io, iter,
+    net::{IpAddr, Ipv4Addr},
+};
+
+/// Log target of the tap bootstrap loop.
+const LOG_TARGET: &str = "myox::bootstrap";
+
+/// Name of the tap device.
+const TAP: &str = "tun0";
+
+/// The addresses the bootstrap loop uses on the tap, and the routes it sends by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Address ARP requests are sent from, and answered for. Defaults to 192.168.0.1
+    pub ip: Ipv4Addr,
+
+    /// MAC address `ip` is reachable at. Defaults to 02:00:00:00:00:01
+    pub mac: MacAddr,
+
+    /// Routes to the hosts behind the tap. Hosts whose route leaves through another
+    /// interface, or that have none, aren't asked for. Defaults to 192.168.0.0/24 on the
+    /// link of the tap
+    pub routes: RoutingTable,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let network = IpCidr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 0)), 24);
+        Config {
+            ip: Ipv4Addr::new(192, 168, 0, 1),
+            mac: MacAddr(0x02, 0x00, 0x00, 0x00, 0x00, 0x01),
+            routes: RoutingTable::builder().link(network, TAP).build(),
+        }
+    }
+}
+
+/// Watch the `tun0` tap device, logging the ARP and IPv6 frames seen on it, caching the
+/// bindings ARP frames announce and sending an ARP request for the next hop towards the
+/// source of every IPv4 frame not in the cache. ARP requests for 192.168.0.1 are answered,
+/// so that the host can reach it through the tap.
+///
+/// Fails if the tap can't be created or captured on, and when capturing does.
+///
+/// Kept for compatibility; use [`Sniffer`] to capture elsewhere or handle frames differently.
+pub fn bootstrap() -> Result<()> {
+    bootstrap_with(&Config::default())
+}
+
+/// [`bootstrap`] with the addresses and routes of `config`.
+pub fn bootstrap_with(config: &Config) -> Result<()> {
+    // the tap only exists while its descriptor is open
+    let _nic = tun_tap::Iface::without_packet_info(TAP, tun_tap::Mode::Tap)?;
+
+    let interface = interface_by_name(TAP)?;
+    log::info!(target: LOG_TARGET, "using interface {:?}", interface);
+    let mut tx = match channel::channel(&interface, Default::default())? {
+        channel::Channel::Ethernet(tx, _) => tx,
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type").into()),
+    };
+    let cache = NeighborCache::default();
+    let bindings = iter::once((config.ip, config.mac)).collect();
+    let (source_ip, source_mac) = (config.ip, config.mac);
+    let routes = config.routes.clone();
+    Sniffer::builder()
+        .interface(&interface.name)
+        .buffer_size(channel::GSO_READ_BUFFER_SIZE)
+        .handler(move |packet| {
+            let ethertype = packet.get_ethertype();
+            log::trace!(
+                target: LOG_TARGET,
+                "frame of {} bytes, ethertype {}",
+                packet.packet().len(),
+                ethertype
+            );
+            if ethertype == EtherTypes::Arp {
+                log_arp(packet.packet());
+                if let Some(arp) = ArpPacket::new(packet.payload()) {
+                    cache.learn_arp(&arp);
+                }
+                if let Some(reply) = responder::reply_to(&bindings, packet) {
+                    let sent = tx
+                        .send_to(&EthernetPacket::new(&reply).unwrap(), None)
+                        .unwrap_or_else(|| {
+                            Err(io::Error::new(io::ErrorKind::Other, "Frame not sent"))
+                        });
+                    if let Err(e) = sent {
+                        log::warn!(target: LOG_TARGET, "sending ARP reply failed: {}", e);
+                    }
+                }
+            } else if ethertype == EtherTypes::Ipv4 {
+                let ip = match Ipv4Packet::new(packet.payload()) {
+                    Some(ip) => ip,
+                    None => {
+                        log::warn!(target: LOG_TARGET, "truncated IPv4 frame");
+                        return;
+                    }
+                };
+                log::debug!(
+                    target: LOG_TARGET,
+                    "IPv4 {} -> {}, protocol {}",
+                    ip.get_source(),
+                    ip.get_destination(),
+                    ip.get_next_level_protocol()
+                );
+                // replies to the source go to the next hop of the route to it
+                let next_hop = match routes.next_hop(IpAddr::V4(ip.get_source())) {
+                    Some((IpAddr::V4(next_hop), name)) if name == TAP => Some(next_hop),
+                    _ => {
+                        log::debug!(target: LOG_TARGET, "no route to {}", ip.get_source());
+                        None
+                    }
+                };
+                let sent = match next_hop {
+                    Some(next_hop) if cache.get(IpAddr::V4(next_hop)).is_none() => {
+                        cache.requested(IpAddr::V4(next_hop));
+                        other::send_arp_packet(
+                            &mut *tx,
+                            source_ip,
+                            source_mac,
+                            next_hop,
+                            MacAddr::new(0, 0, 0, 0, 0, 0),
+                            ArpOperations::Request,
+                        )
+                    }
+                    _ => Ok(()),
+                };
+                let sent = sent.and_then(|_| {
+                    other::refresh_arp_cache(&mut *tx, &cache, source_ip, source_mac)
+                });
+                if let Err(e) = sent {
+                    log::warn!(target: LOG_TARGET, "sending ARP request failed: {}", e);
+                }
+            } else if ethertype == EtherTypes::Ipv6 {
+                match Ipv6Packet::new(packet.payload()) {
+                    Some(ip) => log::debug!(
+                        target: LOG_TARGET,
+                        "IPv6 {} -> {}, next header {}",
+                        ip.get_source(),
+                        ip.get_destination(),
+                        ip.get_next_header()
+                    ),
+                    None => log::warn!(target: LOG_TARGET, "truncated IPv6 frame"),
+                }
+            }
+        })
+        .build()?
+        .run()?;
+    Ok(())
+}
+
+fn log_arp(frame: &[u8]) {
+    match etherparse::SlicedPacket::from_ethernet(frame) {
+        Err(value) => log::warn!(target: LOG_TARGET, "malformed ARP frame: {:?}", value),
+        Ok(value) => {
+            if let Some(etherparse::LinkSlice::Ethernet2(v)) = value.link {
+                log::debug!(target: LOG_TARGET, "ARP frame {:?}", v.to_header());
+            }
+            log::trace!(
+                target: LOG_TARGET,
+                "vlan: {:?}, ip: {:?}, transport: {:?}",
+                value.vlan,
+                value.ip,
+                value.transport
+            );
+        }
+    }
+}
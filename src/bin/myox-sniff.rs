@@ -0,0 +1,35 @@
+use myox_tcp::{
+    render,
+    sniff::{self, Options},
+};
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+fn main() {
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("myox-sniff: {}\n{}", e, sniff::USAGE);
+            process::exit(2);
+        }
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))
+        .expect("failed to install Ctrl-C handler");
+
+    match sniff::run(&options, &stop) {
+        Ok(count) => eprintln!("{} packets captured", count),
+        Err(e) => {
+            let message = format!("myox-sniff: {}", e);
+            eprintln!("{}", render::error(options.color, &message));
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use myox_tcp::cli;
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+fn main() {
+    let (common, command) = match cli::parse(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("myox: {}\n{}", e, cli::help());
+            process::exit(2);
+        }
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || handler_stop.store(true, Ordering::SeqCst))
+        .expect("failed to install Ctrl-C handler");
+
+    if let Err(e) = cli::run(&common, &command, &stop) {
+        eprintln!("myox: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,241 @@
+//! C bindings for the parsers, built with the `capi` feature.
+//!
+//! Each `myox_parse_*` function decodes the header at the start of a byte buffer into a
+//! flat struct and returns 0, or one of the negative `MYOX_ERR_*` codes. Payload offsets
+//! are relative to the start of the buffer passed in, so that the payload can be handed to
+//! the parser of the next layer.
+//!
+//! The declarations are in `include/myox_tcp.h`, which the build script writes from this
+//! module. The crate is only built as an rlib; the shared library comes from
+//! `cargo rustc --release --features capi --crate-type cdylib`.
+
+use crate::{
+    arp::{
+        arp::Error,
+        arp_new::ArpPacket,
+        ether::{EthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    ipv4,
+};
+use std::{os::raw::c_int, slice};
+
+/// A null pointer was passed.
+pub const MYOX_ERR_NULL: c_int = -1;
+/// The buffer ends before the header does.
+pub const MYOX_ERR_TRUNCATED: c_int = -2;
+/// A header field has an impossible value.
+pub const MYOX_ERR_MALFORMED: c_int = -3;
+/// The buffer does not hold this kind of header.
+pub const MYOX_ERR_UNRECOGNIZED: c_int = -4;
+
+/// An Ethernet II header.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MyoxEthernet {
+    pub destination: [u8; 6],
+    pub source: [u8; 6],
+    pub ethertype: u16,
+    pub payload_offset: usize,
+}
+
+/// An Ethernet/IPv4 ARP packet.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MyoxArp {
+    pub hardware_type: u16,
+    pub protocol_type: u16,
+    pub operation: u16,
+    pub sender_mac: [u8; 6],
+    pub sender_ip: [u8; 4],
+    pub target_mac: [u8; 6],
+    pub target_ip: [u8; 4],
+}
+
+/// An IPv4 header. Options are left in the buffer, between byte 20 and `header_len`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MyoxIpv4 {
+    pub header_len: u8,
+    pub dscp: u8,
+    pub ecn: u8,
+    pub total_len: u16,
+    pub identification: u16,
+    /// Reserved, don't fragment and more fragments bits, in this order
+    pub flags: u8,
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub checksum: u16,
+    pub source: [u8; 4],
+    pub destination: [u8; 4],
+    pub payload_offset: usize,
+}
+
+/// A TCP header. Options are left in the buffer, between byte 20 and `header_len`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MyoxTcp {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub sequence: u32,
+    pub acknowledgment: u32,
+    pub header_len: u8,
+    /// CWR, ECE, URG, ACK, PSH, RST, SYN and FIN, from the most significant bit
+    pub flags: u8,
+    pub window: u16,
+    pub checksum: u16,
+    pub urgent_pointer: u16,
+    pub payload_offset: usize,
+}
+
+fn error_code(error: Error) -> c_int {
+    match error {
+        Error::Truncated { .. } => MYOX_ERR_TRUNCATED,
+        Error::Unrecognized => MYOX_ERR_UNRECOGNIZED,
+        _ => MYOX_ERR_MALFORMED,
+    }
+}
+
+fn mac_bytes(mac: MacAddr) -> [u8; 6] {
+    [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]
+}
+
+/// Run `parse` on the `len` bytes at `data`, storing its result in `out`.
+unsafe fn parse_into<T>(
+    data: *const u8,
+    len: usize,
+    out: *mut T,
+    parse: impl FnOnce(&[u8]) -> Result<T, c_int>,
+) -> c_int {
+    if data.is_null() || out.is_null() {
+        return MYOX_ERR_NULL;
+    }
+    match parse(slice::from_raw_parts(data, len)) {
+        Ok(value) => {
+            out.write(value);
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+/// Parse the Ethernet header at the start of the `len` bytes at `data`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable `MyoxEthernet`.
+#[no_mangle]
+pub unsafe extern "C" fn myox_parse_ethernet(
+    data: *const u8,
+    len: usize,
+    out: *mut MyoxEthernet,
+) -> c_int {
+    parse_into(data, len, out, |data| {
+        let packet = EthernetPacket::new(data).ok_or(MYOX_ERR_TRUNCATED)?;
+        Ok(MyoxEthernet {
+            destination: mac_bytes(packet.get_destination()),
+            source: mac_bytes(packet.get_source()),
+            ethertype: packet.get_ethertype().value(),
+            payload_offset: data.len() - packet.payload().len(),
+        })
+    })
+}
+
+/// Parse the ARP packet at the start of the `len` bytes at `data`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable `MyoxArp`.
+#[no_mangle]
+pub unsafe extern "C" fn myox_parse_arp(data: *const u8, len: usize, out: *mut MyoxArp) -> c_int {
+    parse_into(data, len, out, |data| {
+        let arp = ArpPacket::new(data).ok_or(MYOX_ERR_TRUNCATED)?;
+        if arp.get_hw_addr_len() != 6 || arp.get_proto_addr_len() != 4 {
+            return Err(MYOX_ERR_UNRECOGNIZED);
+        }
+        Ok(MyoxArp {
+            hardware_type: arp.get_hardware_type().0,
+            protocol_type: arp.get_protocol_type().value(),
+            operation: arp.get_operation().0,
+            sender_mac: mac_bytes(arp.get_sender_hw_addr()),
+            sender_ip: arp.get_sender_proto_addr().octets(),
+            target_mac: mac_bytes(arp.get_target_hw_addr()),
+            target_ip: arp.get_target_proto_addr().octets(),
+        })
+    })
+}
+
+/// Parse the IPv4 header at the start of the `len` bytes at `data`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable `MyoxIpv4`.
+#[no_mangle]
+pub unsafe extern "C" fn myox_parse_ipv4(data: *const u8, len: usize, out: *mut MyoxIpv4) -> c_int {
+    parse_into(data, len, out, |data| {
+        let header_len = ipv4::header_len(data).map_err(error_code)?;
+        let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let mut source = [0u8; 4];
+        source.copy_from_slice(&data[12..16]);
+        let mut destination = [0u8; 4];
+        destination.copy_from_slice(&data[16..20]);
+        Ok(MyoxIpv4 {
+            header_len: header_len as u8,
+            dscp: data[1] >> 2,
+            ecn: data[1] & 0x03,
+            total_len: word(2),
+            identification: word(4),
+            flags: data[6] >> 5,
+            fragment_offset: word(6) & 0x1fff,
+            ttl: data[8],
+            protocol: data[9],
+            checksum: word(10),
+            source,
+            destination,
+            payload_offset: header_len,
+        })
+    })
+}
+
+/// Parse the TCP header at the start of the `len` bytes at `data`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable `MyoxTcp`.
+#[no_mangle]
+pub unsafe extern "C" fn myox_parse_tcp(data: *const u8, len: usize, out: *mut MyoxTcp) -> c_int {
+    parse_into(data, len, out, |data| {
+        if data.len() < 20 {
+            return Err(MYOX_ERR_TRUNCATED);
+        }
+        let header_len = usize::from(data[12] >> 4) * 4;
+        if header_len < 20 {
+            return Err(MYOX_ERR_MALFORMED);
+        }
+        if header_len > data.len() {
+            return Err(MYOX_ERR_TRUNCATED);
+        }
+        let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let long = |offset: usize| {
+            u32::from_be_bytes([
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            ])
+        };
+        Ok(MyoxTcp {
+            source_port: word(0),
+            destination_port: word(2),
+            sequence: long(4),
+            acknowledgment: long(8),
+            header_len: header_len as u8,
+            flags: data[13],
+            window: word(14),
+            checksum: word(16),
+            urgent_pointer: word(18),
+            payload_offset: header_len,
+        })
+    })
+}
@@ -0,0 +1,176 @@
+//! The Internet checksum (RFC 1071) used by IPv4, ICMP, TCP and UDP.
+
+use crate::ipproto::{IPPROTO_TCP, IPPROTO_UDP};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// A protocol whose checksum can be verified.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Layer {
+    /// The IPv4 header checksum
+    Ipv4,
+    /// The ICMP checksum, covering the message
+    Icmp,
+    /// The TCP checksum, covering the pseudo-header and the segment
+    Tcp,
+    /// The UDP checksum, covering the pseudo-header and the datagram
+    Udp,
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Layer::Ipv4 => "ip",
+            Layer::Icmp => "icmp",
+            Layer::Tcp => "tcp",
+            Layer::Udp => "udp",
+        })
+    }
+}
+
+/// Add `data` to the running one's complement `sum` as a sequence of 16 bit words.
+pub fn add(mut sum: u32, data: &[u8]) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u32::from(u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        sum += u32::from(*last) << 8;
+    }
+    sum
+}
+
+/// Fold the running `sum` to 16 bits and complement it.
+pub fn finish(mut sum: u32) -> u16 {
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Compute the checksum of `data`.
+pub fn checksum(data: &[u8]) -> u16 {
+    finish(add(0, data))
+}
+
+/// The checksum `sum` of data in which the 16 bit aligned bytes `old` were replaced with
+/// `new`, computed without going over the data again (RFC 1624).
+pub fn update(sum: u16, old: &[u8], new: &[u8]) -> u16 {
+    let mut running = u32::from(!sum);
+    let mut chunks = old.chunks_exact(2);
+    for chunk in &mut chunks {
+        running += u32::from(!u16::from_be_bytes([chunk[0], chunk[1]]));
+    }
+    if let [last] = chunks.remainder() {
+        running += u32::from(!(u16::from(*last) << 8));
+    }
+    finish(add(running, new))
+}
+
+/// Running sum of the IPv4 pseudo-header for an upper layer `protocol` of `len` bytes.
+pub fn ipv4_pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: u16) -> u32 {
+    let sum = add(add(0, &src.octets()), &dst.octets());
+    sum + u32::from(protocol) + u32::from(len)
+}
+
+/// Running sum of the IPv6 pseudo-header for an upper layer `next_header` of `len` bytes.
+pub fn ipv6_pseudo_header(src: Ipv6Addr, dst: Ipv6Addr, next_header: u8, len: u32) -> u32 {
+    let sum = add(add(0, &src.octets()), &dst.octets());
+    sum + (len >> 16) + (len & 0xffff) + u32::from(next_header)
+}
+
+/// Offset of the IPv4 header and its length, if the frame carries one.
+pub(crate) fn ipv4_header(frame: &[u8]) -> Option<(usize, usize)> {
+    if frame.len() < 14 + 20 || frame[12..14] != [0x08, 0x00] {
+        return None;
+    }
+    let ihl = (frame[14] & 0x0f) as usize * 4;
+    if ihl < 20 || frame.len() < 14 + ihl {
+        return None;
+    }
+    Some((14, ihl))
+}
+
+/// Recompute the IPv4 header checksum of the packet carried by an Ethernet frame, and the
+/// TCP or UDP checksum if it carries a segment, after its contents were rewritten.
+///
+/// Frames not carrying IPv4 are left untouched, as are fragments and UDP datagrams without
+/// a checksum.
+pub fn update_ipv4_frame(frame: &mut [u8]) {
+    let (ip, ihl) = match ipv4_header(frame) {
+        Some(header) => header,
+        None => return,
+    };
+    frame[ip + 10] = 0;
+    frame[ip + 11] = 0;
+    let sum = checksum(&frame[ip..ip + ihl]);
+    frame[ip + 10..ip + 12].copy_from_slice(&sum.to_be_bytes());
+
+    let total_len = u16::from_be_bytes([frame[ip + 2], frame[ip + 3]]) as usize;
+    let end = (ip + total_len).min(frame.len());
+    let transport = ip + ihl;
+    // fragments only carry part of the segment the checksum covers
+    let fragmented = u16::from_be_bytes([frame[ip + 6], frame[ip + 7]]) & 0x3fff != 0;
+    if fragmented || end <= transport {
+        return;
+    }
+    let protocol = frame[ip + 9];
+    let offset = match protocol {
+        IPPROTO_TCP if end - transport >= 20 => 16,
+        // a zero UDP checksum means none was computed, leave it that way
+        IPPROTO_UDP if end - transport >= 8 && frame[transport + 6..transport + 8] != [0, 0] => 6,
+        _ => return,
+    };
+    let src = Ipv4Addr::new(
+        frame[ip + 12],
+        frame[ip + 13],
+        frame[ip + 14],
+        frame[ip + 15],
+    );
+    let dst = Ipv4Addr::new(
+        frame[ip + 16],
+        frame[ip + 17],
+        frame[ip + 18],
+        frame[ip + 19],
+    );
+
+    let segment = &mut frame[transport..end];
+    segment[offset] = 0;
+    segment[offset + 1] = 0;
+    let pseudo = ipv4_pseudo_header(src, dst, protocol, segment.len() as u16);
+    let mut sum = finish(add(pseudo, segment));
+    if protocol == IPPROTO_UDP && sum == 0 {
+        sum = 0xffff;
+    }
+    segment[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn update_matches_rfc_1624() {
+        // the example of section 4, where RFC 1141 gave 0xffff
+        assert_eq!(update(0xdd2f, &[0x55, 0x55], &[0x32, 0x85]), 0x0000);
+        assert_eq!(
+            checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]),
+            !0xddf2
+        );
+    }
+
+    #[test]
+    fn update_matches_a_full_computation() {
+        let mut rng = Rng::new(7);
+        let mut data: Vec<u8> = (0..61).map(|_| rng.next_u64() as u8).collect();
+        for (start, len) in [(0, 2), (10, 4), (58, 3)] {
+            let sum = checksum(&data);
+            let old = data[start..start + len].to_vec();
+            for byte in &mut data[start..start + len] {
+                *byte = rng.next_u64() as u8;
+            }
+            let new = &data[start..start + len];
+            assert_eq!(update(sum, &old, new), checksum(&data), "at {}", start);
+        }
+    }
+}
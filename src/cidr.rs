@@ -0,0 +1,505 @@
+//! IPv4 and IPv6 networks in CIDR notation.
+//!
+//! A network is an address and a prefix length, written `192.168.0.0/24`. The address may
+//! have host bits set, so that an interface address and its network fit in one value;
+//! `network()` clears them. With the `serde` feature the types serialize as strings in
+//! that notation.
+
+use std::{
+    convert::TryFrom,
+    error, fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+/// Error returned when parsing a network fails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CidrParseError;
+
+impl fmt::Display for CidrParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("invalid network, expected an address and a prefix length like 10.0.0.0/8")
+    }
+}
+
+impl error::Error for CidrParseError {}
+
+// split `value` into an address and a prefix length, which defaults to `max_len`
+fn split<A: FromStr>(value: &str, max_len: u8) -> Result<(A, u8), CidrParseError> {
+    let (address, prefix_len) = match value.find('/') {
+        Some(slash) => (
+            &value[..slash],
+            value[slash + 1..].parse().map_err(|_| CidrParseError)?,
+        ),
+        None => (value, max_len),
+    };
+    if prefix_len > max_len {
+        return Err(CidrParseError);
+    }
+    Ok((address.parse().map_err(|_| CidrParseError)?, prefix_len))
+}
+
+/// An IPv4 network.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Ipv4Cidr {
+    address: Ipv4Addr,
+    prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// The network of `address` with the given prefix length.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix_len` is greater than 32.
+    pub fn new(address: Ipv4Addr, prefix_len: u8) -> Ipv4Cidr {
+        assert!(
+            prefix_len <= 32,
+            "prefix length {} out of range",
+            prefix_len
+        );
+        Ipv4Cidr {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// The address, as given.
+    pub fn address(&self) -> Ipv4Addr {
+        self.address
+    }
+
+    /// Number of leading bits identifying the network.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    fn mask(&self) -> u32 {
+        match self.prefix_len {
+            0 => 0,
+            len => !0u32 << (32 - len),
+        }
+    }
+
+    /// The mask covering the prefix, e.g. 255.255.255.0 for a /24.
+    pub fn netmask(&self) -> Ipv4Addr {
+        Ipv4Addr::from(self.mask())
+    }
+
+    /// The first address of the network.
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.address) & self.mask())
+    }
+
+    /// The last address of the network, None for /31 and /32 networks which have none
+    /// (RFC 3021).
+    pub fn broadcast(&self) -> Option<Ipv4Addr> {
+        if self.prefix_len >= 31 {
+            return None;
+        }
+        Some(Ipv4Addr::from(u32::from(self.address) | !self.mask()))
+    }
+
+    /// Whether `address` is in the network.
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        u32::from(address) & self.mask() == u32::from(self.address) & self.mask()
+    }
+
+    /// Whether every address of `other` is in the network.
+    pub fn contains_subnet(&self, other: &Ipv4Cidr) -> bool {
+        self.prefix_len <= other.prefix_len && self.contains(other.address)
+    }
+
+    /// The addresses a host of the network can have: all but the network and broadcast
+    /// addresses, if there are any.
+    pub fn hosts(&self) -> Ipv4Hosts {
+        let first = u64::from(u32::from(self.network()));
+        let size = 1u64 << (32 - self.prefix_len);
+        if self.broadcast().is_some() {
+            Ipv4Hosts {
+                next: first + 1,
+                end: first + size - 1,
+            }
+        } else {
+            Ipv4Hosts {
+                next: first,
+                end: first + size,
+            }
+        }
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl FromStr for Ipv4Cidr {
+    type Err = CidrParseError;
+
+    /// Parse `address/prefix_len`; a bare address is a /32.
+    fn from_str(s: &str) -> Result<Ipv4Cidr, CidrParseError> {
+        let (address, prefix_len) = split(s, 32)?;
+        Ok(Ipv4Cidr::new(address, prefix_len))
+    }
+}
+
+/// Iterator over the host addresses of an IPv4 network, see [`Ipv4Cidr::hosts`].
+#[derive(Clone, Debug)]
+pub struct Ipv4Hosts {
+    next: u64,
+    end: u64,
+}
+
+impl Iterator for Ipv4Hosts {
+    type Item = Ipv4Addr;
+
+    fn next(&mut self) -> Option<Ipv4Addr> {
+        if self.next >= self.end {
+            return None;
+        }
+        self.next += 1;
+        Some(Ipv4Addr::from((self.next - 1) as u32))
+    }
+
+    // skipping a large part of a network shouldn't take as long as walking it
+    fn nth(&mut self, n: usize) -> Option<Ipv4Addr> {
+        self.next = self.next.saturating_add(n as u64).min(self.end);
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match usize::try_from(self.remaining()) {
+            Ok(len) => (len, Some(len)),
+            Err(_) => (usize::MAX, None),
+        }
+    }
+}
+
+impl Ipv4Hosts {
+    /// Number of addresses left, which can be more than a 32 bit `usize` holds.
+    pub fn remaining(&self) -> u64 {
+        self.end.saturating_sub(self.next)
+    }
+}
+
+/// An IPv6 network.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Ipv6Cidr {
+    address: Ipv6Addr,
+    prefix_len: u8,
+}
+
+impl Ipv6Cidr {
+    /// The network of `address` with the given prefix length.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix_len` is greater than 128.
+    pub fn new(address: Ipv6Addr, prefix_len: u8) -> Ipv6Cidr {
+        assert!(
+            prefix_len <= 128,
+            "prefix length {} out of range",
+            prefix_len
+        );
+        Ipv6Cidr {
+            address,
+            prefix_len,
+        }
+    }
+
+    /// The address, as given.
+    pub fn address(&self) -> Ipv6Addr {
+        self.address
+    }
+
+    /// Number of leading bits identifying the network.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    fn mask(&self) -> u128 {
+        match self.prefix_len {
+            0 => 0,
+            len => !0u128 << (128 - len),
+        }
+    }
+
+    /// The mask covering the prefix.
+    pub fn netmask(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.mask())
+    }
+
+    /// The first address of the network.
+    pub fn network(&self) -> Ipv6Addr {
+        Ipv6Addr::from(u128::from(self.address) & self.mask())
+    }
+
+    /// Whether `address` is in the network.
+    pub fn contains(&self, address: Ipv6Addr) -> bool {
+        u128::from(address) & self.mask() == u128::from(self.address) & self.mask()
+    }
+
+    /// Whether every address of `other` is in the network.
+    pub fn contains_subnet(&self, other: &Ipv6Cidr) -> bool {
+        self.prefix_len <= other.prefix_len && self.contains(other.address)
+    }
+
+    /// Every address of the network; IPv6 has no broadcast address.
+    pub fn hosts(&self) -> Ipv6Hosts {
+        let first = u128::from(self.network());
+        Ipv6Hosts {
+            next: Some(first),
+            last: first | !self.mask(),
+        }
+    }
+}
+
+impl fmt::Display for Ipv6Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+impl FromStr for Ipv6Cidr {
+    type Err = CidrParseError;
+
+    /// Parse `address/prefix_len`; a bare address is a /128.
+    fn from_str(s: &str) -> Result<Ipv6Cidr, CidrParseError> {
+        let (address, prefix_len) = split(s, 128)?;
+        Ok(Ipv6Cidr::new(address, prefix_len))
+    }
+}
+
+/// Iterator over the addresses of an IPv6 network, see [`Ipv6Cidr::hosts`].
+#[derive(Clone, Debug)]
+pub struct Ipv6Hosts {
+    next: Option<u128>,
+    last: u128,
+}
+
+impl Iterator for Ipv6Hosts {
+    type Item = Ipv6Addr;
+
+    fn next(&mut self) -> Option<Ipv6Addr> {
+        let next = self.next?;
+        self.next = if next < self.last {
+            Some(next + 1)
+        } else {
+            None
+        };
+        Some(Ipv6Addr::from(next))
+    }
+}
+
+/// An IPv4 or IPv6 network.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum IpCidr {
+    /// An IPv4 network
+    V4(Ipv4Cidr),
+    /// An IPv6 network
+    V6(Ipv6Cidr),
+}
+
+impl IpCidr {
+    /// The network of `address` with the given prefix length.
+    ///
+    /// # Panics
+    ///
+    /// If `prefix_len` is longer than the address.
+    pub fn new(address: IpAddr, prefix_len: u8) -> IpCidr {
+        match address {
+            IpAddr::V4(address) => IpCidr::V4(Ipv4Cidr::new(address, prefix_len)),
+            IpAddr::V6(address) => IpCidr::V6(Ipv6Cidr::new(address, prefix_len)),
+        }
+    }
+
+    /// The network holding only `address`.
+    pub fn host(address: IpAddr) -> IpCidr {
+        let prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        IpCidr::new(address, prefix_len)
+    }
+
+    /// The address, as given.
+    pub fn address(&self) -> IpAddr {
+        match self {
+            IpCidr::V4(cidr) => IpAddr::V4(cidr.address()),
+            IpCidr::V6(cidr) => IpAddr::V6(cidr.address()),
+        }
+    }
+
+    /// Number of leading bits identifying the network.
+    pub fn prefix_len(&self) -> u8 {
+        match self {
+            IpCidr::V4(cidr) => cidr.prefix_len(),
+            IpCidr::V6(cidr) => cidr.prefix_len(),
+        }
+    }
+
+    /// The first address of the network.
+    pub fn network(&self) -> IpAddr {
+        match self {
+            IpCidr::V4(cidr) => IpAddr::V4(cidr.network()),
+            IpCidr::V6(cidr) => IpAddr::V6(cidr.network()),
+        }
+    }
+
+    /// Whether `address` is in the network. Addresses of the other family never are.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self, address) {
+            (IpCidr::V4(cidr), IpAddr::V4(address)) => cidr.contains(address),
+            (IpCidr::V6(cidr), IpAddr::V6(address)) => cidr.contains(address),
+            _ => false,
+        }
+    }
+}
+
+impl From<Ipv4Cidr> for IpCidr {
+    fn from(cidr: Ipv4Cidr) -> IpCidr {
+        IpCidr::V4(cidr)
+    }
+}
+
+impl From<Ipv6Cidr> for IpCidr {
+    fn from(cidr: Ipv6Cidr) -> IpCidr {
+        IpCidr::V6(cidr)
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IpCidr::V4(cidr) => cidr.fmt(f),
+            IpCidr::V6(cidr) => cidr.fmt(f),
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = CidrParseError;
+
+    /// Parse an IPv4 or IPv6 `address/prefix_len`; a bare address is a host network.
+    fn from_str(s: &str) -> Result<IpCidr, CidrParseError> {
+        s.parse()
+            .map(IpCidr::V4)
+            .or_else(|_| s.parse().map(IpCidr::V6))
+    }
+}
+
+#[cfg(feature = "serde")]
+macro_rules! serde_as_string {
+    ($type:ty, $expecting:expr) => {
+        impl serde::Serialize for $type {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $type {
+            fn deserialize<D: serde::Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<$type, D::Error> {
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = $type;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str($expecting)
+                    }
+
+                    fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<$type, E> {
+                        value.parse().map_err(E::custom)
+                    }
+                }
+
+                deserializer.deserialize_str(Visitor)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde")]
+serde_as_string!(Ipv4Cidr, "an IPv4 network like 192.168.0.0/24");
+#[cfg(feature = "serde")]
+serde_as_string!(Ipv6Cidr, "an IPv6 network like fd00::/64");
+#[cfg(feature = "serde")]
+serde_as_string!(IpCidr, "an IP network like 192.168.0.0/24");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(s: &str) -> Ipv4Cidr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv4_hosts_skip_network_and_broadcast() {
+        let cidr = v4("192.168.1.5/30");
+        assert_eq!(cidr.network(), Ipv4Addr::new(192, 168, 1, 4));
+        assert_eq!(cidr.broadcast(), Some(Ipv4Addr::new(192, 168, 1, 7)));
+        let hosts: Vec<_> = cidr.hosts().collect();
+        assert_eq!(
+            hosts,
+            [Ipv4Addr::new(192, 168, 1, 5), Ipv4Addr::new(192, 168, 1, 6)]
+        );
+        assert_eq!(cidr.hosts().size_hint(), (2, Some(2)));
+    }
+
+    #[test]
+    fn ipv4_edges() {
+        // point-to-point links use both addresses (RFC 3021)
+        let link = v4("10.0.0.1/31");
+        assert_eq!(link.broadcast(), None);
+        let hosts: Vec<_> = link.hosts().collect();
+        assert_eq!(
+            hosts,
+            [Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 0, 1)]
+        );
+
+        let host = v4("10.0.0.7");
+        assert_eq!(host.prefix_len(), 32);
+        assert_eq!(host.netmask(), Ipv4Addr::BROADCAST);
+        assert_eq!(
+            host.hosts().collect::<Vec<_>>(),
+            [Ipv4Addr::new(10, 0, 0, 7)]
+        );
+        let last = v4("255.255.255.255/32");
+        assert_eq!(last.hosts().collect::<Vec<_>>(), [Ipv4Addr::BROADCAST]);
+
+        let all = v4("0.0.0.0/0");
+        assert_eq!(all.netmask(), Ipv4Addr::UNSPECIFIED);
+        assert_eq!(all.broadcast(), Some(Ipv4Addr::BROADCAST));
+        assert!(all.contains(Ipv4Addr::new(203, 0, 113, 9)));
+        assert!(all.contains_subnet(&link));
+        let mut hosts = all.hosts();
+        assert_eq!(hosts.remaining(), (1 << 32) - 2);
+        assert_eq!(hosts.next(), Some(Ipv4Addr::new(0, 0, 0, 1)));
+        assert_eq!(
+            hosts.nth(0xffff_fffc),
+            Some(Ipv4Addr::new(255, 255, 255, 254))
+        );
+        assert_eq!(hosts.next(), None);
+
+        assert!("10.0.0.0/33".parse::<Ipv4Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Ipv4Cidr>().is_err());
+    }
+
+    #[test]
+    fn ipv6_hosts_cover_the_whole_network() {
+        let cidr: Ipv6Cidr = "fd00::5/126".parse().unwrap();
+        let hosts: Vec<_> = cidr.hosts().collect();
+        let expected: Vec<Ipv6Addr> = (0..4)
+            .map(|i| Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 4 + i))
+            .collect();
+        assert_eq!(hosts, expected);
+
+        let last: Ipv6Cidr = "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".parse().unwrap();
+        assert_eq!(last.hosts().count(), 1);
+        let all: Ipv6Cidr = "::/0".parse().unwrap();
+        assert!(all.contains(Ipv6Addr::LOCALHOST));
+        assert_eq!(all.hosts().nth(1), Some(Ipv6Addr::LOCALHOST));
+    }
+}
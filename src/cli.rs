@@ -0,0 +1,2109 @@
+//! The `myox` command line: a single entry point dispatching to the individual tools.
+//!
+//! Options given before the subcommand (`-i interface`, `-q`, `--json`, `--color`,
+//! `-m address`) are shared by all of them.
+
+use crate::{
+    arp::{
+        arp_new::{ArpOperations, ArpPacket},
+        channel::{channel, Channel, Config, EthernetDataLinkSender, Native},
+        ether::{EtherTypes, EthernetPacket, Packet},
+        network_interface::{MacAddr, NetworkInterface},
+        other::build_arp_packet,
+        scan as arp_scan,
+    },
+    cidr::{IpCidr, Ipv4Cidr},
+    dhcp, discover, dns,
+    generate::{self, Field, Generator, Rule},
+    metrics::{self, Metrics},
+    neighbor::{self, Neighbor, NeighborCache},
+    perf, ping,
+    render::ColorMode,
+    replay,
+    scan::ports,
+    shape,
+    sniff::{self, select_interface, ParseError},
+    spoof::SourceMac,
+    synflood,
+    tcp::stack::Interface,
+    vlanhop, wol,
+};
+#[cfg(target_os = "linux")]
+use crate::{offload, routes};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Subcommands with a one-line description, in the order they are listed by `help`.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("sniff", "capture and print frames"),
+    ("arping", "probe a host with ARP requests"),
+    ("scan", "discover hosts on the local network"),
+    (
+        "discover",
+        "map hosts and switch ports of the local network",
+    ),
+    ("portscan", "find open TCP or UDP ports of a host"),
+    ("ping", "send ICMP echo requests"),
+    ("trace", "print the route packets take to a host"),
+    ("wol", "send a Wake-on-LAN magic packet"),
+    ("generate", "send crafted frames at a given rate"),
+    ("replay", "send the frames of a pcap file"),
+    ("perf", "measure throughput to another myox host"),
+    ("serve-dhcp", "run a DHCP server"),
+    (
+        "dhcp-starve",
+        "test DHCP pool exhaustion and spot rogue servers",
+    ),
+    ("dns-monitor", "report suspicious DNS responses"),
+    (
+        "syn-flood",
+        "send SYN segments at a limited rate to a lab host",
+    ),
+    ("vlan-hop", "test whether a switch port keeps to its VLAN"),
+];
+
+/// Command line usage of `myox`, without the list of subcommands.
+pub const USAGE: &str =
+    "usage: myox [-i interface] [-q] [--json] [--color|--no-color] [-m address] <command> [args...]";
+
+/// Name of the histogram `ping` records round trip times in.
+pub const PING_RTT: &str = "ping.rtt";
+
+/// Name of the histogram the times neighbors took to answer ARP requests are recorded in.
+pub const ARP_RESOLUTION: &str = "arp.resolution";
+
+/// Options shared by all subcommands.
+#[derive(Debug, Clone, Default)]
+pub struct Common {
+    /// Interface to work on. Defaults to the first interface that is up and not a loopback
+    pub interface: Option<String>,
+    /// Only report errors
+    pub quiet: bool,
+    /// Print results as JSON, for the commands that can: sniff, discover and serve-dhcp
+    pub json: bool,
+    /// Whether output is colored, None to leave it to the command. Defaults to None
+    pub color: Option<ColorMode>,
+    /// Address to serve the metrics of the command on in the Prometheus format, None not
+    /// to. Defaults to None
+    pub metrics: Option<SocketAddr>,
+}
+
+/// Options of `myox arping`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArpingOptions {
+    /// Address to probe
+    pub target: Ipv4Addr,
+    /// Stop after this many probes. Defaults to None, probing until interrupted
+    pub count: Option<usize>,
+    /// Time to wait for a reply before sending the next probe. Defaults to one second
+    pub timeout: Duration,
+}
+
+/// Options of `myox generate`.
+#[derive(Debug, Clone)]
+pub struct GenerateOptions {
+    /// Frame to send
+    pub template: Vec<u8>,
+    /// Rewrite rules applied to each copy of the template
+    pub rules: Vec<(Field, Rule)>,
+    /// Seed of the random rules. Defaults to None, seeding from the clock
+    pub seed: Option<u64>,
+    /// Source address of the frames. Defaults to the template's
+    pub source_mac: SourceMac,
+    /// Rate and count
+    pub config: generate::Config,
+    /// Bandwidth limit, None for none
+    pub shaping: Option<shape::Config>,
+}
+
+/// Options of `myox replay`.
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    /// Capture file to replay
+    pub path: PathBuf,
+    /// Timing, loops and rewrites
+    pub config: replay::Config,
+    /// Bandwidth limit, None for none
+    pub shaping: Option<shape::Config>,
+}
+
+/// Options of `myox perf`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PerfOptions {
+    /// Answer test clients
+    Server,
+    /// Run a test against a server
+    Client(perf::ClientConfig),
+    /// Answer UDP test clients on the address, over the userspace stack
+    UdpServer(SocketAddrV4),
+    /// Run a test over UDP against a server
+    UdpClient(perf::SocketConfig),
+    /// Answer TCP test clients on the address, over the userspace stack
+    TcpServer(SocketAddrV4),
+    /// Run a test over TCP against a server
+    TcpClient(perf::SocketConfig),
+}
+
+/// How `myox perf` sends its test traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PerfMode {
+    Ethernet,
+    Udp,
+    Tcp,
+}
+
+/// Port of `myox perf` servers over UDP and TCP, the one of iperf3.
+const PERF_PORT: u16 = 5201;
+
+/// Options of `myox portscan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortscanOptions {
+    /// Host to scan
+    pub target: IpAddr,
+    /// Ports to scan. Defaults to 1-1024
+    pub ports: Vec<u16>,
+    /// Scanning technique. Defaults to connect
+    pub mode: ports::Mode,
+    /// Next hop for SYN scans, required by them
+    pub next_hop: Option<MacAddr>,
+    /// Timeouts, retries and concurrency
+    pub config: ports::Config,
+}
+
+/// Options of `myox discover`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoverOptions {
+    /// Network to sweep. Defaults to the /24 of the interface's address
+    pub network: Option<Ipv4Cidr>,
+    /// Print the inventory as JSON
+    pub json: bool,
+    /// Sweep rate and listening time
+    pub config: discover::Config,
+}
+
+/// Options of `myox scan`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanOptions {
+    /// Network to sweep. Defaults to the /24 of the interface's address
+    pub network: Option<Ipv4Cidr>,
+    /// Rate and waiting time
+    pub config: arp_scan::Config,
+}
+
+/// Options of `myox ping`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PingOptions {
+    /// Host to probe
+    pub target: Ipv4Addr,
+    /// MAC address of the target, or of the gateway. Defaults to None, resolving it
+    pub next_hop: Option<MacAddr>,
+    /// Count, interval and timeout
+    pub config: ping::Config,
+}
+
+/// Options of `myox trace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceOptions {
+    /// Host to trace the route to
+    pub target: Ipv4Addr,
+    /// MAC address of the gateway. Defaults to None, resolving it
+    pub next_hop: Option<MacAddr>,
+    /// Hops and timeout
+    pub config: ping::TraceConfig,
+}
+
+/// Options of `myox syn-flood`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SynFloodOptions {
+    /// Host to send to
+    pub target: Ipv4Addr,
+    /// MAC address of the target, or of the gateway
+    pub next_hop: MacAddr,
+    /// Allow-list, rate and sources
+    pub config: synflood::Config,
+}
+
+/// Options of `myox vlan-hop`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VlanHopOptions {
+    /// Interface in the victim VLAN watched for probes
+    pub monitor: Option<String>,
+    /// VLANs, target and variants
+    pub config: vlanhop::Config,
+}
+
+/// A parsed subcommand.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// Capture frames
+    Sniff(sniff::Options),
+    /// Probe a host with ARP requests
+    Arping(ArpingOptions),
+    /// Find the hosts of a network with ARP
+    Scan(ScanOptions),
+    /// Wake the host with the given MAC address
+    Wol(MacAddr),
+    /// Send generated frames
+    Generate(GenerateOptions),
+    /// Send the frames of a capture file
+    Replay(ReplayOptions),
+    /// Measure throughput
+    Perf(PerfOptions),
+    /// Scan ports
+    Portscan(PortscanOptions),
+    /// Build an inventory of the local network
+    Discover(DiscoverOptions),
+    /// Probe a host with ICMP echo requests
+    Ping(PingOptions),
+    /// Print the routers on the way to a host
+    Trace(TraceOptions),
+    /// Hand out addresses to DHCP clients
+    ServeDhcp(dhcp::ServerConfig),
+    /// Exhaust DHCP pools and watch for rogue servers
+    DhcpStarve(dhcp::Config),
+    /// Watch DNS traffic for spoofed responses
+    DnsMonitor(dns::Config),
+    /// Send SYN segments to a lab host
+    SynFlood(SynFloodOptions),
+    /// Test VLAN isolation of a switch port
+    VlanHop(VlanHopOptions),
+    /// Print usage
+    Help,
+}
+
+/// Full help text listing every subcommand.
+pub fn help() -> String {
+    let mut text = format!("{}\n\ncommands:\n", USAGE);
+    for (name, description) in COMMANDS {
+        text.push_str(&format!("    {:<12}{}\n", name, description));
+    }
+    text
+}
+
+/// Parse command line arguments, without the program name.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<(Common, Command), ParseError> {
+    let mut common: Common = Default::default();
+    let mut args = args.into_iter();
+    let name = loop {
+        match args.next() {
+            Some(arg) => match arg.as_str() {
+                "-i" => {
+                    common.interface = Some(
+                        args.next()
+                            .ok_or_else(|| ParseError("option -i requires a value".to_owned()))?,
+                    )
+                }
+                "-q" => common.quiet = true,
+                "--json" => common.json = true,
+                "--color" => common.color = Some(ColorMode::Always),
+                "--no-color" => common.color = Some(ColorMode::Never),
+                "-m" => {
+                    let address = args
+                        .next()
+                        .ok_or_else(|| ParseError("option -m requires a value".to_owned()))?;
+                    common.metrics = Some(address.parse().map_err(|_| {
+                        ParseError(format!("invalid metrics address `{}`", address))
+                    })?);
+                }
+                "-h" | "--help" => return Ok((common, Command::Help)),
+                _ if arg.starts_with('-') => {
+                    return Err(ParseError(format!("unknown option `{}`", arg)))
+                }
+                _ => break arg,
+            },
+            None => return Ok((common, Command::Help)),
+        }
+    };
+
+    let command = match name.as_str() {
+        "help" => Command::Help,
+        "sniff" => Command::Sniff(sniff::Options::from_args(args)?),
+        "arping" => Command::Arping(parse_arping(args)?),
+        "scan" => Command::Scan(parse_scan(args)?),
+        "wol" => {
+            let target = single_operand(args, "wol")?;
+            Command::Wol(
+                target
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid MAC address `{}`", target)))?,
+            )
+        }
+        "generate" => Command::Generate(parse_generate(args)?),
+        "replay" => Command::Replay(parse_replay(args)?),
+        "perf" => Command::Perf(parse_perf(args)?),
+        "portscan" => Command::Portscan(parse_portscan(args)?),
+        "discover" => Command::Discover(parse_discover(args)?),
+        "ping" => Command::Ping(parse_ping(args)?),
+        "trace" => Command::Trace(parse_trace(args)?),
+        "serve-dhcp" => Command::ServeDhcp(parse_serve_dhcp(args)?),
+        "dhcp-starve" => Command::DhcpStarve(parse_dhcp_starve(args)?),
+        "dns-monitor" => Command::DnsMonitor(parse_dns_monitor(args)?),
+        "syn-flood" => Command::SynFlood(parse_syn_flood(args)?),
+        "vlan-hop" => Command::VlanHop(parse_vlan_hop(args)?),
+        _ => return Err(ParseError(format!("unknown command `{}`", name))),
+    };
+    Ok((common, command))
+}
+
+fn parse_arping<I: Iterator<Item = String>>(mut args: I) -> Result<ArpingOptions, ParseError> {
+    let mut target = None;
+    let mut count = None;
+    let mut timeout = Duration::from_secs(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        match arg.as_str() {
+            "-c" => {
+                let value = value("-c")?;
+                count = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid count `{}`", value)))?,
+                );
+            }
+            "-t" => {
+                let value = value("-t")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid timeout `{}`", value)))?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid timeout `{}`", value)));
+                }
+                timeout = Duration::from_secs_f64(secs);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => {
+                target = Some(
+                    arg.parse()
+                        .map_err(|_| ParseError(format!("invalid IPv4 address `{}`", arg)))?,
+                )
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(ArpingOptions {
+        target: target.ok_or_else(|| ParseError("arping requires a target".to_owned()))?,
+        count,
+        timeout,
+    })
+}
+
+fn parse_scan<I: Iterator<Item = String>>(mut args: I) -> Result<ScanOptions, ParseError> {
+    let mut network = None;
+    let mut config: arp_scan::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        match arg.as_str() {
+            "-r" => {
+                let value = value("-r")?;
+                config.rate = match value.parse::<f64>() {
+                    Ok(rate) if rate > 0.0 && rate.is_finite() => rate,
+                    _ => return Err(ParseError(format!("invalid rate `{}`", value))),
+                };
+            }
+            "-w" => {
+                let value = value("-w")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid duration `{}`", value)))?;
+                if !(secs >= 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid duration `{}`", value)));
+                }
+                config.wait = Duration::from_secs_f64(secs);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if network.is_none() => network = Some(parse_network(&arg)?),
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(ScanOptions { network, config })
+}
+
+fn parse_generate<I: Iterator<Item = String>>(mut args: I) -> Result<GenerateOptions, ParseError> {
+    let mut hex = String::new();
+    let mut rules = Vec::new();
+    let mut seed = None;
+    let mut source_mac = SourceMac::Keep;
+    let mut config: generate::Config = Default::default();
+    let mut shaping: ShapingArgs = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let invalid = |what: &str, value: &str| ParseError(format!("invalid {} `{}`", what, value));
+        match arg.as_str() {
+            "-c" => {
+                let value = value("-c")?;
+                config.count = Some(value.parse().map_err(|_| invalid("count", &value))?);
+            }
+            "-r" => {
+                let value = value("-r")?;
+                let rate: f64 = value.parse().map_err(|_| invalid("rate", &value))?;
+                if !(rate > 0.0 && rate.is_finite()) {
+                    return Err(invalid("rate", &value));
+                }
+                config.rate = Some(rate);
+            }
+            "--cpu" => {
+                let value = value("--cpu")?;
+                config.cpu = Some(value.parse().map_err(|_| invalid("cpu", &value))?);
+            }
+            "-s" => {
+                let value = value("-s")?;
+                seed = Some(value.parse().map_err(|_| invalid("seed", &value))?);
+            }
+            "--src-mac" => source_mac = value("--src-mac")?.parse()?,
+            "--shape" | "--burst" | "--per-flow" | "--classify" => {
+                shaping.set(&arg, &value(&arg)?)?
+            }
+            "--increment" => rules.push((value("--increment")?.parse()?, Rule::Increment)),
+            "--random" => rules.push((value("--random")?.parse()?, Rule::Random)),
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ => hex.push_str(&arg),
+        }
+    }
+    if hex.is_empty() {
+        return Err(ParseError("generate requires a hex frame".to_owned()));
+    }
+    let template = generate::parse_hex(&hex)?;
+    if template.len() < 14 {
+        return Err(ParseError(
+            "frame is shorter than an Ethernet header".to_owned(),
+        ));
+    }
+    Ok(GenerateOptions {
+        template,
+        rules,
+        seed,
+        source_mac,
+        config,
+        shaping: shaping.finish()?,
+    })
+}
+
+fn parse_replay<I: Iterator<Item = String>>(mut args: I) -> Result<ReplayOptions, ParseError> {
+    let mut path = None;
+    let mut config: replay::Config = Default::default();
+    let mut shaping: ShapingArgs = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let mac = |value: String| {
+            value
+                .parse()
+                .map_err(|_| ParseError(format!("invalid MAC address `{}`", value)))
+        };
+        let ip = |value: String| {
+            value
+                .parse()
+                .map_err(|_| ParseError(format!("invalid IPv4 address `{}`", value)))
+        };
+        match arg.as_str() {
+            "-l" => {
+                let value = value("-l")?;
+                config.loops = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid loop count `{}`", value)))?;
+            }
+            "-t" => config.preserve_timing = true,
+            "--speed" => {
+                let value = value("--speed")?;
+                config.speed = value
+                    .parse()
+                    .ok()
+                    .filter(|speed: &f64| *speed > 0.0)
+                    .ok_or_else(|| ParseError(format!("invalid speed `{}`", value)))?;
+                config.preserve_timing = true;
+            }
+            "--shape" | "--burst" | "--per-flow" | "--classify" => {
+                shaping.set(&arg, &value(&arg)?)?
+            }
+            "--src-mac" => config.rewrite.src_mac = Some(mac(value("--src-mac")?)?),
+            "--dst-mac" => config.rewrite.dst_mac = Some(mac(value("--dst-mac")?)?),
+            "--src-ip" => config.rewrite.src_ip = Some(ip(value("--src-ip")?)?),
+            "--dst-ip" => config.rewrite.dst_ip = Some(ip(value("--dst-ip")?)?),
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if path.is_none() => path = Some(PathBuf::from(arg)),
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(ReplayOptions {
+        path: path.ok_or_else(|| ParseError("replay requires a capture file".to_owned()))?,
+        config,
+        shaping: shaping.finish()?,
+    })
+}
+
+fn parse_perf<I: Iterator<Item = String>>(mut args: I) -> Result<PerfOptions, ParseError> {
+    let mut server = false;
+    let mut mode = PerfMode::Ethernet;
+    let mut address = None;
+    let mut port = None;
+    let mut target = None;
+    let mut duration = None;
+    let mut size = None;
+    let mut rate = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        match arg.as_str() {
+            "-s" => server = true,
+            "-m" => {
+                mode = match value("-m")?.as_str() {
+                    "eth" => PerfMode::Ethernet,
+                    "udp" => PerfMode::Udp,
+                    "tcp" => PerfMode::Tcp,
+                    other => return Err(ParseError(format!("invalid mode `{}`", other))),
+                }
+            }
+            "-a" => {
+                let value = value("-a")?;
+                address = Some(
+                    value
+                        .parse::<Ipv4Addr>()
+                        .map_err(|_| ParseError(format!("invalid address `{}`", value)))?,
+                );
+            }
+            "-p" => {
+                let value = value("-p")?;
+                port = Some(
+                    value
+                        .parse::<u16>()
+                        .map_err(|_| ParseError(format!("invalid port `{}`", value)))?,
+                );
+            }
+            "-t" => {
+                let value = value("-t")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid duration `{}`", value)))?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid duration `{}`", value)));
+                }
+                duration = Some(Duration::from_secs_f64(secs));
+            }
+            "-l" => {
+                let value = value("-l")?;
+                size = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| ParseError(format!("invalid size `{}`", value)))?,
+                );
+            }
+            "-b" => {
+                let value = value("-b")?;
+                rate = Some(
+                    parse_rate(&value)
+                        .ok_or_else(|| ParseError(format!("invalid rate `{}`", value)))?,
+                );
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => target = Some(arg),
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+
+    let (min_size, max_size) = match mode {
+        PerfMode::Ethernet => (perf::MIN_FRAME_SIZE, usize::MAX),
+        PerfMode::Udp => (perf::MIN_DATAGRAM_SIZE, perf::MAX_DATAGRAM_SIZE),
+        PerfMode::Tcp => (1, usize::MAX),
+    };
+    match size {
+        Some(size) if size < min_size => {
+            return Err(ParseError(format!("size must be at least {}", min_size)))
+        }
+        Some(size) if size > max_size => {
+            return Err(ParseError(format!("size must be at most {}", max_size)))
+        }
+        _ => {}
+    }
+
+    match (server, target) {
+        (true, Some(_)) => Err(ParseError(
+            "perf takes either -s or a server address".to_owned(),
+        )),
+        (false, None) => Err(ParseError(
+            "perf requires -s or a server address".to_owned(),
+        )),
+        (true, None) => {
+            let local = || match address {
+                Some(address) => Ok(SocketAddrV4::new(address, port.unwrap_or(PERF_PORT))),
+                None => Err(ParseError(
+                    "perf servers over UDP and TCP require -a".to_owned(),
+                )),
+            };
+            match mode {
+                PerfMode::Ethernet if address.is_some() || port.is_some() => Err(ParseError(
+                    "-a and -p apply to UDP and TCP servers".to_owned(),
+                )),
+                PerfMode::Ethernet => Ok(PerfOptions::Server),
+                PerfMode::Udp => Ok(PerfOptions::UdpServer(local()?)),
+                PerfMode::Tcp => Ok(PerfOptions::TcpServer(local()?)),
+            }
+        }
+        (false, Some(target)) => {
+            if address.is_some() || port.is_some() {
+                return Err(ParseError("-a and -p apply to servers".to_owned()));
+            }
+            if mode == PerfMode::Ethernet {
+                let server = target
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid MAC address `{}`", target)))?;
+                let mut config = perf::ClientConfig::new(server);
+                config.duration = duration.unwrap_or(config.duration);
+                config.frame_size = size.unwrap_or(config.frame_size);
+                config.rate = rate;
+                return Ok(PerfOptions::Client(config));
+            }
+            let server = match target.parse::<SocketAddr>() {
+                Ok(server) => server,
+                Err(_) => SocketAddr::new(
+                    target
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid address `{}`", target)))?,
+                    PERF_PORT,
+                ),
+            };
+            let mut config = perf::SocketConfig::new(server);
+            config.duration = duration.unwrap_or(config.duration);
+            config.size = size.unwrap_or(config.size);
+            config.rate = rate;
+            Ok(match mode {
+                PerfMode::Udp => PerfOptions::UdpClient(config),
+                _ => PerfOptions::TcpClient(config),
+            })
+        }
+    }
+}
+
+/// Shaping options shared by `generate` and `replay`.
+#[derive(Default)]
+struct ShapingArgs {
+    rate: Option<u64>,
+    burst: Option<u64>,
+    per_flow: Option<u64>,
+    classifier: Option<shape::Classifier>,
+}
+
+impl ShapingArgs {
+    fn set(&mut self, option: &str, value: &str) -> Result<(), ParseError> {
+        let rate =
+            || parse_rate(value).ok_or_else(|| ParseError(format!("invalid rate `{}`", value)));
+        match option {
+            "--shape" => self.rate = Some(rate()?),
+            "--per-flow" => self.per_flow = Some(rate()?),
+            "--burst" => {
+                self.burst = Some(
+                    sniff::parse_size(value)
+                        .ok_or_else(|| ParseError(format!("invalid burst size `{}`", value)))?,
+                )
+            }
+            _ => self.classifier = Some(value.parse()?),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Option<shape::Config>, ParseError> {
+        let rate = match self.rate {
+            Some(rate) => rate,
+            None if self.burst.is_some()
+                || self.per_flow.is_some()
+                || self.classifier.is_some() =>
+            {
+                return Err(ParseError(
+                    "shaping options require a rate (--shape)".to_owned(),
+                ))
+            }
+            None => return Ok(None),
+        };
+        let mut config = shape::Config {
+            rate,
+            ..Default::default()
+        };
+        config.burst = self.burst.unwrap_or(config.burst);
+        config.per_flow = self.per_flow.map(|per_flow| shape::FlowLimit {
+            classifier: self.classifier.unwrap_or(shape::Classifier::Flow),
+            rate: per_flow,
+            burst: config.burst,
+        });
+        Ok(Some(config))
+    }
+}
+
+/// Parse a rate in bits per second with an optional `k`, `M` or `G` suffix.
+fn parse_rate(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1e3),
+        'm' | 'M' => (&value[..value.len() - 1], 1e6),
+        'g' | 'G' => (&value[..value.len() - 1], 1e9),
+        _ => (value, 1.0),
+    };
+    match number.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Some((rate * multiplier) as u64),
+        _ => None,
+    }
+}
+
+fn parse_portscan<I: Iterator<Item = String>>(mut args: I) -> Result<PortscanOptions, ParseError> {
+    let mut target = None;
+    let mut ports = None;
+    let mut mode = ports::Mode::Connect;
+    let mut next_hop = None;
+    let mut config: ports::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let number = |what: &str, value: String| {
+            value
+                .parse::<usize>()
+                .map_err(|_| ParseError(format!("invalid {} `{}`", what, value)))
+        };
+        match arg.as_str() {
+            "-m" => mode = value("-m")?.parse()?,
+            "-p" => ports = Some(ports::parse_ports(&value("-p")?)?),
+            "-t" => {
+                let value = value("-t")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid timeout `{}`", value)))?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid timeout `{}`", value)));
+                }
+                config.timeout = Duration::from_secs_f64(secs);
+            }
+            "-r" => config.retries = number("retry count", value("-r")?)?,
+            "-j" => config.concurrency = number("concurrency", value("-j")?)?.max(1),
+            "-g" => {
+                let value = value("-g")?;
+                next_hop = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid MAC address `{}`", value)))?,
+                );
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => {
+                target = Some(
+                    arg.parse()
+                        .map_err(|_| ParseError(format!("invalid IP address `{}`", arg)))?,
+                )
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+
+    let target: IpAddr =
+        target.ok_or_else(|| ParseError("portscan requires a target".to_owned()))?;
+    if mode == ports::Mode::Syn && (next_hop.is_none() || !target.is_ipv4()) {
+        return Err(ParseError(
+            "a SYN scan needs an IPv4 target and the next hop's MAC address (-g)".to_owned(),
+        ));
+    }
+    Ok(PortscanOptions {
+        target,
+        ports: ports.unwrap_or_else(|| (1..=1024).collect()),
+        mode,
+        next_hop,
+        config,
+    })
+}
+
+fn parse_discover<I: Iterator<Item = String>>(mut args: I) -> Result<DiscoverOptions, ParseError> {
+    let mut network = None;
+    let mut json = false;
+    let mut config: discover::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        match arg.as_str() {
+            "-t" => {
+                let value = value("-t")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid duration `{}`", value)))?;
+                if !(secs >= 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid duration `{}`", value)));
+                }
+                config.listen = Duration::from_secs_f64(secs);
+            }
+            "--json" => json = true,
+            "-n" | "--resolve" => config.resolve_names = true,
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if network.is_none() => network = Some(parse_network(&arg)?),
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(DiscoverOptions {
+        network,
+        json,
+        config,
+    })
+}
+
+fn parse_ping<I: Iterator<Item = String>>(mut args: I) -> Result<PingOptions, ParseError> {
+    let mut target = None;
+    let mut next_hop = None;
+    let mut config: ping::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        match arg.as_str() {
+            "-c" => {
+                let value = value("-c")?;
+                config.count = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid count `{}`", value)))?,
+                );
+            }
+            "-i" => {
+                let value = value("-i")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid interval `{}`", value)))?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid interval `{}`", value)));
+                }
+                config.interval = Duration::from_secs_f64(secs);
+            }
+            "-t" => {
+                let value = value("-t")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid timeout `{}`", value)))?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid timeout `{}`", value)));
+                }
+                config.timeout = Duration::from_secs_f64(secs);
+            }
+            "-s" => {
+                let value = value("-s")?;
+                config.payload_len = match value.parse() {
+                    // an ICMP message of the size has to fit an Ethernet frame
+                    Ok(len) if len <= 1472 => len,
+                    _ => return Err(ParseError(format!("invalid size `{}`", value))),
+                };
+            }
+            "-g" => {
+                let value = value("-g")?;
+                next_hop = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid MAC address `{}`", value)))?,
+                );
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => {
+                target = Some(
+                    arg.parse()
+                        .map_err(|_| ParseError(format!("invalid IPv4 address `{}`", arg)))?,
+                )
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(PingOptions {
+        target: target.ok_or_else(|| ParseError("ping requires a target".to_owned()))?,
+        next_hop,
+        config,
+    })
+}
+
+fn parse_trace<I: Iterator<Item = String>>(mut args: I) -> Result<TraceOptions, ParseError> {
+    let mut target = None;
+    let mut next_hop = None;
+    let mut config: ping::TraceConfig = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        match arg.as_str() {
+            "-m" => {
+                let value = value("-m")?;
+                config.max_hops = match value.parse() {
+                    Ok(hops) if hops > 0 => hops,
+                    _ => return Err(ParseError(format!("invalid hop count `{}`", value))),
+                };
+            }
+            "-t" => {
+                let value = value("-t")?;
+                let secs: f64 = value
+                    .parse()
+                    .map_err(|_| ParseError(format!("invalid timeout `{}`", value)))?;
+                if !(secs > 0.0 && secs.is_finite()) {
+                    return Err(ParseError(format!("invalid timeout `{}`", value)));
+                }
+                config.timeout = Duration::from_secs_f64(secs);
+            }
+            "-g" => {
+                let value = value("-g")?;
+                next_hop = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid MAC address `{}`", value)))?,
+                );
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => {
+                target = Some(
+                    arg.parse()
+                        .map_err(|_| ParseError(format!("invalid IPv4 address `{}`", arg)))?,
+                )
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(TraceOptions {
+        target: target.ok_or_else(|| ParseError("trace requires a target".to_owned()))?,
+        next_hop,
+        config,
+    })
+}
+
+fn parse_serve_dhcp<I: Iterator<Item = String>>(
+    mut args: I,
+) -> Result<dhcp::ServerConfig, ParseError> {
+    let mut config: dhcp::ServerConfig = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let invalid = |what: &str, value: &str| ParseError(format!("invalid {} `{}`", what, value));
+        match arg.as_str() {
+            "-p" => config.pool = Some(parse_network(&value("-p")?)?),
+            "-r" => {
+                let value = value("-r")?;
+                config.router = Some(value.parse().map_err(|_| invalid("IPv4 address", &value))?);
+            }
+            "-d" => {
+                let value = value("-d")?;
+                config
+                    .dns_servers
+                    .push(value.parse().map_err(|_| invalid("IPv4 address", &value))?);
+            }
+            "-l" => {
+                let value = value("-l")?;
+                config.lease_time = match value.parse() {
+                    Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                    _ => return Err(invalid("lease time", &value)),
+                };
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(config)
+}
+
+fn parse_dhcp_starve<I: Iterator<Item = String>>(mut args: I) -> Result<dhcp::Config, ParseError> {
+    let mut config: dhcp::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let invalid = |what: &str, value: &str| ParseError(format!("invalid {} `{}`", what, value));
+        match arg.as_str() {
+            "-r" => {
+                let value = value("-r")?;
+                let rate: f64 = value.parse().map_err(|_| invalid("rate", &value))?;
+                if !(rate > 0.0 && rate.is_finite()) {
+                    return Err(invalid("rate", &value));
+                }
+                config.rate = rate;
+            }
+            "-c" => {
+                let value = value("-c")?;
+                // 0 keeps going until interrupted
+                config.count = match value.parse().map_err(|_| invalid("count", &value))? {
+                    0 => None,
+                    count => Some(count),
+                };
+            }
+            "-a" => {
+                let value = value("-a")?;
+                config
+                    .allowed_servers
+                    .push(value.parse().map_err(|_| invalid("IPv4 address", &value))?);
+            }
+            "-s" => {
+                let value = value("-s")?;
+                config.seed = Some(value.parse().map_err(|_| invalid("seed", &value))?);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(config)
+}
+
+fn parse_dns_monitor<I: Iterator<Item = String>>(mut args: I) -> Result<dns::Config, ParseError> {
+    let mut config: dns::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut ttl = |name: &str| {
+            let value = args
+                .next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))?;
+            value
+                .parse()
+                .map_err(|_| ParseError(format!("invalid TTL `{}`", value)))
+        };
+        match arg.as_str() {
+            "--min-ttl" => config.min_ttl = ttl("--min-ttl")?,
+            "--max-ttl" => config.max_ttl = ttl("--max-ttl")?,
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+    Ok(config)
+}
+
+fn parse_syn_flood<I: Iterator<Item = String>>(mut args: I) -> Result<SynFloodOptions, ParseError> {
+    let mut target = None;
+    let mut next_hop = None;
+    let mut config: synflood::Config = Default::default();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let invalid = |what: &str, value: &str| ParseError(format!("invalid {} `{}`", what, value));
+        match arg.as_str() {
+            "-a" => config.allowed.push(parse_network(&value("-a")?)?),
+            "-g" => {
+                let value = value("-g")?;
+                next_hop = Some(value.parse().map_err(|_| invalid("MAC address", &value))?);
+            }
+            "-p" => config.ports = ports::parse_ports(&value("-p")?)?,
+            "-r" => {
+                let value = value("-r")?;
+                let rate: f64 = value.parse().map_err(|_| invalid("rate", &value))?;
+                if !(rate > 0.0 && rate <= synflood::MAX_RATE) {
+                    return Err(ParseError(format!(
+                        "rate must be between 0 and {}",
+                        synflood::MAX_RATE
+                    )));
+                }
+                config.rate = rate;
+            }
+            "-c" => {
+                let value = value("-c")?;
+                // 0 keeps going until interrupted
+                config.count = match value.parse().map_err(|_| invalid("count", &value))? {
+                    0 => None,
+                    count => Some(count),
+                };
+            }
+            "-s" => {
+                let value = value("-s")?;
+                config.seed = Some(value.parse().map_err(|_| invalid("seed", &value))?);
+            }
+            "--random-ports" => config.source = synflood::Source::RandomPort,
+            "--random-src" => {
+                config.source =
+                    synflood::Source::RandomAddress(parse_network(&value("--random-src")?)?);
+            }
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => {
+                target = Some(arg.parse().map_err(|_| invalid("IPv4 address", &arg))?)
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+
+    let target = target.ok_or_else(|| ParseError("syn-flood requires a target".to_owned()))?;
+    let next_hop = next_hop.ok_or_else(|| {
+        ParseError("syn-flood requires the next hop's MAC address (-g)".to_owned())
+    })?;
+    if config.allowed.is_empty() {
+        return Err(ParseError(
+            "syn-flood requires the networks it may target (-a)".to_owned(),
+        ));
+    }
+    synflood::validate(target, &config).map_err(|e| ParseError(e.to_string()))?;
+    Ok(SynFloodOptions {
+        target,
+        next_hop,
+        config,
+    })
+}
+
+fn parse_vlan_hop<I: Iterator<Item = String>>(mut args: I) -> Result<VlanHopOptions, ParseError> {
+    let mut target = None;
+    let mut monitor = None;
+    let mut native_vlan = None;
+    let mut victim_vlan = None;
+    let mut target_mac = None;
+    let mut count = None;
+    let mut dtp = false;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+        };
+        let vlan = |value: String| match value.parse() {
+            Ok(vlan) if vlan > 0 && vlan < 4095 => Ok(vlan),
+            _ => Err(ParseError(format!("invalid VLAN ID `{}`", value))),
+        };
+        match arg.as_str() {
+            "-n" => native_vlan = Some(vlan(value("-n")?)?),
+            "-v" => victim_vlan = Some(vlan(value("-v")?)?),
+            "-m" => monitor = Some(value("-m")?),
+            "-g" => {
+                let value = value("-g")?;
+                target_mac = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid MAC address `{}`", value)))?,
+                );
+            }
+            "-c" => {
+                let value = value("-c")?;
+                count = Some(
+                    value
+                        .parse()
+                        .map_err(|_| ParseError(format!("invalid count `{}`", value)))?,
+                );
+            }
+            "--dtp" => dtp = true,
+            _ if arg.starts_with('-') => {
+                return Err(ParseError(format!("unknown option `{}`", arg)))
+            }
+            _ if target.is_none() => {
+                target = Some(
+                    arg.parse()
+                        .map_err(|_| ParseError(format!("invalid IPv4 address `{}`", arg)))?,
+                )
+            }
+            _ => return Err(ParseError(format!("unexpected `{}`", arg))),
+        }
+    }
+
+    let target = target.ok_or_else(|| ParseError("vlan-hop requires a target".to_owned()))?;
+    let mut config = vlanhop::Config::new(target);
+    config.native_vlan = native_vlan.unwrap_or(config.native_vlan);
+    config.victim_vlan = victim_vlan
+        .ok_or_else(|| ParseError("vlan-hop requires the victim VLAN (-v)".to_owned()))?;
+    if config.native_vlan == config.victim_vlan {
+        return Err(ParseError(
+            "the victim VLAN must differ from the native VLAN".to_owned(),
+        ));
+    }
+    config.target_mac = target_mac.unwrap_or(config.target_mac);
+    config.count = count.unwrap_or(config.count);
+    if dtp {
+        config.variants.push(vlanhop::Variant::Dtp);
+    }
+    Ok(VlanHopOptions { monitor, config })
+}
+
+/// Parse an IPv4 network like `192.168.0.0/24`. A bare address is a /32.
+fn parse_network(value: &str) -> Result<Ipv4Cidr, ParseError> {
+    value
+        .parse()
+        .map_err(|_| ParseError(format!("invalid network `{}`", value)))
+}
+
+fn single_operand<I: Iterator<Item = String>>(
+    mut args: I,
+    command: &str,
+) -> Result<String, ParseError> {
+    match (args.next(), args.next()) {
+        (Some(operand), None) if !operand.starts_with('-') => Ok(operand),
+        (None, _) => Err(ParseError(format!("{} requires an argument", command))),
+        (Some(operand), _) => Err(ParseError(format!("unexpected `{}`", operand))),
+    }
+}
+
+/// Run `command` until it completes or `stop` is set.
+///
+/// With `common.metrics` set, the latencies and counters the command records, like
+/// `PING_RTT` and `ARP_RESOLUTION`, are served in the Prometheus format while it runs.
+pub fn run(common: &Common, command: &Command, stop: &AtomicBool) -> io::Result<()> {
+    let metrics = Metrics::new();
+    let address = match common.metrics {
+        Some(address) => address,
+        None => return execute(common, command, &metrics, stop),
+    };
+    let listener = TcpListener::bind(address)?;
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let exporter = scope.spawn(|| metrics::serve(&listener, &metrics, &done));
+        let result = execute(common, command, &metrics, stop);
+        done.store(true, Ordering::SeqCst);
+        // the command failing is what matters most
+        result.and(exporter.join().unwrap())
+    })
+}
+
+fn execute(
+    common: &Common,
+    command: &Command,
+    metrics: &Metrics,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    match command {
+        Command::Help => {
+            print!("{}", help());
+            Ok(())
+        }
+        Command::Sniff(options) => {
+            let mut options = options.clone();
+            if options.interface.is_none() {
+                options.interface = common.interface.clone();
+            }
+            options.quiet |= common.quiet;
+            options.json |= common.json;
+            if let Some(color) = common.color {
+                options.color = color;
+            }
+            #[cfg(target_os = "linux")]
+            if let (false, Some(name)) = (common.quiet, options.interface.as_deref()) {
+                // best effort, interfaces without ethtool support have nothing to warn about
+                if let Ok(offloads) = offload::query(name) {
+                    for warning in offloads.warnings() {
+                        eprintln!("{}: {}", name, warning);
+                    }
+                }
+            }
+            let count = sniff::run(&options, stop)?;
+            if !common.quiet {
+                eprintln!("{} packets captured", count);
+            }
+            Ok(())
+        }
+        Command::Arping(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            match arping(&interface, options, common.quiet, metrics, stop)? {
+                0 => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no reply from {}", options.target),
+                )),
+                _ => Ok(()),
+            }
+        }
+        Command::Wol(target) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            wol::wake(&interface, *target)?;
+            if !common.quiet {
+                println!("Sent magic packet to {} on {}", target, interface.name);
+            }
+            Ok(())
+        }
+        Command::Generate(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let mut tx = match channel(&interface, Default::default())? {
+                Channel::Ethernet(tx, _) => shaped(tx, options.shaping),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            let mut generator = Generator::new(options.template.clone());
+            for (field, rule) in &options.rules {
+                generator = generator.rule(*field, *rule);
+            }
+            if options.source_mac != SourceMac::Keep {
+                generator = generator.source_mac(options.source_mac);
+            }
+            if let Some(seed) = options.seed {
+                generator = generator.seed(seed);
+            }
+            let sent = generate::run(&mut *tx, &mut generator, &options.config, stop)?;
+            if !common.quiet {
+                eprintln!("{} frames sent", sent);
+            }
+            Ok(())
+        }
+        Command::Replay(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let mut tx = match channel(&interface, Default::default())? {
+                Channel::Ethernet(tx, _) => shaped(tx, options.shaping),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            let sent = replay::replay_file(&mut *tx, &options.path, &options.config, stop)?;
+            if !common.quiet {
+                eprintln!("{} frames sent", sent);
+            }
+            Ok(())
+        }
+        Command::Perf(PerfOptions::UdpClient(config)) => {
+            println!("{}", perf::run_udp_client(config, stop)?);
+            Ok(())
+        }
+        Command::Perf(PerfOptions::TcpClient(config)) => {
+            println!("{}", perf::run_tcp_client(config, stop)?);
+            Ok(())
+        }
+        Command::Perf(PerfOptions::UdpServer(local)) => {
+            let stack = perf_stack(common, *local)?;
+            perf::serve_udp(&stack, *local, stop, |client, report| {
+                if !common.quiet {
+                    println!("{}: {}", client, report);
+                }
+            })
+        }
+        Command::Perf(PerfOptions::TcpServer(local)) => {
+            let stack = perf_stack(common, *local)?;
+            perf::serve_tcp(&stack, local.port(), stop, |client, report| {
+                if !common.quiet {
+                    println!("{}: {}", client, report);
+                }
+            })
+        }
+        Command::Perf(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let local = interface.mac.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("interface {} has no MAC address", interface.name),
+                )
+            })?;
+            let config = Config {
+                read_buffer_size: 65536,
+                // wake up regularly to notice `stop` and the end of the test
+                read_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            match options {
+                PerfOptions::Client(config) => {
+                    let report = perf::run_client(&mut *tx, &mut *rx, local, config, stop)?;
+                    println!("{}", report);
+                    Ok(())
+                }
+                // the Ethernet server, the other modes are run above
+                _ => {
+                    if !common.quiet {
+                        println!("Listening on {} ({})", interface.name, local);
+                    }
+                    perf::serve(&mut *tx, &mut *rx, local, stop, |client, report| {
+                        if !common.quiet {
+                            println!("{}: {}", client, report);
+                        }
+                    })
+                }
+            }
+        }
+        Command::Portscan(options) => {
+            let results = match (options.mode, options.target, options.next_hop) {
+                (ports::Mode::Syn, IpAddr::V4(target), Some(next_hop)) => {
+                    let interface = select_interface(common.interface.as_deref())?;
+                    let source = interface_ipv4_mac(&interface)?;
+                    let config = Config {
+                        // wake up regularly to notice `stop` and probe timeouts
+                        read_timeout: Some(Duration::from_millis(50)),
+                        ..Default::default()
+                    };
+                    let (mut tx, mut rx) = match channel(&interface, config)? {
+                        Channel::Ethernet(tx, rx) => (tx, rx),
+                        _ => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::Other,
+                                "Unknown channel type",
+                            ))
+                        }
+                    };
+                    let target = ports::SynTarget {
+                        source,
+                        target,
+                        next_hop,
+                    };
+                    ports::syn_scan(
+                        &mut *tx,
+                        &mut *rx,
+                        &target,
+                        &options.ports,
+                        &options.config,
+                        stop,
+                    )?
+                }
+                (ports::Mode::Syn, _, _) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "a SYN scan needs an IPv4 target and a next hop",
+                    ))
+                }
+                (ports::Mode::Connect, target, _) => {
+                    ports::connect_scan(target, &options.ports, &options.config)
+                }
+                (ports::Mode::Udp, target, _) => {
+                    ports::udp_scan(target, &options.ports, &options.config)
+                }
+            };
+
+            for result in &results {
+                // closed ports are usually the bulk of the list; quiet lists only open ones
+                let shown = match result.state {
+                    ports::PortState::Open => true,
+                    ports::PortState::Closed => false,
+                    _ => !common.quiet,
+                };
+                if !shown {
+                    continue;
+                }
+                match result.rtt {
+                    Some(rtt) => println!(
+                        "{:<6} {:<14} {:.3} ms",
+                        result.port,
+                        result.state,
+                        rtt.as_secs_f64() * 1e3
+                    ),
+                    None => println!("{:<6} {}", result.port, result.state),
+                }
+            }
+            Ok(())
+        }
+        Command::Discover(options) => {
+            let json = options.json || common.json;
+            let interface = select_interface(common.interface.as_deref())?;
+            let source = interface_ipv4_mac(&interface)?;
+            let network = options
+                .network
+                .unwrap_or_else(|| Ipv4Cidr::new(source.ip, 24));
+            let channel_config = Config {
+                read_buffer_size: 65536,
+                // ARP requests are sent between reads
+                read_timeout: Some(Duration::from_millis(10)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, channel_config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            if !common.quiet && !json {
+                eprintln!(
+                    "Sweeping {} on {}, then listening for {} s",
+                    network,
+                    interface.name,
+                    options.config.listen.as_secs()
+                );
+            }
+            let inventory =
+                discover::discover(&mut *tx, &mut *rx, source, network, &options.config, stop)?;
+            if json {
+                println!("{}", inventory.to_json());
+            } else {
+                for neighbor in inventory.neighbors() {
+                    println!("{}", neighbor);
+                }
+                for host in inventory.hosts() {
+                    println!("{}", host);
+                }
+            }
+            Ok(())
+        }
+        Command::Scan(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let network = match options.network {
+                Some(network) => network,
+                None => Ipv4Cidr::new(interface_ipv4_mac(&interface)?.ip, 24),
+            };
+            if !common.quiet {
+                eprintln!("Scanning {} on {}", network, interface.name);
+            }
+            let hosts = arp_scan::arp_scan_with(&interface, network, &options.config)?;
+            for (ip, mac) in &hosts {
+                println!("{:<15} {}", ip, mac);
+            }
+            if !common.quiet {
+                eprintln!("{} hosts up", hosts.len());
+            }
+            Ok(())
+        }
+        Command::Ping(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let target = ports::SynTarget {
+                source: interface_ipv4_mac(&interface)?,
+                target: options.target,
+                next_hop: match options.next_hop {
+                    Some(next_hop) => next_hop,
+                    None => resolve_next_hop(&interface, options.target, metrics)?,
+                },
+            };
+            let config = Config {
+                // wake up regularly to notice `stop` and the reply timeout
+                read_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            if !common.quiet {
+                println!(
+                    "PING {} from {} {}",
+                    options.target, target.source.ip, interface.name
+                );
+            }
+            let report = ping::ping(
+                &mut *tx,
+                &mut *rx,
+                &target,
+                &options.config,
+                stop,
+                |reply| {
+                    metrics.record(PING_RTT, reply.rtt);
+                    if !common.quiet {
+                        println!("{}", reply);
+                    }
+                },
+            )?;
+            println!("{}", report);
+            match report.received {
+                0 => Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("no reply from {}", options.target),
+                )),
+                _ => Ok(()),
+            }
+        }
+        Command::Trace(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let target = ports::SynTarget {
+                source: interface_ipv4_mac(&interface)?,
+                target: options.target,
+                next_hop: match options.next_hop {
+                    Some(next_hop) => next_hop,
+                    None => resolve_next_hop(&interface, options.target, metrics)?,
+                },
+            };
+            let config = Config {
+                // wake up regularly to notice `stop` and the probe timeout
+                read_timeout: Some(Duration::from_millis(50)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            if !common.quiet {
+                println!(
+                    "trace to {}, {} hops max",
+                    options.target, options.config.max_hops
+                );
+            }
+            ping::trace(&mut *tx, &mut *rx, &target, &options.config, stop, |hop| {
+                println!("{}", hop)
+            })?;
+            Ok(())
+        }
+        Command::ServeDhcp(config) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let mac = interface.mac.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("interface {} has no MAC address", interface.name),
+                )
+            })?;
+            let address = interface
+                .addresses
+                .iter()
+                .find_map(|address| match address {
+                    IpCidr::V4(address) => Some(*address),
+                    IpCidr::V6(_) => None,
+                })
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("interface {} has no IPv4 address", interface.name),
+                    )
+                })?;
+            let channel_config = Config {
+                read_buffer_size: 65536,
+                // wake up regularly to notice `stop`
+                read_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, channel_config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            if !common.quiet && !common.json {
+                eprintln!("Serving DHCP on {} as {}", interface.name, address);
+            }
+            let mut server = dhcp::Server::new(mac, address, config.clone());
+            server.serve(&mut *tx, &mut *rx, stop, |reply| {
+                if common.json {
+                    println!("{}", reply.to_json());
+                } else if !common.quiet {
+                    println!("{}", reply);
+                }
+            })
+        }
+        Command::DhcpStarve(config) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let channel_config = Config {
+                read_buffer_size: 65536,
+                // DISCOVERs are sent between reads
+                read_timeout: Some(Duration::from_millis(10)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, channel_config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            let report = dhcp::starve(&mut *tx, &mut *rx, config, stop, |offer| {
+                // rogue servers are the finding, report them even when quiet
+                if offer.rogue || (offer.ours && !common.quiet) {
+                    println!("{}", offer);
+                }
+            })?;
+            println!("{}", report);
+            Ok(())
+        }
+        Command::DnsMonitor(config) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let channel_config = Config {
+                read_buffer_size: 65536,
+                // wake up regularly to notice `stop`
+                read_timeout: Some(Duration::from_millis(100)),
+                ..Default::default()
+            };
+            let mut rx = match channel(&interface, channel_config)? {
+                Channel::Ethernet(_, rx) => rx,
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            if !common.quiet {
+                println!("Watching DNS traffic on {}", interface.name);
+            }
+            dns::monitor(&mut *rx, *config, stop, |event| {
+                println!("{} {}", sniff::format_timestamp(event.time), event)
+            })
+        }
+        Command::SynFlood(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let target = ports::SynTarget {
+                source: interface_ipv4_mac(&interface)?,
+                target: options.target,
+                next_hop: options.next_hop,
+            };
+            let mut tx = match channel(&interface, Default::default())? {
+                Channel::Ethernet(tx, _) => tx,
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            let report = synflood::run(&mut *tx, &target, &options.config, stop)?;
+            if !common.quiet {
+                eprintln!("{}", report);
+            }
+            Ok(())
+        }
+        Command::VlanHop(options) => {
+            let interface = select_interface(common.interface.as_deref())?;
+            let source = interface_ipv4_mac(&interface)?;
+            let channel_config = Config {
+                read_buffer_size: 65536,
+                // probes are sent between reads
+                read_timeout: Some(Duration::from_millis(10)),
+                ..Default::default()
+            };
+            let (mut tx, mut rx) = match channel(&interface, channel_config)? {
+                Channel::Ethernet(tx, rx) => (tx, rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            };
+            let mut monitor = match &options.monitor {
+                Some(name) => match channel(&select_interface(Some(name))?, channel_config)? {
+                    Channel::Ethernet(_, rx) => Some(rx),
+                    _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+                },
+                None => None,
+            };
+            let report = vlanhop::run(
+                &mut *tx,
+                &mut *rx,
+                monitor.as_mut().map(|rx| &mut **rx as _),
+                source,
+                &options.config,
+                stop,
+            )?;
+            println!("{}", report);
+            Ok(())
+        }
+    }
+}
+
+/// `tx`, wrapped in a shaper if a limit is given.
+fn shaped(
+    tx: Box<dyn EthernetDataLinkSender>,
+    shaping: Option<shape::Config>,
+) -> Box<dyn EthernetDataLinkSender> {
+    match shaping {
+        Some(config) => Box::new(shape::ShapingSender::new(tx, config)),
+        None => tx,
+    }
+}
+
+/// MAC address and first IPv4 address of `interface`.
+fn interface_ipv4_mac(interface: &NetworkInterface) -> io::Result<ports::Ipv4Mac> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("interface {} has no {}", interface.name, what),
+        )
+    };
+    Ok(ports::Ipv4Mac {
+        mac: interface.mac.ok_or_else(|| invalid("MAC address"))?,
+        ip: interface
+            .ips
+            .iter()
+            .flatten()
+            .find_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(*ip),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or_else(|| invalid("IPv4 address"))?,
+    })
+}
+
+/// The userspace stack of a `myox perf` server over UDP or TCP, owning the address of
+/// `local`, which the host shouldn't use itself.
+fn perf_stack(common: &Common, local: SocketAddrV4) -> io::Result<Interface> {
+    let mut interface = select_interface(common.interface.as_deref())?;
+    interface.ips = Some(vec![IpAddr::V4(*local.ip())]);
+    let stack = Interface::open_with_backend(&Native::default(), &interface)?;
+    if !common.quiet {
+        println!("Listening on {} ({})", interface.name, local);
+    }
+    Ok(stack)
+}
+
+/// MAC address frames from `interface` to `target` are sent to: the target's if it is on
+/// a network of the interface, the gateway's of the route to it otherwise. The time the
+/// answer took is recorded in `metrics` as [`ARP_RESOLUTION`].
+fn resolve_next_hop(
+    interface: &NetworkInterface,
+    target: Ipv4Addr,
+    metrics: &Metrics,
+) -> io::Result<MacAddr> {
+    let on_link = interface
+        .addresses
+        .iter()
+        .any(|network| network.contains(IpAddr::V4(target)));
+    let hop = if on_link {
+        IpAddr::V4(target)
+    } else {
+        gateway(interface, target)?
+    };
+    let cache = NeighborCache::default().with_metrics(metrics, "arp");
+    neighbor::Resolver::open(interface, Default::default())?
+        .with_cache(Arc::new(cache))
+        .resolve(hop)
+}
+
+#[cfg(target_os = "linux")]
+fn gateway(interface: &NetworkInterface, target: Ipv4Addr) -> io::Result<IpAddr> {
+    match routes::system_routes()?.next_hop(IpAddr::V4(target)) {
+        Some((hop, _)) => Ok(hop),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no route to {} from {}", target, interface.name),
+        )),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn gateway(interface: &NetworkInterface, target: Ipv4Addr) -> io::Result<IpAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "{} is not on a network of {}, give the gateway's MAC address with -g",
+            target, interface.name
+        ),
+    ))
+}
+
+/// Name of the histogram `arping` records round trip times in.
+pub const ARPING_RTT: &str = "arping.rtt";
+
+/// Send ARP requests for `options.target` and report the replies.
+///
+/// Round trip times are recorded in `metrics` as `ARPING_RTT`. Returns the number of
+/// probes that were answered.
+pub fn arping(
+    interface: &NetworkInterface,
+    options: &ArpingOptions,
+    quiet: bool,
+    metrics: &Metrics,
+    stop: &AtomicBool,
+) -> io::Result<usize> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("interface {} has no {}", interface.name, what),
+        )
+    };
+    let source_mac = interface.mac.ok_or_else(|| invalid("MAC address"))?;
+    let source_ip = interface
+        .ips
+        .iter()
+        .flatten()
+        .find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(*ip),
+            IpAddr::V6(_) => None,
+        })
+        .ok_or_else(|| invalid("IPv4 address"))?;
+
+    let config = Config {
+        // wake up regularly to notice `stop` and the probe timeout
+        read_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let (mut tx, mut rx) = match channel(interface, config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+    };
+
+    let request = build_arp_packet(
+        MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+        source_mac,
+        source_ip,
+        MacAddr::new(0, 0, 0, 0, 0, 0),
+        options.target,
+        ArpOperations::Request,
+    );
+    let request = EthernetPacket::new(&request[..]).unwrap();
+
+    if !quiet {
+        println!(
+            "ARPING {} from {} {}",
+            options.target, source_ip, interface.name
+        );
+    }
+
+    let _span = trace_span!(DEBUG, "arping", target = %options.target);
+    let mut iter = rx.iter();
+    let mut sent = 0;
+    let mut answered = 0;
+    while !stop.load(Ordering::SeqCst) && options.count.map_or(true, |count| sent < count) {
+        tx.send_to(&request, None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+        sent += 1;
+        trace_event!(debug, probe = sent, "sent ARP request");
+
+        let probed = Instant::now();
+        let mut replied = false;
+        while probed.elapsed() < options.timeout && !stop.load(Ordering::SeqCst) {
+            let packet = match iter.next() {
+                Ok(packet) => packet,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            if replied || packet.get_ethertype() != EtherTypes::Arp {
+                continue;
+            }
+            let arp = match ArpPacket::new(packet.payload()) {
+                Some(arp) => arp,
+                None => continue,
+            };
+            if arp.get_operation() != ArpOperations::Reply
+                || arp.get_sender_proto_addr() != options.target
+            {
+                continue;
+            }
+            replied = true;
+            answered += 1;
+            let rtt = probed.elapsed();
+            metrics.record(ARPING_RTT, rtt);
+            trace_event!(
+                debug,
+                mac = %arp.get_sender_hw_addr(),
+                rtt_us = rtt.as_micros() as u64,
+                "resolved"
+            );
+            if !quiet {
+                println!(
+                    "Unicast reply from {} [{}]  {}.{:03}ms",
+                    options.target,
+                    arp.get_sender_hw_addr(),
+                    rtt.as_millis(),
+                    rtt.subsec_micros() % 1000
+                );
+            }
+        }
+    }
+
+    if answered == 0 {
+        trace_event!(info, probes = sent, "no ARP reply");
+    }
+    if !quiet {
+        println!("Sent {} probes, received {} responses", sent, answered);
+        if let Some(summary) = metrics.summary(ARPING_RTT) {
+            println!("rtt {}", summary);
+        }
+    }
+    Ok(answered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, ParseError> {
+        parse(args.iter().map(|arg| arg.to_string())).map(|(_, command)| command)
+    }
+
+    #[test]
+    fn parses_shared_options() {
+        let args = [
+            "-i",
+            "eth1",
+            "-q",
+            "--json",
+            "--no-color",
+            "-m",
+            "127.0.0.1:9100",
+            "help",
+        ];
+        let (common, command) = parse(args.iter().map(|arg| arg.to_string())).unwrap();
+        assert_eq!(common.interface.as_deref(), Some("eth1"));
+        assert!(common.quiet);
+        assert!(common.json);
+        assert_eq!(common.color, Some(ColorMode::Never));
+        assert_eq!(common.metrics, Some("127.0.0.1:9100".parse().unwrap()));
+        assert!(matches!(command, Command::Help));
+
+        assert!(parse_args(&["-m", "9100", "help"]).is_err());
+        assert!(parse_args(&["-m"]).is_err());
+    }
+
+    #[test]
+    fn parses_scan_ping_and_trace() {
+        match parse_args(&["scan", "10.0.0.0/28", "-r", "50", "-w", "0.5"]).unwrap() {
+            Command::Scan(options) => {
+                assert_eq!(options.network, Some("10.0.0.0/28".parse().unwrap()));
+                assert_eq!(options.config.rate, 50.0);
+                assert_eq!(options.config.wait, Duration::from_millis(500));
+            }
+            command => panic!("{:?}", command),
+        }
+
+        match parse_args(&["ping", "192.0.2.1", "-c", "3", "-s", "100"]).unwrap() {
+            Command::Ping(options) => {
+                assert_eq!(options.target, Ipv4Addr::new(192, 0, 2, 1));
+                assert_eq!(options.next_hop, None);
+                assert_eq!(options.config.count, Some(3));
+                assert_eq!(options.config.payload_len, 100);
+            }
+            command => panic!("{:?}", command),
+        }
+        assert!(parse_args(&["ping"]).is_err());
+        assert!(parse_args(&["ping", "192.0.2.1", "-s", "9000"]).is_err());
+
+        match parse_args(&["trace", "192.0.2.1", "-m", "8", "-g", "02:00:00:00:00:fe"]).unwrap() {
+            Command::Trace(options) => {
+                assert_eq!(options.config.max_hops, 8);
+                assert_eq!(options.next_hop, Some(MacAddr(2, 0, 0, 0, 0, 0xfe)));
+            }
+            command => panic!("{:?}", command),
+        }
+        assert!(parse_args(&["trace", "192.0.2.1", "-m", "0"]).is_err());
+    }
+
+    #[test]
+    fn parses_perf_modes() {
+        match parse_args(&["perf", "-m", "udp", "192.0.2.1", "-l", "100"]).unwrap() {
+            Command::Perf(PerfOptions::UdpClient(config)) => {
+                assert_eq!(config.server, "192.0.2.1:5201".parse().unwrap());
+                assert_eq!(config.size, 100);
+            }
+            command => panic!("{:?}", command),
+        }
+        match parse_args(&["perf", "-m", "tcp", "-s", "-a", "10.0.0.9", "-p", "9000"]).unwrap() {
+            Command::Perf(PerfOptions::TcpServer(local)) => {
+                assert_eq!(local, "10.0.0.9:9000".parse().unwrap());
+            }
+            command => panic!("{:?}", command),
+        }
+        assert!(parse_args(&["perf", "-m", "udp", "-s"]).is_err());
+        assert!(parse_args(&["perf", "-m", "udp", "192.0.2.1", "-l", "9000"]).is_err());
+        assert!(parse_args(&["perf", "-s", "-a", "10.0.0.9"]).is_err());
+    }
+
+    #[test]
+    fn every_listed_command_is_known() {
+        for (name, _) in COMMANDS {
+            if let Err(ParseError(message)) = parse_args(&[name]) {
+                assert!(!message.starts_with("unknown command"), "{}", name);
+            }
+        }
+        let unknown = parse_args(&["serve-ftp"]).unwrap_err();
+        assert_eq!(unknown.0, "unknown command `serve-ftp`");
+    }
+
+    #[test]
+    fn parses_serve_dhcp() {
+        let args = [
+            "serve-dhcp",
+            "-p",
+            "10.0.0.128/25",
+            "-r",
+            "10.0.0.1",
+            "-d",
+            "10.0.0.53",
+        ];
+        match parse_args(&args).unwrap() {
+            Command::ServeDhcp(config) => {
+                assert_eq!(config.pool, Some("10.0.0.128/25".parse().unwrap()));
+                assert_eq!(config.router, Some(Ipv4Addr::new(10, 0, 0, 1)));
+                assert_eq!(config.dns_servers, [Ipv4Addr::new(10, 0, 0, 53)]);
+                assert_eq!(config.lease_time, Duration::from_secs(3600));
+            }
+            command => panic!("{:?}", command),
+        }
+        assert!(parse_args(&["serve-dhcp", "-l", "0"]).is_err());
+        assert!(parse_args(&["serve-dhcp", "-r", "gateway"]).is_err());
+    }
+}
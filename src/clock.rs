@@ -0,0 +1,85 @@
+//! Time sources.
+//!
+//! Components with timers read the time from a [`Clock`] instead of `Instant::now`, so
+//! that a [`MockClock`] can drive them: its time only moves when told to, which makes
+//! expiry and timeouts deterministic.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A source of time.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The current time.
+    fn now(&self) -> Instant;
+
+    /// Block for `duration`.
+    fn sleep(&self, duration: Duration);
+
+    /// Block until `deadline`, returning at once if it has passed.
+    fn sleep_until(&self, deadline: Instant) {
+        let now = self.now();
+        if deadline > now {
+            self.sleep(deadline - now);
+        }
+    }
+}
+
+/// The system's monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A clock that stands still until advanced. Sleeping advances it by the time slept,
+/// without blocking.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+}
+
+impl MockClock {
+    /// A clock starting at the current time.
+    pub fn new() -> MockClock {
+        MockClock {
+            now: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Move the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+/// The clock components use unless given another one.
+pub fn system() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
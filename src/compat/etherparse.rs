@@ -0,0 +1,153 @@
+//! etherparse interoperability.
+//!
+//! Lets analysis code written against etherparse consume frames captured through myox
+//! channels, and turns etherparse headers back into myox packets.
+
+use crate::arp::{
+    ether::{strip_trailer, EtherType, Ethernet, EthernetPacket, Packet},
+    network_interface::MacAddr,
+};
+use ::etherparse::{
+    EtherType as EpEtherType, Ethernet2Header, Ethernet2HeaderSlice, PacketHeaders, ReadError,
+    SerializedSize, SlicedPacket,
+};
+use std::convert::TryFrom;
+
+fn mac_from_bytes(bytes: &[u8]) -> MacAddr {
+    MacAddr::new(bytes[0], bytes[1], bytes[2], bytes[3], bytes[4], bytes[5])
+}
+
+fn mac_to_bytes(mac: MacAddr) -> [u8; 6] {
+    [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5]
+}
+
+impl From<EpEtherType> for EtherType {
+    fn from(ethertype: EpEtherType) -> EtherType {
+        EtherType::new(ethertype as u16)
+    }
+}
+
+impl TryFrom<EtherType> for EpEtherType {
+    type Error = EtherType;
+
+    /// Fails, returning the input, for ethertypes etherparse has no variant for.
+    fn try_from(ethertype: EtherType) -> Result<EpEtherType, EtherType> {
+        EpEtherType::from_u16(ethertype.value()).ok_or(ethertype)
+    }
+}
+
+impl<'p> From<&EthernetPacket<'p>> for Ethernet2Header {
+    fn from(packet: &EthernetPacket<'p>) -> Ethernet2Header {
+        Ethernet2Header {
+            source: mac_to_bytes(packet.get_source()),
+            destination: mac_to_bytes(packet.get_destination()),
+            ether_type: packet.get_ethertype().value(),
+        }
+    }
+}
+
+impl From<&Ethernet> for Ethernet2Header {
+    fn from(ethernet: &Ethernet) -> Ethernet2Header {
+        Ethernet2Header {
+            source: mac_to_bytes(ethernet.source),
+            destination: mac_to_bytes(ethernet.destination),
+            ether_type: ethernet.ethertype.value(),
+        }
+    }
+}
+
+impl<'a> From<&Ethernet2HeaderSlice<'a>> for Ethernet {
+    /// The payload is left empty, a header slice doesn't reference it.
+    fn from(slice: &Ethernet2HeaderSlice<'a>) -> Ethernet {
+        Ethernet {
+            destination: mac_from_bytes(slice.destination()),
+            source: mac_from_bytes(slice.source()),
+            ethertype: EtherType::new(slice.ether_type()),
+            payload: Vec::new(),
+        }
+    }
+}
+
+/// Slice a captured frame with etherparse. The Ethernet padding of short packets is
+/// left out, so that it doesn't end up in their payload.
+pub fn to_sliced<'a>(packet: &'a EthernetPacket) -> Result<SlicedPacket<'a>, ReadError> {
+    SlicedPacket::from_ethernet(strip_trailer(packet.packet()))
+}
+
+/// Decode all headers of a captured frame with etherparse, leaving out the Ethernet
+/// padding of short packets.
+pub fn to_headers<'a>(packet: &'a EthernetPacket) -> Result<PacketHeaders<'a>, ReadError> {
+    PacketHeaders::from_ethernet_slice(strip_trailer(packet.packet()))
+}
+
+/// Build an owned myox packet from an etherparse header and a payload.
+pub fn to_packet(header: &Ethernet2Header, payload: &[u8]) -> EthernetPacket<'static> {
+    let mut buffer = Vec::with_capacity(Ethernet2Header::SERIALIZED_SIZE + payload.len());
+    // writing into a Vec cannot fail
+    header.write(&mut buffer).unwrap();
+    buffer.extend_from_slice(payload);
+    EthernetPacket::owned(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arp::ether::EtherTypes, generate};
+    use ::etherparse::{InternetSlice, TransportSlice};
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const SOURCE: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+    const DESTINATION: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 2);
+
+    // a short datagram, padded to the 60 bytes of a minimum Ethernet frame
+    fn padded() -> Vec<u8> {
+        let from = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000);
+        let to = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 53);
+        let mut frame = generate::udp_frame(SOURCE, DESTINATION, from, to, b"hi");
+        frame.resize(60, 0);
+        frame
+    }
+
+    #[test]
+    fn ethertypes_convert() {
+        assert_eq!(EtherType::from(EpEtherType::Ipv6), EtherTypes::Ipv6);
+        assert_eq!(EpEtherType::try_from(EtherTypes::Arp), Ok(EpEtherType::Arp));
+        let unknown = EtherType::new(0x88b5);
+        assert_eq!(EpEtherType::try_from(unknown), Err(unknown));
+    }
+
+    #[test]
+    fn padding_is_left_out() {
+        let frame = padded();
+        let packet = EthernetPacket::new(&frame).unwrap();
+        let sliced = to_sliced(&packet).unwrap();
+        match sliced.ip {
+            Some(InternetSlice::Ipv4(ip)) => {
+                assert_eq!(ip.source_addr(), Ipv4Addr::new(10, 0, 0, 1))
+            }
+            _ => panic!("no IPv4 header"),
+        }
+        match sliced.transport {
+            Some(TransportSlice::Udp(udp)) => assert_eq!(udp.destination_port(), 53),
+            _ => panic!("no UDP header"),
+        }
+        assert_eq!(sliced.payload, b"hi");
+        assert_eq!(to_headers(&packet).unwrap().payload, b"hi");
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let frame = padded();
+        let packet = EthernetPacket::new(&frame).unwrap();
+        let header = Ethernet2Header::from(&packet);
+        assert_eq!(header.source, mac_to_bytes(SOURCE));
+        assert_eq!(header.ether_type, EtherTypes::Ipv4.value());
+        let rebuilt = to_packet(&header, packet.payload());
+        assert_eq!(rebuilt.packet(), &frame[..]);
+
+        let slice = Ethernet2HeaderSlice::from_slice(&frame).unwrap();
+        let ethernet = Ethernet::from(&slice);
+        assert_eq!(ethernet.destination, DESTINATION);
+        assert_eq!(Ethernet2Header::from(&ethernet), header);
+    }
+}
@@ -0,0 +1,7 @@
+//! Conversions between myox types and the equivalent types of other networking crates.
+
+pub mod etherparse;
+#[cfg(feature = "pnet-compat")]
+pub mod pnet;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod smoltcp;
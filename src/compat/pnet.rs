@@ -0,0 +1,156 @@
+//! pnet interoperability, enabled by the `pnet-compat` feature.
+//!
+//! Much of the datalink code started as a port of pnet, so the types map one to one.
+//! Packets are converted by borrowing the same buffer, no bytes are copied.
+
+use crate::{
+    arp::{
+        ether::{EtherType, EthernetPacket, Packet},
+        network_interface::{LinkType, MacAddr, NetworkInterface, OperState},
+    },
+    cidr::IpCidr,
+};
+use ::pnet::{
+    datalink::NetworkInterface as PnetNetworkInterface,
+    ipnetwork::IpNetwork,
+    packet::{
+        ethernet::{EtherType as PnetEtherType, EthernetPacket as PnetEthernetPacket},
+        Packet as PnetPacket,
+    },
+    util::MacAddr as PnetMacAddr,
+};
+
+impl From<MacAddr> for PnetMacAddr {
+    fn from(mac: MacAddr) -> PnetMacAddr {
+        PnetMacAddr::new(mac.0, mac.1, mac.2, mac.3, mac.4, mac.5)
+    }
+}
+
+impl From<PnetMacAddr> for MacAddr {
+    fn from(mac: PnetMacAddr) -> MacAddr {
+        MacAddr::new(mac.0, mac.1, mac.2, mac.3, mac.4, mac.5)
+    }
+}
+
+impl From<EtherType> for PnetEtherType {
+    fn from(ethertype: EtherType) -> PnetEtherType {
+        PnetEtherType::new(ethertype.value())
+    }
+}
+
+impl From<PnetEtherType> for EtherType {
+    fn from(ethertype: PnetEtherType) -> EtherType {
+        EtherType::new(ethertype.0)
+    }
+}
+
+impl From<NetworkInterface> for PnetNetworkInterface {
+    fn from(interface: NetworkInterface) -> PnetNetworkInterface {
+        PnetNetworkInterface {
+            name: interface.name,
+            index: interface.index,
+            mac: interface.mac.map(Into::into),
+            // the prefix lengths are always in range, `IpCidr` checked them
+            ips: interface
+                .addresses
+                .iter()
+                .map(|cidr| IpNetwork::new(cidr.address(), cidr.prefix_len()).unwrap())
+                .collect(),
+            flags: interface.flags,
+            description: String::new(),
+        }
+    }
+}
+
+impl From<PnetNetworkInterface> for NetworkInterface {
+    fn from(interface: PnetNetworkInterface) -> NetworkInterface {
+        NetworkInterface {
+            name: interface.name,
+            index: interface.index,
+            mac: interface.mac.map(Into::into),
+            addresses: interface
+                .ips
+                .iter()
+                .map(|network| IpCidr::new(network.ip(), network.prefix()))
+                .collect(),
+            ips: Some(interface.ips.iter().map(IpNetwork::ip).collect()),
+            flags: interface.flags,
+            // pnet doesn't tell the link
+            mtu: None,
+            oper_state: OperState::Unknown,
+            link_type: LinkType::VOID,
+        }
+    }
+}
+
+impl<'a, 'p> From<&'a EthernetPacket<'p>> for PnetEthernetPacket<'a> {
+    fn from(packet: &'a EthernetPacket<'p>) -> PnetEthernetPacket<'a> {
+        // both types accept any buffer of at least 14 bytes
+        PnetEthernetPacket::new(Packet::packet(packet)).unwrap()
+    }
+}
+
+impl<'a, 'p> From<&'a PnetEthernetPacket<'p>> for EthernetPacket<'a> {
+    fn from(packet: &'a PnetEthernetPacket<'p>) -> EthernetPacket<'a> {
+        EthernetPacket::new(PnetPacket::packet(packet)).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arp::ether::EtherTypes;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn interfaces_round_trip() {
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let v6 = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+        let interface = NetworkInterface {
+            name: "eth0".to_owned(),
+            index: 2,
+            mac: Some(MacAddr::new(2, 0, 0, 0, 0, 1)),
+            ips: Some(vec![v4, v6]),
+            flags: 0x1003,
+            mtu: Some(1500),
+            oper_state: OperState::Up,
+            link_type: LinkType::ETHER,
+            addresses: vec![IpCidr::new(v4, 24), IpCidr::new(v6, 64)],
+        };
+
+        let pnet = PnetNetworkInterface::from(interface.clone());
+        assert_eq!(pnet.mac, Some(PnetMacAddr::new(2, 0, 0, 0, 0, 1)));
+        assert_eq!(
+            pnet.ips,
+            [
+                IpNetwork::new(v4, 24).unwrap(),
+                IpNetwork::new(v6, 64).unwrap()
+            ]
+        );
+
+        let back = NetworkInterface::from(pnet);
+        assert_eq!(back.name, interface.name);
+        assert_eq!(back.index, interface.index);
+        assert_eq!(back.mac, interface.mac);
+        assert_eq!(back.ips, interface.ips);
+        assert_eq!(back.addresses, interface.addresses);
+        assert_eq!(back.flags, interface.flags);
+        assert_eq!(back.mtu, None);
+    }
+
+    #[test]
+    fn packets_share_their_buffer() {
+        let mut frame = vec![0u8; 60];
+        frame[12..14].copy_from_slice(&[0x08, 0x06]);
+        let packet = EthernetPacket::new(&frame).unwrap();
+        let pnet = PnetEthernetPacket::from(&packet);
+        assert_eq!(EtherType::from(pnet.get_ethertype()), EtherTypes::Arp);
+        assert_eq!(PnetPacket::packet(&pnet).as_ptr(), frame.as_ptr());
+        let back = EthernetPacket::from(&pnet);
+        assert_eq!(back.get_ethertype(), EtherTypes::Arp);
+        assert_eq!(
+            PnetEtherType::from(EtherTypes::Ipv4),
+            ::pnet::packet::ethernet::EtherTypes::Ipv4
+        );
+    }
+}
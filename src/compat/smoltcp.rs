@@ -0,0 +1,430 @@
+//! smoltcp interoperability.
+//!
+//! Besides the wire type conversions this module provides [`ChannelDevice`], a smoltcp
+//! `phy::Device` running over a myox datalink channel, so a smoltcp `EthernetInterface`
+//! can be driven by any of the crate's channel backends.
+
+use crate::arp::{
+    arp_new::{Arp, ArpHardwareType, ArpHardwareTypes, ArpOperation, ArpPacket},
+    channel::{Channel, EthernetDataLinkReceiver, EthernetDataLinkSender},
+    ether::{EtherType, EtherTypes, EthernetPacket, Packet},
+    network_interface::MacAddr,
+};
+use ::smoltcp::{
+    phy::{self, DeviceCapabilities},
+    time::Instant,
+    wire::{
+        ArpHardware, ArpOperation as SmolArpOperation, ArpRepr, EthernetAddress, EthernetProtocol,
+        EthernetRepr,
+    },
+};
+use std::{convert::TryFrom, io};
+
+const LOG_TARGET: &str = "myox::compat::smoltcp";
+
+impl From<MacAddr> for EthernetAddress {
+    fn from(mac: MacAddr) -> EthernetAddress {
+        EthernetAddress([mac.0, mac.1, mac.2, mac.3, mac.4, mac.5])
+    }
+}
+
+impl From<EthernetAddress> for MacAddr {
+    fn from(addr: EthernetAddress) -> MacAddr {
+        let [a, b, c, d, e, f] = addr.0;
+        MacAddr::new(a, b, c, d, e, f)
+    }
+}
+
+impl From<EtherType> for EthernetProtocol {
+    fn from(ethertype: EtherType) -> EthernetProtocol {
+        EthernetProtocol::from(ethertype.value())
+    }
+}
+
+impl From<EthernetProtocol> for EtherType {
+    fn from(protocol: EthernetProtocol) -> EtherType {
+        EtherType::new(u16::from(protocol))
+    }
+}
+
+impl From<ArpOperation> for SmolArpOperation {
+    fn from(operation: ArpOperation) -> SmolArpOperation {
+        SmolArpOperation::from(operation.0)
+    }
+}
+
+impl From<SmolArpOperation> for ArpOperation {
+    fn from(operation: SmolArpOperation) -> ArpOperation {
+        ArpOperation::new(u16::from(operation))
+    }
+}
+
+impl From<ArpHardwareType> for ArpHardware {
+    fn from(hardware: ArpHardwareType) -> ArpHardware {
+        ArpHardware::from(hardware.0)
+    }
+}
+
+impl From<ArpHardware> for ArpHardwareType {
+    fn from(hardware: ArpHardware) -> ArpHardwareType {
+        ArpHardwareType::new(u16::from(hardware))
+    }
+}
+
+impl<'p> From<&EthernetPacket<'p>> for EthernetRepr {
+    fn from(packet: &EthernetPacket<'p>) -> EthernetRepr {
+        EthernetRepr {
+            src_addr: packet.get_source().into(),
+            dst_addr: packet.get_destination().into(),
+            ethertype: packet.get_ethertype().into(),
+        }
+    }
+}
+
+impl<'p> TryFrom<&ArpPacket<'p>> for ArpRepr {
+    type Error = ::smoltcp::Error;
+
+    /// Fails with `Error::Unrecognized` unless the packet is Ethernet/IPv4 ARP, the only
+    /// kind smoltcp represents.
+    fn try_from(packet: &ArpPacket<'p>) -> Result<ArpRepr, Self::Error> {
+        if packet.get_hardware_type() != ArpHardwareTypes::Ethernet
+            || packet.get_protocol_type() != EtherTypes::Ipv4
+            || packet.get_hw_addr_len() != 6
+            || packet.get_proto_addr_len() != 4
+        {
+            return Err(::smoltcp::Error::Unrecognized);
+        }
+        Ok(ArpRepr::EthernetIpv4 {
+            operation: packet.get_operation().into(),
+            source_hardware_addr: packet.get_sender_hw_addr().into(),
+            source_protocol_addr: packet.get_sender_proto_addr().into(),
+            target_hardware_addr: packet.get_target_hw_addr().into(),
+            target_protocol_addr: packet.get_target_proto_addr().into(),
+        })
+    }
+}
+
+impl TryFrom<ArpRepr> for Arp {
+    type Error = ::smoltcp::Error;
+
+    fn try_from(repr: ArpRepr) -> Result<Arp, Self::Error> {
+        match repr {
+            ArpRepr::EthernetIpv4 {
+                operation,
+                source_hardware_addr,
+                source_protocol_addr,
+                target_hardware_addr,
+                target_protocol_addr,
+            } => Ok(Arp {
+                hardware_type: ArpHardwareTypes::Ethernet,
+                protocol_type: EtherTypes::Ipv4,
+                hw_addr_len: 6,
+                proto_addr_len: 4,
+                operation: operation.into(),
+                sender_hw_addr: source_hardware_addr.into(),
+                sender_proto_addr: source_protocol_addr.into(),
+                target_hw_addr: target_hardware_addr.into(),
+                target_proto_addr: target_protocol_addr.into(),
+                payload: Vec::new(),
+            }),
+            _ => Err(::smoltcp::Error::Unrecognized),
+        }
+    }
+}
+
+/// A smoltcp `Device` backed by a myox datalink channel.
+///
+/// smoltcp polls its device, so the receiver should be opened with a short
+/// `read_timeout`: every receive timeout is reported to smoltcp as "no frame available".
+/// smoltcp can't be told of other receive errors, so they are logged and kept for
+/// [`ChannelDevice::take_error`].
+pub struct ChannelDevice {
+    tx: Box<dyn EthernetDataLinkSender>,
+    rx: Box<dyn EthernetDataLinkReceiver>,
+    mtu: usize,
+    error: Option<io::Error>,
+}
+
+impl ChannelDevice {
+    /// Wrap the two halves of a channel. `mtu` is the maximum frame size including the
+    /// Ethernet header, 1514 for regular Ethernet.
+    pub fn new(
+        tx: Box<dyn EthernetDataLinkSender>,
+        rx: Box<dyn EthernetDataLinkReceiver>,
+        mtu: usize,
+    ) -> ChannelDevice {
+        ChannelDevice {
+            tx,
+            rx,
+            mtu,
+            error: None,
+        }
+    }
+
+    /// Wrap an Ethernet `Channel`.
+    pub fn from_channel(channel: Channel, mtu: usize) -> io::Result<ChannelDevice> {
+        match channel {
+            Channel::Ethernet(tx, rx) => Ok(ChannelDevice::new(tx, rx, mtu)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Unknown channel type",
+            )),
+        }
+    }
+
+    /// Return the last receive error other than a timeout, clearing it.
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Return the channel halves.
+    pub fn into_inner(
+        self,
+    ) -> (
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        (self.tx, self.rx)
+    }
+}
+
+impl<'a> phy::Device<'a> for ChannelDevice {
+    type RxToken = RxToken;
+    type TxToken = TxToken<'a>;
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let frame = match self.rx.iter().next() {
+            Ok(packet) => packet.packet().to_vec(),
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return None
+            }
+            Err(e) => {
+                log::warn!(target: LOG_TARGET, "receive failed: {}", e);
+                self.error = Some(e);
+                return None;
+            }
+        };
+        Some((RxToken(frame), TxToken { tx: &mut *self.tx }))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        Some(TxToken { tx: &mut *self.tx })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.max_transmission_unit = self.mtu;
+        caps
+    }
+}
+
+/// A received frame handed to smoltcp.
+pub struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> ::smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> ::smoltcp::Result<R>,
+    {
+        f(&mut self.0[..])
+    }
+}
+
+/// Permission to send one frame over the channel.
+pub struct TxToken<'a> {
+    tx: &'a mut dyn EthernetDataLinkSender,
+}
+
+impl<'a> phy::TxToken for TxToken<'a> {
+    fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> ::smoltcp::Result<R>
+    where
+        F: FnOnce(&mut [u8]) -> ::smoltcp::Result<R>,
+    {
+        let mut buffer = vec![0u8; len];
+        let result = f(&mut buffer[..])?;
+        let packet = EthernetPacket::new(&buffer[..]).ok_or(::smoltcp::Error::Truncated)?;
+        match self.tx.send_to(&packet, None) {
+            Some(Ok(())) => Ok(result),
+            _ => Err(::smoltcp::Error::Exhausted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::{arp_new::ArpOperations, channel::Config, other::build_arp_packet},
+        pcap::{OfflineReceiver, Reader, Writer},
+        sim::{Network, NodeId},
+    };
+    use ::smoltcp::{
+        iface::{EthernetInterfaceBuilder, NeighborCache},
+        phy::{Device, RxToken as _, TxToken as _},
+        socket::SocketSet,
+        wire::{IpCidr, Ipv4Address},
+    };
+    use std::{collections::BTreeMap, net::Ipv4Addr};
+
+    const SMOLTCP: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+    const PEER: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 2);
+
+    fn open(
+        network: &Network,
+        node: NodeId,
+    ) -> (
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        match network.channel(node, Config::default()).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        }
+    }
+
+    // a device on one node of a network, and the channel of the node linked to it
+    fn link() -> (
+        ChannelDevice,
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        let network = Network::new(1);
+        let device = network.add_node("smoltcp", SMOLTCP);
+        let peer = network.add_node("peer", PEER);
+        network.link(device, peer, Default::default());
+        let channel = network.channel(device, Config::default()).unwrap();
+        let (tx, rx) = open(&network, peer);
+        (ChannelDevice::from_channel(channel, 1514).unwrap(), tx, rx)
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let mac = MacAddr::new(2, 0xab, 3, 4, 5, 6);
+        assert_eq!(MacAddr::from(EthernetAddress::from(mac)), mac);
+        assert_eq!(
+            EthernetProtocol::from(EtherTypes::Arp),
+            EthernetProtocol::Arp
+        );
+        assert_eq!(EtherType::from(EthernetProtocol::Ipv6), EtherTypes::Ipv6);
+        assert_eq!(
+            ArpOperation::from(SmolArpOperation::from(ArpOperations::Reply)),
+            ArpOperations::Reply
+        );
+
+        let frame = build_arp_packet(
+            SMOLTCP,
+            PEER,
+            Ipv4Addr::new(10, 0, 0, 2),
+            SMOLTCP,
+            Ipv4Addr::new(10, 0, 0, 1),
+            ArpOperations::Reply,
+        );
+        let ethernet = EthernetPacket::new(&frame).unwrap();
+        let repr = EthernetRepr::from(&ethernet);
+        assert_eq!(repr.src_addr, EthernetAddress::from(PEER));
+        assert_eq!(repr.ethertype, EthernetProtocol::Arp);
+        let arp = ArpPacket::new(ethernet.payload()).unwrap();
+        let repr = ArpRepr::try_from(&arp).unwrap();
+        let back = Arp::try_from(repr).unwrap();
+        assert_eq!(back.operation, ArpOperations::Reply);
+        assert_eq!(back.sender_hw_addr, PEER);
+        assert_eq!(back.target_proto_addr, Ipv4Addr::new(10, 0, 0, 1));
+    }
+
+    #[test]
+    fn frames_cross_the_device() {
+        let (mut device, mut tx, mut rx) = link();
+        assert!(device.receive().is_none());
+
+        let frame = build_arp_packet(
+            SMOLTCP,
+            PEER,
+            Ipv4Addr::new(10, 0, 0, 2),
+            SMOLTCP,
+            Ipv4Addr::new(10, 0, 0, 1),
+            ArpOperations::Reply,
+        );
+        tx.send_to(&EthernetPacket::new(&frame).unwrap(), None);
+        let (token, _) = device.receive().unwrap();
+        let received = token
+            .consume(Instant::from_millis(0), |buffer| Ok(buffer.to_vec()))
+            .unwrap();
+        assert_eq!(received, frame);
+
+        device
+            .transmit()
+            .unwrap()
+            .consume(Instant::from_millis(0), frame.len(), |buffer| {
+                buffer.copy_from_slice(&frame);
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(rx.iter().next().unwrap().packet(), &frame[..]);
+        assert!(device.take_error().is_none());
+    }
+
+    #[test]
+    fn smoltcp_answers_over_a_channel() {
+        let (device, mut tx, mut rx) = link();
+        let mut addresses = [IpCidr::new(Ipv4Address::new(10, 0, 0, 1).into(), 24)];
+        let mut interface = EthernetInterfaceBuilder::new(device)
+            .ethernet_addr(SMOLTCP.into())
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(&mut addresses[..])
+            .finalize();
+        let mut sockets = SocketSet::new(Vec::new());
+
+        tx.send_to(
+            &EthernetPacket::new(&build_arp_packet(
+                MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+                PEER,
+                Ipv4Addr::new(10, 0, 0, 2),
+                MacAddr::new(0, 0, 0, 0, 0, 0),
+                Ipv4Addr::new(10, 0, 0, 1),
+                ArpOperations::Request,
+            ))
+            .unwrap(),
+            None,
+        );
+        interface
+            .poll(&mut sockets, Instant::from_millis(0))
+            .unwrap();
+
+        let mut iter = rx.iter();
+        let reply = iter.next().unwrap();
+        assert_eq!(reply.get_destination(), PEER);
+        let arp = ArpPacket::new(reply.payload()).unwrap();
+        match ArpRepr::try_from(&arp).unwrap() {
+            ArpRepr::EthernetIpv4 {
+                operation,
+                source_hardware_addr,
+                source_protocol_addr,
+                ..
+            } => {
+                assert_eq!(operation, SmolArpOperation::Reply);
+                assert_eq!(source_hardware_addr, SMOLTCP.into());
+                assert_eq!(source_protocol_addr, Ipv4Address::new(10, 0, 0, 1));
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn receive_errors_are_kept() {
+        let mut file = Vec::new();
+        Writer::new(&mut file).unwrap();
+        let rx = OfflineReceiver::new(Reader::new(io::Cursor::new(file)).unwrap()).unwrap();
+        let network = Network::new(1);
+        let node = network.add_node("smoltcp", SMOLTCP);
+        let (tx, _) = open(&network, node);
+        let mut device = ChannelDevice::new(tx, Box::new(rx), 1514);
+
+        // the capture is over, which smoltcp only sees as nothing received
+        assert!(device.receive().is_none());
+        let error = device.take_error().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(device.take_error().is_none());
+    }
+}
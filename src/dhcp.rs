@@ -0,0 +1,1029 @@
+//! DHCP client messages, a small server and a pool exhaustion tester.
+//!
+//! [`discover_frame`] builds a broadcast DHCPDISCOVER and [`Message`] decodes the replies.
+//! [`Server`] hands out the addresses of a network to the clients on a link.
+//! [`starve`] is a lab tool: it sends DISCOVERs from random client MAC addresses to find out
+//! whether a server's address pool can be exhausted, and at the same time watches every
+//! OFFER on the link to spot servers that are not supposed to be there. Only run it on
+//! networks you are responsible for.
+
+use crate::{
+    arp::{
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    cidr::Ipv4Cidr,
+    clock::{Clock, SystemClock},
+    flows::FlowPacket,
+    generate,
+    ipproto::IPPROTO_UDP,
+    rng::Rng,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+/// UDP port of DHCP servers.
+pub const SERVER_PORT: u16 = 67;
+
+/// UDP port of DHCP clients.
+pub const CLIENT_PORT: u16 = 68;
+
+/// DHCP message types (option 53).
+pub mod message_types {
+    /// Client looking for servers
+    pub const DISCOVER: u8 = 1;
+    /// Server offering an address
+    pub const OFFER: u8 = 2;
+    /// Client requesting an offered address
+    pub const REQUEST: u8 = 3;
+    /// Client declining an address already in use
+    pub const DECLINE: u8 = 4;
+    /// Server confirming a lease
+    pub const ACK: u8 = 5;
+    /// Server refusing a request
+    pub const NAK: u8 = 6;
+    /// Client giving up its lease
+    pub const RELEASE: u8 = 7;
+}
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Fixed part of a BOOTP message, up to and including the magic cookie.
+const FIXED_LEN: usize = 240;
+/// Smallest BOOTP message relays and old servers accept.
+const MIN_MESSAGE_LEN: usize = 300;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVERS: u8 = 6;
+const OPTION_REQUESTED_ADDR: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_PARAMETER_REQUEST: u8 = 55;
+const OPTION_END: u8 = 255;
+
+/// Build a broadcast DHCPDISCOVER frame from `client` with transaction ID `xid`.
+pub fn discover_frame(client: MacAddr, xid: u32) -> Vec<u8> {
+    client_frame(client, xid, message_types::DISCOVER, &[])
+}
+
+// A broadcast message of `message_type` from `client`, carrying `options` after the
+// message type and the parameter request list.
+fn client_frame(client: MacAddr, xid: u32, message_type: u8, options: &[u8]) -> Vec<u8> {
+    let mut bootp = vec![0u8; FIXED_LEN];
+    bootp[0] = BOOTREQUEST;
+    bootp[1] = HTYPE_ETHERNET;
+    bootp[2] = 6;
+    bootp[4..8].copy_from_slice(&xid.to_be_bytes());
+    // ask for broadcast replies, the client has no address yet
+    bootp[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+    bootp[28..34].copy_from_slice(&[client.0, client.1, client.2, client.3, client.4, client.5]);
+    bootp[236..240].copy_from_slice(&MAGIC_COOKIE);
+    bootp.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+    // subnet mask, router, DNS servers, lease time, server identifier
+    bootp.extend_from_slice(&[OPTION_PARAMETER_REQUEST, 5, 1, 3, 6, 51, 54]);
+    bootp.extend_from_slice(options);
+    bootp.push(OPTION_END);
+    if bootp.len() < MIN_MESSAGE_LEN {
+        bootp.resize(MIN_MESSAGE_LEN, OPTION_PAD);
+    }
+
+    generate::udp_frame(
+        client,
+        MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+        SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, CLIENT_PORT),
+        SocketAddrV4::new(Ipv4Addr::BROADCAST, SERVER_PORT),
+        &bootp,
+    )
+}
+
+/// The fields of a DHCP message the tester looks at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    /// Whether the message was sent by a server
+    pub reply: bool,
+    /// Transaction ID
+    pub xid: u32,
+    /// Whether the client asked for broadcast replies
+    pub broadcast: bool,
+    /// Address the client already has, unspecified if none
+    pub client_addr: Ipv4Addr,
+    /// Address offered or assigned to the client
+    pub your_addr: Ipv4Addr,
+    /// Client hardware address
+    pub client_mac: MacAddr,
+    /// Message type (option 53)
+    pub message_type: Option<u8>,
+    /// Server identifier (option 54)
+    pub server_id: Option<Ipv4Addr>,
+    /// Address the client asks for (option 50)
+    pub requested_addr: Option<Ipv4Addr>,
+    /// Lease time in seconds (option 51)
+    pub lease_time: Option<u32>,
+}
+
+impl Message {
+    /// Decode the BOOTP message carried by a UDP datagram.
+    pub fn parse(data: &[u8]) -> Option<Message> {
+        if data.len() < FIXED_LEN || data[236..240] != MAGIC_COOKIE {
+            return None;
+        }
+        let addr = |offset: usize| {
+            Ipv4Addr::new(
+                data[offset],
+                data[offset + 1],
+                data[offset + 2],
+                data[offset + 3],
+            )
+        };
+        let mut message = Message {
+            reply: data[0] == BOOTREPLY,
+            xid: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            broadcast: u16::from_be_bytes([data[10], data[11]]) & FLAG_BROADCAST != 0,
+            client_addr: addr(12),
+            your_addr: addr(16),
+            client_mac: MacAddr::new(data[28], data[29], data[30], data[31], data[32], data[33]),
+            message_type: None,
+            server_id: None,
+            requested_addr: None,
+            lease_time: None,
+        };
+
+        let mut options = &data[FIXED_LEN..];
+        while let Some((&code, rest)) = options.split_first() {
+            match code {
+                OPTION_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPTION_END => break,
+                _ => {}
+            }
+            let len = *rest.first()? as usize;
+            let value = rest.get(1..1 + len)?;
+            match (code, len) {
+                (OPTION_MESSAGE_TYPE, 1) => message.message_type = Some(value[0]),
+                (OPTION_SERVER_ID, 4) => {
+                    message.server_id = Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                }
+                (OPTION_REQUESTED_ADDR, 4) => {
+                    message.requested_addr =
+                        Some(Ipv4Addr::new(value[0], value[1], value[2], value[3]))
+                }
+                (OPTION_LEASE_TIME, 4) => {
+                    message.lease_time =
+                        Some(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+                }
+                _ => {}
+            }
+            options = &rest[1 + len..];
+        }
+        Some(message)
+    }
+}
+
+/// An OFFER seen on the link.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Offer {
+    /// Server identifier, or the source address if the option is missing
+    pub server: Ipv4Addr,
+    /// Ethernet address the offer came from
+    pub server_mac: MacAddr,
+    /// Address offered
+    pub offered: Ipv4Addr,
+    /// Client the offer is for
+    pub client_mac: MacAddr,
+    /// Whether the offer answers one of the tester's DISCOVERs
+    pub ours: bool,
+    /// Whether the server is not among the allowed ones
+    pub rogue: bool,
+}
+
+impl fmt::Display for Offer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "offer {} for {} from {} ({})",
+            self.offered, self.client_mac, self.server, self.server_mac
+        )?;
+        if self.rogue {
+            write!(f, " ROGUE")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parameters of a starvation test.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// DISCOVERs per second. Defaults to 50
+    pub rate: f64,
+
+    /// Number of DISCOVERs to send, None to send until stopped. Defaults to 1000
+    pub count: Option<u64>,
+
+    /// How long to keep listening for offers after the last DISCOVER. Defaults to 3 seconds
+    pub linger: Duration,
+
+    /// Servers allowed on the link; offers from others are flagged as rogue. When empty no
+    /// server is flagged. Defaults to empty
+    pub allowed_servers: Vec<Ipv4Addr>,
+
+    /// Seed of the client MAC addresses, None to seed from the clock. Defaults to None
+    pub seed: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rate: 50.0,
+            count: Some(1000),
+            linger: Duration::from_secs(3),
+            allowed_servers: Vec::new(),
+            seed: None,
+        }
+    }
+}
+
+/// Outcome of a starvation test.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// DISCOVERs sent
+    pub sent: u64,
+    /// OFFERs answering them
+    pub offers: u64,
+    /// Distinct addresses offered to the tester
+    pub addresses: BTreeSet<Ipv4Addr>,
+    /// Offers seen per server, all clients included
+    pub servers: BTreeMap<Ipv4Addr, u64>,
+    /// Servers that are not allowed
+    pub rogue_servers: BTreeSet<Ipv4Addr>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} discovers sent, {} offers received, {} distinct addresses",
+            self.sent,
+            self.offers,
+            self.addresses.len()
+        )?;
+        for (server, offers) in &self.servers {
+            let rogue = if self.rogue_servers.contains(server) {
+                " (rogue)"
+            } else {
+                ""
+            };
+            write!(f, "\n  server {}: {} offers{}", server, offers, rogue)?;
+        }
+        Ok(())
+    }
+}
+
+/// Send DISCOVERs from random clients on `tx` and collect the OFFERs received on `rx`.
+///
+/// `rx` should be configured with a short read timeout, around 10 milliseconds, as it is
+/// polled between DISCOVERs. `on_offer` is called for every OFFER seen on the link, the
+/// ones for other clients included.
+pub fn starve<F: FnMut(&Offer)>(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    config: &Config,
+    stop: &AtomicBool,
+    on_offer: F,
+) -> io::Result<Report> {
+    starve_with_clock(tx, rx, config, &SystemClock, stop, on_offer)
+}
+
+/// [`starve`], pacing the DISCOVERs and lingering as measured by `clock`.
+pub fn starve_with_clock<F: FnMut(&Offer)>(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    config: &Config,
+    clock: &dyn Clock,
+    stop: &AtomicBool,
+    mut on_offer: F,
+) -> io::Result<Report> {
+    let mut rng = Rng::seeded(config.seed);
+    // the transaction IDs of the tester share a random prefix to tell its offers apart
+    let xid_prefix = (rng.next_u64() as u32) & 0xffff_0000;
+    let rate = config.rate.max(0.001);
+
+    let mut report: Report = Default::default();
+    let start = clock.now();
+    let mut last_sent = start;
+    let mut iter = rx.iter();
+    while !stop.load(Ordering::SeqCst) {
+        let now = clock.now();
+        let done = config.count.map_or(false, |count| report.sent >= count);
+        if done && now.duration_since(last_sent) >= config.linger {
+            break;
+        }
+        let due = start + Duration::from_secs_f64(report.sent as f64 / rate);
+        if !done && now >= due {
+            let random = rng.next_u64().to_be_bytes();
+            // locally administered unicast addresses
+            let client = MacAddr::new(
+                (random[0] & 0xfe) | 0x02,
+                random[1],
+                random[2],
+                random[3],
+                random[4],
+                random[5],
+            );
+            let xid = xid_prefix | (report.sent as u32 & 0xffff);
+            let frame = discover_frame(client, xid);
+            tx.send_to(&EthernetPacket::new(&frame).unwrap(), None)
+                .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+            report.sent += 1;
+            last_sent = now;
+        }
+
+        let packet = match iter.next() {
+            Ok(packet) => packet,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        let parsed = match FlowPacket::parse(&packet) {
+            Some(parsed) if parsed.protocol == IPPROTO_UDP => parsed,
+            _ => continue,
+        };
+        if parsed.src.port() != SERVER_PORT || parsed.dst.port() != CLIENT_PORT {
+            continue;
+        }
+        let payload = &packet.packet()[parsed.payload_offset..][..parsed.payload_len];
+        let message = match Message::parse(payload) {
+            Some(message) if message.reply => message,
+            _ => continue,
+        };
+        if message.message_type != Some(message_types::OFFER) {
+            continue;
+        }
+
+        let server = message.server_id.unwrap_or(match parsed.src.ip() {
+            IpAddr::V4(addr) => addr,
+            IpAddr::V6(_) => continue,
+        });
+        let offer = Offer {
+            server,
+            server_mac: packet.get_source(),
+            offered: message.your_addr,
+            client_mac: message.client_mac,
+            ours: message.xid & 0xffff_0000 == xid_prefix,
+            rogue: !config.allowed_servers.is_empty() && !config.allowed_servers.contains(&server),
+        };
+        *report.servers.entry(server).or_insert(0) += 1;
+        if offer.rogue {
+            report.rogue_servers.insert(server);
+        }
+        if offer.ours {
+            report.offers += 1;
+            report.addresses.insert(offer.offered);
+        }
+        on_offer(&offer);
+    }
+    Ok(report)
+}
+
+/// How long an offered address is kept for the client before it may be offered to others.
+const OFFER_HOLD: Duration = Duration::from_secs(60);
+
+/// Parameters of a DHCP server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Network whose hosts are handed out, None for the network of the server. Defaults to
+    /// None
+    pub pool: Option<Ipv4Cidr>,
+
+    /// Router given to clients (option 3), None for none. Defaults to None
+    pub router: Option<Ipv4Addr>,
+
+    /// DNS servers given to clients (option 6). Defaults to empty
+    pub dns_servers: Vec<Ipv4Addr>,
+
+    /// How long clients may keep their address. Defaults to one hour
+    pub lease_time: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            pool: None,
+            router: None,
+            dns_servers: Vec::new(),
+            lease_time: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// A reply sent by a [`Server`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reply {
+    /// Frame carrying the reply
+    pub frame: Vec<u8>,
+    /// Message type of the reply: OFFER, ACK or NAK
+    pub message_type: u8,
+    /// Address offered or assigned, unspecified for a NAK
+    pub address: Ipv4Addr,
+    /// Client the reply is for
+    pub client_mac: MacAddr,
+}
+
+impl Reply {
+    fn kind(&self) -> &'static str {
+        match self.message_type {
+            message_types::OFFER => "offer",
+            message_types::ACK => "ack",
+            _ => "nak",
+        }
+    }
+
+    /// The reply as a JSON object on one line.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"type\":\"{}\",\"address\":\"{}\",\"client_mac\":\"{}\"}}",
+            self.kind(),
+            self.address,
+            self.client_mac
+        )
+    }
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.message_type == message_types::NAK {
+            return write!(f, "nak for {}", self.client_mac);
+        }
+        write!(
+            f,
+            "{} {} for {}",
+            self.kind(),
+            self.address,
+            self.client_mac
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Binding {
+    client: MacAddr,
+    expires: Instant,
+    // whether the client requested the address, rather than only being offered it
+    acked: bool,
+}
+
+/// A DHCP server handing out the host addresses of a network.
+///
+/// Each client is offered the address it had or asked for if it is free, otherwise the
+/// first free one. Addresses are kept until their lease expires or the client releases
+/// them, and declined ones are never offered again.
+#[derive(Clone, Debug)]
+pub struct Server {
+    mac: MacAddr,
+    address: Ipv4Cidr,
+    config: ServerConfig,
+    bindings: BTreeMap<Ipv4Addr, Binding>,
+    declined: BTreeSet<Ipv4Addr>,
+}
+
+impl Server {
+    /// A server sending from `mac` and identifying itself with the address of `address`,
+    /// whose prefix length is the one given to clients.
+    pub fn new(mac: MacAddr, address: Ipv4Cidr, config: ServerConfig) -> Server {
+        Server {
+            mac,
+            address,
+            config,
+            bindings: BTreeMap::new(),
+            declined: BTreeSet::new(),
+        }
+    }
+
+    /// The addresses assigned to clients, with the time their lease expires.
+    pub fn leases(&self) -> impl Iterator<Item = (Ipv4Addr, MacAddr, Instant)> + '_ {
+        self.bindings
+            .iter()
+            .filter(|(_, binding)| binding.acked)
+            .map(|(address, binding)| (*address, binding.client, binding.expires))
+    }
+
+    /// Answer the DHCP message carried by `packet`, if it is one for the server.
+    pub fn answer(&mut self, packet: &EthernetPacket, now: Instant) -> Option<Reply> {
+        if packet.get_source() == self.mac {
+            return None;
+        }
+        let parsed = match FlowPacket::parse(packet) {
+            Some(parsed) if parsed.protocol == IPPROTO_UDP => parsed,
+            _ => return None,
+        };
+        if parsed.dst.port() != SERVER_PORT {
+            return None;
+        }
+        let payload = &packet.packet()[parsed.payload_offset..][..parsed.payload_len];
+        let message = match Message::parse(payload) {
+            Some(message) if !message.reply => message,
+            _ => return None,
+        };
+        let client = message.client_mac;
+        match message.message_type? {
+            message_types::DISCOVER => {
+                let address = self
+                    .bindings
+                    .iter()
+                    .find(|(_, binding)| binding.client == client)
+                    .map(|(address, _)| *address)
+                    .into_iter()
+                    .chain(message.requested_addr)
+                    .find(|&address| self.available(address, client, now))
+                    .or_else(|| {
+                        self.pool()
+                            .hosts()
+                            .find(|&address| self.available(address, client, now))
+                    })?;
+                // a lease being renewed stays one
+                let leased = self.bindings.get(&address).map_or(false, |binding| {
+                    binding.client == client && binding.acked && binding.expires > now
+                });
+                if !leased {
+                    self.bind(address, client, now + OFFER_HOLD, false);
+                }
+                Some(self.reply(&message, message_types::OFFER, address))
+            }
+            message_types::REQUEST => {
+                if message
+                    .server_id
+                    .map_or(false, |id| id != self.address.address())
+                {
+                    // the client took the offer of another server
+                    self.bindings
+                        .retain(|_, binding| binding.client != client || binding.acked);
+                    return None;
+                }
+                let wanted = message.requested_addr.unwrap_or(message.client_addr);
+                if wanted.is_unspecified() {
+                    return None;
+                }
+                if !self.available(wanted, client, now) {
+                    return Some(self.reply(&message, message_types::NAK, Ipv4Addr::UNSPECIFIED));
+                }
+                self.bind(wanted, client, now + self.config.lease_time, true);
+                Some(self.reply(&message, message_types::ACK, wanted))
+            }
+            message_types::RELEASE => {
+                if self.owned_by(message.client_addr, client) {
+                    self.bindings.remove(&message.client_addr);
+                }
+                None
+            }
+            message_types::DECLINE => {
+                // someone else uses the address
+                let address = message.requested_addr?;
+                if self.owned_by(address, client) {
+                    self.bindings.remove(&address);
+                    self.declined.insert(address);
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Answer the clients on `rx` until `stop` is set.
+    ///
+    /// `rx` should be configured with a read timeout, for `stop` to be noticed. `on_reply`
+    /// is called for every reply sent.
+    pub fn serve<F: FnMut(&Reply)>(
+        &mut self,
+        tx: &mut dyn EthernetDataLinkSender,
+        rx: &mut dyn EthernetDataLinkReceiver,
+        stop: &AtomicBool,
+        on_reply: F,
+    ) -> io::Result<()> {
+        self.serve_with_clock(tx, rx, &SystemClock, stop, on_reply)
+    }
+
+    /// [`serve`](Server::serve), expiring leases as measured by `clock`.
+    pub fn serve_with_clock<F: FnMut(&Reply)>(
+        &mut self,
+        tx: &mut dyn EthernetDataLinkSender,
+        rx: &mut dyn EthernetDataLinkReceiver,
+        clock: &dyn Clock,
+        stop: &AtomicBool,
+        mut on_reply: F,
+    ) -> io::Result<()> {
+        let mut iter = rx.iter();
+        while !stop.load(Ordering::SeqCst) {
+            let packet = match iter.next() {
+                Ok(packet) => packet,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(reply) = self.answer(&packet, clock.now()) {
+                tx.send_to(&EthernetPacket::new(&reply.frame).unwrap(), None)
+                    .unwrap_or_else(|| {
+                        Err(io::Error::new(io::ErrorKind::Other, "Frame not sent"))
+                    })?;
+                on_reply(&reply);
+            }
+        }
+        Ok(())
+    }
+
+    // the network whose hosts are handed out
+    fn pool(&self) -> Ipv4Cidr {
+        self.config
+            .pool
+            .unwrap_or_else(|| Ipv4Cidr::new(self.address.network(), self.address.prefix_len()))
+    }
+
+    // whether `address` may be given to `client`
+    fn available(&self, address: Ipv4Addr, client: MacAddr, now: Instant) -> bool {
+        let pool = self.pool();
+        let in_pool = pool.contains(address)
+            && (pool.broadcast().is_none()
+                || (address != pool.network() && Some(address) != pool.broadcast()));
+        in_pool
+            && address != self.address.address()
+            && Some(address) != self.config.router
+            && !self.declined.contains(&address)
+            && self.bindings.get(&address).map_or(true, |binding| {
+                binding.client == client || binding.expires <= now
+            })
+    }
+
+    fn owned_by(&self, address: Ipv4Addr, client: MacAddr) -> bool {
+        self.bindings
+            .get(&address)
+            .map_or(false, |binding| binding.client == client)
+    }
+
+    // give `address` to `client` alone
+    fn bind(&mut self, address: Ipv4Addr, client: MacAddr, expires: Instant, acked: bool) {
+        self.bindings.retain(|_, binding| binding.client != client);
+        self.bindings.insert(
+            address,
+            Binding {
+                client,
+                expires,
+                acked,
+            },
+        );
+    }
+
+    fn reply(&self, request: &Message, message_type: u8, address: Ipv4Addr) -> Reply {
+        let client = request.client_mac;
+        let mut bootp = vec![0u8; FIXED_LEN];
+        bootp[0] = BOOTREPLY;
+        bootp[1] = HTYPE_ETHERNET;
+        bootp[2] = 6;
+        bootp[4..8].copy_from_slice(&request.xid.to_be_bytes());
+        if request.broadcast {
+            bootp[10..12].copy_from_slice(&FLAG_BROADCAST.to_be_bytes());
+        }
+        bootp[12..16].copy_from_slice(&request.client_addr.octets());
+        bootp[16..20].copy_from_slice(&address.octets());
+        bootp[20..24].copy_from_slice(&self.address.address().octets());
+        bootp[28..34]
+            .copy_from_slice(&[client.0, client.1, client.2, client.3, client.4, client.5]);
+        bootp[236..240].copy_from_slice(&MAGIC_COOKIE);
+        bootp.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_type]);
+        bootp.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+        bootp.extend_from_slice(&self.address.address().octets());
+        if message_type != message_types::NAK {
+            let lease_time = self.config.lease_time.as_secs().min(u64::from(u32::MAX)) as u32;
+            bootp.extend_from_slice(&[OPTION_LEASE_TIME, 4]);
+            bootp.extend_from_slice(&lease_time.to_be_bytes());
+            bootp.extend_from_slice(&[OPTION_SUBNET_MASK, 4]);
+            bootp.extend_from_slice(&self.address.netmask().octets());
+            if let Some(router) = self.config.router {
+                bootp.extend_from_slice(&[OPTION_ROUTER, 4]);
+                bootp.extend_from_slice(&router.octets());
+            }
+            // at most 63 servers fit the option
+            let dns_servers = &self.config.dns_servers[..self.config.dns_servers.len().min(63)];
+            if !dns_servers.is_empty() {
+                bootp.extend_from_slice(&[OPTION_DNS_SERVERS, 4 * dns_servers.len() as u8]);
+                for server in dns_servers {
+                    bootp.extend_from_slice(&server.octets());
+                }
+            }
+        }
+        bootp.push(OPTION_END);
+        if bootp.len() < MIN_MESSAGE_LEN {
+            bootp.resize(MIN_MESSAGE_LEN, OPTION_PAD);
+        }
+
+        // clients without an address can only take broadcasts, unless they say otherwise
+        let broadcast = MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff);
+        let (destination_mac, destination) =
+            if message_type == message_types::NAK || request.broadcast {
+                (broadcast, Ipv4Addr::BROADCAST)
+            } else if !request.client_addr.is_unspecified() {
+                (client, request.client_addr)
+            } else {
+                (client, address)
+            };
+        let frame = generate::udp_frame(
+            self.mac,
+            destination_mac,
+            SocketAddrV4::new(self.address.address(), SERVER_PORT),
+            SocketAddrV4::new(destination, CLIENT_PORT),
+            &bootp,
+        );
+        Reply {
+            frame,
+            message_type,
+            address,
+            client_mac: client,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::{
+            channel::{Channel, Config as ChannelConfig},
+            loopback::Loopback,
+        },
+        clock::MockClock,
+    };
+    use std::{sync::Arc, thread};
+
+    // the OFFER of `server` giving `offered` to the client of `discover`
+    fn offer_frame(discover: &Message, server: Ipv4Addr, offered: Ipv4Addr) -> Vec<u8> {
+        let mut bootp = vec![0u8; FIXED_LEN];
+        bootp[0] = BOOTREPLY;
+        bootp[1] = HTYPE_ETHERNET;
+        bootp[2] = 6;
+        bootp[4..8].copy_from_slice(&discover.xid.to_be_bytes());
+        bootp[16..20].copy_from_slice(&offered.octets());
+        let client = discover.client_mac;
+        bootp[28..34]
+            .copy_from_slice(&[client.0, client.1, client.2, client.3, client.4, client.5]);
+        bootp[236..240].copy_from_slice(&MAGIC_COOKIE);
+        bootp.extend_from_slice(&[OPTION_MESSAGE_TYPE, 1, message_types::OFFER]);
+        bootp.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+        bootp.extend_from_slice(&server.octets());
+        bootp.push(OPTION_END);
+
+        generate::udp_frame(
+            MacAddr::new(0x02, 0, 0, 0, 0, 1),
+            MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+            SocketAddrV4::new(server, SERVER_PORT),
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, CLIENT_PORT),
+            &bootp,
+        )
+    }
+
+    fn channel(
+        device: &Loopback,
+    ) -> (
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        let config = ChannelConfig {
+            read_timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        match device.channel(config).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn starve_collects_offers() {
+        let device = Loopback::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let server = Ipv4Addr::new(10, 0, 0, 1);
+
+        // a server handing out a fresh address for every DISCOVER
+        let (mut server_tx, mut server_rx) = channel(&device);
+        let server_stop = stop.clone();
+        let server_thread = thread::spawn(move || {
+            let mut leased = 0u8;
+            let mut iter = server_rx.iter();
+            while !server_stop.load(Ordering::SeqCst) {
+                let packet = match iter.next() {
+                    Ok(packet) => packet,
+                    Err(_) => continue,
+                };
+                let parsed = match FlowPacket::parse(&packet) {
+                    Some(parsed) if parsed.dst.port() == SERVER_PORT => parsed,
+                    _ => continue,
+                };
+                let payload = &packet.packet()[parsed.payload_offset..][..parsed.payload_len];
+                let discover = match Message::parse(payload) {
+                    Some(message) if message.message_type == Some(message_types::DISCOVER) => {
+                        message
+                    }
+                    _ => continue,
+                };
+                leased += 1;
+                let frame = offer_frame(&discover, server, Ipv4Addr::new(10, 0, 0, 100 + leased));
+                server_tx.send_to(&EthernetPacket::new(&frame).unwrap(), None);
+            }
+        });
+
+        let (mut tx, mut rx) = channel(&device);
+        let config = Config {
+            rate: 1000.0,
+            count: Some(5),
+            linger: Duration::from_millis(200),
+            allowed_servers: vec![Ipv4Addr::new(10, 0, 0, 2)],
+            seed: Some(1),
+        };
+        let mut seen = Vec::new();
+        let report = starve(
+            &mut *tx,
+            &mut *rx,
+            &config,
+            &AtomicBool::new(false),
+            |offer| seen.push(offer.clone()),
+        )
+        .unwrap();
+        stop.store(true, Ordering::SeqCst);
+        server_thread.join().unwrap();
+
+        assert_eq!(report.sent, 5);
+        assert_eq!(report.offers, 5);
+        assert_eq!(report.addresses.len(), 5);
+        assert_eq!(report.servers.get(&server), Some(&5));
+        assert!(report.rogue_servers.contains(&server));
+        assert!(seen.iter().all(|offer| offer.ours && offer.rogue));
+    }
+
+    // the REQUEST of `client` for `address` from `server`
+    fn request_frame(client: MacAddr, xid: u32, address: Ipv4Addr, server: Ipv4Addr) -> Vec<u8> {
+        let mut options = vec![OPTION_REQUESTED_ADDR, 4];
+        options.extend_from_slice(&address.octets());
+        options.extend_from_slice(&[OPTION_SERVER_ID, 4]);
+        options.extend_from_slice(&server.octets());
+        client_frame(client, xid, message_types::REQUEST, &options)
+    }
+
+    fn answer(server: &mut Server, frame: &[u8], now: Instant) -> Option<(Reply, Message)> {
+        let reply = server.answer(&EthernetPacket::new(frame).unwrap(), now)?;
+        let packet = EthernetPacket::new(&reply.frame).unwrap();
+        let parsed = FlowPacket::parse(&packet).unwrap();
+        assert_eq!(parsed.dst.port(), CLIENT_PORT);
+        let message =
+            Message::parse(&reply.frame[parsed.payload_offset..][..parsed.payload_len]).unwrap();
+        Some((reply, message))
+    }
+
+    #[test]
+    fn server_hands_out_leases() {
+        let own = Ipv4Addr::new(10, 0, 0, 1);
+        let config = ServerConfig {
+            router: Some(Ipv4Addr::new(10, 0, 0, 6)),
+            lease_time: Duration::from_secs(600),
+            ..Default::default()
+        };
+        let mut server = Server::new(
+            MacAddr::new(2, 0, 0, 0, 0, 1),
+            Ipv4Cidr::new(own, 29),
+            config,
+        );
+        let now = Instant::now();
+        let a = MacAddr::new(2, 0, 0, 0, 0, 0xa);
+        let b = MacAddr::new(2, 0, 0, 0, 0, 0xb);
+
+        let (reply, offer) = answer(&mut server, &discover_frame(a, 1), now).unwrap();
+        assert_eq!(reply.to_string(), "offer 10.0.0.2 for 02:00:00:00:00:0a");
+        assert!(offer.reply);
+        assert_eq!(offer.xid, 1);
+        assert_eq!(offer.message_type, Some(message_types::OFFER));
+        assert_eq!(offer.server_id, Some(own));
+        assert_eq!(offer.lease_time, Some(600));
+        // the offer is held for the client
+        let (_, offer) = answer(&mut server, &discover_frame(b, 2), now).unwrap();
+        assert_eq!(offer.your_addr, Ipv4Addr::new(10, 0, 0, 3));
+        let taken = request_frame(b, 2, Ipv4Addr::new(10, 0, 0, 2), own);
+        let (reply, _) = answer(&mut server, &taken, now).unwrap();
+        assert_eq!(reply.message_type, message_types::NAK);
+
+        let request = request_frame(a, 1, Ipv4Addr::new(10, 0, 0, 2), own);
+        let (reply, ack) = answer(&mut server, &request, now).unwrap();
+        assert_eq!(ack.message_type, Some(message_types::ACK));
+        assert_eq!(
+            reply.to_json(),
+            r#"{"type":"ack","address":"10.0.0.2","client_mac":"02:00:00:00:00:0a"}"#
+        );
+        let leases: Vec<_> = server.leases().collect();
+        assert_eq!(leases, [(ack.your_addr, a, now + Duration::from_secs(600))]);
+
+        // b takes the offer of another server, its address is free again
+        let elsewhere = request_frame(
+            b,
+            2,
+            Ipv4Addr::new(192, 0, 2, 9),
+            Ipv4Addr::new(192, 0, 2, 1),
+        );
+        assert!(answer(&mut server, &elsewhere, now).is_none());
+        let c = MacAddr::new(2, 0, 0, 0, 0, 0xc);
+        let (_, offer) = answer(&mut server, &discover_frame(c, 3), now).unwrap();
+        assert_eq!(offer.your_addr, Ipv4Addr::new(10, 0, 0, 3));
+
+        // neither the server's address nor the router's are handed out
+        let d = MacAddr::new(2, 0, 0, 0, 0, 0xd);
+        let (_, offer) = answer(&mut server, &discover_frame(d, 4), now).unwrap();
+        assert_eq!(offer.your_addr, Ipv4Addr::new(10, 0, 0, 4));
+        let e = MacAddr::new(2, 0, 0, 0, 0, 0xe);
+        let (_, offer) = answer(&mut server, &discover_frame(e, 5), now).unwrap();
+        assert_eq!(offer.your_addr, Ipv4Addr::new(10, 0, 0, 5));
+        assert!(answer(&mut server, &discover_frame(b, 6), now).is_none());
+
+        // a declined address is never offered again, an expired lease is
+        let mut declined = vec![OPTION_REQUESTED_ADDR, 4];
+        declined.extend_from_slice(&[10, 0, 0, 2]);
+        let decline = client_frame(a, 7, message_types::DECLINE, &declined);
+        assert!(answer(&mut server, &decline, now).is_none());
+        assert_eq!(server.leases().count(), 0);
+        let later = now + OFFER_HOLD;
+        let (_, offer) = answer(&mut server, &discover_frame(b, 8), later).unwrap();
+        assert_eq!(offer.your_addr, Ipv4Addr::new(10, 0, 0, 3));
+    }
+
+    #[test]
+    fn server_answers_starving_clients() {
+        let device = Loopback::new();
+        let stop = Arc::new(AtomicBool::new(false));
+        let own = Ipv4Addr::new(10, 0, 0, 1);
+
+        let (mut server_tx, mut server_rx) = channel(&device);
+        let server_stop = stop.clone();
+        let server_thread = thread::spawn(move || {
+            let mut server = Server::new(
+                MacAddr::new(2, 0, 0, 0, 0, 1),
+                Ipv4Cidr::new(own, 24),
+                Default::default(),
+            );
+            let mut replies = 0;
+            server
+                .serve(&mut *server_tx, &mut *server_rx, &server_stop, |_| {
+                    replies += 1
+                })
+                .unwrap();
+            replies
+        });
+
+        let (mut tx, mut rx) = channel(&device);
+        let config = Config {
+            rate: 1000.0,
+            count: Some(3),
+            linger: Duration::from_millis(200),
+            allowed_servers: vec![own],
+            seed: Some(1),
+        };
+        let report = starve(&mut *tx, &mut *rx, &config, &AtomicBool::new(false), |_| {}).unwrap();
+        stop.store(true, Ordering::SeqCst);
+
+        assert_eq!(server_thread.join().unwrap(), 3);
+        assert_eq!(report.offers, 3);
+        assert_eq!(report.addresses.len(), 3);
+        assert!(report.addresses.contains(&Ipv4Addr::new(10, 0, 0, 2)));
+        assert!(report.rogue_servers.is_empty());
+    }
+
+    #[test]
+    fn lingers_as_long_as_the_clock_says() {
+        let device = Loopback::new();
+        let (mut tx, mut rx) = channel(&device);
+        let clock = Arc::new(MockClock::new());
+        let config = Config {
+            count: Some(1),
+            linger: Duration::from_secs(600),
+            seed: Some(1),
+            ..Default::default()
+        };
+        // ten minutes pass in no time
+        let advance = {
+            let clock = clock.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                clock.advance(Duration::from_secs(600));
+            })
+        };
+        let stop = AtomicBool::new(false);
+        let report =
+            starve_with_clock(&mut *tx, &mut *rx, &config, &*clock, &stop, |_| {}).unwrap();
+        advance.join().unwrap();
+        assert_eq!(report.sent, 1);
+        assert_eq!(report.offers, 0);
+    }
+}
@@ -0,0 +1,750 @@
+//! LAN topology discovery.
+//!
+//! An [`Inventory`] merges what the link gives away about its hosts: ARP traffic, LLDP and
+//! CDP advertisements of switches and other infrastructure, mDNS records and SSDP
+//! announcements. Hosts are keyed by MAC address; the switch ports learned from LLDP and
+//! CDP are kept as [`Neighbor`]s. [`discover`] fills an inventory by sweeping a network with
+//! ARP requests, asking for mDNS and SSDP services and listening for a while, and can
+//! look up the PTR records of the hosts found.
+
+use crate::{
+    arp::{
+        arp_new::{ArpOperations, ArpPacket},
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EtherTypes, EthernetPacket, Packet},
+        network_interface::MacAddr,
+        other::build_arp_packet,
+    },
+    cidr::Ipv4Cidr,
+    dns::{self, RecordData, Resolver},
+    flows::FlowPacket,
+    generate,
+    ipproto::IPPROTO_UDP,
+    render::{json_string, rfc3339},
+    scan::ports::Ipv4Mac,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+const ETHERTYPE_LLDP: u16 = 0x88cc;
+const CDP_ADDRESS: MacAddr = MacAddr(0x01, 0x00, 0x0c, 0xcc, 0xcc, 0xcc);
+/// LLC/SNAP header of CDP frames: SNAP, Cisco OUI, protocol 0x2000.
+const CDP_SNAP: [u8; 8] = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x0c, 0x20, 0x00];
+
+const MDNS_PORT: u16 = 5353;
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_MAC: MacAddr = MacAddr(0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb);
+/// Name queried to enumerate the service types of a link (RFC 6763 section 9).
+const MDNS_SERVICES: &str = "_services._dns-sd._udp.local";
+const SSDP_PORT: u16 = 1900;
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_MAC: MacAddr = MacAddr(0x01, 0x00, 0x5e, 0x7f, 0xff, 0xfa);
+
+/// Where a piece of information came from.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Source {
+    /// ARP requests and replies
+    Arp,
+    /// LLDP advertisements
+    Lldp,
+    /// CDP advertisements
+    Cdp,
+    /// mDNS responses
+    Mdns,
+    /// SSDP responses and announcements
+    Ssdp,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Source::Arp => "arp",
+            Source::Lldp => "lldp",
+            Source::Cdp => "cdp",
+            Source::Mdns => "mdns",
+            Source::Ssdp => "ssdp",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A station seen on the link.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Host {
+    /// MAC address
+    pub mac: MacAddr,
+    /// IP addresses it uses
+    pub addresses: BTreeSet<IpAddr>,
+    /// Names it goes by, from mDNS, LLDP, CDP or reverse DNS
+    pub names: BTreeSet<String>,
+    /// Services it offers, as mDNS service types or SSDP search targets
+    pub services: BTreeSet<String>,
+    /// Server description from SSDP, or system description from LLDP and CDP
+    pub description: Option<String>,
+    /// How it was found
+    pub sources: BTreeSet<Source>,
+    /// First time it was seen
+    pub first_seen: SystemTime,
+    /// Last time it was seen
+    pub last_seen: SystemTime,
+}
+
+impl Host {
+    fn new(mac: MacAddr, timestamp: SystemTime) -> Host {
+        Host {
+            mac,
+            addresses: BTreeSet::new(),
+            names: BTreeSet::new(),
+            services: BTreeSet::new(),
+            description: None,
+            sources: BTreeSet::new(),
+            first_seen: timestamp,
+            last_seen: timestamp,
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.mac)?;
+        for address in &self.addresses {
+            write!(f, " {}", address)?;
+        }
+        if !self.names.is_empty() {
+            let names: Vec<&str> = self.names.iter().map(String::as_str).collect();
+            write!(f, " ({})", names.join(", "))?;
+        }
+        if !self.services.is_empty() {
+            let services: Vec<&str> = self.services.iter().map(String::as_str).collect();
+            write!(f, " [{}]", services.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// A switch port, or another device port, advertised with LLDP or CDP.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Neighbor {
+    /// `Source::Lldp` or `Source::Cdp`
+    pub protocol: Source,
+    /// Ethernet address the advertisement came from
+    pub mac: MacAddr,
+    /// Chassis ID, or CDP device ID
+    pub chassis: String,
+    /// Port ID
+    pub port: String,
+    /// Port description
+    pub port_description: Option<String>,
+    /// System name
+    pub system_name: Option<String>,
+    /// System description, or CDP platform and software version
+    pub description: Option<String>,
+    /// Management addresses
+    pub addresses: Vec<IpAddr>,
+    /// Last time it was advertised
+    pub last_seen: SystemTime,
+}
+
+impl fmt::Display for Neighbor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} port {}",
+            self.protocol,
+            self.system_name.as_deref().unwrap_or(&self.chassis),
+            self.port
+        )?;
+        if let Some(description) = &self.port_description {
+            write!(f, " ({})", description)?;
+        }
+        for address in &self.addresses {
+            write!(f, " {}", address)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hosts and switch ports seen on a link.
+#[derive(Clone, Debug, Default)]
+pub struct Inventory {
+    hosts: BTreeMap<MacAddr, Host>,
+    neighbors: BTreeMap<(String, String), Neighbor>,
+}
+
+impl Inventory {
+    /// Create an empty inventory.
+    pub fn new() -> Inventory {
+        Default::default()
+    }
+
+    /// Learn what `packet`, captured at `timestamp`, tells about the link.
+    pub fn update(&mut self, packet: &EthernetPacket, timestamp: SystemTime) {
+        let ethertype = packet.get_ethertype();
+        if ethertype == EtherTypes::Arp {
+            self.update_arp(packet, timestamp);
+        } else if ethertype.value() == ETHERTYPE_LLDP {
+            if let Some(neighbor) = parse_lldp(packet, timestamp) {
+                self.add_neighbor(neighbor);
+            }
+        } else if ethertype.value() <= 1500 && packet.get_destination() == CDP_ADDRESS {
+            if let Some(neighbor) = parse_cdp(packet, timestamp) {
+                self.add_neighbor(neighbor);
+            }
+        } else {
+            self.update_udp(packet, timestamp);
+        }
+    }
+
+    /// The host with MAC address `mac`.
+    pub fn host(&self, mac: MacAddr) -> Option<&Host> {
+        self.hosts.get(&mac)
+    }
+
+    /// The host using `address`.
+    pub fn host_by_address(&self, address: IpAddr) -> Option<&Host> {
+        self.hosts
+            .values()
+            .find(|host| host.addresses.contains(&address))
+    }
+
+    /// Hosts whose MAC address, IP address, name, service or description contains `query`,
+    /// ignoring case.
+    pub fn find(&self, query: &str) -> Vec<&Host> {
+        let query = query.to_lowercase();
+        let matches = |value: &str| value.to_lowercase().contains(&query);
+        self.hosts
+            .values()
+            .filter(|host| {
+                matches(&host.mac.to_string())
+                    || host.addresses.iter().any(|a| matches(&a.to_string()))
+                    || host.names.iter().any(|name| matches(name))
+                    || host.services.iter().any(|service| matches(service))
+                    || host.description.as_deref().map_or(false, matches)
+            })
+            .collect()
+    }
+
+    /// Hosts learned from `source`.
+    pub fn hosts_from(&self, source: Source) -> impl Iterator<Item = &Host> {
+        self.hosts
+            .values()
+            .filter(move |host| host.sources.contains(&source))
+    }
+
+    /// All hosts, ordered by MAC address.
+    pub fn hosts(&self) -> impl Iterator<Item = &Host> {
+        self.hosts.values()
+    }
+
+    /// All advertised ports, ordered by chassis and port.
+    pub fn neighbors(&self) -> impl Iterator<Item = &Neighbor> {
+        self.neighbors.values()
+    }
+
+    /// Number of hosts.
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Whether no host was seen.
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// The inventory as a JSON object with `hosts` and `neighbors` arrays.
+    pub fn to_json(&self) -> String {
+        let strings = |values: &mut dyn Iterator<Item = String>| {
+            let values: Vec<String> = values.map(|value| json_string(&value)).collect();
+            format!("[{}]", values.join(","))
+        };
+        let optional = |value: &Option<String>| match value {
+            Some(value) => json_string(value),
+            None => "null".to_owned(),
+        };
+
+        let hosts: Vec<String> = self
+            .hosts
+            .values()
+            .map(|host| {
+                format!(
+                    "{{\"mac\":\"{}\",\"addresses\":{},\"names\":{},\"services\":{},\
+                     \"description\":{},\"sources\":{},\"first_seen\":\"{}\",\"last_seen\":\"{}\"}}",
+                    host.mac,
+                    strings(&mut host.addresses.iter().map(IpAddr::to_string)),
+                    strings(&mut host.names.iter().cloned()),
+                    strings(&mut host.services.iter().cloned()),
+                    optional(&host.description),
+                    strings(&mut host.sources.iter().map(Source::to_string)),
+                    rfc3339(host.first_seen),
+                    rfc3339(host.last_seen)
+                )
+            })
+            .collect();
+        let neighbors: Vec<String> = self
+            .neighbors
+            .values()
+            .map(|neighbor| {
+                format!(
+                    "{{\"protocol\":\"{}\",\"mac\":\"{}\",\"chassis\":{},\"port\":{},\
+                     \"port_description\":{},\"system_name\":{},\"description\":{},\
+                     \"addresses\":{},\"last_seen\":\"{}\"}}",
+                    neighbor.protocol,
+                    neighbor.mac,
+                    json_string(&neighbor.chassis),
+                    json_string(&neighbor.port),
+                    optional(&neighbor.port_description),
+                    optional(&neighbor.system_name),
+                    optional(&neighbor.description),
+                    strings(&mut neighbor.addresses.iter().map(IpAddr::to_string)),
+                    rfc3339(neighbor.last_seen)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"hosts\":[{}],\"neighbors\":[{}]}}",
+            hosts.join(","),
+            neighbors.join(",")
+        )
+    }
+
+    /// Add the names the addresses of the hosts point back to. Addresses without a PTR
+    /// record, and those the name servers don't answer for, are skipped.
+    pub fn resolve_names(&mut self, resolver: &Resolver) {
+        for host in self.hosts.values_mut() {
+            for address in &host.addresses {
+                if let Ok(Some(name)) = resolver.reverse(*address) {
+                    host.names.insert(name);
+                }
+            }
+        }
+    }
+
+    fn host_mut(&mut self, mac: MacAddr, source: Source, timestamp: SystemTime) -> &mut Host {
+        let host = self
+            .hosts
+            .entry(mac)
+            .or_insert_with(|| Host::new(mac, timestamp));
+        host.sources.insert(source);
+        if timestamp > host.last_seen {
+            host.last_seen = timestamp;
+        }
+        host
+    }
+
+    fn update_arp(&mut self, packet: &EthernetPacket, timestamp: SystemTime) {
+        let arp = match ArpPacket::new(packet.payload()) {
+            Some(arp) => arp,
+            None => return,
+        };
+        let sender = arp.get_sender_hw_addr();
+        let address = arp.get_sender_proto_addr();
+        // probes carry no sender address
+        if address.is_unspecified() || sender == MacAddr(0, 0, 0, 0, 0, 0) {
+            return;
+        }
+        let op = arp.get_operation();
+        if op != ArpOperations::Request && op != ArpOperations::Reply {
+            return;
+        }
+        self.host_mut(sender, Source::Arp, timestamp)
+            .addresses
+            .insert(IpAddr::V4(address));
+    }
+
+    fn add_neighbor(&mut self, neighbor: Neighbor) {
+        let host = self.host_mut(neighbor.mac, neighbor.protocol, neighbor.last_seen);
+        host.addresses.extend(neighbor.addresses.iter().cloned());
+        if let Some(name) = &neighbor.system_name {
+            host.names.insert(name.clone());
+        }
+        if neighbor.description.is_some() {
+            host.description = neighbor.description.clone();
+        }
+        let key = (neighbor.chassis.clone(), neighbor.port.clone());
+        self.neighbors.insert(key, neighbor);
+    }
+
+    fn update_udp(&mut self, packet: &EthernetPacket, timestamp: SystemTime) {
+        let parsed = match FlowPacket::parse(packet) {
+            Some(parsed) if parsed.protocol == IPPROTO_UDP => parsed,
+            _ => return,
+        };
+        let payload = &packet.packet()[parsed.payload_offset..][..parsed.payload_len];
+        let mac = packet.get_source();
+        if parsed.src.port() == MDNS_PORT {
+            let message = match dns::Message::parse(payload) {
+                Some(message) if message.is_response() => message,
+                _ => return,
+            };
+            let host = self.host_mut(mac, Source::Mdns, timestamp);
+            host.addresses.insert(parsed.src.ip());
+            for answer in &message.answers {
+                match &answer.data {
+                    RecordData::A(address) => {
+                        host.addresses.insert(IpAddr::V4(*address));
+                        host.names.insert(answer.name.clone());
+                    }
+                    RecordData::Aaaa(address) => {
+                        host.addresses.insert(IpAddr::V6(*address));
+                        host.names.insert(answer.name.clone());
+                    }
+                    RecordData::Name(target) if answer.rtype == dns::TYPE_PTR => {
+                        if answer.name == MDNS_SERVICES {
+                            // service type enumeration
+                            host.services.insert(target.clone());
+                        } else if answer.name.starts_with('_') {
+                            // an instance of a service type
+                            host.services.insert(answer.name.clone());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        } else if parsed.src.port() == SSDP_PORT || parsed.dst.port() == SSDP_PORT {
+            let headers = match parse_ssdp(payload) {
+                Some(headers) => headers,
+                None => return,
+            };
+            let host = self.host_mut(mac, Source::Ssdp, timestamp);
+            host.addresses.insert(parsed.src.ip());
+            if let Some(target) = headers.get("st").or_else(|| headers.get("nt")) {
+                host.services.insert(target.clone());
+            }
+            if let Some(server) = headers.get("server") {
+                host.description = Some(server.clone());
+            }
+        }
+    }
+}
+
+/// Parameters of an active discovery.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// ARP requests per second during the sweep. Defaults to 200
+    pub rate: f64,
+
+    /// Time to keep listening after the sweep. LLDP is usually sent every 30 seconds and
+    /// CDP every minute. Defaults to 35 seconds
+    pub listen: Duration,
+
+    /// Largest number of addresses swept. Defaults to 4096
+    pub max_addresses: u32,
+
+    /// Look up the PTR records of the addresses found, with the system's name servers.
+    /// Defaults to false
+    pub resolve_names: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rate: 200.0,
+            listen: Duration::from_secs(35),
+            max_addresses: 4096,
+            resolve_names: false,
+        }
+    }
+}
+
+/// Build the inventory of the link `tx` and `rx` are attached to.
+///
+/// Every host address of `network` is sent an ARP request from `source`, then an
+/// mDNS service enumeration and an SSDP search are sent and the link is listened to for
+/// `config.listen`. With `config.resolve_names` the names the addresses found point back
+/// to are added to the hosts last. `rx` should be configured with a short read timeout, around 10
+/// milliseconds, as it is polled between requests.
+pub fn discover(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    source: Ipv4Mac,
+    network: Ipv4Cidr,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<Inventory> {
+    let mut hosts = network.hosts();
+    if hosts.remaining() > u64::from(config.max_addresses) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} has more than {} addresses",
+                network, config.max_addresses
+            ),
+        ));
+    }
+
+    let mut inventory = Inventory::new();
+    let mut queries = vec![mdns_query(source), ssdp_search(source)];
+    let rate = config.rate.max(0.001);
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+    let mut done_at = None;
+    let mut iter = rx.iter();
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        match done_at {
+            Some(done_at) if now.duration_since(done_at) >= config.listen => break,
+            Some(_) => {}
+            None if hosts.remaining() > 0 => {
+                let due = start + Duration::from_secs_f64(sent as f64 / rate);
+                if now >= due {
+                    let target = hosts.next().unwrap();
+                    let request = build_arp_packet(
+                        MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+                        source.mac,
+                        source.ip,
+                        MacAddr(0, 0, 0, 0, 0, 0),
+                        target,
+                        ArpOperations::Request,
+                    );
+                    send(tx, &request)?;
+                    sent += 1;
+                }
+            }
+            None => match queries.pop() {
+                Some(query) => {
+                    send(tx, &query)?;
+                    // don't let the responses to both queries collide
+                    thread::sleep(Duration::from_millis(100));
+                }
+                None => done_at = Some(now),
+            },
+        }
+
+        match iter.next() {
+            Ok(packet) => inventory.update(&packet, SystemTime::now()),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    if config.resolve_names && !stop.load(Ordering::SeqCst) {
+        inventory.resolve_names(&Resolver::system()?);
+    }
+    Ok(inventory)
+}
+
+fn send(tx: &mut dyn EthernetDataLinkSender, frame: &[u8]) -> io::Result<()> {
+    tx.send_to(&EthernetPacket::new(frame).unwrap(), None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+}
+
+/// An mDNS query enumerating the service types of the link, asking for unicast answers.
+fn mdns_query(source: Ipv4Mac) -> Vec<u8> {
+    // ID 0, no flags, a single question
+    let mut message = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in MDNS_SERVICES.split('.') {
+        message.push(label.len() as u8);
+        message.extend_from_slice(label.as_bytes());
+    }
+    message.push(0);
+    message.extend_from_slice(&dns::TYPE_PTR.to_be_bytes());
+    // class IN with the unicast response bit
+    message.extend_from_slice(&0x8001u16.to_be_bytes());
+    generate::udp_frame(
+        source.mac,
+        MDNS_MAC,
+        SocketAddrV4::new(source.ip, MDNS_PORT),
+        SocketAddrV4::new(MDNS_GROUP, MDNS_PORT),
+        &message,
+    )
+}
+
+/// An SSDP search for all devices and services.
+fn ssdp_search(source: Ipv4Mac) -> Vec<u8> {
+    let message = "M-SEARCH * HTTP/1.1\r\n\
+                   HOST: 239.255.255.250:1900\r\n\
+                   MAN: \"ssdp:discover\"\r\n\
+                   MX: 2\r\n\
+                   ST: ssdp:all\r\n\r\n";
+    generate::udp_frame(
+        source.mac,
+        SSDP_MAC,
+        SocketAddrV4::new(source.ip, SSDP_PORT),
+        SocketAddrV4::new(SSDP_GROUP, SSDP_PORT),
+        message.as_bytes(),
+    )
+}
+
+/// Headers of an SSDP response or NOTIFY, with lowercase names. Searches are left out.
+fn parse_ssdp(payload: &[u8]) -> Option<BTreeMap<String, String>> {
+    let text = std::str::from_utf8(payload).ok()?;
+    let mut lines = text.split("\r\n");
+    let start = lines.next()?;
+    if !start.starts_with("HTTP/1.1 200") && !start.starts_with("NOTIFY ") {
+        return None;
+    }
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| {
+            let colon = line.find(':')?;
+            Some((
+                line[..colon].trim().to_lowercase(),
+                line[colon + 1..].trim().to_owned(),
+            ))
+        })
+        .collect();
+    Some(headers)
+}
+
+fn parse_lldp(packet: &EthernetPacket, timestamp: SystemTime) -> Option<Neighbor> {
+    let mut data = packet.payload();
+    let mut chassis = None;
+    let mut port = None;
+    let mut neighbor = Neighbor {
+        protocol: Source::Lldp,
+        mac: packet.get_source(),
+        chassis: String::new(),
+        port: String::new(),
+        port_description: None,
+        system_name: None,
+        description: None,
+        addresses: Vec::new(),
+        last_seen: timestamp,
+    };
+    while data.len() >= 2 {
+        let header = u16::from_be_bytes([data[0], data[1]]);
+        let (kind, len) = (header >> 9, (header & 0x1ff) as usize);
+        let value = data.get(2..2 + len)?;
+        data = &data[2 + len..];
+        match kind {
+            0 => break,
+            1 => chassis = lldp_id(value, 4, 5),
+            2 => port = lldp_id(value, 3, 4),
+            4 => neighbor.port_description = Some(text(value)),
+            5 => neighbor.system_name = Some(text(value)),
+            6 => neighbor.description = Some(text(value)),
+            8 if value.len() >= 2 => {
+                // address string length, covering the subtype
+                let len = value[0] as usize;
+                if let Some(address) = value.get(2..1 + len).and_then(|a| address(value[1], a)) {
+                    neighbor.addresses.push(address);
+                }
+            }
+            _ => {}
+        }
+    }
+    neighbor.chassis = chassis?;
+    neighbor.port = port?;
+    Some(neighbor)
+}
+
+/// Format an LLDP chassis or port ID. The two number their MAC and network address
+/// subtypes differently.
+fn lldp_id(value: &[u8], mac_subtype: u8, address_subtype: u8) -> Option<String> {
+    let (&subtype, value) = value.split_first()?;
+    let id = if subtype == mac_subtype && value.len() == 6 {
+        MacAddr(value[0], value[1], value[2], value[3], value[4], value[5]).to_string()
+    } else if subtype == address_subtype && !value.is_empty() {
+        address(value[0], &value[1..])
+            .map(|address| address.to_string())
+            .unwrap_or_else(|| text(value))
+    } else {
+        text(value)
+    };
+    Some(id)
+}
+
+/// An address with an IANA address family number.
+fn address(family: u8, value: &[u8]) -> Option<IpAddr> {
+    match (family, value.len()) {
+        (1, 4) => Some(IpAddr::V4(Ipv4Addr::new(
+            value[0], value[1], value[2], value[3],
+        ))),
+        (2, 16) => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(value);
+            Some(IpAddr::V6(Ipv6Addr::from(octets)))
+        }
+        _ => None,
+    }
+}
+
+fn parse_cdp(packet: &EthernetPacket, timestamp: SystemTime) -> Option<Neighbor> {
+    let payload = packet.payload();
+    if payload.get(..CDP_SNAP.len())? != CDP_SNAP {
+        return None;
+    }
+    // version, TTL and checksum
+    let mut data = payload.get(CDP_SNAP.len() + 4..)?;
+    let mut neighbor = Neighbor {
+        protocol: Source::Cdp,
+        mac: packet.get_source(),
+        chassis: String::new(),
+        port: String::new(),
+        port_description: None,
+        system_name: None,
+        description: None,
+        addresses: Vec::new(),
+        last_seen: timestamp,
+    };
+    let mut platform = None;
+    let mut version = None;
+    while data.len() >= 4 {
+        let kind = u16::from_be_bytes([data[0], data[1]]);
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if len < 4 {
+            return None;
+        }
+        let value = data.get(4..len)?;
+        data = &data[len..];
+        match kind {
+            0x0001 => neighbor.chassis = text(value),
+            0x0002 => neighbor.addresses.extend(cdp_addresses(value)),
+            0x0003 => neighbor.port = text(value),
+            0x0005 => version = text(value).lines().next().map(str::to_owned),
+            0x0006 => platform = Some(text(value)),
+            _ => {}
+        }
+    }
+    if neighbor.chassis.is_empty() || neighbor.port.is_empty() {
+        return None;
+    }
+    neighbor.system_name = Some(neighbor.chassis.clone());
+    neighbor.description = match (platform, version) {
+        (Some(platform), Some(version)) => Some(format!("{}, {}", platform, version)),
+        (platform, version) => platform.or(version),
+    };
+    Some(neighbor)
+}
+
+/// IPv4 addresses of a CDP address TLV.
+fn cdp_addresses(mut value: &[u8]) -> Vec<IpAddr> {
+    let mut addresses = Vec::new();
+    // number of addresses
+    value = value.get(4..).unwrap_or(&[]);
+    while value.len() >= 2 {
+        let protocol_len = value[1] as usize;
+        let protocol = match value.get(2..2 + protocol_len) {
+            Some(protocol) => protocol,
+            None => break,
+        };
+        let rest = &value[2 + protocol_len..];
+        if rest.len() < 2 {
+            break;
+        }
+        let len = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+        let address = match rest.get(2..2 + len) {
+            Some(address) => address,
+            None => break,
+        };
+        // NLPID 0xcc is IP
+        if protocol == [0xcc] && len == 4 {
+            addresses.push(IpAddr::V4(Ipv4Addr::new(
+                address[0], address[1], address[2], address[3],
+            )));
+        }
+        value = &rest[2 + len..];
+    }
+    addresses
+}
+
+fn text(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .to_owned()
+}
@@ -0,0 +1,745 @@
+//! DNS message parsing and spoofing detection.
+//!
+//! [`Message`] decodes the header, questions and answers of a DNS message. [`Monitor`]
+//! watches the DNS traffic on a link, pairs responses with the queries they answer and
+//! reports the anomalies cache poisoning attempts leave behind: responses nobody asked
+//! for, responses with the wrong transaction ID or question, several responses giving
+//! different answers to the same query and answers with implausible TTLs. A [`Resolver`]
+//! asks the configured name servers for the PTR records of addresses.
+
+use crate::{
+    arp::{
+        channel::EthernetDataLinkReceiver,
+        ether::{EthernetPacket, Packet},
+    },
+    flows::FlowPacket,
+    ipproto::IPPROTO_UDP,
+};
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::atomic::{AtomicBool, AtomicU16, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// DNS server port.
+pub const PORT: u16 = 53;
+
+/// Record type of IPv4 addresses.
+pub const TYPE_A: u16 = 1;
+/// Record type of name servers.
+pub const TYPE_NS: u16 = 2;
+/// Record type of aliases.
+pub const TYPE_CNAME: u16 = 5;
+/// Record type of reverse lookups.
+pub const TYPE_PTR: u16 = 12;
+/// Record type of IPv6 addresses.
+pub const TYPE_AAAA: u16 = 28;
+/// Record type asking for records of any type.
+pub const TYPE_ANY: u16 = 255;
+/// Class of Internet records.
+pub const CLASS_IN: u16 = 1;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+const RCODE_NXDOMAIN: u8 = 3;
+const HEADER_LEN: usize = 12;
+// bounds the work spent on compression pointer chains
+const MAX_POINTERS: usize = 32;
+
+/// A question of a DNS message.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Question {
+    /// Queried name, without the trailing dot
+    pub name: String,
+    /// Record type
+    pub qtype: u16,
+    /// Class, 1 for Internet
+    pub qclass: u16,
+}
+
+/// Data of a resource record.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum RecordData {
+    /// IPv4 address
+    A(Ipv4Addr),
+    /// IPv6 address
+    Aaaa(Ipv6Addr),
+    /// Domain name of CNAME, NS and PTR records
+    Name(String),
+    /// Data of other record types, undecoded
+    Other(Vec<u8>),
+}
+
+impl fmt::Display for RecordData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordData::A(addr) => write!(f, "{}", addr),
+            RecordData::Aaaa(addr) => write!(f, "{}", addr),
+            RecordData::Name(name) => write!(f, "{}", name),
+            RecordData::Other(data) => {
+                for byte in data {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A resource record of the answer section.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Record {
+    /// Owner name, without the trailing dot
+    pub name: String,
+    /// Record type
+    pub rtype: u16,
+    /// Class, 1 for Internet
+    pub class: u16,
+    /// Time to live in seconds
+    pub ttl: u32,
+    /// Record data
+    pub data: RecordData,
+}
+
+/// A DNS message. Authority and additional records are not decoded.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Message {
+    /// Transaction ID
+    pub id: u16,
+    /// Flags, including opcode and response code
+    pub flags: u16,
+    /// Question section
+    pub questions: Vec<Question>,
+    /// Answer section
+    pub answers: Vec<Record>,
+}
+
+impl Message {
+    /// Decode a DNS message.
+    pub fn parse(data: &[u8]) -> Option<Message> {
+        if data.len() < HEADER_LEN {
+            return None;
+        }
+        let word = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let mut message = Message {
+            id: word(0),
+            flags: word(2),
+            questions: Vec::new(),
+            answers: Vec::new(),
+        };
+
+        let mut offset = HEADER_LEN;
+        for _ in 0..word(4) {
+            let (name, end) = read_name(data, offset)?;
+            let fixed = data.get(end..end + 4)?;
+            message.questions.push(Question {
+                name,
+                qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+                qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+            });
+            offset = end + 4;
+        }
+        for _ in 0..word(6) {
+            let (name, end) = read_name(data, offset)?;
+            let fixed = data.get(end..end + 10)?;
+            let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+            let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+            let start = end + 10;
+            let rdata = data.get(start..start + len)?;
+            let data = match (rtype, len) {
+                (TYPE_A, 4) => RecordData::A(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+                (TYPE_AAAA, 16) => {
+                    let mut addr = [0u8; 16];
+                    addr.copy_from_slice(rdata);
+                    RecordData::Aaaa(addr.into())
+                }
+                (TYPE_CNAME, _) | (TYPE_NS, _) | (TYPE_PTR, _) => {
+                    RecordData::Name(read_name(data, start)?.0)
+                }
+                _ => RecordData::Other(rdata.to_vec()),
+            };
+            message.answers.push(Record {
+                name,
+                rtype,
+                class: u16::from_be_bytes([fixed[2], fixed[3]]),
+                ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+                data,
+            });
+            offset = start + len;
+        }
+        Some(message)
+    }
+
+    /// Whether the message is a response.
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+
+    /// Response code.
+    pub fn rcode(&self) -> u8 {
+        (self.flags & 0x000f) as u8
+    }
+}
+
+/// Read the possibly compressed name at `offset`, returning it and the offset following
+/// it in the message.
+fn read_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *data.get(offset)? as usize;
+        match len & 0xc0 {
+            0x00 if len == 0 => {
+                let end = end.unwrap_or(offset + 1);
+                return Some((labels.join("."), end));
+            }
+            0x00 => {
+                let label = data.get(offset + 1..offset + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                offset += 1 + len;
+            }
+            0xc0 => {
+                pointers += 1;
+                if pointers > MAX_POINTERS {
+                    return None;
+                }
+                let target = (len & 0x3f) << 8 | *data.get(offset + 1)? as usize;
+                end.get_or_insert(offset + 2);
+                offset = target;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// An anomaly in DNS traffic.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Anomaly {
+    /// A response to a query that was never seen
+    UnsolicitedResponse,
+    /// A response with the transaction ID of none of the outstanding queries to that server
+    IdMismatch,
+    /// A response whose question differs from the one of the query it answers
+    QuestionMismatch {
+        /// Name that was queried
+        expected: String,
+    },
+    /// A further response to an answered query, with different answers
+    ConflictingAnswers {
+        /// Answers of the first response
+        previous: Vec<RecordData>,
+    },
+    /// An answer whose TTL lies outside the configured bounds
+    AbnormalTtl {
+        /// Offending TTL, in seconds
+        ttl: u32,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Anomaly::UnsolicitedResponse => write!(f, "unsolicited response"),
+            Anomaly::IdMismatch => write!(f, "transaction ID matches no query"),
+            Anomaly::QuestionMismatch { expected } => {
+                write!(f, "question differs from query for {}", expected)
+            }
+            Anomaly::ConflictingAnswers { previous } => {
+                let previous: Vec<String> = previous.iter().map(ToString::to_string).collect();
+                write!(f, "answers conflict with earlier {}", previous.join(" "))
+            }
+            Anomaly::AbnormalTtl { ttl } => write!(f, "abnormal TTL {}", ttl),
+        }
+    }
+}
+
+/// A suspicious DNS response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// When the response was seen
+    pub time: SystemTime,
+    /// Endpoint the response was sent to
+    pub client: SocketAddr,
+    /// Endpoint the response came from
+    pub server: SocketAddr,
+    /// Transaction ID of the response
+    pub id: u16,
+    /// First question of the response
+    pub name: String,
+    /// Answers of the response
+    pub answers: Vec<RecordData>,
+    /// What is wrong with it
+    pub anomaly: Anomaly,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let answers: Vec<String> = self.answers.iter().map(ToString::to_string).collect();
+        write!(
+            f,
+            "{} > {} id {} {} [{}]: {}",
+            self.server,
+            self.client,
+            self.id,
+            self.name,
+            answers.join(" "),
+            self.anomaly
+        )
+    }
+}
+
+/// Monitor parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Queries are forgotten when unanswered for this long. Defaults to 5 seconds
+    pub query_timeout: Duration,
+
+    /// Answered queries are remembered this long to catch conflicting responses.
+    /// Defaults to 10 seconds
+    pub answer_window: Duration,
+
+    /// Answers with a TTL below this are reported. Defaults to 0
+    pub min_ttl: u32,
+
+    /// Answers with a TTL above this are reported. Defaults to one week
+    pub max_ttl: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            query_timeout: Duration::from_secs(5),
+            answer_window: Duration::from_secs(10),
+            min_ttl: 0,
+            max_ttl: 7 * 24 * 3600,
+        }
+    }
+}
+
+struct Query {
+    id: u16,
+    name: String,
+    time: SystemTime,
+}
+
+struct Answered {
+    name: String,
+    answers: Vec<RecordData>,
+    time: SystemTime,
+}
+
+/// Pairs DNS queries and responses and reports anomalies.
+pub struct Monitor {
+    config: Config,
+    /// Outstanding queries by client and server
+    queries: HashMap<(SocketAddr, SocketAddr), Vec<Query>>,
+    /// Recently answered queries by client, server and transaction ID
+    answered: HashMap<(SocketAddr, SocketAddr, u16), Answered>,
+}
+
+impl Monitor {
+    /// Create a monitor that has seen no traffic yet.
+    pub fn new(config: Config) -> Monitor {
+        Monitor {
+            config,
+            queries: HashMap::new(),
+            answered: HashMap::new(),
+        }
+    }
+
+    /// Process a frame captured at `timestamp`, returning the anomalies it shows.
+    pub fn update(&mut self, packet: &EthernetPacket, timestamp: SystemTime) -> Vec<Event> {
+        let parsed = match FlowPacket::parse(packet) {
+            Some(parsed) if parsed.protocol == IPPROTO_UDP => parsed,
+            _ => return Vec::new(),
+        };
+        let payload = &packet.packet()[parsed.payload_offset..][..parsed.payload_len];
+        let message = match Message::parse(payload) {
+            Some(message) => message,
+            None => return Vec::new(),
+        };
+        self.expire(timestamp);
+
+        match (message.is_response(), parsed.src.port(), parsed.dst.port()) {
+            (false, _, PORT) => {
+                let name = message
+                    .questions
+                    .first()
+                    .map_or_else(String::new, |question| question.name.to_ascii_lowercase());
+                self.queries
+                    .entry((parsed.src, parsed.dst))
+                    .or_insert_with(Vec::new)
+                    .push(Query {
+                        id: message.id,
+                        name,
+                        time: timestamp,
+                    });
+                Vec::new()
+            }
+            (true, PORT, _) => self.response(parsed.dst, parsed.src, &message, timestamp),
+            _ => Vec::new(),
+        }
+    }
+
+    fn response(
+        &mut self,
+        client: SocketAddr,
+        server: SocketAddr,
+        message: &Message,
+        timestamp: SystemTime,
+    ) -> Vec<Event> {
+        let name = message
+            .questions
+            .first()
+            .map_or_else(String::new, |question| question.name.to_ascii_lowercase());
+        let mut answers: Vec<RecordData> = message
+            .answers
+            .iter()
+            .map(|record| record.data.clone())
+            .collect();
+        answers.sort();
+        let event = |anomaly| Event {
+            time: timestamp,
+            client,
+            server,
+            id: message.id,
+            name: name.clone(),
+            answers: answers.clone(),
+            anomaly,
+        };
+
+        let key = (client, server);
+        let query = self.queries.get_mut(&key).and_then(|queries| {
+            let index = queries.iter().position(|query| query.id == message.id)?;
+            Some(queries.swap_remove(index))
+        });
+        let outstanding = self
+            .queries
+            .get(&key)
+            .map_or(false, |queries| !queries.is_empty());
+        if !outstanding {
+            self.queries.remove(&key);
+        }
+
+        let mut events = Vec::new();
+        match query {
+            Some(query) => {
+                if query.name != name {
+                    events.push(event(Anomaly::QuestionMismatch {
+                        expected: query.name.clone(),
+                    }));
+                }
+                self.answered.insert(
+                    (client, server, message.id),
+                    Answered {
+                        name: query.name,
+                        answers: answers.clone(),
+                        time: timestamp,
+                    },
+                );
+            }
+            None => match self.answered.get(&(client, server, message.id)) {
+                Some(answered) if answered.name == name => {
+                    // plain duplicates, e.g. from retransmitted queries, are fine
+                    if answered.answers != answers {
+                        events.push(event(Anomaly::ConflictingAnswers {
+                            previous: answered.answers.clone(),
+                        }));
+                    }
+                }
+                _ if outstanding => events.push(event(Anomaly::IdMismatch)),
+                _ => events.push(event(Anomaly::UnsolicitedResponse)),
+            },
+        }
+
+        for record in &message.answers {
+            if record.ttl < self.config.min_ttl || record.ttl > self.config.max_ttl {
+                events.push(event(Anomaly::AbnormalTtl { ttl: record.ttl }));
+            }
+        }
+        events
+    }
+
+    /// Forget the queries and answers that are too old at `now`.
+    pub fn expire(&mut self, now: SystemTime) {
+        let age = |time: SystemTime| now.duration_since(time).unwrap_or_default();
+        let config = self.config;
+        self.queries.retain(|_, queries| {
+            queries.retain(|query| age(query.time) < config.query_timeout);
+            !queries.is_empty()
+        });
+        self.answered
+            .retain(|_, answered| age(answered.time) < config.answer_window);
+    }
+}
+
+/// Watch the DNS traffic received on `rx` until `stop` is set, calling `on_event` for every
+/// anomaly.
+///
+/// `rx` should be configured with a read timeout so that `stop` is noticed.
+pub fn monitor<F: FnMut(&Event)>(
+    rx: &mut dyn EthernetDataLinkReceiver,
+    config: Config,
+    stop: &AtomicBool,
+    mut on_event: F,
+) -> io::Result<()> {
+    let mut monitor = Monitor::new(config);
+    let mut iter = rx.iter();
+    while !stop.load(Ordering::SeqCst) {
+        let packet = match iter.next() {
+            Ok(packet) => packet,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        for event in monitor.update(&packet, SystemTime::now()) {
+            on_event(&event);
+        }
+    }
+    Ok(())
+}
+
+/// The name holding the PTR record of `addr`, like `4.3.2.1.in-addr.arpa`.
+pub fn reverse_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let o = addr.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(addr) => {
+            let mut name = String::with_capacity(72);
+            for byte in addr.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Encode a recursive query for `name` and record type `qtype`.
+pub fn query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut message = Vec::with_capacity(HEADER_LEN + name.len() + 6);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // a single question
+    message.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    write_name(&mut message, name);
+    message.extend_from_slice(&qtype.to_be_bytes());
+    message.extend_from_slice(&CLASS_IN.to_be_bytes());
+    message
+}
+
+/// Encode a response with `flags`, the response flag set, echoing `questions` and giving
+/// `answers`. Names are not compressed.
+pub fn response(id: u16, flags: u16, questions: &[Question], answers: &[Record]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(512);
+    message.extend_from_slice(&id.to_be_bytes());
+    message.extend_from_slice(&(flags | FLAG_RESPONSE).to_be_bytes());
+    message.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    message.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    // no authority or additional records
+    message.extend_from_slice(&[0, 0, 0, 0]);
+    for question in questions {
+        write_name(&mut message, &question.name);
+        message.extend_from_slice(&question.qtype.to_be_bytes());
+        message.extend_from_slice(&question.qclass.to_be_bytes());
+    }
+    for record in answers {
+        write_name(&mut message, &record.name);
+        message.extend_from_slice(&record.rtype.to_be_bytes());
+        message.extend_from_slice(&record.class.to_be_bytes());
+        message.extend_from_slice(&record.ttl.to_be_bytes());
+        let mut data = Vec::new();
+        match &record.data {
+            RecordData::A(addr) => data.extend_from_slice(&addr.octets()),
+            RecordData::Aaaa(addr) => data.extend_from_slice(&addr.octets()),
+            RecordData::Name(name) => write_name(&mut data, name),
+            RecordData::Other(other) => data.extend_from_slice(other),
+        }
+        message.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        message.extend_from_slice(&data);
+    }
+    message
+}
+
+/// Append `name` as a sequence of labels, cutting labels longer than 63 bytes.
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        out.push(label.len().min(63) as u8);
+        out.extend_from_slice(&label.as_bytes()[..label.len().min(63)]);
+    }
+    out.push(0);
+}
+
+// varies the transaction IDs of the queries of a process
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// A stub resolver asking name servers over UDP.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolver {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+    attempts: usize,
+}
+
+impl Resolver {
+    /// Ask `servers`, in order, waiting 2 seconds for each answer.
+    pub fn new(servers: Vec<SocketAddr>) -> Resolver {
+        Resolver {
+            servers,
+            timeout: Duration::from_secs(2),
+            attempts: 1,
+        }
+    }
+
+    /// Ask the name servers of `/etc/resolv.conf`.
+    pub fn system() -> io::Result<Resolver> {
+        let config = fs::read_to_string("/etc/resolv.conf")?;
+        let servers: Vec<SocketAddr> = config
+            .lines()
+            .filter_map(|line| {
+                let mut words = line.split_whitespace();
+                match (words.next(), words.next()) {
+                    (Some("nameserver"), Some(addr)) => addr.parse::<IpAddr>().ok(),
+                    _ => None,
+                }
+            })
+            .map(|addr| SocketAddr::new(addr, PORT))
+            .collect();
+        if servers.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "No name server configured",
+            ));
+        }
+        Ok(Resolver::new(servers))
+    }
+
+    /// Wait this long for each answer.
+    pub fn timeout(mut self, timeout: Duration) -> Resolver {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Ask every server this many times before giving up.
+    pub fn attempts(mut self, attempts: usize) -> Resolver {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// The name `addr` points back to, None if it has no PTR record.
+    pub fn reverse(&self, addr: IpAddr) -> io::Result<Option<String>> {
+        let name = reverse_name(addr);
+        let answer = self.ask(&name, TYPE_PTR)?;
+        Ok(answer
+            .answers
+            .into_iter()
+            .find_map(|record| match record.data {
+                RecordData::Name(target) if record.rtype == TYPE_PTR => Some(target),
+                _ => None,
+            }))
+    }
+
+    /// Send a query for `name` to the servers until one answers it.
+    fn ask(&self, name: &str, qtype: u16) -> io::Result<Message> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "No name server configured");
+        for _ in 0..self.attempts {
+            for server in &self.servers {
+                match self.ask_server(*server, name, qtype) {
+                    Ok(message) => return Ok(message),
+                    Err(e) => last_error = e,
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    fn ask_server(&self, server: SocketAddr, name: &str, qtype: u16) -> io::Result<Message> {
+        let local: SocketAddr = match server {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(server)?;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let id = (nanos as u16) ^ NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        socket.send(&query(id, name, qtype))?;
+
+        // stray datagrams don't extend the wait
+        let deadline = Instant::now() + self.timeout;
+        let mut buffer = [0u8; 1500];
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::from_secs(0) {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out"));
+            }
+            socket.set_read_timeout(Some(left))?;
+            let len = match socket.recv(&mut buffer) {
+                Ok(len) => len,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out"))
+                }
+                Err(e) => return Err(e),
+            };
+            // ignore stray and spoofed datagrams rather than failing the lookup
+            let message = match Message::parse(&buffer[..len]) {
+                Some(message) if message.is_response() && message.id == id => message,
+                _ => continue,
+            };
+            if !message
+                .questions
+                .iter()
+                .any(|q| q.qtype == qtype && q.name.eq_ignore_ascii_case(name))
+            {
+                continue;
+            }
+            return match message.rcode() {
+                0 | RCODE_NXDOMAIN => Ok(message),
+                rcode => Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("name server {} answered with rcode {}", server, rcode),
+                )),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn stray_datagrams_dont_extend_the_timeout() {
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = server.local_addr().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let flooding = Arc::clone(&stop);
+        let flood = thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            let (_, client) = server.recv_from(&mut buffer).unwrap();
+            while !flooding.load(Ordering::Relaxed) {
+                server.send_to(b"not a response", client).unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let resolver = Resolver::new(vec![address]).timeout(Duration::from_millis(200));
+        let start = Instant::now();
+        let result = resolver.reverse(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let elapsed = start.elapsed();
+        stop.store(true, Ordering::Relaxed);
+        flood.join().unwrap();
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(elapsed < Duration::from_secs(1), "waited {:?}", elapsed);
+    }
+}
@@ -0,0 +1,235 @@
+//! Differentiated Services Code Point marking (RFC 2474).
+//!
+//! The DSCP occupies the six high bits of the IPv4 ToS / IPv6 traffic class byte, the two
+//! remaining bits belong to ECN and are left untouched by everything in this module.
+
+use crate::{
+    arp::{
+        channel::EthernetDataLinkSender,
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, MutablePacket, Packet},
+        network_interface::NetworkInterface,
+    },
+    ttl,
+};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+/// Represents a DSCP value.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Clone, Copy)]
+pub struct Dscp(pub u8);
+
+impl Dscp {
+    /// Construct a new `Dscp`. Only the six low bits of `value` are kept.
+    pub fn new(value: u8) -> Dscp {
+        Dscp(value & 0x3f)
+    }
+
+    /// Extract the DSCP from a ToS / traffic class byte.
+    pub fn from_tos(tos: u8) -> Dscp {
+        Dscp(tos >> 2)
+    }
+
+    /// Return `tos` with its DSCP bits replaced by this value.
+    pub fn apply(self, tos: u8) -> u8 {
+        (self.0 << 2) | (tos & 0b11)
+    }
+}
+
+impl Default for Dscp {
+    fn default() -> Dscp {
+        Dscps::Cs0
+    }
+}
+
+impl std::fmt::Display for Dscp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            &Dscps::Ef => write!(f, "EF"),
+            &Dscps::VoiceAdmit => write!(f, "VA"),
+            Dscp(v) if v & 0b111 == 0 => write!(f, "CS{}", v >> 3),
+            Dscp(v) if v >> 3 >= 1 && v >> 3 <= 4 && v & 1 == 0 && (v >> 1) & 0b11 != 0 => {
+                write!(f, "AF{}{}", v >> 3, (v >> 1) & 0b11)
+            }
+            Dscp(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Well known DSCP values.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod Dscps {
+    use super::Dscp;
+
+    /// Default forwarding / class selector 0.
+    pub const Cs0: Dscp = Dscp(0);
+    /// Class selector 1 (lower effort).
+    pub const Cs1: Dscp = Dscp(8);
+    /// Class selector 2 (OAM).
+    pub const Cs2: Dscp = Dscp(16);
+    /// Class selector 3 (broadcast video).
+    pub const Cs3: Dscp = Dscp(24);
+    /// Class selector 4 (real-time interactive).
+    pub const Cs4: Dscp = Dscp(32);
+    /// Class selector 5 (signaling).
+    pub const Cs5: Dscp = Dscp(40);
+    /// Class selector 6 (network control).
+    pub const Cs6: Dscp = Dscp(48);
+    /// Class selector 7.
+    pub const Cs7: Dscp = Dscp(56);
+    /// Assured forwarding class 1, low drop precedence.
+    pub const Af11: Dscp = Dscp(10);
+    /// Assured forwarding class 1, medium drop precedence.
+    pub const Af12: Dscp = Dscp(12);
+    /// Assured forwarding class 1, high drop precedence.
+    pub const Af13: Dscp = Dscp(14);
+    /// Assured forwarding class 2, low drop precedence.
+    pub const Af21: Dscp = Dscp(18);
+    /// Assured forwarding class 2, medium drop precedence.
+    pub const Af22: Dscp = Dscp(20);
+    /// Assured forwarding class 2, high drop precedence.
+    pub const Af23: Dscp = Dscp(22);
+    /// Assured forwarding class 3, low drop precedence.
+    pub const Af31: Dscp = Dscp(26);
+    /// Assured forwarding class 3, medium drop precedence.
+    pub const Af32: Dscp = Dscp(28);
+    /// Assured forwarding class 3, high drop precedence.
+    pub const Af33: Dscp = Dscp(30);
+    /// Assured forwarding class 4, low drop precedence.
+    pub const Af41: Dscp = Dscp(34);
+    /// Assured forwarding class 4, medium drop precedence.
+    pub const Af42: Dscp = Dscp(36);
+    /// Assured forwarding class 4, high drop precedence.
+    pub const Af43: Dscp = Dscp(38);
+    /// Expedited forwarding [RFC3246].
+    pub const Ef: Dscp = Dscp(46);
+    /// Voice admit [RFC5865].
+    pub const VoiceAdmit: Dscp = Dscp(44);
+}
+
+/// Return the DSCP of the IPv4 or IPv6 packet carried by `frame`.
+///
+/// Returns None for other ethertypes or truncated headers.
+pub fn frame_dscp(frame: &EthernetPacket) -> Option<Dscp> {
+    let payload = frame.payload();
+    match frame.get_ethertype() {
+        EtherTypes::Ipv4 if payload.len() >= 20 => Some(Dscp::from_tos(payload[1])),
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            Some(Dscp::from_tos((payload[0] << 4) | (payload[1] >> 4)))
+        }
+        _ => None,
+    }
+}
+
+/// Rewrite the DSCP of the IPv4 or IPv6 packet carried by `frame`, fixing up the IPv4
+/// header checksum.
+///
+/// Returns false if the frame doesn't carry an IP packet.
+pub fn set_frame_dscp(frame: &mut MutableEthernetPacket, dscp: Dscp) -> bool {
+    let ethertype = frame.get_ethertype();
+    let payload = frame.payload_mut();
+    match ethertype {
+        EtherTypes::Ipv4 if payload.len() >= 20 => {
+            payload[1] = dscp.apply(payload[1]);
+            ttl::update_ipv4_checksum(payload);
+            true
+        }
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            let tc = dscp.apply((payload[0] << 4) | (payload[1] >> 4));
+            payload[0] = (payload[0] & 0xf0) | (tc >> 4);
+            payload[1] = (payload[1] & 0x0f) | (tc << 4);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// An `EthernetDataLinkSender` that marks every outgoing IP packet with a DSCP.
+///
+/// This is the per-socket counterpart of [set_frame_dscp]; frames that don't carry IP
+/// are sent unchanged.
+///
+/// [set_frame_dscp]: fn.set_frame_dscp.html
+pub struct DscpSender {
+    inner: Box<dyn EthernetDataLinkSender>,
+    dscp: Dscp,
+    buffer: Vec<u8>,
+}
+
+impl DscpSender {
+    /// Wrap `inner`, marking outgoing packets with `dscp`.
+    pub fn new(inner: Box<dyn EthernetDataLinkSender>, dscp: Dscp) -> DscpSender {
+        DscpSender {
+            inner,
+            dscp,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Return the DSCP applied to outgoing packets.
+    pub fn dscp(&self) -> Dscp {
+        self.dscp
+    }
+
+    /// Change the DSCP applied to outgoing packets.
+    pub fn set_dscp(&mut self, dscp: Dscp) {
+        self.dscp = dscp;
+    }
+}
+
+impl EthernetDataLinkSender for DscpSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(packet.packet());
+        let mut marked = MutableEthernetPacket::new(&mut self.buffer[..])?;
+        set_frame_dscp(&mut marked, self.dscp);
+        self.inner.send_to(&marked.to_immutable(), dst)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::network_interface::MacAddr,
+        ecn::Ecn,
+        generate,
+        ipv4::{packet::header_checksum, Ipv4Packet},
+    };
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    #[test]
+    fn marks_frames_and_keeps_ecn() {
+        let mac = MacAddr(0x02, 0, 0, 0, 0, 1);
+        let from = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 4000);
+        let to = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 53);
+        let mut frame = generate::udp_frame(mac, mac, from, to, b"query");
+        frame[15] = Ecn::Ce.apply(frame[15]);
+
+        let mut ethernet = MutableEthernetPacket::new(&mut frame).unwrap();
+        assert!(set_frame_dscp(&mut ethernet, Dscps::Af21));
+        let ethernet = ethernet.to_immutable();
+        assert_eq!(frame_dscp(&ethernet), Some(Dscps::Af21));
+        let ip = Ipv4Packet::new(ethernet.payload()).unwrap();
+        assert_eq!(Ecn::from_tos(ethernet.payload()[1]), Ecn::Ce);
+        assert_eq!(header_checksum(&ip), ip.get_checksum());
+    }
+
+    #[test]
+    fn names_well_known_values() {
+        assert_eq!(Dscps::Ef.to_string(), "EF");
+        assert_eq!(Dscps::Cs6.to_string(), "CS6");
+        assert_eq!(Dscps::Af32.to_string(), "AF32");
+        assert_eq!(Dscp::new(0xff).to_string(), "63");
+    }
+}
@@ -0,0 +1,303 @@
+//! Explicit Congestion Notification support (RFC 3168).
+//!
+//! The IP part is the two-bit codepoint carried in the low bits of the IPv4 ToS /
+//! IPv6 traffic class byte, the TCP part is the ECE/CWR flag negotiation and echo logic.
+
+/// Mask of the ECN bits inside the ToS / traffic class byte.
+pub const ECN_MASK: u8 = 0b0000_0011;
+
+/// TCP ECN-Echo flag.
+pub const TCP_FLAG_ECE: u8 = 0x40;
+/// TCP Congestion Window Reduced flag.
+pub const TCP_FLAG_CWR: u8 = 0x80;
+
+/// ECN codepoint of an IP packet.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Ecn {
+    /// Not ECN-Capable Transport
+    NotEct,
+    /// ECN-Capable Transport, ECT(1)
+    Ect1,
+    /// ECN-Capable Transport, ECT(0)
+    Ect0,
+    /// Congestion Experienced
+    Ce,
+}
+
+impl Ecn {
+    /// Extract the codepoint from a ToS / traffic class byte.
+    pub fn from_tos(tos: u8) -> Ecn {
+        Ecn::from(tos)
+    }
+
+    /// Return `tos` with its ECN bits replaced by this codepoint.
+    pub fn apply(self, tos: u8) -> u8 {
+        (tos & !ECN_MASK) | u8::from(self)
+    }
+
+    /// Whether the sender declared the transport ECN-capable.
+    pub fn is_ect(self) -> bool {
+        match self {
+            Ecn::Ect0 | Ecn::Ect1 | Ecn::Ce => true,
+            Ecn::NotEct => false,
+        }
+    }
+}
+
+impl Default for Ecn {
+    fn default() -> Ecn {
+        Ecn::NotEct
+    }
+}
+
+impl From<u8> for Ecn {
+    fn from(value: u8) -> Self {
+        match value & ECN_MASK {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+}
+
+impl From<Ecn> for u8 {
+    fn from(value: Ecn) -> Self {
+        match value {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+impl std::fmt::Display for Ecn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Ecn::NotEct => "Not-ECT",
+                Ecn::Ect1 => "ECT(1)",
+                Ecn::Ect0 => "ECT(0)",
+                Ecn::Ce => "CE",
+            }
+        )
+    }
+}
+
+/// Per-connection ECN configuration.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Config {
+    /// Whether to negotiate ECN on the connection. Defaults to false
+    pub enabled: bool,
+
+    /// Codepoint to mark outgoing data segments with once ECN is negotiated.
+    /// Defaults to ECT(0)
+    pub codepoint: Ecn,
+
+    /// Whether to accept ECN when the peer initiates it even if `enabled` is false.
+    /// Defaults to true
+    pub accept_incoming: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            enabled: false,
+            codepoint: Ecn::Ect0,
+            accept_incoming: true,
+        }
+    }
+}
+
+/// ECN state of a single TCP connection.
+///
+/// The state tracks negotiation during the handshake, the ECE echo after a CE mark has
+/// been received and the CWR acknowledgement after the local congestion window has been
+/// reduced.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Connection {
+    config: Config,
+    negotiated: bool,
+    echo_pending: bool,
+    cwr_pending: bool,
+    /// Next sequence number to send when the window was last reduced; ECE doesn't reduce
+    /// it again until that is acknowledged
+    recover: Option<u32>,
+}
+
+impl Connection {
+    /// Construct a new `Connection` with the given configuration.
+    pub fn new(config: Config) -> Connection {
+        Connection {
+            config,
+            negotiated: false,
+            echo_pending: false,
+            cwr_pending: false,
+            recover: None,
+        }
+    }
+
+    /// Return the connection configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Whether both endpoints agreed to use ECN.
+    pub fn is_negotiated(&self) -> bool {
+        self.negotiated
+    }
+
+    /// Flags to add to an active open SYN.
+    pub fn syn_flags(&self) -> u8 {
+        if self.config.enabled {
+            TCP_FLAG_ECE | TCP_FLAG_CWR
+        } else {
+            0
+        }
+    }
+
+    /// Process the flags of a received SYN and return the flags to add to the SYN-ACK.
+    pub fn on_syn(&mut self, flags: u8) -> u8 {
+        let requested = flags & (TCP_FLAG_ECE | TCP_FLAG_CWR) == TCP_FLAG_ECE | TCP_FLAG_CWR;
+        self.negotiated = requested && (self.config.enabled || self.config.accept_incoming);
+        if self.negotiated {
+            TCP_FLAG_ECE
+        } else {
+            0
+        }
+    }
+
+    /// Process the flags of a received SYN-ACK answering our SYN.
+    pub fn on_syn_ack(&mut self, flags: u8) {
+        self.negotiated =
+            self.config.enabled && flags & (TCP_FLAG_ECE | TCP_FLAG_CWR) == TCP_FLAG_ECE;
+    }
+
+    /// Codepoint to put into the IP header of an outgoing segment.
+    ///
+    /// Pure acknowledgements and retransmissions must not be marked ECN-capable.
+    pub fn outgoing_codepoint(&self, has_payload: bool, retransmission: bool) -> Ecn {
+        if self.negotiated && has_payload && !retransmission {
+            self.config.codepoint
+        } else {
+            Ecn::NotEct
+        }
+    }
+
+    /// Process a received non-SYN segment acknowledging up to `ack`.
+    ///
+    /// Returns true when the peer signalled congestion with ECE and the caller should
+    /// reduce its congestion window (and then call [on_window_reduced]). The window is
+    /// reduced at most once per window of data (RFC 3168, 6.1.2): ECE is ignored until
+    /// what was sent before the last reduction is acknowledged.
+    ///
+    /// [on_window_reduced]: #method.on_window_reduced
+    pub fn on_segment(&mut self, ip_codepoint: Ecn, flags: u8, ack: u32) -> bool {
+        if !self.negotiated {
+            return false;
+        }
+        if flags & TCP_FLAG_CWR != 0 {
+            self.echo_pending = false;
+        }
+        if ip_codepoint == Ecn::Ce {
+            self.echo_pending = true;
+        }
+        if flags & TCP_FLAG_ECE == 0 {
+            return false;
+        }
+        match self.recover {
+            // acknowledges data sent after the reduction
+            Some(recover) => ack != recover && ack.wrapping_sub(recover) < 1 << 31,
+            None => true,
+        }
+    }
+
+    /// Record that the congestion window was reduced in response to ECE, with `snd_nxt`
+    /// the next sequence number to send.
+    pub fn on_window_reduced(&mut self, snd_nxt: u32) {
+        if self.negotiated {
+            self.cwr_pending = true;
+            self.recover = Some(snd_nxt);
+        }
+    }
+
+    /// Flags to add to the next outgoing non-SYN segment.
+    ///
+    /// The CWR flag is sent only once per window reduction.
+    pub fn outgoing_flags(&mut self, has_payload: bool) -> u8 {
+        if !self.negotiated {
+            return 0;
+        }
+        let mut flags = 0;
+        if self.echo_pending {
+            flags |= TCP_FLAG_ECE;
+        }
+        if self.cwr_pending && has_payload {
+            flags |= TCP_FLAG_CWR;
+            self.cwr_pending = false;
+        }
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_when_asked() {
+        let mut passive = Connection::new(Config::default());
+        assert_eq!(passive.on_syn(TCP_FLAG_ECE | TCP_FLAG_CWR), TCP_FLAG_ECE);
+        assert!(passive.is_negotiated());
+
+        let mut refusing = Connection::new(Config {
+            accept_incoming: false,
+            ..Default::default()
+        });
+        assert_eq!(refusing.on_syn(TCP_FLAG_ECE | TCP_FLAG_CWR), 0);
+        assert_eq!(refusing.outgoing_codepoint(true, false), Ecn::NotEct);
+
+        let mut active = Connection::new(Config {
+            enabled: true,
+            ..Default::default()
+        });
+        assert_eq!(active.syn_flags(), TCP_FLAG_ECE | TCP_FLAG_CWR);
+        active.on_syn_ack(TCP_FLAG_ECE);
+        assert!(active.is_negotiated());
+        assert_eq!(active.outgoing_codepoint(true, false), Ecn::Ect0);
+        assert_eq!(active.outgoing_codepoint(false, false), Ecn::NotEct);
+        assert_eq!(active.outgoing_codepoint(true, true), Ecn::NotEct);
+    }
+
+    #[test]
+    fn reduces_once_per_window() {
+        let mut connection = Connection::new(Config::default());
+        connection.on_syn(TCP_FLAG_ECE | TCP_FLAG_CWR);
+
+        assert!(connection.on_segment(Ecn::NotEct, TCP_FLAG_ECE, 1000));
+        connection.on_window_reduced(5000);
+        assert_eq!(connection.outgoing_flags(true), TCP_FLAG_CWR);
+        assert_eq!(connection.outgoing_flags(true), 0);
+        // the echo goes on until the peer sees our CWR
+        assert!(!connection.on_segment(Ecn::NotEct, TCP_FLAG_ECE, 2000));
+        assert!(!connection.on_segment(Ecn::NotEct, TCP_FLAG_ECE, 5000));
+        assert!(connection.on_segment(Ecn::NotEct, TCP_FLAG_ECE, 5001));
+    }
+
+    #[test]
+    fn echoes_ce_until_cwr() {
+        let mut connection = Connection::new(Config::default());
+        connection.on_syn(TCP_FLAG_ECE | TCP_FLAG_CWR);
+        assert_eq!(connection.outgoing_flags(false), 0);
+
+        connection.on_segment(Ecn::Ce, 0, 0);
+        assert_eq!(connection.outgoing_flags(false), TCP_FLAG_ECE);
+        connection.on_segment(Ecn::Ect0, 0, 0);
+        assert_eq!(connection.outgoing_flags(false), TCP_FLAG_ECE);
+        connection.on_segment(Ecn::Ect0, TCP_FLAG_CWR, 0);
+        assert_eq!(connection.outgoing_flags(false), 0);
+    }
+}
@@ -0,0 +1,108 @@
+//! The crate's error type.
+//!
+//! Failed system calls come back as [`Error::Io`], apart from the failures callers tell
+//! apart: an interface that doesn't exist and privileges the process lacks. Errors convert
+//! to and from `io::Error` both ways, so `?` mixes the crate's [`Result`] with `io::Result`
+//! code, and the packet parsers' [`arp::Error`] converts to [`Error::Parse`].
+
+use crate::arp::arp;
+use std::{error, fmt, io};
+
+/// What went wrong.
+#[derive(Debug)]
+pub enum Error {
+    /// A system call failed
+    Io(io::Error),
+    /// A packet could not be parsed
+    Parse(arp::Error),
+    /// No interface has the name
+    InterfaceNotFound(String),
+    /// The process lacks the privileges the operation needs, as the system call said
+    PermissionDenied(io::Error),
+}
+
+/// A `Result` with the crate's [`Error`].
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The `io::ErrorKind` the error converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::Io(e) => e.kind(),
+            Error::Parse(_) => io::ErrorKind::InvalidData,
+            Error::InterfaceNotFound(_) => io::ErrorKind::NotFound,
+            Error::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) | Error::PermissionDenied(e) => write!(f, "{}", e),
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::InterfaceNotFound(name) => write!(f, "no such interface: {}", name),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) | Error::PermissionDenied(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::InterfaceNotFound(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(e),
+            _ => Error::Io(e),
+        }
+    }
+}
+
+impl From<arp::Error> for Error {
+    fn from(e: arp::Error) -> Error {
+        Error::Parse(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) | Error::PermissionDenied(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipv4;
+
+    #[test]
+    fn parse_errors_convert() {
+        // a header length of 24 in a 20 byte packet
+        let mut packet = [0u8; 20];
+        packet[0] = 0x46;
+        let e = Error::from(ipv4::options(&packet).unwrap_err());
+        assert!(matches!(e, Error::Parse(arp::Error::Truncated { .. })));
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let e = io::Error::from(e);
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.into_inner().unwrap().downcast::<Error>().is_ok());
+    }
+
+    #[test]
+    fn permission_denied_keeps_the_io_error() {
+        let e = Error::from(io::Error::from_raw_os_error(1));
+        assert!(matches!(e, Error::PermissionDenied(_)));
+        assert!(error::Error::source(&e).is_some());
+        assert_eq!(io::Error::from(e).raw_os_error(), Some(1));
+    }
+}
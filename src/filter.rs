@@ -0,0 +1,691 @@
+//! Packet filtering in userspace.
+//!
+//! A [`Filter`] is a predicate over Ethernet frames built from primitives (ethertype,
+//! VLAN, MAC and IP addresses, ports, direction) and the usual boolean combinators. It
+//! can be wrapped around any receiver with [`FilteredReceiver`], around the iterator of
+//! one with `filtered`, or applied to the records of a capture file with
+//! `pcap::Reader::filtered`, for when attaching a kernel BPF program is not possible or
+//! cannot express the condition.
+//!
+//! A [`RuleSet`] is an ordered list of filters with an allow or deny action each, like a
+//! firewall's: the first rule matching a frame decides its fate, a default action the
+//! fate of frames no rule matches.
+
+use crate::{
+    address::Endpoint,
+    arp::{
+        channel::{EthernetDataLinkChannelIterator, EthernetDataLinkReceiver, RxMeta},
+        ether::{EtherType, EtherTypes, EthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    cidr::IpCidr,
+    ipproto::{IPPROTO_TCP, IPPROTO_UDP},
+    pcap, vlan,
+};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::{
+    borrow::BorrowMut,
+    io::{self, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops,
+};
+
+const IPPROTO_SCTP: u8 = 132;
+
+/// Which address or port of a frame a primitive looks at.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Side {
+    /// The source
+    Src,
+    /// The destination
+    Dst,
+    /// Either of them
+    Any,
+}
+
+impl Side {
+    fn matches<T, F: Fn(&T) -> bool>(self, src: T, dst: T, predicate: F) -> bool {
+        match self {
+            Side::Src => predicate(&src),
+            Side::Dst => predicate(&dst),
+            Side::Any => predicate(&src) || predicate(&dst),
+        }
+    }
+}
+
+/// Direction of a frame relative to a local MAC address.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    /// Frames not sent from the local address
+    Inbound,
+    /// Frames sent from the local address
+    Outbound,
+}
+
+/// A predicate over Ethernet frames.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Filter {
+    /// Matches every frame
+    All,
+    /// Matches frames whose outer ethertype, or the one following their VLAN tags, is the
+    /// given one
+    EtherType(EtherType),
+    /// Matches VLAN tagged frames, optionally only those with the given VLAN ID in any of
+    /// their tags
+    Vlan(Option<u16>),
+    /// Matches frames by MAC address
+    Mac(Side, MacAddr),
+    /// Matches IPv4 and IPv6 packets by network
+    Net(Side, IpCidr),
+    /// Matches IPv4 and IPv6 packets by IP protocol number
+    IpProto(u8),
+    /// Matches TCP, UDP and SCTP packets by port
+    Port(Side, u16),
+    /// Matches TCP, UDP and SCTP packets by address and port on the same side; an
+    /// unspecified address or a zero port matches any
+    Endpoint(Side, Endpoint),
+    /// Matches frames by direction relative to the given local address
+    Direction(Direction, MacAddr),
+    /// Negation
+    Not(Box<Filter>),
+    /// Conjunction
+    And(Box<Filter>, Box<Filter>),
+    /// Disjunction
+    Or(Box<Filter>, Box<Filter>),
+}
+
+impl Filter {
+    /// Matches IP packets from or to `network`.
+    pub fn net(network: IpCidr) -> Filter {
+        Filter::Net(Side::Any, network)
+    }
+
+    /// Matches IP packets from or to `addr`.
+    pub fn host(addr: IpAddr) -> Filter {
+        Filter::Net(Side::Any, IpCidr::host(addr))
+    }
+
+    /// Matches TCP, UDP and SCTP packets from or to `port`.
+    pub fn port(port: u16) -> Filter {
+        Filter::Port(Side::Any, port)
+    }
+
+    /// Matches TCP, UDP and SCTP packets from or to `endpoint`.
+    pub fn endpoint(endpoint: Endpoint) -> Filter {
+        Filter::Endpoint(Side::Any, endpoint)
+    }
+
+    /// Matches frames that satisfy both `self` and `other`.
+    pub fn and(self, other: Filter) -> Filter {
+        Filter::And(Box::new(self), Box::new(other))
+    }
+
+    /// Matches frames that satisfy `self` or `other`.
+    pub fn or(self, other: Filter) -> Filter {
+        Filter::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Whether `packet` satisfies the filter.
+    pub fn matches(&self, packet: &EthernetPacket) -> bool {
+        self.matches_frame(packet.packet())
+    }
+
+    /// Whether the raw Ethernet frame `frame` satisfies the filter. Frames shorter than an
+    /// Ethernet header never do.
+    pub fn matches_frame(&self, frame: &[u8]) -> bool {
+        match Headers::parse(frame) {
+            Some(headers) => self.eval(&headers),
+            None => false,
+        }
+    }
+
+    fn eval(&self, headers: &Headers) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::EtherType(ethertype) => {
+                headers.outer_ethertype == *ethertype || headers.ethertype == *ethertype
+            }
+            Filter::Vlan(None) => headers.vlans.iter().any(Option::is_some),
+            Filter::Vlan(Some(id)) => headers.vlans.contains(&Some(*id)),
+            Filter::Mac(side, mac) => side.matches(headers.src_mac, headers.dst_mac, |m| m == mac),
+            Filter::Net(side, network) => match headers.ip {
+                Some((src, dst, _)) => side.matches(src, dst, |addr| network.contains(*addr)),
+                None => false,
+            },
+            Filter::IpProto(protocol) => headers.ip.map_or(false, |(_, _, p)| p == *protocol),
+            Filter::Port(side, port) => match headers.ports {
+                Some((src, dst)) => side.matches(src, dst, |p| p == port),
+                None => false,
+            },
+            Filter::Endpoint(side, endpoint) => match (headers.ip, headers.ports) {
+                (Some((src, dst, _)), Some((src_port, dst_port))) => side.matches(
+                    Endpoint::new(src, src_port),
+                    Endpoint::new(dst, dst_port),
+                    |seen| {
+                        (endpoint.addr.is_unspecified() || seen.addr == endpoint.addr)
+                            && (endpoint.port == 0 || seen.port == endpoint.port)
+                    },
+                ),
+                _ => false,
+            },
+            Filter::Direction(direction, local) => {
+                let outbound = headers.src_mac == *local;
+                match direction {
+                    Direction::Inbound => !outbound,
+                    Direction::Outbound => outbound,
+                }
+            }
+            Filter::Not(inner) => !inner.eval(headers),
+            Filter::And(left, right) => left.eval(headers) && right.eval(headers),
+            Filter::Or(left, right) => left.eval(headers) || right.eval(headers),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::All
+    }
+}
+
+impl ops::Not for Filter {
+    type Output = Filter;
+
+    fn not(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+}
+
+impl ops::BitAnd for Filter {
+    type Output = Filter;
+
+    fn bitand(self, other: Filter) -> Filter {
+        self.and(other)
+    }
+}
+
+impl ops::BitOr for Filter {
+    type Output = Filter;
+
+    fn bitor(self, other: Filter) -> Filter {
+        self.or(other)
+    }
+}
+
+/// What a rule does with the frames it matches.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Action {
+    /// Let the frame through
+    Allow,
+    /// Drop the frame
+    Deny,
+}
+
+/// A filter and what to do with the frames it matches.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Rule {
+    pub filter: Filter,
+    pub action: Action,
+}
+
+impl Rule {
+    /// Let the frames matching `filter` through.
+    pub fn allow(filter: Filter) -> Rule {
+        Rule {
+            filter,
+            action: Action::Allow,
+        }
+    }
+
+    /// Drop the frames matching `filter`.
+    pub fn deny(filter: Filter) -> Rule {
+        Rule {
+            filter,
+            action: Action::Deny,
+        }
+    }
+}
+
+/// Rules evaluated in order, the first one matching a frame deciding what happens to it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    default: Action,
+}
+
+impl RuleSet {
+    /// No rules, `default` applying to every frame.
+    pub fn new(default: Action) -> RuleSet {
+        RuleSet {
+            rules: Vec::new(),
+            default,
+        }
+    }
+
+    /// Add `rule` after the others.
+    pub fn rule(mut self, rule: Rule) -> RuleSet {
+        self.push(rule);
+        self
+    }
+
+    /// Add `rule` after the others.
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// The rules, in the order they are evaluated.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The action of frames no rule matches.
+    pub fn default_action(&self) -> Action {
+        self.default
+    }
+
+    /// What happens to `packet`.
+    pub fn evaluate(&self, packet: &EthernetPacket) -> Action {
+        self.evaluate_frame(packet.packet())
+    }
+
+    /// What happens to the raw Ethernet frame `frame`. Frames shorter than an Ethernet
+    /// header are denied.
+    pub fn evaluate_frame(&self, frame: &[u8]) -> Action {
+        let headers = match Headers::parse(frame) {
+            Some(headers) => headers,
+            None => return Action::Deny,
+        };
+        self.rules
+            .iter()
+            .find(|rule| rule.filter.eval(&headers))
+            .map_or(self.default, |rule| rule.action)
+    }
+
+    /// Whether `packet` is let through.
+    pub fn allows(&self, packet: &EthernetPacket) -> bool {
+        self.evaluate(packet) == Action::Allow
+    }
+}
+
+impl Default for RuleSet {
+    /// No rules, every frame allowed.
+    fn default() -> RuleSet {
+        RuleSet::new(Action::Allow)
+    }
+}
+
+/// The header fields filters look at, extracted once per frame.
+struct Headers {
+    src_mac: MacAddr,
+    dst_mac: MacAddr,
+    outer_ethertype: EtherType,
+    /// VLAN IDs of up to two tags, outermost first
+    vlans: [Option<u16>; 2],
+    /// Ethertype following the VLAN tags
+    ethertype: EtherType,
+    /// Source, destination and protocol of an IP packet
+    ip: Option<(IpAddr, IpAddr, u8)>,
+    /// Source and destination port of a TCP, UDP or SCTP packet
+    ports: Option<(u16, u16)>,
+}
+
+impl Headers {
+    fn parse(frame: &[u8]) -> Option<Headers> {
+        let ethernet = EthernetPacket::new(frame)?;
+        let outer_ethertype = ethernet.get_ethertype();
+        let mut ethertype = outer_ethertype;
+        let mut payload = ethernet.payload();
+        let mut vlans = [None; 2];
+        for vlan in vlans.iter_mut() {
+            if !vlan::is_tag(ethertype) || payload.len() < 4 {
+                break;
+            }
+            *vlan = Some(u16::from_be_bytes([payload[0], payload[1]]) & 0x0fff);
+            ethertype = EtherType::new(u16::from_be_bytes([payload[2], payload[3]]));
+            payload = &payload[4..];
+        }
+
+        let (ip, transport) = match ethertype {
+            EtherTypes::Ipv4 if payload.len() >= 20 => {
+                let ihl = (payload[0] & 0x0f) as usize * 4;
+                let fragment_offset = u16::from_be_bytes([payload[6], payload[7]]) & 0x1fff;
+                let src = Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]);
+                let dst = Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]);
+                // later fragments carry no transport header
+                let transport = match fragment_offset {
+                    0 => payload.get(ihl..).unwrap_or(&[]),
+                    _ => &[],
+                };
+                (
+                    Some((IpAddr::V4(src), IpAddr::V4(dst), payload[9])),
+                    transport,
+                )
+            }
+            EtherTypes::Ipv6 if payload.len() >= 40 => {
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&payload[8..24]);
+                dst.copy_from_slice(&payload[24..40]);
+                let ip = (
+                    IpAddr::V6(Ipv6Addr::from(src)),
+                    IpAddr::V6(Ipv6Addr::from(dst)),
+                    payload[6],
+                );
+                (Some(ip), &payload[40..])
+            }
+            _ => (None, &[][..]),
+        };
+        let ports = match ip {
+            Some((_, _, IPPROTO_TCP)) | Some((_, _, IPPROTO_UDP)) | Some((_, _, IPPROTO_SCTP))
+                if transport.len() >= 4 =>
+            {
+                Some((
+                    u16::from_be_bytes([transport[0], transport[1]]),
+                    u16::from_be_bytes([transport[2], transport[3]]),
+                ))
+            }
+            _ => None,
+        };
+
+        Some(Headers {
+            src_mac: ethernet.get_source(),
+            dst_mac: ethernet.get_destination(),
+            outer_ethertype,
+            vlans,
+            ethertype,
+            ip,
+            ports,
+        })
+    }
+}
+
+// What a filtering iterator checks frames against.
+trait Matcher {
+    fn matches(&self, packet: &EthernetPacket) -> bool;
+}
+
+impl Matcher for Filter {
+    fn matches(&self, packet: &EthernetPacket) -> bool {
+        Filter::matches(self, packet)
+    }
+}
+
+impl Matcher for Predicate {
+    fn matches(&self, packet: &EthernetPacket) -> bool {
+        Predicate::matches(self, packet)
+    }
+}
+
+/// What a [`FilteredReceiver`] lets through.
+enum Predicate {
+    Filter(Filter),
+    Rules(RuleSet),
+}
+
+impl Predicate {
+    fn matches(&self, packet: &EthernetPacket) -> bool {
+        match self {
+            Predicate::Filter(filter) => filter.matches(packet),
+            Predicate::Rules(rules) => rules.allows(packet),
+        }
+    }
+}
+
+/// A receiver that only yields the frames of another receiver matching a filter, or
+/// allowed by rules.
+pub struct FilteredReceiver {
+    inner: Box<dyn EthernetDataLinkReceiver>,
+    filter: Predicate,
+    read_buffer: Vec<u8>,
+}
+
+impl FilteredReceiver {
+    /// Wrap `inner`, dropping the frames that don't satisfy `filter`.
+    pub fn new(inner: Box<dyn EthernetDataLinkReceiver>, filter: Filter) -> FilteredReceiver {
+        FilteredReceiver {
+            inner,
+            filter: Predicate::Filter(filter),
+            read_buffer: Vec::new(),
+        }
+    }
+
+    /// Wrap `inner`, dropping the frames `rules` deny.
+    pub fn with_rules(
+        inner: Box<dyn EthernetDataLinkReceiver>,
+        rules: RuleSet,
+    ) -> FilteredReceiver {
+        FilteredReceiver {
+            inner,
+            filter: Predicate::Rules(rules),
+            read_buffer: Vec::new(),
+        }
+    }
+
+    /// Return the wrapped receiver.
+    pub fn into_inner(self) -> Box<dyn EthernetDataLinkReceiver> {
+        self.inner
+    }
+}
+
+impl EthernetDataLinkReceiver for FilteredReceiver {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(FilteredChannelIterator {
+            inner: self.inner.iter(),
+            filter: &self.filter,
+            read_buffer: &mut self.read_buffer,
+        })
+    }
+
+    fn queue(&self) -> Option<u16> {
+        self.inner.queue()
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+/// Only yield the frames of `inner` satisfying `filter`.
+pub(crate) fn filter_iter<'a>(
+    inner: Box<dyn EthernetDataLinkChannelIterator<'a> + 'a>,
+    filter: &'a Filter,
+) -> Box<dyn EthernetDataLinkChannelIterator<'a> + 'a> {
+    Box::new(FilteredChannelIterator {
+        inner,
+        filter,
+        read_buffer: Vec::new(),
+    })
+}
+
+// The read buffer is the receiver's when the receiver filters, so that it outlives the
+// iterators, and the iterator's own when it is wrapped.
+struct FilteredChannelIterator<'a, B> {
+    inner: Box<dyn EthernetDataLinkChannelIterator<'a> + 'a>,
+    filter: &'a dyn Matcher,
+    read_buffer: B,
+}
+
+impl<'a, B: BorrowMut<Vec<u8>>> EthernetDataLinkChannelIterator<'a>
+    for FilteredChannelIterator<'a, B>
+{
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        // errors, timeouts included, are passed on so callers can still notice them
+        loop {
+            let packet = self.inner.next()?;
+            if self.filter.matches(&packet) {
+                // the frame is copied out as it borrows the inner iterator
+                let read_buffer = self.read_buffer.borrow_mut();
+                read_buffer.clear();
+                read_buffer.extend_from_slice(packet.packet());
+                break;
+            }
+        }
+        EthernetPacket::new(&self.read_buffer.borrow()[..])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))
+    }
+
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let meta = loop {
+            let (packet, meta) = self.inner.next_with_meta()?;
+            if self.filter.matches(&packet) {
+                let read_buffer = self.read_buffer.borrow_mut();
+                read_buffer.clear();
+                read_buffer.extend_from_slice(packet.packet());
+                break meta;
+            }
+        };
+        let packet = EthernetPacket::new(&self.read_buffer.borrow()[..])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))?;
+        Ok((packet, meta))
+    }
+
+    fn next_batch(&mut self, max: usize) -> io::Result<Vec<EthernetPacket>> {
+        let filter = self.filter;
+        let mut packets = self.inner.next_batch(max)?;
+        packets.retain(|packet| filter.matches(packet));
+        Ok(packets)
+    }
+}
+
+/// Iterate over the records of an Ethernet capture that satisfy `filter`.
+///
+/// Read errors are passed through.
+pub fn filter_records<R: Read>(
+    reader: pcap::Reader<R>,
+    filter: Filter,
+) -> impl Iterator<Item = io::Result<pcap::Record>> {
+    reader.filter(move |record| match record {
+        Ok(record) => filter.matches_frame(&record.data),
+        Err(_) => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::{
+            channel::{Channel, Config, EthernetDataLinkSender},
+            loopback::Loopback,
+        },
+        generate,
+    };
+    use std::{net::SocketAddrV4, time::Duration, time::UNIX_EPOCH};
+
+    const LOCAL: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+    const REMOTE: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 2);
+
+    // a datagram from 10.0.0.1:`from` to 10.0.0.2:`to`
+    fn datagram(from: u16, to: u16) -> Vec<u8> {
+        let from = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), from);
+        let to = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), to);
+        generate::udp_frame(LOCAL, REMOTE, from, to, b"payload")
+    }
+
+    // the destination port of a datagram made by `datagram`
+    fn port(frame: &[u8]) -> u16 {
+        u16::from_be_bytes([frame[36], frame[37]])
+    }
+
+    #[test]
+    fn primitives_and_combinators() {
+        let frame = datagram(4000, 53);
+        let dns = Filter::Port(Side::Dst, 53);
+        assert!(dns.matches_frame(&frame));
+        assert!(!Filter::Port(Side::Src, 53).matches_frame(&frame));
+        assert!(Filter::EtherType(EtherTypes::Ipv4).matches_frame(&frame));
+        assert!(Filter::IpProto(IPPROTO_UDP).matches_frame(&frame));
+        assert!(Filter::Mac(Side::Src, LOCAL).matches_frame(&frame));
+        assert!(Filter::Direction(Direction::Outbound, LOCAL).matches_frame(&frame));
+        assert!(Filter::Direction(Direction::Inbound, REMOTE).matches_frame(&frame));
+        let network = IpCidr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 24);
+        assert!(Filter::net(network).matches_frame(&frame));
+        assert!(!Filter::host(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3))).matches_frame(&frame));
+        assert!(!Filter::Vlan(None).matches_frame(&frame));
+
+        assert!((dns.clone() & Filter::All).matches_frame(&frame));
+        assert!(!(dns.clone() & !Filter::All).matches_frame(&frame));
+        assert!((!dns.clone() | Filter::port(4000)).matches_frame(&frame));
+        assert!(!dns.matches_frame(&frame[..14]));
+    }
+
+    #[test]
+    fn vlan_tags_are_looked_through() {
+        let untagged = datagram(4000, 53);
+        let mut frame = untagged[..12].to_vec();
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x2a]);
+        frame.extend_from_slice(&untagged[12..]);
+        assert!(Filter::Vlan(None).matches_frame(&frame));
+        assert!(Filter::Vlan(Some(42)).matches_frame(&frame));
+        assert!(!Filter::Vlan(Some(43)).matches_frame(&frame));
+        assert!(Filter::EtherType(EtherTypes::Ipv4).matches_frame(&frame));
+        assert!(Filter::port(53).matches_frame(&frame));
+    }
+
+    #[test]
+    fn rules_decide_in_order() {
+        let rules = RuleSet::new(Action::Deny)
+            .rule(Rule::deny(Filter::port(22)))
+            .rule(Rule::allow(Filter::EtherType(EtherTypes::Ipv4)));
+        assert_eq!(rules.evaluate_frame(&datagram(4000, 53)), Action::Allow);
+        assert_eq!(rules.evaluate_frame(&datagram(4000, 22)), Action::Deny);
+        assert_eq!(rules.evaluate_frame(&[0u8; 14]), Action::Deny);
+    }
+
+    #[test]
+    fn receiver_iterators_are_filtered() {
+        let device = Loopback::new();
+        let config = Config {
+            read_timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let (mut tx, mut rx) = match device.channel(config).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let send = |tx: &mut Box<dyn EthernetDataLinkSender>| {
+            for to in &[80, 53, 443, 53] {
+                tx.send_to(&EthernetPacket::new(&datagram(4000, *to)).unwrap(), None);
+            }
+        };
+
+        send(&mut tx);
+        let filter = Filter::port(53);
+        let mut iter = rx.iter().filtered(&filter);
+        for _ in 0..2 {
+            assert_eq!(port(iter.next().unwrap().packet()), 53);
+        }
+        assert_eq!(iter.next().unwrap_err().kind(), io::ErrorKind::TimedOut);
+        drop(iter);
+
+        send(&mut tx);
+        let rules = RuleSet::new(Action::Allow).rule(Rule::deny(Filter::port(53)));
+        let mut rx = FilteredReceiver::with_rules(rx, rules);
+        let mut iter = rx.iter();
+        for expected in &[80, 443] {
+            assert_eq!(port(iter.next().unwrap().packet()), *expected);
+        }
+        assert_eq!(iter.next().unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn capture_records_are_filtered() {
+        let mut file = Vec::new();
+        let mut writer = pcap::Writer::new(&mut file).unwrap();
+        for to in &[80, 53, 443, 53] {
+            writer
+                .write_packet(UNIX_EPOCH, &datagram(4000, *to))
+                .unwrap();
+        }
+        drop(writer);
+
+        let reader = pcap::Reader::new(&file[..]).unwrap();
+        let ports: Vec<_> = reader
+            .filtered(!Filter::port(53))
+            .map(|record| port(&record.unwrap().data))
+            .collect();
+        assert_eq!(ports, [80, 443]);
+    }
+}
@@ -0,0 +1,675 @@
+//! Flow tracking.
+//!
+//! A [`FlowTable`] aggregates captured frames into bidirectional flows keyed by protocol
+//! and the two endpoints. Every flow counts packets and bytes in each direction, the TCP
+//! flags seen and, for TCP, a coarse connection state and the JA3/JA3S fingerprints of a
+//! TLS handshake. Flows are expired NetFlow style: after being idle for a while, after
+//! being active for too long (the record is exported and counting starts over) or shortly
+//! after a TCP connection closed.
+
+use crate::arp::ether::{EtherType, EtherTypes, EthernetPacket, Packet};
+use crate::ipproto::{IPPROTO_TCP, IPPROTO_UDP};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::metrics::{Counter, Gauge, Metrics};
+#[cfg(feature = "tls")]
+use crate::{reassembly::Stream, tls};
+#[cfg(feature = "tls")]
+use std::collections::hash_map::Entry;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, SystemTime},
+};
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE: u8 = 22;
+/// Longest TLS record a hello is reassembled from, the header and the largest plaintext.
+#[cfg(feature = "tls")]
+const MAX_HELLO_RECORD: usize = 5 + (1 << 14);
+
+/// Identifies a flow regardless of direction: the endpoints are stored in ascending order.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct FlowKey {
+    /// IP protocol number
+    pub protocol: u8,
+    /// Lower endpoint
+    pub low: SocketAddr,
+    /// Higher endpoint
+    pub high: SocketAddr,
+}
+
+impl FlowKey {
+    /// Key of the flow between `a` and `b`.
+    pub fn new(protocol: u8, a: SocketAddr, b: SocketAddr) -> FlowKey {
+        let (low, high) = if a <= b { (a, b) } else { (b, a) };
+        FlowKey {
+            protocol,
+            low,
+            high,
+        }
+    }
+}
+
+/// Header fields of a frame relevant to flow tracking.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FlowPacket {
+    /// IP protocol number
+    pub protocol: u8,
+    /// Sender; the port is zero for protocols without ports
+    pub src: SocketAddr,
+    /// Receiver; the port is zero for protocols without ports
+    pub dst: SocketAddr,
+    /// Length of the IP packet
+    pub len: usize,
+    /// TCP flags, zero for other protocols
+    pub tcp_flags: u8,
+    /// TCP sequence number, zero for other protocols
+    pub tcp_seq: u32,
+    /// Offset of the TCP or UDP payload in the frame, zero for other protocols
+    pub payload_offset: usize,
+    /// Length of the TCP or UDP payload
+    pub payload_len: usize,
+}
+
+impl FlowPacket {
+    /// Extract the flow fields of an IPv4 or IPv6 packet, optionally VLAN tagged.
+    ///
+    /// IPv6 extension headers are not followed; such packets are tracked under the
+    /// protocol of the first extension header.
+    pub fn parse(packet: &EthernetPacket) -> Option<FlowPacket> {
+        let frame_len = packet.packet().len();
+        let mut ethertype = packet.get_ethertype();
+        let mut payload = packet.payload();
+        if ethertype == EtherTypes::Vlan && payload.len() >= 4 {
+            ethertype = EtherType::new(u16::from_be_bytes([payload[2], payload[3]]));
+            payload = &payload[4..];
+        }
+
+        let (protocol, src, dst, len, transport) = match ethertype {
+            EtherTypes::Ipv4 if payload.len() >= 20 => {
+                let ihl = (payload[0] & 0x0f) as usize * 4;
+                let len = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+                let fragment_offset = u16::from_be_bytes([payload[6], payload[7]]) & 0x1fff;
+                let src = Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]);
+                let dst = Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]);
+                // only the first fragment carries the ports
+                let transport = match fragment_offset {
+                    0 => payload.get(ihl..).unwrap_or(&[]),
+                    _ => &[],
+                };
+                (payload[9], IpAddr::V4(src), IpAddr::V4(dst), len, transport)
+            }
+            EtherTypes::Ipv6 if payload.len() >= 40 => {
+                let mut src = [0u8; 16];
+                let mut dst = [0u8; 16];
+                src.copy_from_slice(&payload[8..24]);
+                dst.copy_from_slice(&payload[24..40]);
+                let len = 40 + u16::from_be_bytes([payload[4], payload[5]]) as usize;
+                (
+                    payload[6],
+                    IpAddr::V6(Ipv6Addr::from(src)),
+                    IpAddr::V6(Ipv6Addr::from(dst)),
+                    len,
+                    &payload[40..],
+                )
+            }
+            _ => return None,
+        };
+        let ip_end = (frame_len - payload.len() + len).min(frame_len);
+        let transport_offset = frame_len - transport.len();
+
+        let (src_port, dst_port) = match protocol {
+            IPPROTO_TCP | IPPROTO_UDP if transport.len() >= 4 => (
+                u16::from_be_bytes([transport[0], transport[1]]),
+                u16::from_be_bytes([transport[2], transport[3]]),
+            ),
+            _ => (0, 0),
+        };
+        let tcp_flags = match protocol {
+            IPPROTO_TCP if transport.len() >= 14 => transport[13],
+            _ => 0,
+        };
+        let tcp_seq = match protocol {
+            IPPROTO_TCP if transport.len() >= 8 => {
+                u32::from_be_bytes([transport[4], transport[5], transport[6], transport[7]])
+            }
+            _ => 0,
+        };
+        let header_len = match protocol {
+            IPPROTO_TCP if transport.len() >= 20 => Some((transport[12] >> 4) as usize * 4),
+            IPPROTO_UDP if transport.len() >= 8 => Some(8),
+            _ => None,
+        };
+        let (payload_offset, payload_len) = match header_len {
+            Some(header_len) if transport_offset + header_len <= ip_end => (
+                transport_offset + header_len,
+                ip_end - transport_offset - header_len,
+            ),
+            _ => (0, 0),
+        };
+        Some(FlowPacket {
+            protocol,
+            src: SocketAddr::new(src, src_port),
+            dst: SocketAddr::new(dst, dst_port),
+            len,
+            tcp_flags,
+            tcp_seq,
+            payload_offset,
+            payload_len,
+        })
+    }
+}
+
+/// Coarse state of a flow.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FlowState {
+    /// Non-TCP flow
+    Active,
+    /// TCP: only the initiator's SYN was seen
+    SynSent,
+    /// TCP: handshake completed, or connection picked up midway
+    Established,
+    /// TCP: one side sent FIN
+    Closing,
+    /// TCP: both sides sent FIN, or a RST was seen
+    Closed,
+}
+
+impl fmt::Display for FlowState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FlowState::Active => "active",
+            FlowState::SynSent => "syn-sent",
+            FlowState::Established => "established",
+            FlowState::Closing => "closing",
+            FlowState::Closed => "closed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A bidirectional flow record.
+///
+/// Per direction counters are indexed by `0` for packets from the initiator (the sender of
+/// the first packet seen) and `1` for packets from the responder.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Flow {
+    /// Flow key
+    pub key: FlowKey,
+    /// Sender of the first packet
+    pub initiator: SocketAddr,
+    /// Receiver of the first packet
+    pub responder: SocketAddr,
+    /// Packets per direction
+    pub packets: [u64; 2],
+    /// IP bytes per direction
+    pub bytes: [u64; 2],
+    /// Union of the TCP flags seen
+    pub tcp_flags: u8,
+    /// Time of the first packet
+    pub start: SystemTime,
+    /// Time of the last packet
+    pub end: SystemTime,
+    /// Connection state
+    pub state: FlowState,
+    /// JA3 fingerprint of the TLS ClientHello sent by the initiator, always None without
+    /// the `tls` feature
+    pub ja3: Option<[u8; 16]>,
+    /// JA3S fingerprint of the TLS ServerHello sent by the responder
+    pub ja3s: Option<[u8; 16]>,
+    fins: [bool; 2],
+}
+
+impl Flow {
+    fn new(packet: &FlowPacket, timestamp: SystemTime) -> Flow {
+        Flow {
+            key: FlowKey::new(packet.protocol, packet.src, packet.dst),
+            initiator: packet.src,
+            responder: packet.dst,
+            packets: [0; 2],
+            bytes: [0; 2],
+            tcp_flags: 0,
+            start: timestamp,
+            end: timestamp,
+            state: if packet.protocol == IPPROTO_TCP {
+                FlowState::SynSent
+            } else {
+                FlowState::Active
+            },
+            ja3: None,
+            ja3s: None,
+            fins: [false; 2],
+        }
+    }
+
+    fn update(&mut self, packet: &FlowPacket, timestamp: SystemTime) {
+        let direction = if packet.src == self.initiator { 0 } else { 1 };
+        self.packets[direction] += 1;
+        self.bytes[direction] += packet.len as u64;
+        self.end = self.end.max(timestamp);
+        if self.key.protocol != IPPROTO_TCP {
+            return;
+        }
+
+        let flags = packet.tcp_flags;
+        self.tcp_flags |= flags;
+        if flags & TCP_FIN != 0 {
+            self.fins[direction] = true;
+        }
+        self.state = if flags & TCP_RST != 0 || self.fins == [true, true] {
+            FlowState::Closed
+        } else if self.fins[0] || self.fins[1] {
+            FlowState::Closing
+        } else if flags & TCP_SYN != 0 && flags & TCP_ACK == 0 {
+            match self.state {
+                FlowState::Established => FlowState::Established,
+                _ => FlowState::SynSent,
+            }
+        } else {
+            FlowState::Established
+        };
+    }
+
+    /// Total packets in both directions.
+    pub fn total_packets(&self) -> u64 {
+        self.packets[0] + self.packets[1]
+    }
+
+    /// Total bytes in both directions.
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes[0] + self.bytes[1]
+    }
+
+    /// Time between the first and the last packet.
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Flow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "proto {} {} <-> {} {}/{} packets {}/{} bytes {:.3} s {}",
+            self.key.protocol,
+            self.initiator,
+            self.responder,
+            self.packets[0],
+            self.packets[1],
+            self.bytes[0],
+            self.bytes[1],
+            self.duration().as_secs_f64(),
+            self.state
+        )?;
+        #[cfg(feature = "tls")]
+        {
+            if let Some(ja3) = &self.ja3 {
+                write!(f, " ja3 {}", tls::to_hex(ja3))?;
+            }
+            if let Some(ja3s) = &self.ja3s {
+                write!(f, " ja3s {}", tls::to_hex(ja3s))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Why a flow record left the table.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Expiry {
+    /// No packet for `idle_timeout`
+    Idle,
+    /// Active for `active_timeout`; the flow stays in the table with fresh counters
+    Active,
+    /// The TCP connection closed
+    Closed,
+    /// The table was flushed
+    Flushed,
+}
+
+/// Flow table parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Flows without packets for this long are expired. Defaults to 15 seconds
+    pub idle_timeout: Duration,
+
+    /// Flows are exported and restarted after this long. Defaults to 30 minutes
+    pub active_timeout: Duration,
+
+    /// Closed TCP flows are kept this long to absorb the last ACKs and retransmissions.
+    /// Defaults to 2 seconds
+    pub closed_timeout: Duration,
+
+    /// Maximum number of flows; packets of new flows are not tracked once it is reached.
+    /// Defaults to 65536
+    pub max_flows: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            idle_timeout: Duration::from_secs(15),
+            active_timeout: Duration::from_secs(30 * 60),
+            closed_timeout: Duration::from_secs(2),
+            max_flows: 65536,
+        }
+    }
+}
+
+/// Table of the flows currently tracked.
+pub struct FlowTable {
+    config: Config,
+    flows: HashMap<FlowKey, Flow>,
+    untracked: u64,
+    /// The TLS hellos being reassembled, by flow and by whether the initiator sends them
+    #[cfg(feature = "tls")]
+    hellos: HashMap<(FlowKey, bool), Stream>,
+    #[cfg(not(target_arch = "wasm32"))]
+    counters: Option<TableCounters>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct TableCounters {
+    created: Counter,
+    expired: Counter,
+    untracked: Counter,
+    active: Gauge,
+}
+
+impl FlowTable {
+    /// Create an empty table.
+    pub fn new(config: Config) -> FlowTable {
+        FlowTable {
+            config,
+            flows: HashMap::new(),
+            untracked: 0,
+            #[cfg(feature = "tls")]
+            hellos: HashMap::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            counters: None,
+        }
+    }
+
+    /// Count into `metrics` the flows started as `<prefix>.flows_created` and removed on
+    /// expiry as `<prefix>.flows_expired`, and the packets not tracked as
+    /// `<prefix>.untracked`. `<prefix>.flows_active` is the number of flows tracked.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_metrics(mut self, metrics: &Metrics, prefix: &str) -> FlowTable {
+        let counters = TableCounters {
+            created: metrics.counter(&format!("{}.flows_created", prefix)),
+            expired: metrics.counter(&format!("{}.flows_expired", prefix)),
+            untracked: metrics.counter(&format!("{}.untracked", prefix)),
+            active: metrics.gauge(&format!("{}.flows_active", prefix)),
+        };
+        counters.active.set(self.flows.len() as f64);
+        self.counters = Some(counters);
+        self
+    }
+
+    /// Account a frame captured at `timestamp`, returning its flow.
+    ///
+    /// Returns None for frames that carry no IP packet or belong to a new flow while the
+    /// table is full.
+    ///
+    /// With the `tls` feature, TLS ClientHello and ServerHello messages are fingerprinted
+    /// once the TLS record carrying them is reassembled from the segments that start with
+    /// it.
+    pub fn update(&mut self, packet: &EthernetPacket, timestamp: SystemTime) -> Option<&Flow> {
+        let parsed = FlowPacket::parse(packet)?;
+        let key = FlowKey::new(parsed.protocol, parsed.src, parsed.dst);
+        self.update_with(&parsed, timestamp)?;
+
+        let flow = self.flows.get_mut(&key)?;
+        #[cfg(feature = "tls")]
+        fingerprint(&mut self.hellos, key, flow, &parsed, packet.packet());
+        Some(flow)
+    }
+
+    /// Like `update`, for already parsed header fields.
+    pub fn update_with(&mut self, packet: &FlowPacket, timestamp: SystemTime) -> Option<&Flow> {
+        let key = FlowKey::new(packet.protocol, packet.src, packet.dst);
+        if !self.flows.contains_key(&key) {
+            if self.flows.len() >= self.config.max_flows {
+                self.untracked += 1;
+                self.report(0, 0, 1);
+                return None;
+            }
+            self.flows.insert(key, Flow::new(packet, timestamp));
+            self.report(1, 0, 0);
+        }
+        let flow = self.flows.get_mut(&key)?;
+        flow.update(packet, timestamp);
+        Some(flow)
+    }
+
+    /// Remove and return the flows that timed out at `now`.
+    ///
+    /// Flows expired for being active too long are returned as well but stay in the table
+    /// with their counters reset.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<(Flow, Expiry)> {
+        let config = self.config;
+        let since = |time: SystemTime| now.duration_since(time).unwrap_or_default();
+        let mut expired = Vec::new();
+        self.flows.retain(|_, flow| {
+            let reason =
+                if flow.state == FlowState::Closed && since(flow.end) >= config.closed_timeout {
+                    Expiry::Closed
+                } else if since(flow.end) >= config.idle_timeout {
+                    Expiry::Idle
+                } else if since(flow.start) >= config.active_timeout {
+                    expired.push((*flow, Expiry::Active));
+                    flow.packets = [0; 2];
+                    flow.bytes = [0; 2];
+                    flow.start = now;
+                    flow.end = now;
+                    return true;
+                } else {
+                    return true;
+                };
+            expired.push((*flow, reason));
+            false
+        });
+        #[cfg(feature = "tls")]
+        {
+            let flows = &self.flows;
+            self.hellos.retain(|(key, _), _| flows.contains_key(key));
+        }
+        let removed = expired
+            .iter()
+            .filter(|(_, reason)| *reason != Expiry::Active)
+            .count();
+        self.report(0, removed as u64, 0);
+        expired
+    }
+
+    /// Remove and return all flows.
+    pub fn flush(&mut self) -> Vec<(Flow, Expiry)> {
+        #[cfg(feature = "tls")]
+        self.hellos.clear();
+        let flushed = self
+            .flows
+            .drain()
+            .map(|(_, flow)| (flow, Expiry::Flushed))
+            .collect();
+        self.report(0, 0, 0);
+        flushed
+    }
+
+    /// Return the flow with the given key.
+    pub fn get(&self, key: &FlowKey) -> Option<&Flow> {
+        self.flows.get(key)
+    }
+
+    /// Iterate over the tracked flows in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &Flow> {
+        self.flows.values()
+    }
+
+    /// Number of tracked flows.
+    pub fn len(&self) -> usize {
+        self.flows.len()
+    }
+
+    /// Whether no flow is tracked.
+    pub fn is_empty(&self) -> bool {
+        self.flows.is_empty()
+    }
+
+    /// Number of packets not tracked because the table was full.
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+
+    // add to the counters of `with_metrics`, and update the number of flows
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+    fn report(&self, created: u64, expired: u64, untracked: u64) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(counters) = &self.counters {
+            counters.created.add(created);
+            counters.expired.add(expired);
+            counters.untracked.add(untracked);
+            counters.active.set(self.flows.len() as f64);
+        }
+    }
+}
+
+/// Add the TCP segment `packet` of `flow` to the TLS hello reassembled for its direction,
+/// and fingerprint the hello once the record carrying it is complete or can't be anymore.
+#[cfg(feature = "tls")]
+fn fingerprint(
+    hellos: &mut HashMap<(FlowKey, bool), Stream>,
+    key: FlowKey,
+    flow: &mut Flow,
+    packet: &FlowPacket,
+    frame: &[u8],
+) {
+    let payload = &frame[packet.payload_offset..][..packet.payload_len];
+    if packet.protocol != IPPROTO_TCP || payload.is_empty() {
+        return;
+    }
+    let client = packet.src == flow.initiator;
+    let known = if client {
+        flow.ja3.is_some()
+    } else {
+        packet.src != flow.responder || flow.ja3s.is_some()
+    };
+    if known {
+        return;
+    }
+    let stream = match hellos.entry((key, client)) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        // a hello starts a segment, data in between handshakes isn't reassembled
+        Entry::Vacant(entry) if payload[0] == TLS_HANDSHAKE => entry.insert(Stream::new()),
+        Entry::Vacant(_) => return,
+    };
+    stream.push(packet.tcp_seq, packet.tcp_flags & TCP_SYN != 0, payload);
+
+    let data = stream.data();
+    if stream.gaps() == 0 {
+        match tls::record_len(data) {
+            Some(len) if len <= MAX_HELLO_RECORD && data.len() < len => return,
+            None => return,
+            _ => {}
+        }
+    }
+    // missing data or an oversized record fail to parse
+    if client {
+        flow.ja3 = tls::ClientHello::parse(data).map(|hello| hello.ja3());
+    } else {
+        flow.ja3s = tls::ServerHello::parse(data).map(|hello| hello.ja3s());
+    }
+    hellos.remove(&(key, client));
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    #[cfg(feature = "tls")]
+    use crate::tls::tests::client_hello;
+    use std::net::SocketAddrV4;
+
+    // an Ethernet frame carrying a TCP segment from `src` to `dst`
+    pub(crate) fn tcp_frame(
+        src: SocketAddrV4,
+        dst: SocketAddrV4,
+        seq: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut frame = vec![0u8; 14 + 20 + 20];
+        frame[12..14].copy_from_slice(&[0x08, 0x00]);
+        let ip = &mut frame[14..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&((40 + payload.len()) as u16).to_be_bytes());
+        ip[9] = IPPROTO_TCP;
+        ip[12..16].copy_from_slice(&src.ip().octets());
+        ip[16..20].copy_from_slice(&dst.ip().octets());
+        let tcp = &mut ip[20..];
+        tcp[0..2].copy_from_slice(&src.port().to_be_bytes());
+        tcp[2..4].copy_from_slice(&dst.port().to_be_bytes());
+        tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = flags;
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn fingerprints_hellos_across_segments() {
+        let client = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 40000);
+        let server = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 443);
+        let segment = |seq, flags, payload: &[u8]| tcp_frame(client, server, seq, flags, payload);
+        let mut table = FlowTable::new(Config::default());
+        let mut update = |frame: &[u8]| {
+            let packet = EthernetPacket::new(frame).unwrap();
+            table
+                .update(&packet, SystemTime::now())
+                .map(|flow| flow.ja3)
+        };
+        let hello = client_hello();
+        let expected = tls::ClientHello::parse(&hello).unwrap().ja3();
+
+        assert_eq!(update(&segment(999, TCP_SYN, &[])), Some(None));
+        assert_eq!(update(&segment(1000, TCP_ACK, &hello[..40])), Some(None));
+        // a retransmission of part of the first segment changes nothing
+        assert_eq!(update(&segment(1010, TCP_ACK, &hello[10..30])), Some(None));
+        assert_eq!(
+            update(&segment(1040, TCP_ACK, &hello[40..])),
+            Some(Some(expected))
+        );
+        assert!(table.hellos.is_empty());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn counts_into_metrics() {
+        let server = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+        let client = |port| SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), port);
+        let metrics = Metrics::new();
+        let config = Config {
+            max_flows: 2,
+            ..Default::default()
+        };
+        let mut table = FlowTable::new(config).with_metrics(&metrics, "flows");
+        let start = SystemTime::now();
+        for port in &[40000, 40000, 40001, 40002] {
+            let frame = tcp_frame(client(*port), server, 1, TCP_SYN, &[]);
+            table.update(&EthernetPacket::new(&frame).unwrap(), start);
+        }
+        let counters = || metrics.counters().into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(counters()["flows.flows_created"], 2);
+        assert_eq!(counters()["flows.untracked"], 1);
+        assert_eq!(metrics.gauges(), [("flows.flows_active".to_owned(), 2.0)]);
+
+        table.expire(start + config.idle_timeout);
+        assert_eq!(counters()["flows.flows_expired"], 2);
+        assert_eq!(metrics.gauges(), [("flows.flows_active".to_owned(), 0.0)]);
+    }
+}
@@ -0,0 +1,318 @@
+//! Packet generator.
+//!
+//! A [`Generator`] repeatedly emits a template frame, rewriting selected header fields for
+//! every copy according to [`Rule`]s, and [`run`] sends its output at a fixed rate. The
+//! template can come from a hex string or from any of the crate's frame builders.
+//! Rewritten IPv4 headers get their checksum fixed, and so do TCP and UDP headers.
+
+#[cfg(target_os = "linux")]
+use crate::pipeline::pin_current_thread;
+use crate::{
+    arp::{
+        channel::EthernetDataLinkSender,
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+        network_interface::MacAddr,
+    },
+    checksum,
+    ipproto::{IPPROTO_TCP, IPPROTO_UDP},
+    rng::Rng,
+    sniff::ParseError,
+    spoof::{self, SourceMac},
+    ttl::DEFAULT_TTL,
+    udp::{self, MutableUdpPacket},
+};
+use std::{
+    io,
+    net::SocketAddrV4,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A header field the generator can rewrite.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Field {
+    /// Ethernet source address
+    SrcMac,
+    /// Ethernet destination address
+    DstMac,
+    /// IPv4 source address
+    SrcIp,
+    /// IPv4 destination address
+    DstIp,
+    /// TCP or UDP source port
+    SrcPort,
+    /// TCP or UDP destination port
+    DstPort,
+}
+
+impl std::str::FromStr for Field {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Field, ParseError> {
+        match s {
+            "src-mac" => Ok(Field::SrcMac),
+            "dst-mac" => Ok(Field::DstMac),
+            "src-ip" => Ok(Field::SrcIp),
+            "dst-ip" => Ok(Field::DstIp),
+            "src-port" => Ok(Field::SrcPort),
+            "dst-port" => Ok(Field::DstPort),
+            _ => Err(ParseError(format!("unknown field `{}`", s))),
+        }
+    }
+}
+
+/// How a field changes from one generated frame to the next.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rule {
+    /// The n-th frame carries the template's value plus n, wrapping around
+    Increment,
+    /// Every frame carries a random value. Random MAC addresses are locally administered
+    /// unicast addresses
+    Random,
+}
+
+/// Parse a frame written in hex. Whitespace, `:` and `-` between bytes are ignored.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, ParseError> {
+    let digits: Vec<char> = text
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ':' && *c != '-')
+        .collect();
+    if digits.len() % 2 != 0 {
+        return Err(ParseError("odd number of hex digits".to_owned()));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let byte: String = pair.iter().collect();
+            u8::from_str_radix(&byte, 16)
+                .map_err(|_| ParseError(format!("invalid hex byte `{}`", byte)))
+        })
+        .collect()
+}
+
+/// Build an Ethernet frame carrying `payload` in a UDP datagram from `src` to `dst`.
+pub fn udp_frame(
+    source: MacAddr,
+    destination: MacAddr,
+    src: SocketAddrV4,
+    dst: SocketAddrV4,
+    payload: &[u8],
+) -> Vec<u8> {
+    let udp_len = 8 + payload.len();
+    let mut frame = vec![0u8; 14 + 20 + udp_len];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+    ethernet.set_destination(destination);
+    ethernet.set_source(source);
+    ethernet.set_ethertype(EtherTypes::Ipv4);
+
+    let ip = &mut frame[14..];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(20 + udp_len as u16).to_be_bytes());
+    ip[8] = DEFAULT_TTL;
+    ip[9] = IPPROTO_UDP;
+    ip[12..16].copy_from_slice(&src.ip().octets());
+    ip[16..20].copy_from_slice(&dst.ip().octets());
+    let header_checksum = checksum::checksum(&ip[..20]);
+    ip[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+    let mut udp = MutableUdpPacket::new(&mut ip[20..]).unwrap();
+    udp.set_source(src.port());
+    udp.set_destination(dst.port());
+    udp.set_length(udp_len as u16);
+    udp.set_payload(payload);
+    let udp_checksum = udp::ipv4_checksum(&udp.to_immutable(), *src.ip(), *dst.ip());
+    udp.set_checksum(udp_checksum);
+    frame
+}
+
+/// Produces copies of a template frame with rewritten fields.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    template: Vec<u8>,
+    rules: Vec<(Field, Rule)>,
+    frame: Vec<u8>,
+    sequence: u64,
+    rng: Rng,
+}
+
+impl Generator {
+    /// Create a generator emitting `template` unchanged.
+    pub fn new(template: Vec<u8>) -> Generator {
+        Generator {
+            frame: template.clone(),
+            template,
+            rules: Vec::new(),
+            sequence: 0,
+            rng: Rng::from_time(),
+        }
+    }
+
+    /// Rewrite `field` according to `rule` in every frame. A later rule for the same field
+    /// replaces the earlier one.
+    pub fn rule(mut self, field: Field, rule: Rule) -> Generator {
+        self.rules.retain(|(f, _)| *f != field);
+        self.rules.push((field, rule));
+        self
+    }
+
+    /// Choose the source address of the frames. `SourceMac::Random` is the same as a random
+    /// rule for `Field::SrcMac`; a fixed address is written to the template.
+    pub fn source_mac(mut self, mode: SourceMac) -> Generator {
+        self.rules.retain(|(f, _)| *f != Field::SrcMac);
+        match mode {
+            SourceMac::Keep => {}
+            SourceMac::Fixed(mac) => spoof::rewrite_source(&mut self.template, mac),
+            SourceMac::Random => self.rules.push((Field::SrcMac, Rule::Random)),
+        }
+        self
+    }
+
+    /// Seed the random number generator, to make a run reproducible.
+    pub fn seed(mut self, seed: u64) -> Generator {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// Number of frames generated so far.
+    pub fn generated(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Produce the next frame.
+    pub fn next_frame(&mut self) -> &[u8] {
+        self.frame.clear();
+        self.frame.extend_from_slice(&self.template);
+        let n = self.sequence;
+        self.sequence += 1;
+
+        for i in 0..self.rules.len() {
+            let (field, rule) = self.rules[i];
+            let random = match rule {
+                Rule::Random => self.rng.next_u64(),
+                Rule::Increment => 0,
+            };
+            let range = match field_range(&self.frame, field) {
+                Some(range) => range,
+                None => continue,
+            };
+            let bytes = &mut self.frame[range];
+            match rule {
+                Rule::Increment => add_be(bytes, n),
+                Rule::Random => {
+                    bytes.copy_from_slice(&random.to_be_bytes()[8 - bytes.len()..]);
+                    if let Field::SrcMac | Field::DstMac = field {
+                        bytes[0] = (bytes[0] & 0xfe) | 0x02;
+                    }
+                }
+            }
+        }
+
+        if !self.rules.is_empty() {
+            checksum::update_ipv4_frame(&mut self.frame);
+        }
+        &self.frame
+    }
+}
+
+/// Add `n` to the big-endian number stored in `bytes`, wrapping around.
+fn add_be(bytes: &mut [u8], n: u64) {
+    let mut carry = n as u128;
+    for byte in bytes.iter_mut().rev() {
+        carry += *byte as u128;
+        *byte = carry as u8;
+        carry >>= 8;
+    }
+}
+
+fn field_range(frame: &[u8], field: Field) -> Option<std::ops::Range<usize>> {
+    match field {
+        Field::DstMac if frame.len() >= 14 => return Some(0..6),
+        Field::SrcMac if frame.len() >= 14 => return Some(6..12),
+        Field::DstMac | Field::SrcMac => return None,
+        _ => {}
+    }
+    let (ip, ihl) = checksum::ipv4_header(frame)?;
+    match field {
+        Field::SrcIp => Some(ip + 12..ip + 16),
+        Field::DstIp => Some(ip + 16..ip + 20),
+        _ => {
+            let transport = ip + ihl;
+            let has_ports = matches!(frame[ip + 9], IPPROTO_TCP | IPPROTO_UDP);
+            if !has_ports || frame.len() < transport + 4 {
+                return None;
+            }
+            match field {
+                Field::SrcPort => Some(transport..transport + 2),
+                _ => Some(transport + 2..transport + 4),
+            }
+        }
+    }
+}
+
+/// Pacing of a generator run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// Frames per second, None for as fast as possible. Defaults to None
+    pub rate: Option<f64>,
+    /// Number of frames to send, None for no limit. Defaults to None
+    pub count: Option<u64>,
+    /// CPU to pin the sending thread to, None to leave it unpinned; Linux only. Defaults
+    /// to None
+    pub cpu: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rate: None,
+            count: None,
+            cpu: None,
+        }
+    }
+}
+
+// pinning takes sched_setaffinity
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_cpu: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Pinning threads is only supported on Linux",
+    ))
+}
+
+/// Send the output of `generator` on `tx` until `count` frames were sent or `stop` is set.
+///
+/// Returns the number of frames sent. With `Config::cpu` set the calling thread stays
+/// pinned to that CPU afterwards.
+pub fn run(
+    tx: &mut dyn EthernetDataLinkSender,
+    generator: &mut Generator,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<u64> {
+    if let Some(cpu) = config.cpu {
+        pin_current_thread(cpu)?;
+    }
+    let rate = config.rate.filter(|rate| *rate > 0.0);
+    let start = Instant::now();
+    let mut sent = 0;
+    while !stop.load(Ordering::SeqCst) && config.count.map_or(true, |count| sent < count) {
+        if let Some(rate) = rate {
+            // pace against the start time so that sleep overshoot doesn't accumulate
+            let due = start + Duration::from_secs_f64(sent as f64 / rate);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+
+        let frame = generator.next_frame();
+        let packet = EthernetPacket::new(frame)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Frame too short"))?;
+        tx.send_to(&packet, None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+        sent += 1;
+    }
+    Ok(sent)
+}
@@ -0,0 +1,242 @@
+//! Splitting offloaded TCP segments.
+//!
+//! With generic segmentation offload (GSO), and generic receive offload (GRO) on the
+//! receiving side, packet sockets see TCP segments as the stack handles them rather than
+//! as they are on the wire: up to 64 KiB of payload behind a single set of headers. Such
+//! frames need a read buffer of [`crate::arp::channel::GSO_READ_BUFFER_SIZE`] bytes not to
+//! be truncated, and [`segment`] splits them into the frames the wire carries.
+
+use crate::{checksum, ipproto::IPPROTO_TCP};
+use std::net::Ipv6Addr;
+
+/// Ethertype of IPv4, as bytes.
+const ETHERTYPE_IPV4: [u8; 2] = [0x08, 0x00];
+/// Ethertype of IPv6, as bytes.
+const ETHERTYPE_IPV6: [u8; 2] = [0x86, 0xdd];
+
+const TCP_FIN: u8 = 0x01;
+const TCP_PSH: u8 = 0x08;
+const TCP_CWR: u8 = 0x80;
+
+/// Payload bytes per segment on standard Ethernet with IPv4 and no TCP options.
+pub const DEFAULT_MSS: usize = 1460;
+
+// where the IP and TCP headers of a TCP frame are, and how long its payload is
+struct Layout {
+    ip: usize,
+    tcp: usize,
+    payload: usize,
+    ipv6: bool,
+}
+
+fn layout(frame: &[u8]) -> Option<Layout> {
+    if frame.len() < 14 {
+        return None;
+    }
+    let ip = 14;
+    let (tcp, end, ipv6) = match [frame[12], frame[13]] {
+        ETHERTYPE_IPV4 => {
+            let header = frame.get(ip..ip + 20)?;
+            let ihl = usize::from(header[0] & 0x0f) * 4;
+            let fragmented = u16::from_be_bytes([header[6], header[7]]) & 0x3fff != 0;
+            if header[9] != IPPROTO_TCP || fragmented || ihl < 20 {
+                return None;
+            }
+            // offloaded packets may not have their length filled in
+            let total_len = usize::from(u16::from_be_bytes([header[2], header[3]]));
+            let end = if total_len == 0 {
+                frame.len()
+            } else {
+                (ip + total_len).min(frame.len())
+            };
+            (ip + ihl, end, false)
+        }
+        ETHERTYPE_IPV6 => {
+            let header = frame.get(ip..ip + 40)?;
+            if header[6] != IPPROTO_TCP {
+                return None;
+            }
+            let payload_len = usize::from(u16::from_be_bytes([header[4], header[5]]));
+            let end = if payload_len == 0 {
+                frame.len()
+            } else {
+                (ip + 40 + payload_len).min(frame.len())
+            };
+            (ip + 40, end, true)
+        }
+        _ => return None,
+    };
+    let offset = usize::from(*frame.get(tcp + 12)? >> 4) * 4;
+    if offset < 20 || tcp + offset > end {
+        return None;
+    }
+    Some(Layout {
+        ip,
+        tcp,
+        payload: end - tcp - offset,
+        ipv6,
+    })
+}
+
+/// Whether `frame` is a TCP segment carrying more than `mss` bytes of payload.
+pub fn is_oversized(frame: &[u8], mss: usize) -> bool {
+    layout(frame).map_or(false, |layout| layout.payload > mss)
+}
+
+/// Split a TCP segment into segments of at most `mss` bytes of payload, as TSO or GSO
+/// would before putting them on the wire.
+///
+/// Sequence numbers, IPv4 ids, lengths and checksums are adjusted; FIN and PSH are only
+/// kept on the last segment and CWR on the first. Frames that are not oversized TCP over
+/// IPv4 or IPv6 are returned as they are.
+pub fn segment(frame: &[u8], mss: usize) -> Vec<Vec<u8>> {
+    let layout = match layout(frame) {
+        Some(layout) if mss > 0 && layout.payload > mss => layout,
+        _ => return vec![frame.to_vec()],
+    };
+    let tcp_len = usize::from(frame[layout.tcp + 12] >> 4) * 4;
+    let headers = &frame[..layout.tcp + tcp_len];
+    let payload = &frame[layout.tcp + tcp_len..layout.tcp + tcp_len + layout.payload];
+    let seq = u32::from_be_bytes([
+        frame[layout.tcp + 4],
+        frame[layout.tcp + 5],
+        frame[layout.tcp + 6],
+        frame[layout.tcp + 7],
+    ]);
+    let flags = frame[layout.tcp + 13];
+    let id = u16::from_be_bytes([frame[layout.ip + 4], frame[layout.ip + 5]]);
+
+    let count = (payload.len() + mss - 1) / mss;
+    payload
+        .chunks(mss)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut segment = Vec::with_capacity(headers.len() + chunk.len());
+            segment.extend_from_slice(headers);
+            segment.extend_from_slice(chunk);
+
+            let tcp = layout.tcp;
+            let seq = seq.wrapping_add((index * mss) as u32);
+            segment[tcp + 4..tcp + 8].copy_from_slice(&seq.to_be_bytes());
+            let mut segment_flags = flags;
+            if index + 1 < count {
+                segment_flags &= !(TCP_FIN | TCP_PSH);
+            }
+            if index > 0 {
+                segment_flags &= !TCP_CWR;
+            }
+            segment[tcp + 13] = segment_flags;
+
+            let ip = layout.ip;
+            if layout.ipv6 {
+                let payload_len = (segment.len() - ip - 40) as u16;
+                segment[ip + 4..ip + 6].copy_from_slice(&payload_len.to_be_bytes());
+                update_ipv6_tcp_checksum(&mut segment[ip..]);
+            } else {
+                let total_len = (segment.len() - ip) as u16;
+                segment[ip + 2..ip + 4].copy_from_slice(&total_len.to_be_bytes());
+                let id = id.wrapping_add(index as u16);
+                segment[ip + 4..ip + 6].copy_from_slice(&id.to_be_bytes());
+                checksum::update_ipv4_frame(&mut segment);
+            }
+            segment
+        })
+        .collect()
+}
+
+fn update_ipv6_tcp_checksum(packet: &mut [u8]) {
+    let address = |bytes: &[u8]| {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(bytes);
+        Ipv6Addr::from(octets)
+    };
+    let src = address(&packet[8..24]);
+    let dst = address(&packet[24..40]);
+    let segment = &mut packet[40..];
+    segment[16] = 0;
+    segment[17] = 0;
+    let pseudo = checksum::ipv6_pseudo_header(src, dst, IPPROTO_TCP, segment.len() as u32);
+    let sum = checksum::finish(checksum::add(pseudo, segment));
+    segment[16..18].copy_from_slice(&sum.to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flows::tests::tcp_frame;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const TCP_ACK: u8 = 0x10;
+
+    fn seq(segment: &[u8], tcp: usize) -> u32 {
+        u32::from_be_bytes([
+            segment[tcp + 4],
+            segment[tcp + 5],
+            segment[tcp + 6],
+            segment[tcp + 7],
+        ])
+    }
+
+    #[test]
+    fn splits_ipv4_segments() {
+        let src = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 40000);
+        let dst = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 80);
+        let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let flags = TCP_CWR | TCP_ACK | TCP_PSH | TCP_FIN;
+        let mut frame = tcp_frame(src, dst, u32::MAX - 100, flags, &payload);
+        frame[18..20].copy_from_slice(&7u16.to_be_bytes());
+        checksum::update_ipv4_frame(&mut frame);
+        assert!(is_oversized(&frame, DEFAULT_MSS));
+        assert_eq!(segment(&frame, 3000), [frame.clone()]);
+
+        let segments = segment(&frame, DEFAULT_MSS);
+        let lengths: Vec<_> = segments.iter().map(|s| s.len() - 54).collect();
+        assert_eq!(lengths, [1460, 1460, 80]);
+        let mut rebuilt = Vec::new();
+        for (index, segment) in segments.iter().enumerate() {
+            let (ip, tcp) = (&segment[14..34], &segment[34..]);
+            assert_eq!(
+                usize::from(u16::from_be_bytes([ip[2], ip[3]])),
+                segment.len() - 14
+            );
+            assert_eq!(u16::from_be_bytes([ip[4], ip[5]]), 7 + index as u16);
+            assert_eq!(checksum::checksum(ip), 0);
+            let pseudo =
+                checksum::ipv4_pseudo_header(*src.ip(), *dst.ip(), IPPROTO_TCP, tcp.len() as u16);
+            assert_eq!(checksum::finish(checksum::add(pseudo, tcp)), 0);
+            let expected = (u32::MAX - 100).wrapping_add(index as u32 * 1460);
+            assert_eq!(seq(segment, 34), expected);
+            rebuilt.extend_from_slice(&tcp[20..]);
+        }
+        assert_eq!(rebuilt, payload);
+        assert_eq!(segments[0][47], TCP_CWR | TCP_ACK);
+        assert_eq!(segments[1][47], TCP_ACK);
+        assert_eq!(segments[2][47], TCP_ACK | TCP_PSH | TCP_FIN);
+    }
+
+    #[test]
+    fn splits_ipv6_segments() {
+        let src = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let dst = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 2);
+        let mut frame = vec![0u8; 14 + 40 + 20];
+        frame[12..14].copy_from_slice(&ETHERTYPE_IPV6);
+        frame[14] = 0x60;
+        // left at zero, as offloaded packets may have it
+        frame[20] = IPPROTO_TCP;
+        frame[22..38].copy_from_slice(&src.octets());
+        frame[38..54].copy_from_slice(&dst.octets());
+        frame[54 + 12] = 5 << 4;
+        frame[54 + 13] = TCP_ACK;
+        frame.extend_from_slice(&[0xab; 2000]);
+
+        let segments = segment(&frame, 1000);
+        assert_eq!(segments.len(), 2);
+        for (index, segment) in segments.iter().enumerate() {
+            let tcp = &segment[54..];
+            assert_eq!(segment[18..20], 1020u16.to_be_bytes());
+            let pseudo = checksum::ipv6_pseudo_header(src, dst, IPPROTO_TCP, tcp.len() as u32);
+            assert_eq!(checksum::finish(checksum::add(pseudo, tcp)), 0);
+            assert_eq!(seq(segment, 54), index as u32 * 1000);
+        }
+    }
+}
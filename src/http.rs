@@ -0,0 +1,727 @@
+//! HTTP/1.1 transaction extraction.
+//!
+//! A [`Dissector`] follows the TCP connections that start with an HTTP request, reassembles
+//! both directions and pairs every request with its response, pipelined ones included. Each
+//! completed exchange is reported as a [`Transaction`] with the request line, the response
+//! status, the message sizes and when they were seen.
+
+use crate::{
+    arp::ether::{EthernetPacket, Packet},
+    flows::{FlowKey, FlowPacket},
+    ipproto::IPPROTO_TCP,
+    reassembly::{self, Stream},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+
+const METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// A request and its response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Transaction {
+    /// Endpoint that sent the request
+    pub client: SocketAddr,
+    /// Endpoint that answered it
+    pub server: SocketAddr,
+    /// Request method
+    pub method: String,
+    /// Value of the Host header
+    pub host: Option<String>,
+    /// Request target
+    pub path: String,
+    /// Response status, None if the connection ended before a response
+    pub status: Option<u16>,
+    /// Size of the request, headers and body
+    pub request_size: u64,
+    /// Size of the response, headers and body
+    pub response_size: u64,
+    /// When the first byte of the request was seen
+    pub request_time: SystemTime,
+    /// When the first byte of the response was seen
+    pub response_time: Option<SystemTime>,
+    /// When the response, or the connection, ended
+    pub end_time: SystemTime,
+    /// Whether data of the connection went missing before the exchange completed, so that
+    /// the sizes and the response may be partial
+    pub incomplete: bool,
+}
+
+impl Transaction {
+    /// Time from the start of the request to the start of the response.
+    pub fn response_delay(&self) -> Option<Duration> {
+        let response_time = self.response_time?;
+        Some(
+            response_time
+                .duration_since(self.request_time)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Time from the start of the request to the end of the response.
+    pub fn duration(&self) -> Duration {
+        self.end_time
+            .duration_since(self.request_time)
+            .unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} > {} {} {}{} ",
+            self.client,
+            self.server,
+            self.method,
+            self.host.as_deref().unwrap_or(""),
+            self.path
+        )?;
+        match self.status {
+            Some(status) => write!(f, "{}", status)?,
+            None => write!(f, "-")?,
+        }
+        write!(
+            f,
+            " {}/{} bytes {:.3} ms",
+            self.request_size,
+            self.response_size,
+            self.duration().as_secs_f64() * 1e3
+        )?;
+        if self.incomplete {
+            write!(f, " incomplete")?;
+        }
+        Ok(())
+    }
+}
+
+/// Dissector parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Connections without packets for this long are dropped. Defaults to 60 seconds
+    pub idle_timeout: Duration,
+
+    /// Connections whose message headers grow beyond this are dropped. Defaults to 64 KiB
+    pub max_header_size: usize,
+
+    /// Out of order segments held per direction. Defaults to `reassembly::DEFAULT_MAX_PENDING`
+    pub max_pending: usize,
+
+    /// Maximum number of connections followed; new ones are ignored once it is reached.
+    /// Defaults to 4096
+    pub max_connections: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            idle_timeout: Duration::from_secs(60),
+            max_header_size: 64 * 1024,
+            max_pending: reassembly::DEFAULT_MAX_PENDING,
+            max_connections: 4096,
+        }
+    }
+}
+
+/// Extracts HTTP transactions from captured frames.
+pub struct Dissector {
+    config: Config,
+    connections: HashMap<FlowKey, Connection>,
+    untracked: u64,
+}
+
+impl Dissector {
+    /// Create a dissector following no connection yet.
+    pub fn new(config: Config) -> Dissector {
+        Dissector {
+            config,
+            connections: HashMap::new(),
+            untracked: 0,
+        }
+    }
+
+    /// Process a frame captured at `timestamp`, returning the transactions it completed.
+    pub fn update(&mut self, packet: &EthernetPacket, timestamp: SystemTime) -> Vec<Transaction> {
+        let parsed = match FlowPacket::parse(packet) {
+            Some(parsed) if parsed.protocol == IPPROTO_TCP => parsed,
+            _ => return Vec::new(),
+        };
+        let payload = &packet.packet()[parsed.payload_offset..][..parsed.payload_len];
+        let key = FlowKey::new(parsed.protocol, parsed.src, parsed.dst);
+
+        let config = self.config;
+        if !self.connections.contains_key(&key) {
+            // only connections seen from their first request on are followed
+            if !is_request(payload) {
+                return Vec::new();
+            }
+            if self.connections.len() >= config.max_connections {
+                self.untracked += 1;
+                return Vec::new();
+            }
+        }
+        let connection = self
+            .connections
+            .entry(key)
+            .or_insert_with(|| Connection::new(parsed.src, parsed.dst, &config));
+
+        let direction = if parsed.src == connection.client {
+            0
+        } else {
+            1
+        };
+        connection.last_seen = timestamp;
+        connection.streams[direction].push(
+            parsed.tcp_seq,
+            parsed.tcp_flags & TCP_SYN != 0,
+            payload,
+        );
+        if parsed.tcp_flags & TCP_RST != 0 {
+            connection.closed = [true, true];
+        } else if parsed.tcp_flags & TCP_FIN != 0 {
+            connection.closed[direction] = true;
+        }
+
+        let mut transactions = Vec::new();
+        let healthy = connection.advance(timestamp, &config, &mut transactions);
+        if !healthy || connection.closed == [true, true] {
+            if let Some(connection) = self.connections.remove(&key) {
+                connection.finish(timestamp, &mut transactions);
+            }
+        }
+        transactions
+    }
+
+    /// Drop the connections idle at `now`, returning their unanswered requests.
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Transaction> {
+        let idle_timeout = self.config.idle_timeout;
+        let idle: Vec<FlowKey> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| {
+                now.duration_since(connection.last_seen).unwrap_or_default() >= idle_timeout
+            })
+            .map(|(key, _)| *key)
+            .collect();
+        let mut transactions = Vec::new();
+        for key in idle {
+            if let Some(connection) = self.connections.remove(&key) {
+                let last_seen = connection.last_seen;
+                connection.finish(last_seen, &mut transactions);
+            }
+        }
+        transactions
+    }
+
+    /// Drop all connections, returning their unanswered requests.
+    pub fn flush(&mut self) -> Vec<Transaction> {
+        let mut transactions = Vec::new();
+        for (_, connection) in self.connections.drain() {
+            let last_seen = connection.last_seen;
+            connection.finish(last_seen, &mut transactions);
+        }
+        transactions
+    }
+
+    /// Number of connections followed.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Whether no connection is followed.
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Number of connections not followed because `max_connections` were already.
+    pub fn untracked(&self) -> u64 {
+        self.untracked
+    }
+}
+
+/// Whether `payload` looks like the start of a request.
+fn is_request(payload: &[u8]) -> bool {
+    METHODS.iter().any(|method| {
+        payload.len() > method.len()
+            && payload.starts_with(method.as_bytes())
+            && payload[method.len()] == b' '
+    })
+}
+
+/// A request waiting for its response.
+struct Request {
+    method: String,
+    host: Option<String>,
+    path: String,
+    size: u64,
+    time: SystemTime,
+}
+
+/// A response whose body is being read.
+struct Response {
+    status: u16,
+    interim: bool,
+    time: SystemTime,
+}
+
+struct Connection {
+    client: SocketAddr,
+    server: SocketAddr,
+    /// Client to server, then server to client
+    streams: [Stream; 2],
+    parsers: [Parser; 2],
+    /// Whether data went missing in either direction
+    gaps: [bool; 2],
+    closed: [bool; 2],
+    request: Option<Request>,
+    requests: VecDeque<Request>,
+    response: Option<Response>,
+    upgraded: bool,
+    last_seen: SystemTime,
+}
+
+impl Connection {
+    fn new(client: SocketAddr, server: SocketAddr, config: &Config) -> Connection {
+        Connection {
+            client,
+            server,
+            streams: [
+                Stream::with_max_pending(config.max_pending),
+                Stream::with_max_pending(config.max_pending),
+            ],
+            parsers: [Parser::new(), Parser::new()],
+            gaps: [false; 2],
+            closed: [false; 2],
+            request: None,
+            requests: VecDeque::new(),
+            response: None,
+            upgraded: false,
+            last_seen: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    /// Parse what both streams hold. Returns false if the connection can't be followed
+    /// any more.
+    fn advance(
+        &mut self,
+        timestamp: SystemTime,
+        config: &Config,
+        transactions: &mut Vec<Transaction>,
+    ) -> bool {
+        if self.upgraded {
+            // the connection switched protocols, nothing to parse
+            self.streams[0].consume(usize::MAX);
+            self.streams[1].consume(usize::MAX);
+            return true;
+        }
+        for direction in 0..2 {
+            self.gaps[direction] = self.streams[direction].gaps() > 0;
+        }
+        // messages can't be resynchronized after data went missing
+        if self.gaps != [false; 2] {
+            return false;
+        }
+
+        loop {
+            let event = self.parsers[0].next(&mut self.streams[0], timestamp, config, false);
+            match event {
+                Err(()) => return false,
+                Ok(None) => break,
+                Ok(Some(Event::Head(head))) => {
+                    let mut words = head.start_line.splitn(3, ' ');
+                    let method = words.next().unwrap_or("").to_owned();
+                    let path = match words.next() {
+                        Some(path) => path.to_owned(),
+                        None => return false,
+                    };
+                    self.request = Some(Request {
+                        method,
+                        host: head.header("host").map(str::to_owned),
+                        path,
+                        size: 0,
+                        time: head.time,
+                    });
+                    self.parsers[0].start_body(head.body(false));
+                }
+                Ok(Some(Event::End { size })) => {
+                    if let Some(mut request) = self.request.take() {
+                        request.size = size;
+                        self.requests.push_back(request);
+                    }
+                }
+            }
+        }
+
+        loop {
+            let closed = self.closed[1];
+            let event = self.parsers[1].next(&mut self.streams[1], timestamp, config, closed);
+            match event {
+                Err(()) => return false,
+                Ok(None) => break,
+                Ok(Some(Event::Head(head))) => {
+                    let status = head
+                        .start_line
+                        .split(' ')
+                        .nth(1)
+                        .and_then(|status| status.parse::<u16>().ok());
+                    let status = match status {
+                        Some(status) if head.start_line.starts_with("HTTP/") => status,
+                        _ => return false,
+                    };
+                    let head_request = self
+                        .requests
+                        .front()
+                        .map_or(false, |request| request.method == "HEAD");
+                    let body = if head_request
+                        || (100..200).contains(&status)
+                        || status == 204
+                        || status == 304
+                    {
+                        None
+                    } else {
+                        head.body(true)
+                    };
+                    self.response = Some(Response {
+                        status,
+                        interim: (100..200).contains(&status) && status != 101,
+                        time: head.time,
+                    });
+                    self.parsers[1].start_body(body);
+                }
+                Ok(Some(Event::End { size })) => {
+                    let response = match self.response.take() {
+                        Some(response) => response,
+                        None => continue,
+                    };
+                    if response.interim {
+                        continue;
+                    }
+                    if let Some(request) = self.requests.pop_front() {
+                        transactions.push(Transaction {
+                            client: self.client,
+                            server: self.server,
+                            method: request.method,
+                            host: request.host,
+                            path: request.path,
+                            status: Some(response.status),
+                            request_size: request.size,
+                            response_size: size,
+                            request_time: request.time,
+                            response_time: Some(response.time),
+                            end_time: timestamp,
+                            incomplete: false,
+                        });
+                    }
+                    if response.status == 101 {
+                        self.upgraded = true;
+                        return self.advance(timestamp, config, transactions);
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Report the requests left without a response, as incomplete if data went missing.
+    fn finish(self, timestamp: SystemTime, transactions: &mut Vec<Transaction>) {
+        let client = self.client;
+        let server = self.server;
+        let incomplete = self.gaps != [false; 2];
+        for request in self.requests.into_iter().chain(self.request) {
+            transactions.push(Transaction {
+                client,
+                server,
+                method: request.method,
+                host: request.host,
+                path: request.path,
+                status: None,
+                request_size: request.size,
+                response_size: 0,
+                request_time: request.time,
+                response_time: None,
+                end_time: timestamp,
+                incomplete,
+            });
+        }
+    }
+}
+
+/// The start line and headers of a message.
+struct Head {
+    start_line: String,
+    headers: Vec<(String, String)>,
+    time: SystemTime,
+}
+
+impl Head {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// How the body of the message is delimited, None if it has none. Responses without
+    /// a length last until the connection is closed.
+    fn body(&self, response: bool) -> Option<Body> {
+        let chunked = self.header("transfer-encoding").map_or(false, |value| {
+            value.to_ascii_lowercase().contains("chunked")
+        });
+        if chunked {
+            return Some(Body::Chunked(Chunk::Size));
+        }
+        match self.header("content-length").map(|len| len.parse::<u64>()) {
+            Some(Ok(0)) => None,
+            Some(Ok(len)) => Some(Body::Length(len)),
+            _ if response => Some(Body::UntilClose),
+            _ => None,
+        }
+    }
+}
+
+enum Body {
+    Length(u64),
+    Chunked(Chunk),
+    UntilClose,
+}
+
+enum Chunk {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailer,
+}
+
+enum Event {
+    Head(Head),
+    End { size: u64 },
+}
+
+/// Splits one direction of a connection into messages.
+struct Parser {
+    body: Option<Body>,
+    in_message: bool,
+    size: u64,
+    started: Option<SystemTime>,
+}
+
+impl Parser {
+    fn new() -> Parser {
+        Parser {
+            body: None,
+            in_message: false,
+            size: 0,
+            started: None,
+        }
+    }
+
+    fn start_body(&mut self, body: Option<Body>) {
+        self.body = body;
+    }
+
+    /// Parse the next event out of `stream`. Returns Ok(None) if more data is needed and
+    /// an error if the data is not HTTP.
+    fn next(
+        &mut self,
+        stream: &mut Stream,
+        timestamp: SystemTime,
+        config: &Config,
+        closed: bool,
+    ) -> Result<Option<Event>, ()> {
+        if self.in_message {
+            return match self.read_body(stream, closed) {
+                true => {
+                    self.in_message = false;
+                    self.started = None;
+                    Ok(Some(Event::End { size: self.size }))
+                }
+                false => Ok(None),
+            };
+        }
+
+        let data = stream.data();
+        if data.is_empty() {
+            return Ok(None);
+        }
+        let started = *self.started.get_or_insert(timestamp);
+        let end = match data.windows(4).position(|window| window == b"\r\n\r\n") {
+            Some(end) => end,
+            None if data.len() > config.max_header_size => return Err(()),
+            None => return Ok(None),
+        };
+        let text = String::from_utf8_lossy(&data[..end]).into_owned();
+        stream.consume(end + 4);
+
+        let mut lines = text.split("\r\n");
+        let start_line = lines.next().unwrap_or("").to_owned();
+        let headers = lines
+            .filter_map(|line| {
+                let colon = line.find(':')?;
+                Some((
+                    line[..colon].trim().to_owned(),
+                    line[colon + 1..].trim().to_owned(),
+                ))
+            })
+            .collect();
+        self.in_message = true;
+        self.size = (end + 4) as u64;
+        self.body = None;
+        Ok(Some(Event::Head(Head {
+            start_line,
+            headers,
+            time: started,
+        })))
+    }
+
+    /// Consume body bytes from `stream`, returning true once the body is complete.
+    fn read_body(&mut self, stream: &mut Stream, closed: bool) -> bool {
+        loop {
+            let data = stream.data();
+            let body = match self.body.as_mut() {
+                Some(body) => body,
+                None => return true,
+            };
+            match body {
+                Body::Length(remaining) => {
+                    let take = (*remaining).min(data.len() as u64);
+                    *remaining -= take;
+                    self.size += take;
+                    stream.consume(take as usize);
+                    return *remaining == 0;
+                }
+                Body::UntilClose => {
+                    self.size += data.len() as u64;
+                    stream.consume(data.len());
+                    return closed;
+                }
+                Body::Chunked(chunk) => match chunk {
+                    Chunk::Size | Chunk::Trailer => {
+                        let end = match data.windows(2).position(|window| window == b"\r\n") {
+                            Some(end) => end,
+                            None => return false,
+                        };
+                        let line = String::from_utf8_lossy(&data[..end]).into_owned();
+                        self.size += (end + 2) as u64;
+                        stream.consume(end + 2);
+                        if let Chunk::Trailer = chunk {
+                            if line.is_empty() {
+                                return true;
+                            }
+                            continue;
+                        }
+                        let size = line.split(';').next().unwrap_or("").trim();
+                        *chunk = match u64::from_str_radix(size, 16) {
+                            Ok(0) => Chunk::Trailer,
+                            Ok(size) => Chunk::Data(size),
+                            // not chunked after all, read on until close
+                            Err(_) => {
+                                self.body = Some(Body::UntilClose);
+                                continue;
+                            }
+                        };
+                    }
+                    Chunk::Data(remaining) => {
+                        let take = (*remaining).min(data.len() as u64);
+                        *remaining -= take;
+                        self.size += take;
+                        stream.consume(take as usize);
+                        if *remaining > 0 {
+                            return false;
+                        }
+                        *chunk = Chunk::DataEnd;
+                    }
+                    Chunk::DataEnd => {
+                        if data.len() < 2 {
+                            return false;
+                        }
+                        self.size += 2;
+                        stream.consume(2);
+                        *chunk = Chunk::Size;
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flows::tests::tcp_frame;
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const SERVER: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80);
+    const ACK: u8 = 0x10;
+
+    fn client(port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), port)
+    }
+
+    fn update(dissector: &mut Dissector, frame: &[u8]) -> Vec<Transaction> {
+        let packet = EthernetPacket::new(frame).unwrap();
+        dissector.update(&packet, SystemTime::UNIX_EPOCH)
+    }
+
+    #[test]
+    fn connections_are_bounded() {
+        let mut dissector = Dissector::new(Config {
+            max_connections: 2,
+            ..Default::default()
+        });
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+        for port in 40000..40003 {
+            update(
+                &mut dissector,
+                &tcp_frame(client(port), SERVER, 1, ACK, request),
+            );
+        }
+        assert_eq!(dissector.len(), 2);
+        assert_eq!(dissector.untracked(), 1);
+
+        let response = b"HTTP/1.1 204 No Content\r\n\r\n";
+        let done = update(
+            &mut dissector,
+            &tcp_frame(SERVER, client(40000), 1, ACK, response),
+        );
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].status, Some(204));
+        assert!(!done[0].incomplete);
+    }
+
+    #[test]
+    fn missing_data_marks_requests_incomplete() {
+        let mut dissector = Dissector::new(Config {
+            max_pending: 1,
+            ..Default::default()
+        });
+        let head = b"POST /upload HTTP/1.1\r\nContent-Length: 100\r\n\r\n";
+        let client = client(40000);
+        update(&mut dissector, &tcp_frame(client, SERVER, 1, ACK, head));
+        // the segments after a lost one outnumber those held
+        let start = 1 + head.len() as u32;
+        update(
+            &mut dissector,
+            &tcp_frame(client, SERVER, start + 10, ACK, &[0; 10]),
+        );
+        let done = update(
+            &mut dissector,
+            &tcp_frame(client, SERVER, start + 20, ACK, &[0; 10]),
+        );
+
+        assert!(dissector.is_empty());
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].path, "/upload");
+        assert_eq!(done[0].status, None);
+        assert!(done[0].incomplete);
+        assert!(done[0].to_string().ends_with(" incomplete"));
+    }
+}
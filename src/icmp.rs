@@ -0,0 +1,345 @@
+//! ICMP messages (RFC 792).
+//!
+//! [`IcmpPacket`] gives the type and code every message starts with; [`EchoPacket`] reads
+//! echo requests and replies, which add an identifier and a sequence number. The checksum
+//! covers the message only, unlike those of TCP and UDP.
+
+use crate::{
+    arp::ether::{FromPacket, Packet},
+    checksum::{add, finish},
+};
+use std::fmt;
+
+/// The type of an ICMP message.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
+pub struct IcmpType(pub u8);
+
+impl IcmpType {
+    /// Create a new `IcmpType`.
+    pub fn new(value: u8) -> Self {
+        IcmpType(value)
+    }
+}
+
+/// ICMP message types.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod IcmpTypes {
+    use super::IcmpType;
+
+    /// Echo Reply
+    pub const EchoReply: IcmpType = IcmpType(0);
+    /// Destination Unreachable
+    pub const DestinationUnreachable: IcmpType = IcmpType(3);
+    /// Redirect
+    pub const RedirectMessage: IcmpType = IcmpType(5);
+    /// Echo Request
+    pub const EchoRequest: IcmpType = IcmpType(8);
+    /// Time Exceeded
+    pub const TimeExceeded: IcmpType = IcmpType(11);
+    /// Parameter Problem
+    pub const ParameterProblem: IcmpType = IcmpType(12);
+    /// Timestamp
+    pub const Timestamp: IcmpType = IcmpType(13);
+    /// Timestamp Reply
+    pub const TimestampReply: IcmpType = IcmpType(14);
+}
+
+/// The code of an ICMP message, its meaning depending on the type.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
+pub struct IcmpCode(pub u8);
+
+impl IcmpCode {
+    /// Create a new `IcmpCode`.
+    pub fn new(value: u8) -> Self {
+        IcmpCode(value)
+    }
+}
+
+fn icmp_header_len(_data: &[u8]) -> usize {
+    4
+}
+
+fn echo_header_len(_data: &[u8]) -> usize {
+    8
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    data.len()
+}
+
+// the message with the checksum field taken out
+fn message_checksum(data: &[u8]) -> u16 {
+    finish(add(add(0, &data[..2]), &data[4..]))
+}
+
+packet_types! {
+    /// An ICMP message. The payload is what follows the checksum.
+    pub struct IcmpPacket / MutableIcmpPacket;
+    minimum_size = 4;
+    header_len = icmp_header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the message type
+        #[inline]
+        pub fn get_icmp_type(&self) -> IcmpType {
+            IcmpType(self.packet[0])
+        }
+        /// Get the message code
+        #[inline]
+        pub fn get_icmp_code(&self) -> IcmpCode {
+            IcmpCode(self.packet[1])
+        }
+        /// Get the checksum field
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+    }
+    setters {
+        /// Set the message type
+        #[inline]
+        pub fn set_icmp_type(&mut self, val: IcmpType) {
+            self.packet[0] = val.0;
+        }
+        /// Set the message code
+        #[inline]
+        pub fn set_icmp_code(&mut self, val: IcmpCode) {
+            self.packet[1] = val.0;
+        }
+        /// Set the checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let len = vals.len().min(self.packet.len() - 4);
+            self.packet[4..4 + len].copy_from_slice(&vals[..len]);
+        }
+        /// Recompute the checksum
+        pub fn update_checksum(&mut self) {
+            let sum = message_checksum(&self.packet[..]);
+            self.set_checksum(sum);
+        }
+    }
+}
+
+packet_types! {
+    /// An ICMP echo request or reply. The payload is the data echoed back.
+    pub struct EchoPacket / MutableEchoPacket;
+    minimum_size = 8;
+    header_len = echo_header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the message type, `EchoRequest` or `EchoReply`
+        #[inline]
+        pub fn get_icmp_type(&self) -> IcmpType {
+            IcmpType(self.packet[0])
+        }
+        /// Get the message code, always 0
+        #[inline]
+        pub fn get_icmp_code(&self) -> IcmpCode {
+            IcmpCode(self.packet[1])
+        }
+        /// Get the checksum field
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+        /// Get the identifier, telling apart the requests of different senders
+        #[inline]
+        pub fn get_identifier(&self) -> u16 {
+            u16::from_be_bytes([self.packet[4], self.packet[5]])
+        }
+        /// Get the sequence number
+        #[inline]
+        pub fn get_sequence_number(&self) -> u16 {
+            u16::from_be_bytes([self.packet[6], self.packet[7]])
+        }
+        /// Whether this is an echo request.
+        pub fn is_request(&self) -> bool {
+            self.get_icmp_type() == IcmpTypes::EchoRequest
+        }
+        /// Whether this is an echo reply.
+        pub fn is_reply(&self) -> bool {
+            self.get_icmp_type() == IcmpTypes::EchoReply
+        }
+    }
+    setters {
+        /// Set the message type
+        #[inline]
+        pub fn set_icmp_type(&mut self, val: IcmpType) {
+            self.packet[0] = val.0;
+        }
+        /// Set the message code
+        #[inline]
+        pub fn set_icmp_code(&mut self, val: IcmpCode) {
+            self.packet[1] = val.0;
+        }
+        /// Set the checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the identifier
+        #[inline]
+        pub fn set_identifier(&mut self, val: u16) {
+            self.packet[4..6].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the sequence number
+        #[inline]
+        pub fn set_sequence_number(&mut self, val: u16) {
+            self.packet[6..8].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let len = vals.len().min(self.packet.len() - 8);
+            self.packet[8..8 + len].copy_from_slice(&vals[..len]);
+        }
+        /// Recompute the checksum
+        pub fn update_checksum(&mut self) {
+            let sum = message_checksum(&self.packet[..]);
+            self.set_checksum(sum);
+        }
+    }
+}
+
+/// The checksum of `packet`, computed with the checksum field zeroed.
+pub fn checksum(packet: &IcmpPacket) -> u16 {
+    message_checksum(packet.packet())
+}
+
+/// An echo message of `icmp_type` with its checksum filled in.
+fn echo(icmp_type: IcmpType, identifier: u16, sequence: u16, payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![0u8; 8 + payload.len()];
+    let mut echo = MutableEchoPacket::new(&mut message).unwrap();
+    echo.set_icmp_type(icmp_type);
+    echo.set_identifier(identifier);
+    echo.set_sequence_number(sequence);
+    echo.set_payload(payload);
+    echo.update_checksum();
+    message
+}
+
+/// An echo request carrying `payload`.
+pub fn echo_request(identifier: u16, sequence: u16, payload: &[u8]) -> Vec<u8> {
+    echo(IcmpTypes::EchoRequest, identifier, sequence, payload)
+}
+
+/// An echo reply carrying `payload`.
+pub fn echo_reply(identifier: u16, sequence: u16, payload: &[u8]) -> Vec<u8> {
+    echo(IcmpTypes::EchoReply, identifier, sequence, payload)
+}
+
+/// The reply to `request`, echoing its identifier, sequence number and payload, None if
+/// it isn't an echo request.
+pub fn reply_to(request: &EchoPacket) -> Option<Vec<u8>> {
+    if !request.is_request() {
+        return None;
+    }
+    Some(echo_reply(
+        request.get_identifier(),
+        request.get_sequence_number(),
+        request.payload(),
+    ))
+}
+
+/// An ICMP message, owned.
+#[derive(Clone, Debug)]
+pub struct Icmp {
+    pub icmp_type: IcmpType,
+    pub icmp_code: IcmpCode,
+    pub checksum: u16,
+    pub payload: Vec<u8>,
+}
+
+/// An ICMP echo request or reply, owned.
+#[derive(Clone, Debug)]
+pub struct Echo {
+    pub icmp_type: IcmpType,
+    pub icmp_code: IcmpCode,
+    pub checksum: u16,
+    pub identifier: u16,
+    pub sequence_number: u16,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for IcmpPacket<'p> {
+    type T = Icmp;
+    fn from_packet(&self) -> Icmp {
+        Icmp {
+            icmp_type: self.get_icmp_type(),
+            icmp_code: self.get_icmp_code(),
+            checksum: self.get_checksum(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableIcmpPacket<'p> {
+    type T = Icmp;
+    fn from_packet(&self) -> Icmp {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> FromPacket for EchoPacket<'p> {
+    type T = Echo;
+    fn from_packet(&self) -> Echo {
+        Echo {
+            icmp_type: self.get_icmp_type(),
+            icmp_code: self.get_icmp_code(),
+            checksum: self.get_checksum(),
+            identifier: self.get_identifier(),
+            sequence_number: self.get_sequence_number(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableEchoPacket<'p> {
+    type T = Echo;
+    fn from_packet(&self) -> Echo {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for IcmpPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "IcmpPacket {{ icmp_type : {:?}, icmp_code : {:?}, checksum : {:#06x} }}",
+            self.get_icmp_type(),
+            self.get_icmp_code(),
+            self.get_checksum()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableIcmpPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
+
+impl<'p> fmt::Debug for EchoPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "EchoPacket {{ icmp_type : {:?}, icmp_code : {:?}, checksum : {:#06x}, \
+             identifier : {}, sequence_number : {} }}",
+            self.get_icmp_type(),
+            self.get_icmp_code(),
+            self.get_checksum(),
+            self.get_identifier(),
+            self.get_sequence_number()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableEchoPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
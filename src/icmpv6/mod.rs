@@ -0,0 +1,184 @@
+//! ICMPv6 messages (RFC 4443).
+//!
+//! [`Icmpv6Packet`] gives the type and code every message starts with; the Neighbor
+//! Discovery messages are in [`ndp`]. Like those of TCP and UDP, the checksum covers a
+//! pseudo-header of the enclosing IPv6 packet, so [`checksum`] takes its addresses.
+
+pub mod ndp;
+
+use crate::{
+    arp::ether::{FromPacket, Packet},
+    checksum::{add, finish, ipv6_pseudo_header},
+    ipproto::IPPROTO_ICMPV6,
+};
+use std::{fmt, net::Ipv6Addr};
+
+/// The type of an ICMPv6 message.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
+pub struct Icmpv6Type(pub u8);
+
+impl Icmpv6Type {
+    /// Create a new `Icmpv6Type`.
+    pub fn new(value: u8) -> Self {
+        Icmpv6Type(value)
+    }
+
+    /// Whether messages of this type report errors, which are never answered with errors.
+    pub fn is_error(&self) -> bool {
+        self.0 < 128
+    }
+}
+
+/// ICMPv6 message types.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod Icmpv6Types {
+    use super::Icmpv6Type;
+
+    /// Destination Unreachable
+    pub const DestinationUnreachable: Icmpv6Type = Icmpv6Type(1);
+    /// Packet Too Big
+    pub const PacketTooBig: Icmpv6Type = Icmpv6Type(2);
+    /// Time Exceeded
+    pub const TimeExceeded: Icmpv6Type = Icmpv6Type(3);
+    /// Parameter Problem
+    pub const ParameterProblem: Icmpv6Type = Icmpv6Type(4);
+    /// Echo Request
+    pub const EchoRequest: Icmpv6Type = Icmpv6Type(128);
+    /// Echo Reply
+    pub const EchoReply: Icmpv6Type = Icmpv6Type(129);
+    /// Router Solicitation
+    pub const RouterSolicit: Icmpv6Type = Icmpv6Type(133);
+    /// Router Advertisement
+    pub const RouterAdvert: Icmpv6Type = Icmpv6Type(134);
+    /// Neighbor Solicitation
+    pub const NeighborSolicit: Icmpv6Type = Icmpv6Type(135);
+    /// Neighbor Advertisement
+    pub const NeighborAdvert: Icmpv6Type = Icmpv6Type(136);
+    /// Redirect
+    pub const Redirect: Icmpv6Type = Icmpv6Type(137);
+}
+
+/// The code of an ICMPv6 message, its meaning depending on the type.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
+pub struct Icmpv6Code(pub u8);
+
+impl Icmpv6Code {
+    /// Create a new `Icmpv6Code`.
+    pub fn new(value: u8) -> Self {
+        Icmpv6Code(value)
+    }
+}
+
+fn header_len(_data: &[u8]) -> usize {
+    4
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    data.len()
+}
+
+packet_types! {
+    /// An ICMPv6 message. The payload is what follows the checksum.
+    pub struct Icmpv6Packet / MutableIcmpv6Packet;
+    minimum_size = 4;
+    header_len = header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the message type
+        #[inline]
+        pub fn get_icmpv6_type(&self) -> Icmpv6Type {
+            Icmpv6Type(self.packet[0])
+        }
+        /// Get the message code
+        #[inline]
+        pub fn get_icmpv6_code(&self) -> Icmpv6Code {
+            Icmpv6Code(self.packet[1])
+        }
+        /// Get the checksum field
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+    }
+    setters {
+        /// Set the message type
+        #[inline]
+        pub fn set_icmpv6_type(&mut self, val: Icmpv6Type) {
+            self.packet[0] = val.0;
+        }
+        /// Set the message code
+        #[inline]
+        pub fn set_icmpv6_code(&mut self, val: Icmpv6Code) {
+            self.packet[1] = val.0;
+        }
+        /// Set the checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let len = vals.len().min(self.packet.len() - 4);
+            self.packet[4..4 + len].copy_from_slice(&vals[..len]);
+        }
+    }
+}
+
+/// The checksum of `message`, any ICMPv6 message, carried from `source` to
+/// `destination`.
+pub fn message_checksum(message: &[u8], source: Ipv6Addr, destination: Ipv6Addr) -> u16 {
+    let pseudo = ipv6_pseudo_header(source, destination, IPPROTO_ICMPV6, message.len() as u32);
+    finish(add(add(pseudo, &message[..2]), &message[4..]))
+}
+
+/// The checksum of `packet` carried from `source` to `destination`.
+pub fn checksum(packet: &Icmpv6Packet, source: Ipv6Addr, destination: Ipv6Addr) -> u16 {
+    message_checksum(packet.packet(), source, destination)
+}
+
+/// An ICMPv6 message, owned.
+#[derive(Clone, Debug)]
+pub struct Icmpv6 {
+    pub icmpv6_type: Icmpv6Type,
+    pub icmpv6_code: Icmpv6Code,
+    pub checksum: u16,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for Icmpv6Packet<'p> {
+    type T = Icmpv6;
+    fn from_packet(&self) -> Icmpv6 {
+        Icmpv6 {
+            icmpv6_type: self.get_icmpv6_type(),
+            icmpv6_code: self.get_icmpv6_code(),
+            checksum: self.get_checksum(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableIcmpv6Packet<'p> {
+    type T = Icmpv6;
+    fn from_packet(&self) -> Icmpv6 {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for Icmpv6Packet<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Icmpv6Packet {{ icmpv6_type : {:?}, icmpv6_code : {:?}, checksum : {:#06x} }}",
+            self.get_icmpv6_type(),
+            self.get_icmpv6_code(),
+            self.get_checksum()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableIcmpv6Packet<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
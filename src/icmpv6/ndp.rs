@@ -0,0 +1,528 @@
+//! Neighbor Discovery messages (RFC 4861).
+//!
+//! Options follow the fixed part of each message. Every option is a type byte, a length
+//! byte counting units of 8 bytes, type and length included, and data.
+
+use super::{message_checksum, Icmpv6Code, Icmpv6Type};
+use crate::arp::{
+    arp::{Error, Result},
+    network_interface::MacAddr,
+};
+use std::{fmt, net::Ipv6Addr};
+
+/// Option type numbers.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod NdpOptionKinds {
+    /// Source Link-Layer Address.
+    pub const SourceLinkLayerAddress: u8 = 1;
+    /// Target Link-Layer Address.
+    pub const TargetLinkLayerAddress: u8 = 2;
+    /// Prefix Information.
+    pub const PrefixInformation: u8 = 3;
+    /// Redirected Header.
+    pub const RedirectedHeader: u8 = 4;
+    /// MTU.
+    pub const Mtu: u8 = 5;
+}
+
+/// A Neighbor Discovery option.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NdpOption {
+    /// MAC address of the sender
+    SourceLinkLayerAddress(MacAddr),
+    /// MAC address of the target
+    TargetLinkLayerAddress(MacAddr),
+    /// A prefix of the link, sent by routers
+    PrefixInformation {
+        prefix_length: u8,
+        /// Addresses of the prefix are on the link
+        on_link: bool,
+        /// Hosts may configure addresses of the prefix themselves
+        autonomous: bool,
+        /// Seconds the prefix is on the link, all ones meaning forever
+        valid_lifetime: u32,
+        /// Seconds addresses of the prefix stay preferred
+        preferred_lifetime: u32,
+        prefix: Ipv6Addr,
+    },
+    /// As much of the redirected packet as fits
+    RedirectedHeader(Vec<u8>),
+    /// MTU of the link, sent by routers
+    Mtu(u32),
+    /// An option this module does not know about, without its type and length bytes
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl NdpOption {
+    /// The option type number.
+    pub fn kind(&self) -> u8 {
+        match self {
+            NdpOption::SourceLinkLayerAddress(_) => NdpOptionKinds::SourceLinkLayerAddress,
+            NdpOption::TargetLinkLayerAddress(_) => NdpOptionKinds::TargetLinkLayerAddress,
+            NdpOption::PrefixInformation { .. } => NdpOptionKinds::PrefixInformation,
+            NdpOption::RedirectedHeader(_) => NdpOptionKinds::RedirectedHeader,
+            NdpOption::Mtu(_) => NdpOptionKinds::Mtu,
+            NdpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Number of bytes the option takes on the wire, padding included.
+    pub fn encoded_len(&self) -> usize {
+        let len = match self {
+            NdpOption::SourceLinkLayerAddress(_) | NdpOption::TargetLinkLayerAddress(_) => 8,
+            NdpOption::PrefixInformation { .. } => 32,
+            // six reserved bytes come first
+            NdpOption::RedirectedHeader(data) => 8 + data.len(),
+            NdpOption::Mtu(_) => 8,
+            NdpOption::Unknown { data, .. } => 2 + data.len(),
+        };
+        (len + 7) / 8 * 8
+    }
+
+    /// Append the option to `out`.
+    ///
+    /// Fails with `Malformed` if it is longer than a length byte can say.
+    pub fn write(&self, out: &mut Vec<u8>) -> Result<()> {
+        let len = self.encoded_len();
+        if len / 8 > usize::from(u8::MAX) {
+            return Err(Error::malformed("option length", 1));
+        }
+        let start = out.len();
+        out.push(self.kind());
+        out.push((len / 8) as u8);
+        match self {
+            NdpOption::SourceLinkLayerAddress(mac) | NdpOption::TargetLinkLayerAddress(mac) => {
+                let MacAddr(a, b, c, d, e, f) = *mac;
+                out.extend_from_slice(&[a, b, c, d, e, f]);
+            }
+            NdpOption::PrefixInformation {
+                prefix_length,
+                on_link,
+                autonomous,
+                valid_lifetime,
+                preferred_lifetime,
+                prefix,
+            } => {
+                out.push(*prefix_length);
+                out.push((*on_link as u8) << 7 | (*autonomous as u8) << 6);
+                out.extend_from_slice(&valid_lifetime.to_be_bytes());
+                out.extend_from_slice(&preferred_lifetime.to_be_bytes());
+                out.extend_from_slice(&[0; 4]);
+                out.extend_from_slice(&prefix.octets());
+            }
+            NdpOption::RedirectedHeader(data) => {
+                out.extend_from_slice(&[0; 6]);
+                out.extend_from_slice(data);
+            }
+            NdpOption::Mtu(mtu) => {
+                out.extend_from_slice(&[0; 2]);
+                out.extend_from_slice(&mtu.to_be_bytes());
+            }
+            NdpOption::Unknown { data, .. } => out.extend_from_slice(data),
+        }
+        out.resize(start + len, 0);
+        Ok(())
+    }
+
+    fn parse(kind: u8, data: &[u8]) -> Result<NdpOption> {
+        let word = |data: &[u8]| u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        Ok(match kind {
+            NdpOptionKinds::SourceLinkLayerAddress | NdpOptionKinds::TargetLinkLayerAddress => {
+                if data.len() < 6 {
+                    return Err(Error::malformed("link-layer address", 0));
+                }
+                let mac = MacAddr::new(data[0], data[1], data[2], data[3], data[4], data[5]);
+                if kind == NdpOptionKinds::SourceLinkLayerAddress {
+                    NdpOption::SourceLinkLayerAddress(mac)
+                } else {
+                    NdpOption::TargetLinkLayerAddress(mac)
+                }
+            }
+            NdpOptionKinds::PrefixInformation => {
+                if data.len() != 30 {
+                    return Err(Error::malformed("prefix information", 0));
+                }
+                let mut prefix = [0u8; 16];
+                prefix.copy_from_slice(&data[14..30]);
+                NdpOption::PrefixInformation {
+                    prefix_length: data[0],
+                    on_link: data[1] & 0x80 != 0,
+                    autonomous: data[1] & 0x40 != 0,
+                    valid_lifetime: word(&data[2..6]),
+                    preferred_lifetime: word(&data[6..10]),
+                    prefix: Ipv6Addr::from(prefix),
+                }
+            }
+            NdpOptionKinds::RedirectedHeader => NdpOption::RedirectedHeader(data[6..].to_vec()),
+            NdpOptionKinds::Mtu => NdpOption::Mtu(word(&data[2..6])),
+            _ => NdpOption::Unknown {
+                kind,
+                data: data.to_vec(),
+            },
+        })
+    }
+}
+
+/// Iterator over the options of a message.
+///
+/// Stops after the first option that is truncated or malformed, which it yields as an
+/// error with its offset in the options area.
+#[derive(Clone, Debug)]
+pub struct NdpOptions<'a> {
+    data: &'a [u8],
+    // of `data` in what was passed to `new`
+    offset: usize,
+}
+
+impl<'a> NdpOptions<'a> {
+    /// Iterate over `data`, the bytes after the fixed part of the message.
+    pub fn new(data: &'a [u8]) -> NdpOptions<'a> {
+        NdpOptions { data, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for NdpOptions<'a> {
+    type Item = Result<NdpOption>;
+
+    fn next(&mut self) -> Option<Result<NdpOption>> {
+        let kind = *self.data.first()?;
+        let len = match self.data.get(1) {
+            Some(&len) => usize::from(len) * 8,
+            None => {
+                self.data = &[];
+                return Some(Err(Error::truncated("option length", self.offset + 1)));
+            }
+        };
+        // a zero length would never end
+        if len == 0 {
+            self.data = &[];
+            return Some(Err(Error::malformed("option length", self.offset + 1)));
+        }
+        if len > self.data.len() {
+            self.data = &[];
+            return Some(Err(Error::truncated("option data", self.offset + 2)));
+        }
+        let option =
+            NdpOption::parse(kind, &self.data[2..len]).map_err(|e| e.shifted(self.offset + 2));
+        self.data = if option.is_ok() {
+            self.offset += len;
+            &self.data[len..]
+        } else {
+            &[]
+        };
+        Some(option)
+    }
+}
+
+/// Encode `options` for a message.
+pub fn encode(options: &[NdpOption]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for option in options {
+        option.write(&mut out)?;
+    }
+    Ok(out)
+}
+
+/// Router Advertisement flags.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod RouterAdvertFlags {
+    /// Addresses are available from DHCPv6
+    pub const ManagedAddressConf: u8 = 0x80;
+    /// Other configuration is available from DHCPv6
+    pub const OtherConf: u8 = 0x40;
+}
+
+/// Neighbor Advertisement flags.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod NeighborAdvertFlags {
+    /// The sender is a router
+    pub const Router: u8 = 0x80;
+    /// The advertisement answers a solicitation
+    pub const Solicited: u8 = 0x40;
+    /// The advertisement replaces cached link-layer addresses
+    pub const Override: u8 = 0x20;
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    data.len()
+}
+
+fn address(data: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&data[..16]);
+    Ipv6Addr::from(octets)
+}
+
+// the accessors every message has, the options being its payload
+macro_rules! common_getters {
+    () => {
+        /// Get the message type
+        #[inline]
+        pub fn get_icmpv6_type(&self) -> Icmpv6Type {
+            Icmpv6Type(self.packet[0])
+        }
+        /// Get the message code, always 0
+        #[inline]
+        pub fn get_icmpv6_code(&self) -> Icmpv6Code {
+            Icmpv6Code(self.packet[1])
+        }
+        /// Get the checksum field
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+        /// Iterate over the options
+        pub fn get_options_iter(&self) -> NdpOptions {
+            NdpOptions::new(crate::arp::ether::Packet::payload(self))
+        }
+        /// Get the options, up to the first malformed one
+        pub fn get_options(&self) -> Vec<NdpOption> {
+            self.get_options_iter().map_while(Result::ok).collect()
+        }
+    };
+}
+
+macro_rules! common_setters {
+    () => {
+        /// Set the message type
+        #[inline]
+        pub fn set_icmpv6_type(&mut self, val: Icmpv6Type) {
+            self.packet[0] = val.0;
+        }
+        /// Set the message code
+        #[inline]
+        pub fn set_icmpv6_code(&mut self, val: Icmpv6Code) {
+            self.packet[1] = val.0;
+        }
+        /// Set the checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Copy `vals` into the options area
+        pub fn set_options_raw(&mut self, vals: &[u8]) {
+            let options = crate::arp::ether::MutablePacket::payload_mut(self);
+            let len = vals.len().min(options.len());
+            options[..len].copy_from_slice(&vals[..len]);
+        }
+        /// Recompute the checksum for a packet from `source` to `destination`
+        pub fn update_checksum(&mut self, source: Ipv6Addr, destination: Ipv6Addr) {
+            let sum = message_checksum(&self.packet[..], source, destination);
+            self.set_checksum(sum);
+        }
+    };
+}
+
+fn router_solicit_len(_data: &[u8]) -> usize {
+    8
+}
+
+packet_types! {
+    /// A Router Solicitation, asking routers to advertise themselves.
+    pub struct RouterSolicitPacket / MutableRouterSolicitPacket;
+    minimum_size = 8;
+    header_len = router_solicit_len;
+    packet_len = packet_len;
+    getters {
+        common_getters!();
+    }
+    setters {
+        common_setters!();
+    }
+}
+
+fn router_advert_len(_data: &[u8]) -> usize {
+    16
+}
+
+packet_types! {
+    /// A Router Advertisement, announcing a router and the configuration of the link.
+    pub struct RouterAdvertPacket / MutableRouterAdvertPacket;
+    minimum_size = 16;
+    header_len = router_advert_len;
+    packet_len = packet_len;
+    getters {
+        common_getters!();
+        /// Get the hop limit hosts should use, 0 if unspecified
+        #[inline]
+        pub fn get_hop_limit(&self) -> u8 {
+            self.packet[4]
+        }
+        /// Get the flags, see `RouterAdvertFlags`
+        #[inline]
+        pub fn get_flags(&self) -> u8 {
+            self.packet[5]
+        }
+        /// Get the seconds the router can be a default router, 0 if it can't
+        #[inline]
+        pub fn get_lifetime(&self) -> u16 {
+            u16::from_be_bytes([self.packet[6], self.packet[7]])
+        }
+        /// Get the milliseconds a neighbor stays reachable, 0 if unspecified
+        #[inline]
+        pub fn get_reachable_time(&self) -> u32 {
+            u32::from_be_bytes([self.packet[8], self.packet[9], self.packet[10], self.packet[11]])
+        }
+        /// Get the milliseconds between solicitations, 0 if unspecified
+        #[inline]
+        pub fn get_retrans_time(&self) -> u32 {
+            u32::from_be_bytes([self.packet[12], self.packet[13], self.packet[14], self.packet[15]])
+        }
+    }
+    setters {
+        common_setters!();
+        /// Set the hop limit hosts should use
+        #[inline]
+        pub fn set_hop_limit(&mut self, val: u8) {
+            self.packet[4] = val;
+        }
+        /// Set the flags
+        #[inline]
+        pub fn set_flags(&mut self, val: u8) {
+            self.packet[5] = val;
+        }
+        /// Set the router lifetime, in seconds
+        #[inline]
+        pub fn set_lifetime(&mut self, val: u16) {
+            self.packet[6..8].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the reachable time, in milliseconds
+        #[inline]
+        pub fn set_reachable_time(&mut self, val: u32) {
+            self.packet[8..12].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the retransmission timer, in milliseconds
+        #[inline]
+        pub fn set_retrans_time(&mut self, val: u32) {
+            self.packet[12..16].copy_from_slice(&val.to_be_bytes());
+        }
+    }
+}
+
+fn neighbor_len(_data: &[u8]) -> usize {
+    24
+}
+
+packet_types! {
+    /// A Neighbor Solicitation, asking the target for its link-layer address.
+    pub struct NeighborSolicitPacket / MutableNeighborSolicitPacket;
+    minimum_size = 24;
+    header_len = neighbor_len;
+    packet_len = packet_len;
+    getters {
+        common_getters!();
+        /// Get the address whose link-layer address is asked for
+        #[inline]
+        pub fn get_target_addr(&self) -> Ipv6Addr {
+            address(&self.packet[8..24])
+        }
+    }
+    setters {
+        common_setters!();
+        /// Set the address whose link-layer address is asked for
+        #[inline]
+        pub fn set_target_addr(&mut self, val: Ipv6Addr) {
+            self.packet[8..24].copy_from_slice(&val.octets());
+        }
+    }
+}
+
+packet_types! {
+    /// A Neighbor Advertisement, announcing the link-layer address of the target.
+    pub struct NeighborAdvertPacket / MutableNeighborAdvertPacket;
+    minimum_size = 24;
+    header_len = neighbor_len;
+    packet_len = packet_len;
+    getters {
+        common_getters!();
+        /// Get the flags, see `NeighborAdvertFlags`
+        #[inline]
+        pub fn get_flags(&self) -> u8 {
+            self.packet[4]
+        }
+        /// Get the address the advertisement is about
+        #[inline]
+        pub fn get_target_addr(&self) -> Ipv6Addr {
+            address(&self.packet[8..24])
+        }
+        /// The target link-layer address option, if the advertisement has one.
+        pub fn get_target_link_layer_address(&self) -> Option<MacAddr> {
+            self.get_options_iter()
+                .map_while(Result::ok)
+                .find_map(|option| match option {
+                    NdpOption::TargetLinkLayerAddress(mac) => Some(mac),
+                    _ => None,
+                })
+        }
+    }
+    setters {
+        common_setters!();
+        /// Set the flags
+        #[inline]
+        pub fn set_flags(&mut self, val: u8) {
+            self.packet[4] = val;
+        }
+        /// Set the address the advertisement is about
+        #[inline]
+        pub fn set_target_addr(&mut self, val: Ipv6Addr) {
+            self.packet[8..24].copy_from_slice(&val.octets());
+        }
+    }
+}
+
+impl<'p> fmt::Debug for RouterSolicitPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "RouterSolicitPacket {{ checksum : {:#06x}, options : {:?} }}",
+            self.get_checksum(),
+            self.get_options()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for RouterAdvertPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "RouterAdvertPacket {{ checksum : {:#06x}, hop_limit : {}, flags : {:#04x}, \
+             lifetime : {}, reachable_time : {}, retrans_time : {}, options : {:?} }}",
+            self.get_checksum(),
+            self.get_hop_limit(),
+            self.get_flags(),
+            self.get_lifetime(),
+            self.get_reachable_time(),
+            self.get_retrans_time(),
+            self.get_options()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for NeighborSolicitPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "NeighborSolicitPacket {{ checksum : {:#06x}, target_addr : {}, options : {:?} }}",
+            self.get_checksum(),
+            self.get_target_addr(),
+            self.get_options()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for NeighborAdvertPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "NeighborAdvertPacket {{ checksum : {:#06x}, flags : {:#04x}, target_addr : {}, \
+             options : {:?} }}",
+            self.get_checksum(),
+            self.get_flags(),
+            self.get_target_addr(),
+            self.get_options()
+        )
+    }
+}
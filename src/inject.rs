@@ -0,0 +1,143 @@
+//! Sending many frames through one channel.
+//!
+//! Opening a channel means creating a packet socket, binding it and computing the link
+//! layer address frames are sent to. An [`Injector`] does this once for an interface and
+//! then builds every frame in the same buffer, so tools transmitting often only pay for
+//! the `sendto` itself.
+
+use crate::arp::{
+    arp_new::{ArpOperation, ArpOperations},
+    channel::{channel, Channel, Config as ChannelConfig, EthernetDataLinkSender},
+    ether::{EtherType, EthernetPacket, MutableEthernetPacket},
+    network_interface::{MacAddr, NetworkInterface},
+    other::build_arp_packet,
+};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr},
+};
+
+/// Length of an Ethernet header.
+const HEADER_LEN: usize = 14;
+
+/// A channel sender kept open for an interface, with the addresses frames are sent from.
+pub struct Injector {
+    tx: Box<dyn EthernetDataLinkSender>,
+    mac: MacAddr,
+    ip: Option<Ipv4Addr>,
+    buffer: Vec<u8>,
+    sent: u64,
+}
+
+impl Injector {
+    /// Open a channel on `interface`, sending from its MAC address and first IPv4 address.
+    pub fn open(interface: &NetworkInterface, config: ChannelConfig) -> io::Result<Injector> {
+        let mac = interface.mac.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("interface {} has no MAC address", interface.name),
+            )
+        })?;
+        let tx = match channel(interface, config)? {
+            Channel::Ethernet(tx, _) => tx,
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+        };
+        let ip = interface.ips.iter().flatten().find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(*ip),
+            _ => None,
+        });
+        Ok(Injector::new(tx, mac, ip))
+    }
+
+    /// Send through an already open sender, from `mac` and, for ARP, `ip`.
+    pub fn new(
+        tx: Box<dyn EthernetDataLinkSender>,
+        mac: MacAddr,
+        ip: Option<Ipv4Addr>,
+    ) -> Injector {
+        Injector {
+            tx,
+            mac,
+            ip,
+            buffer: Vec::new(),
+            sent: 0,
+        }
+    }
+
+    /// The MAC address frames are sent from.
+    pub fn mac(&self) -> MacAddr {
+        self.mac
+    }
+
+    /// The IPv4 address ARP packets are sent from, if the interface has one.
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.ip
+    }
+
+    /// Number of frames sent so far.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Send an ARP packet asking for or announcing `target_ip`.
+    ///
+    /// Requests are broadcast; other operations go to `target_mac` directly.
+    pub fn send_arp(
+        &mut self,
+        operation: ArpOperation,
+        target_ip: Ipv4Addr,
+        target_mac: MacAddr,
+    ) -> io::Result<()> {
+        let source_ip = self.ip.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no IPv4 address to send from")
+        })?;
+        let destination = if operation == ArpOperations::Request {
+            MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff)
+        } else {
+            target_mac
+        };
+        let frame = build_arp_packet(
+            destination,
+            self.mac,
+            source_ip,
+            target_mac,
+            target_ip,
+            operation,
+        );
+        self.send_raw(&frame)
+    }
+
+    /// Send `payload` to `destination` in an Ethernet frame of type `ethertype`.
+    pub fn send_ethernet(
+        &mut self,
+        destination: MacAddr,
+        ethertype: EtherType,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let mut buffer = std::mem::replace(&mut self.buffer, Vec::new());
+        buffer.clear();
+        buffer.resize(HEADER_LEN + payload.len(), 0);
+        {
+            let mut ethernet = MutableEthernetPacket::new(&mut buffer[..]).unwrap();
+            ethernet.set_destination(destination);
+            ethernet.set_source(self.mac);
+            ethernet.set_ethertype(ethertype);
+            ethernet.set_payload(payload);
+        }
+        let result = self.send_raw(&buffer);
+        self.buffer = buffer;
+        result
+    }
+
+    /// Send a complete Ethernet frame as is.
+    pub fn send_raw(&mut self, frame: &[u8]) -> io::Result<()> {
+        let packet = EthernetPacket::new(frame).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "frame shorter than a header")
+        })?;
+        self.tx
+            .send_to(&packet, None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+        self.sent += 1;
+        Ok(())
+    }
+}
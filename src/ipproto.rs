@@ -0,0 +1,7 @@
+//! Numbers of the IP protocols the crate reads and writes, as carried in the protocol
+//! field of an IPv4 header and the next header field of an IPv6 one.
+
+pub(crate) const IPPROTO_ICMP: u8 = 1;
+pub(crate) const IPPROTO_TCP: u8 = 6;
+pub(crate) const IPPROTO_UDP: u8 = 17;
+pub(crate) const IPPROTO_ICMPV6: u8 = 58;
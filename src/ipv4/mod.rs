@@ -0,0 +1,168 @@
+//! IPv4 headers.
+//!
+//! The functions here work on the bytes of an IPv4 packet, starting at its header: the
+//! header length field (IHL) gives where the options end and the payload starts.
+
+pub mod options;
+pub mod packet;
+
+pub use options::{Ipv4Option, Options, TimestampFormat};
+pub use packet::{
+    header_checksum, IpNextHeaderProtocol, IpNextHeaderProtocols, Ipv4, Ipv4Flags, Ipv4Packet,
+    MutableIpv4Packet,
+};
+
+use crate::{
+    arp::arp::{Error, Result},
+    checksum::{self, Layer},
+    ipproto::{IPPROTO_ICMP, IPPROTO_TCP, IPPROTO_UDP},
+};
+use std::net::Ipv4Addr;
+
+/// Length of a header without options.
+pub const MIN_HEADER_LEN: usize = 20;
+
+/// Length of a header with the most options.
+pub const MAX_HEADER_LEN: usize = 60;
+
+/// Length of the header of `packet`, options included, as given by its IHL field.
+pub fn header_len(packet: &[u8]) -> Result<usize> {
+    let first = *packet.first().ok_or(Error::truncated("version", 0))?;
+    if first >> 4 != 4 {
+        return Err(Error::Unrecognized);
+    }
+    let len = usize::from(first & 0x0f) * 4;
+    if len < MIN_HEADER_LEN {
+        return Err(Error::malformed("header length", 0));
+    }
+    if len > packet.len() {
+        return Err(Error::truncated("options", MIN_HEADER_LEN));
+    }
+    Ok(len)
+}
+
+/// Iterate over the options of the header of `packet`.
+pub fn options(packet: &[u8]) -> Result<Options> {
+    let len = header_len(packet)?;
+    Ok(Options::new(&packet[MIN_HEADER_LEN..len]))
+}
+
+/// Replace the options of the header of `packet` with `new`, moving the payload.
+///
+/// The options are padded to a multiple of 4 bytes, and the IHL, total length and header
+/// checksum are updated.
+pub fn set_options(packet: &mut Vec<u8>, new: &[Ipv4Option]) -> Result<()> {
+    let len = header_len(packet)?;
+    let encoded = options::encode(new)?;
+    let total_len = packet.len() - len + MIN_HEADER_LEN + encoded.len();
+    if total_len > usize::from(u16::MAX) {
+        return Err(Error::Exhausted);
+    }
+    packet.splice(MIN_HEADER_LEN..len, encoded.iter().cloned());
+
+    let header_len = MIN_HEADER_LEN + encoded.len();
+    packet[0] = 0x40 | (header_len / 4) as u8;
+    packet[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    update_checksum(packet);
+    Ok(())
+}
+
+/// Recompute the header checksum of `packet`.
+pub fn update_checksum(packet: &mut [u8]) {
+    let len = usize::from(packet[0] & 0x0f) * 4;
+    packet[10] = 0;
+    packet[11] = 0;
+    let sum = checksum::checksum(&packet[..len]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// How checksums that don't match are judged.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ChecksumMode {
+    /// Every wrong checksum is an error
+    Strict,
+    /// Checksums left zero or partial, as they are in packets captured on the sending
+    /// host when the NIC computes them, are reported as possibly offloaded
+    OffloadAware,
+}
+
+/// Checksums of a packet that passed [`check_checksums`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ChecksumStatus {
+    /// All checksums are right
+    Valid,
+    /// The checksum of this layer is wrong but looks like the NIC was left to compute it
+    PossiblyOffloaded(Layer),
+}
+
+/// Check the header checksum of `packet` and the checksum of the ICMP message, TCP segment
+/// or UDP datagram it carries, failing with `Error::Checksum` naming the first layer whose
+/// checksum is wrong.
+///
+/// Transport checksums of fragments are not checked since they cover the whole datagram,
+/// nor are UDP checksums left out by the sender.
+pub fn verify_checksums(packet: &[u8]) -> Result<()> {
+    check_checksums(packet, ChecksumMode::Strict).map(|_| ())
+}
+
+/// Like [`verify_checksums`], except that in `OffloadAware` mode a wrong checksum that is
+/// zero, or only covers the pseudo-header, makes the packet possibly offloaded.
+pub fn check_checksums(packet: &[u8], mode: ChecksumMode) -> Result<ChecksumStatus> {
+    let offloaded = |layer, field: u16, partial: u16| match mode {
+        ChecksumMode::OffloadAware if field == 0 || field == partial => {
+            Ok(ChecksumStatus::PossiblyOffloaded(layer))
+        }
+        _ => Err(Error::Checksum(layer)),
+    };
+
+    let len = header_len(packet)?;
+    if checksum::checksum(&packet[..len]) != 0 {
+        let field = u16::from_be_bytes([packet[10], packet[11]]);
+        return offloaded(Layer::Ipv4, field, 0);
+    }
+
+    let total_len = usize::from(u16::from_be_bytes([packet[2], packet[3]]));
+    if total_len < len || total_len > packet.len() {
+        return Err(Error::truncated("total length", 2));
+    }
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return Ok(ChecksumStatus::Valid);
+    }
+    let payload = &packet[len..total_len];
+    let protocol = packet[9];
+    let src = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+    let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+    let pseudo = checksum::ipv4_pseudo_header(src, dst, protocol, payload.len() as u16);
+    let (layer, sum, offset) = match protocol {
+        IPPROTO_ICMP if payload.len() >= 4 => (Layer::Icmp, checksum::checksum(payload), 2),
+        IPPROTO_TCP if payload.len() >= 20 => (
+            Layer::Tcp,
+            checksum::finish(checksum::add(pseudo, payload)),
+            16,
+        ),
+        IPPROTO_UDP if payload.len() >= 8 => {
+            if payload[6..8] == [0, 0] {
+                return Ok(ChecksumStatus::Valid);
+            }
+            (
+                Layer::Udp,
+                checksum::finish(checksum::add(pseudo, payload)),
+                6,
+            )
+        }
+        IPPROTO_ICMP | IPPROTO_TCP | IPPROTO_UDP => {
+            return Err(Error::truncated("transport header", len))
+        }
+        _ => return Ok(ChecksumStatus::Valid),
+    };
+    if sum != 0 {
+        let field = u16::from_be_bytes([payload[offset], payload[offset + 1]]);
+        // a partial checksum is the folded pseudo-header sum, not yet complemented
+        let partial = match layer {
+            Layer::Icmp => 0,
+            _ => !checksum::finish(pseudo),
+        };
+        return offloaded(layer, field, partial);
+    }
+    Ok(ChecksumStatus::Valid)
+}
@@ -0,0 +1,433 @@
+//! IPv4 header options (RFC 791, RFC 2113).
+//!
+//! Options follow the fixed 20 bytes of the header. Apart from End of Option List and No
+//! Operation, which are a single byte, each option is a type byte, a length byte covering
+//! the whole option, and data. The options area is padded to a multiple of 4 bytes since
+//! the header length is counted in 32 bit words.
+
+use crate::arp::arp::{Error, Result};
+use std::net::Ipv4Addr;
+
+/// Most bytes of options a header can carry.
+pub const MAX_OPTIONS_LEN: usize = 40;
+
+/// Option type numbers, copy flag and class included.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod OptionKinds {
+    /// End of Option List.
+    pub const EndOfList: u8 = 0;
+    /// No Operation, used to align options.
+    pub const NoOperation: u8 = 1;
+    /// Record Route.
+    pub const RecordRoute: u8 = 7;
+    /// Internet Timestamp.
+    pub const Timestamp: u8 = 68;
+    /// Loose Source and Record Route.
+    pub const LooseSourceRoute: u8 = 131;
+    /// Strict Source and Record Route.
+    pub const StrictSourceRoute: u8 = 137;
+    /// Router Alert.
+    pub const RouterAlert: u8 = 148;
+}
+
+/// What the slots of a Timestamp option hold.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TimestampFormat {
+    /// Timestamps only
+    Timestamps,
+    /// Each router's address followed by its timestamp
+    AddressesAndTimestamps,
+    /// Timestamps of the routers whose addresses were filled in by the sender
+    Prespecified,
+}
+
+impl TimestampFormat {
+    fn flag(self) -> u8 {
+        match self {
+            TimestampFormat::Timestamps => 0,
+            TimestampFormat::AddressesAndTimestamps => 1,
+            TimestampFormat::Prespecified => 3,
+        }
+    }
+
+    fn from_flag(flag: u8) -> Option<TimestampFormat> {
+        match flag {
+            0 => Some(TimestampFormat::Timestamps),
+            1 => Some(TimestampFormat::AddressesAndTimestamps),
+            3 => Some(TimestampFormat::Prespecified),
+            _ => None,
+        }
+    }
+
+    fn slot_len(self) -> usize {
+        match self {
+            TimestampFormat::Timestamps => 4,
+            _ => 8,
+        }
+    }
+}
+
+/// An IPv4 header option.
+///
+/// `pointer` fields are the one-based offset, within the option, of the next slot to fill
+/// in; a pointer beyond the last slot means the option is full.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Ipv4Option {
+    /// End of the options, only followed by padding
+    EndOfList,
+    /// Padding between options
+    NoOperation,
+    /// Addresses of the routers the packet went through, unused slots are unspecified
+    RecordRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    /// Routers the packet has to visit, possibly through others
+    LooseSourceRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    /// Routers the packet has to visit and nothing in between
+    StrictSourceRoute { pointer: u8, route: Vec<Ipv4Addr> },
+    /// Times, in milliseconds since midnight UT, at which routers handled the packet
+    Timestamp {
+        pointer: u8,
+        /// Routers that could not add a timestamp for lack of space
+        overflow: u8,
+        format: TimestampFormat,
+        /// Address, if the format has them, and timestamp of each slot
+        entries: Vec<(Option<Ipv4Addr>, u32)>,
+    },
+    /// Routers should look at the packet more closely, 0 meaning the packet carries MLD or
+    /// IGMP
+    RouterAlert(u16),
+    /// An option this module does not know about, without its type and length bytes
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl Ipv4Option {
+    /// A Record Route option with room for `slots` addresses.
+    pub fn record_route(slots: usize) -> Ipv4Option {
+        Ipv4Option::RecordRoute {
+            pointer: 4,
+            route: vec![Ipv4Addr::UNSPECIFIED; slots],
+        }
+    }
+
+    /// A Timestamp option with room for `slots` entries.
+    pub fn timestamp(format: TimestampFormat, slots: usize) -> Ipv4Option {
+        let address = match format {
+            TimestampFormat::Timestamps => None,
+            _ => Some(Ipv4Addr::UNSPECIFIED),
+        };
+        Ipv4Option::Timestamp {
+            pointer: 5,
+            overflow: 0,
+            format,
+            entries: vec![(address, 0); slots],
+        }
+    }
+
+    /// The option type number.
+    pub fn kind(&self) -> u8 {
+        match self {
+            Ipv4Option::EndOfList => OptionKinds::EndOfList,
+            Ipv4Option::NoOperation => OptionKinds::NoOperation,
+            Ipv4Option::RecordRoute { .. } => OptionKinds::RecordRoute,
+            Ipv4Option::LooseSourceRoute { .. } => OptionKinds::LooseSourceRoute,
+            Ipv4Option::StrictSourceRoute { .. } => OptionKinds::StrictSourceRoute,
+            Ipv4Option::Timestamp { .. } => OptionKinds::Timestamp,
+            Ipv4Option::RouterAlert(_) => OptionKinds::RouterAlert,
+            Ipv4Option::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Number of bytes the option takes on the wire.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Ipv4Option::EndOfList | Ipv4Option::NoOperation => 1,
+            Ipv4Option::RecordRoute { route, .. }
+            | Ipv4Option::LooseSourceRoute { route, .. }
+            | Ipv4Option::StrictSourceRoute { route, .. } => 3 + 4 * route.len(),
+            Ipv4Option::Timestamp {
+                format, entries, ..
+            } => 4 + format.slot_len() * entries.len(),
+            Ipv4Option::RouterAlert(_) => 4,
+            Ipv4Option::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    /// Append the option to `out`.
+    ///
+    /// Fails with `Malformed` if it is longer than a length byte can say, or if the
+    /// entries of a Timestamp option don't match its format.
+    pub fn write(&self, out: &mut Vec<u8>) -> Result<()> {
+        let len = self.encoded_len();
+        if len > usize::from(u8::MAX) {
+            return Err(Error::malformed("option length", 1));
+        }
+        out.push(self.kind());
+        match self {
+            Ipv4Option::EndOfList | Ipv4Option::NoOperation => {}
+            Ipv4Option::RecordRoute { pointer, route }
+            | Ipv4Option::LooseSourceRoute { pointer, route }
+            | Ipv4Option::StrictSourceRoute { pointer, route } => {
+                out.push(len as u8);
+                out.push(*pointer);
+                for address in route {
+                    out.extend_from_slice(&address.octets());
+                }
+            }
+            Ipv4Option::Timestamp {
+                pointer,
+                overflow,
+                format,
+                entries,
+            } => {
+                out.push(len as u8);
+                out.push(*pointer);
+                out.push(overflow << 4 | format.flag());
+                for (index, (address, timestamp)) in entries.iter().enumerate() {
+                    match (format, address) {
+                        (TimestampFormat::Timestamps, None) => {}
+                        (TimestampFormat::Timestamps, Some(_)) | (_, None) => {
+                            let offset = 4 + index * format.slot_len();
+                            return Err(Error::malformed("timestamp entry", offset));
+                        }
+                        (_, Some(address)) => out.extend_from_slice(&address.octets()),
+                    }
+                    out.extend_from_slice(&timestamp.to_be_bytes());
+                }
+            }
+            Ipv4Option::RouterAlert(value) => {
+                out.push(4);
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            Ipv4Option::Unknown { data, .. } => {
+                out.push(len as u8);
+                out.extend_from_slice(data);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse(kind: u8, data: &[u8]) -> Result<Ipv4Option> {
+        let route = |data: &[u8]| -> Result<(u8, Vec<Ipv4Addr>)> {
+            if data.is_empty() {
+                return Err(Error::malformed("pointer", 0));
+            }
+            if (data.len() - 1) % 4 != 0 {
+                return Err(Error::malformed("route", 1));
+            }
+            let addresses = data[1..]
+                .chunks(4)
+                .map(|a| Ipv4Addr::new(a[0], a[1], a[2], a[3]))
+                .collect();
+            Ok((data[0], addresses))
+        };
+        Ok(match kind {
+            OptionKinds::RecordRoute => {
+                let (pointer, route) = route(data)?;
+                Ipv4Option::RecordRoute { pointer, route }
+            }
+            OptionKinds::LooseSourceRoute => {
+                let (pointer, route) = route(data)?;
+                Ipv4Option::LooseSourceRoute { pointer, route }
+            }
+            OptionKinds::StrictSourceRoute => {
+                let (pointer, route) = route(data)?;
+                Ipv4Option::StrictSourceRoute { pointer, route }
+            }
+            OptionKinds::Timestamp => {
+                if data.len() < 2 {
+                    return Err(Error::malformed("flags", 1));
+                }
+                let format = TimestampFormat::from_flag(data[1] & 0x0f)
+                    .ok_or_else(|| Error::malformed("flags", 1))?;
+                let slots = &data[2..];
+                if slots.len() % format.slot_len() != 0 {
+                    return Err(Error::malformed("timestamp entry", 2));
+                }
+                let entries = slots
+                    .chunks(format.slot_len())
+                    .map(|slot| match format {
+                        TimestampFormat::Timestamps => (
+                            None,
+                            u32::from_be_bytes([slot[0], slot[1], slot[2], slot[3]]),
+                        ),
+                        _ => (
+                            Some(Ipv4Addr::new(slot[0], slot[1], slot[2], slot[3])),
+                            u32::from_be_bytes([slot[4], slot[5], slot[6], slot[7]]),
+                        ),
+                    })
+                    .collect();
+                Ipv4Option::Timestamp {
+                    pointer: data[0],
+                    overflow: data[1] >> 4,
+                    format,
+                    entries,
+                }
+            }
+            OptionKinds::RouterAlert => match data {
+                [high, low] => Ipv4Option::RouterAlert(u16::from_be_bytes([*high, *low])),
+                _ => return Err(Error::malformed("router alert value", 0)),
+            },
+            _ => Ipv4Option::Unknown {
+                kind,
+                data: data.to_vec(),
+            },
+        })
+    }
+}
+
+/// Iterator over the options area of a header.
+///
+/// Stops after End of Option List, and after the first option that is truncated or
+/// malformed, which it yields as an error with its offset in the options area.
+#[derive(Clone, Debug)]
+pub struct Options<'a> {
+    data: &'a [u8],
+    // of `data` in what was passed to `new`
+    offset: usize,
+}
+
+impl<'a> Options<'a> {
+    /// Iterate over `data`, the bytes between the fixed header and the payload.
+    pub fn new(data: &'a [u8]) -> Options<'a> {
+        Options { data, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = Result<Ipv4Option>;
+
+    fn next(&mut self) -> Option<Result<Ipv4Option>> {
+        let kind = *self.data.first()?;
+        match kind {
+            OptionKinds::EndOfList => {
+                self.data = &[];
+                return Some(Ok(Ipv4Option::EndOfList));
+            }
+            OptionKinds::NoOperation => {
+                self.data = &self.data[1..];
+                self.offset += 1;
+                return Some(Ok(Ipv4Option::NoOperation));
+            }
+            _ => {}
+        }
+        let len = match self.data.get(1) {
+            Some(&len) => usize::from(len),
+            None => {
+                self.data = &[];
+                return Some(Err(Error::truncated("option length", self.offset + 1)));
+            }
+        };
+        if len < 2 {
+            self.data = &[];
+            return Some(Err(Error::malformed("option length", self.offset + 1)));
+        }
+        if len > self.data.len() {
+            self.data = &[];
+            return Some(Err(Error::truncated("option data", self.offset + 2)));
+        }
+        let option =
+            Ipv4Option::parse(kind, &self.data[2..len]).map_err(|e| e.shifted(self.offset + 2));
+        self.data = if option.is_ok() {
+            self.offset += len;
+            &self.data[len..]
+        } else {
+            &[]
+        };
+        Some(option)
+    }
+}
+
+/// Encode `options` for a header, padded with End of Option List to a multiple of 4 bytes.
+///
+/// Fails with `Exhausted` if they take more than [`MAX_OPTIONS_LEN`] bytes.
+pub fn encode(options: &[Ipv4Option]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for option in options {
+        option.write(&mut out)?;
+    }
+    while out.len() % 4 != 0 {
+        out.push(OptionKinds::EndOfList);
+    }
+    if out.len() > MAX_OPTIONS_LEN {
+        return Err(Error::Exhausted);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> Result<Vec<Ipv4Option>> {
+        Options::new(data).collect()
+    }
+
+    #[test]
+    fn options_round_trip() {
+        let mut timestamp = Ipv4Option::timestamp(TimestampFormat::AddressesAndTimestamps, 1);
+        if let Ipv4Option::Timestamp { overflow, .. } = &mut timestamp {
+            *overflow = 3;
+        }
+        let options = vec![
+            Ipv4Option::NoOperation,
+            Ipv4Option::RouterAlert(0),
+            Ipv4Option::LooseSourceRoute {
+                pointer: 8,
+                route: vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)],
+            },
+            timestamp,
+            Ipv4Option::Unknown {
+                kind: 30,
+                data: vec![1, 2],
+            },
+        ];
+        let encoded = encode(&options).unwrap();
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(encoded[16..19], [OptionKinds::Timestamp, 12, 5]);
+        assert_eq!(encoded[19], 3 << 4 | 1);
+        assert_eq!(parse(&encoded).unwrap(), options);
+
+        // padded with End of Option List
+        let options = [Ipv4Option::record_route(1)];
+        let encoded = encode(&options).unwrap();
+        assert_eq!(encoded, [7, 7, 4, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            parse(&encoded).unwrap(),
+            [Ipv4Option::record_route(1), Ipv4Option::EndOfList]
+        );
+    }
+
+    #[test]
+    fn bad_options_are_located() {
+        let kind = OptionKinds::RecordRoute;
+        assert_eq!(parse(&[kind]), Err(Error::truncated("option length", 1)));
+        assert_eq!(parse(&[kind, 1]), Err(Error::malformed("option length", 1)));
+        assert_eq!(
+            parse(&[kind, 7, 4]),
+            Err(Error::truncated("option data", 2))
+        );
+        // offsets are in the options area
+        let misaligned = [OptionKinds::NoOperation, kind, 4, 4, 0];
+        assert_eq!(parse(&misaligned), Err(Error::malformed("route", 4)));
+        let alert = [OptionKinds::RouterAlert, 3, 0];
+        assert_eq!(
+            parse(&alert),
+            Err(Error::malformed("router alert value", 2))
+        );
+
+        assert_eq!(
+            encode(&[Ipv4Option::record_route(10)]),
+            Err(Error::Exhausted)
+        );
+        let mismatched = Ipv4Option::Timestamp {
+            pointer: 5,
+            overflow: 0,
+            format: TimestampFormat::Timestamps,
+            entries: vec![(Some(Ipv4Addr::LOCALHOST), 0)],
+        };
+        assert_eq!(
+            encode(&[mismatched]),
+            Err(Error::malformed("timestamp entry", 4))
+        );
+    }
+}
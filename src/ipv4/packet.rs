@@ -0,0 +1,403 @@
+//! IPv4 packets in the style of `ether::EthernetPacket`.
+
+use super::options::{self, Ipv4Option, Options};
+use crate::{
+    arp::ether::{FromPacket, Packet, PrimitiveValues},
+    checksum,
+};
+use std::{fmt, net::Ipv4Addr};
+
+/// Represents the protocol carried by an IP packet.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Debug, Copy, Clone)]
+pub struct IpNextHeaderProtocol(pub u8);
+
+impl IpNextHeaderProtocol {
+    /// Create a new `IpNextHeaderProtocol`.
+    pub fn new(value: u8) -> Self {
+        IpNextHeaderProtocol(value)
+    }
+}
+
+impl PrimitiveValues for IpNextHeaderProtocol {
+    type T = (u8,);
+    fn to_primitive_values(&self) -> (u8,) {
+        (self.0,)
+    }
+}
+
+impl fmt::Display for IpNextHeaderProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            IpNextHeaderProtocols::Icmp => "icmp",
+            IpNextHeaderProtocols::Tcp => "tcp",
+            IpNextHeaderProtocols::Udp => "udp",
+            IpNextHeaderProtocols::Ipv6Route => "ipv6-route",
+            IpNextHeaderProtocols::Ipv6Frag => "ipv6-frag",
+            IpNextHeaderProtocols::Icmpv6 => "icmpv6",
+            IpNextHeaderProtocols::Ipv6NoNxt => "ipv6-nonxt",
+            IpNextHeaderProtocols::Ipv6Opts => "ipv6-opts",
+            _ => return write!(f, "{}", self.0),
+        };
+        f.write_str(name)
+    }
+}
+
+/// IP protocol numbers.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod IpNextHeaderProtocols {
+    use super::IpNextHeaderProtocol;
+
+    /// IPv6 Hop-by-Hop Option
+    pub const Hopopt: IpNextHeaderProtocol = IpNextHeaderProtocol(0);
+    /// Internet Control Message Protocol
+    pub const Icmp: IpNextHeaderProtocol = IpNextHeaderProtocol(1);
+    /// Internet Group Management Protocol
+    pub const Igmp: IpNextHeaderProtocol = IpNextHeaderProtocol(2);
+    /// Transmission Control Protocol
+    pub const Tcp: IpNextHeaderProtocol = IpNextHeaderProtocol(6);
+    /// User Datagram Protocol
+    pub const Udp: IpNextHeaderProtocol = IpNextHeaderProtocol(17);
+    /// IPv6 encapsulation
+    pub const Ipv6: IpNextHeaderProtocol = IpNextHeaderProtocol(41);
+    /// Routing Header for IPv6
+    pub const Ipv6Route: IpNextHeaderProtocol = IpNextHeaderProtocol(43);
+    /// Fragment Header for IPv6
+    pub const Ipv6Frag: IpNextHeaderProtocol = IpNextHeaderProtocol(44);
+    /// Generic Routing Encapsulation
+    pub const Gre: IpNextHeaderProtocol = IpNextHeaderProtocol(47);
+    /// Encapsulating Security Payload
+    pub const Esp: IpNextHeaderProtocol = IpNextHeaderProtocol(50);
+    /// Authentication Header
+    pub const Ah: IpNextHeaderProtocol = IpNextHeaderProtocol(51);
+    /// ICMP for IPv6
+    pub const Icmpv6: IpNextHeaderProtocol = IpNextHeaderProtocol(58);
+    /// No Next Header for IPv6
+    pub const Ipv6NoNxt: IpNextHeaderProtocol = IpNextHeaderProtocol(59);
+    /// Destination Options for IPv6
+    pub const Ipv6Opts: IpNextHeaderProtocol = IpNextHeaderProtocol(60);
+    /// Stream Control Transmission Protocol
+    pub const Sctp: IpNextHeaderProtocol = IpNextHeaderProtocol(132);
+}
+
+/// The fragmentation flags.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod Ipv4Flags {
+    /// Don't Fragment
+    pub const DontFragment: u8 = 0b010;
+    /// More Fragments
+    pub const MoreFragments: u8 = 0b001;
+}
+
+fn header_len(data: &[u8]) -> usize {
+    usize::from(data[0] & 0x0f) * 4
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    let total_len = usize::from(u16::from_be_bytes([data[2], data[3]]));
+    // offloaded packets may not have their length filled in
+    if total_len < header_len(data) {
+        data.len()
+    } else {
+        total_len
+    }
+}
+
+packet_types! {
+    /// An IPv4 packet. The payload ends where the total length says, leaving out the
+    /// Ethernet padding of short packets.
+    pub struct Ipv4Packet / MutableIpv4Packet;
+    minimum_size = 20;
+    header_len = header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the version field, 4 for valid packets
+        #[inline]
+        pub fn get_version(&self) -> u8 {
+            self.packet[0] >> 4
+        }
+        /// Get the header length field, in 32 bit words
+        #[inline]
+        pub fn get_header_length(&self) -> u8 {
+            self.packet[0] & 0x0f
+        }
+        /// Get the Differentiated Services Code Point
+        #[inline]
+        pub fn get_dscp(&self) -> u8 {
+            self.packet[1] >> 2
+        }
+        /// Get the Explicit Congestion Notification bits
+        #[inline]
+        pub fn get_ecn(&self) -> u8 {
+            self.packet[1] & 0x03
+        }
+        /// Get the total length field, header included
+        #[inline]
+        pub fn get_total_length(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+        /// Get the identification field
+        #[inline]
+        pub fn get_identification(&self) -> u16 {
+            u16::from_be_bytes([self.packet[4], self.packet[5]])
+        }
+        /// Get the flags, see `Ipv4Flags`
+        #[inline]
+        pub fn get_flags(&self) -> u8 {
+            self.packet[6] >> 5
+        }
+        /// Get the fragment offset, in 8 byte units
+        #[inline]
+        pub fn get_fragment_offset(&self) -> u16 {
+            u16::from_be_bytes([self.packet[6], self.packet[7]]) & 0x1fff
+        }
+        /// Get the time to live field
+        #[inline]
+        pub fn get_ttl(&self) -> u8 {
+            self.packet[8]
+        }
+        /// Get the protocol of the payload
+        #[inline]
+        pub fn get_next_level_protocol(&self) -> IpNextHeaderProtocol {
+            IpNextHeaderProtocol(self.packet[9])
+        }
+        /// Get the header checksum field
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[10], self.packet[11]])
+        }
+        /// Get the source address
+        #[inline]
+        pub fn get_source(&self) -> Ipv4Addr {
+            Ipv4Addr::new(self.packet[12], self.packet[13], self.packet[14], self.packet[15])
+        }
+        /// Get the destination address
+        #[inline]
+        pub fn get_destination(&self) -> Ipv4Addr {
+            Ipv4Addr::new(self.packet[16], self.packet[17], self.packet[18], self.packet[19])
+        }
+        /// Get the bytes of the options, between the fixed header and the payload
+        pub fn get_options_raw(&self) -> &[u8] {
+            let end = header_len(&self.packet[..]).max(20).min(self.packet.len());
+            &self.packet[20..end]
+        }
+        /// Iterate over the options
+        pub fn get_options_iter(&self) -> Options {
+            Options::new(self.get_options_raw())
+        }
+        /// Get the options, up to the first malformed one
+        pub fn get_options(&self) -> Vec<Ipv4Option> {
+            self.get_options_iter().map_while(Result::ok).collect()
+        }
+    }
+    setters {
+        /// Set the version field
+        #[inline]
+        pub fn set_version(&mut self, val: u8) {
+            self.packet[0] = (self.packet[0] & 0x0f) | (val << 4);
+        }
+        /// Set the header length field, in 32 bit words
+        #[inline]
+        pub fn set_header_length(&mut self, val: u8) {
+            self.packet[0] = (self.packet[0] & 0xf0) | (val & 0x0f);
+        }
+        /// Set the Differentiated Services Code Point
+        #[inline]
+        pub fn set_dscp(&mut self, val: u8) {
+            self.packet[1] = (self.packet[1] & 0x03) | (val << 2);
+        }
+        /// Set the Explicit Congestion Notification bits
+        #[inline]
+        pub fn set_ecn(&mut self, val: u8) {
+            self.packet[1] = (self.packet[1] & 0xfc) | (val & 0x03);
+        }
+        /// Set the total length field
+        #[inline]
+        pub fn set_total_length(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the identification field
+        #[inline]
+        pub fn set_identification(&mut self, val: u16) {
+            self.packet[4..6].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the flags
+        #[inline]
+        pub fn set_flags(&mut self, val: u8) {
+            self.packet[6] = (self.packet[6] & 0x1f) | (val << 5);
+        }
+        /// Set the fragment offset, in 8 byte units
+        #[inline]
+        pub fn set_fragment_offset(&mut self, val: u16) {
+            let flags = u16::from(self.packet[6] & 0xe0) << 8;
+            self.packet[6..8].copy_from_slice(&(flags | (val & 0x1fff)).to_be_bytes());
+        }
+        /// Set the time to live field
+        #[inline]
+        pub fn set_ttl(&mut self, val: u8) {
+            self.packet[8] = val;
+        }
+        /// Set the protocol of the payload
+        #[inline]
+        pub fn set_next_level_protocol(&mut self, val: IpNextHeaderProtocol) {
+            self.packet[9] = val.0;
+        }
+        /// Set the header checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[10..12].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the source address
+        #[inline]
+        pub fn set_source(&mut self, val: Ipv4Addr) {
+            self.packet[12..16].copy_from_slice(&val.octets());
+        }
+        /// Set the destination address
+        #[inline]
+        pub fn set_destination(&mut self, val: Ipv4Addr) {
+            self.packet[16..20].copy_from_slice(&val.octets());
+        }
+        /// Copy `vals` into the options area, as far as the header length leaves room
+        pub fn set_options_raw(&mut self, vals: &[u8]) {
+            let end = header_len(&self.packet[..]).max(20).min(self.packet.len());
+            let len = vals.len().min(end - 20);
+            self.packet[20..20 + len].copy_from_slice(&vals[..len]);
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let start = header_len(&self.packet[..]).min(self.packet.len());
+            let len = vals.len().min(self.packet.len() - start);
+            self.packet[start..start + len].copy_from_slice(&vals[..len]);
+        }
+        /// Recompute the header checksum
+        pub fn update_checksum(&mut self) {
+            let sum = header_checksum(&self.to_immutable());
+            self.set_checksum(sum);
+        }
+        /// Populates the packet using an `Ipv4` structure. The buffer has to hold the
+        /// header, with `header_length` words, and the payload
+        pub fn populate(&mut self, packet: &Ipv4) {
+            self.set_version(packet.version);
+            self.set_header_length(packet.header_length);
+            self.set_dscp(packet.dscp);
+            self.set_ecn(packet.ecn);
+            self.set_total_length(packet.total_length);
+            self.set_identification(packet.identification);
+            self.set_flags(packet.flags);
+            self.set_fragment_offset(packet.fragment_offset);
+            self.set_ttl(packet.ttl);
+            self.set_next_level_protocol(packet.next_level_protocol);
+            self.set_checksum(packet.checksum);
+            self.set_source(packet.source);
+            self.set_destination(packet.destination);
+            // options that don't fit the header length are left out
+            self.set_options_raw(&options::encode(&packet.options).unwrap_or_default());
+            self.set_payload(&packet.payload);
+        }
+    }
+}
+
+impl<'a> Ipv4Packet<'a> {
+    /// The size (in bytes) of an Ipv4 instance when converted into a byte-array.
+    pub fn packet_size(packet: &Ipv4) -> usize {
+        usize::from(packet.header_length) * 4 + packet.payload.len()
+    }
+}
+
+impl<'a> MutableIpv4Packet<'a> {
+    /// The size (in bytes) of an Ipv4 instance when converted into a byte-array.
+    pub fn packet_size(packet: &Ipv4) -> usize {
+        usize::from(packet.header_length) * 4 + packet.payload.len()
+    }
+}
+
+/// The checksum of the header of `packet`, computed with the checksum field zeroed.
+pub fn header_checksum(packet: &Ipv4Packet) -> u16 {
+    let data = packet.packet();
+    let len = header_len(data).max(20).min(data.len());
+    let sum = checksum::add(0, &data[..10]);
+    checksum::finish(checksum::add(sum, &data[12..len]))
+}
+
+/// An IPv4 packet, owned.
+#[derive(Clone, Debug)]
+pub struct Ipv4 {
+    pub version: u8,
+    pub header_length: u8,
+    pub dscp: u8,
+    pub ecn: u8,
+    pub total_length: u16,
+    pub identification: u16,
+    pub flags: u8,
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub next_level_protocol: IpNextHeaderProtocol,
+    pub checksum: u16,
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub options: Vec<Ipv4Option>,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for Ipv4Packet<'p> {
+    type T = Ipv4;
+    fn from_packet(&self) -> Ipv4 {
+        Ipv4 {
+            version: self.get_version(),
+            header_length: self.get_header_length(),
+            dscp: self.get_dscp(),
+            ecn: self.get_ecn(),
+            total_length: self.get_total_length(),
+            identification: self.get_identification(),
+            flags: self.get_flags(),
+            fragment_offset: self.get_fragment_offset(),
+            ttl: self.get_ttl(),
+            next_level_protocol: self.get_next_level_protocol(),
+            checksum: self.get_checksum(),
+            source: self.get_source(),
+            destination: self.get_destination(),
+            options: self.get_options(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableIpv4Packet<'p> {
+    type T = Ipv4;
+    fn from_packet(&self) -> Ipv4 {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for Ipv4Packet<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Ipv4Packet {{ version : {}, header_length : {}, dscp : {}, ecn : {}, \
+             total_length : {}, identification : {}, flags : {}, fragment_offset : {}, \
+             ttl : {}, next_level_protocol : {}, checksum : {:#06x}, source : {}, \
+             destination : {}, options : {:?} }}",
+            self.get_version(),
+            self.get_header_length(),
+            self.get_dscp(),
+            self.get_ecn(),
+            self.get_total_length(),
+            self.get_identification(),
+            self.get_flags(),
+            self.get_fragment_offset(),
+            self.get_ttl(),
+            self.get_next_level_protocol(),
+            self.get_checksum(),
+            self.get_source(),
+            self.get_destination(),
+            self.get_options()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableIpv4Packet<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
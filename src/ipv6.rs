@@ -0,0 +1,222 @@
+//! IPv6 headers.
+//!
+//! [`Ipv6Packet`] covers the fixed header only: extension headers are part of its payload,
+//! with `get_next_header` giving the type of the first one.
+
+use crate::{
+    arp::ether::{FromPacket, Packet},
+    ipv4::IpNextHeaderProtocol,
+};
+use std::{fmt, net::Ipv6Addr};
+
+/// Length of the fixed header.
+pub const HEADER_LEN: usize = 40;
+
+fn header_len(_data: &[u8]) -> usize {
+    HEADER_LEN
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    match u16::from_be_bytes([data[4], data[5]]) {
+        // jumbograms and offloaded packets leave the length to the link layer
+        0 => data.len(),
+        len => HEADER_LEN + usize::from(len),
+    }
+}
+
+fn address(data: &[u8]) -> Ipv6Addr {
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(&data[..16]);
+    Ipv6Addr::from(octets)
+}
+
+packet_types! {
+    /// An IPv6 packet. The payload ends where the payload length says, leaving out the
+    /// Ethernet padding of short packets.
+    pub struct Ipv6Packet / MutableIpv6Packet;
+    minimum_size = HEADER_LEN;
+    header_len = header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the version field, 6 for valid packets
+        #[inline]
+        pub fn get_version(&self) -> u8 {
+            self.packet[0] >> 4
+        }
+        /// Get the traffic class, DSCP and ECN bits together
+        #[inline]
+        pub fn get_traffic_class(&self) -> u8 {
+            (self.packet[0] << 4) | (self.packet[1] >> 4)
+        }
+        /// Get the 20 bit flow label
+        #[inline]
+        pub fn get_flow_label(&self) -> u32 {
+            u32::from_be_bytes([0, self.packet[1] & 0x0f, self.packet[2], self.packet[3]])
+        }
+        /// Get the payload length field, extension headers included
+        #[inline]
+        pub fn get_payload_length(&self) -> u16 {
+            u16::from_be_bytes([self.packet[4], self.packet[5]])
+        }
+        /// Get the type of the header following this one
+        #[inline]
+        pub fn get_next_header(&self) -> IpNextHeaderProtocol {
+            IpNextHeaderProtocol(self.packet[6])
+        }
+        /// Get the hop limit
+        #[inline]
+        pub fn get_hop_limit(&self) -> u8 {
+            self.packet[7]
+        }
+        /// Get the source address
+        #[inline]
+        pub fn get_source(&self) -> Ipv6Addr {
+            address(&self.packet[8..24])
+        }
+        /// Get the destination address
+        #[inline]
+        pub fn get_destination(&self) -> Ipv6Addr {
+            address(&self.packet[24..40])
+        }
+    }
+    setters {
+        /// Set the version field
+        #[inline]
+        pub fn set_version(&mut self, val: u8) {
+            self.packet[0] = (self.packet[0] & 0x0f) | (val << 4);
+        }
+        /// Set the traffic class
+        #[inline]
+        pub fn set_traffic_class(&mut self, val: u8) {
+            self.packet[0] = (self.packet[0] & 0xf0) | (val >> 4);
+            self.packet[1] = (self.packet[1] & 0x0f) | (val << 4);
+        }
+        /// Set the flow label, of which the low 20 bits are used
+        #[inline]
+        pub fn set_flow_label(&mut self, val: u32) {
+            let bytes = val.to_be_bytes();
+            self.packet[1] = (self.packet[1] & 0xf0) | (bytes[1] & 0x0f);
+            self.packet[2] = bytes[2];
+            self.packet[3] = bytes[3];
+        }
+        /// Set the payload length field
+        #[inline]
+        pub fn set_payload_length(&mut self, val: u16) {
+            self.packet[4..6].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the type of the header following this one
+        #[inline]
+        pub fn set_next_header(&mut self, val: IpNextHeaderProtocol) {
+            self.packet[6] = val.0;
+        }
+        /// Set the hop limit
+        #[inline]
+        pub fn set_hop_limit(&mut self, val: u8) {
+            self.packet[7] = val;
+        }
+        /// Set the source address
+        #[inline]
+        pub fn set_source(&mut self, val: Ipv6Addr) {
+            self.packet[8..24].copy_from_slice(&val.octets());
+        }
+        /// Set the destination address
+        #[inline]
+        pub fn set_destination(&mut self, val: Ipv6Addr) {
+            self.packet[24..40].copy_from_slice(&val.octets());
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let len = vals.len().min(self.packet.len() - HEADER_LEN);
+            self.packet[HEADER_LEN..HEADER_LEN + len].copy_from_slice(&vals[..len]);
+        }
+        /// Populates the packet using an `Ipv6` structure.
+        pub fn populate(&mut self, packet: &Ipv6) {
+            self.set_version(packet.version);
+            self.set_traffic_class(packet.traffic_class);
+            self.set_flow_label(packet.flow_label);
+            self.set_payload_length(packet.payload_length);
+            self.set_next_header(packet.next_header);
+            self.set_hop_limit(packet.hop_limit);
+            self.set_source(packet.source);
+            self.set_destination(packet.destination);
+            self.set_payload(&packet.payload);
+        }
+    }
+}
+
+impl<'a> Ipv6Packet<'a> {
+    /// The size (in bytes) of an Ipv6 instance when converted into a byte-array.
+    pub fn packet_size(packet: &Ipv6) -> usize {
+        HEADER_LEN + packet.payload.len()
+    }
+}
+
+impl<'a> MutableIpv6Packet<'a> {
+    /// The size (in bytes) of an Ipv6 instance when converted into a byte-array.
+    pub fn packet_size(packet: &Ipv6) -> usize {
+        HEADER_LEN + packet.payload.len()
+    }
+}
+
+/// An IPv6 packet, owned.
+#[derive(Clone, Debug)]
+pub struct Ipv6 {
+    pub version: u8,
+    pub traffic_class: u8,
+    pub flow_label: u32,
+    pub payload_length: u16,
+    pub next_header: IpNextHeaderProtocol,
+    pub hop_limit: u8,
+    pub source: Ipv6Addr,
+    pub destination: Ipv6Addr,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for Ipv6Packet<'p> {
+    type T = Ipv6;
+    fn from_packet(&self) -> Ipv6 {
+        Ipv6 {
+            version: self.get_version(),
+            traffic_class: self.get_traffic_class(),
+            flow_label: self.get_flow_label(),
+            payload_length: self.get_payload_length(),
+            next_header: self.get_next_header(),
+            hop_limit: self.get_hop_limit(),
+            source: self.get_source(),
+            destination: self.get_destination(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableIpv6Packet<'p> {
+    type T = Ipv6;
+    fn from_packet(&self) -> Ipv6 {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for Ipv6Packet<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "Ipv6Packet {{ version : {}, traffic_class : {}, flow_label : {:#07x}, \
+             payload_length : {}, next_header : {}, hop_limit : {}, source : {}, \
+             destination : {} }}",
+            self.get_version(),
+            self.get_traffic_class(),
+            self.get_flow_label(),
+            self.get_payload_length(),
+            self.get_next_header(),
+            self.get_hop_limit(),
+            self.get_source(),
+            self.get_destination()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableIpv6Packet<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
@@ -0,0 +1,98 @@
+#[macro_use]
+// only the modules that need sockets are instrumented
+#[cfg_attr(target_arch = "wasm32", allow(unused_macros))]
+mod trace;
+#[macro_use]
+mod packet;
+
+mod ipproto;
+mod rng;
+
+pub mod address;
+pub mod arp;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod checksum;
+pub mod cidr;
+#[cfg(all(not(target_arch = "wasm32"), feature = "dhcp", feature = "dns"))]
+pub mod cli;
+pub mod clock;
+pub mod compat;
+#[cfg(all(not(target_arch = "wasm32"), feature = "dhcp"))]
+pub mod dhcp;
+#[cfg(all(not(target_arch = "wasm32"), feature = "dns"))]
+pub mod discover;
+#[cfg(all(not(target_arch = "wasm32"), feature = "dns"))]
+pub mod dns;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dscp;
+pub mod ecn;
+pub mod error;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod filter;
+pub mod flows;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod generate;
+pub mod gso;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod icmp;
+pub mod icmpv6;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod inject;
+pub mod ipv4;
+pub mod ipv6;
+#[cfg(all(not(target_arch = "wasm32"), feature = "dns"))]
+pub mod mdns;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod metrics;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod nat;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod neighbor;
+#[cfg(target_os = "linux")]
+pub mod offload;
+pub mod pcap;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod perf;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ping;
+#[cfg(target_os = "linux")]
+pub mod pipeline;
+pub mod pool;
+#[cfg(target_os = "linux")]
+pub mod privileges;
+pub mod reassembly;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod render;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod replay;
+pub mod routes;
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod sandbox;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod scan;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod shape;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sim;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sniff;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod spoof;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod synflood;
+pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ttl;
+pub mod udp;
+pub mod vlan;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod vlanhop;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod wol;
@@ -1,5 +1,14 @@
-mod arp;
+#[cfg(target_os = "linux")]
+fn main() {
+    if let Err(e) = myox_tcp::arp::bootstrap() {
+        eprintln!("myox: {}", e);
+        std::process::exit(1);
+    }
+}
 
+// the bootstrap runs on a tap device
+#[cfg(not(target_os = "linux"))]
 fn main() {
-    arp::bootstrap();
+    eprintln!("myox: the tap bootstrap is only supported on Linux");
+    std::process::exit(1);
 }
@@ -0,0 +1,268 @@
+//! Multicast DNS (RFC 6762).
+//!
+//! Hosts of a link answer questions about their `.local` names themselves, sent to a
+//! multicast group instead of a name server. [`query`] asks the group from an ephemeral
+//! port, which makes responders answer by unicast, and a [`Responder`] joins the group and
+//! answers for the names it is given. Messages are encoded and decoded with [`crate::dns`].
+
+use crate::dns::{self, Message, Question, Record, RecordData};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+#[cfg(unix)]
+use std::{mem, os::unix::io::FromRawFd};
+
+/// The IPv4 mDNS group.
+pub const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+/// The mDNS port.
+pub const PORT: u16 = 5353;
+
+/// Flags of responses: authoritative answer.
+const FLAGS_RESPONSE: u16 = 0x0400;
+/// Top bit of the class of questions, asking for a unicast answer.
+const UNICAST_RESPONSE: u16 = 0x8000;
+/// Top bit of the class of records, replacing what caches hold for the name.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// The addresses of `name`, collected for a second.
+pub fn query(name: &str) -> io::Result<Vec<IpAddr>> {
+    query_with(name, Duration::from_secs(1))
+}
+
+/// The addresses of `name`, collected for `timeout`. Empty if nobody answered.
+pub fn query_with(name: &str, timeout: Duration) -> io::Result<Vec<IpAddr>> {
+    let name = normalize(name);
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    // answers from elsewhere than the link are not meant for us
+    socket.set_multicast_ttl_v4(255)?;
+    let group = SocketAddrV4::new(GROUP, PORT);
+    for qtype in &[dns::TYPE_A, dns::TYPE_AAAA] {
+        // ID 0 and no recursion, as mDNS queries have
+        let mut message = dns::query(0, &name, *qtype);
+        message[2..4].copy_from_slice(&[0, 0]);
+        socket.send_to(&message, group)?;
+    }
+
+    let mut found = BTreeSet::new();
+    let deadline = Instant::now() + timeout;
+    let mut buffer = [0u8; 9000];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let len = match socket.recv_from(&mut buffer) {
+            Ok((len, _)) => len,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        };
+        let message = match Message::parse(&buffer[..len]) {
+            Some(message) if message.is_response() => message,
+            _ => continue,
+        };
+        for record in message.answers {
+            if !record.name.eq_ignore_ascii_case(&name) {
+                continue;
+            }
+            match record.data {
+                RecordData::A(addr) => found.insert(IpAddr::V4(addr)),
+                RecordData::Aaaa(addr) => found.insert(IpAddr::V6(addr)),
+                _ => continue,
+            };
+        }
+    }
+    Ok(found.into_iter().collect())
+}
+
+/// Answers mDNS questions for a set of names.
+#[derive(Debug)]
+pub struct Responder {
+    socket: UdpSocket,
+    names: HashMap<String, Vec<IpAddr>>,
+    ttl: u32,
+}
+
+impl Responder {
+    /// Bind the mDNS port, sharing it with other responders of the host, and join the
+    /// group on the default interface.
+    pub fn bind() -> io::Result<Responder> {
+        let socket = bind_shared(PORT)?;
+        socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_ttl_v4(255)?;
+        // wake up regularly to notice the stop flag
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        Ok(Responder {
+            socket,
+            names: HashMap::new(),
+            ttl: 120,
+        })
+    }
+
+    /// Answer questions for `name` with `addresses`.
+    pub fn name(mut self, name: &str, addresses: Vec<IpAddr>) -> Responder {
+        self.names.insert(normalize(name), addresses);
+        self
+    }
+
+    /// Give answers this TTL, in seconds. Defaults to 120.
+    pub fn ttl(mut self, ttl: u32) -> Responder {
+        self.ttl = ttl;
+        self
+    }
+
+    /// The records answering the questions of `query` about the configured names.
+    pub fn answers(&self, query: &Message) -> Vec<Record> {
+        let mut answers = Vec::new();
+        for question in &query.questions {
+            let addresses = match self.names.get(&normalize(&question.name)) {
+                Some(addresses) => addresses,
+                None => continue,
+            };
+            for addr in addresses {
+                let (rtype, data) = match addr {
+                    IpAddr::V4(addr) => (dns::TYPE_A, RecordData::A(*addr)),
+                    IpAddr::V6(addr) => (dns::TYPE_AAAA, RecordData::Aaaa(*addr)),
+                };
+                if question.qtype != rtype && question.qtype != dns::TYPE_ANY {
+                    continue;
+                }
+                answers.push(Record {
+                    name: question.name.clone(),
+                    rtype,
+                    class: dns::CLASS_IN | CACHE_FLUSH,
+                    ttl: self.ttl,
+                    data,
+                });
+            }
+        }
+        answers
+    }
+
+    /// Answer questions until `stop` is set, returning the number of responses sent.
+    pub fn run(&mut self, stop: &AtomicBool) -> io::Result<u64> {
+        let mut sent = 0;
+        let mut buffer = [0u8; 9000];
+        while !stop.load(Ordering::SeqCst) {
+            let (len, source) = match self.socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            };
+            let query = match Message::parse(&buffer[..len]) {
+                Some(message) if !message.is_response() => message,
+                _ => continue,
+            };
+            let answers = self.answers(&query);
+            if answers.is_empty() {
+                continue;
+            }
+            // queries from other ports come from simple resolvers expecting a unicast
+            // answer repeating the ID and questions
+            let legacy = source.port() != PORT;
+            let unicast = legacy
+                || query
+                    .questions
+                    .iter()
+                    .all(|question| question.qclass & UNICAST_RESPONSE != 0);
+            let response = if legacy {
+                let questions: Vec<Question> = query
+                    .questions
+                    .iter()
+                    .map(|question| Question {
+                        qclass: question.qclass & !UNICAST_RESPONSE,
+                        ..question.clone()
+                    })
+                    .collect();
+                dns::response(query.id, FLAGS_RESPONSE, &questions, &answers)
+            } else {
+                dns::response(0, FLAGS_RESPONSE, &[], &answers)
+            };
+            let destination = if unicast {
+                source
+            } else {
+                SocketAddr::V4(SocketAddrV4::new(GROUP, PORT))
+            };
+            self.socket.send_to(&response, destination)?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+}
+
+/// Names are compared without case and trailing dot.
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// A UDP socket bound to `port` on every address, with the port shared.
+#[cfg(unix)]
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    // other systems have no SOCK_CLOEXEC, the flag is set right after there
+    #[cfg(target_os = "linux")]
+    let kind = libc::SOCK_DGRAM | libc::SOCK_CLOEXEC;
+    #[cfg(not(target_os = "linux"))]
+    let kind = libc::SOCK_DGRAM;
+    let fd = unsafe { libc::socket(libc::AF_INET, kind, 0) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // closes the descriptor on the error paths too
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+    #[cfg(not(target_os = "linux"))]
+    {
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let one: libc::c_int = 1;
+    for option in &[libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+        if unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                *option,
+                (&one as *const libc::c_int) as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        } == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    let mut address: libc::sockaddr_in = unsafe { mem::zeroed() };
+    address.sin_family = libc::AF_INET as libc::sa_family_t;
+    address.sin_port = port.to_be();
+    if unsafe {
+        libc::bind(
+            fd,
+            (&address as *const libc::sockaddr_in) as *const libc::sockaddr,
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket)
+}
+
+/// A UDP socket bound to `port` on every address. The sockets of libc aren't there to
+/// share the port, so this fails while another responder holds it.
+#[cfg(not(unix))]
+fn bind_shared(port: u16) -> io::Result<UdpSocket> {
+    UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port))
+}
@@ -0,0 +1,791 @@
+//! Latency measurement and counters.
+//!
+//! [`Histogram`] records durations in log-linear buckets like HdrHistogram: values are
+//! kept exactly up to a small threshold and with a bounded relative error above it, so
+//! percentiles stay accurate from nanoseconds to minutes in a few kilobytes. [`Metrics`]
+//! is a shared set of named counters, gauges and histograms the tools record into.
+//!
+//! Metrics are exported through a [`Sink`]; [`PrometheusWriter`] and [`serve`] produce the
+//! Prometheus text format, the latter over HTTP. [`MeteredSender`] and [`MeteredReceiver`]
+//! count the traffic of a channel.
+
+use crate::arp::{
+    channel::{
+        EthernetDataLinkChannelIterator, EthernetDataLinkReceiver, EthernetDataLinkSender, RxMeta,
+    },
+    ether::{EthernetPacket, Packet},
+    network_interface::NetworkInterface,
+};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Default number of bits of precision, bounding the relative error to 1/128.
+pub const DEFAULT_PRECISION: u32 = 8;
+
+/// A histogram of durations with nanosecond resolution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    precision: u32,
+    counts: Vec<u64>,
+    count: u64,
+    sum: u128,
+    min: u64,
+    max: u64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with the default precision.
+    pub fn new() -> Histogram {
+        Histogram::with_precision(DEFAULT_PRECISION)
+    }
+
+    /// Create an empty histogram whose buckets are at most `1 / 2^(precision - 1)` of
+    /// their value wide. `precision` is clamped to `1..=16`.
+    pub fn with_precision(precision: u32) -> Histogram {
+        Histogram {
+            precision: precision.max(1).min(16),
+            counts: Vec::new(),
+            count: 0,
+            sum: 0,
+            min: u64::MAX,
+            max: 0,
+        }
+    }
+
+    /// Record one duration.
+    pub fn record(&mut self, value: Duration) {
+        let nanos = value.as_nanos().min(u128::from(u64::MAX)) as u64;
+        let index = self.index(nanos);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum += u128::from(nanos);
+        self.min = self.min.min(nanos);
+        self.max = self.max.max(nanos);
+    }
+
+    /// Add all values recorded in `other`.
+    ///
+    /// Values are re-bucketed to this histogram's precision if the two differ.
+    pub fn merge(&mut self, other: &Histogram) {
+        for (index, &count) in other.counts.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let target = self.index(other.highest_equivalent(index));
+            if target >= self.counts.len() {
+                self.counts.resize(target + 1, 0);
+            }
+            self.counts[target] += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    /// Forget all recorded values.
+    pub fn reset(&mut self) {
+        *self = Histogram::with_precision(self.precision);
+    }
+
+    /// Number of recorded values.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Sum of the recorded values.
+    pub fn sum(&self) -> Duration {
+        Duration::from_nanos(self.sum.min(u128::from(u64::MAX)) as u64)
+    }
+
+    /// Smallest recorded value, zero if empty.
+    pub fn min(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            _ => Duration::from_nanos(self.min),
+        }
+    }
+
+    /// Largest recorded value, zero if empty.
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// Mean of the recorded values, zero if empty.
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::from_secs(0),
+            count => Duration::from_nanos((self.sum / u128::from(count)) as u64),
+        }
+    }
+
+    /// Value below or at which a `quantile` (in `[0, 1]`) of the recorded values fall.
+    ///
+    /// The result is the upper end of the bucket holding that value, so it overestimates
+    /// by at most the bucket width. Zero if empty.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::from_secs(0);
+        }
+        let quantile = quantile.max(0.0).min(1.0);
+        let rank = ((quantile * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, &count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let value = self.highest_equivalent(index).min(self.max).max(self.min);
+                return Duration::from_nanos(value);
+            }
+        }
+        self.max()
+    }
+
+    /// The usual percentiles of the recorded values.
+    pub fn summary(&self) -> Summary {
+        Summary {
+            count: self.count,
+            min: self.min(),
+            p50: self.quantile(0.50),
+            p95: self.quantile(0.95),
+            p99: self.quantile(0.99),
+            max: self.max(),
+        }
+    }
+
+    /// Bucket of `value`: the first `2^precision` buckets hold one value each, above that
+    /// every power of two is split into `2^(precision - 1)` buckets.
+    fn index(&self, value: u64) -> usize {
+        let linear = 1u64 << self.precision;
+        if value < linear {
+            return value as usize;
+        }
+        let msb = 63 - value.leading_zeros();
+        let shift = msb + 1 - self.precision;
+        let half = linear >> 1;
+        (linear + u64::from(shift - 1) * half + ((value >> shift) - half)) as usize
+    }
+
+    /// Largest value that falls into bucket `index`.
+    fn highest_equivalent(&self, index: usize) -> u64 {
+        let linear = 1u64 << self.precision;
+        let index = index as u64;
+        if index < linear {
+            return index;
+        }
+        let half = linear >> 1;
+        let shift = (index - linear) / half + 1;
+        let sub = (index - linear) % half + half;
+        let lowest = sub << shift;
+        lowest.saturating_add((1u64 << shift) - 1)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+/// Percentiles of a latency distribution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Number of values
+    pub count: u64,
+    /// Smallest value
+    pub min: Duration,
+    /// Median
+    pub p50: Duration,
+    /// 95th percentile
+    pub p95: Duration,
+    /// 99th percentile
+    pub p99: Duration,
+    /// Largest value
+    pub max: Duration,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |value: Duration| value.as_secs_f64() * 1e3;
+        write!(
+            f,
+            "min/p50/p95/p99/max = {:.3}/{:.3}/{:.3}/{:.3}/{:.3} ms",
+            ms(self.min),
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.p99),
+            ms(self.max)
+        )
+    }
+}
+
+/// A monotonically increasing count, shared with the [`Metrics`] it was taken from.
+#[derive(Clone, Debug, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    /// Add `value` to the count.
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Add one to the count.
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    /// Current count.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that goes up and down, shared with the [`Metrics`] it was taken from.
+#[derive(Clone, Debug, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    /// Set the value.
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Current value.
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// A latency histogram recorded into by name, shared with the [`Metrics`] it was taken
+/// from.
+#[derive(Clone, Debug, Default)]
+pub struct Timer {
+    metrics: Metrics,
+    name: String,
+}
+
+impl Timer {
+    /// Record `latency`.
+    pub fn record(&self, latency: Duration) {
+        self.metrics.record(&self.name, latency);
+    }
+}
+
+/// Named counters, gauges and latency histograms, cheap to clone and share between
+/// threads.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    histograms: Arc<Mutex<BTreeMap<String, Histogram>>>,
+    counters: Arc<Mutex<BTreeMap<String, Counter>>>,
+    gauges: Arc<Mutex<BTreeMap<String, Gauge>>>,
+}
+
+impl Metrics {
+    /// Create an empty set of metrics.
+    pub fn new() -> Metrics {
+        Default::default()
+    }
+
+    /// Record `latency` in the histogram called `name`, creating it if needed.
+    pub fn record(&self, name: &str, latency: Duration) {
+        let mut histograms = self.histograms.lock().unwrap();
+        match histograms.get_mut(name) {
+            Some(histogram) => histogram.record(latency),
+            None => {
+                let mut histogram = Histogram::new();
+                histogram.record(latency);
+                histograms.insert(name.to_owned(), histogram);
+            }
+        }
+    }
+
+    /// The histogram called `name` to record into, created on the first value.
+    pub fn timer(&self, name: &str) -> Timer {
+        Timer {
+            metrics: self.clone(),
+            name: name.to_owned(),
+        }
+    }
+
+    /// Return a copy of the histogram called `name`.
+    pub fn histogram(&self, name: &str) -> Option<Histogram> {
+        self.histograms.lock().unwrap().get(name).cloned()
+    }
+
+    /// Percentiles of the histogram called `name`.
+    pub fn summary(&self, name: &str) -> Option<Summary> {
+        self.histograms
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(Histogram::summary)
+    }
+
+    /// Percentiles of every histogram, ordered by name.
+    pub fn summaries(&self) -> Vec<(String, Summary)> {
+        self.histograms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, histogram)| (name.clone(), histogram.summary()))
+            .collect()
+    }
+
+    /// The counter called `name`, created at zero if needed. Keeping it avoids looking it
+    /// up for every update.
+    pub fn counter(&self, name: &str) -> Counter {
+        self.counters
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    /// Add `value` to the counter called `name`.
+    pub fn add(&self, name: &str, value: u64) {
+        self.counter(name).add(value);
+    }
+
+    /// Values of every counter, ordered by name.
+    pub fn counters(&self) -> Vec<(String, u64)> {
+        self.counters
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, counter)| (name.clone(), counter.get()))
+            .collect()
+    }
+
+    /// The gauge called `name`, created at zero if needed.
+    pub fn gauge(&self, name: &str) -> Gauge {
+        self.gauges
+            .lock()
+            .unwrap()
+            .entry(name.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    /// Set the gauge called `name` to `value`.
+    pub fn set(&self, name: &str, value: f64) {
+        self.gauge(name).set(value);
+    }
+
+    /// Values of every gauge, ordered by name.
+    pub fn gauges(&self) -> Vec<(String, f64)> {
+        self.gauges
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, gauge)| (name.clone(), gauge.get()))
+            .collect()
+    }
+
+    /// Copies of every histogram, ordered by name.
+    pub fn histograms(&self) -> Vec<(String, Histogram)> {
+        self.histograms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, histogram)| (name.clone(), histogram.clone()))
+            .collect()
+    }
+
+    /// Forget all recorded values. Counters and gauges handed out stay registered and are
+    /// set to zero.
+    pub fn reset(&self) {
+        self.histograms.lock().unwrap().clear();
+        for counter in self.counters.lock().unwrap().values() {
+            counter.0.store(0, Ordering::Relaxed);
+        }
+        for gauge in self.gauges.lock().unwrap().values() {
+            gauge.set(0.0);
+        }
+    }
+}
+
+/// Destination of exported metrics.
+pub trait Sink {
+    /// Export the current values of `metrics`.
+    fn export(&mut self, metrics: &Metrics) -> io::Result<()>;
+}
+
+/// Export `metrics` to `sink` every `interval` until `stop` is set, and once more at the
+/// end.
+pub fn export_every(
+    metrics: &Metrics,
+    sink: &mut dyn Sink,
+    interval: Duration,
+    stop: &AtomicBool,
+) -> io::Result<()> {
+    let mut due = Instant::now() + interval;
+    while !stop.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= due {
+            sink.export(metrics)?;
+            due += interval;
+        } else {
+            // wake up regularly to notice `stop`
+            thread::sleep((due - now).min(Duration::from_millis(100)));
+        }
+    }
+    sink.export(metrics)
+}
+
+/// A sink writing the Prometheus text format.
+pub struct PrometheusWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> PrometheusWriter<W> {
+    /// Write exported metrics to `inner`.
+    pub fn new(inner: W) -> PrometheusWriter<W> {
+        PrometheusWriter { inner }
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Sink for PrometheusWriter<W> {
+    fn export(&mut self, metrics: &Metrics) -> io::Result<()> {
+        self.inner.write_all(prometheus_text(metrics).as_bytes())?;
+        self.inner.flush()
+    }
+}
+
+/// Render `metrics` in the Prometheus text exposition format.
+///
+/// Names are reduced to the characters Prometheus allows, `arping.rtt` becoming
+/// `arping_rtt`. Histograms are rendered as summaries in seconds.
+pub fn prometheus_text(metrics: &Metrics) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+    for (name, value) in metrics.counters() {
+        let name = prometheus_name(&name);
+        let _ = writeln!(out, "# TYPE {} counter\n{} {}", name, name, value);
+    }
+    for (name, value) in metrics.gauges() {
+        let name = prometheus_name(&name);
+        let _ = writeln!(
+            out,
+            "# TYPE {} gauge\n{} {}",
+            name,
+            name,
+            prometheus_float(value)
+        );
+    }
+    for (name, histogram) in metrics.histograms() {
+        let name = prometheus_name(&name);
+        let _ = writeln!(out, "# TYPE {} summary", name);
+        for &quantile in &[0.5, 0.95, 0.99] {
+            let _ = writeln!(
+                out,
+                "{}{{quantile=\"{}\"}} {}",
+                name,
+                quantile,
+                prometheus_float(histogram.quantile(quantile).as_secs_f64())
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_sum {}\n{}_count {}",
+            name,
+            prometheus_float(histogram.sum().as_secs_f64()),
+            name,
+            histogram.count()
+        );
+    }
+    out
+}
+
+fn prometheus_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | ':' => c,
+            _ => '_',
+        })
+        .collect();
+    if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+        out.insert(0, '_');
+    }
+    out
+}
+
+fn prometheus_float(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Answer HTTP requests for `/metrics` on `listener` with the Prometheus rendering of
+/// `metrics` until `stop` is set.
+///
+/// Requests are handled one at a time; this is meant for a scraper, not for browsers.
+pub fn serve(listener: &TcpListener, metrics: &Metrics, stop: &AtomicBool) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            // a misbehaving client must not stop the exposer
+            Ok((stream, _)) => {
+                let _ = respond(stream, metrics);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                thread::sleep(Duration::from_millis(100))
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            n => request.extend_from_slice(&buffer[..n]),
+        }
+    }
+    let line = request.split(|&b| b == b'\r').next().unwrap_or(&[]);
+    let mut parts = line.split(|&b| b == b' ');
+    let (method, path) = (parts.next(), parts.next());
+    let (status, body) = match (method, path) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", prometheus_text(metrics)),
+        (Some(b"GET"), _) => ("404 Not Found", "not found\n".to_owned()),
+        _ => ("405 Method Not Allowed", "method not allowed\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+/// A sender counting the frames sent through it as `<prefix>.tx_packets`,
+/// `<prefix>.tx_bytes` and `<prefix>.tx_errors`.
+pub struct MeteredSender {
+    inner: Box<dyn EthernetDataLinkSender>,
+    packets: Counter,
+    bytes: Counter,
+    errors: Counter,
+}
+
+impl MeteredSender {
+    /// Wrap `inner`, counting into `metrics`.
+    pub fn new(
+        inner: Box<dyn EthernetDataLinkSender>,
+        metrics: &Metrics,
+        prefix: &str,
+    ) -> MeteredSender {
+        MeteredSender {
+            inner,
+            packets: metrics.counter(&format!("{}.tx_packets", prefix)),
+            bytes: metrics.counter(&format!("{}.tx_bytes", prefix)),
+            errors: metrics.counter(&format!("{}.tx_errors", prefix)),
+        }
+    }
+
+    /// The wrapped sender.
+    pub fn into_inner(self) -> Box<dyn EthernetDataLinkSender> {
+        self.inner
+    }
+}
+
+impl EthernetDataLinkSender for MeteredSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        let result = self.inner.send_to(packet, dst);
+        match result {
+            Some(Ok(())) => {
+                self.packets.increment();
+                self.bytes.add(packet.packet().len() as u64);
+            }
+            _ => self.errors.increment(),
+        }
+        result
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+/// A receiver counting the frames read from it as `<prefix>.rx_packets`,
+/// `<prefix>.rx_bytes` and `<prefix>.rx_errors`. Timeouts and reads that would block are
+/// not counted as errors.
+pub struct MeteredReceiver {
+    inner: Box<dyn EthernetDataLinkReceiver>,
+    counters: RxCounters,
+}
+
+struct RxCounters {
+    packets: Counter,
+    bytes: Counter,
+    errors: Counter,
+}
+
+impl RxCounters {
+    fn received(&self, packets: &[EthernetPacket]) {
+        self.packets.add(packets.len() as u64);
+        let bytes: usize = packets.iter().map(|packet| packet.packet().len()).sum();
+        self.bytes.add(bytes as u64);
+    }
+
+    fn failed(&self, error: &io::Error) {
+        match error.kind() {
+            // nothing arrived in time, the link itself is fine
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {}
+            _ => self.errors.increment(),
+        }
+    }
+}
+
+impl MeteredReceiver {
+    /// Wrap `inner`, counting into `metrics`.
+    pub fn new(
+        inner: Box<dyn EthernetDataLinkReceiver>,
+        metrics: &Metrics,
+        prefix: &str,
+    ) -> MeteredReceiver {
+        MeteredReceiver {
+            inner,
+            counters: RxCounters {
+                packets: metrics.counter(&format!("{}.rx_packets", prefix)),
+                bytes: metrics.counter(&format!("{}.rx_bytes", prefix)),
+                errors: metrics.counter(&format!("{}.rx_errors", prefix)),
+            },
+        }
+    }
+
+    /// The wrapped receiver.
+    pub fn into_inner(self) -> Box<dyn EthernetDataLinkReceiver> {
+        self.inner
+    }
+}
+
+impl EthernetDataLinkReceiver for MeteredReceiver {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(MeteredChannelIterator {
+            inner: self.inner.iter(),
+            counters: &self.counters,
+        })
+    }
+
+    fn queue(&self) -> Option<u16> {
+        self.inner.queue()
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+struct MeteredChannelIterator<'a> {
+    inner: Box<dyn EthernetDataLinkChannelIterator<'a> + 'a>,
+    counters: &'a RxCounters,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for MeteredChannelIterator<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        let result = self.inner.next();
+        match result {
+            Ok(ref packet) => self.counters.received(std::slice::from_ref(packet)),
+            Err(ref e) => self.counters.failed(e),
+        }
+        result
+    }
+
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let result = self.inner.next_with_meta();
+        match result {
+            Ok((ref packet, _)) => self.counters.received(std::slice::from_ref(packet)),
+            Err(ref e) => self.counters.failed(e),
+        }
+        result
+    }
+
+    fn next_batch(&mut self, max: usize) -> io::Result<Vec<EthernetPacket>> {
+        let result = self.inner.next_batch(max);
+        match result {
+            Ok(ref packets) => self.counters.received(packets),
+            Err(ref e) => self.counters.failed(e),
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_reads_are_not_errors() {
+        let metrics = Metrics::new();
+        let counters = RxCounters {
+            packets: metrics.counter("rx_packets"),
+            bytes: metrics.counter("rx_bytes"),
+            errors: metrics.counter("rx_errors"),
+        };
+
+        counters.failed(&io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+        counters.failed(&io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        assert_eq!(counters.errors.get(), 0);
+
+        counters.failed(&io::Error::new(io::ErrorKind::Other, "link down"));
+        assert_eq!(counters.errors.get(), 1);
+    }
+
+    #[test]
+    fn histograms_are_exported() {
+        let metrics = Metrics::new();
+        let timer = metrics.timer("ping.rtt");
+        assert!(metrics.histogram("ping.rtt").is_none());
+        for ms in 1..=100 {
+            timer.record(Duration::from_millis(ms));
+        }
+        let summary = metrics.summary("ping.rtt").unwrap();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.max, Duration::from_millis(100));
+
+        let text = prometheus_text(&metrics);
+        assert!(text.contains("# TYPE ping_rtt summary\n"));
+        assert!(text.contains("ping_rtt_count 100\n"));
+        assert!(text.contains("ping_rtt{quantile=\"0.5\"} 0.05"));
+    }
+}
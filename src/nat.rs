@@ -0,0 +1,675 @@
+//! NAT44: sharing one external IPv4 address between the hosts of an inside network.
+//!
+//! Packets leaving the inside get the external address as source and a port picked from
+//! a range in place of their source port, or their ICMP echo identifier; answers to that
+//! port are translated back. Mappings depend on the inside address and port only, so one
+//! works with any remote host (RFC 4787), and expire once unused for a while. Checksums
+//! are updated incrementally rather than computed again.
+//!
+//! Fragments, ICMP errors and protocols other than TCP, UDP and ICMP echo are not
+//! translated. [`forward`] runs the translation between two interfaces, and
+//! [`forward_with`] filters the packets forwarded too.
+
+use crate::{
+    arp::{
+        channel::{channel, Channel, Config as ChannelConfig, EthernetDataLinkSender},
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, MutablePacket, Packet},
+        network_interface::{MacAddr, NetworkInterface},
+    },
+    checksum,
+    clock::{self, Clock},
+    filter::RuleSet,
+    ipproto::{IPPROTO_ICMP, IPPROTO_TCP, IPPROTO_UDP},
+    ttl::{self, Hop, RouterAddr},
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddrV4},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Log target of translated packets.
+const LOG_TARGET: &str = "myox::nat";
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_RST: u8 = 0x04;
+
+/// Translation parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// First external port handed out. Defaults to 49152
+    pub first_port: u16,
+
+    /// Last external port handed out. Defaults to 65535
+    pub last_port: u16,
+
+    /// How long a TCP mapping lives unused. Defaults to 2 h 4 min (RFC 5382)
+    pub tcp_timeout: Duration,
+
+    /// How long a TCP mapping lives unused once a FIN or RST went through. Defaults to
+    /// 4 min
+    pub tcp_closing_timeout: Duration,
+
+    /// How long a UDP mapping lives unused. Defaults to 5 min
+    pub udp_timeout: Duration,
+
+    /// How long an ICMP echo mapping lives unused. Defaults to 60 s
+    pub icmp_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            first_port: 49152,
+            last_port: 65535,
+            tcp_timeout: Duration::from_secs(124 * 60),
+            tcp_closing_timeout: Duration::from_secs(4 * 60),
+            udp_timeout: Duration::from_secs(5 * 60),
+            icmp_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// A translation of an inside address and port to an external port.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Mapping {
+    /// IP protocol number
+    pub protocol: u8,
+    /// The inside address and port, or ICMP echo identifier
+    pub inside: SocketAddrV4,
+    /// The external port, or ICMP echo identifier
+    pub external_port: u16,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    external_port: u16,
+    used: Instant,
+    // a FIN or RST went through
+    closing: bool,
+}
+
+/// The translation table.
+#[derive(Debug)]
+pub struct Nat {
+    external: Ipv4Addr,
+    config: Config,
+    clock: Arc<dyn Clock>,
+    inside: HashMap<(u8, SocketAddrV4), Entry>,
+    outside: HashMap<(u8, u16), SocketAddrV4>,
+    // where the search for a free port starts, per protocol
+    next_port: HashMap<u8, u16>,
+}
+
+impl Nat {
+    /// Create an empty table translating to `external`.
+    pub fn new(external: Ipv4Addr, config: Config) -> Nat {
+        Nat::with_clock(external, config, clock::system())
+    }
+
+    /// Create an empty table measuring time with `clock`.
+    pub fn with_clock(external: Ipv4Addr, config: Config, clock: Arc<dyn Clock>) -> Nat {
+        Nat {
+            external,
+            config,
+            clock,
+            inside: HashMap::new(),
+            outside: HashMap::new(),
+            next_port: HashMap::new(),
+        }
+    }
+
+    /// The address translated packets leave from.
+    pub fn external(&self) -> Ipv4Addr {
+        self.external
+    }
+
+    /// Translate the source of `packet`, an IPv4 packet leaving the inside, creating a
+    /// mapping if there is none. False if the packet can't be translated, or no port is
+    /// free; it should be dropped then.
+    pub fn outbound(&mut self, packet: &mut [u8]) -> bool {
+        let (ihl, protocol) = match translatable(packet) {
+            Some(header) => header,
+            None => return false,
+        };
+        let port_offset = match port_offset(&packet[ihl..], protocol, true) {
+            Some(offset) => offset,
+            None => return false,
+        };
+        let source = Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]);
+        let port = u16::from_be_bytes([packet[ihl + port_offset], packet[ihl + port_offset + 1]]);
+        let inside = SocketAddrV4::new(source, port);
+        let now = self.clock.now();
+        self.expire_key(protocol, inside, now);
+
+        let external_port = match self.inside.get_mut(&(protocol, inside)) {
+            Some(entry) => {
+                entry.used = now;
+                entry.external_port
+            }
+            None => {
+                let external_port = match self.free_port(protocol) {
+                    Some(port) => port,
+                    None => {
+                        log::warn!(target: LOG_TARGET, "no port left for {}", inside);
+                        return false;
+                    }
+                };
+                log::debug!(
+                    target: LOG_TARGET,
+                    "mapping {} {} to port {}",
+                    protocol,
+                    inside,
+                    external_port
+                );
+                let entry = Entry {
+                    external_port,
+                    used: now,
+                    closing: false,
+                };
+                self.inside.insert((protocol, inside), entry);
+                self.outside.insert((protocol, external_port), inside);
+                external_port
+            }
+        };
+        if protocol == IPPROTO_TCP && packet[ihl + 13] & (TCP_FIN | TCP_RST) != 0 {
+            if let Some(entry) = self.inside.get_mut(&(protocol, inside)) {
+                entry.closing = true;
+            }
+        }
+        rewrite(
+            packet,
+            ihl,
+            protocol,
+            12,
+            port_offset,
+            self.external,
+            external_port,
+        );
+        true
+    }
+
+    /// Translate the destination of `packet`, an IPv4 packet arriving at the external
+    /// address, back to the inside. False if no mapping matches; the packet should be
+    /// dropped then.
+    pub fn inbound(&mut self, packet: &mut [u8]) -> bool {
+        let (ihl, protocol) = match translatable(packet) {
+            Some(header) => header,
+            None => return false,
+        };
+        if packet[16..20] != self.external.octets() {
+            return false;
+        }
+        let port_offset = match port_offset(&packet[ihl..], protocol, false) {
+            Some(offset) => offset,
+            None => return false,
+        };
+        let port = u16::from_be_bytes([packet[ihl + port_offset], packet[ihl + port_offset + 1]]);
+        let inside = match self.outside.get(&(protocol, port)) {
+            Some(inside) => *inside,
+            None => return false,
+        };
+        let now = self.clock.now();
+        if self.expire_key(protocol, inside, now) {
+            return false;
+        }
+        if let Some(entry) = self.inside.get_mut(&(protocol, inside)) {
+            entry.used = now;
+            if protocol == IPPROTO_TCP && packet[ihl + 13] & (TCP_FIN | TCP_RST) != 0 {
+                entry.closing = true;
+            }
+        }
+        rewrite(
+            packet,
+            ihl,
+            protocol,
+            16,
+            port_offset,
+            *inside.ip(),
+            inside.port(),
+        );
+        true
+    }
+
+    /// Remove the mappings unused for longer than their timeout, returning how many.
+    pub fn expire(&mut self) -> usize {
+        let now = self.clock.now();
+        let expired: Vec<_> = self
+            .inside
+            .iter()
+            .filter(|(key, entry)| self.is_expired(key.0, entry, now))
+            .map(|(key, _)| *key)
+            .collect();
+        for (protocol, inside) in &expired {
+            self.remove(*protocol, *inside);
+        }
+        expired.len()
+    }
+
+    /// The mappings in place, expired ones included until [`Nat::expire`] removes them.
+    pub fn mappings(&self) -> Vec<Mapping> {
+        self.inside
+            .iter()
+            .map(|(&(protocol, inside), entry)| Mapping {
+                protocol,
+                inside,
+                external_port: entry.external_port,
+            })
+            .collect()
+    }
+
+    /// Number of mappings.
+    pub fn len(&self) -> usize {
+        self.inside.len()
+    }
+
+    /// Whether there are no mappings.
+    pub fn is_empty(&self) -> bool {
+        self.inside.is_empty()
+    }
+
+    fn timeout(&self, protocol: u8, entry: &Entry) -> Duration {
+        match protocol {
+            IPPROTO_TCP if entry.closing => self.config.tcp_closing_timeout,
+            IPPROTO_TCP => self.config.tcp_timeout,
+            IPPROTO_UDP => self.config.udp_timeout,
+            _ => self.config.icmp_timeout,
+        }
+    }
+
+    fn is_expired(&self, protocol: u8, entry: &Entry, now: Instant) -> bool {
+        now.duration_since(entry.used) >= self.timeout(protocol, entry)
+    }
+
+    // remove the mapping of `inside` if it expired, returning whether it did
+    fn expire_key(&mut self, protocol: u8, inside: SocketAddrV4, now: Instant) -> bool {
+        let expired = match self.inside.get(&(protocol, inside)) {
+            Some(entry) => self.is_expired(protocol, entry, now),
+            None => return false,
+        };
+        if expired {
+            self.remove(protocol, inside);
+        }
+        expired
+    }
+
+    fn remove(&mut self, protocol: u8, inside: SocketAddrV4) {
+        if let Some(entry) = self.inside.remove(&(protocol, inside)) {
+            self.outside.remove(&(protocol, entry.external_port));
+        }
+    }
+
+    fn free_port(&mut self, protocol: u8) -> Option<u16> {
+        let (first, last) = (self.config.first_port, self.config.last_port);
+        if first > last {
+            return None;
+        }
+        let count = u32::from(last - first) + 1;
+        let start = *self.next_port.get(&protocol).unwrap_or(&first);
+        let now = self.clock.now();
+        for i in 0..count {
+            let port = first + ((u32::from(start.max(first) - first) + i) % count) as u16;
+            // a port whose mapping expired may be taken over
+            if let Some(inside) = self.outside.get(&(protocol, port)).copied() {
+                if !self.expire_key(protocol, inside, now) {
+                    continue;
+                }
+            }
+            self.next_port
+                .insert(protocol, if port == last { first } else { port + 1 });
+            return Some(port);
+        }
+        None
+    }
+}
+
+// the header length and protocol of a packet that can be translated
+fn translatable(packet: &[u8]) -> Option<(usize, u8)> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let ihl = usize::from(packet[0] & 0x0f) * 4;
+    if ihl < 20 || packet.len() < ihl {
+        return None;
+    }
+    // later fragments carry no ports
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return None;
+    }
+    Some((ihl, packet[9]))
+}
+
+// offset in the transport header of the port or identifier to rewrite
+fn port_offset(transport: &[u8], protocol: u8, outbound: bool) -> Option<usize> {
+    match protocol {
+        IPPROTO_TCP if transport.len() >= 20 => Some(if outbound { 0 } else { 2 }),
+        IPPROTO_UDP if transport.len() >= 8 => Some(if outbound { 0 } else { 2 }),
+        IPPROTO_ICMP if transport.len() >= 8 => {
+            let expected = if outbound {
+                ICMP_ECHO_REQUEST
+            } else {
+                ICMP_ECHO_REPLY
+            };
+            if transport[0] == expected {
+                Some(4)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+// replace the address at `address_offset` of the IP header and the port at `port_offset`
+// of the transport header, updating the checksums
+fn rewrite(
+    packet: &mut [u8],
+    ihl: usize,
+    protocol: u8,
+    address_offset: usize,
+    port_offset: usize,
+    address: Ipv4Addr,
+    port: u16,
+) {
+    let old_address = [
+        packet[address_offset],
+        packet[address_offset + 1],
+        packet[address_offset + 2],
+        packet[address_offset + 3],
+    ];
+    let new_address = address.octets();
+    let old_port = [packet[ihl + port_offset], packet[ihl + port_offset + 1]];
+    let new_port = port.to_be_bytes();
+
+    let sum = u16::from_be_bytes([packet[10], packet[11]]);
+    let sum = checksum::update(sum, &old_address, &new_address);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet[address_offset..address_offset + 4].copy_from_slice(&new_address);
+
+    let transport = &mut packet[ihl..];
+    let (sum_offset, pseudo) = match protocol {
+        IPPROTO_TCP => (16, true),
+        IPPROTO_UDP => (6, true),
+        _ => (2, false),
+    };
+    let sum = u16::from_be_bytes([transport[sum_offset], transport[sum_offset + 1]]);
+    // a zero UDP checksum means none was computed, leave it that way
+    if !(protocol == IPPROTO_UDP && sum == 0) {
+        let mut sum = checksum::update(sum, &old_port, &new_port);
+        if pseudo {
+            sum = checksum::update(sum, &old_address, &new_address);
+        }
+        if protocol == IPPROTO_UDP && sum == 0 {
+            sum = 0xffff;
+        }
+        transport[sum_offset..sum_offset + 2].copy_from_slice(&sum.to_be_bytes());
+    }
+    transport[port_offset..port_offset + 2].copy_from_slice(&new_port);
+}
+
+/// Route IPv4 packets between `inside` and `outside` until `stop` is set, translating
+/// them with `nat`, and return the number of packets forwarded.
+///
+/// Packets from the inside addressed to the MAC address of `inside`, and not to one of
+/// its addresses, go out to `gateway`. Packets arriving at the external address go to the
+/// inside host that sent through the mapping, at the MAC address it sent from. The TTL is
+/// decremented, and packets whose TTL runs out are answered with an ICMP Time Exceeded
+/// from the address of the interface they came in on, so traceroute sees the router.
+pub fn forward(
+    nat: &mut Nat,
+    inside: &NetworkInterface,
+    outside: &NetworkInterface,
+    gateway: MacAddr,
+    stop: &AtomicBool,
+) -> io::Result<u64> {
+    let rules = RuleSet::default();
+    forward_with(nat, inside, outside, gateway, &rules, stop)
+}
+
+/// [`forward`] the packets `rules` allow, as received on either interface, before
+/// translation.
+pub fn forward_with(
+    nat: &mut Nat,
+    inside: &NetworkInterface,
+    outside: &NetworkInterface,
+    gateway: MacAddr,
+    rules: &RuleSet,
+    stop: &AtomicBool,
+) -> io::Result<u64> {
+    let no_mac = |interface: &NetworkInterface| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("interface {} has no MAC address", interface.name),
+        )
+    };
+    let inside_mac = inside.mac.ok_or_else(|| no_mac(inside))?;
+    let outside_mac = outside.mac.ok_or_else(|| no_mac(outside))?;
+    let own: Vec<Ipv4Addr> = inside
+        .ips
+        .iter()
+        .flatten()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(*ip),
+            IpAddr::V6(_) => None,
+        })
+        .collect();
+
+    let config = ChannelConfig {
+        // alternate between the interfaces without waiting long on either
+        read_timeout: Some(Duration::from_millis(1)),
+        ..Default::default()
+    };
+    let open = |interface| match channel(interface, config)? {
+        Channel::Ethernet(tx, rx) => Ok((tx, rx)),
+        _ => Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+    };
+    let (mut inside_tx, mut inside_rx) = open(inside)?;
+    let (mut outside_tx, mut outside_rx) = open(outside)?;
+    let (mut inside_rx, mut outside_rx) = (inside_rx.iter(), outside_rx.iter());
+    let mut router = Router {
+        nat,
+        inside_mac,
+        outside_mac,
+        gateway,
+        own,
+        hosts: HashMap::new(),
+        rules,
+    };
+    let mut forwarded = 0;
+    let mut last_expiry = Instant::now();
+
+    while !stop.load(Ordering::SeqCst) {
+        if last_expiry.elapsed() >= Duration::from_secs(1) {
+            router.nat.expire();
+            last_expiry = Instant::now();
+        }
+
+        let out = match inside_rx.next() {
+            Ok(frame) => router.outgoing(&frame),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => None,
+            Err(e) => return Err(e),
+        };
+        match out {
+            Some(Routed::Forward(frame)) => {
+                send(&mut *outside_tx, &frame)?;
+                forwarded += 1;
+            }
+            Some(Routed::Expired(error)) => send(&mut *inside_tx, &error)?,
+            None => {}
+        }
+
+        let back = match outside_rx.next() {
+            Ok(frame) => router.incoming(&frame),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => None,
+            Err(e) => return Err(e),
+        };
+        match back {
+            Some(Routed::Forward(frame)) => {
+                send(&mut *inside_tx, &frame)?;
+                forwarded += 1;
+            }
+            Some(Routed::Expired(error)) => send(&mut *outside_tx, &error)?,
+            None => {}
+        }
+    }
+    Ok(forwarded)
+}
+
+struct Router<'a> {
+    nat: &'a mut Nat,
+    inside_mac: MacAddr,
+    outside_mac: MacAddr,
+    gateway: MacAddr,
+    // addresses of the inside interface, packets to them aren't forwarded
+    own: Vec<Ipv4Addr>,
+    // the MAC address of every inside host seen sending
+    hosts: HashMap<Ipv4Addr, MacAddr>,
+    rules: &'a RuleSet,
+}
+
+// what becomes of a frame the router takes
+#[derive(Debug, PartialEq)]
+enum Routed {
+    // passed on to the other interface
+    Forward(Vec<u8>),
+    // its TTL ran out, the ICMP error goes back where it came from
+    Expired(Vec<u8>),
+}
+
+impl<'a> Router<'a> {
+    // what to do with `frame`, received on the inside
+    fn outgoing(&mut self, frame: &EthernetPacket) -> Option<Routed> {
+        let payload = frame.payload();
+        if frame.get_ethertype() != EtherTypes::Ipv4
+            || frame.get_destination() != self.inside_mac
+            || payload.len() < 20
+            || self.own.iter().any(|ip| payload[16..20] == ip.octets())
+            || !self.rules.allows(frame)
+        {
+            return None;
+        }
+        let source = Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]);
+        let mut out = frame.packet().to_vec();
+        let mut ethernet = MutableEthernetPacket::new(&mut out)?;
+        let inside = RouterAddr {
+            mac: self.inside_mac,
+            ipv4: self.own.first().copied(),
+            ipv6: None,
+        };
+        match ttl::forward(&mut ethernet, &inside) {
+            Hop::Forward => {}
+            Hop::Expired(error) => return Some(Routed::Expired(error)),
+            Hop::Dropped | Hop::NotIp => return None,
+        }
+        if !self.nat.outbound(ethernet.payload_mut()) {
+            return None;
+        }
+        ethernet.set_source(self.outside_mac);
+        ethernet.set_destination(self.gateway);
+        self.hosts.insert(source, frame.get_source());
+        Some(Routed::Forward(out))
+    }
+
+    // what to do with `frame`, received on the outside
+    fn incoming(&mut self, frame: &EthernetPacket) -> Option<Routed> {
+        if frame.get_ethertype() != EtherTypes::Ipv4
+            || frame.get_destination() != self.outside_mac
+            || !self.rules.allows(frame)
+        {
+            return None;
+        }
+        let mut out = frame.packet().to_vec();
+        let mut ethernet = MutableEthernetPacket::new(&mut out)?;
+        let outside = RouterAddr {
+            mac: self.outside_mac,
+            ipv4: Some(self.nat.external),
+            ipv6: None,
+        };
+        match ttl::forward(&mut ethernet, &outside) {
+            Hop::Forward => {}
+            Hop::Expired(error) => return Some(Routed::Expired(error)),
+            Hop::Dropped | Hop::NotIp => return None,
+        }
+        let payload = ethernet.payload_mut();
+        if !self.nat.inbound(payload) {
+            return None;
+        }
+        let destination = Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]);
+        let mac = *self.hosts.get(&destination)?;
+        ethernet.set_source(self.inside_mac);
+        ethernet.set_destination(mac);
+        Some(Routed::Forward(out))
+    }
+}
+
+fn send(tx: &mut dyn EthernetDataLinkSender, frame: &[u8]) -> io::Result<()> {
+    tx.send_to(&EthernetPacket::new(frame).unwrap(), None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate, ttl::frame_ttl};
+
+    const HOST: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 2);
+    const INSIDE: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 1);
+    const OUTSIDE: MacAddr = MacAddr(0x02, 0, 0, 0, 1, 1);
+    const GATEWAY: MacAddr = MacAddr(0x02, 0, 0, 0, 1, 2);
+    const EXTERNAL: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 10);
+
+    // a datagram from the inside host to a far address, with `ttl` hops left
+    fn datagram(ttl: u8) -> Vec<u8> {
+        let from = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 33434);
+        let to = SocketAddrV4::new(Ipv4Addr::new(198, 51, 100, 1), 33434);
+        let mut frame = generate::udp_frame(HOST, INSIDE, from, to, b"probe");
+        ttl::set_frame_ttl(&mut MutableEthernetPacket::new(&mut frame).unwrap(), ttl);
+        frame
+    }
+
+    #[test]
+    fn expired_packets_are_answered() {
+        let mut nat = Nat::new(EXTERNAL, Config::default());
+        let rules = RuleSet::default();
+        let mut router = Router {
+            nat: &mut nat,
+            inside_mac: INSIDE,
+            outside_mac: OUTSIDE,
+            gateway: GATEWAY,
+            own: vec![Ipv4Addr::new(10, 0, 0, 1)],
+            hosts: HashMap::new(),
+            rules: &rules,
+        };
+
+        let frame = datagram(5);
+        let out = match router.outgoing(&EthernetPacket::new(&frame).unwrap()) {
+            Some(Routed::Forward(out)) => out,
+            routed => panic!("{:?}", routed),
+        };
+        let out = EthernetPacket::new(&out).unwrap();
+        assert_eq!(out.get_destination(), GATEWAY);
+        assert_eq!(frame_ttl(&out), Some(4));
+        assert_eq!(out.payload()[12..16], EXTERNAL.octets());
+
+        // traceroute's first probe
+        let frame = datagram(1);
+        let error = match router.outgoing(&EthernetPacket::new(&frame).unwrap()) {
+            Some(Routed::Expired(error)) => error,
+            routed => panic!("{:?}", routed),
+        };
+        let error = EthernetPacket::new(&error).unwrap();
+        assert_eq!(error.get_destination(), HOST);
+        let ip = error.payload();
+        assert_eq!(ip[9], IPPROTO_ICMP);
+        assert_eq!(ip[12..16], [10, 0, 0, 1]);
+        assert_eq!(ip[20], 11);
+    }
+}
@@ -0,0 +1,855 @@
+//! Resolving IP addresses of neighbors to MAC addresses.
+//!
+//! IPv4 neighbors are found with ARP and IPv6 neighbors with NDP neighbor solicitations
+//! (RFC 4861). A [`Resolver`] speaks both on one interface and implements [`Neighbor`], so
+//! code sending frames only deals with `IpAddr`s. Answers go to a [`NeighborCache`] that
+//! several resolvers can share, and other bindings seen while waiting for one as its
+//! policy for unsolicited bindings says.
+
+#[cfg(target_os = "linux")]
+use crate::arp::network_interface::addresses;
+use crate::{
+    arp::{
+        arp_new::{ArpOperations, ArpPacket},
+        channel::{
+            Channel, Config as ChannelConfig, DatalinkBackend, EthernetDataLinkReceiver,
+            EthernetDataLinkSender, Native,
+        },
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, Packet},
+        network_interface::{MacAddr, NetworkInterface},
+        other::build_arp_packet,
+    },
+    clock::{self, Clock},
+    icmpv6::{
+        ndp::{self, MutableNeighborSolicitPacket, NdpOption, NeighborAdvertPacket},
+        Icmpv6Types,
+    },
+    ipv4::IpNextHeaderProtocols,
+    ipv6::{Ipv6Packet, MutableIpv6Packet},
+    metrics::{Counter, Gauge, Metrics, Timer},
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Finds the MAC address of a neighbor.
+pub trait Neighbor {
+    /// The MAC address of the host using `ip` on the local network.
+    fn resolve(&mut self, ip: IpAddr) -> io::Result<MacAddr>;
+}
+
+/// A protocol asking neighbors of one address family for their MAC address.
+pub trait Protocol: Send {
+    /// The frame asking for the address of `target`, None if `target` is of another
+    /// family.
+    fn request(&self, target: IpAddr) -> Option<Vec<u8>>;
+
+    /// The binding announced by `frame`, if it is an answer of this protocol.
+    fn binding(&self, frame: &EthernetPacket) -> Option<(IpAddr, MacAddr)>;
+}
+
+/// ARP, for IPv4 neighbors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Arp {
+    /// MAC address requests are sent from
+    pub mac: MacAddr,
+    /// IPv4 address requests are sent from
+    pub ip: Ipv4Addr,
+}
+
+impl Protocol for Arp {
+    fn request(&self, target: IpAddr) -> Option<Vec<u8>> {
+        let target = match target {
+            IpAddr::V4(target) => target,
+            IpAddr::V6(_) => return None,
+        };
+        let frame = build_arp_packet(
+            MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+            self.mac,
+            self.ip,
+            MacAddr::new(0, 0, 0, 0, 0, 0),
+            target,
+            ArpOperations::Request,
+        );
+        Some(frame.to_vec())
+    }
+
+    fn binding(&self, frame: &EthernetPacket) -> Option<(IpAddr, MacAddr)> {
+        if frame.get_ethertype() != EtherTypes::Arp {
+            return None;
+        }
+        let arp = ArpPacket::new(frame.payload())?;
+        let sender = arp.get_sender_proto_addr();
+        if arp.get_operation() != ArpOperations::Reply || sender.is_unspecified() {
+            return None;
+        }
+        Some((IpAddr::V4(sender), arp.get_sender_hw_addr()))
+    }
+}
+
+/// NDP, for IPv6 neighbors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ndp {
+    /// MAC address solicitations are sent from
+    pub mac: MacAddr,
+    /// IPv6 address solicitations are sent from, usually the link-local one
+    pub ip: Ipv6Addr,
+}
+
+impl Ndp {
+    /// The solicited-node multicast address of `target`.
+    pub fn solicited_node(target: Ipv6Addr) -> Ipv6Addr {
+        let o = target.octets();
+        Ipv6Addr::new(
+            0xff02,
+            0,
+            0,
+            0,
+            0,
+            1,
+            0xff00 | u16::from(o[13]),
+            u16::from_be_bytes([o[14], o[15]]),
+        )
+    }
+}
+
+impl Protocol for Ndp {
+    fn request(&self, target: IpAddr) -> Option<Vec<u8>> {
+        let target = match target {
+            IpAddr::V6(target) => target,
+            IpAddr::V4(_) => return None,
+        };
+        let group = Ndp::solicited_node(target);
+        let g = group.octets();
+        let mut frame = vec![0u8; 14 + 40 + 32];
+        let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+        ethernet.set_destination(MacAddr::new(0x33, 0x33, g[12], g[13], g[14], g[15]));
+        ethernet.set_source(self.mac);
+        ethernet.set_ethertype(EtherTypes::Ipv6);
+
+        let mut ip = MutableIpv6Packet::new(&mut frame[14..]).unwrap();
+        ip.set_version(6);
+        ip.set_payload_length(32);
+        ip.set_next_header(IpNextHeaderProtocols::Icmpv6);
+        // NDP messages from elsewhere than the link are ignored
+        ip.set_hop_limit(255);
+        ip.set_source(self.ip);
+        ip.set_destination(group);
+
+        let mut solicit = MutableNeighborSolicitPacket::new(&mut frame[14 + 40..]).unwrap();
+        solicit.set_icmpv6_type(Icmpv6Types::NeighborSolicit);
+        solicit.set_target_addr(target);
+        let options = ndp::encode(&[NdpOption::SourceLinkLayerAddress(self.mac)]).unwrap();
+        solicit.set_options_raw(&options);
+        solicit.update_checksum(self.ip, group);
+        Some(frame)
+    }
+
+    fn binding(&self, frame: &EthernetPacket) -> Option<(IpAddr, MacAddr)> {
+        if frame.get_ethertype() != EtherTypes::Ipv6 {
+            return None;
+        }
+        let ip = Ipv6Packet::new(frame.payload())?;
+        if ip.get_next_header() != IpNextHeaderProtocols::Icmpv6 || ip.get_hop_limit() != 255 {
+            return None;
+        }
+        let advert = NeighborAdvertPacket::new(ip.payload())?;
+        if advert.get_icmpv6_type() != Icmpv6Types::NeighborAdvert {
+            return None;
+        }
+        // the target link-layer address option, if any, is authoritative
+        let mac = advert
+            .get_target_link_layer_address()
+            .unwrap_or_else(|| frame.get_source());
+        Some((IpAddr::V6(advert.get_target_addr()), mac))
+    }
+}
+
+/// What a [`NeighborCache`] does with bindings it didn't ask for, like gratuitous ARP
+/// replies or the senders of requests.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unsolicited {
+    /// Learn them like answers
+    Accept,
+    /// Refresh the bindings already cached with them, the way Linux does by default
+    UpdateOnly,
+    /// Ignore them, leaving only answers to poison the cache
+    Ignore,
+}
+
+/// Cache parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CacheConfig {
+    /// How long a binding stays valid. Defaults to 60 s
+    pub ttl: Duration,
+
+    /// Most bindings kept, and most requests awaiting an answer. Defaults to 1024
+    pub capacity: usize,
+
+    /// How long before expiry a lookup asks for a refresh. Defaults to 5 s
+    pub refresh_before: Duration,
+
+    /// What to do with bindings nobody asked for. Defaults to `Unsolicited::UpdateOnly`
+    pub unsolicited: Unsolicited,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            ttl: Duration::from_secs(60),
+            capacity: 1024,
+            refresh_before: Duration::from_secs(5),
+            unsolicited: Unsolicited::UpdateOnly,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    mac: MacAddr,
+    learned: Instant,
+    used: Instant,
+    // looked up close to expiry and not reported yet
+    refresh: bool,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    bindings: HashMap<IpAddr, Entry>,
+    // when a request for the address went out
+    requested: HashMap<IpAddr, Instant>,
+}
+
+/// IP to MAC bindings learned from neighbors, shared between the code sending frames and
+/// the code receiving answers.
+///
+/// Entries expire after a TTL. An entry looked up while close to expiring is reported by
+/// [`NeighborCache::refresh_due`] once, so that the owner can send a new request and keep
+/// it alive without a lookup ever missing; entries nobody looks up just expire. When full,
+/// the entry used longest ago makes room. Bindings learned from the network are only
+/// trusted as answers to requests sent, others are handled by the `unsolicited` policy.
+#[derive(Debug)]
+pub struct NeighborCache {
+    entries: Mutex<Entries>,
+    config: CacheConfig,
+    clock: Arc<dyn Clock>,
+    counters: Option<CacheCounters>,
+}
+
+#[derive(Debug)]
+struct CacheCounters {
+    hits: Counter,
+    misses: Counter,
+    learned: Counter,
+    rejected: Counter,
+    evictions: Counter,
+    entries: Gauge,
+    resolution: Timer,
+}
+
+impl NeighborCache {
+    /// Create an empty cache.
+    pub fn new(config: CacheConfig) -> NeighborCache {
+        NeighborCache::with_clock(config, clock::system())
+    }
+
+    /// Create an empty cache measuring time with `clock`.
+    pub fn with_clock(config: CacheConfig, clock: Arc<dyn Clock>) -> NeighborCache {
+        NeighborCache {
+            entries: Mutex::new(Entries::default()),
+            config,
+            clock,
+            counters: None,
+        }
+    }
+
+    /// Count into `metrics` the lookups answered as `<prefix>.hits` and missed as
+    /// `<prefix>.misses`, the bindings learned from neighbors as `<prefix>.learned` and
+    /// refused as `<prefix>.rejected`, and the bindings making room for others as
+    /// `<prefix>.evictions`. `<prefix>.entries` is the number of bindings held, and
+    /// `<prefix>.resolution` the histogram of the times from a request to its answer.
+    pub fn with_metrics(mut self, metrics: &Metrics, prefix: &str) -> NeighborCache {
+        let counters = CacheCounters {
+            hits: metrics.counter(&format!("{}.hits", prefix)),
+            misses: metrics.counter(&format!("{}.misses", prefix)),
+            learned: metrics.counter(&format!("{}.learned", prefix)),
+            rejected: metrics.counter(&format!("{}.rejected", prefix)),
+            evictions: metrics.counter(&format!("{}.evictions", prefix)),
+            entries: metrics.gauge(&format!("{}.entries", prefix)),
+            resolution: metrics.timer(&format!("{}.resolution", prefix)),
+        };
+        counters.entries.set(self.len() as f64);
+        self.counters = Some(counters);
+        self
+    }
+
+    /// The MAC address bound to `ip`, unless the binding expired.
+    pub fn get(&self, ip: IpAddr) -> Option<MacAddr> {
+        let mac = self.lookup(ip);
+        if let Some(counters) = &self.counters {
+            match mac {
+                Some(_) => counters.hits.increment(),
+                None => counters.misses.increment(),
+            }
+        }
+        mac
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Option<MacAddr> {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.bindings.get_mut(&ip)?;
+        let age = now.duration_since(entry.learned);
+        if age >= self.config.ttl {
+            entries.bindings.remove(&ip);
+            self.count_entries(&entries);
+            return None;
+        }
+        entry.used = now;
+        if age + self.config.refresh_before >= self.config.ttl {
+            entry.refresh = true;
+        }
+        Some(entry.mac)
+    }
+
+    /// Bind `ip` to `mac`, replacing any previous binding and evicting the least recently
+    /// used one if the cache is full.
+    pub fn insert(&self, ip: IpAddr, mac: MacAddr) {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.requested.remove(&ip);
+        self.bind(&mut entries, ip, mac, now);
+    }
+
+    /// Note that a request for `ip` went out, so that the answer is learned.
+    pub fn requested(&self, ip: IpAddr) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        let now = self.clock.now();
+        let ttl = self.config.ttl;
+        let mut entries = self.entries.lock().unwrap();
+        let requested = &mut entries.requested;
+        if !requested.contains_key(&ip) && requested.len() >= self.config.capacity {
+            requested.retain(|_, sent| now.duration_since(*sent) < ttl);
+            if requested.len() >= self.config.capacity {
+                let oldest = requested.iter().min_by_key(|(_, sent)| **sent);
+                if let Some(oldest) = oldest.map(|(ip, _)| *ip) {
+                    requested.remove(&oldest);
+                }
+            }
+        }
+        requested.insert(ip, now);
+    }
+
+    /// Learn that `ip` is at `mac`, as announced by a neighbor. Answers to requests noted
+    /// with `requested` are learned, other bindings as the `unsolicited` policy says.
+    /// Returns whether the binding was learned.
+    pub fn learn(&self, ip: IpAddr, mac: MacAddr) -> bool {
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        // how long the answer took, if it is one
+        let answer = entries
+            .requested
+            .remove(&ip)
+            .map(|sent| now.duration_since(sent))
+            .filter(|&waited| waited < self.config.ttl);
+        let learn = answer.is_some()
+            || match self.config.unsolicited {
+                Unsolicited::Accept => true,
+                Unsolicited::UpdateOnly => entries.bindings.contains_key(&ip),
+                Unsolicited::Ignore => false,
+            };
+        if learn {
+            self.bind(&mut entries, ip, mac, now);
+        }
+        if let Some(_waited) = answer {
+            trace_event!(
+                debug,
+                ip = %ip,
+                mac = %mac,
+                waited_us = _waited.as_micros() as u64,
+                "answered"
+            );
+        } else if !learn {
+            trace_event!(debug, ip = %ip, mac = %mac, "unsolicited binding ignored");
+        }
+        if let Some(counters) = &self.counters {
+            if let Some(waited) = answer {
+                counters.resolution.record(waited);
+            }
+            if learn {
+                counters.learned.increment();
+            } else {
+                counters.rejected.increment();
+            }
+        }
+        learn
+    }
+
+    /// Learn the sender binding of an ARP request or reply, see `learn`. Returns whether
+    /// it was learned.
+    pub fn learn_arp(&self, arp: &ArpPacket) -> bool {
+        let ip = arp.get_sender_proto_addr();
+        // probes come from the unspecified address
+        if ip.is_unspecified() {
+            return false;
+        }
+        self.learn(IpAddr::V4(ip), arp.get_sender_hw_addr())
+    }
+
+    /// Forget the binding of `ip`.
+    pub fn remove(&self, ip: IpAddr) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.bindings.remove(&ip);
+        self.count_entries(&entries);
+    }
+
+    /// Addresses whose bindings were looked up close to expiring, each reported once per
+    /// binding and noted as requested. Sending a request for them and learning the answer
+    /// keeps them cached.
+    pub fn refresh_due(&self) -> Vec<IpAddr> {
+        let now = self.clock.now();
+        let ttl = self.config.ttl;
+        let due: Vec<IpAddr> = {
+            let mut entries = self.entries.lock().unwrap();
+            entries
+                .bindings
+                .iter_mut()
+                .filter(|(_, entry)| entry.refresh && now.duration_since(entry.learned) < ttl)
+                .map(|(ip, entry)| {
+                    entry.refresh = false;
+                    *ip
+                })
+                .collect()
+        };
+        for &ip in &due {
+            self.requested(ip);
+        }
+        due
+    }
+
+    /// The bindings that are still valid.
+    pub fn entries(&self) -> Vec<(IpAddr, MacAddr)> {
+        let now = self.clock.now();
+        let entries = self.entries.lock().unwrap();
+        entries
+            .bindings
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.learned) < self.config.ttl)
+            .map(|(&ip, entry)| (ip, entry.mac))
+            .collect()
+    }
+
+    /// Number of bindings held, expired ones not yet dropped included.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().bindings.len()
+    }
+
+    /// Whether no bindings are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn bind(&self, entries: &mut Entries, ip: IpAddr, mac: MacAddr, now: Instant) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        let bindings = &mut entries.bindings;
+        if !bindings.contains_key(&ip) && bindings.len() >= self.config.capacity {
+            let ttl = self.config.ttl;
+            bindings.retain(|_, entry| now.duration_since(entry.learned) < ttl);
+            if bindings.len() >= self.config.capacity {
+                let oldest = bindings.iter().min_by_key(|(_, entry)| entry.used);
+                if let Some(oldest) = oldest.map(|(ip, _)| *ip) {
+                    bindings.remove(&oldest);
+                    if let Some(counters) = &self.counters {
+                        counters.evictions.increment();
+                    }
+                }
+            }
+        }
+        bindings.insert(
+            ip,
+            Entry {
+                mac,
+                learned: now,
+                used: now,
+                refresh: false,
+            },
+        );
+        self.count_entries(entries);
+    }
+
+    fn count_entries(&self, entries: &Entries) {
+        if let Some(counters) = &self.counters {
+            counters.entries.set(entries.bindings.len() as f64);
+        }
+    }
+}
+
+impl Default for NeighborCache {
+    fn default() -> NeighborCache {
+        NeighborCache::new(CacheConfig::default())
+    }
+}
+
+/// Resolver parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// How long to wait for an answer to each request. Defaults to 1 s
+    pub timeout: Duration,
+
+    /// Number of requests sent before giving up. Defaults to 3
+    pub attempts: u32,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            timeout: Duration::from_secs(1),
+            attempts: 3,
+        }
+    }
+}
+
+/// Resolves neighbors of one interface with ARP and NDP.
+pub struct Resolver {
+    tx: Box<dyn EthernetDataLinkSender>,
+    rx: Box<dyn EthernetDataLinkReceiver>,
+    protocols: Vec<Box<dyn Protocol>>,
+    cache: Arc<NeighborCache>,
+    config: Config,
+    clock: Arc<dyn Clock>,
+}
+
+impl Resolver {
+    /// Open a channel on `interface`, resolving IPv4 addresses if it has an IPv4 address
+    /// and IPv6 addresses if it has an IPv6 one.
+    pub fn open(interface: &NetworkInterface, config: Config) -> io::Result<Resolver> {
+        Resolver::open_with_backend(&Native::default(), interface, config)
+    }
+
+    /// [`Resolver::open`] with the channel opened through `backend`.
+    pub fn open_with_backend(
+        backend: &dyn DatalinkBackend,
+        interface: &NetworkInterface,
+        config: Config,
+    ) -> io::Result<Resolver> {
+        let mac = interface.mac.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("interface {} has no MAC address", interface.name),
+            )
+        })?;
+        let channel_config = ChannelConfig {
+            // wake up regularly to notice the timeout
+            read_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let (tx, rx) = match backend.channel(interface, channel_config)? {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+        };
+
+        let ips = source_addresses(interface);
+        let mut protocols: Vec<Box<dyn Protocol>> = Vec::new();
+        if let Some(ip) = ips.iter().find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(*ip),
+            _ => None,
+        }) {
+            protocols.push(Box::new(Arp { mac, ip }));
+        }
+        // solicitations are best sent from the link-local address
+        let ipv6 = ips.iter().filter_map(|ip| match ip {
+            IpAddr::V6(ip) => Some(*ip),
+            _ => None,
+        });
+        let link_local = ipv6.clone().find(|ip| ip.segments()[0] & 0xffc0 == 0xfe80);
+        if let Some(ip) = link_local.or_else(|| ipv6.clone().next()) {
+            protocols.push(Box::new(Ndp { mac, ip }));
+        }
+        Ok(Resolver::new(tx, rx, protocols, config))
+    }
+
+    /// Resolve with `protocols` over an already open channel.
+    pub fn new(
+        tx: Box<dyn EthernetDataLinkSender>,
+        rx: Box<dyn EthernetDataLinkReceiver>,
+        protocols: Vec<Box<dyn Protocol>>,
+        config: Config,
+    ) -> Resolver {
+        Resolver {
+            tx,
+            rx,
+            protocols,
+            cache: Arc::new(NeighborCache::default()),
+            config,
+            clock: clock::system(),
+        }
+    }
+
+    /// Time answers out with `clock`. The cache keeps its own clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Resolver {
+        self.clock = clock;
+        self
+    }
+
+    /// Use `cache`, possibly shared with other resolvers, instead of a cache of its own.
+    pub fn with_cache(mut self, cache: Arc<NeighborCache>) -> Resolver {
+        self.cache = cache;
+        self
+    }
+
+    /// The cache answers go to.
+    pub fn cache(&self) -> &Arc<NeighborCache> {
+        &self.cache
+    }
+}
+
+impl Neighbor for Resolver {
+    fn resolve(&mut self, ip: IpAddr) -> io::Result<MacAddr> {
+        let _span = trace_span!(DEBUG, "resolve", ip = %ip);
+        if let Some(mac) = self.cache.get(ip) {
+            trace_event!(trace, mac = %mac, "cached");
+            return Ok(mac);
+        }
+        let request = self
+            .protocols
+            .iter()
+            .find_map(|protocol| protocol.request(ip))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("no address to resolve {} from", ip),
+                )
+            })?;
+        let request = EthernetPacket::owned(request).unwrap();
+
+        let mut iter = self.rx.iter();
+        for _attempt in 0..self.config.attempts {
+            self.cache.requested(ip);
+            self.tx
+                .send_to(&request, None)
+                .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+            trace_event!(debug, attempt = _attempt + 1, "sent request");
+            let deadline = self.clock.now() + self.config.timeout;
+            while self.clock.now() < deadline {
+                let frame = match iter.next() {
+                    Ok(frame) => frame,
+                    Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                    Err(e) => return Err(e),
+                };
+                for protocol in &self.protocols {
+                    if let Some((bound, mac)) = protocol.binding(&frame) {
+                        if self.cache.learn(bound, mac) && bound == ip {
+                            trace_event!(debug, mac = %mac, attempt = _attempt + 1, "resolved");
+                            return Ok(mac);
+                        }
+                    }
+                }
+            }
+        }
+        trace_event!(info, attempts = self.config.attempts, "no answer");
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("no answer from {}", ip),
+        ))
+    }
+}
+
+// The addresses of `interface` new traffic may come from, leaving out tentative and
+// deprecated IPv6 ones when the kernel can tell.
+#[cfg(target_os = "linux")]
+fn source_addresses(interface: &NetworkInterface) -> Vec<IpAddr> {
+    let known: Vec<_> = match addresses() {
+        Ok(addresses) => addresses
+            .into_iter()
+            .filter(|address| address.index == interface.index)
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    // devices the kernel doesn't know, like a `Loopback`, describe their own
+    if known.is_empty() {
+        return interface.ips.iter().flatten().cloned().collect();
+    }
+    known
+        .iter()
+        .filter(|address| address.is_preferred_source())
+        .map(|address| address.cidr.address())
+        .collect()
+}
+
+// Elsewhere there is no netlink to tell the states of the addresses.
+#[cfg(not(target_os = "linux"))]
+fn source_addresses(interface: &NetworkInterface) -> Vec<IpAddr> {
+    interface.ips.iter().flatten().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arp::loopback::Loopback, clock::MockClock};
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn cache_is_bounded_and_checks_unsolicited_bindings() {
+        let clock = Arc::new(MockClock::new());
+        let config = CacheConfig {
+            ttl: Duration::from_secs(10),
+            capacity: 2,
+            refresh_before: Duration::from_secs(2),
+            unsolicited: Unsolicited::UpdateOnly,
+        };
+        let cache = NeighborCache::with_clock(config, clock.clone());
+        let ip = |last| IpAddr::V4(Ipv4Addr::new(10, 0, 0, last));
+        let mac = |last| MacAddr::new(0x02, 0, 0, 0, 0, last);
+
+        // only answers create bindings
+        assert!(!cache.learn(ip(1), mac(1)));
+        cache.requested(ip(1));
+        assert!(cache.learn(ip(1), mac(1)));
+        assert!(cache.learn(ip(1), mac(11)));
+        assert_eq!(cache.get(ip(1)), Some(mac(11)));
+
+        // the least recently used binding makes room
+        cache.insert(ip(2), mac(2));
+        clock.advance(Duration::from_secs(1));
+        cache.get(ip(1));
+        cache.insert(ip(3), mac(3));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(ip(2)), None);
+
+        // a lookup close to expiry asks for a refresh, once
+        clock.advance(Duration::from_secs(8));
+        assert_eq!(cache.get(ip(1)), Some(mac(11)));
+        assert_eq!(cache.refresh_due(), [ip(1)]);
+        assert!(cache.refresh_due().is_empty());
+        assert!(cache.learn(ip(1), mac(1)));
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(cache.get(ip(1)), Some(mac(1)));
+        assert_eq!(cache.get(ip(3)), None);
+
+        let strict = NeighborCache::new(CacheConfig {
+            unsolicited: Unsolicited::Ignore,
+            ..Default::default()
+        });
+        strict.insert(ip(1), mac(1));
+        assert!(!strict.learn(ip(1), mac(2)));
+        assert_eq!(strict.get(ip(1)), Some(mac(1)));
+    }
+
+    #[test]
+    fn cache_counts_into_metrics() {
+        let clock = Arc::new(MockClock::new());
+        let config = CacheConfig {
+            ttl: Duration::from_secs(10),
+            capacity: 2,
+            ..Default::default()
+        };
+        let metrics = Metrics::new();
+        let cache = NeighborCache::with_clock(config, clock.clone()).with_metrics(&metrics, "arp");
+        let ip = |last| IpAddr::V4(Ipv4Addr::new(10, 0, 0, last));
+        let mac = |last| MacAddr::new(0x02, 0, 0, 0, 0, last);
+
+        cache.requested(ip(1));
+        clock.advance(Duration::from_millis(3));
+        assert!(cache.learn(ip(1), mac(1)));
+        assert!(!cache.learn(ip(2), mac(2)));
+        clock.advance(Duration::from_secs(1));
+        cache.insert(ip(2), mac(2));
+        cache.insert(ip(3), mac(3));
+        assert_eq!(cache.get(ip(3)), Some(mac(3)));
+        assert_eq!(cache.get(ip(1)), None);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(cache.get(ip(3)), None);
+
+        let counters: HashMap<_, _> = metrics.counters().into_iter().collect();
+        assert_eq!(counters["arp.hits"], 1);
+        assert_eq!(counters["arp.misses"], 2);
+        assert_eq!(counters["arp.learned"], 1);
+        assert_eq!(counters["arp.rejected"], 1);
+        assert_eq!(counters["arp.evictions"], 1);
+        assert_eq!(metrics.gauges(), [("arp.entries".to_owned(), 1.0)]);
+        let resolution = metrics.summary("arp.resolution").unwrap();
+        assert_eq!(resolution.count, 1);
+        assert_eq!(resolution.max, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn resolves_over_a_backend() {
+        let ours = MacAddr::new(0x02, 0, 0, 0, 0, 1);
+        let device =
+            Loopback::new().with_address(ours, vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+        let neighbor = (
+            Ipv4Addr::new(10, 0, 0, 2),
+            MacAddr::new(0x02, 0, 0, 0, 0, 2),
+        );
+
+        // a host answering the requests for its address
+        let (mut tx, mut rx) = match device.channel(ChannelConfig {
+            read_timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        }) {
+            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let host_stop = stop.clone();
+        let host = thread::spawn(move || {
+            let mut iter = rx.iter();
+            while !host_stop.load(Ordering::SeqCst) {
+                let frame = match iter.next() {
+                    Ok(frame) if frame.get_ethertype() == EtherTypes::Arp => frame,
+                    _ => continue,
+                };
+                let arp = match ArpPacket::new(frame.payload()) {
+                    Some(arp) => arp,
+                    None => continue,
+                };
+                if arp.get_operation() != ArpOperations::Request
+                    || arp.get_target_proto_addr() != neighbor.0
+                {
+                    continue;
+                }
+                let reply = build_arp_packet(
+                    arp.get_sender_hw_addr(),
+                    neighbor.1,
+                    neighbor.0,
+                    arp.get_sender_hw_addr(),
+                    arp.get_sender_proto_addr(),
+                    ArpOperations::Reply,
+                );
+                tx.send_to(&EthernetPacket::new(&reply).unwrap(), None);
+            }
+        });
+
+        let config = Config {
+            timeout: Duration::from_millis(200),
+            attempts: 2,
+        };
+        let mut resolver =
+            Resolver::open_with_backend(&device, device.interface(), config).unwrap();
+        let resolved = resolver.resolve(IpAddr::V4(neighbor.0));
+        let unanswered = resolver.resolve(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)));
+        stop.store(true, Ordering::SeqCst);
+        host.join().unwrap();
+
+        assert_eq!(resolved.unwrap(), neighbor.1);
+        assert_eq!(
+            resolver.cache().get(IpAddr::V4(neighbor.0)),
+            Some(neighbor.1)
+        );
+        assert_eq!(unanswered.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        // no NDP without an IPv6 address
+        let ipv6 = resolver.resolve(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert_eq!(ipv6.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,262 @@
+//! Interface offload settings.
+//!
+//! With checksum offload the NIC fills in checksums after packet sockets saw the frame,
+//! so captured outgoing packets carry wrong ones, and with segmentation or receive offload
+//! captured frames can be far larger than the MTU. [`query`] reads these settings through
+//! the ethtool ioctl so that tools can warn about them, and [`OffloadOverride`] turns them
+//! off for clean captures, putting them back when dropped.
+
+use crate::{
+    arp::channel::FileDesc,
+    privileges::{self, Capability},
+};
+use std::{fmt, io, mem};
+
+const SIOCETHTOOL: libc::c_ulong = 0x8946;
+
+/// An offload feature.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Offload {
+    /// Receive checksum verification
+    RxChecksum,
+    /// Transmit checksum computation
+    TxChecksum,
+    /// TCP segmentation offload
+    Tso,
+    /// Generic segmentation offload
+    Gso,
+    /// Generic receive offload
+    Gro,
+}
+
+impl Offload {
+    /// Every feature, in the order `Offloads` lists them.
+    pub const ALL: [Offload; 5] = [
+        Offload::RxChecksum,
+        Offload::TxChecksum,
+        Offload::Tso,
+        Offload::Gso,
+        Offload::Gro,
+    ];
+
+    // ethtool get and set commands
+    fn commands(self) -> (u32, u32) {
+        match self {
+            Offload::RxChecksum => (0x14, 0x15),
+            Offload::TxChecksum => (0x16, 0x17),
+            Offload::Tso => (0x1e, 0x1f),
+            Offload::Gso => (0x23, 0x24),
+            Offload::Gro => (0x2b, 0x2c),
+        }
+    }
+}
+
+impl fmt::Display for Offload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Offload::RxChecksum => "rx-checksum",
+            Offload::TxChecksum => "tx-checksum",
+            Offload::Tso => "tso",
+            Offload::Gso => "gso",
+            Offload::Gro => "gro",
+        })
+    }
+}
+
+/// The offload settings of an interface.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Offloads {
+    /// Receive checksum verification
+    pub rx_checksum: bool,
+    /// Transmit checksum computation
+    pub tx_checksum: bool,
+    /// TCP segmentation offload
+    pub tso: bool,
+    /// Generic segmentation offload
+    pub gso: bool,
+    /// Generic receive offload
+    pub gro: bool,
+}
+
+impl Offloads {
+    /// Whether `offload` is on.
+    pub fn get(&self, offload: Offload) -> bool {
+        match offload {
+            Offload::RxChecksum => self.rx_checksum,
+            Offload::TxChecksum => self.tx_checksum,
+            Offload::Tso => self.tso,
+            Offload::Gso => self.gso,
+            Offload::Gro => self.gro,
+        }
+    }
+
+    /// Whether captured outgoing packets may carry checksums the NIC fixes later.
+    pub fn checksums_unreliable(&self) -> bool {
+        self.tx_checksum
+    }
+
+    /// Whether captured frames may be several wire frames coalesced into one.
+    pub fn coalesces(&self) -> bool {
+        self.tso || self.gso || self.gro
+    }
+
+    /// Warnings to show before capturing on the interface.
+    pub fn warnings(&self) -> Vec<&'static str> {
+        let mut warnings = Vec::new();
+        if self.checksums_unreliable() {
+            warnings.push("transmit checksum offload is on, outgoing checksums will look bad");
+        }
+        if self.coalesces() {
+            warnings.push("segmentation or receive offload is on, frames may exceed the MTU");
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for Offloads {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, offload) in Offload::ALL.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            let state = if self.get(*offload) { "on" } else { "off" };
+            write!(f, "{} {}", offload, state)?;
+        }
+        Ok(())
+    }
+}
+
+/// The offload settings of `interface`.
+pub fn query(interface: &str) -> io::Result<Offloads> {
+    Ok(Offloads {
+        rx_checksum: get(interface, Offload::RxChecksum)?,
+        tx_checksum: get(interface, Offload::TxChecksum)?,
+        tso: get(interface, Offload::Tso)?,
+        gso: get(interface, Offload::Gso)?,
+        gro: get(interface, Offload::Gro)?,
+    })
+}
+
+/// Whether `offload` is on for `interface`.
+pub fn get(interface: &str, offload: Offload) -> io::Result<bool> {
+    let mut value = EthtoolValue {
+        cmd: offload.commands().0,
+        data: 0,
+    };
+    ethtool(interface, &mut value)?;
+    Ok(value.data != 0)
+}
+
+/// Turn `offload` on or off for `interface`. Needs `CAP_NET_ADMIN`, and fails for
+/// features the driver can't change.
+pub fn set(interface: &str, offload: Offload, on: bool) -> io::Result<()> {
+    privileges::require(&[Capability::NetAdmin])?;
+    let mut value = EthtoolValue {
+        cmd: offload.commands().1,
+        data: on as u32,
+    };
+    ethtool(interface, &mut value)?;
+    trace_event!(debug, interface, offload = %offload, on, "offload set");
+    Ok(())
+}
+
+/// `struct ethtool_value`.
+#[repr(C)]
+struct EthtoolValue {
+    cmd: u32,
+    data: u32,
+}
+
+/// `struct ifreq` pointing at ethtool command data.
+#[repr(C)]
+struct EthtoolRequest {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    data: *mut libc::c_void,
+    // the union in `struct ifreq` is larger than a pointer
+    _padding: [u8; 16],
+}
+
+fn ethtool(interface: &str, value: &mut EthtoolValue) -> io::Result<()> {
+    if interface.is_empty() || interface.len() >= libc::IFNAMSIZ || interface.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid interface name `{}`", interface),
+        ));
+    }
+    let mut request: EthtoolRequest = unsafe { mem::zeroed() };
+    for (c, byte) in request.name.iter_mut().zip(interface.bytes()) {
+        *c = byte as libc::c_char;
+    }
+    request.data = (value as *mut EthtoolValue) as *mut libc::c_void;
+
+    let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    if socket == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let socket = FileDesc { fd: socket };
+    if unsafe {
+        libc::ioctl(
+            socket.fd,
+            SIOCETHTOOL as _,
+            &mut request as *mut EthtoolRequest,
+        )
+    } == -1
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Offloads turned off on an interface, turned back on when dropped.
+#[derive(Debug)]
+pub struct OffloadOverride {
+    interface: String,
+    // the features this override turned off
+    disabled: Vec<Offload>,
+    restored: bool,
+}
+
+impl OffloadOverride {
+    /// Turn off every offload that is on for `interface`, so that captures show the
+    /// frames and checksums the wire carries.
+    pub fn disable_all(interface: &str) -> io::Result<OffloadOverride> {
+        let offloads = query(interface)?;
+        let mut guard = OffloadOverride {
+            interface: interface.to_owned(),
+            disabled: Vec::new(),
+            restored: false,
+        };
+        for offload in Offload::ALL
+            .iter()
+            .filter(|offload| offloads.get(**offload))
+        {
+            // dropping the guard restores the ones already turned off
+            set(interface, *offload, false)?;
+            guard.disabled.push(*offload);
+        }
+        Ok(guard)
+    }
+
+    /// The features turned off.
+    pub fn disabled(&self) -> &[Offload] {
+        &self.disabled
+    }
+
+    /// Turn the features back on, reporting failures that dropping would ignore.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restored = true;
+        self.disabled
+            .iter()
+            .try_for_each(|offload| set(&self.interface, *offload, true))
+    }
+}
+
+impl Drop for OffloadOverride {
+    fn drop(&mut self) {
+        if !self.restored {
+            for offload in &self.disabled {
+                let _ = set(&self.interface, *offload, true);
+            }
+        }
+    }
+}
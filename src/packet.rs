@@ -0,0 +1,178 @@
+//! Declaring packet types.
+//!
+//! [`packet_types!`] declares a borrowed and a mutable view of a protocol's packets with
+//! the constructors and `Packet` impls `EthernetPacket` and `ArpPacket` have, leaving the
+//! field accessors to the protocol module.
+
+/// Declare `$name` and `$mutable` over packet data at least `$min` bytes long.
+///
+/// `$header_len` and `$packet_len` are functions of the packet bytes giving where the
+/// payload starts and ends; both are clamped to the bytes there are. The `getters` are
+/// implemented for both types, the `setters` for the mutable one only.
+macro_rules! packet_types {
+    (
+        $(#[$doc:meta])*
+        pub struct $name:ident / $mutable:ident;
+        minimum_size = $min:expr;
+        header_len = $header_len:path;
+        packet_len = $packet_len:path;
+        getters { $($getters:tt)* }
+        setters { $($setters:tt)* }
+    ) => {
+        $(#[$doc])*
+        #[derive(PartialEq)]
+        pub struct $name<'p> {
+            packet: $crate::arp::ether::PacketData<'p>,
+        }
+
+        $(#[$doc])*
+        #[derive(PartialEq)]
+        pub struct $mutable<'p> {
+            packet: $crate::arp::ether::MutPacketData<'p>,
+        }
+
+        impl<'a> $name<'a> {
+            /// Constructs a new packet. If the provided buffer is less than the minimum
+            /// required packet size, this will return None.
+            #[inline]
+            pub fn new<'p>(packet: &'p [u8]) -> Option<$name<'p>> {
+                if packet.len() >= $min {
+                    Some($name {
+                        packet: $crate::arp::ether::PacketData::Borrowed(packet),
+                    })
+                } else {
+                    None
+                }
+            }
+            /// Constructs a new packet owning its buffer. If the provided buffer is less
+            /// than the minimum required packet size, this will return None.
+            pub fn owned(packet: Vec<u8>) -> Option<$name<'static>> {
+                if packet.len() >= $min {
+                    Some($name {
+                        packet: $crate::arp::ether::PacketData::Owned(packet),
+                    })
+                } else {
+                    None
+                }
+            }
+            /// Borrow the packet.
+            #[inline]
+            pub fn to_immutable<'p>(&'p self) -> $name<'p> {
+                $name {
+                    packet: $crate::arp::ether::PacketData::Borrowed(self.packet.as_slice()),
+                }
+            }
+            /// Consume the packet, keeping its data.
+            #[inline]
+            pub fn consume_to_immutable(self) -> $name<'a> {
+                $name {
+                    packet: self.packet.to_immutable(),
+                }
+            }
+            /// The minimum size (in bytes) a packet of this type can be.
+            #[inline]
+            pub const fn minimum_packet_size() -> usize {
+                $min
+            }
+            $($getters)*
+        }
+
+        impl<'a> $mutable<'a> {
+            /// Constructs a new packet. If the provided buffer is less than the minimum
+            /// required packet size, this will return None.
+            #[inline]
+            pub fn new<'p>(packet: &'p mut [u8]) -> Option<$mutable<'p>> {
+                if packet.len() >= $min {
+                    Some($mutable {
+                        packet: $crate::arp::ether::MutPacketData::Borrowed(packet),
+                    })
+                } else {
+                    None
+                }
+            }
+            /// Constructs a new packet owning its buffer. If the provided buffer is less
+            /// than the minimum required packet size, this will return None.
+            pub fn owned(packet: Vec<u8>) -> Option<$mutable<'static>> {
+                if packet.len() >= $min {
+                    Some($mutable {
+                        packet: $crate::arp::ether::MutPacketData::Owned(packet),
+                    })
+                } else {
+                    None
+                }
+            }
+            /// Borrow the packet immutably.
+            #[inline]
+            pub fn to_immutable<'p>(&'p self) -> $name<'p> {
+                $name {
+                    packet: $crate::arp::ether::PacketData::Borrowed(self.packet.as_slice()),
+                }
+            }
+            /// Consume the packet, keeping its data.
+            #[inline]
+            pub fn consume_to_immutable(self) -> $name<'a> {
+                $name {
+                    packet: self.packet.to_immutable(),
+                }
+            }
+            /// The minimum size (in bytes) a packet of this type can be.
+            #[inline]
+            pub const fn minimum_packet_size() -> usize {
+                $min
+            }
+            $($getters)*
+            $($setters)*
+        }
+
+        impl<'a> $crate::arp::ether::Packet for $name<'a> {
+            #[inline]
+            fn packet(&self) -> &[u8] {
+                &self.packet[..]
+            }
+            fn payload(&self) -> &[u8] {
+                let data = &self.packet[..];
+                let start = $header_len(data).min(data.len());
+                let end = $packet_len(data).max(start).min(data.len());
+                &data[start..end]
+            }
+        }
+
+        impl<'a> $crate::arp::ether::Packet for $mutable<'a> {
+            #[inline]
+            fn packet(&self) -> &[u8] {
+                &self.packet[..]
+            }
+            fn payload(&self) -> &[u8] {
+                let data = &self.packet[..];
+                let start = $header_len(data).min(data.len());
+                let end = $packet_len(data).max(start).min(data.len());
+                &data[start..end]
+            }
+        }
+
+        impl<'a> $crate::arp::ether::MutablePacket for $mutable<'a> {
+            #[inline]
+            fn packet_mut(&mut self) -> &mut [u8] {
+                &mut self.packet[..]
+            }
+            fn payload_mut(&mut self) -> &mut [u8] {
+                let data = &mut self.packet[..];
+                let start = $header_len(data).min(data.len());
+                let end = $packet_len(data).max(start).min(data.len());
+                &mut data[start..end]
+            }
+        }
+
+        impl<'a> $crate::arp::ether::PacketSize for $name<'a> {
+            fn packet_size(&self) -> usize {
+                $packet_len(&self.packet[..]).min(self.packet.len())
+            }
+        }
+
+        impl<'a> $crate::arp::ether::PacketSize for $mutable<'a> {
+            fn packet_size(&self) -> usize {
+                $packet_len(&self.packet[..]).min(self.packet.len())
+            }
+        }
+    };
+}
@@ -0,0 +1,873 @@
+//! Reading and writing of libpcap capture files.
+//!
+//! Files are written in the classic pcap format, optionally rotated into a ring of files
+//! by [`RotatingWriter`]. The reader also understands pcapng, and an [`OfflineReceiver`]
+//! hands the frames of an Ethernet capture to code written for live channels. As an
+//! [`OfflineBackend`], a capture file stands in for an interface. [`Reader::filtered`]
+//! skips the frames a `Filter` doesn't match.
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::arp::{
+    channel::{
+        Channel, ChannelType, Config, DatalinkBackend, EthernetDataLinkChannelIterator,
+        EthernetDataLinkReceiver, EthernetDataLinkSender, RxMeta,
+    },
+    ether::EthernetPacket,
+    network_interface::{LinkType, MacAddr, NetworkInterface, OperState, FLAG_UP},
+};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::filter::{self, Filter};
+use crate::pool::{Buffer, BufferPool};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Magic number of a microsecond resolution capture file.
+pub const MAGIC: u32 = 0xa1b2_c3d4;
+
+/// Magic number of a nanosecond resolution capture file.
+pub const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+/// Block type of a pcapng section header, which also starts a pcapng file.
+pub const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+
+const FILE_HEADER_LEN: u64 = 24;
+const RECORD_HEADER_LEN: u64 = 16;
+
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_TSRESOL: u16 = 9;
+
+/// Largest pcapng block read, and largest packet of pcap files that don't give a snaplen.
+/// Longer lengths are taken for corruption rather than allocated.
+const MAX_BLOCK_LEN: usize = 16 << 20;
+
+/// Link type of Ethernet captures.
+pub const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Default maximum number of bytes stored per packet.
+pub const DEFAULT_SNAPLEN: u32 = 65535;
+
+/// Writes packets to a capture file.
+pub struct Writer<W: Write> {
+    inner: W,
+    snaplen: u32,
+}
+
+impl<W: Write> Writer<W> {
+    /// Write the file header to `inner` and return a writer for Ethernet captures.
+    pub fn new(inner: W) -> io::Result<Writer<W>> {
+        Writer::with_snaplen(inner, DEFAULT_SNAPLEN)
+    }
+
+    /// Like `new`, truncating packets to `snaplen` bytes.
+    pub fn with_snaplen(mut inner: W, snaplen: u32) -> io::Result<Writer<W>> {
+        let mut header = [0u8; FILE_HEADER_LEN as usize];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&2u16.to_le_bytes());
+        header[6..8].copy_from_slice(&4u16.to_le_bytes());
+        // thiszone and sigfigs stay zero
+        header[16..20].copy_from_slice(&snaplen.to_le_bytes());
+        header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        inner.write_all(&header)?;
+
+        Ok(Writer { inner, snaplen })
+    }
+
+    /// Append a packet captured at `timestamp`.
+    pub fn write_packet(&mut self, timestamp: SystemTime, data: &[u8]) -> io::Result<()> {
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let captured = data.len().min(self.snaplen as usize);
+
+        let mut header = [0u8; RECORD_HEADER_LEN as usize];
+        header[0..4].copy_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        header[8..12].copy_from_slice(&(captured as u32).to_le_bytes());
+        header[12..16].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&data[..captured])
+    }
+
+    /// Flush buffered packets to the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+
+    /// Return the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+/// When a [`RotatingWriter`] moves on to a new file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rotation {
+    /// Start a new file before one would grow beyond this many bytes. Defaults to none
+    pub max_size: Option<u64>,
+
+    /// Start a new file once a packet is this much younger than the first one of the
+    /// current file. Defaults to none
+    pub max_age: Option<Duration>,
+
+    /// Delete the oldest file when more than this many exist. Defaults to none
+    pub max_files: Option<usize>,
+}
+
+impl Rotation {
+    /// Whether files are rotated at all.
+    pub fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.max_age.is_some()
+    }
+}
+
+/// Writes packets to a ring of capture files.
+///
+/// Without rotation packets go to the given path. Otherwise files are named after it with
+/// a sequence number appended, `capture.pcap.0`, `capture.pcap.1` and so on. Files left by
+/// an earlier session are kept and count towards `max_files`, numbering going on after
+/// them. Every file is synced to disk when it is closed, so a crash loses at most the
+/// current file.
+pub struct RotatingWriter {
+    path: PathBuf,
+    rotation: Rotation,
+    snaplen: u32,
+    writer: Writer<BufWriter<File>>,
+    size: u64,
+    first: Option<SystemTime>,
+    sequence: u64,
+    files: VecDeque<PathBuf>,
+}
+
+impl RotatingWriter {
+    /// Create the first file of the ring.
+    pub fn create<P: AsRef<Path>>(path: P, rotation: Rotation) -> io::Result<RotatingWriter> {
+        RotatingWriter::with_snaplen(path, rotation, DEFAULT_SNAPLEN)
+    }
+
+    /// Like `create`, truncating packets to `snaplen` bytes.
+    pub fn with_snaplen<P: AsRef<Path>>(
+        path: P,
+        rotation: Rotation,
+        snaplen: u32,
+    ) -> io::Result<RotatingWriter> {
+        let path = path.as_ref().to_owned();
+        let mut files = if rotation.is_enabled() {
+            RotatingWriter::existing_files(&path)?
+        } else {
+            VecDeque::new()
+        };
+        let sequence = files.back().map_or(0, |(sequence, _)| sequence + 1);
+        let first = RotatingWriter::file_name(&path, &rotation, sequence);
+        let writer = Writer::with_snaplen(BufWriter::new(File::create(&first)?), snaplen)?;
+        files.push_back((sequence, first));
+        let mut writer = RotatingWriter {
+            path,
+            rotation,
+            snaplen,
+            writer,
+            size: FILE_HEADER_LEN,
+            first: None,
+            sequence,
+            files: files.into_iter().map(|(_, file)| file).collect(),
+        };
+        writer.remove_oldest()?;
+        Ok(writer)
+    }
+
+    /// Append a packet captured at `timestamp`, rotating first if needed.
+    pub fn write_packet(&mut self, timestamp: SystemTime, data: &[u8]) -> io::Result<()> {
+        let len = RECORD_HEADER_LEN + data.len().min(self.snaplen as usize) as u64;
+        if self.first.is_some() && self.needs_rotation(timestamp, len) {
+            self.rotate()?;
+        }
+        self.writer.write_packet(timestamp, data)?;
+        self.size += len;
+        self.first.get_or_insert(timestamp);
+        Ok(())
+    }
+
+    /// Close the current file and start the next one, deleting the oldest file if the
+    /// ring is full.
+    pub fn rotate(&mut self) -> io::Result<()> {
+        self.sequence += 1;
+        let next = RotatingWriter::file_name(&self.path, &self.rotation, self.sequence);
+        let writer = Writer::with_snaplen(BufWriter::new(File::create(&next)?), self.snaplen)?;
+        let previous = std::mem::replace(&mut self.writer, writer);
+        sync(previous)?;
+
+        self.size = FILE_HEADER_LEN;
+        self.first = None;
+        self.files.push_back(next);
+        self.remove_oldest()
+    }
+
+    /// Flush buffered packets to the current file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Flush and sync the current file to disk.
+    pub fn close(self) -> io::Result<()> {
+        sync(self.writer)
+    }
+
+    /// Path of the file currently written.
+    pub fn current_path(&self) -> &Path {
+        self.files
+            .back()
+            .map(PathBuf::as_path)
+            .unwrap_or(&self.path)
+    }
+
+    fn needs_rotation(&self, timestamp: SystemTime, len: u64) -> bool {
+        let too_big = self
+            .rotation
+            .max_size
+            .map_or(false, |max_size| self.size + len > max_size);
+        let too_old = match (self.rotation.max_age, self.first) {
+            (Some(max_age), Some(first)) => {
+                timestamp.duration_since(first).unwrap_or_default() >= max_age
+            }
+            _ => false,
+        };
+        too_big || too_old
+    }
+
+    // delete files until the ring fits in `max_files`
+    fn remove_oldest(&mut self) -> io::Result<()> {
+        if let Some(max_files) = self.rotation.max_files {
+            while self.files.len() > max_files.max(1) {
+                if let Some(oldest) = self.files.pop_front() {
+                    fs::remove_file(oldest)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the files of the ring of `path` already on disk, by sequence number
+    fn existing_files(path: &Path) -> io::Result<VecDeque<(u64, PathBuf)>> {
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => format!("{}.", name),
+            None => return Ok(VecDeque::new()),
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let sequence = entry
+                .file_name()
+                .to_str()
+                .and_then(|file| file.strip_prefix(&name))
+                .and_then(|sequence| sequence.parse::<u64>().ok());
+            if let Some(sequence) = sequence {
+                files.push((sequence, path.with_file_name(entry.file_name())));
+            }
+        }
+        files.sort();
+        Ok(files.into())
+    }
+
+    fn file_name(path: &Path, rotation: &Rotation, sequence: u64) -> PathBuf {
+        if !rotation.is_enabled() {
+            return path.to_owned();
+        }
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", sequence));
+        name.into()
+    }
+}
+
+fn sync(writer: Writer<BufWriter<File>>) -> io::Result<()> {
+    let file = writer
+        .into_inner()
+        .into_inner()
+        .map_err(|e| e.into_error())?;
+    file.sync_all()
+}
+
+/// A packet read from a capture file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Time the packet was captured
+    pub timestamp: SystemTime,
+    /// Length of the packet on the wire, which may exceed `data.len()`
+    pub original_len: u32,
+    /// Captured bytes
+    pub data: Vec<u8>,
+}
+
+/// A packet read from a capture file into a pooled buffer.
+#[derive(Debug, PartialEq)]
+pub struct PooledRecord {
+    /// Time the packet was captured
+    pub timestamp: SystemTime,
+    /// Length of the packet on the wire, which may exceed `data.len()`
+    pub original_len: u32,
+    /// Captured bytes
+    pub data: Buffer,
+}
+
+enum Format {
+    // packets longer than `snaplen` are taken for corruption
+    Pcap { nanos: bool, snaplen: usize },
+    // timestamp resolution of each interface described so far, in units per second
+    Pcapng { interfaces: Vec<u64> },
+}
+
+/// Reads packets from a pcap or pcapng capture file.
+///
+/// pcapng files may describe several interfaces; `link_type` reports the first one and
+/// packets of all interfaces are returned.
+pub struct Reader<R: Read> {
+    inner: R,
+    format: Format,
+    big_endian: bool,
+    // None until a pcapng interface description was read
+    link_type: Option<u32>,
+    // body of the last pcapng block, reused between blocks
+    block: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Read the file header from `inner`.
+    pub fn new(mut inner: R) -> io::Result<Reader<R>> {
+        let mut magic = [0u8; 4];
+        inner.read_exact(&mut magic)?;
+
+        if u32::from_be_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let mut reader = Reader {
+                inner,
+                format: Format::Pcapng {
+                    interfaces: Vec::new(),
+                },
+                big_endian: false,
+                link_type: None,
+                block: Vec::new(),
+            };
+            let mut total_len = [0u8; 4];
+            reader.inner.read_exact(&mut total_len)?;
+            reader.read_section_header(total_len)?;
+            // packet blocks refer to interfaces, so a description precedes the first one
+            while reader.link_type.is_none() {
+                let block_type = reader
+                    .read_block()?
+                    .ok_or_else(|| invalid_data("pcapng file without an interface description"))?;
+                let body = mem::replace(&mut reader.block, Vec::new());
+                let handled = reader.handle_block(block_type, &body, &mut Vec::new());
+                reader.block = body;
+                handled?;
+            }
+            return Ok(reader);
+        }
+
+        let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+            (MAGIC, _) => (false, false),
+            (MAGIC_NANOS, _) => (false, true),
+            (_, MAGIC) => (true, false),
+            (_, MAGIC_NANOS) => (true, true),
+            _ => return Err(invalid_data("not a pcap file")),
+        };
+        let mut header = [0u8; 20];
+        inner.read_exact(&mut header)?;
+        let mut reader = Reader {
+            inner,
+            format: Format::Pcap { nanos, snaplen: 0 },
+            big_endian,
+            link_type: None,
+            block: Vec::new(),
+        };
+        reader.link_type = Some(reader.u32_at(&header, 16));
+        let snaplen = match reader.u32_at(&header, 12) as usize {
+            0 => MAX_BLOCK_LEN,
+            snaplen => snaplen.min(MAX_BLOCK_LEN),
+        };
+        reader.format = Format::Pcap { nanos, snaplen };
+        Ok(reader)
+    }
+
+    /// Link type of the capture, `LINKTYPE_ETHERNET` for Ethernet frames.
+    pub fn link_type(&self) -> u32 {
+        // always set once `new` returned
+        self.link_type.unwrap_or(LINKTYPE_ETHERNET)
+    }
+
+    /// Read the next packet, or None at the end of the file.
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        let mut data = Vec::new();
+        Ok(self
+            .read_next(&mut data)?
+            .map(|(timestamp, original_len)| Record {
+                timestamp,
+                original_len,
+                data,
+            }))
+    }
+
+    /// Read the next packet into a buffer leased from `pool`, or None at the end of the
+    /// file.
+    pub fn next_pooled(&mut self, pool: &BufferPool) -> io::Result<Option<PooledRecord>> {
+        let mut data = pool.lease();
+        Ok(self
+            .read_next(&mut data)?
+            .map(|(timestamp, original_len)| PooledRecord {
+                timestamp,
+                original_len,
+                data,
+            }))
+    }
+
+    /// Return the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Iterate over the records of an Ethernet capture that satisfy `filter`, checked in
+    /// userspace. Read errors are passed through.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn filtered(self, filter: Filter) -> impl Iterator<Item = io::Result<Record>> {
+        filter::filter_records(self, filter)
+    }
+
+    /// Read the next packet into `data`, returning its timestamp and original length.
+    fn read_next(&mut self, data: &mut Vec<u8>) -> io::Result<Option<(SystemTime, u32)>> {
+        if let Format::Pcap { nanos, snaplen } = self.format {
+            let mut header = [0u8; 16];
+            if !read_exact_or_eof(&mut self.inner, &mut header)? {
+                return Ok(None);
+            }
+            let secs = self.u32_at(&header, 0) as u64;
+            let fraction = self.u32_at(&header, 4);
+            let captured = self.u32_at(&header, 8) as usize;
+            let original_len = self.u32_at(&header, 12);
+            if captured > snaplen {
+                return Err(invalid_data("pcap packet longer than the snaplen"));
+            }
+            data.clear();
+            data.resize(captured, 0);
+            self.inner.read_exact(data)?;
+            let since_epoch = if nanos {
+                Duration::new(secs, fraction)
+            } else {
+                Duration::from_secs(secs) + Duration::from_micros(fraction as u64)
+            };
+            return Ok(Some((UNIX_EPOCH + since_epoch, original_len)));
+        }
+
+        while let Some(block_type) = self.read_block()? {
+            let body = mem::replace(&mut self.block, Vec::new());
+            let handled = self.handle_block(block_type, &body, data);
+            self.block = body;
+            if let Some(packet) = handled? {
+                return Ok(Some(packet));
+            }
+        }
+        Ok(None)
+    }
+
+    fn u16_at(&self, data: &[u8], offset: usize) -> u16 {
+        let bytes = [data[offset], data[offset + 1]];
+        if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32_at(&self, data: &[u8], offset: usize) -> u32 {
+        let bytes = [
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ];
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    /// Read the rest of a section header whose block type and length were consumed.
+    ///
+    /// The byte order magic following the length decides how the length, and everything
+    /// else in the section, is to be read.
+    fn read_section_header(&mut self, total_len: [u8; 4]) -> io::Result<()> {
+        let mut magic = [0u8; 4];
+        self.inner.read_exact(&mut magic)?;
+        self.big_endian = if u32::from_le_bytes(magic) == PCAPNG_BYTE_ORDER_MAGIC {
+            false
+        } else if u32::from_be_bytes(magic) == PCAPNG_BYTE_ORDER_MAGIC {
+            true
+        } else {
+            return Err(invalid_data("invalid pcapng byte order magic"));
+        };
+        let total_len = self.u32_at(&total_len, 0);
+        if total_len < 28 || total_len % 4 != 0 {
+            return Err(invalid_data("invalid pcapng block length"));
+        }
+        // version, section length, options and the trailing length
+        let rest = u64::from(total_len) - 12;
+        if io::copy(&mut (&mut self.inner).take(rest), &mut io::sink())? != rest {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        // interface ids are local to a section
+        self.format = Format::Pcapng {
+            interfaces: Vec::new(),
+        };
+        Ok(())
+    }
+
+    /// Read a pcapng block into `self.block`, returning its type.
+    fn read_block(&mut self) -> io::Result<Option<u32>> {
+        let mut header = [0u8; 8];
+        if !read_exact_or_eof(&mut self.inner, &mut header)? {
+            return Ok(None);
+        }
+        // the section header type reads the same in both byte orders
+        if u32::from_be_bytes([header[0], header[1], header[2], header[3]]) == PCAPNG_SECTION_HEADER
+        {
+            self.read_section_header([header[4], header[5], header[6], header[7]])?;
+            self.block.clear();
+            return Ok(Some(PCAPNG_SECTION_HEADER));
+        }
+        let block_type = self.u32_at(&header, 0);
+        let total_len = self.u32_at(&header, 4) as usize;
+        if total_len < 12 || total_len % 4 != 0 || total_len > MAX_BLOCK_LEN {
+            return Err(invalid_data("invalid pcapng block length"));
+        }
+        self.block.clear();
+        self.block.resize(total_len - 8, 0);
+        self.inner.read_exact(&mut self.block)?;
+        self.block.truncate(total_len - 12);
+        Ok(Some(block_type))
+    }
+
+    /// Interpret a pcapng block. The packet it carries, if any, is copied into `data` and
+    /// its timestamp and original length are returned.
+    fn handle_block(
+        &mut self,
+        block_type: u32,
+        body: &[u8],
+        data: &mut Vec<u8>,
+    ) -> io::Result<Option<(SystemTime, u32)>> {
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION if body.len() >= 8 => {
+                let link_type = u32::from(self.u16_at(body, 0));
+                let resolution = self.tsresol(&body[8..]);
+                self.link_type.get_or_insert(link_type);
+                if let Format::Pcapng { interfaces } = &mut self.format {
+                    interfaces.push(resolution);
+                }
+                Ok(None)
+            }
+            PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                let interface = self.u32_at(body, 0) as usize;
+                let resolution = match &self.format {
+                    Format::Pcapng { interfaces } => interfaces.get(interface).copied(),
+                    Format::Pcap { .. } => None,
+                }
+                .ok_or_else(|| invalid_data("packet of an undescribed interface"))?;
+                let ticks = u64::from(self.u32_at(body, 4)) << 32 | u64::from(self.u32_at(body, 8));
+                let captured = self.u32_at(body, 12) as usize;
+                let original_len = self.u32_at(body, 16);
+                let captured = body
+                    .get(20..20 + captured)
+                    .ok_or_else(|| invalid_data("truncated pcapng packet block"))?;
+                let nanos = u128::from(ticks % resolution) * 1_000_000_000 / u128::from(resolution);
+                let since_epoch =
+                    Duration::from_secs(ticks / resolution) + Duration::from_nanos(nanos as u64);
+                data.clear();
+                data.extend_from_slice(captured);
+                Ok(Some((UNIX_EPOCH + since_epoch, original_len)))
+            }
+            PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                // simple packets carry no timestamp
+                let original_len = self.u32_at(body, 0);
+                let captured = (original_len as usize).min(body.len() - 4);
+                data.clear();
+                data.extend_from_slice(&body[4..4 + captured]);
+                Ok(Some((UNIX_EPOCH, original_len)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Timestamp units per second from the options of an interface description.
+    fn tsresol(&self, mut options: &[u8]) -> u64 {
+        while options.len() >= 4 {
+            let code = self.u16_at(options, 0);
+            let len = self.u16_at(options, 2) as usize;
+            if code == PCAPNG_OPTION_TSRESOL && len == 1 && options.len() > 4 {
+                let exponent = u32::from(options[4] & 0x7f);
+                let base: u64 = if options[4] & 0x80 == 0 { 10 } else { 2 };
+                return match base.checked_pow(exponent) {
+                    Some(0) | None => 1_000_000,
+                    Some(resolution) => resolution,
+                };
+            }
+            // option values are padded to 32 bits
+            let advance = 4 + (len + 3) / 4 * 4;
+            options = options.get(advance..).unwrap_or(&[]);
+        }
+        1_000_000
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        self.next_record().transpose()
+    }
+}
+
+/// A receiver yielding the frames of an Ethernet capture, with their capture timestamp and
+/// original length as metadata. Reads fail with `UnexpectedEof` after the last frame.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct OfflineReceiver<R: Read> {
+    reader: Reader<R>,
+    read_buffer: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R: Read> OfflineReceiver<R> {
+    /// Read the frames of `reader`, failing with `InvalidData` if it isn't an Ethernet
+    /// capture.
+    pub fn new(reader: Reader<R>) -> io::Result<OfflineReceiver<R>> {
+        if reader.link_type() != LINKTYPE_ETHERNET {
+            return Err(invalid_data("not an Ethernet capture"));
+        }
+        Ok(OfflineReceiver {
+            reader,
+            read_buffer: Vec::new(),
+        })
+    }
+
+    /// Return the capture reader.
+    pub fn into_inner(self) -> Reader<R> {
+        self.reader
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OfflineReceiver<io::BufReader<File>> {
+    /// Read the frames of the capture file at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<OfflineReceiver<io::BufReader<File>>> {
+        OfflineReceiver::new(Reader::new(io::BufReader::new(File::open(path)?))?)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R: Read + Send> EthernetDataLinkReceiver for OfflineReceiver<R> {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(OfflineChannelIterator { pc: self })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct OfflineChannelIterator<'a, R: Read> {
+    pc: &'a mut OfflineReceiver<R>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a, R: Read> OfflineChannelIterator<'a, R> {
+    // the next frame that holds an Ethernet header, with its timestamp and original length
+    fn read(&mut self) -> io::Result<(SystemTime, u32)> {
+        loop {
+            let (timestamp, original_len) = self
+                .pc
+                .reader
+                .read_next(&mut self.pc.read_buffer)?
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            // records truncated below the header can't be handed out
+            if EthernetPacket::new(&self.pc.read_buffer[..]).is_some() {
+                return Ok((timestamp, original_len));
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a, R: Read> EthernetDataLinkChannelIterator<'a> for OfflineChannelIterator<'a, R> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        self.read()?;
+        Ok(EthernetPacket::new(&self.pc.read_buffer[..]).unwrap())
+    }
+
+    fn next_with_meta(&mut self) -> io::Result<(EthernetPacket, RxMeta)> {
+        let (timestamp, original_len) = self.read()?;
+        let packet = EthernetPacket::new(&self.pc.read_buffer[..]).unwrap();
+        let meta = RxMeta {
+            timestamp,
+            original_len: original_len as usize,
+            ..RxMeta::from_frame(&packet)
+        };
+        Ok((packet, meta))
+    }
+}
+
+/// A capture file standing in for an interface, for code that opens its own channels.
+///
+/// Every channel opened reads the file from its start with an [`OfflineReceiver`], and
+/// drops the frames sent on it.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Debug)]
+pub struct OfflineBackend {
+    path: PathBuf,
+    interface: NetworkInterface,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OfflineBackend {
+    /// Replay the capture file at `path`, as an interface named after it.
+    pub fn new<P: AsRef<Path>>(path: P) -> OfflineBackend {
+        let path = path.as_ref().to_owned();
+        OfflineBackend {
+            interface: NetworkInterface {
+                name: path.display().to_string(),
+                index: 0,
+                mac: Some(MacAddr::new(0, 0, 0, 0, 0, 0)),
+                ips: None,
+                flags: FLAG_UP,
+                mtu: None,
+                oper_state: OperState::Up,
+                link_type: LinkType::ETHER,
+                addresses: Vec::new(),
+            },
+            path,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DatalinkBackend for OfflineBackend {
+    /// Open a channel reading the file, whatever `network_interface` is. Only Layer2
+    /// channels are supported, and the timeouts of `config` don't apply.
+    fn channel(
+        &self,
+        _network_interface: &NetworkInterface,
+        config: Config,
+    ) -> io::Result<Channel> {
+        if config.channel_type != ChannelType::Layer2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only Layer2 channels are supported by capture files",
+            ));
+        }
+        let receiver = OfflineReceiver::open(&self.path)?;
+        Ok(Channel::Ethernet(
+            Box::new(DiscardSender),
+            Box::new(receiver),
+        ))
+    }
+
+    /// The file alone.
+    fn interfaces(&self) -> io::Result<Vec<NetworkInterface>> {
+        Ok(vec![self.interface.clone()])
+    }
+}
+
+// frames sent on a capture file go nowhere
+#[cfg(not(target_arch = "wasm32"))]
+struct DiscardSender;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl EthernetDataLinkSender for DiscardSender {
+    fn send_to(
+        &mut self,
+        _packet: &EthernetPacket,
+        _dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        Some(Ok(()))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Fill `buf`, returning false if the reader is at its end before the first byte.
+fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arp::ether::Packet;
+
+    // a capture of `frames` in a fresh file under the temporary directory
+    fn capture(name: &str, frames: &[&[u8]]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("myox-{}-{}.pcap", name, std::process::id()));
+        let mut writer = Writer::new(File::create(&path).unwrap()).unwrap();
+        for frame in frames {
+            writer.write_packet(SystemTime::now(), frame).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn offline_backend_replays_the_file() {
+        let frame = [0xffu8; 60];
+        let path = capture("backend", &[&frame, &frame[..14]]);
+        let backend = OfflineBackend::new(&path);
+        let interface = backend.interfaces().unwrap().remove(0);
+        for _ in 0..2 {
+            let mut rx = match backend.channel(&interface, Config::default()).unwrap() {
+                Channel::Ethernet(_, rx) => rx,
+                _ => unreachable!(),
+            };
+            let mut iter = rx.iter();
+            assert_eq!(iter.next().unwrap().packet().len(), 60);
+            assert_eq!(iter.next().unwrap().packet().len(), 14);
+            let end = iter.next().map(|_| ()).unwrap_err();
+            assert_eq!(end.kind(), io::ErrorKind::UnexpectedEof);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn lengths_are_bounded() {
+        let mut file = Vec::new();
+        let mut writer = Writer::with_snaplen(&mut file, 128).unwrap();
+        writer.write_packet(UNIX_EPOCH, &[0u8; 64]).unwrap();
+        // a record header claiming 4 GiB
+        file.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0]);
+        let mut reader = Reader::new(&file[..]).unwrap();
+        assert_eq!(reader.next_record().unwrap().unwrap().data.len(), 64);
+        let e = reader.next_record().map(|_| ()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        // a section header, then a block claiming 4 GiB
+        let mut file = PCAPNG_SECTION_HEADER.to_be_bytes().to_vec();
+        file.extend_from_slice(&28u32.to_le_bytes());
+        file.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        file.extend_from_slice(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+        file.extend_from_slice(&28u32.to_le_bytes());
+        file.extend_from_slice(&PCAPNG_INTERFACE_DESCRIPTION.to_le_bytes());
+        file.extend_from_slice(&0xffff_fffcu32.to_le_bytes());
+        let e = Reader::new(&file[..]).map(|_| ()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,861 @@
+//! Throughput measurement between two hosts, in the spirit of iperf.
+//!
+//! The client sends sequenced, timestamped test frames straight over Ethernet for a fixed
+//! time and then asks the server for its view of the run. The server counts what arrives
+//! and estimates the interarrival jitter the way RTP does (RFC 3550, 6.4.1). Frames use
+//! the IEEE local experimental ethertype, so no address configuration is needed and the
+//! test traffic is easy to filter out.
+//!
+//! The same test runs over UDP and TCP against a server on the userspace stack of
+//! [`tcp::stack`](crate::tcp::stack), with [`run_udp_client`] and [`run_tcp_client`]
+//! connecting from the system's sockets. Over UDP the test messages are those of the
+//! Ethernet test, without the Ethernet header; over TCP the client writes for the length
+//! of the test and shuts down its side, and the server answers with its report.
+
+use crate::{
+    arp::{
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EtherType, EthernetPacket, MutableEthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    metrics::{Histogram, Summary},
+    tcp::stack::{Interest, Interface, TcpStream},
+};
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io::{self, Read, Write},
+    net::{self, Shutdown, SocketAddr, SocketAddrV4},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Ethertype of test frames (IEEE 802 local experimental ethertype 1).
+pub const ETHERTYPE: EtherType = EtherType::Unknown(0x88b5);
+
+/// Smallest test frame, the minimum Ethernet frame size without FCS.
+pub const MIN_FRAME_SIZE: usize = 60;
+
+/// Smallest UDP test datagram: kind, session, sequence number and timestamp.
+pub const MIN_DATAGRAM_SIZE: usize = MESSAGE_HEADER_LEN + 16;
+
+/// Largest UDP test datagram, the payload of an unfragmented packet on Ethernet.
+pub const MAX_DATAGRAM_SIZE: usize = 1500 - 20 - 8;
+
+/// Most tests a server keeps track of at once. Tests starting while it is full are
+/// ignored.
+pub const MAX_SESSIONS: usize = 64;
+
+/// Time after which a server forgets a test it hasn't heard from.
+pub const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+const KIND_DATA: u8 = 1;
+const KIND_DONE: u8 = 2;
+const KIND_REPORT: u8 = 3;
+
+/// Offset of the kind specific fields in a test message: kind and session.
+const MESSAGE_HEADER_LEN: usize = 5;
+/// Offset of the kind specific fields in a test frame.
+const HEADER_LEN: usize = 14 + MESSAGE_HEADER_LEN;
+const REPORT_FIELDS: usize = 12;
+
+/// Interval at which the client repeats its end-of-test message until the server answers.
+const DONE_INTERVAL: Duration = Duration::from_millis(100);
+const DONE_ATTEMPTS: usize = 10;
+
+/// Time the socket servers wait for traffic before checking `stop`.
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Time a TCP client waits to connect, and for the report after its last write.
+const TCP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Options of a test run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// MAC address of the server
+    pub server: MacAddr,
+    /// Length of the test. Defaults to 10 seconds
+    pub duration: Duration,
+    /// Size of every test frame, Ethernet header included. Defaults to 1514
+    pub frame_size: usize,
+    /// Sending rate in bits per second, None for as fast as possible. Defaults to None
+    pub rate: Option<u64>,
+}
+
+impl ClientConfig {
+    /// Options for a test against `server` with the default length, frame size and rate.
+    pub fn new(server: MacAddr) -> ClientConfig {
+        ClientConfig {
+            server,
+            duration: Duration::from_secs(10),
+            frame_size: 1514,
+            rate: None,
+        }
+    }
+}
+
+/// Options of a test run over UDP or TCP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SocketConfig {
+    /// Address of the server
+    pub server: SocketAddr,
+    /// Length of the test. Defaults to 10 seconds
+    pub duration: Duration,
+    /// Size of every datagram, or of every write over TCP. Defaults to 1400
+    pub size: usize,
+    /// Sending rate in bits per second, None for as fast as possible. Defaults to None
+    pub rate: Option<u64>,
+}
+
+impl SocketConfig {
+    /// Options for a test against `server` with the default length, size and rate.
+    pub fn new(server: SocketAddr) -> SocketConfig {
+        SocketConfig {
+            server,
+            duration: Duration::from_secs(10),
+            size: 1400,
+            rate: None,
+        }
+    }
+}
+
+/// Outcome of a test run as seen by the server.
+///
+/// Over TCP only `bytes` and `duration` are measured, the rest stays zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Test frames or datagrams sent by the client
+    pub sent: u64,
+    /// Test frames or datagrams received by the server
+    pub received: u64,
+    /// Ethernet or UDP payload bytes received, or bytes read from the TCP stream
+    pub bytes: u64,
+    /// Time between the first and the last received frame
+    pub duration: Duration,
+    /// Interarrival jitter
+    pub jitter: Duration,
+    /// Frames that arrived after a frame sent later
+    pub out_of_order: u64,
+    /// Distribution of the interarrival delay variation, the difference in transit time
+    /// of consecutive frames (RFC 5481 IPDV)
+    pub ipdv: Summary,
+}
+
+impl Report {
+    /// Frames sent but never received.
+    pub fn lost(&self) -> u64 {
+        self.sent.saturating_sub(self.received)
+    }
+
+    /// Received payload rate in bits per second.
+    pub fn goodput(&self) -> f64 {
+        match self.duration.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes as f64 * 8.0 / secs,
+            _ => 0.0,
+        }
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.sent == 0 && self.received == 0 {
+            // a test over TCP, which counts no messages
+            return write!(
+                f,
+                "{:.3} s  {} bytes  {:.2} Mbit/s",
+                self.duration.as_secs_f64(),
+                self.bytes,
+                self.goodput() / 1e6
+            );
+        }
+        let loss = match self.sent {
+            0 => 0.0,
+            sent => self.lost() as f64 * 100.0 / sent as f64,
+        };
+        write!(
+            f,
+            "{:.3} s  {} bytes  {:.2} Mbit/s  jitter {:.3} ms  lost {}/{} ({:.2}%)  out of order {}  ipdv {}",
+            self.duration.as_secs_f64(),
+            self.bytes,
+            self.goodput() / 1e6,
+            self.jitter.as_secs_f64() * 1e3,
+            self.lost(),
+            self.sent,
+            loss,
+            self.out_of_order,
+            self.ipdv
+        )
+    }
+}
+
+/// Run a test against a server and return its report.
+///
+/// `rx` should be opened with a short `read_timeout`, which bounds how late the end of the
+/// test and `stop` are noticed.
+pub fn run_client(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    local: MacAddr,
+    config: &ClientConfig,
+    stop: &AtomicBool,
+) -> io::Result<Report> {
+    if config.frame_size < MIN_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("frame size must be at least {}", MIN_FRAME_SIZE),
+        ));
+    }
+    let session = session_id();
+    let interval = interval(config.frame_size, config.rate);
+
+    let mut frame = vec![0u8; config.frame_size];
+    write_header(&mut frame, config.server, local, KIND_DATA, session);
+
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+    while start.elapsed() < config.duration && !stop.load(Ordering::SeqCst) {
+        pace(start, interval, sent);
+        write_data(&mut frame[HEADER_LEN..], sent, start);
+        send(tx, &frame)?;
+        sent += 1;
+    }
+
+    let mut done = vec![0u8; MIN_FRAME_SIZE];
+    write_header(&mut done, config.server, local, KIND_DONE, session);
+    done[HEADER_LEN..HEADER_LEN + 8].copy_from_slice(&sent.to_be_bytes());
+
+    let mut iter = rx.iter();
+    for _ in 0..DONE_ATTEMPTS {
+        send(tx, &done)?;
+        let asked = Instant::now();
+        while asked.elapsed() < DONE_INTERVAL {
+            let packet = match iter.next() {
+                Ok(packet) => packet,
+                Err(ref e) if is_timeout(e) => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some((KIND_REPORT, id, from, payload)) = parse(&packet) {
+                if id == session && from == config.server {
+                    return Ok(decode_report(payload));
+                }
+            }
+        }
+    }
+    Err(no_report())
+}
+
+/// Run a test over UDP against a server and return its report.
+pub fn run_udp_client(config: &SocketConfig, stop: &AtomicBool) -> io::Result<Report> {
+    if config.size < MIN_DATAGRAM_SIZE || config.size > MAX_DATAGRAM_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "datagram size must be between {} and {}",
+                MIN_DATAGRAM_SIZE, MAX_DATAGRAM_SIZE
+            ),
+        ));
+    }
+    let local: SocketAddr = match config.server {
+        SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+        SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+    };
+    let socket = net::UdpSocket::bind(local)?;
+    socket.connect(config.server)?;
+    socket.set_read_timeout(Some(DONE_INTERVAL))?;
+    let session = session_id();
+    let interval = interval(config.size, config.rate);
+
+    let mut datagram = vec![0u8; config.size];
+    write_message_header(&mut datagram, KIND_DATA, session);
+
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+    while start.elapsed() < config.duration && !stop.load(Ordering::SeqCst) {
+        pace(start, interval, sent);
+        write_data(&mut datagram[MESSAGE_HEADER_LEN..], sent, start);
+        socket.send(&datagram)?;
+        sent += 1;
+    }
+
+    let mut done = [0u8; MESSAGE_HEADER_LEN + 8];
+    write_message_header(&mut done, KIND_DONE, session);
+    done[MESSAGE_HEADER_LEN..].copy_from_slice(&sent.to_be_bytes());
+
+    let mut buf = [0u8; MESSAGE_HEADER_LEN + REPORT_FIELDS * 8];
+    for _ in 0..DONE_ATTEMPTS {
+        socket.send(&done)?;
+        let asked = Instant::now();
+        while asked.elapsed() < DONE_INTERVAL {
+            let len = match socket.recv(&mut buf) {
+                Ok(len) => len,
+                Err(ref e) if is_timeout(e) => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some((KIND_REPORT, id, payload)) = parse_message(&buf[..len]) {
+                if id == session {
+                    return Ok(decode_report(payload));
+                }
+            }
+        }
+    }
+    Err(no_report())
+}
+
+/// Run a test over TCP against a server and return its report.
+///
+/// The client writes `size` bytes at a time for the length of the test, then shuts down
+/// its side of the connection and reads the server's report.
+pub fn run_tcp_client(config: &SocketConfig, stop: &AtomicBool) -> io::Result<Report> {
+    if config.size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "write size must not be zero",
+        ));
+    }
+    let mut stream = net::TcpStream::connect_timeout(&config.server, TCP_TIMEOUT)?;
+    stream.set_write_timeout(Some(TCP_TIMEOUT))?;
+    let interval = interval(config.size, config.rate);
+    let data = vec![0u8; config.size];
+
+    let start = Instant::now();
+    let mut written: u64 = 0;
+    while start.elapsed() < config.duration && !stop.load(Ordering::SeqCst) {
+        pace(start, interval, written);
+        stream.write_all(&data)?;
+        written += 1;
+    }
+    stream.shutdown(Shutdown::Write)?;
+
+    stream.set_read_timeout(Some(TCP_TIMEOUT))?;
+    let mut report = [0u8; REPORT_FIELDS * 8];
+    stream.read_exact(&mut report).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => no_report(),
+        _ => e,
+    })?;
+    Ok(decode_report(&report))
+}
+
+#[derive(Default)]
+struct Session {
+    report: Report,
+    done: bool,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    next_seq: u64,
+    // previous difference between arrival and send time, in nanoseconds
+    transit: Option<i128>,
+    jitter: f64,
+    ipdv: Histogram,
+}
+
+impl Session {
+    /// Count test message `seq` of `bytes` bytes, sent `sent_at` nanoseconds into the test.
+    fn receive(&mut self, seq: u64, sent_at: u64, bytes: usize, arrived: Instant) {
+        let first = *self.first.get_or_insert(arrived);
+        self.last = Some(arrived);
+
+        self.report.received += 1;
+        self.report.bytes += bytes as u64;
+        if seq < self.next_seq {
+            self.report.out_of_order += 1;
+        } else {
+            self.next_seq = seq + 1;
+        }
+
+        let transit = (arrived - first).as_nanos() as i128 - sent_at as i128;
+        if let Some(previous) = self.transit {
+            let d = (transit - previous).abs();
+            self.jitter += (d as f64 - self.jitter) / 16.0;
+            self.ipdv.record(Duration::from_nanos(d as u64));
+        }
+        self.transit = Some(transit);
+    }
+
+    /// Complete the report of a test the client sent `sent` messages in. Returns whether
+    /// this is the first end-of-test message, as repeated ones are answered again without
+    /// reporting twice.
+    fn finish(&mut self, sent: u64) -> bool {
+        let first_done = !self.done;
+        self.done = true;
+        self.report.sent = sent;
+        if let (Some(first), Some(last)) = (self.first, self.last) {
+            self.report.duration = last - first;
+        }
+        self.report.jitter = Duration::from_nanos(self.jitter as u64);
+        self.report.ipdv = self.ipdv.summary();
+        first_done
+    }
+}
+
+/// The tests a server keeps track of, by client and session, forgotten after
+/// [`SESSION_TIMEOUT`] without traffic.
+struct Sessions<K> {
+    sessions: HashMap<K, (Session, Instant)>,
+    expired: Instant,
+}
+
+impl<K: Eq + Hash> Sessions<K> {
+    fn new(now: Instant) -> Sessions<K> {
+        Sessions {
+            sessions: HashMap::new(),
+            expired: now,
+        }
+    }
+
+    /// The session `key`, started if there is room for it.
+    fn get(&mut self, key: K, now: Instant) -> Option<&mut Session> {
+        // sweeping more often than this makes no difference to a 30 second timeout
+        if now.saturating_duration_since(self.expired) >= Duration::from_secs(1) {
+            self.sessions
+                .retain(|_, (_, seen)| now.saturating_duration_since(*seen) < SESSION_TIMEOUT);
+            self.expired = now;
+        }
+        if !self.sessions.contains_key(&key) && self.sessions.len() >= MAX_SESSIONS {
+            return None;
+        }
+        let (session, seen) = self
+            .sessions
+            .entry(key)
+            .or_insert_with(|| (Session::default(), now));
+        *seen = now;
+        Some(session)
+    }
+}
+
+/// Answer clients until `stop` is set, calling `on_report` whenever a test completes.
+///
+/// `local` is the server's MAC address; test frames sent to other addresses are ignored.
+/// `rx` should be opened with a short `read_timeout` so that `stop` is noticed.
+pub fn serve<F>(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    local: MacAddr,
+    stop: &AtomicBool,
+    mut on_report: F,
+) -> io::Result<()>
+where
+    F: FnMut(MacAddr, &Report),
+{
+    let mut sessions = Sessions::new(Instant::now());
+    let mut iter = rx.iter();
+    while !stop.load(Ordering::SeqCst) {
+        let packet = match iter.next() {
+            Ok(packet) => packet,
+            Err(ref e) if is_timeout(e) => continue,
+            Err(e) => return Err(e),
+        };
+        let arrived = Instant::now();
+        if packet.get_destination() != local {
+            continue;
+        }
+        let (kind, session_id, from, payload) = match parse(&packet) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        match kind {
+            KIND_DATA if payload.len() >= 16 => {
+                if let Some(session) = sessions.get((from, session_id), arrived) {
+                    let seq = read_u64(&payload[0..8]);
+                    let sent_at = read_u64(&payload[8..16]);
+                    session.receive(seq, sent_at, packet.payload().len(), arrived);
+                }
+            }
+            KIND_DONE if payload.len() >= 8 => {
+                let session = match sessions.get((from, session_id), arrived) {
+                    Some(session) => session,
+                    None => continue,
+                };
+                let first_done = session.finish(read_u64(&payload[0..8]));
+
+                let mut frame = vec![0u8; HEADER_LEN + REPORT_FIELDS * 8];
+                write_header(&mut frame, from, local, KIND_REPORT, session_id);
+                encode_report(&session.report, &mut frame[HEADER_LEN..]);
+                send(tx, &frame)?;
+
+                if first_done {
+                    on_report(from, &session.report);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Answer UDP clients on `local` of `interface` until `stop` is set, calling `on_report`
+/// whenever a test completes.
+pub fn serve_udp<F>(
+    interface: &Interface,
+    local: SocketAddrV4,
+    stop: &AtomicBool,
+    mut on_report: F,
+) -> io::Result<()>
+where
+    F: FnMut(SocketAddrV4, &Report),
+{
+    let socket = interface.bind_udp(local)?;
+    let mut poller = interface.poller();
+    poller.register(&socket, 0, Interest::READABLE)?;
+    let mut events = Vec::new();
+    let mut sessions = Sessions::new(Instant::now());
+    let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+    while !stop.load(Ordering::SeqCst) {
+        poller.poll(&mut events, Some(POLL_TIMEOUT))?;
+        if events.is_empty() {
+            continue;
+        }
+        let (len, from) = socket.recv_from(&mut buf)?;
+        let arrived = Instant::now();
+        let (kind, session_id, payload) = match parse_message(&buf[..len]) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        match kind {
+            KIND_DATA if payload.len() >= 16 => {
+                if let Some(session) = sessions.get((from, session_id), arrived) {
+                    let seq = read_u64(&payload[0..8]);
+                    let sent_at = read_u64(&payload[8..16]);
+                    session.receive(seq, sent_at, len, arrived);
+                }
+            }
+            KIND_DONE if payload.len() >= 8 => {
+                let session = match sessions.get((from, session_id), arrived) {
+                    Some(session) => session,
+                    None => continue,
+                };
+                let first_done = session.finish(read_u64(&payload[0..8]));
+
+                let mut reply = [0u8; MESSAGE_HEADER_LEN + REPORT_FIELDS * 8];
+                write_message_header(&mut reply, KIND_REPORT, session_id);
+                encode_report(&session.report, &mut reply[MESSAGE_HEADER_LEN..]);
+                socket.send_to(&reply, from)?;
+
+                if first_done {
+                    on_report(from, &session.report);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// a test over TCP in progress
+struct Stream {
+    stream: TcpStream,
+    report: Report,
+    first: Option<Instant>,
+    last: Instant,
+}
+
+/// Answer TCP clients on `port` of `interface` until `stop` is set, calling `on_report`
+/// whenever a test completes.
+///
+/// Up to [`MAX_SESSIONS`] tests run at once, further connections are closed right away.
+pub fn serve_tcp<F>(
+    interface: &Interface,
+    port: u16,
+    stop: &AtomicBool,
+    mut on_report: F,
+) -> io::Result<()>
+where
+    F: FnMut(SocketAddrV4, &Report),
+{
+    const LISTENER: usize = 0;
+
+    let listener = interface.bind(port)?;
+    let mut poller = interface.poller();
+    poller.register(&listener, LISTENER, Interest::READABLE)?;
+    let mut events = Vec::new();
+    let mut streams: HashMap<usize, Stream> = HashMap::new();
+    let mut next_token = LISTENER + 1;
+    let mut buf = vec![0u8; 64 * 1024];
+    while !stop.load(Ordering::SeqCst) {
+        poller.poll(&mut events, Some(POLL_TIMEOUT))?;
+        let now = Instant::now();
+        for event in &events {
+            if event.token == LISTENER {
+                let (stream, _) = listener.accept()?;
+                if streams.len() < MAX_SESSIONS {
+                    poller.register(&stream, next_token, Interest::READABLE)?;
+                    streams.insert(
+                        next_token,
+                        Stream {
+                            stream,
+                            report: Report::default(),
+                            first: None,
+                            last: now,
+                        },
+                    );
+                    next_token += 1;
+                }
+                continue;
+            }
+            let test = match streams.get_mut(&event.token) {
+                Some(test) => test,
+                None => continue,
+            };
+            match test.stream.read(&mut buf) {
+                Ok(0) => {
+                    // the client is done writing and waits for the report
+                    let mut test = streams.remove(&event.token).unwrap();
+                    poller.deregister(event.token);
+                    if let Some(first) = test.first {
+                        test.report.duration = test.last - first;
+                    }
+                    let mut report = [0u8; REPORT_FIELDS * 8];
+                    encode_report(&test.report, &mut report);
+                    if test.stream.write_all(&report).is_ok() {
+                        on_report(test.stream.peer_addr(), &test.report);
+                    }
+                }
+                Ok(read) => {
+                    test.first.get_or_insert(now);
+                    test.last = now;
+                    test.report.bytes += read as u64;
+                }
+                Err(_) => {
+                    streams.remove(&event.token);
+                    poller.deregister(event.token);
+                }
+            }
+        }
+        streams.retain(|&token, test| {
+            let idle = now.saturating_duration_since(test.last) >= SESSION_TIMEOUT;
+            if idle {
+                poller.deregister(token);
+            }
+            !idle
+        });
+    }
+    Ok(())
+}
+
+/// A test session id, random enough to tell apart the tests of one client.
+fn session_id() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos()
+}
+
+/// Time between messages of `size` bytes to send at `rate` bits per second.
+fn interval(size: usize, rate: Option<u64>) -> Option<Duration> {
+    rate.filter(|rate| *rate > 0)
+        .map(|rate| Duration::from_secs_f64(size as f64 * 8.0 / rate as f64))
+}
+
+/// Wait until message `sent` of a test started at `start` is due.
+fn pace(start: Instant, interval: Option<Duration>, sent: u64) {
+    if let Some(interval) = interval {
+        let due = start + interval.mul_f64(sent as f64);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+/// Fill in the sequence number and timestamp of a data message.
+fn write_data(fields: &mut [u8], seq: u64, start: Instant) {
+    fields[0..8].copy_from_slice(&seq.to_be_bytes());
+    let elapsed = start.elapsed().as_nanos() as u64;
+    fields[8..16].copy_from_slice(&elapsed.to_be_bytes());
+}
+
+fn no_report() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "no report from the server")
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    )
+}
+
+fn send(tx: &mut dyn EthernetDataLinkSender, frame: &[u8]) -> io::Result<()> {
+    let packet = EthernetPacket::new(frame).unwrap();
+    tx.send_to(&packet, None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+}
+
+/// Fill in the Ethernet header and the kind and session fields of a test frame.
+fn write_header(frame: &mut [u8], dst: MacAddr, src: MacAddr, kind: u8, session: u32) {
+    let mut ethernet = MutableEthernetPacket::new(frame).unwrap();
+    ethernet.set_destination(dst);
+    ethernet.set_source(src);
+    ethernet.set_ethertype(ETHERTYPE);
+    write_message_header(&mut frame[14..], kind, session);
+}
+
+/// Fill in the kind and session fields of a test message.
+fn write_message_header(message: &mut [u8], kind: u8, session: u32) {
+    message[0] = kind;
+    message[1..MESSAGE_HEADER_LEN].copy_from_slice(&session.to_be_bytes());
+}
+
+/// Split a test frame into its kind, session, sender and kind specific payload.
+fn parse<'p>(packet: &'p EthernetPacket) -> Option<(u8, u32, MacAddr, &'p [u8])> {
+    if packet.get_ethertype() != ETHERTYPE {
+        return None;
+    }
+    let (kind, session, payload) = parse_message(packet.payload())?;
+    Some((kind, session, packet.get_source(), payload))
+}
+
+/// Split a test message into its kind, session and kind specific payload.
+fn parse_message(message: &[u8]) -> Option<(u8, u32, &[u8])> {
+    if message.len() < MESSAGE_HEADER_LEN {
+        return None;
+    }
+    let session = u32::from_be_bytes([message[1], message[2], message[3], message[4]]);
+    Some((message[0], session, &message[MESSAGE_HEADER_LEN..]))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(value)
+}
+
+fn encode_report(report: &Report, out: &mut [u8]) {
+    let fields: [u64; REPORT_FIELDS] = [
+        report.sent,
+        report.received,
+        report.bytes,
+        report.duration.as_nanos() as u64,
+        report.jitter.as_nanos() as u64,
+        report.out_of_order,
+        report.ipdv.count,
+        report.ipdv.min.as_nanos() as u64,
+        report.ipdv.p50.as_nanos() as u64,
+        report.ipdv.p95.as_nanos() as u64,
+        report.ipdv.p99.as_nanos() as u64,
+        report.ipdv.max.as_nanos() as u64,
+    ];
+    for (chunk, value) in out.chunks_mut(8).zip(fields.iter()) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn decode_report(payload: &[u8]) -> Report {
+    let field = |i: usize| payload.get(i * 8..i * 8 + 8).map_or(0, read_u64);
+    Report {
+        sent: field(0),
+        received: field(1),
+        bytes: field(2),
+        duration: Duration::from_nanos(field(3)),
+        jitter: Duration::from_nanos(field(4)),
+        out_of_order: field(5),
+        ipdv: Summary {
+            count: field(6),
+            min: Duration::from_nanos(field(7)),
+            p50: Duration::from_nanos(field(8)),
+            p95: Duration::from_nanos(field(9)),
+            p99: Duration::from_nanos(field(10)),
+            max: Duration::from_nanos(field(11)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::{
+            channel::{Channel, Config},
+            loopback::Loopback,
+        },
+        generate,
+    };
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn sessions_are_bounded_and_expire() {
+        let start = Instant::now();
+        let mut sessions = Sessions::new(start);
+        for client in 0..MAX_SESSIONS {
+            assert!(sessions.get(client, start).is_some());
+        }
+        assert!(sessions.get(MAX_SESSIONS, start).is_none());
+        // known sessions go on while the table is full
+        let later = start + SESSION_TIMEOUT / 2;
+        assert!(sessions.get(0, later).is_some());
+
+        let expired = start + SESSION_TIMEOUT;
+        assert!(sessions.get(MAX_SESSIONS, expired).is_some());
+        assert_eq!(sessions.sessions.len(), 2);
+    }
+
+    #[test]
+    fn serves_udp_tests() {
+        let server = (MacAddr(2, 0, 0, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 1));
+        let client = (MacAddr(2, 0, 0, 0, 0, 2), Ipv4Addr::new(10, 0, 0, 2));
+        let device = Loopback::new().with_address(server.0, vec![IpAddr::V4(server.1)]);
+        let (mut tx, mut rx) = match device.channel(Config {
+            read_timeout: Some(Duration::from_secs(2)),
+            ..Default::default()
+        }) {
+            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let interface = Interface::open_with_backend(&device, device.interface()).unwrap();
+        let local = SocketAddrV4::new(server.1, 5201);
+        let from = SocketAddrV4::new(client.1, 40000);
+        let stop = AtomicBool::new(false);
+
+        let report = thread::scope(|scope| {
+            let serving = scope.spawn(|| {
+                let mut reports = Vec::new();
+                serve_udp(&interface, local, &stop, |client, report| {
+                    reports.push((client, *report))
+                })
+                .map(|()| reports)
+            });
+
+            let mut send = |message: &[u8]| {
+                let frame = generate::udp_frame(client.0, server.0, from, local, message);
+                tx.send_to(&EthernetPacket::new(&frame).unwrap(), None);
+            };
+            // the server binds its socket first
+            thread::sleep(Duration::from_millis(50));
+            let mut data = [0u8; MIN_DATAGRAM_SIZE + 11];
+            write_message_header(&mut data, KIND_DATA, 7);
+            // 2 is lost, 1 arrives after 3
+            for &seq in &[0u64, 3, 1] {
+                data[MESSAGE_HEADER_LEN..MESSAGE_HEADER_LEN + 8]
+                    .copy_from_slice(&seq.to_be_bytes());
+                send(&data);
+            }
+            let mut done = [0u8; MESSAGE_HEADER_LEN + 8];
+            write_message_header(&mut done, KIND_DONE, 7);
+            done[MESSAGE_HEADER_LEN..].copy_from_slice(&4u64.to_be_bytes());
+
+            let mut iter = rx.iter();
+            let report = loop {
+                send(&done);
+                let frame = iter.next().unwrap();
+                if frame.get_source() != server.0 || frame.payload().len() < 28 {
+                    continue;
+                }
+                // past the IPv4 and UDP headers
+                match parse_message(&frame.payload()[28..]) {
+                    Some((KIND_REPORT, 7, payload)) => break decode_report(payload),
+                    _ => continue,
+                }
+            };
+            stop.store(true, Ordering::SeqCst);
+            assert_eq!(serving.join().unwrap().unwrap(), [(from, report)]);
+            report
+        });
+        assert_eq!(report.sent, 4);
+        assert_eq!(report.received, 3);
+        assert_eq!(report.lost(), 1);
+        assert_eq!(report.out_of_order, 1);
+        assert_eq!(report.bytes, 3 * (MIN_DATAGRAM_SIZE as u64 + 11));
+    }
+}
@@ -0,0 +1,517 @@
+//! ICMP echo probes: ping and traceroute.
+//!
+//! [`ping`] sends echo requests to a host and times its replies. [`trace`] sends them with
+//! growing TTLs, listing the routers that answer with Time Exceeded on the way, until the
+//! host itself replies.
+
+use crate::{
+    arp::{
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, Packet},
+    },
+    checksum,
+    icmp::{self, IcmpTypes},
+    ipproto::IPPROTO_ICMP,
+    ipv4::{IpNextHeaderProtocols, Ipv4Packet},
+    metrics::Histogram,
+    scan::ports::SynTarget,
+    ttl::{self, DEFAULT_TTL},
+};
+use std::{
+    fmt, io,
+    net::Ipv4Addr,
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Parameters of [`ping`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Number of requests, None to send until stopped. Defaults to None
+    pub count: Option<u64>,
+
+    /// Time between requests. Defaults to 1 s
+    pub interval: Duration,
+
+    /// Time to wait for the reply to a request. Defaults to 1 s
+    pub timeout: Duration,
+
+    /// Bytes of data carried by each request. Defaults to 56
+    pub payload_len: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            count: None,
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(1),
+            payload_len: 56,
+        }
+    }
+}
+
+/// Parameters of [`trace`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceConfig {
+    /// Largest TTL probed with. Defaults to 30
+    pub max_hops: u8,
+
+    /// Time to wait for an answer to each probe. Defaults to 1 s
+    pub timeout: Duration,
+}
+
+impl Default for TraceConfig {
+    fn default() -> TraceConfig {
+        TraceConfig {
+            max_hops: 30,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// An echo reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Reply {
+    /// Host that replied
+    pub from: Ipv4Addr,
+    /// Sequence number of the request
+    pub sequence: u16,
+    /// TTL the reply arrived with
+    pub ttl: u8,
+    /// Length of the ICMP message
+    pub bytes: usize,
+    /// Time from the request to the reply
+    pub rtt: Duration,
+}
+
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+            self.bytes,
+            self.from,
+            self.sequence,
+            self.ttl,
+            self.rtt.as_secs_f64() * 1e3
+        )
+    }
+}
+
+/// Outcome of [`ping`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Requests sent
+    pub sent: u64,
+    /// Requests answered
+    pub received: u64,
+    /// Round trips of the answered requests
+    pub rtt: Histogram,
+}
+
+impl Report {
+    fn record(&mut self, rtt: Duration) {
+        self.received += 1;
+        self.rtt.record(rtt);
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let loss = match self.sent {
+            0 => 0.0,
+            sent => 100.0 * (sent - self.received) as f64 / sent as f64,
+        };
+        write!(
+            f,
+            "{} packets transmitted, {} received, {:.0}% packet loss",
+            self.sent, self.received, loss
+        )?;
+        if self.rtt.count() > 0 {
+            write!(f, ", rtt {}", self.rtt.summary())?;
+        }
+        Ok(())
+    }
+}
+
+/// A hop of the route to the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hop {
+    /// TTL of the probe
+    pub ttl: u8,
+    /// Router or target that answered, None if nothing did in time
+    pub from: Option<Ipv4Addr>,
+    /// Time from the probe to the answer
+    pub rtt: Option<Duration>,
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.from, self.rtt) {
+            (Some(from), Some(rtt)) => write!(
+                f,
+                "{:>2}  {}  {:.3} ms",
+                self.ttl,
+                from,
+                rtt.as_secs_f64() * 1e3
+            ),
+            _ => write!(f, "{:>2}  *", self.ttl),
+        }
+    }
+}
+
+/// Build an Ethernet frame carrying an ICMP echo request from `target.source` to
+/// `target.target` with the given TTL.
+pub fn echo_frame(
+    target: &SynTarget,
+    ttl: u8,
+    identifier: u16,
+    sequence: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let icmp = icmp::echo_request(identifier, sequence, payload);
+
+    let mut frame = vec![0u8; 14 + 20];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+    ethernet.set_destination(target.next_hop);
+    ethernet.set_source(target.source.mac);
+    ethernet.set_ethertype(EtherTypes::Ipv4);
+
+    let ip = &mut frame[14..];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&((20 + icmp.len()) as u16).to_be_bytes());
+    ip[4..6].copy_from_slice(&sequence.to_be_bytes());
+    ip[8] = DEFAULT_TTL;
+    ip[9] = IPPROTO_ICMP;
+    ip[12..16].copy_from_slice(&target.source.ip.octets());
+    ip[16..20].copy_from_slice(&target.target.octets());
+    let header_checksum = checksum::checksum(&ip[..20]);
+    ip[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+    frame.extend_from_slice(&icmp);
+
+    if ttl != DEFAULT_TTL {
+        ttl::set_frame_ttl(
+            &mut MutableEthernetPacket::new(&mut frame[..]).unwrap(),
+            ttl,
+        );
+    }
+    frame
+}
+
+// what answered a request of ours
+enum Answer {
+    // the target, with the TTL and length of its reply
+    Reply(u16, u8, usize),
+    // a router the request ran out of TTL at
+    TimeExceeded(u16, Ipv4Addr),
+}
+
+// the answer `frame` carries to a request of `identifier` sent to `target`
+fn answer(frame: &EthernetPacket, target: &SynTarget, identifier: u16) -> Option<Answer> {
+    if frame.get_ethertype() != EtherTypes::Ipv4 {
+        return None;
+    }
+    let ip = Ipv4Packet::new(frame.payload())?;
+    if ip.get_next_level_protocol() != IpNextHeaderProtocols::Icmp
+        || ip.get_destination() != target.source.ip
+    {
+        return None;
+    }
+    let message = ip.payload();
+    if message.len() < 8 {
+        return None;
+    }
+    let echoed = |echo: &[u8]| {
+        let id = u16::from_be_bytes([echo[4], echo[5]]);
+        (id == identifier).then(|| u16::from_be_bytes([echo[6], echo[7]]))
+    };
+    match icmp::IcmpType(message[0]) {
+        IcmpTypes::EchoReply if ip.get_source() == target.target => {
+            let sequence = echoed(message)?;
+            Some(Answer::Reply(sequence, ip.get_ttl(), message.len()))
+        }
+        IcmpTypes::TimeExceeded => {
+            // the header of our request and the first 8 bytes of its payload
+            let quoted = &message[8..];
+            if quoted.len() < 20
+                || quoted[9] != IPPROTO_ICMP
+                || quoted[16..20] != target.target.octets()
+            {
+                return None;
+            }
+            let echo = quoted.get(usize::from(quoted[0] & 0x0f) * 4..)?;
+            if echo.len() < 8 || icmp::IcmpType(echo[0]) != IcmpTypes::EchoRequest {
+                return None;
+            }
+            Some(Answer::TimeExceeded(echoed(echo)?, ip.get_source()))
+        }
+        _ => None,
+    }
+}
+
+// the identifier of our requests, told apart from those of other pings on the host
+fn identifier() -> u16 {
+    process::id() as u16
+}
+
+fn send(tx: &mut dyn EthernetDataLinkSender, frame: &[u8]) -> io::Result<()> {
+    tx.send_to(&EthernetPacket::new(frame).unwrap(), None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+}
+
+/// Send echo requests to `target.target` every `config.interval` and report the replies.
+///
+/// `on_reply` is called for every reply as it arrives. `rx` should have a read timeout
+/// well below `config.timeout` so that `stop` is noticed.
+pub fn ping<F: FnMut(&Reply)>(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    target: &SynTarget,
+    config: &Config,
+    stop: &AtomicBool,
+    mut on_reply: F,
+) -> io::Result<Report> {
+    let identifier = identifier();
+    let payload: Vec<u8> = (0..config.payload_len).map(|i| i as u8).collect();
+    let mut report = Report::default();
+    let mut iter = rx.iter();
+    while !stop.load(Ordering::SeqCst) && config.count.map_or(true, |count| report.sent < count) {
+        let sequence = report.sent as u16;
+        send(
+            tx,
+            &echo_frame(target, DEFAULT_TTL, identifier, sequence, &payload),
+        )?;
+        report.sent += 1;
+
+        let sent = Instant::now();
+        let mut replied = false;
+        while !replied && sent.elapsed() < config.timeout && !stop.load(Ordering::SeqCst) {
+            let frame = match iter.next() {
+                Ok(frame) => frame,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(Answer::Reply(answered, ttl, bytes)) = answer(&frame, target, identifier) {
+                // late replies to earlier requests were already counted as lost
+                if answered != sequence {
+                    continue;
+                }
+                let rtt = sent.elapsed();
+                replied = true;
+                report.record(rtt);
+                on_reply(&Reply {
+                    from: target.target,
+                    sequence,
+                    ttl,
+                    bytes,
+                    rtt,
+                });
+            }
+        }
+
+        let last = config.count.map_or(false, |count| report.sent >= count);
+        while !last && sent.elapsed() < config.interval && !stop.load(Ordering::SeqCst) {
+            thread::sleep((config.interval - sent.elapsed()).min(Duration::from_millis(100)));
+        }
+    }
+    Ok(report)
+}
+
+/// Send echo requests to `target.target` with TTLs from 1 up, and report who answers each,
+/// until the target replies, `config.max_hops` is reached or `stop` is set.
+///
+/// `on_hop` is called for every hop as it is probed. The last hop of the result is the
+/// target if it was reached.
+pub fn trace<F: FnMut(&Hop)>(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    target: &SynTarget,
+    config: &TraceConfig,
+    stop: &AtomicBool,
+    mut on_hop: F,
+) -> io::Result<Vec<Hop>> {
+    let identifier = identifier();
+    let mut hops = Vec::new();
+    let mut iter = rx.iter();
+    for ttl in 1..=config.max_hops {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let sequence = u16::from(ttl);
+        send(
+            tx,
+            &echo_frame(target, ttl, identifier, sequence, b"myox trace"),
+        )?;
+
+        let sent = Instant::now();
+        let mut hop = Hop {
+            ttl,
+            from: None,
+            rtt: None,
+        };
+        while hop.from.is_none() && sent.elapsed() < config.timeout && !stop.load(Ordering::SeqCst)
+        {
+            let frame = match iter.next() {
+                Ok(frame) => frame,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            hop.from = match answer(&frame, target, identifier) {
+                Some(Answer::Reply(answered, _, _)) if answered == sequence => Some(target.target),
+                Some(Answer::TimeExceeded(answered, router)) if answered == sequence => {
+                    Some(router)
+                }
+                _ => continue,
+            };
+            hop.rtt = Some(sent.elapsed());
+        }
+        on_hop(&hop);
+        hops.push(hop);
+        if hop.from == Some(target.target) {
+            break;
+        }
+    }
+    Ok(hops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::{
+            channel::{Channel, Config as ChannelConfig},
+            loopback::Loopback,
+            network_interface::MacAddr,
+        },
+        scan::ports::Ipv4Mac,
+    };
+    use std::sync::Arc;
+
+    const HOST: Ipv4Mac = Ipv4Mac {
+        ip: Ipv4Addr::new(10, 0, 0, 1),
+        mac: MacAddr(0x02, 0, 0, 0, 0, 1),
+    };
+    const GATEWAY: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 0xfe);
+    const TARGET: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 7);
+
+    fn channel(
+        device: &Loopback,
+    ) -> (
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        let config = ChannelConfig {
+            read_timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        match device.channel(config).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        }
+    }
+
+    // an IPv4 frame from the gateway to HOST carrying `message`
+    fn icmp_frame(source: Ipv4Addr, ttl: u8, message: &[u8]) -> Vec<u8> {
+        let back = SynTarget {
+            source: Ipv4Mac {
+                ip: source,
+                mac: GATEWAY,
+            },
+            target: HOST.ip,
+            next_hop: HOST.mac,
+        };
+        let mut frame = echo_frame(&back, ttl, 0, 0, b"");
+        frame.truncate(14 + 20);
+        frame.extend_from_slice(message);
+        frame[14 + 2..14 + 4].copy_from_slice(&((20 + message.len()) as u16).to_be_bytes());
+        checksum::update_ipv4_frame(&mut frame);
+        frame
+    }
+
+    #[test]
+    fn pings_and_traces_through_routers() {
+        let device = Loopback::new();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // two routers in front of the target, which drops every third request
+        let (mut net_tx, mut net_rx) = channel(&device);
+        let net_stop = stop.clone();
+        let net = thread::spawn(move || {
+            let mut iter = net_rx.iter();
+            while !net_stop.load(Ordering::SeqCst) {
+                let frame = match iter.next() {
+                    Ok(frame) if frame.get_source() == HOST.mac => frame.packet().to_vec(),
+                    _ => continue,
+                };
+                let ip = &frame[14..];
+                let echo = icmp::EchoPacket::new(&ip[20..]).unwrap();
+                let reply = match ip[8] {
+                    ttl @ 1..=2 => {
+                        let mut message = vec![11, 0, 0, 0, 0, 0, 0, 0];
+                        message.extend_from_slice(&ip[..28]);
+                        let sum = checksum::checksum(&message);
+                        message[2..4].copy_from_slice(&sum.to_be_bytes());
+                        icmp_frame(Ipv4Addr::new(10, 0, ttl, 254), 255, &message)
+                    }
+                    _ if echo.get_sequence_number() % 3 == 2 => continue,
+                    _ => icmp_frame(TARGET, 61, &icmp::reply_to(&echo).unwrap()),
+                };
+                net_tx
+                    .send_to(&EthernetPacket::new(&reply).unwrap(), None)
+                    .unwrap()
+                    .unwrap();
+            }
+        });
+
+        let (mut tx, mut rx) = channel(&device);
+        let target = SynTarget {
+            source: HOST,
+            target: TARGET,
+            next_hop: GATEWAY,
+        };
+        let config = Config {
+            count: Some(4),
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(200),
+            payload_len: 16,
+        };
+        let mut replies = Vec::new();
+        let report = ping(&mut *tx, &mut *rx, &target, &config, &stop, |reply| {
+            replies.push(*reply)
+        })
+        .unwrap();
+        assert_eq!((report.sent, report.received), (4, 3));
+        assert_eq!(report.rtt.count(), 3);
+        let slowest = replies.iter().map(|reply| reply.rtt).max().unwrap();
+        assert_eq!(report.rtt.max(), slowest);
+        let sequences: Vec<_> = replies.iter().map(|reply| reply.sequence).collect();
+        assert_eq!(sequences, [0, 1, 3]);
+        assert!(replies
+            .iter()
+            .all(|reply| (reply.from, reply.ttl, reply.bytes) == (TARGET, 61, 24)));
+        assert!(report
+            .to_string()
+            .starts_with("4 packets transmitted, 3 received, 25% packet loss, rtt min/p50"));
+
+        let config = TraceConfig {
+            max_hops: 5,
+            timeout: Duration::from_millis(200),
+        };
+        let hops = trace(&mut *tx, &mut *rx, &target, &config, &stop, |_| ()).unwrap();
+        let route: Vec<_> = hops.iter().map(|hop| (hop.ttl, hop.from)).collect();
+        assert_eq!(
+            route,
+            [
+                (1, Some(Ipv4Addr::new(10, 0, 1, 254))),
+                (2, Some(Ipv4Addr::new(10, 0, 2, 254))),
+                (3, Some(TARGET)),
+            ]
+        );
+
+        stop.store(true, Ordering::SeqCst);
+        net.join().unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+//! Multi-threaded packet processing.
+//!
+//! A [`Pipeline`] opens one channel per worker on an interface, all in the same
+//! `PACKET_FANOUT` group so that the kernel spreads the packets among them, and runs a
+//! handler for every packet on the thread of the worker that received it. Statistics are
+//! kept per worker and can be read while the pipeline runs.
+//!
+//! On multi-queue NICs `Config::per_queue` opens one worker per RX queue instead, so that
+//! the worker index is the queue id and each worker sees the flows RSS steered to it.
+//! `Config::first_cpu` pins each worker to its own core.
+
+use crate::arp::{
+    channel::{
+        channel, Channel, Config as ChannelConfig, DatalinkBackend, Fanout, FanoutMode, Native,
+    },
+    ether::{EthernetPacket, Packet},
+    network_interface::NetworkInterface,
+};
+use std::{
+    fmt, io, mem, process,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+// distinguishes the groups of several pipelines in one process
+static NEXT_GROUP: AtomicU16 = AtomicU16::new(0);
+
+/// Pipeline parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// Number of capture threads. Defaults to 4
+    pub workers: usize,
+
+    /// How packets are spread among the workers. Defaults to Hash, keeping flows on one
+    /// worker
+    pub mode: FanoutMode,
+
+    /// Fanout group id, None to pick one from the process id. Defaults to None
+    pub group_id: Option<u16>,
+
+    /// Reassemble IP fragments before spreading them. Defaults to false
+    pub defrag: bool,
+
+    /// Open a worker per RX queue, or per CPU in `FanoutMode::Cpu`, ignoring `workers`;
+    /// requires one of those modes. Defaults to false
+    pub per_queue: bool,
+
+    /// Pin worker `i` to CPU `first_cpu + i`, wrapping around the online CPUs; None to
+    /// let the scheduler move them. Defaults to None
+    pub first_cpu: Option<usize>,
+
+    /// Size of each worker's read buffer. Defaults to 65536
+    pub read_buffer_size: usize,
+
+    /// How often idle workers check whether to stop. Defaults to 100 ms
+    pub poll_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            workers: 4,
+            mode: FanoutMode::Hash,
+            group_id: None,
+            defrag: false,
+            per_queue: false,
+            first_cpu: None,
+            read_buffer_size: 65536,
+            poll_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Counters of one worker.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    /// Packets handled
+    pub packets: u64,
+    /// Bytes handled
+    pub bytes: u64,
+    /// Receive errors
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    packets: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Counters {
+    fn snapshot(&self) -> WorkerStats {
+        WorkerStats {
+            packets: self.packets.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Statistics of all workers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Counters of each worker, by worker index
+    pub workers: Vec<WorkerStats>,
+}
+
+impl Report {
+    /// Counters summed over all workers.
+    pub fn total(&self) -> WorkerStats {
+        self.workers
+            .iter()
+            .fold(WorkerStats::default(), |total, worker| WorkerStats {
+                packets: total.packets + worker.packets,
+                bytes: total.bytes + worker.bytes,
+                errors: total.errors + worker.errors,
+            })
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, worker) in self.workers.iter().enumerate() {
+            writeln!(
+                f,
+                "worker {}: {} packets, {} bytes, {} errors",
+                index, worker.packets, worker.bytes, worker.errors
+            )?;
+        }
+        let total = self.total();
+        write!(
+            f,
+            "total: {} packets, {} bytes, {} errors",
+            total.packets, total.bytes, total.errors
+        )
+    }
+}
+
+/// Capture workers sharing an interface through a fanout group.
+///
+/// The workers run until [`Pipeline::stop`] is called or the pipeline is dropped. A worker
+/// gives up on the first receive error other than a timeout, which [`Pipeline::join`]
+/// reports.
+pub struct Pipeline {
+    stop: Arc<AtomicBool>,
+    counters: Arc<Vec<Counters>>,
+    threads: Vec<JoinHandle<io::Result<()>>>,
+}
+
+impl Pipeline {
+    /// Open the channels of all workers on `interface` and start them, calling `handler`
+    /// with the worker index and every packet received.
+    pub fn start<F>(
+        interface: &NetworkInterface,
+        config: &Config,
+        handler: F,
+    ) -> io::Result<Pipeline>
+    where
+        F: Fn(usize, &EthernetPacket) + Send + Sync + 'static,
+    {
+        let group_id = config.group_id.unwrap_or_else(|| {
+            (process::id() as u16).wrapping_add(NEXT_GROUP.fetch_add(1, Ordering::Relaxed))
+        });
+        let fanout = Fanout {
+            id: group_id,
+            mode: config.mode,
+            defrag: config.defrag,
+        };
+        let channel_config = ChannelConfig {
+            read_buffer_size: config.read_buffer_size,
+            // workers have to notice when they are stopped
+            read_timeout: Some(config.poll_interval),
+            fanout: Some(fanout),
+            ..Default::default()
+        };
+        // open every channel before starting any worker, so that failing to join the group
+        // is reported here
+        let channels = if config.per_queue {
+            Native::default().queue_channels(interface, fanout, channel_config)?
+        } else {
+            (0..config.workers.max(1))
+                .map(|_| channel(interface, channel_config))
+                .collect::<crate::error::Result<Vec<Channel>>>()?
+        };
+        let mut receivers = Vec::new();
+        for ch in channels {
+            match ch {
+                Channel::Ethernet(_, rx) => receivers.push(rx),
+                _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+            }
+        }
+
+        let cpus = online_cpus()?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let counters: Arc<Vec<Counters>> =
+            Arc::new(receivers.iter().map(|_| Counters::default()).collect());
+        let handler = Arc::new(handler);
+        let threads = receivers
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut rx)| {
+                let stop = stop.clone();
+                let counters = counters.clone();
+                let handler = handler.clone();
+                let cpu = config.first_cpu.map(|first| (first + index) % cpus);
+                thread::spawn(move || {
+                    if let Some(cpu) = cpu {
+                        pin_current_thread(cpu)?;
+                    }
+                    let counters = &counters[index];
+                    let mut iter = rx.iter();
+                    while !stop.load(Ordering::Relaxed) {
+                        match iter.next() {
+                            Ok(packet) => {
+                                counters.packets.fetch_add(1, Ordering::Relaxed);
+                                counters
+                                    .bytes
+                                    .fetch_add(packet.packet().len() as u64, Ordering::Relaxed);
+                                handler(index, &packet);
+                            }
+                            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                            Err(e) => {
+                                counters.errors.fetch_add(1, Ordering::Relaxed);
+                                return Err(e);
+                            }
+                        }
+                    }
+                    Ok(())
+                })
+            })
+            .collect();
+
+        Ok(Pipeline {
+            stop,
+            counters,
+            threads,
+        })
+    }
+
+    /// Number of workers.
+    pub fn workers(&self) -> usize {
+        self.counters.len()
+    }
+
+    /// Current statistics of all workers.
+    pub fn stats(&self) -> Report {
+        Report {
+            workers: self.counters.iter().map(Counters::snapshot).collect(),
+        }
+    }
+
+    /// The flag stopping the workers, to be set from elsewhere, e.g. a signal handler.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Ask the workers to stop. They finish within the poll interval.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Wait for the workers to finish, returning their statistics or the first error one
+    /// of them stopped with.
+    pub fn join(mut self) -> io::Result<Report> {
+        let mut result = Ok(());
+        for thread in self.threads.drain(..) {
+            let outcome = thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Worker panicked")));
+            if result.is_ok() {
+                result = outcome;
+            }
+        }
+        result.map(|_| self.stats())
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        self.stop();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Number of online CPUs.
+pub fn online_cpus() -> io::Result<usize> {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cpus < 1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(cpus as usize)
+    }
+}
+
+/// Restrict the calling thread to run on `cpu` only.
+pub fn pin_current_thread(cpu: usize) -> io::Result<()> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    if cpu >= 8 * mem::size_of::<libc::cpu_set_t>() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "CPU out of range",
+        ));
+    }
+    unsafe {
+        libc::CPU_SET(cpu, &mut set);
+    }
+    // pid 0 is the calling thread
+    if unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) } == -1 {
+        let err = io::Error::last_os_error();
+        trace_event!(warn, error = %err, cpu, "pinning thread failed");
+        return Err(err);
+    }
+    trace_event!(debug, cpu, "thread pinned");
+    Ok(())
+}
@@ -0,0 +1,237 @@
+//! Reusable packet buffers.
+//!
+//! A [`BufferPool`] hands out [`Buffer`]s and takes them back when they are dropped, so
+//! code keeping packets around doesn't allocate for each of them. Buffers can be read into
+//! from a receiver with [`BufferPool::receive`], filled by the pcap reader, and turned into
+//! owned packets with `EthernetPacket::pooled`. Channel iterators do the latter with
+//! `next_pooled`, and a pool set up with [`BufferPool::with_config`] can have its buffers
+//! allocated before the first packet arrives.
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::arp::{channel::EthernetDataLinkChannelIterator, ether::Packet};
+#[cfg(not(target_arch = "wasm32"))]
+use std::io;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Default capacity of pooled buffers, enough for a jumbo frame.
+pub const DEFAULT_BUFFER_SIZE: usize = 9216;
+
+/// How a [`BufferPool`] is set up.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Config {
+    /// Capacity of the buffers. Defaults to `DEFAULT_BUFFER_SIZE`
+    pub buffer_size: usize,
+
+    /// Buffers kept around while not leased, the rest are freed. Defaults to 1024
+    pub max_free: usize,
+
+    /// Buffers allocated up front, at most `max_free`. Defaults to 0
+    pub preallocate: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_free: 1024,
+            preallocate: 0,
+        }
+    }
+}
+
+struct Shared {
+    free: Mutex<Vec<Vec<u8>>>,
+    buffer_size: usize,
+    max_free: usize,
+    allocated: AtomicU64,
+    leased: AtomicU64,
+}
+
+/// A pool of byte buffers. Clones share the same buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    shared: Arc<Shared>,
+}
+
+impl BufferPool {
+    /// Create a pool of buffers with room for `buffer_size` bytes, keeping at most
+    /// `max_free` of them around when they are not leased.
+    pub fn new(buffer_size: usize, max_free: usize) -> BufferPool {
+        BufferPool {
+            shared: Arc::new(Shared {
+                free: Mutex::new(Vec::new()),
+                buffer_size,
+                max_free,
+                allocated: AtomicU64::new(0),
+                leased: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Create a pool set up by `config`, allocating the buffers it asks for up front.
+    pub fn with_config(config: Config) -> BufferPool {
+        let pool = BufferPool::new(config.buffer_size, config.max_free);
+        pool.preallocate(config.preallocate);
+        pool
+    }
+
+    /// Allocate buffers until `count` are free, or `max_free` are.
+    pub fn preallocate(&self, count: usize) {
+        let mut free = self.shared.free.lock().unwrap();
+        let count = count.min(self.shared.max_free);
+        while free.len() < count {
+            self.shared.allocated.fetch_add(1, Ordering::Relaxed);
+            free.push(Vec::with_capacity(self.shared.buffer_size));
+        }
+    }
+
+    /// Take an empty buffer out of the pool, allocating one if none is free.
+    pub fn lease(&self) -> Buffer {
+        self.shared.leased.fetch_add(1, Ordering::Relaxed);
+        let reused = self.shared.free.lock().unwrap().pop();
+        let data = reused.unwrap_or_else(|| {
+            self.shared.allocated.fetch_add(1, Ordering::Relaxed);
+            Vec::with_capacity(self.shared.buffer_size)
+        });
+        Buffer {
+            data,
+            pool: Some(self.shared.clone()),
+        }
+    }
+
+    /// Copy the next frame of `iter` into a leased buffer.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn receive(&self, iter: &mut dyn EthernetDataLinkChannelIterator) -> io::Result<Buffer> {
+        let packet = iter.next()?;
+        let mut buffer = self.lease();
+        buffer.extend_from_slice(packet.packet());
+        Ok(buffer)
+    }
+
+    /// Number of buffers waiting to be leased.
+    pub fn free(&self) -> usize {
+        self.shared.free.lock().unwrap().len()
+    }
+
+    /// Number of buffers the pool had to allocate so far.
+    pub fn allocated(&self) -> u64 {
+        self.shared.allocated.load(Ordering::Relaxed)
+    }
+
+    /// Number of leases so far, reused buffers included.
+    pub fn leased(&self) -> u64 {
+        self.shared.leased.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for BufferPool {
+    fn default() -> BufferPool {
+        BufferPool::with_config(Config::default())
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("buffer_size", &self.shared.buffer_size)
+            .field("free", &self.free())
+            .field("allocated", &self.allocated())
+            .finish()
+    }
+}
+
+/// A buffer leased from a [`BufferPool`], going back to it when dropped.
+pub struct Buffer {
+    data: Vec<u8>,
+    pool: Option<Arc<Shared>>,
+}
+
+impl Buffer {
+    /// Keep the bytes for good; the pool allocates a replacement when it runs out.
+    pub fn detach(mut self) -> Vec<u8> {
+        self.pool = None;
+        std::mem::replace(&mut self.data, Vec::new())
+    }
+}
+
+impl Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.data
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            let mut free = pool.free.lock().unwrap();
+            // buffers grown far beyond the usual size are not worth keeping
+            if free.len() < pool.max_free && self.data.capacity() <= 2 * pool.buffer_size.max(1) {
+                let mut data = std::mem::replace(&mut self.data, Vec::new());
+                data.clear();
+                free.push(data);
+            }
+        }
+    }
+}
+
+impl PartialEq for Buffer {
+    fn eq(&self, other: &Buffer) -> bool {
+        self.data == other.data
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Buffer")
+            .field("len", &self.data.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arp::{
+        channel::{self, Channel},
+        ether::EthernetPacket,
+        loopback::Loopback,
+    };
+
+    #[test]
+    fn pooled_packets_keep_the_buffer_size() {
+        let device = Loopback::new();
+        let config = channel::Config {
+            read_buffer_size: 65536,
+            ..Default::default()
+        };
+        let (mut tx, mut rx) = match device.channel(config).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let pool = BufferPool::new(256, 4);
+        let frame = [0u8; 60];
+        for _ in 0..3 {
+            tx.send_to(&EthernetPacket::new(&frame).unwrap(), None);
+            let packet = rx.iter().next_pooled(&pool).unwrap();
+            assert_eq!(packet.packet(), &frame[..]);
+        }
+        // a buffer grown to the read buffer size would not have been taken back
+        assert_eq!(pool.allocated(), 1);
+        assert_eq!((pool.leased(), pool.free()), (3, 1));
+    }
+}
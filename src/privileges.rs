@@ -0,0 +1,177 @@
+//! Capability checks and dropping privileges.
+//!
+//! Packet sockets need `CAP_NET_RAW` and changing interface settings needs
+//! `CAP_NET_ADMIN`. [`require`] turns a missing capability into an error naming it instead
+//! of a bare "Operation not permitted". Once the channels are open a tool can give up root
+//! with [`drop_privileges`], optionally keeping some capabilities with [`limit_capabilities`].
+
+use std::{ffi::CString, fmt, io, mem, ptr};
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// A Linux capability the crate may need.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Capability {
+    /// Changing interface addresses, flags and MAC addresses
+    NetAdmin,
+    /// Opening raw and packet sockets
+    NetRaw,
+}
+
+impl Capability {
+    /// Bit number of the capability.
+    pub fn number(self) -> u32 {
+        match self {
+            Capability::NetAdmin => 12,
+            Capability::NetRaw => 13,
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Capability::NetAdmin => "CAP_NET_ADMIN",
+            Capability::NetRaw => "CAP_NET_RAW",
+        })
+    }
+}
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn capget() -> io::Result<[CapData; 2]> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(data)
+}
+
+fn capset(data: &[CapData; 2]) -> io::Result<()> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    if unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether the calling thread has `capability` in its effective set.
+pub fn has_capability(capability: Capability) -> io::Result<bool> {
+    let data = capget()?;
+    let bit = capability.number();
+    Ok(data[(bit / 32) as usize].effective & (1 << (bit % 32)) != 0)
+}
+
+/// Fail with `PermissionDenied` naming the first of `capabilities` the calling thread
+/// lacks.
+///
+/// Nothing is reported if the capabilities cannot be queried; the operation needing them
+/// will fail on its own then.
+pub fn require(capabilities: &[Capability]) -> io::Result<()> {
+    for &capability in capabilities {
+        if let Ok(false) = has_capability(capability) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "missing {}; run as root or grant it with `setcap {}+ep`",
+                    capability,
+                    capability.to_string().to_lowercase()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reduce the permitted and effective capabilities of the calling thread to `keep`.
+///
+/// Capabilities are per thread; call this before spawning threads that should be limited
+/// as well.
+pub fn limit_capabilities(keep: &[Capability]) -> io::Result<()> {
+    let mut data = [CapData::default(); 2];
+    for capability in keep {
+        let bit = capability.number();
+        let set = &mut data[(bit / 32) as usize];
+        set.permitted |= 1 << (bit % 32);
+        set.effective |= 1 << (bit % 32);
+    }
+    capset(&data)
+}
+
+/// User and group ids of the user called `name`.
+pub fn lookup_user(name: &str) -> io::Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = CString::new(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid user name"))?;
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut result = ptr::null_mut();
+    let ret = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::from_raw_os_error(ret));
+    }
+    if result.is_null() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such user: {}", name),
+        ));
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+/// Switch to `uid` and `gid` for good, dropping supplementary groups, while keeping the
+/// capabilities in `keep`.
+///
+/// Open channels keep working, so this is meant to be called right after they are
+/// created. Fails if root could be regained afterwards.
+pub fn drop_privileges(uid: libc::uid_t, gid: libc::gid_t, keep: &[Capability]) -> io::Result<()> {
+    let check = |ret: libc::c_int| match ret {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    };
+    if !keep.is_empty() {
+        // otherwise changing the uid clears the permitted set
+        check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) })?;
+    }
+    check(unsafe { libc::setgroups(0, ptr::null()) })?;
+    check(unsafe { libc::setresgid(gid, gid, gid) })?;
+    check(unsafe { libc::setresuid(uid, uid, uid) })?;
+    if !keep.is_empty() {
+        check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 0, 0, 0, 0) })?;
+    }
+    limit_capabilities(keep)?;
+
+    if uid != 0 && unsafe { libc::setuid(0) } != -1 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            "root privileges could be regained",
+        ));
+    }
+    Ok(())
+}
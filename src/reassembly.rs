@@ -0,0 +1,145 @@
+//! TCP stream reassembly.
+//!
+//! A [`Stream`] puts the segments of one direction of a TCP connection back in order,
+//! trimming retransmitted data and holding segments that arrive early until the hole in
+//! front of them is filled. Dissectors read the contiguous bytes and consume what they
+//! parsed.
+
+/// Default number of out of order segments held per stream.
+pub const DEFAULT_MAX_PENDING: usize = 64;
+
+/// One direction of a TCP connection.
+#[derive(Clone, Debug)]
+pub struct Stream {
+    next: Option<u32>,
+    buffer: Vec<u8>,
+    pending: Vec<(u32, Vec<u8>)>,
+    max_pending: usize,
+    gaps: u64,
+}
+
+impl Stream {
+    /// Create a stream holding at most `DEFAULT_MAX_PENDING` out of order segments.
+    pub fn new() -> Stream {
+        Stream::with_max_pending(DEFAULT_MAX_PENDING)
+    }
+
+    /// Create a stream holding at most `max_pending` out of order segments. When more
+    /// arrive the missing data is given up on, see `gaps`.
+    pub fn with_max_pending(max_pending: usize) -> Stream {
+        Stream {
+            next: None,
+            buffer: Vec::new(),
+            pending: Vec::new(),
+            max_pending: max_pending.max(1),
+            gaps: 0,
+        }
+    }
+
+    /// Add a segment with sequence number `seq` carrying `data`. `syn` tells whether the
+    /// SYN flag is set, which takes up one sequence number in front of the data.
+    ///
+    /// The first segment seen fixes where the stream starts, so streams can be picked up
+    /// in the middle of a connection.
+    pub fn push(&mut self, seq: u32, syn: bool, data: &[u8]) {
+        let seq = if syn { seq.wrapping_add(1) } else { seq };
+        let next = *self.next.get_or_insert(seq);
+        if data.is_empty() {
+            return;
+        }
+        if offset(next, seq) > 0 {
+            self.pending.push((seq, data.to_vec()));
+            if self.pending.len() > self.max_pending {
+                self.skip_to_pending();
+            }
+            return;
+        }
+        self.append(seq, data);
+        self.drain_pending();
+    }
+
+    /// The bytes received in order and not consumed yet.
+    pub fn data(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Drop the first `len` bytes of `data`.
+    pub fn consume(&mut self, len: usize) {
+        let len = len.min(self.buffer.len());
+        self.buffer.drain(..len);
+    }
+
+    /// Number of times data went missing for good and was skipped over.
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+
+    /// Sequence number of the next byte expected, if any segment was seen.
+    pub fn next_seq(&self) -> Option<u32> {
+        self.next
+    }
+
+    /// Append the part of the segment at `seq` that lies at or after `next`.
+    fn append(&mut self, seq: u32, data: &[u8]) {
+        let next = match self.next {
+            Some(next) => next,
+            None => return,
+        };
+        let overlap = -offset(next, seq);
+        if overlap < 0 || overlap as usize >= data.len() {
+            return;
+        }
+        let fresh = &data[overlap as usize..];
+        self.buffer.extend_from_slice(fresh);
+        self.next = Some(next.wrapping_add(fresh.len() as u32));
+    }
+
+    fn drain_pending(&mut self) {
+        loop {
+            let next = match self.next {
+                Some(next) => next,
+                None => return,
+            };
+            let ready = self
+                .pending
+                .iter()
+                .position(|(seq, _)| offset(next, *seq) <= 0);
+            match ready {
+                Some(index) => {
+                    let (seq, data) = self.pending.swap_remove(index);
+                    self.append(seq, &data);
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Give up on the hole in front of the earliest pending segment.
+    fn skip_to_pending(&mut self) {
+        let next = match self.next {
+            Some(next) => next,
+            None => return,
+        };
+        let earliest = self
+            .pending
+            .iter()
+            .map(|(seq, _)| *seq)
+            .min_by_key(|&seq| offset(next, seq));
+        if let Some(seq) = earliest {
+            self.next = Some(seq);
+            self.gaps += 1;
+            self.drain_pending();
+        }
+    }
+}
+
+impl Default for Stream {
+    fn default() -> Stream {
+        Stream::new()
+    }
+}
+
+/// Distance from `next` to `seq` in sequence space, negative if `seq` lies before it.
+fn offset(next: u32, seq: u32) -> i32 {
+    seq.wrapping_sub(next) as i32
+}
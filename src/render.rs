@@ -0,0 +1,392 @@
+//! Terminal rendering of captured frames.
+//!
+//! [`Renderer`] turns frames into the one-line summaries of [`sniff::summarize`], colored
+//! by protocol when writing to a terminal. It also remembers the IPv4 to MAC bindings
+//! announced over ARP to point out suspicious ARP traffic, and flags malformed IPv4
+//! headers and wrong checksums. [`json`] renders frames as JSON objects instead, one per line, for feeding
+//! captures to other tools.
+//!
+//! [`sniff::summarize`]: crate::sniff::summarize
+
+use crate::{
+    arp::{
+        arp::Error,
+        arp_new::{ArpOperations, ArpPacket},
+        ether::{EtherType, EtherTypes, EthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    ipv4::{self, ChecksumMode},
+    sniff::{self, ParseError},
+};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Write,
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const RED: &str = "\x1b[1;31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+// the standard descriptors, numbered the same by the C runtime of Windows
+const STDOUT: libc::c_int = 1;
+const STDERR: libc::c_int = 2;
+
+/// Whether output is colored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Color when writing to a terminal and `NO_COLOR` is not set
+    Auto,
+    /// Always color
+    Always,
+    /// Never color
+    Never,
+}
+
+impl ColorMode {
+    /// Resolve the mode for output to the file descriptor `fd`.
+    pub fn enabled(self, fd: libc::c_int) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => {
+                let tty = unsafe { libc::isatty(fd) } != 0;
+                tty && env::var_os("NO_COLOR").is_none()
+                    && env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+        }
+    }
+}
+
+impl Default for ColorMode {
+    fn default() -> ColorMode {
+        ColorMode::Auto
+    }
+}
+
+impl FromStr for ColorMode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<ColorMode, ParseError> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err(ParseError(format!("invalid color mode `{}`", s))),
+        }
+    }
+}
+
+/// Renders captured frames as lines of text.
+pub struct Renderer {
+    color: bool,
+    checksum_mode: ChecksumMode,
+    bindings: HashMap<Ipv4Addr, MacAddr>,
+}
+
+impl Renderer {
+    /// Create a renderer for standard output, coloring according to `mode`.
+    pub fn new(mode: ColorMode) -> Renderer {
+        Renderer {
+            color: mode.enabled(STDOUT),
+            checksum_mode: ChecksumMode::OffloadAware,
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Judge checksums according to `mode`. Defaults to not flagging checksums that look
+    /// offloaded, which packets sent by the capturing host usually have.
+    pub fn checksum_mode(mut self, mode: ChecksumMode) -> Renderer {
+        self.checksum_mode = mode;
+        self
+    }
+
+    /// Describe a frame captured at `timestamp` on one line, without a line break.
+    pub fn render(&mut self, timestamp: SystemTime, packet: &EthernetPacket) -> String {
+        let warnings = self.warnings(packet);
+        let mut line = format!(
+            "{} {}",
+            self.paint(DIM, &sniff::format_timestamp(timestamp)),
+            self.paint(protocol_color(packet), &sniff::summarize(packet))
+        );
+        for warning in warnings {
+            line.push(' ');
+            line.push_str(&self.paint(RED, &format!("[{}]", warning)));
+        }
+        line
+    }
+
+    fn paint(&self, color: &str, text: &str) -> String {
+        paint(self.color, color, text)
+    }
+
+    /// Anomalies worth pointing out in `packet`.
+    fn warnings(&mut self, packet: &EthernetPacket) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let payload = packet.payload();
+        match packet.get_ethertype() {
+            EtherTypes::Arp => {
+                let arp = match ArpPacket::new(payload) {
+                    Some(arp) => arp,
+                    None => return vec!["truncated arp".to_owned()],
+                };
+                let sender_ip = arp.get_sender_proto_addr();
+                let sender_mac = arp.get_sender_hw_addr();
+                if sender_mac != packet.get_source() {
+                    warnings.push(format!("arp sender {} differs from source", sender_mac));
+                }
+                if sender_ip == arp.get_target_proto_addr() {
+                    warnings.push("gratuitous arp".to_owned());
+                }
+                let announces = match arp.get_operation() {
+                    ArpOperations::Reply => true,
+                    // requests from an unconfigured host (probes) bind nothing
+                    ArpOperations::Request => !sender_ip.is_unspecified(),
+                    _ => false,
+                };
+                if announces {
+                    if let Some(previous) = self.bindings.insert(sender_ip, sender_mac) {
+                        if previous != sender_mac {
+                            warnings.push(format!("{} moved from {}", sender_ip, previous));
+                        }
+                    }
+                }
+            }
+            EtherTypes::Ipv4 => {
+                let header_len = payload.first().map_or(0, |b| (b & 0x0f) as usize * 4);
+                if payload.len() < 20 || header_len < 20 || payload.len() < header_len {
+                    warnings.push("truncated ip".to_owned());
+                } else {
+                    let total_len = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+                    if total_len > payload.len() {
+                        warnings.push("truncated ip".to_owned());
+                    }
+                    if let Err(Error::Checksum(layer)) =
+                        ipv4::check_checksums(payload, self.checksum_mode)
+                    {
+                        warnings.push(format!("bad {} checksum", layer));
+                    }
+                }
+            }
+            _ => {}
+        }
+        warnings
+    }
+}
+
+/// Format an error message for standard error, highlighted according to `mode`.
+pub fn error(mode: ColorMode, message: &str) -> String {
+    paint(mode.enabled(STDERR), RED, message)
+}
+
+fn paint(enabled: bool, color: &str, text: &str) -> String {
+    if enabled && !color.is_empty() {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_owned()
+    }
+}
+
+fn protocol_color(packet: &EthernetPacket) -> &'static str {
+    let payload = packet.payload();
+    match packet.get_ethertype() {
+        EtherTypes::Arp | EtherTypes::Rarp => YELLOW,
+        EtherTypes::Ipv4 if payload.len() >= 20 => match payload[9] {
+            // ICMP, TCP, UDP
+            1 => MAGENTA,
+            6 => GREEN,
+            17 => BLUE,
+            _ => "",
+        },
+        EtherTypes::Ipv6 => CYAN,
+        _ => "",
+    }
+}
+
+/// Describe a frame captured at `timestamp` on `interface` as a single line JSON object.
+///
+/// The object holds the capture time in RFC 3339 format, the interface, the frame length,
+/// one object per decoded layer under `layers` and the bytes following the last decoded
+/// layer as hex under `payload`.
+pub fn json(timestamp: SystemTime, interface: &str, packet: &EthernetPacket) -> String {
+    let mut layers = Vec::new();
+    layers.push(format!(
+        "{{\"type\":\"ethernet\",\"src\":\"{}\",\"dst\":\"{}\",\"ethertype\":{}}}",
+        packet.get_source(),
+        packet.get_destination(),
+        packet.get_ethertype().value()
+    ));
+
+    let mut ethertype = packet.get_ethertype();
+    let mut payload = packet.payload();
+    while (ethertype == EtherTypes::Vlan || ethertype == EtherTypes::QinQ) && payload.len() >= 4 {
+        let tci = u16::from_be_bytes([payload[0], payload[1]]);
+        ethertype = EtherType::new(u16::from_be_bytes([payload[2], payload[3]]));
+        layers.push(format!(
+            "{{\"type\":\"vlan\",\"pcp\":{},\"id\":{},\"ethertype\":{}}}",
+            tci >> 13,
+            tci & 0x0fff,
+            ethertype.value()
+        ));
+        payload = &payload[4..];
+    }
+
+    let mut protocol = None;
+    match ethertype {
+        EtherTypes::Arp => {
+            if let Some(arp) = ArpPacket::new(payload) {
+                layers.push(format!(
+                    "{{\"type\":\"arp\",\"operation\":{},\"sender_mac\":\"{}\",\"sender_ip\":\"{}\",\"target_mac\":\"{}\",\"target_ip\":\"{}\"}}",
+                    arp.get_operation().0,
+                    arp.get_sender_hw_addr(),
+                    arp.get_sender_proto_addr(),
+                    arp.get_target_hw_addr(),
+                    arp.get_target_proto_addr()
+                ));
+                payload = &payload[payload.len().min(28)..];
+            }
+        }
+        EtherTypes::Ipv4 if payload.len() >= 20 => {
+            let header_len = ((payload[0] & 0x0f) as usize * 4)
+                .max(20)
+                .min(payload.len());
+            let total_len = u16::from_be_bytes([payload[2], payload[3]]) as usize;
+            let fragment_offset = u16::from_be_bytes([payload[6], payload[7]]) & 0x1fff;
+            layers.push(format!(
+                "{{\"type\":\"ipv4\",\"src\":\"{}\",\"dst\":\"{}\",\"protocol\":{},\"ttl\":{},\"tos\":{},\"id\":{},\"length\":{}}}",
+                Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]),
+                Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]),
+                payload[9],
+                payload[8],
+                payload[1],
+                u16::from_be_bytes([payload[4], payload[5]]),
+                total_len
+            ));
+            if fragment_offset == 0 {
+                protocol = Some(payload[9]);
+            }
+            // drop Ethernet padding
+            let end = total_len.max(header_len).min(payload.len());
+            payload = &payload[header_len..end];
+        }
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&payload[8..24]);
+            dst.copy_from_slice(&payload[24..40]);
+            let payload_len = u16::from_be_bytes([payload[4], payload[5]]) as usize;
+            layers.push(format!(
+                "{{\"type\":\"ipv6\",\"src\":\"{}\",\"dst\":\"{}\",\"next_header\":{},\"hop_limit\":{},\"length\":{}}}",
+                Ipv6Addr::from(src),
+                Ipv6Addr::from(dst),
+                payload[6],
+                payload[7],
+                payload_len
+            ));
+            protocol = Some(payload[6]);
+            payload = &payload[40..(40 + payload_len).min(payload.len())];
+        }
+        _ => {}
+    }
+
+    match protocol {
+        Some(6) if payload.len() >= 20 => {
+            let header_len = ((payload[12] >> 4) as usize * 4).max(20).min(payload.len());
+            layers.push(format!(
+                "{{\"type\":\"tcp\",\"src_port\":{},\"dst_port\":{},\"seq\":{},\"ack\":{},\"flags\":{},\"window\":{}}}",
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+                u32::from_be_bytes([payload[4], payload[5], payload[6], payload[7]]),
+                u32::from_be_bytes([payload[8], payload[9], payload[10], payload[11]]),
+                payload[13],
+                u16::from_be_bytes([payload[14], payload[15]])
+            ));
+            payload = &payload[header_len..];
+        }
+        Some(17) if payload.len() >= 8 => {
+            layers.push(format!(
+                "{{\"type\":\"udp\",\"src_port\":{},\"dst_port\":{},\"length\":{}}}",
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+                u16::from_be_bytes([payload[4], payload[5]])
+            ));
+            payload = &payload[8..];
+        }
+        Some(1) | Some(58) if payload.len() >= 4 => {
+            let name = if protocol == Some(1) {
+                "icmp"
+            } else {
+                "icmpv6"
+            };
+            layers.push(format!(
+                "{{\"type\":\"{}\",\"icmp_type\":{},\"code\":{}}}",
+                name, payload[0], payload[1]
+            ));
+            payload = &payload[4..];
+        }
+        _ => {}
+    }
+
+    let mut hex = String::with_capacity(payload.len() * 2);
+    for byte in payload {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    format!(
+        "{{\"timestamp\":\"{}\",\"interface\":{},\"length\":{},\"layers\":[{}],\"payload\":\"{}\"}}",
+        rfc3339(timestamp),
+        json_string(interface),
+        packet.packet().len(),
+        layers.join(","),
+        hex
+    )
+}
+
+/// Format `timestamp` as an RFC 3339 UTC date and time with microsecond precision.
+pub(crate) fn rfc3339(timestamp: SystemTime) -> String {
+    let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{}Z",
+        year,
+        month,
+        day,
+        sniff::format_timestamp(timestamp)
+    )
+}
+
+/// Quote and escape `value` as a JSON string.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
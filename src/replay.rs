@@ -0,0 +1,200 @@
+//! Retransmission of captured traffic.
+//!
+//! Frames are read from a pcap or pcapng file and sent on a datalink channel, either as
+//! fast as possible or spaced like they were captured, optionally sped up or slowed
+//! down. Addresses can be rewritten on the way, e.g. to aim a capture taken elsewhere at a
+//! device under test.
+
+use crate::{
+    arp::{
+        channel::{self, Channel, EthernetDataLinkSender},
+        ether::{EthernetPacket, MutableEthernetPacket},
+        network_interface::{MacAddr, NetworkInterface},
+    },
+    checksum, pcap,
+};
+use std::{
+    fs::File,
+    io::{self, BufReader, Read},
+    net::Ipv4Addr,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Addresses written into every replayed frame. Fields left as None are not changed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rewrite {
+    /// Ethernet source address
+    pub src_mac: Option<MacAddr>,
+    /// Ethernet destination address
+    pub dst_mac: Option<MacAddr>,
+    /// IPv4 source address
+    pub src_ip: Option<Ipv4Addr>,
+    /// IPv4 destination address
+    pub dst_ip: Option<Ipv4Addr>,
+}
+
+impl Rewrite {
+    /// Apply the rewrite to `frame`, fixing up the checksums covering rewritten IPv4
+    /// addresses.
+    pub fn apply(&self, frame: &mut [u8]) {
+        if let Some(mut ethernet) = MutableEthernetPacket::new(frame) {
+            if let Some(mac) = self.src_mac {
+                ethernet.set_source(mac);
+            }
+            if let Some(mac) = self.dst_mac {
+                ethernet.set_destination(mac);
+            }
+        }
+        if self.src_ip.is_none() && self.dst_ip.is_none() {
+            return;
+        }
+        let (ip, _) = match checksum::ipv4_header(frame) {
+            Some(header) => header,
+            None => return,
+        };
+        if let Some(addr) = self.src_ip {
+            frame[ip + 12..ip + 16].copy_from_slice(&addr.octets());
+        }
+        if let Some(addr) = self.dst_ip {
+            frame[ip + 16..ip + 20].copy_from_slice(&addr.octets());
+        }
+        checksum::update_ipv4_frame(frame);
+    }
+}
+
+/// Options of a replay.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Config {
+    /// Space frames like they were captured instead of sending them back to back.
+    /// Defaults to false
+    pub preserve_timing: bool,
+
+    /// How many times faster than captured frames are sent when timing is preserved, 2.0
+    /// halving the gaps and 0.5 doubling them. Defaults to 1.0
+    pub speed: f64,
+
+    /// Number of times the capture is sent. Defaults to 1
+    pub loops: usize,
+
+    /// Addresses to rewrite. Defaults to none
+    pub rewrite: Rewrite,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            preserve_timing: false,
+            speed: 1.0,
+            loops: 1,
+            rewrite: Default::default(),
+        }
+    }
+}
+
+/// Replay the capture file at `path` on `tx`.
+///
+/// Returns the number of frames sent.
+pub fn replay_file(
+    tx: &mut dyn EthernetDataLinkSender,
+    path: &Path,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<u64> {
+    replay(
+        tx,
+        || pcap::Reader::new(BufReader::new(File::open(path)?)),
+        config,
+        stop,
+    )
+}
+
+/// Replay the capture file at `path` on `interface`.
+///
+/// Returns the number of frames sent.
+pub fn replay_file_on(
+    interface: &NetworkInterface,
+    path: &Path,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<u64> {
+    let mut tx = match channel::channel(interface, Default::default())? {
+        Channel::Ethernet(tx, _) => tx,
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+    };
+    replay_file(&mut *tx, path, config, stop)
+}
+
+/// Replay a capture on `tx` until it was sent `config.loops` times or `stop` is set.
+///
+/// `open` is called at the start of every loop to read the capture from its beginning.
+/// Returns the number of frames sent.
+pub fn replay<R, F>(
+    tx: &mut dyn EthernetDataLinkSender,
+    mut open: F,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<u64>
+where
+    R: Read,
+    F: FnMut() -> io::Result<pcap::Reader<R>>,
+{
+    let mut sent = 0;
+    for _ in 0..config.loops {
+        let reader = open()?;
+        if reader.link_type() != pcap::LINKTYPE_ETHERNET {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported link type {}", reader.link_type()),
+            ));
+        }
+
+        let mut first = None;
+        let started = Instant::now();
+        for record in reader {
+            if stop.load(Ordering::SeqCst) {
+                return Ok(sent);
+            }
+            let mut record = record?;
+
+            if config.preserve_timing {
+                let first = *first.get_or_insert(record.timestamp);
+                // packets stamped earlier than the first one go out immediately
+                let offset = record.timestamp.duration_since(first).unwrap_or_default();
+                // a speed of zero or less would never send the next frame
+                let offset = offset.div_f64(config.speed.max(1e-6));
+                if !sleep_until(started + offset, stop) {
+                    return Ok(sent);
+                }
+            }
+
+            config.rewrite.apply(&mut record.data);
+            let packet = match EthernetPacket::new(&record.data) {
+                Some(packet) => packet,
+                // too short to be a frame, nothing sensible to send
+                None => continue,
+            };
+            tx.send_to(&packet, None)
+                .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Sleep until `deadline`, returning false if `stop` was set in the meantime.
+fn sleep_until(deadline: Instant, stop: &AtomicBool) -> bool {
+    loop {
+        if stop.load(Ordering::SeqCst) {
+            return false;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        // wake up regularly to notice `stop` during long gaps
+        thread::sleep((deadline - now).min(Duration::from_millis(100)));
+    }
+}
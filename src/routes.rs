@@ -0,0 +1,395 @@
+//! Routing: which gateway and interface packets to an address leave through.
+//!
+//! A [`RoutingTable`] holds routes to networks and picks the one with the longest prefix
+//! holding the destination, the lowest metric breaking ties. Tables are put together with
+//! [`RoutingTable::builder`] or loaded from lines in the format of `ip route`:
+//!
+//! ```text
+//! default via 192.168.0.1 dev eth0 metric 100
+//! 192.168.0.0/24 dev eth0
+//! 10.8.0.0/16 via 10.8.0.1 dev tun0
+//! ```
+//!
+//! On Linux, [`system_routes`] reads the main table of the kernel instead, over netlink.
+
+#[cfg(target_os = "linux")]
+use crate::arp::network_interface::{self, netlink};
+use crate::cidr::IpCidr;
+use std::{
+    error, fmt,
+    io::{self, BufRead},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+// route types `ip route` puts before the destination of routes that aren't unicast
+const ROUTE_TYPES: &[&str] = &[
+    "unicast",
+    "local",
+    "broadcast",
+    "multicast",
+    "anycast",
+    "unreachable",
+    "blackhole",
+    "prohibit",
+    "throw",
+];
+
+// route types discarding packets instead of sending them anywhere
+const DISCARDING: &[&str] = &["unreachable", "blackhole", "prohibit", "throw"];
+
+// attributes that are flags without a value
+const FLAGS: &[&str] = &[
+    "onlink",
+    "linkdown",
+    "dead",
+    "pervasive",
+    "offload",
+    "trap",
+    "rt_offload",
+    "rt_trap",
+    "rt_offload_failed",
+];
+
+/// Error returned when parsing a route fails.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RouteParseError(String);
+
+impl fmt::Display for RouteParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid route: {}", self.0)
+    }
+}
+
+impl error::Error for RouteParseError {}
+
+/// A route to a network.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Route {
+    /// The network the route leads to
+    pub destination: IpCidr,
+    /// The router packets are handed to, None if the network is on the link
+    pub gateway: Option<IpAddr>,
+    /// Name of the interface packets leave through
+    pub interface: String,
+    /// Preference among routes to the same network, the lowest wins
+    pub metric: u32,
+}
+
+impl Route {
+    /// A route to `destination` on the link of `interface`.
+    pub fn new(destination: IpCidr, interface: &str) -> Route {
+        Route {
+            destination,
+            gateway: None,
+            interface: interface.to_owned(),
+            metric: 0,
+        }
+    }
+
+    /// The address packets to `destination` are sent to on the link: the gateway, or
+    /// `destination` itself.
+    pub fn next_hop(&self, destination: IpAddr) -> IpAddr {
+        self.gateway.unwrap_or(destination)
+    }
+
+    /// Whether this is a default route.
+    pub fn is_default(&self) -> bool {
+        self.destination.prefix_len() == 0
+    }
+}
+
+impl fmt::Display for Route {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_default() {
+            write!(f, "default")?;
+        } else {
+            write!(f, "{}", self.destination)?;
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {}", gateway)?;
+        }
+        write!(f, " dev {}", self.interface)?;
+        if self.metric != 0 {
+            write!(f, " metric {}", self.metric)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Route {
+    type Err = RouteParseError;
+
+    /// Parse a route in the format of `ip route`. A route type before the destination, as
+    /// in `local 127.0.0.1 dev lo`, and flags like `onlink` are skipped, and so are
+    /// attributes other than `via`, `dev` and `metric` along with their value; a `default`
+    /// route is IPv4 unless its gateway is IPv6.
+    fn from_str(s: &str) -> Result<Route, RouteParseError> {
+        let error = || RouteParseError(s.to_owned());
+        let mut words = s.split_whitespace().peekable();
+        words.next_if(|word| ROUTE_TYPES.contains(word));
+        let destination = words.next().ok_or_else(error)?;
+        let mut gateway = None;
+        let mut interface = None;
+        let mut metric = 0;
+        while let Some(word) = words.next() {
+            if FLAGS.contains(&word) {
+                continue;
+            }
+            let value = words.next().ok_or_else(error)?;
+            match word {
+                "via" => gateway = Some(value.parse().map_err(|_| error())?),
+                "dev" => interface = Some(value.to_owned()),
+                "metric" => metric = value.parse().map_err(|_| error())?,
+                _ => {}
+            }
+        }
+        let destination = match destination {
+            "default" => match gateway {
+                Some(IpAddr::V6(_)) => IpCidr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+                _ => IpCidr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            },
+            destination => destination.parse().map_err(|_| error())?,
+        };
+        if let (Some(gateway), IpCidr::V4(_)) = (gateway, destination) {
+            if !gateway.is_ipv4() {
+                return Err(error());
+            }
+        }
+        Ok(Route {
+            destination,
+            gateway,
+            interface: interface.ok_or_else(error)?,
+            metric,
+        })
+    }
+}
+
+/// Routes to look destinations up in.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RoutingTable {
+    routes: Vec<Route>,
+}
+
+impl RoutingTable {
+    /// An empty table.
+    pub fn new() -> RoutingTable {
+        RoutingTable::default()
+    }
+
+    /// Start putting a table together.
+    pub fn builder() -> RoutingTableBuilder {
+        RoutingTableBuilder {
+            table: RoutingTable::new(),
+        }
+    }
+
+    /// Read a table from lines in the format of `ip route`, skipping blank lines, `#`
+    /// comments and routes discarding packets, like `unreachable` ones. Fails with
+    /// `InvalidData` on a line that isn't a route.
+    pub fn load<R: BufRead>(reader: R) -> io::Result<RoutingTable> {
+        let mut table = RoutingTable::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            let kind = line.split_whitespace().next();
+            if kind.map_or(true, |kind| {
+                kind.starts_with('#') || DISCARDING.contains(&kind)
+            }) {
+                continue;
+            }
+            let route = line
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            table.insert(route);
+        }
+        Ok(table)
+    }
+
+    /// Add `route`, replacing the one to the same network with the same metric.
+    pub fn insert(&mut self, route: Route) {
+        self.remove(route.destination, route.metric);
+        self.routes.push(route);
+    }
+
+    /// Remove the route to `destination` with `metric`, returning it.
+    pub fn remove(&mut self, destination: IpCidr, metric: u32) -> Option<Route> {
+        let destination = normalize(destination);
+        let index = self.routes.iter().position(|route| {
+            normalize(route.destination) == destination && route.metric == metric
+        })?;
+        Some(self.routes.remove(index))
+    }
+
+    /// The route packets to `destination` take: the one to the network with the longest
+    /// prefix holding it, with the lowest metric among those. None if no route leads there.
+    pub fn lookup(&self, destination: IpAddr) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.destination.contains(destination))
+            .min_by_key(|route| (u8::MAX - route.destination.prefix_len(), route.metric))
+    }
+
+    /// The address packets to `destination` are sent to on the link, and the interface
+    /// they leave through.
+    pub fn next_hop(&self, destination: IpAddr) -> Option<(IpAddr, &str)> {
+        self.lookup(destination)
+            .map(|route| (route.next_hop(destination), route.interface.as_str()))
+    }
+
+    /// The default route with the lowest metric, of IPv6 if `ipv6` and IPv4 otherwise.
+    pub fn default_route(&self, ipv6: bool) -> Option<&Route> {
+        self.routes
+            .iter()
+            .filter(|route| route.is_default() && route.destination.address().is_ipv6() == ipv6)
+            .min_by_key(|route| route.metric)
+    }
+
+    /// The routes, in the order they were added.
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    /// Number of routes.
+    pub fn len(&self) -> usize {
+        self.routes.len()
+    }
+
+    /// Whether there are no routes.
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl fmt::Display for RoutingTable {
+    /// One route per line, in the format [`RoutingTable::load`] reads.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for route in &self.routes {
+            writeln!(f, "{}", route)?;
+        }
+        Ok(())
+    }
+}
+
+/// The unicast routes of the main routing table of the kernel, IPv4 and IPv6.
+#[cfg(target_os = "linux")]
+pub fn system_routes() -> io::Result<RoutingTable> {
+    let interfaces = network_interface::get_interfaces();
+    let name_of = |index| {
+        interfaces
+            .iter()
+            .find(|interface| interface.index == index)
+            .map(|interface| interface.name.clone())
+    };
+    let mut table = RoutingTable::new();
+    for route in netlink::routes(name_of)? {
+        table.insert(route);
+    }
+    Ok(table)
+}
+
+/// The IPv4 gateway of the default route through `interface` with the lowest metric,
+/// None if there is no such route. ARP requests for destinations off the link go to it.
+#[cfg(target_os = "linux")]
+pub fn default_gateway(interface: &str) -> io::Result<Option<Ipv4Addr>> {
+    let table = system_routes()?;
+    Ok(table
+        .routes()
+        .iter()
+        .filter(|route| route.is_default() && route.interface == interface)
+        .filter_map(|route| match route.gateway {
+            Some(IpAddr::V4(gateway)) => Some((route.metric, gateway)),
+            _ => None,
+        })
+        .min()
+        .map(|(_, gateway)| gateway))
+}
+
+/// Puts a [`RoutingTable`] together.
+#[derive(Clone, Debug)]
+pub struct RoutingTableBuilder {
+    table: RoutingTable,
+}
+
+impl RoutingTableBuilder {
+    /// Reach `destination` on the link of `interface`.
+    pub fn link(mut self, destination: IpCidr, interface: &str) -> RoutingTableBuilder {
+        self.table.insert(Route::new(destination, interface));
+        self
+    }
+
+    /// Reach `destination` through `gateway`, on the link of `interface`.
+    pub fn via(
+        mut self,
+        destination: IpCidr,
+        gateway: IpAddr,
+        interface: &str,
+    ) -> RoutingTableBuilder {
+        self.table.insert(Route {
+            gateway: Some(gateway),
+            ..Route::new(destination, interface)
+        });
+        self
+    }
+
+    /// Send what no other route leads to through `gateway`.
+    pub fn default_gateway(self, gateway: IpAddr, interface: &str) -> RoutingTableBuilder {
+        let any = match gateway {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        self.via(IpCidr::new(any, 0), gateway, interface)
+    }
+
+    /// Add `route` as it is.
+    pub fn route(mut self, route: Route) -> RoutingTableBuilder {
+        self.table.insert(route);
+        self
+    }
+
+    /// The table.
+    pub fn build(self) -> RoutingTable {
+        self.table
+    }
+}
+
+// networks compare by their first address, not the address they were given with
+fn normalize(cidr: IpCidr) -> IpCidr {
+    IpCidr::new(cidr.network(), cidr.prefix_len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ip_route_output() {
+        let route: Route = "10.1.0.0/16 via 10.0.0.1 dev eth0 proto static onlink metric 5"
+            .parse()
+            .unwrap();
+        assert_eq!(route.gateway, Some("10.0.0.1".parse().unwrap()));
+        assert_eq!((route.interface.as_str(), route.metric), ("eth0", 5));
+        let route: Route = "local 127.0.0.1 dev lo table local proto kernel scope host"
+            .parse()
+            .unwrap();
+        assert_eq!(route.destination, "127.0.0.1/32".parse().unwrap());
+        assert_eq!(route.interface, "lo");
+        let route: Route = "default via 192.168.0.1 dev wlan0 linkdown dead"
+            .parse()
+            .unwrap();
+        assert!(route.is_default());
+        assert!("10.0.0.0/8 dev".parse::<Route>().is_err());
+
+        let output = "\
+# main table
+default via 192.168.0.1 dev eth0 proto dhcp metric 100
+unreachable 10.0.0.0/8 metric 1
+blackhole 10.9.0.0/16
+192.168.0.0/24 dev eth0 proto kernel scope link src 192.168.0.7 linkdown
+";
+        let table = RoutingTable::load(output.as_bytes()).unwrap();
+        assert_eq!(table.len(), 2);
+        let next_hop = table.next_hop("192.168.0.9".parse().unwrap());
+        assert_eq!(next_hop, Some(("192.168.0.9".parse().unwrap(), "eth0")));
+    }
+}
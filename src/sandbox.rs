@@ -0,0 +1,233 @@
+//! Seccomp sandbox for capture tools.
+//!
+//! [`install`] restricts every thread of the process to the system calls a sniffer or
+//! responder needs once its channels are open: reading and writing descriptors, waiting on
+//! them, memory management, time and exiting. Anything else, opening files or sockets
+//! included, is refused. Call it once setup is done, after
+//! [`crate::privileges::drop_privileges`] if privileges are dropped.
+
+use std::io;
+
+/// System calls for creating, rotating and syncing capture files.
+pub const FILES: &[libc::c_long] = &[
+    libc::SYS_openat,
+    libc::SYS_fsync,
+    libc::SYS_fdatasync,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_unlinkat,
+    libc::SYS_ftruncate,
+];
+
+/// System calls allowed by every sandbox.
+pub const BASE: &[libc::c_long] = &[
+    libc::SYS_read,
+    libc::SYS_readv,
+    libc::SYS_write,
+    libc::SYS_writev,
+    libc::SYS_lseek,
+    libc::SYS_close,
+    libc::SYS_fstat,
+    libc::SYS_fcntl,
+    libc::SYS_recvfrom,
+    libc::SYS_recvmsg,
+    libc::SYS_recvmmsg,
+    libc::SYS_sendto,
+    libc::SYS_sendmsg,
+    libc::SYS_sendmmsg,
+    libc::SYS_ppoll,
+    libc::SYS_pselect6,
+    libc::SYS_epoll_pwait,
+    libc::SYS_futex,
+    libc::SYS_sched_yield,
+    libc::SYS_nanosleep,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_clock_gettime,
+    libc::SYS_gettimeofday,
+    libc::SYS_getrandom,
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_madvise,
+    libc::SYS_mprotect,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_sigaltstack,
+    libc::SYS_restart_syscall,
+    // made by threads as they start, which may happen right after installing
+    libc::SYS_rseq,
+    libc::SYS_set_robust_list,
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+// older variants only x86_64 still has
+#[cfg(target_arch = "x86_64")]
+const LEGACY: &[libc::c_long] = &[libc::SYS_poll, libc::SYS_select, libc::SYS_epoll_wait];
+#[cfg(not(target_arch = "x86_64"))]
+const LEGACY: &[libc::c_long] = &[];
+
+/// What happens to a thread making a system call that is not allowed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Action {
+    /// Kill the process
+    Kill,
+    /// Fail the call with this errno, handy while finding out which calls are missing
+    Errno(u16),
+}
+
+/// Sandbox parameters.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// System calls allowed in addition to `BASE`, e.g. `FILES` for rotating captures.
+    /// Defaults to none
+    pub allow: Vec<libc::c_long>,
+
+    /// What disallowed calls do. Defaults to Kill
+    pub action: Action,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            allow: Vec::new(),
+            action: Action::Kill,
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// x32 system calls share the x86_64 audit architecture and are told apart by this bit
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+const SECCOMP_SET_MODE_FILTER: libc::c_uint = 1;
+const SECCOMP_FILTER_FLAG_TSYNC: libc::c_uint = 1;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+/// Most instructions the kernel accepts in a filter.
+const BPF_MAXINSNS: usize = 4096;
+
+// offsets in `struct seccomp_data`
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+#[repr(C)]
+struct SockFprog {
+    len: libc::c_ushort,
+    filter: *const SockFilter,
+}
+
+fn statement(code: u16, k: u32) -> SockFilter {
+    SockFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn jump(code: u16, k: u32, jt: u8, jf: u8) -> SockFilter {
+    SockFilter { code, jt, jf, k }
+}
+
+/// Build the BPF program checking the architecture and the system call number.
+fn program(config: &Config) -> Vec<SockFilter> {
+    let denied = match config.action {
+        Action::Kill => SECCOMP_RET_KILL_PROCESS,
+        Action::Errno(errno) => SECCOMP_RET_ERRNO | u32::from(errno),
+    };
+    let mut filter = vec![
+        statement(BPF_LD_W_ABS, OFFSET_ARCH),
+        jump(BPF_JEQ_K, AUDIT_ARCH, 1, 0),
+        statement(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        statement(BPF_LD_W_ABS, OFFSET_NR),
+        jump(BPF_JGE_K, X32_SYSCALL_BIT, 0, 1),
+        statement(BPF_RET_K, denied),
+    ];
+    for &nr in BASE.iter().chain(LEGACY).chain(config.allow.iter()) {
+        filter.push(jump(BPF_JEQ_K, nr as u32, 0, 1));
+        filter.push(statement(BPF_RET_K, SECCOMP_RET_ALLOW));
+    }
+    filter.push(statement(BPF_RET_K, denied));
+    filter
+}
+
+/// Restrict all threads of the process to `BASE` and `config.allow` for good.
+///
+/// Threads cannot be created afterwards, so worker threads have to be started first.
+///
+/// Also sets `no_new_privs`, so that the filter can be installed without
+/// `CAP_SYS_ADMIN` and executed programs cannot gain privileges.
+pub fn install(config: &Config) -> io::Result<()> {
+    let filter = program(config);
+    if filter.len() > BPF_MAXINSNS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many allowed system calls",
+        ));
+    }
+    let prog = SockFprog {
+        len: filter.len() as libc::c_ushort,
+        filter: filter.as_ptr(),
+    };
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            SECCOMP_FILTER_FLAG_TSYNC,
+            &prog as *const SockFprog,
+        )
+    };
+    match ret {
+        0 => Ok(()),
+        -1 => Err(io::Error::last_os_error()),
+        // with TSYNC, the id of a thread that could not be synchronized
+        thread => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("thread {} could not be sandboxed", thread),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_fit_the_kernel_limit() {
+        let filter = program(&Config::default());
+        assert_eq!(filter.len(), 7 + 2 * (BASE.len() + LEGACY.len()));
+        assert!(filter.len() <= BPF_MAXINSNS);
+
+        // rejected before the process is touched
+        let config = Config {
+            allow: vec![libc::SYS_getpid; BPF_MAXINSNS / 2],
+            ..Default::default()
+        };
+        let rejected = install(&config).unwrap_err();
+        assert_eq!(rejected.kind(), io::ErrorKind::InvalidInput);
+    }
+}
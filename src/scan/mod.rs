@@ -0,0 +1,3 @@
+//! Network scanning.
+
+pub mod ports;
@@ -0,0 +1,472 @@
+//! TCP and UDP port scanning.
+//!
+//! Three techniques are offered. A SYN scan crafts TCP SYN segments and classifies the
+//! replies read from a datalink channel; it never completes a handshake and needs raw
+//! access. A connect scan and a UDP probe use ordinary sockets instead and work without
+//! privileges. All of them keep a bounded number of probes in flight and report the
+//! round trip time of every port that answered.
+
+use crate::{
+    arp::{
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    checksum,
+    ipproto::{IPPROTO_ICMP, IPPROTO_TCP},
+    sniff::ParseError,
+    ttl::DEFAULT_TTL,
+};
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_ACK: u8 = 0x10;
+
+/// Scanning technique.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Mode {
+    /// Half-open scan with crafted SYN segments
+    Syn,
+    /// Full TCP connection through the operating system
+    Connect,
+    /// Empty UDP datagram, relying on ICMP port unreachable for closed ports
+    Udp,
+}
+
+impl std::str::FromStr for Mode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Mode, ParseError> {
+        match s {
+            "syn" => Ok(Mode::Syn),
+            "connect" => Ok(Mode::Connect),
+            "udp" => Ok(Mode::Udp),
+            _ => Err(ParseError(format!("unknown scan mode `{}`", s))),
+        }
+    }
+}
+
+/// What a probe revealed about a port.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PortState {
+    /// Something accepts connections or answered the datagram
+    Open,
+    /// The host answered that nothing listens
+    Closed,
+    /// No answer, or an ICMP error other than port unreachable
+    Filtered,
+    /// UDP only: no answer, the port may be open or the probe dropped
+    OpenFiltered,
+}
+
+impl std::fmt::Display for PortState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::OpenFiltered => "open|filtered",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Result of scanning one port.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PortResult {
+    /// Port number
+    pub port: u16,
+    /// Port state
+    pub state: PortState,
+    /// Time between the last probe and its answer, None if there was no answer
+    pub rtt: Option<Duration>,
+}
+
+/// Scan parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Time to wait for an answer to a probe. Defaults to 1 second
+    pub timeout: Duration,
+
+    /// Number of probes sent again to ports that didn't answer. Defaults to 1
+    pub retries: usize,
+
+    /// Maximum number of probes in flight. Defaults to 64
+    pub concurrency: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            timeout: Duration::from_secs(1),
+            retries: 1,
+            concurrency: 64,
+        }
+    }
+}
+
+/// Parse a port list like `22,80,8000-8100`.
+pub fn parse_ports(spec: &str) -> Result<Vec<u16>, ParseError> {
+    let invalid = || ParseError(format!("invalid port list `{}`", spec));
+    let mut ports = Vec::new();
+    for part in spec.split(',') {
+        let (first, last) = match part.find('-') {
+            Some(dash) => (&part[..dash], &part[dash + 1..]),
+            None => (part, part),
+        };
+        let first: u16 = first.parse().map_err(|_| invalid())?;
+        let last: u16 = last.parse().map_err(|_| invalid())?;
+        if first == 0 || first > last {
+            return Err(invalid());
+        }
+        ports.extend(first..=last);
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Ok(ports)
+}
+
+/// An IPv4 address together with the MAC address of its interface.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Ipv4Mac {
+    /// MAC address
+    pub mac: MacAddr,
+    /// IPv4 address
+    pub ip: Ipv4Addr,
+}
+
+/// Addresses used by a SYN scan.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SynTarget {
+    /// Addresses of the scanning interface
+    pub source: Ipv4Mac,
+    /// Host to scan
+    pub target: Ipv4Addr,
+    /// MAC address of the target, or of the gateway if it is not on the local network
+    pub next_hop: MacAddr,
+}
+
+/// Build an Ethernet frame carrying a TCP SYN segment with an MSS option.
+pub fn syn_frame(
+    source: Ipv4Mac,
+    next_hop: MacAddr,
+    target: Ipv4Addr,
+    source_port: u16,
+    port: u16,
+    sequence: u32,
+    identification: u16,
+) -> Vec<u8> {
+    let mut frame = vec![0u8; 14 + 20 + 24];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+    ethernet.set_destination(next_hop);
+    ethernet.set_source(source.mac);
+    ethernet.set_ethertype(EtherTypes::Ipv4);
+
+    let ip = &mut frame[14..];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(20u16 + 24).to_be_bytes());
+    ip[4..6].copy_from_slice(&identification.to_be_bytes());
+    ip[8] = DEFAULT_TTL;
+    ip[9] = IPPROTO_TCP;
+    ip[12..16].copy_from_slice(&source.ip.octets());
+    ip[16..20].copy_from_slice(&target.octets());
+
+    let tcp = &mut ip[20..];
+    tcp[0..2].copy_from_slice(&source_port.to_be_bytes());
+    tcp[2..4].copy_from_slice(&port.to_be_bytes());
+    tcp[4..8].copy_from_slice(&sequence.to_be_bytes());
+    // 24 byte header
+    tcp[12] = 6 << 4;
+    tcp[13] = TCP_SYN;
+    tcp[14..16].copy_from_slice(&1024u16.to_be_bytes());
+    // MSS 1460
+    tcp[20..24].copy_from_slice(&[2, 4, 0x05, 0xb4]);
+
+    checksum::update_ipv4_frame(&mut frame);
+    frame
+}
+
+struct Probe {
+    sent: Instant,
+    attempts: usize,
+}
+
+struct SynProber<'t> {
+    tx: &'t mut dyn EthernetDataLinkSender,
+    target: SynTarget,
+    source_port: u16,
+    sequence: u32,
+    identification: u16,
+}
+
+impl<'t> SynProber<'t> {
+    fn send(&mut self, port: u16) -> io::Result<()> {
+        self.identification = self.identification.wrapping_add(1);
+        let frame = syn_frame(
+            self.target.source,
+            self.target.next_hop,
+            self.target.target,
+            self.source_port,
+            port,
+            self.sequence,
+            self.identification,
+        );
+        let packet = EthernetPacket::new(&frame[..]).unwrap();
+        self.tx
+            .send_to(&packet, None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+    }
+}
+
+/// Scan `ports` of `target.target` with crafted SYN segments.
+///
+/// `rx` should be opened with a short `read_timeout` so that timeouts and `stop` are
+/// noticed. Ports not scanned when `stop` is set are left out of the result, which is
+/// ordered by port.
+pub fn syn_scan(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    target: &SynTarget,
+    ports: &[u16],
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<Vec<PortResult>> {
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let mut prober = SynProber {
+        tx,
+        target: *target,
+        // below the range the kernel picks ephemeral ports from, so replies are ours
+        source_port: 30000 + (seed % 2000) as u16,
+        sequence: seed.rotate_left(13),
+        identification: seed as u16,
+    };
+
+    let mut pending = ports.iter().copied();
+    let mut in_flight: HashMap<u16, Probe> = HashMap::new();
+    let mut results = Vec::with_capacity(ports.len());
+    let mut iter = rx.iter();
+
+    while !stop.load(Ordering::SeqCst) {
+        // retry or give up on probes that timed out
+        let now = Instant::now();
+        for (&port, probe) in in_flight.iter_mut() {
+            if now.saturating_duration_since(probe.sent) >= config.timeout
+                && probe.attempts <= config.retries
+            {
+                prober.send(port)?;
+                probe.sent = Instant::now();
+                probe.attempts += 1;
+            }
+        }
+        in_flight.retain(|&port, probe| {
+            let unanswered = now.saturating_duration_since(probe.sent) >= config.timeout;
+            if unanswered {
+                results.push(PortResult {
+                    port,
+                    state: PortState::Filtered,
+                    rtt: None,
+                });
+            }
+            !unanswered
+        });
+
+        while in_flight.len() < config.concurrency.max(1) {
+            let port = match pending.next() {
+                Some(port) => port,
+                None => break,
+            };
+            prober.send(port)?;
+            in_flight.insert(
+                port,
+                Probe {
+                    sent: Instant::now(),
+                    attempts: 1,
+                },
+            );
+        }
+        if in_flight.is_empty() {
+            break;
+        }
+
+        let packet = match iter.next() {
+            Ok(packet) => packet,
+            Err(ref e)
+                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        if let Some((port, state)) = classify(&packet, target.target, prober.source_port) {
+            if let Some(probe) = in_flight.remove(&port) {
+                results.push(PortResult {
+                    port,
+                    state,
+                    rtt: Some(probe.sent.elapsed()),
+                });
+            }
+        }
+    }
+
+    results.sort_by_key(|result| result.port);
+    Ok(results)
+}
+
+/// Match a received frame to a probe, returning the probed port and what the answer says.
+fn classify(
+    packet: &EthernetPacket,
+    target: Ipv4Addr,
+    source_port: u16,
+) -> Option<(u16, PortState)> {
+    if packet.get_ethertype() != EtherTypes::Ipv4 {
+        return None;
+    }
+    let ip = packet.payload();
+    if ip.len() < 20 {
+        return None;
+    }
+    let ihl = (ip[0] & 0x0f) as usize * 4;
+    let from = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+    let transport = ip.get(ihl..)?;
+
+    match ip[9] {
+        IPPROTO_TCP if from == target && transport.len() >= 14 => {
+            let port = u16::from_be_bytes([transport[0], transport[1]]);
+            let dst_port = u16::from_be_bytes([transport[2], transport[3]]);
+            if dst_port != source_port {
+                return None;
+            }
+            let flags = transport[13];
+            if flags & (TCP_SYN | TCP_ACK) == TCP_SYN | TCP_ACK {
+                Some((port, PortState::Open))
+            } else if flags & TCP_RST != 0 {
+                Some((port, PortState::Closed))
+            } else {
+                None
+            }
+        }
+        // destination unreachable quoting our probe
+        IPPROTO_ICMP if transport.len() >= 8 + 20 + 4 && transport[0] == 3 => {
+            let quoted = &transport[8..];
+            let quoted_ihl = (quoted[0] & 0x0f) as usize * 4;
+            let quoted_dst = Ipv4Addr::new(quoted[16], quoted[17], quoted[18], quoted[19]);
+            let ports = quoted.get(quoted_ihl..quoted_ihl + 4)?;
+            if quoted[9] != IPPROTO_TCP
+                || quoted_dst != target
+                || u16::from_be_bytes([ports[0], ports[1]]) != source_port
+            {
+                return None;
+            }
+            let port = u16::from_be_bytes([ports[2], ports[3]]);
+            match transport[1] {
+                1 | 2 | 3 | 9 | 10 | 13 => Some((port, PortState::Filtered)),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Scan `ports` of `target` by connecting to them.
+pub fn connect_scan(target: IpAddr, ports: &[u16], config: &Config) -> Vec<PortResult> {
+    let timeout = config.timeout;
+    parallel(ports, config.concurrency, move |port| {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&SocketAddr::new(target, port), timeout) {
+            Ok(_) => (PortState::Open, Some(started.elapsed())),
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                (PortState::Closed, Some(started.elapsed()))
+            }
+            Err(_) => (PortState::Filtered, None),
+        }
+    })
+}
+
+/// Scan UDP `ports` of `target` with empty datagrams.
+///
+/// A port is closed if the host answers with ICMP port unreachable, which connected UDP
+/// sockets report as a refused connection.
+pub fn udp_scan(target: IpAddr, ports: &[u16], config: &Config) -> Vec<PortResult> {
+    let config = *config;
+    parallel(ports, config.concurrency, move |port| {
+        let bind: SocketAddr = match target {
+            IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            IpAddr::V6(_) => (std::net::Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = match UdpSocket::bind(bind) {
+            Ok(socket) => socket,
+            Err(_) => return (PortState::Filtered, None),
+        };
+        if socket.connect((target, port)).is_err()
+            || socket.set_read_timeout(Some(config.timeout)).is_err()
+        {
+            return (PortState::Filtered, None);
+        }
+
+        let mut buffer = [0u8; 1500];
+        for _ in 0..=config.retries {
+            let started = Instant::now();
+            if socket.send(&[]).is_err() {
+                return (PortState::Closed, Some(started.elapsed()));
+            }
+            match socket.recv(&mut buffer) {
+                Ok(_) => return (PortState::Open, Some(started.elapsed())),
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    return (PortState::Closed, Some(started.elapsed()))
+                }
+                Err(_) => {}
+            }
+        }
+        (PortState::OpenFiltered, None)
+    })
+}
+
+/// Probe every port with `probe`, running at most `concurrency` probes at a time.
+fn parallel<F>(ports: &[u16], concurrency: usize, probe: F) -> Vec<PortResult>
+where
+    F: Fn(u16) -> (PortState, Option<Duration>) + Send + Sync + 'static,
+{
+    let queue = Arc::new(Mutex::new(ports.to_vec().into_iter()));
+    let results = Arc::new(Mutex::new(Vec::with_capacity(ports.len())));
+    let probe = Arc::new(probe);
+
+    let workers: Vec<_> = (0..concurrency.max(1).min(ports.len()))
+        .map(|_| {
+            let queue = queue.clone();
+            let results = results.clone();
+            let probe = probe.clone();
+            thread::spawn(move || loop {
+                let port = match queue.lock().unwrap().next() {
+                    Some(port) => port,
+                    None => break,
+                };
+                let (state, rtt) = probe(port);
+                results
+                    .lock()
+                    .unwrap()
+                    .push(PortResult { port, state, rtt });
+            })
+        })
+        .collect();
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut results = std::mem::take(&mut *results.lock().unwrap());
+    results.sort_by_key(|result| result.port);
+    results
+}
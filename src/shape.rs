@@ -0,0 +1,231 @@
+//! Token bucket traffic shaping.
+//!
+//! A [`ShapingSender`] wraps a sender and delays frames so that the traffic leaving it
+//! stays within a rate and burst size, keeping generators and replays from saturating lab
+//! links. Frames can additionally be classified into flows, each with a bucket of its own.
+
+use crate::{
+    arp::{
+        channel::EthernetDataLinkSender,
+        ether::{EthernetPacket, Packet},
+        network_interface::{MacAddr, NetworkInterface},
+    },
+    flows::{FlowKey, FlowPacket},
+};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::{
+    collections::HashMap,
+    io, thread,
+    time::{Duration, Instant},
+};
+
+/// A token bucket metering bytes.
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Create a full bucket refilled at `rate` bits per second and holding at most `burst`
+    /// bytes.
+    pub fn new(rate: u64, burst: u64) -> TokenBucket {
+        TokenBucket {
+            rate: rate as f64 / 8.0,
+            burst: burst as f64,
+            tokens: burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// How long to wait before `len` bytes conform at `now`. Frames larger than the burst
+    /// size conform once the bucket is full.
+    pub fn delay(&mut self, len: usize, now: Instant) -> Duration {
+        self.refill(now);
+        let needed = (len as f64).min(self.burst);
+        if self.tokens >= needed || self.rate <= 0.0 {
+            return Duration::from_secs(0);
+        }
+        Duration::from_secs_f64((needed - self.tokens) / self.rate)
+    }
+
+    /// Take `len` bytes out of the bucket. The bucket may go into debt for frames larger
+    /// than the burst size.
+    pub fn consume(&mut self, len: usize, now: Instant) {
+        self.refill(now);
+        self.tokens -= len as f64;
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if now > self.updated {
+            let elapsed = now.duration_since(self.updated).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+            self.updated = now;
+        }
+    }
+}
+
+/// How frames are told apart for per-flow shaping.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Classifier {
+    /// By IP protocol and endpoints, regardless of direction. Other frames are only
+    /// subject to the overall limit
+    Flow,
+    /// By Ethernet source address
+    SourceMac,
+    /// By Ethernet destination address
+    DestinationMac,
+}
+
+impl std::str::FromStr for Classifier {
+    type Err = crate::sniff::ParseError;
+
+    fn from_str(s: &str) -> Result<Classifier, Self::Err> {
+        match s {
+            "flow" => Ok(Classifier::Flow),
+            "src-mac" => Ok(Classifier::SourceMac),
+            "dst-mac" => Ok(Classifier::DestinationMac),
+            _ => Err(crate::sniff::ParseError(format!(
+                "unknown classifier `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+/// Limit applied to every class of frames separately.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FlowLimit {
+    /// How frames are classified
+    pub classifier: Classifier,
+    /// Bits per second of each class
+    pub rate: u64,
+    /// Burst size of each class in bytes
+    pub burst: u64,
+}
+
+/// Shaping parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// Bits per second of all traffic. Defaults to 100 Mbit/s
+    pub rate: u64,
+
+    /// Burst size in bytes. Defaults to 64 KB
+    pub burst: u64,
+
+    /// Additional limit per class of frames, None for none. Defaults to None
+    pub per_flow: Option<FlowLimit>,
+
+    /// Most classes tracked at once; the buckets are reset when there are more. Defaults
+    /// to 4096
+    pub max_flows: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            rate: 100_000_000,
+            burst: 64_000,
+            per_flow: None,
+            max_flows: 4096,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Class {
+    Flow(FlowKey),
+    Mac(MacAddr),
+}
+
+/// A sender keeping its traffic within a [`Config`].
+pub struct ShapingSender {
+    inner: Box<dyn EthernetDataLinkSender>,
+    config: Config,
+    bucket: TokenBucket,
+    flows: HashMap<Class, TokenBucket>,
+    delayed: Duration,
+}
+
+impl ShapingSender {
+    /// Wrap `inner`, shaping the frames sent through it according to `config`.
+    pub fn new(inner: Box<dyn EthernetDataLinkSender>, config: Config) -> ShapingSender {
+        ShapingSender {
+            inner,
+            bucket: TokenBucket::new(config.rate, config.burst),
+            config,
+            flows: HashMap::new(),
+            delayed: Duration::from_secs(0),
+        }
+    }
+
+    /// Total time frames were held back.
+    pub fn delayed(&self) -> Duration {
+        self.delayed
+    }
+
+    /// The wrapped sender.
+    pub fn into_inner(self) -> Box<dyn EthernetDataLinkSender> {
+        self.inner
+    }
+
+    fn classify(&self, packet: &EthernetPacket) -> Option<Class> {
+        match self.config.per_flow?.classifier {
+            Classifier::Flow => {
+                let parsed = FlowPacket::parse(packet)?;
+                Some(Class::Flow(FlowKey::new(
+                    parsed.protocol,
+                    parsed.src,
+                    parsed.dst,
+                )))
+            }
+            Classifier::SourceMac => Some(Class::Mac(packet.get_source())),
+            Classifier::DestinationMac => Some(Class::Mac(packet.get_destination())),
+        }
+    }
+}
+
+impl EthernetDataLinkSender for ShapingSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        let len = packet.packet().len();
+        let class = self.classify(packet);
+        if let (Some(class), Some(limit)) = (class, self.config.per_flow) {
+            if !self.flows.contains_key(&class) && self.flows.len() >= self.config.max_flows {
+                self.flows.clear();
+            }
+            self.flows
+                .entry(class)
+                .or_insert_with(|| TokenBucket::new(limit.rate, limit.burst));
+        }
+
+        loop {
+            let now = Instant::now();
+            let mut delay = self.bucket.delay(len, now);
+            if let Some(bucket) = class.and_then(|class| self.flows.get_mut(&class)) {
+                delay = delay.max(bucket.delay(len, now));
+            }
+            if delay == Duration::from_secs(0) {
+                self.bucket.consume(len, now);
+                if let Some(bucket) = class.and_then(|class| self.flows.get_mut(&class)) {
+                    bucket.consume(len, now);
+                }
+                break;
+            }
+            thread::sleep(delay);
+            self.delayed += delay;
+        }
+        self.inner.send_to(packet, dst)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
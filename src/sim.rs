@@ -0,0 +1,552 @@
+//! Deterministic virtual network.
+//!
+//! A [`Network`] owns a set of nodes connected by point-to-point links. Every node hands
+//! out ordinary `Channel`s, each with its own receive queue, and the network is a
+//! `DatalinkBackend` for the interfaces of its nodes, so anything written against the
+//! datalink traits can be attached to it. Frames sent by a node travel over every link
+//! attached to it, taking the link's serialization time and delay, and may be dropped
+//! according to the link's loss rate. Nothing happens on its own: time only moves when
+//! [`Network::advance`] or [`Network::step`] is called, and losses come from a seeded
+//! generator, so a run is fully reproducible. The network is also a `Clock` showing that
+//! time, for the timers of the stacks attached to it.
+
+use crate::{
+    arp::{
+        channel::{
+            Channel, Config, DatalinkBackend, EthernetDataLinkChannelIterator,
+            EthernetDataLinkReceiver, EthernetDataLinkSender,
+        },
+        ether::{EthernetPacket, Packet},
+        network_interface::{LinkType, MacAddr, NetworkInterface, OperState, FLAG_UP},
+    },
+    cidr::IpCidr,
+    clock::Clock,
+    rng::Rng,
+};
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fmt, io,
+    net::IpAddr,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Identifies a node of a `Network`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NodeId(pub usize);
+
+/// Properties of a link between two nodes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkConfig {
+    /// One-way propagation delay. Defaults to zero
+    pub delay: Duration,
+
+    /// Probability in `[0, 1]` that a frame is lost. Defaults to 0
+    pub loss: f64,
+
+    /// Link rate in bits per second, None for infinitely fast. Defaults to None
+    pub bandwidth: Option<u64>,
+}
+
+impl Default for LinkConfig {
+    fn default() -> LinkConfig {
+        LinkConfig {
+            delay: Duration::from_secs(0),
+            loss: 0.0,
+            bandwidth: None,
+        }
+    }
+}
+
+/// Frame counters of a `Network`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Frames handed to links
+    pub sent: u64,
+    /// Frames that reached a node
+    pub delivered: u64,
+    /// Frames dropped by link loss
+    pub lost: u64,
+}
+
+/// A deterministic virtual network driven by a virtual clock.
+///
+/// As a `Clock` its time starts at the `Instant` the network was created and only moves
+/// with the network. Sleeping advances the network, like it advances a `MockClock`.
+#[derive(Clone)]
+pub struct Network {
+    inner: Arc<Mutex<Inner>>,
+    epoch: Instant,
+}
+
+struct Inner {
+    now: Duration,
+    nodes: Vec<Node>,
+    links: Vec<Link>,
+    in_flight: BinaryHeap<Reverse<InFlight>>,
+    sequence: u64,
+    rng: Rng,
+    stats: Stats,
+}
+
+struct Node {
+    interface: NetworkInterface,
+    // one per open channel, each getting a copy of every frame
+    channels: Vec<mpsc::Sender<Vec<u8>>>,
+}
+
+struct Link {
+    ends: [NodeId; 2],
+    config: LinkConfig,
+    // time at which each direction finishes transmitting its last frame
+    busy_until: [Duration; 2],
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    at: Duration,
+    sequence: u64,
+    to: NodeId,
+    frame: Vec<u8>,
+}
+
+impl Network {
+    /// Create an empty network. `seed` drives the loss decisions.
+    pub fn new(seed: u64) -> Network {
+        Network {
+            inner: Arc::new(Mutex::new(Inner {
+                now: Duration::from_secs(0),
+                nodes: Vec::new(),
+                links: Vec::new(),
+                in_flight: BinaryHeap::new(),
+                sequence: 0,
+                rng: Rng::new(seed),
+                stats: Default::default(),
+            })),
+            epoch: Instant::now(),
+        }
+    }
+
+    /// Add a node with the given interface name and MAC address.
+    pub fn add_node(&self, name: &str, mac: MacAddr) -> NodeId {
+        let mut inner = self.inner.lock().unwrap();
+        let id = NodeId(inner.nodes.len());
+        inner.nodes.push(Node {
+            interface: NetworkInterface {
+                name: name.to_owned(),
+                index: id.0 as u32 + 1,
+                mac: Some(mac),
+                ips: None,
+                flags: FLAG_UP,
+                mtu: None,
+                oper_state: OperState::Up,
+                link_type: LinkType::ETHER,
+                addresses: Vec::new(),
+            },
+            channels: Vec::new(),
+        });
+        id
+    }
+
+    /// Describe a node as having the IP addresses `ips`. Defaults to none.
+    pub fn set_addresses(&self, node: NodeId, ips: Vec<IpAddr>) {
+        let mut inner = self.inner.lock().unwrap();
+        let interface = &mut inner.nodes[node.0].interface;
+        // a host route each, as nodes have no networks
+        interface.addresses = ips.iter().map(|ip| IpCidr::host(*ip)).collect();
+        interface.ips = Some(ips);
+    }
+
+    /// Connect two nodes with a link.
+    pub fn link(&self, a: NodeId, b: NodeId, config: LinkConfig) {
+        let mut inner = self.inner.lock().unwrap();
+        let now = inner.now;
+        inner.links.push(Link {
+            ends: [a, b],
+            config,
+            busy_until: [now, now],
+        });
+    }
+
+    /// Return the interface description of a node.
+    pub fn interface(&self, node: NodeId) -> NetworkInterface {
+        self.inner.lock().unwrap().nodes[node.0].interface.clone()
+    }
+
+    /// Open a channel on a node. It receives every frame reaching the node from the time
+    /// it is opened.
+    ///
+    /// Without a `read_timeout` receiving never blocks: when no frame has arrived by the
+    /// current virtual time the iterator returns an `io::ErrorKind::WouldBlock` error.
+    /// With one, receiving waits up to that long in real time for another thread to move
+    /// the network and deliver a frame, then returns an `io::ErrorKind::TimedOut` error.
+    /// Only `read_buffer_size` and `read_timeout` of `config` are used.
+    pub fn channel(&self, node: NodeId, config: Config) -> io::Result<Channel> {
+        let (tx, rx) = mpsc::channel();
+        self.inner.lock().unwrap().nodes[node.0].channels.push(tx);
+        let sender = Box::new(SimSender {
+            network: self.clone(),
+            node,
+        });
+        let receiver = Box::new(SimReceiver {
+            queue: rx,
+            read_buffer: Vec::with_capacity(config.read_buffer_size),
+            read_buffer_size: config.read_buffer_size,
+            timeout: config.read_timeout,
+        });
+        Ok(Channel::Ethernet(sender, receiver))
+    }
+
+    /// Return the current virtual time.
+    pub fn now(&self) -> Duration {
+        self.inner.lock().unwrap().now
+    }
+
+    /// Return the frame counters.
+    pub fn stats(&self) -> Stats {
+        self.inner.lock().unwrap().stats
+    }
+
+    /// Number of frames still travelling over links.
+    pub fn in_flight(&self) -> usize {
+        self.inner.lock().unwrap().in_flight.len()
+    }
+
+    /// Move the virtual clock forward by `by`, delivering every frame due in that time.
+    pub fn advance(&self, by: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let until = inner.now + by;
+        inner.deliver_until(until);
+        inner.now = until;
+    }
+
+    /// Move the virtual clock to the next frame arrival and deliver it.
+    ///
+    /// Returns false when nothing is in flight.
+    pub fn step(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let at = match inner.in_flight.peek() {
+            Some(Reverse(next)) => next.at,
+            None => return false,
+        };
+        inner.deliver_until(at);
+        inner.now = at;
+        true
+    }
+
+    fn send(&self, from: NodeId, frame: &[u8]) {
+        self.inner.lock().unwrap().send(from, frame)
+    }
+}
+
+impl fmt::Debug for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Network")
+            .field("now", &inner.now)
+            .field("nodes", &inner.nodes.len())
+            .field("links", &inner.links.len())
+            .field("stats", &inner.stats)
+            .finish()
+    }
+}
+
+impl Clock for Network {
+    fn now(&self) -> Instant {
+        self.epoch + Network::now(self)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+impl DatalinkBackend for Network {
+    /// Open a channel on the node `network_interface` describes.
+    fn channel(&self, network_interface: &NetworkInterface, config: Config) -> io::Result<Channel> {
+        let node = {
+            let inner = self.inner.lock().unwrap();
+            inner
+                .nodes
+                .iter()
+                .position(|node| node.interface.index == network_interface.index)
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no node {} in the network", network_interface.name),
+                    )
+                })?
+        };
+        Network::channel(self, NodeId(node), config)
+    }
+
+    /// The interfaces of the nodes.
+    fn interfaces(&self) -> io::Result<Vec<NetworkInterface>> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .nodes
+            .iter()
+            .map(|node| node.interface.clone())
+            .collect())
+    }
+}
+
+impl Inner {
+    fn send(&mut self, from: NodeId, frame: &[u8]) {
+        let now = self.now;
+        for i in 0..self.links.len() {
+            let (direction, to) = match self.links[i].ends {
+                [a, b] if a == from => (0, b),
+                [a, b] if b == from => (1, a),
+                _ => continue,
+            };
+            self.stats.sent += 1;
+
+            let config = self.links[i].config;
+            let start = self.links[i].busy_until[direction].max(now);
+            let serialization = match config.bandwidth {
+                Some(bps) if bps > 0 => {
+                    Duration::from_nanos(frame.len() as u64 * 8 * 1_000_000_000 / bps)
+                }
+                _ => Duration::from_secs(0),
+            };
+            self.links[i].busy_until[direction] = start + serialization;
+
+            if self.rng.next_f64() < config.loss {
+                self.stats.lost += 1;
+                continue;
+            }
+
+            self.sequence += 1;
+            self.in_flight.push(Reverse(InFlight {
+                at: start + serialization + config.delay,
+                sequence: self.sequence,
+                to,
+                frame: frame.to_vec(),
+            }));
+        }
+        // over links without delay or rate limit frames arrive as they are sent
+        self.deliver_until(now);
+    }
+
+    fn deliver_until(&mut self, until: Duration) {
+        while let Some(Reverse(next)) = self.in_flight.peek() {
+            if next.at > until {
+                break;
+            }
+            let Reverse(arrived) = self.in_flight.pop().unwrap();
+            // channels that were dropped are pruned on the way
+            self.nodes[arrived.to.0]
+                .channels
+                .retain(|channel| channel.send(arrived.frame.clone()).is_ok());
+            self.stats.delivered += 1;
+        }
+    }
+}
+
+struct SimSender {
+    network: Network,
+    node: NodeId,
+}
+
+impl EthernetDataLinkSender for SimSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        _dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        self.network.send(self.node, packet.packet());
+        Some(Ok(()))
+    }
+}
+
+struct SimReceiver {
+    queue: mpsc::Receiver<Vec<u8>>,
+    read_buffer: Vec<u8>,
+    read_buffer_size: usize,
+    timeout: Option<Duration>,
+}
+
+impl EthernetDataLinkReceiver for SimReceiver {
+    fn iter<'a>(&'a mut self) -> Box<dyn EthernetDataLinkChannelIterator + 'a> {
+        Box::new(SimChannelIterator { pc: self })
+    }
+}
+
+struct SimChannelIterator<'a> {
+    pc: &'a mut SimReceiver,
+}
+
+impl<'a> EthernetDataLinkChannelIterator<'a> for SimChannelIterator<'a> {
+    fn next(&mut self) -> io::Result<EthernetPacket> {
+        let frame = match self.pc.timeout {
+            None => self.pc.queue.try_recv().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "No frame at current virtual time",
+                )
+            })?,
+            Some(timeout) => self
+                .pc
+                .queue
+                .recv_timeout(timeout)
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "Timed out"))?,
+        };
+
+        let len = frame.len().min(self.pc.read_buffer_size);
+        self.pc.read_buffer.clear();
+        self.pc.read_buffer.extend_from_slice(&frame[..len]);
+
+        EthernetPacket::new(&self.pc.read_buffer[..])
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Frame too short"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neighbor::{self, Arp, Neighbor, Resolver};
+    use std::{net::Ipv4Addr, thread};
+
+    fn open(
+        network: &Network,
+        node: NodeId,
+    ) -> (
+        Box<dyn EthernetDataLinkSender>,
+        Box<dyn EthernetDataLinkReceiver>,
+    ) {
+        match network.channel(node, Default::default()).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        }
+    }
+
+    fn pair(network: &Network, config: LinkConfig) -> (NodeId, NodeId) {
+        let a = network.add_node("a", MacAddr::new(2, 0, 0, 0, 0, 1));
+        let b = network.add_node("b", MacAddr::new(2, 0, 0, 0, 0, 2));
+        network.link(a, b, config);
+        (a, b)
+    }
+
+    fn frame(number: u16, len: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; len];
+        frame[14..16].copy_from_slice(&number.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn frames_arrive_on_virtual_time() {
+        let network = Network::new(1);
+        let config = LinkConfig {
+            delay: Duration::from_millis(10),
+            // a byte per microsecond
+            bandwidth: Some(8_000_000),
+            ..Default::default()
+        };
+        let (a, b) = pair(&network, config);
+        let (mut tx, _) = open(&network, a);
+        let (_, mut rx) = open(&network, b);
+        let start = Clock::now(&network);
+        for n in 0..2 {
+            tx.send_to(&EthernetPacket::new(&frame(n, 1000)).unwrap(), None);
+        }
+        let mut iter = rx.iter();
+        assert_eq!(iter.next().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        network.advance(Duration::from_millis(10));
+        assert!(iter.next().is_err());
+        assert!(network.step());
+        assert_eq!(network.now(), Duration::from_millis(11));
+        assert!(iter.next().is_ok());
+        assert!(network.step());
+        assert_eq!(network.now(), Duration::from_millis(12));
+        assert!(!network.step());
+        assert_eq!(network.in_flight(), 0);
+        assert_eq!(Clock::now(&network) - start, Duration::from_millis(12));
+    }
+
+    #[test]
+    fn channels_have_their_own_queues() {
+        let network = Network::new(1);
+        let (a, b) = pair(&network, Default::default());
+        let (mut tx, _) = open(&network, a);
+        let (_, mut first) = open(&network, b);
+        let (_, mut second) = open(&network, b);
+        tx.send_to(&EthernetPacket::new(&frame(1, 64)).unwrap(), None);
+
+        // both see the frame, without a delay at once
+        assert!(first.iter().next().is_ok());
+        assert!(first.iter().next().is_err());
+        assert!(second.iter().next().is_ok());
+        assert_eq!(network.stats().delivered, 1);
+
+        // a channel opened later only sees what comes after
+        let (_, mut third) = open(&network, b);
+        assert!(third.iter().next().is_err());
+    }
+
+    #[test]
+    fn resolving_times_out_on_virtual_time() {
+        let network = Network::new(1);
+        let (a, _) = pair(&network, Default::default());
+        let config = Config {
+            read_timeout: Some(Duration::from_millis(1)),
+            ..Default::default()
+        };
+        let (tx, rx) = match network.channel(a, config).unwrap() {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let arp = Arp {
+            mac: MacAddr::new(2, 0, 0, 0, 0, 1),
+            ip: Ipv4Addr::new(10, 0, 0, 1),
+        };
+        let mut resolver = Resolver::new(tx, rx, vec![Box::new(arp)], neighbor::Config::default())
+            .with_clock(Arc::new(network.clone()));
+        let resolving =
+            thread::spawn(move || resolver.resolve(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2))));
+
+        // b never answers, the resolver only gives up as the virtual clock moves
+        let start = network.now();
+        while !resolving.is_finished() {
+            network.advance(Duration::from_millis(100));
+            thread::sleep(Duration::from_millis(1));
+        }
+        let error = resolving.join().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(network.stats().sent, 3);
+        assert!(network.now() - start >= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn losses_follow_the_seed() {
+        let run = |seed| {
+            let network = Network::new(seed);
+            let config = LinkConfig {
+                loss: 0.3,
+                ..Default::default()
+            };
+            let (a, b) = pair(&network, config);
+            let (mut tx, _) = open(&network, a);
+            let (_, mut rx) = open(&network, b);
+            for n in 0..200 {
+                tx.send_to(&EthernetPacket::new(&frame(n, 64)).unwrap(), None);
+            }
+            network.advance(Duration::from_secs(1));
+            let mut iter = rx.iter();
+            let mut received = Vec::new();
+            while let Ok(frame) = iter.next() {
+                received.push(u16::from_be_bytes([frame.packet()[14], frame.packet()[15]]));
+            }
+            (received, network.stats())
+        };
+        let (received, stats) = run(5);
+        assert_eq!(stats.sent, 200);
+        assert!(stats.lost > 0);
+        assert_eq!(stats.delivered + stats.lost, 200);
+        assert_eq!(received.len() as u64, stats.delivered);
+        assert_eq!(run(5), (received.clone(), stats));
+        assert_ne!(run(6).0, received);
+    }
+}
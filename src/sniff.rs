@@ -0,0 +1,657 @@
+//! Live packet capture as done by the `myox-sniff` binary: a small filter expression
+//! language, one-line packet summaries, hexdumps and optional pcap output.
+
+use crate::{
+    arp::{
+        arp_new::{ArpOperations, ArpPacket},
+        channel::{
+            channel, Channel, Config, DatalinkBackend, EthernetDataLinkReceiver, Native,
+            GSO_READ_BUFFER_SIZE,
+        },
+        ether::{EtherType, EtherTypes, EthernetPacket, Packet},
+        network_interface::{MacAddr, NetworkInterface},
+    },
+    gso,
+    ipv4::ChecksumMode,
+    pcap,
+    render::{self, ColorMode, Renderer},
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Error returned for malformed command lines and filter expressions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError(pub String);
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A filter expression.
+///
+/// The grammar is a small subset of tcpdump's: the primitives `arp`, `ip`, `ip6`, `vlan`,
+/// `ether proto <number>`, `ether host <mac>`, `ether src <mac>` and `ether dst <mac>`
+/// combined with `not`, `and` and `or` (`and` binds tighter than `or`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// Matches frames with the given ethertype
+    EtherProto(EtherType),
+    /// Matches frames sent from or to the given MAC address
+    EtherHost(MacAddr),
+    /// Matches frames sent from the given MAC address
+    EtherSrc(MacAddr),
+    /// Matches frames sent to the given MAC address
+    EtherDst(MacAddr),
+    /// Negation
+    Not(Box<Expr>),
+    /// Conjunction
+    And(Box<Expr>, Box<Expr>),
+    /// Disjunction
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parse an expression split into words, as found on a command line.
+    pub fn parse<S: AsRef<str>>(words: &[S]) -> Result<Expr, ParseError> {
+        let words: Vec<&str> = words.iter().map(|w| w.as_ref()).collect();
+        let mut parser = ExprParser {
+            words: &words,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(word) => Err(ParseError(format!("unexpected `{}`", word))),
+        }
+    }
+
+    /// Whether `packet` satisfies the expression.
+    pub fn matches(&self, packet: &EthernetPacket) -> bool {
+        match self {
+            Expr::EtherProto(ethertype) => packet.get_ethertype() == *ethertype,
+            Expr::EtherHost(mac) => packet.get_source() == *mac || packet.get_destination() == *mac,
+            Expr::EtherSrc(mac) => packet.get_source() == *mac,
+            Expr::EtherDst(mac) => packet.get_destination() == *mac,
+            Expr::Not(inner) => !inner.matches(packet),
+            Expr::And(left, right) => left.matches(packet) && right.matches(packet),
+            Expr::Or(left, right) => left.matches(packet) || right.matches(packet),
+        }
+    }
+}
+
+struct ExprParser<'a> {
+    words: &'a [&'a str],
+    pos: usize,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Result<&'a str, ParseError> {
+        let word = self
+            .peek()
+            .ok_or_else(|| ParseError("unexpected end of expression".to_owned()))?;
+        self.pos += 1;
+        Ok(word)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some("or") {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.unary()?;
+        loop {
+            match self.peek() {
+                Some("and") => self.pos += 1,
+                // juxtaposition means `and`, like in tcpdump
+                Some(word) if word != "or" => {}
+                _ => return Ok(expr),
+            }
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.next()? {
+            "not" => Ok(Expr::Not(Box::new(self.unary()?))),
+            "arp" => Ok(Expr::EtherProto(EtherTypes::Arp)),
+            "ip" => Ok(Expr::EtherProto(EtherTypes::Ipv4)),
+            "ip6" => Ok(Expr::EtherProto(EtherTypes::Ipv6)),
+            "vlan" => Ok(Expr::EtherProto(EtherTypes::Vlan)),
+            "ether" => match self.next()? {
+                "proto" => parse_ethertype(self.next()?).map(Expr::EtherProto),
+                "host" => parse_mac(self.next()?).map(Expr::EtherHost),
+                "src" => parse_mac(self.next()?).map(Expr::EtherSrc),
+                "dst" => parse_mac(self.next()?).map(Expr::EtherDst),
+                word => Err(ParseError(format!("unknown ether qualifier `{}`", word))),
+            },
+            word => Err(ParseError(format!("unknown primitive `{}`", word))),
+        }
+    }
+}
+
+fn parse_ethertype(word: &str) -> Result<EtherType, ParseError> {
+    let value = if word.starts_with("0x") {
+        u16::from_str_radix(&word[2..], 16)
+    } else {
+        word.parse()
+    };
+    value
+        .map(EtherType::new)
+        .map_err(|_| ParseError(format!("invalid ethertype `{}`", word)))
+}
+
+fn parse_mac(word: &str) -> Result<MacAddr, ParseError> {
+    word.parse()
+        .map_err(|_| ParseError(format!("invalid MAC address `{}`", word)))
+}
+
+/// Options of a capture session.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Interface to capture on. Defaults to the first interface that is up and not a loopback
+    pub interface: Option<String>,
+    /// Only frames matching the expression are reported
+    pub filter: Option<Expr>,
+    /// Print a hexdump of each frame after its summary
+    pub hexdump: bool,
+    /// Write matching frames to this pcap file
+    pub write: Option<PathBuf>,
+    /// Rotation of the pcap output. Defaults to a single file
+    pub rotation: pcap::Rotation,
+    /// Only the first this many bytes of each frame are stored and dumped, the pcap
+    /// records keep the original length. Defaults to `pcap::DEFAULT_SNAPLEN`
+    pub snaplen: Option<u32>,
+    /// Don't print anything, useful together with `write`
+    pub quiet: bool,
+    /// Whether printed frames are colored. Defaults to coloring on terminals
+    pub color: ColorMode,
+    /// Print frames as JSON objects, one per line, instead of summaries
+    pub json: bool,
+    /// Also write frames as JSON objects, one per line, to this file
+    pub json_file: Option<PathBuf>,
+    /// Stop after this many matching frames
+    pub count: Option<usize>,
+    /// Flag checksums that look offloaded as bad, for captures of traffic not sent by
+    /// this host
+    pub strict_checksums: bool,
+}
+
+/// Command line usage of `myox-sniff`.
+pub const USAGE: &str =
+    "usage: myox-sniff [-i interface] [-c count] [-s snaplen] [-w file [-C size] [-G secs] [-W files]] [-x] [-q] [--color|--no-color] [--strict-checksums] [--json] [--json-file file] [expression]";
+
+impl Options {
+    /// Parse command line arguments, without the program name.
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, ParseError> {
+        let mut options: Options = Default::default();
+        let mut expression = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| ParseError(format!("option {} requires a value", name)))
+            };
+            match arg.as_str() {
+                "-i" => options.interface = Some(value("-i")?),
+                "-w" => options.write = Some(value("-w")?.into()),
+                "-c" => {
+                    let count = value("-c")?;
+                    options.count = Some(
+                        count
+                            .parse()
+                            .map_err(|_| ParseError(format!("invalid count `{}`", count)))?,
+                    );
+                }
+                "-s" => {
+                    let snaplen = value("-s")?;
+                    options.snaplen = Some(
+                        snaplen
+                            .parse()
+                            .ok()
+                            .filter(|&snaplen| snaplen > 0)
+                            .ok_or_else(|| ParseError(format!("invalid snaplen `{}`", snaplen)))?,
+                    );
+                }
+                "-C" => {
+                    let size = value("-C")?;
+                    options.rotation.max_size = Some(
+                        parse_size(&size)
+                            .ok_or_else(|| ParseError(format!("invalid size `{}`", size)))?,
+                    );
+                }
+                "-G" => {
+                    let secs = value("-G")?;
+                    options.rotation.max_age = Some(Duration::from_secs(
+                        secs.parse()
+                            .ok()
+                            .filter(|&secs| secs > 0)
+                            .ok_or_else(|| ParseError(format!("invalid interval `{}`", secs)))?,
+                    ));
+                }
+                "-W" => {
+                    let files = value("-W")?;
+                    options.rotation.max_files = Some(
+                        files
+                            .parse()
+                            .ok()
+                            .filter(|&files| files > 0)
+                            .ok_or_else(|| ParseError(format!("invalid file count `{}`", files)))?,
+                    );
+                }
+                "-x" => options.hexdump = true,
+                "-q" => options.quiet = true,
+                "--color" => options.color = ColorMode::Always,
+                "--no-color" => options.color = ColorMode::Never,
+                "--strict-checksums" => options.strict_checksums = true,
+                "--json" => options.json = true,
+                "--json-file" => options.json_file = Some(value("--json-file")?.into()),
+                _ if arg.starts_with('-') => {
+                    return Err(ParseError(format!("unknown option `{}`", arg)))
+                }
+                _ => expression.push(arg),
+            }
+        }
+        if !expression.is_empty() {
+            options.filter = Some(Expr::parse(&expression)?);
+        }
+        if options.write.is_none() && options.rotation != Default::default() {
+            return Err(ParseError("-C, -G and -W require -w".to_owned()));
+        }
+        Ok(options)
+    }
+}
+
+/// Parse a byte count with an optional k, M or G (powers of 1000) suffix.
+pub(crate) fn parse_size(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1_000),
+        'm' | 'M' => (&value[..value.len() - 1], 1_000_000),
+        'g' | 'G' => (&value[..value.len() - 1], 1_000_000_000),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(size) if size > 0 => size.checked_mul(multiplier),
+        _ => None,
+    }
+}
+
+/// Find the interface to capture on among those of the host.
+pub fn select_interface(name: Option<&str>) -> io::Result<NetworkInterface> {
+    select_backend_interface(&Native::default(), name)
+}
+
+/// Find the interface to capture on among those of `backend`: the one called `name`, or
+/// the first that is up and not a loopback.
+pub fn select_backend_interface(
+    backend: &dyn DatalinkBackend,
+    name: Option<&str>,
+) -> io::Result<NetworkInterface> {
+    let interfaces = backend.interfaces()?;
+    let found = match name {
+        Some(name) => interfaces.into_iter().find(|iface| iface.name == name),
+        None => interfaces
+            .into_iter()
+            .find(|iface| iface.is_up() && !iface.is_loopback() && iface.mac.is_some()),
+    };
+    found.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no such interface: {}", name.unwrap_or("<any>")),
+        )
+    })
+}
+
+/// Capture on the selected interface until `stop` is set or `count` frames were reported.
+///
+/// Returns the number of frames that matched the filter.
+pub fn run(options: &Options, stop: &AtomicBool) -> io::Result<usize> {
+    let interface = select_interface(options.interface.as_deref())?;
+    let config = Config {
+        read_buffer_size: 65536,
+        // wake up regularly to notice `stop`
+        read_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    let (_, mut rx) = match channel(&interface, config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+    };
+
+    let snaplen = options.snaplen.unwrap_or(pcap::DEFAULT_SNAPLEN);
+    let mut writer = match &options.write {
+        Some(path) => Some(pcap::RotatingWriter::with_snaplen(
+            path,
+            options.rotation,
+            snaplen,
+        )?),
+        None => None,
+    };
+
+    let mut json_file = match &options.json_file {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let checksum_mode = if options.strict_checksums {
+        ChecksumMode::Strict
+    } else {
+        ChecksumMode::OffloadAware
+    };
+    let mut renderer = Renderer::new(options.color).checksum_mode(checksum_mode);
+    let mut iter = rx.iter();
+    let mut matched = 0;
+    while !stop.load(Ordering::SeqCst) && options.count.map_or(true, |count| matched < count) {
+        // stamped by the kernel on arrival rather than when read
+        let (packet, now) = match iter.next_with_meta() {
+            Ok((packet, meta)) => (packet, meta.timestamp),
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(filter) = &options.filter {
+            if !filter.matches(&packet) {
+                continue;
+            }
+        }
+        matched += 1;
+
+        if let Some(writer) = writer.as_mut() {
+            writer.write_packet(now, packet.packet())?;
+        }
+        if let Some(file) = json_file.as_mut() {
+            writeln!(file, "{}", render::json(now, &interface.name, &packet))?;
+        }
+        if options.quiet {
+            continue;
+        }
+        if options.json {
+            println!("{}", render::json(now, &interface.name, &packet));
+        } else {
+            println!("{}", renderer.render(now, &packet));
+            if options.hexdump {
+                let data = packet.packet();
+                print!("{}", hexdump(&data[..data.len().min(snaplen as usize)]));
+            }
+        }
+    }
+
+    if let Some(writer) = writer {
+        writer.close()?;
+    }
+    if let Some(mut file) = json_file {
+        file.flush()?;
+    }
+    Ok(matched)
+}
+
+type Handler = Box<dyn FnMut(&EthernetPacket) + Send>;
+
+/// Configures a [`Sniffer`].
+pub struct SnifferBuilder {
+    backend: Arc<dyn DatalinkBackend>,
+    interface: Option<String>,
+    buffer_size: usize,
+    poll_interval: Duration,
+    filter: Option<Expr>,
+    segment_mss: Option<usize>,
+    handler: Option<Handler>,
+}
+
+impl SnifferBuilder {
+    /// Open the channel through `backend`. Defaults to the native backend of the platform.
+    pub fn backend(mut self, backend: Arc<dyn DatalinkBackend>) -> SnifferBuilder {
+        self.backend = backend;
+        self
+    }
+
+    /// Capture on the interface called `name` instead of the first one that is up.
+    pub fn interface(mut self, name: &str) -> SnifferBuilder {
+        self.interface = Some(name.to_owned());
+        self
+    }
+
+    /// Read frames into a buffer of `size` bytes; longer frames are truncated. Defaults to
+    /// `GSO_READ_BUFFER_SIZE`, enough for segments captured with segmentation offload.
+    pub fn buffer_size(mut self, size: usize) -> SnifferBuilder {
+        self.buffer_size = size;
+        self
+    }
+
+    /// How often to check whether to stop while no frames arrive. Defaults to 100 ms.
+    pub fn poll_interval(mut self, interval: Duration) -> SnifferBuilder {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Only hand frames matching `filter` to the handler.
+    pub fn filter(mut self, filter: Expr) -> SnifferBuilder {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Hand TCP segments carrying more than `mss` bytes of payload, as captured with
+    /// segmentation offload, to the handler as the frames the wire carries. Defaults to
+    /// handing them over as they are.
+    pub fn segment_offloaded(mut self, mss: usize) -> SnifferBuilder {
+        self.segment_mss = Some(mss);
+        self
+    }
+
+    /// Call `handler` with every captured frame.
+    pub fn handler<F>(mut self, handler: F) -> SnifferBuilder
+    where
+        F: FnMut(&EthernetPacket) + Send + 'static,
+    {
+        self.handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Open the capture channel.
+    pub fn build(self) -> io::Result<Sniffer> {
+        let interface = select_backend_interface(&*self.backend, self.interface.as_deref())?;
+        let config = Config {
+            read_buffer_size: self.buffer_size,
+            // wake up regularly to notice `stop`
+            read_timeout: Some(self.poll_interval),
+            ..Default::default()
+        };
+        let rx = match self.backend.channel(&interface, config)? {
+            Channel::Ethernet(_, rx) => rx,
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+        };
+        Ok(Sniffer {
+            interface,
+            rx,
+            filter: self.filter,
+            segment_mss: self.segment_mss,
+            handler: self.handler.unwrap_or_else(|| Box::new(|_| {})),
+            stop: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+/// A capture on one interface, handing frames to a callback. Created with
+/// [`Sniffer::builder`].
+pub struct Sniffer {
+    interface: NetworkInterface,
+    rx: Box<dyn EthernetDataLinkReceiver>,
+    filter: Option<Expr>,
+    segment_mss: Option<usize>,
+    handler: Handler,
+    stop: Arc<AtomicBool>,
+}
+
+impl Sniffer {
+    /// Start configuring a sniffer.
+    pub fn builder() -> SnifferBuilder {
+        SnifferBuilder {
+            backend: Arc::new(Native::default()),
+            interface: None,
+            buffer_size: GSO_READ_BUFFER_SIZE,
+            poll_interval: Duration::from_millis(100),
+            filter: None,
+            segment_mss: None,
+            handler: None,
+        }
+    }
+
+    /// The interface captured on.
+    pub fn interface(&self) -> &NetworkInterface {
+        &self.interface
+    }
+
+    /// Capture until stopped, returning the number of frames handed to the handler.
+    pub fn run(&mut self) -> io::Result<u64> {
+        let mut iter = self.rx.iter();
+        let mut handled = 0;
+        while !self.stop.load(Ordering::SeqCst) {
+            let packet = match iter.next() {
+                Ok(packet) => packet,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(filter) = &self.filter {
+                if !filter.matches(&packet) {
+                    continue;
+                }
+            }
+            match self.segment_mss {
+                Some(mss) if gso::is_oversized(packet.packet(), mss) => {
+                    for segment in gso::segment(packet.packet(), mss) {
+                        handled += 1;
+                        (self.handler)(&EthernetPacket::owned(segment).unwrap());
+                    }
+                }
+                _ => {
+                    handled += 1;
+                    (self.handler)(&packet);
+                }
+            }
+        }
+        Ok(handled)
+    }
+
+    /// Make `run` return, within the poll interval when no frames arrive. A stopped
+    /// sniffer stays stopped.
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+
+    /// The flag `stop` sets, for stopping the sniffer from another thread or a signal
+    /// handler while it runs.
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+}
+
+/// Format the time of day of `timestamp` (UTC) with microsecond precision.
+pub fn format_timestamp(timestamp: SystemTime) -> String {
+    let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86400;
+    format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_micros()
+    )
+}
+
+/// Describe a frame on one line.
+pub fn summarize(packet: &EthernetPacket) -> String {
+    let ethertype = packet.get_ethertype();
+    let payload = packet.payload();
+    let details = match ethertype {
+        EtherTypes::Arp => ArpPacket::new(payload).map(|arp| match arp.get_operation() {
+            ArpOperations::Request => format!(
+                "Request who-has {} tell {}",
+                arp.get_target_proto_addr(),
+                arp.get_sender_proto_addr()
+            ),
+            ArpOperations::Reply => format!(
+                "Reply {} is-at {}",
+                arp.get_sender_proto_addr(),
+                arp.get_sender_hw_addr()
+            ),
+            operation => format!("operation {}", operation.0),
+        }),
+        EtherTypes::Ipv4 if payload.len() >= 20 => Some(format!(
+            "{} > {}: proto {}",
+            Ipv4Addr::new(payload[12], payload[13], payload[14], payload[15]),
+            Ipv4Addr::new(payload[16], payload[17], payload[18], payload[19]),
+            payload[9]
+        )),
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&payload[8..24]);
+            dst.copy_from_slice(&payload[24..40]);
+            Some(format!(
+                "{} > {}: next-header {}",
+                Ipv6Addr::from(src),
+                Ipv6Addr::from(dst),
+                payload[6]
+            ))
+        }
+        _ => None,
+    };
+
+    let mut line = format!(
+        "{} > {}, {} (0x{:04x}), length {}",
+        packet.get_source(),
+        packet.get_destination(),
+        ethertype,
+        ethertype.value(),
+        packet.packet().len()
+    );
+    if let Some(details) = details {
+        line.push_str(": ");
+        line.push_str(&details);
+    }
+    line
+}
+
+/// Format `data` as offset, hex and ASCII columns, 16 bytes per line.
+pub fn hexdump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "\t0x{:04x}:  {:<47}  {}\n",
+            i * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
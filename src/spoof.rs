@@ -0,0 +1,258 @@
+//! Source MAC address spoofing.
+//!
+//! A [`SpoofingSender`] rewrites the source address of every frame sent through it, so any
+//! of the crate's frame builders can be used with a fixed or random address without
+//! touching them. [`MacOverride`] changes the address of the interface itself and puts the
+//! original back when dropped, for tests where replies have to reach a spoofed address; it
+//! is Linux only.
+
+use crate::{
+    arp::{
+        channel::EthernetDataLinkSender,
+        ether::{EtherTypes, EthernetPacket, Packet},
+        network_interface::{MacAddr, NetworkInterface},
+    },
+    rng::Rng,
+    sniff::ParseError,
+};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+#[cfg(target_os = "linux")]
+pub use self::hardware::{hardware_address, set_hardware_address, MacOverride};
+
+/// Source address given to outgoing frames.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SourceMac {
+    /// Leave the address set by the frame builder
+    Keep,
+    /// Use this address
+    Fixed(MacAddr),
+    /// Use a random locally administered unicast address for every frame
+    Random,
+}
+
+impl Default for SourceMac {
+    fn default() -> SourceMac {
+        SourceMac::Keep
+    }
+}
+
+impl std::str::FromStr for SourceMac {
+    type Err = ParseError;
+
+    /// Parse `random` or a MAC address.
+    fn from_str(s: &str) -> Result<SourceMac, ParseError> {
+        match s {
+            "random" => Ok(SourceMac::Random),
+            _ => s
+                .parse()
+                .map(SourceMac::Fixed)
+                .map_err(|_| ParseError(format!("invalid MAC address `{}`", s))),
+        }
+    }
+}
+
+/// Set the Ethernet source address of `frame` to `mac`.
+///
+/// The sender hardware address of an ARP payload is changed along with it when it matched
+/// the old source, so that the frame stays consistent.
+pub fn rewrite_source(frame: &mut [u8], mac: MacAddr) {
+    if frame.len() < 14 {
+        return;
+    }
+    let old = [frame[6], frame[7], frame[8], frame[9], frame[10], frame[11]];
+    let new = [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5];
+    frame[6..12].copy_from_slice(&new);
+
+    let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+    // Ethernet hardware addresses start right after the 8 byte ARP header
+    if ethertype == EtherTypes::Arp.value()
+        && frame.len() >= 14 + 14
+        && frame[18] == 6
+        && frame[22..28] == old
+    {
+        frame[22..28].copy_from_slice(&new);
+    }
+}
+
+/// A sender giving every frame the source address chosen by a [`SourceMac`].
+pub struct SpoofingSender {
+    inner: Box<dyn EthernetDataLinkSender>,
+    mode: SourceMac,
+    rng: Rng,
+    buffer: Vec<u8>,
+}
+
+impl SpoofingSender {
+    /// Wrap `inner`, rewriting the source of the frames it sends according to `mode`.
+    pub fn new(inner: Box<dyn EthernetDataLinkSender>, mode: SourceMac) -> SpoofingSender {
+        SpoofingSender {
+            inner,
+            mode,
+            rng: Rng::from_time(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Seed the random addresses, to make a run reproducible.
+    pub fn seed(mut self, seed: u64) -> SpoofingSender {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    /// The wrapped sender.
+    pub fn into_inner(self) -> Box<dyn EthernetDataLinkSender> {
+        self.inner
+    }
+}
+
+impl EthernetDataLinkSender for SpoofingSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        let mac = match self.mode {
+            SourceMac::Keep => return self.inner.send_to(packet, dst),
+            SourceMac::Fixed(mac) => mac,
+            SourceMac::Random => {
+                let random = self.rng.next_u64().to_be_bytes();
+                MacAddr(
+                    (random[0] & 0xfe) | 0x02,
+                    random[1],
+                    random[2],
+                    random[3],
+                    random[4],
+                    random[5],
+                )
+            }
+        };
+        self.buffer.clear();
+        self.buffer.extend_from_slice(packet.packet());
+        rewrite_source(&mut self.buffer, mac);
+        let packet = EthernetPacket::new(&self.buffer)?;
+        self.inner.send_to(&packet, dst)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+/// Changing the address of an interface, through Linux ioctls.
+#[cfg(target_os = "linux")]
+mod hardware {
+    use crate::{
+        arp::{channel::FileDesc, network_interface::MacAddr},
+        privileges::{self, Capability},
+    };
+    use std::{io, mem};
+
+    /// `struct ifreq` holding a hardware address.
+    #[repr(C)]
+    struct HwAddrRequest {
+        name: [libc::c_char; libc::IFNAMSIZ],
+        addr: libc::sockaddr,
+        // the union in `struct ifreq` is larger than a sockaddr
+        _padding: [u8; 8],
+    }
+
+    /// A changed interface MAC address, restored when dropped.
+    ///
+    /// Changing the address needs `CAP_NET_ADMIN`, and some drivers only accept it while the
+    /// interface is down.
+    #[derive(Debug)]
+    pub struct MacOverride {
+        interface: String,
+        original: MacAddr,
+        restored: bool,
+    }
+
+    impl MacOverride {
+        /// Give `interface` the address `mac`, remembering the current one.
+        pub fn set(interface: &str, mac: MacAddr) -> io::Result<MacOverride> {
+            privileges::require(&[Capability::NetAdmin])?;
+            let original = hardware_address(interface)?;
+            set_hardware_address(interface, mac)?;
+            Ok(MacOverride {
+                interface: interface.to_owned(),
+                original,
+                restored: false,
+            })
+        }
+
+        /// The address the interface had before.
+        pub fn original(&self) -> MacAddr {
+            self.original
+        }
+
+        /// Put the original address back, reporting failures that dropping would ignore.
+        pub fn restore(mut self) -> io::Result<()> {
+            self.restored = true;
+            set_hardware_address(&self.interface, self.original)
+        }
+    }
+
+    impl Drop for MacOverride {
+        fn drop(&mut self) {
+            if !self.restored {
+                let _ = set_hardware_address(&self.interface, self.original);
+            }
+        }
+    }
+
+    /// The MAC address of `interface`.
+    pub fn hardware_address(interface: &str) -> io::Result<MacAddr> {
+        let mut request = request(interface)?;
+        hardware_ioctl(libc::SIOCGIFHWADDR, &mut request)?;
+        let data = request.addr.sa_data;
+        Ok(MacAddr(
+            data[0] as u8,
+            data[1] as u8,
+            data[2] as u8,
+            data[3] as u8,
+            data[4] as u8,
+            data[5] as u8,
+        ))
+    }
+
+    /// Set the MAC address of `interface`.
+    pub fn set_hardware_address(interface: &str, mac: MacAddr) -> io::Result<()> {
+        let mut request = request(interface)?;
+        request.addr.sa_family = libc::ARPHRD_ETHER;
+        let octets = [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5];
+        for (byte, octet) in request.addr.sa_data.iter_mut().zip(octets.iter()) {
+            *byte = *octet as libc::c_char;
+        }
+        hardware_ioctl(libc::SIOCSIFHWADDR, &mut request)
+    }
+
+    fn request(interface: &str) -> io::Result<HwAddrRequest> {
+        if interface.is_empty() || interface.len() >= libc::IFNAMSIZ || interface.contains('\0') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid interface name `{}`", interface),
+            ));
+        }
+        let mut request: HwAddrRequest = unsafe { mem::zeroed() };
+        for (c, byte) in request.name.iter_mut().zip(interface.bytes()) {
+            *c = byte as libc::c_char;
+        }
+        Ok(request)
+    }
+
+    fn hardware_ioctl(op: libc::c_ulong, request: &mut HwAddrRequest) -> io::Result<()> {
+        let socket = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+        if socket == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = FileDesc { fd: socket };
+        if unsafe { libc::ioctl(socket.fd, op as _, request as *mut HwAddrRequest) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+//! Rate limited SYN generator for lab testing.
+//!
+//! [`run`] sends crafted TCP SYN segments to a host at a fixed rate, optionally from random
+//! source ports or from random addresses of a lab prefix, to exercise SYN cookies and
+//! backlog handling of devices under test. The target has to be covered by an explicit
+//! allow-list and the rate is capped at [`MAX_RATE`]. Only point it at equipment in an
+//! isolated test network you are responsible for.
+
+use crate::{
+    arp::{channel::EthernetDataLinkSender, ether::EthernetPacket},
+    cidr::Ipv4Cidr,
+    rng::Rng,
+    scan::ports::{syn_frame, Ipv4Mac, SynTarget},
+};
+use std::{
+    fmt, io,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Highest rate accepted, in segments per second.
+pub const MAX_RATE: f64 = 10_000.0;
+
+// the networks allowed ones have to be part of: private (RFC 1918), shared (RFC 6598),
+// link-local (RFC 3927), benchmarking (RFC 2544) and documentation (RFC 5737) ones
+const LAB_NETWORKS: &[(Ipv4Addr, u8)] = &[
+    (Ipv4Addr::new(10, 0, 0, 0), 8),
+    (Ipv4Addr::new(100, 64, 0, 0), 10),
+    (Ipv4Addr::new(169, 254, 0, 0), 16),
+    (Ipv4Addr::new(172, 16, 0, 0), 12),
+    (Ipv4Addr::new(192, 0, 2, 0), 24),
+    (Ipv4Addr::new(192, 168, 0, 0), 16),
+    (Ipv4Addr::new(198, 18, 0, 0), 15),
+    (Ipv4Addr::new(198, 51, 100, 0), 24),
+    (Ipv4Addr::new(203, 0, 113, 0), 24),
+];
+
+/// Where the segments appear to come from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Source {
+    /// The interface's address and a single source port
+    Fixed,
+    /// The interface's address and a random source port for every segment
+    RandomPort,
+    /// A random address of the given lab network and a random source port for every
+    /// segment. The network has to be part of an allowed one. The Ethernet source stays
+    /// the interface's
+    RandomAddress(Ipv4Cidr),
+}
+
+impl Default for Source {
+    fn default() -> Source {
+        Source::Fixed
+    }
+}
+
+/// Parameters of a SYN run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Networks the target has to be part of. Nothing is sent when it is empty, and each
+    /// has to be a private, shared, link-local, benchmarking or documentation network.
+    /// Defaults to empty
+    pub allowed: Vec<Ipv4Cidr>,
+
+    /// Destination ports, used in turn. Defaults to 80
+    pub ports: Vec<u16>,
+
+    /// Segments per second, at most `MAX_RATE`. Defaults to 100
+    pub rate: f64,
+
+    /// Number of segments to send, None to send until stopped. Defaults to 1000
+    pub count: Option<u64>,
+
+    /// Source addresses and ports. Defaults to `Source::Fixed`
+    pub source: Source,
+
+    /// Seed of the random sources and sequence numbers, None to seed from the clock.
+    /// Defaults to None
+    pub seed: Option<u64>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            allowed: Vec::new(),
+            ports: vec![80],
+            rate: 100.0,
+            count: Some(1000),
+            source: Source::Fixed,
+            seed: None,
+        }
+    }
+}
+
+/// Summary of a SYN run.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Segments sent
+    pub sent: u64,
+    /// Time spent sending
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        let rate = if secs > 0.0 {
+            self.sent as f64 / secs
+        } else {
+            0.0
+        };
+        write!(
+            f,
+            "{} SYN segments sent in {:.3} s ({:.1}/s)",
+            self.sent, secs, rate
+        )
+    }
+}
+
+/// Check `config` against `target` without sending anything.
+///
+/// Fails unless the allowed networks are lab networks, the target and the random source
+/// network are in one of them, the rate is positive and at most `MAX_RATE` and there is a
+/// port to send to.
+pub fn validate(target: Ipv4Addr, config: &Config) -> io::Result<()> {
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    if config.allowed.is_empty() {
+        return invalid("no target networks are allowed".to_owned());
+    }
+    for network in &config.allowed {
+        let lab = LAB_NETWORKS
+            .iter()
+            .any(|&(address, len)| Ipv4Cidr::new(address, len).contains_subnet(network));
+        if network.prefix_len() == 0 || !lab {
+            return invalid(format!(
+                "{} is not a private, shared, link-local, benchmarking or documentation network",
+                network
+            ));
+        }
+    }
+    if !config
+        .allowed
+        .iter()
+        .any(|network| network.contains(target))
+    {
+        return invalid(format!("{} is not in an allowed network", target));
+    }
+    if !(config.rate > 0.0 && config.rate <= MAX_RATE) {
+        return invalid(format!(
+            "rate must be between 0 and {} segments per second",
+            MAX_RATE
+        ));
+    }
+    if let Source::RandomAddress(sources) = config.source {
+        if !config
+            .allowed
+            .iter()
+            .any(|network| network.contains_subnet(&sources))
+        {
+            return invalid(format!("sources {} are not in an allowed network", sources));
+        }
+    }
+    if config.ports.is_empty() {
+        return invalid("no destination ports".to_owned());
+    }
+    Ok(())
+}
+
+/// Send SYN segments to `target` until `count` were sent or `stop` is set.
+///
+/// The configuration is checked with [`validate`] first. Replies are not read, the host
+/// running the test answers SYN-ACKs to addresses of its own with resets unless told
+/// otherwise.
+pub fn run(
+    tx: &mut dyn EthernetDataLinkSender,
+    target: &SynTarget,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<Report> {
+    validate(target.target, config)?;
+
+    let mut rng = Rng::seeded(config.seed);
+    let fixed_port = 32768 + (rng.next_u64() % 28232) as u16;
+
+    let start = Instant::now();
+    let mut sent: u64 = 0;
+    while !stop.load(Ordering::SeqCst) && config.count.map_or(true, |count| sent < count) {
+        // pace against the start time so that sleep overshoot doesn't accumulate
+        let due = start + Duration::from_secs_f64(sent as f64 / config.rate);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+
+        let random = rng.next_u64();
+        let (ip, source_port) = match config.source {
+            Source::Fixed => (target.source.ip, fixed_port),
+            Source::RandomPort => (target.source.ip, random_port(random)),
+            Source::RandomAddress(network) => {
+                let mask = u32::from(network.netmask());
+                let host = (random >> 32) as u32 & !mask;
+                let ip = Ipv4Addr::from(u32::from(network.network()) | host);
+                (ip, random_port(random))
+            }
+        };
+        let port = config.ports[(sent % config.ports.len() as u64) as usize];
+        let frame = syn_frame(
+            Ipv4Mac {
+                mac: target.source.mac,
+                ip,
+            },
+            target.next_hop,
+            target.target,
+            source_port,
+            port,
+            (random >> 16) as u32,
+            sent as u16,
+        );
+        tx.send_to(&EthernetPacket::new(&frame).unwrap(), None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))?;
+        sent += 1;
+    }
+    Ok(Report {
+        sent,
+        elapsed: start.elapsed(),
+    })
+}
+
+fn random_port(random: u64) -> u16 {
+    1024 + (random % (65536 - 1024)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allowed: &[&str]) -> Config {
+        Config {
+            allowed: allowed
+                .iter()
+                .map(|network| network.parse().unwrap())
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn only_lab_networks_are_allowed() {
+        let target = Ipv4Addr::new(192, 168, 7, 10);
+        assert!(validate(target, &config(&["192.168.7.0/24"])).is_ok());
+        assert!(validate(target, &config(&[])).is_err());
+        assert!(validate(target, &config(&["10.0.0.0/8"])).is_err());
+        // covers the target, but the internet too
+        assert!(validate(target, &config(&["0.0.0.0/0"])).is_err());
+        assert!(validate(target, &config(&["192.0.0.0/2"])).is_err());
+        let public = Ipv4Addr::new(8, 8, 8, 8);
+        assert!(validate(public, &config(&["8.8.8.0/24"])).is_err());
+        let benchmark = Ipv4Addr::new(198, 19, 0, 1);
+        assert!(validate(benchmark, &config(&["198.18.0.0/15"])).is_ok());
+    }
+
+    #[test]
+    fn random_sources_stay_in_allowed_networks() {
+        let target = Ipv4Addr::new(10, 1, 0, 1);
+        let mut config = config(&["10.1.0.0/16"]);
+        config.source = Source::RandomAddress("10.1.128.0/17".parse().unwrap());
+        assert!(validate(target, &config).is_ok());
+        config.source = Source::RandomAddress("10.0.0.0/8".parse().unwrap());
+        assert!(validate(target, &config).is_err());
+        config.source = Source::RandomAddress("203.0.113.0/24".parse().unwrap());
+        assert!(validate(target, &config).is_err());
+    }
+}
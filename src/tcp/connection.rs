@@ -0,0 +1,1046 @@
+//! The state of a TCP connection (RFC 793), opened passively.
+//!
+//! A [`Connection`] doesn't do any I/O nor read the time: segments it receives are handed
+//! to [`Connection::on_segment`] along with the current time, and the IPv4 packets it
+//! wants sent are pushed to the `out` vector its functions take. Segments that arrive out
+//! of order are dropped and left for the peer to retransmit.
+//!
+//! Data is sent within a congestion window (RFC 5681), which ECN (RFC 3168) reduces too
+//! when the peer asks for it in its SYN.
+
+use super::{ipv4_checksum, MutableTcpPacket, TcpFlags, TcpOption, TcpPacket};
+use crate::{
+    arp::ether::Packet,
+    dscp::Dscp,
+    ecn::{self, Ecn},
+    ipv4::{IpNextHeaderProtocols, Ipv4Flags, MutableIpv4Packet},
+    ttl::DEFAULT_TTL,
+};
+use std::{
+    collections::{hash_map::RandomState, VecDeque},
+    hash::BuildHasher,
+    net::SocketAddrV4,
+    time::{Duration, Instant},
+};
+
+/// The most data sent in a segment, whatever the peer takes.
+const MSS: usize = 1460;
+
+/// The most data sent in a segment to a peer that didn't tell its maximum segment size
+/// (RFC 9293, 3.7.1).
+const DEFAULT_MSS: usize = 536;
+
+/// The most data received and not read yet.
+const RECV_BUFFER: usize = 64 * 1024;
+
+/// The most data written and not acknowledged yet.
+const SEND_BUFFER: usize = 64 * 1024;
+
+/// How long the oldest segment not acknowledged waits before being sent again.
+const RTO: Duration = Duration::from_secs(1);
+
+/// How many times a segment is sent again before the connection is given up.
+const MAX_RETRIES: u32 = 5;
+
+/// The longest time between probes of a zero window.
+const MAX_PERSIST: Duration = Duration::from_secs(60);
+
+/// How long a connection lingers in `TimeWait`.
+const TIME_WAIT: Duration = Duration::from_secs(2);
+
+/// Segments of the initial congestion window (RFC 6928).
+const INITIAL_WINDOW: usize = 10;
+
+/// Settings of a connection.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Config {
+    /// ECN negotiation. Defaults to using ECN when the peer asks for it
+    pub ecn: ecn::Config,
+    /// Differentiated services of the segments sent. Defaults to CS0
+    pub dscp: Dscp,
+    /// Time to live of the segments sent. Defaults to 64
+    pub ttl: u8,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            ecn: ecn::Config::default(),
+            dscp: Dscp::default(),
+            ttl: DEFAULT_TTL,
+        }
+    }
+}
+
+/// The two ends of a connection.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Quad {
+    pub local: SocketAddrV4,
+    pub remote: SocketAddrV4,
+}
+
+/// Picks initial sequence numbers as of RFC 6528: a clock ticking every 4 microseconds
+/// plus a keyed hash of the ends of the connection, so that the numbers of one connection
+/// tell nothing about those of another.
+#[derive(Debug)]
+pub struct SequenceGenerator {
+    /// Secret key of the hash, drawn at random
+    key: RandomState,
+    /// When the clock started ticking
+    epoch: Instant,
+}
+
+impl SequenceGenerator {
+    /// Create a generator with a new key, its clock starting at `now`.
+    pub fn new(now: Instant) -> SequenceGenerator {
+        SequenceGenerator {
+            key: RandomState::new(),
+            epoch: now,
+        }
+    }
+
+    /// The initial sequence number of a connection between the ends of `quad` opened at
+    /// `now`.
+    pub fn generate(&self, quad: Quad, now: Instant) -> u32 {
+        let ticks = (now.saturating_duration_since(self.epoch).as_micros() / 4) as u32;
+        ticks.wrapping_add(self.key.hash_one(quad) as u32)
+    }
+}
+
+/// The states of a passively opened connection.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum State {
+    /// Our SYN has been sent, in answer to the peer's
+    SynReceived,
+    /// Both sides may send data
+    Established,
+    /// We closed, our FIN isn't acknowledged yet
+    FinWait1,
+    /// We closed and our FIN is acknowledged, the peer may still send data
+    FinWait2,
+    /// The peer closed, we may still send data
+    CloseWait,
+    /// Both sides closed at the same time
+    Closing,
+    /// The peer closed, then we did
+    LastAck,
+    /// Both sides closed, waiting for stray segments to go away
+    TimeWait,
+    /// The connection is over
+    Closed,
+}
+
+/// A TCP connection.
+#[derive(Debug)]
+pub struct Connection {
+    quad: Quad,
+    state: State,
+    /// Initial send sequence number
+    iss: u32,
+    /// Oldest sequence number not acknowledged
+    snd_una: u32,
+    /// Next sequence number to send
+    snd_nxt: u32,
+    /// Highest sequence number sent, `snd_nxt` goes back from it when retransmitting
+    snd_max: u32,
+    /// Window the peer advertised
+    snd_wnd: u16,
+    /// The most data sent in a segment, as the peer asked in its SYN
+    mss: usize,
+    /// The most data sent and not acknowledged, for the network's sake
+    cwnd: usize,
+    /// Slow start threshold: the congestion window grows by a segment per round trip
+    /// rather than per acknowledgment above it
+    ssthresh: usize,
+    /// ECN negotiated with the peer, and the signals in flight
+    ecn: ecn::Connection,
+    /// Marks the segments sent
+    dscp: Dscp,
+    /// Time to live of the segments sent
+    ttl: u8,
+    /// Next sequence number expected
+    rcv_nxt: u32,
+    /// Data written and not acknowledged yet, starting at `snd_una` once the SYN is
+    /// acknowledged
+    unacked: VecDeque<u8>,
+    /// Data received and not read yet
+    incoming: VecDeque<u8>,
+    /// Writes were shut down, a FIN follows the data
+    closed: bool,
+    /// The connection ended with a reset rather than both sides closing
+    reset: bool,
+    /// Sequence number of our FIN, once sent
+    fin_seq: Option<u32>,
+    /// When the oldest segment not acknowledged was sent
+    timer: Option<Instant>,
+    retries: u32,
+    /// Times the timer went off and segments were sent again
+    retransmissions: u32,
+    /// When the zero window of the peer is probed next
+    persist: Option<Instant>,
+    /// Probes sent since the window closed
+    probes: u32,
+    time_wait: Option<Instant>,
+    ip_id: u16,
+}
+
+impl Connection {
+    /// Answer the SYN of `tcp`, sent from `remote` to `local` and received at `now`, with a
+    /// sequence number from `sequences`. `None` for other segments.
+    pub fn accept(
+        local: SocketAddrV4,
+        remote: SocketAddrV4,
+        tcp: &TcpPacket,
+        config: Config,
+        sequences: &SequenceGenerator,
+        now: Instant,
+        out: &mut Vec<Vec<u8>>,
+    ) -> Option<Connection> {
+        let flags = tcp.get_flags();
+        if flags & TcpFlags::SYN == 0 || flags & (TcpFlags::ACK | TcpFlags::RST) != 0 {
+            return None;
+        }
+        let quad = Quad { local, remote };
+        let iss = sequences.generate(quad, now);
+        let mss = peer_mss(tcp);
+        let mut ecn = ecn::Connection::new(config.ecn);
+        ecn.on_syn(flags);
+        let mut connection = Connection {
+            quad,
+            state: State::SynReceived,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_max: iss,
+            snd_wnd: tcp.get_window(),
+            mss,
+            cwnd: INITIAL_WINDOW * mss,
+            ssthresh: usize::MAX,
+            ecn,
+            dscp: config.dscp,
+            ttl: config.ttl,
+            rcv_nxt: tcp.get_sequence().wrapping_add(1),
+            unacked: VecDeque::new(),
+            incoming: VecDeque::new(),
+            closed: false,
+            reset: false,
+            fin_seq: None,
+            timer: None,
+            retries: 0,
+            retransmissions: 0,
+            persist: None,
+            probes: 0,
+            time_wait: None,
+            ip_id: 0,
+        };
+        trace_event!(debug, local = %local, remote = %remote, "SYN received");
+        connection.send_syn(now, out);
+        Some(connection)
+    }
+
+    /// The ends of the connection.
+    pub fn quad(&self) -> Quad {
+        self.quad
+    }
+
+    /// The state the connection is in.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Whether the peer won't send any more data.
+    pub fn is_recv_closed(&self) -> bool {
+        matches!(
+            self.state,
+            State::CloseWait | State::Closing | State::LastAck | State::TimeWait | State::Closed
+        )
+    }
+
+    /// Whether writes were shut down.
+    pub fn is_send_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Whether the connection is over and can be forgotten.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Closed
+    }
+
+    /// Whether the connection was reset, by the peer or for going unanswered.
+    pub fn was_reset(&self) -> bool {
+        self.reset
+    }
+
+    /// How many times unacknowledged segments were sent again.
+    pub fn retransmissions(&self) -> u32 {
+        self.retransmissions
+    }
+
+    /// Whether ECN was negotiated with the peer.
+    pub fn is_ecn_capable(&self) -> bool {
+        self.ecn.is_negotiated()
+    }
+
+    /// The DSCP the segments sent are marked with.
+    pub fn dscp(&self) -> Dscp {
+        self.dscp
+    }
+
+    /// Mark the segments sent from now on with `dscp`.
+    pub fn set_dscp(&mut self, dscp: Dscp) {
+        self.dscp = dscp;
+    }
+
+    /// The time to live of the segments sent.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    /// Send the segments from now on with a time to live of `ttl`.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+
+    /// Queue `data` to be sent, returning how much of it fit the send buffer. Nothing is
+    /// taken once writes are shut down.
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        if self.closed || self.state == State::Closed {
+            return 0;
+        }
+        let len = data.len().min(SEND_BUFFER - self.unacked.len());
+        self.unacked.extend(&data[..len]);
+        len
+    }
+
+    /// Take up to `buf.len()` bytes of the data received.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.incoming.len());
+        for (to, from) in buf.iter_mut().zip(self.incoming.drain(..len)) {
+            *to = from;
+        }
+        len
+    }
+
+    /// Bytes received and not read yet.
+    pub fn available(&self) -> usize {
+        self.incoming.len()
+    }
+
+    /// Bytes written and not acknowledged yet.
+    pub fn unacknowledged(&self) -> usize {
+        self.unacked.len()
+    }
+
+    /// Bytes a write would take now.
+    pub fn send_space(&self) -> usize {
+        if self.closed || self.state == State::Closed {
+            return 0;
+        }
+        SEND_BUFFER - self.unacked.len()
+    }
+
+    /// Shut down writes: a FIN is sent after the data written so far.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    /// Handle `tcp`, received on the connection at `now` in an IP packet marked with
+    /// `codepoint`.
+    pub fn on_segment(
+        &mut self,
+        tcp: &TcpPacket,
+        codepoint: Ecn,
+        now: Instant,
+        out: &mut Vec<Vec<u8>>,
+    ) {
+        let flags = tcp.get_flags();
+        let seq = tcp.get_sequence();
+        let data = tcp.payload();
+        if flags & TcpFlags::RST != 0 {
+            // RFC 5961: only a reset at the very sequence number expected is taken, others in
+            // the window get a challenge ACK, which a peer that really reset answers with
+            // one that is
+            if seq == self.rcv_nxt {
+                self.set_state(State::Closed);
+                self.reset = true;
+            } else if self.in_window(seq) {
+                self.send_segment(self.snd_nxt, TcpFlags::ACK, &[], out);
+            }
+            return;
+        }
+        if self.state == State::SynReceived && flags & TcpFlags::SYN != 0 {
+            // our SYN got lost
+            if seq.wrapping_add(1) == self.rcv_nxt {
+                self.send_syn(now, out);
+            }
+            return;
+        }
+        let congested = self
+            .ecn
+            .on_segment(codepoint, flags, tcp.get_acknowledgement());
+        if seq != self.rcv_nxt {
+            // out of order, a duplicate or a probe of our window: tell the peer what we
+            // expect
+            self.send_segment(self.snd_nxt, TcpFlags::ACK, &[], out);
+            return;
+        }
+        if flags & TcpFlags::ACK == 0 {
+            return;
+        }
+
+        if congested {
+            self.reduce_window();
+            self.cwnd = self.ssthresh;
+            self.ecn.on_window_reduced(self.snd_nxt);
+        }
+        self.on_ack(tcp.get_acknowledgement(), tcp.get_window(), now);
+        if self.state == State::Closed {
+            return;
+        }
+
+        let mut occupied = false;
+        let receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        let mut taken = data.len();
+        if receiving && !data.is_empty() {
+            taken = data.len().min(RECV_BUFFER - self.incoming.len());
+            self.incoming.extend(&data[..taken]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(taken as u32);
+            occupied = true;
+        }
+        // a FIN only counts once the data before it is in
+        if flags & TcpFlags::FIN != 0 && taken == data.len() && !self.is_recv_closed() {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            let state = match self.state {
+                State::SynReceived | State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                _ => {
+                    self.time_wait = Some(now);
+                    State::TimeWait
+                }
+            };
+            self.set_state(state);
+            occupied = true;
+        }
+        if occupied {
+            self.send_segment(self.snd_nxt, TcpFlags::ACK, &[], out);
+        }
+    }
+
+    /// Send the data and FIN that are due, and retransmit what went unacknowledged for too
+    /// long. To be called regularly.
+    pub fn on_tick(&mut self, now: Instant, out: &mut Vec<Vec<u8>>) {
+        match self.state {
+            State::Closed => return,
+            State::TimeWait => {
+                if self
+                    .time_wait
+                    .map_or(true, |since| now - since >= TIME_WAIT)
+                {
+                    self.set_state(State::Closed);
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if self.timer.map_or(false, |sent| now - sent >= RTO) {
+            self.retries += 1;
+            if self.retries > MAX_RETRIES {
+                self.send_segment(self.snd_nxt, TcpFlags::RST, &[], out);
+                self.set_state(State::Closed);
+                self.reset = true;
+                return;
+            }
+            // go back to the oldest segment not acknowledged, a segment at a time
+            self.retransmissions += 1;
+            self.timer = None;
+            self.reduce_window();
+            self.cwnd = self.mss;
+            if self.state == State::SynReceived {
+                self.send_syn(now, out);
+                return;
+            }
+            self.snd_nxt = self.snd_una;
+        }
+        if self.state == State::SynReceived {
+            return;
+        }
+
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let window = usize::from(self.snd_wnd)
+                .min(self.cwnd)
+                .saturating_sub(offset);
+            let len = (self.unacked.len().saturating_sub(offset))
+                .min(window)
+                .min(self.mss);
+            if len == 0 {
+                break;
+            }
+            let data: Vec<u8> = self.unacked.range(offset..offset + len).cloned().collect();
+            self.send_segment(self.snd_nxt, TcpFlags::ACK | TcpFlags::PSH, &data, out);
+            self.advance(len as u32);
+            self.timer.get_or_insert(now);
+        }
+
+        // the update opening a zero window may get lost, so the window is probed for as long
+        // as data waits for it, less and less often
+        let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+        if self.snd_wnd == 0 && self.unacked.len() > offset && self.timer.is_none() {
+            match self.persist {
+                Some(due) if now >= due => {
+                    // an old sequence number, which the peer answers with its window
+                    self.send_segment(self.snd_una.wrapping_sub(1), TcpFlags::ACK, &[], out);
+                    self.probes += 1;
+                    let backoff = RTO * (1 << self.probes.min(6));
+                    self.persist = Some(now + backoff.min(MAX_PERSIST));
+                }
+                Some(_) => {}
+                None => self.persist = Some(now + RTO),
+            }
+        } else {
+            self.persist = None;
+            self.probes = 0;
+        }
+
+        // the FIN follows the last of the data, it is due when that's just been sent
+        let fin_due = self.closed
+            && self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.unacked.len()
+            && match self.state {
+                State::Established | State::CloseWait => true,
+                // sent before, and gone back over when retransmitting
+                State::FinWait1 | State::Closing | State::LastAck => true,
+                _ => false,
+            };
+        if fin_due {
+            self.fin_seq = Some(self.snd_nxt);
+            self.send_segment(self.snd_nxt, TcpFlags::FIN | TcpFlags::ACK, &[], out);
+            self.advance(1);
+            self.timer.get_or_insert(now);
+            let state = match self.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                state => state,
+            };
+            self.set_state(state);
+        }
+    }
+
+    // move to `state`, telling subscribers
+    fn set_state(&mut self, state: State) {
+        if state != self.state {
+            trace_event!(
+                debug,
+                local = %self.quad.local,
+                remote = %self.quad.remote,
+                from = ?self.state,
+                to = ?state,
+                "state change"
+            );
+            self.state = state;
+        }
+    }
+
+    // halve the threshold to what is in flight, on a loss or a sign of congestion
+    fn reduce_window(&mut self) {
+        let flight = self.snd_max.wrapping_sub(self.snd_una) as usize;
+        self.ssthresh = (flight / 2).max(2 * self.mss);
+    }
+
+    fn advance(&mut self, len: u32) {
+        self.snd_nxt = self.snd_nxt.wrapping_add(len);
+        if self.snd_nxt.wrapping_sub(self.snd_una) > self.snd_max.wrapping_sub(self.snd_una) {
+            self.snd_max = self.snd_nxt;
+        }
+    }
+
+    fn on_ack(&mut self, ack: u32, window: u16, now: Instant) {
+        // acknowledgments of what wasn't sent yet, or was already acknowledged, are ignored
+        let acceptable =
+            ack == self.snd_una || between(self.snd_una, ack, self.snd_max.wrapping_add(1));
+        if !acceptable {
+            return;
+        }
+        self.snd_wnd = window;
+        if ack == self.snd_una {
+            return;
+        }
+        if self.state == State::SynReceived {
+            // the SYN takes up the first sequence number
+            self.snd_una = self.snd_una.wrapping_add(1);
+            self.set_state(State::Established);
+        }
+        let advanced = ack.wrapping_sub(self.snd_una) as usize;
+        self.cwnd += if self.cwnd < self.ssthresh {
+            advanced.min(self.mss)
+        } else {
+            (self.mss * self.mss / self.cwnd).max(1)
+        };
+        let fin_acked = self.fin_seq.map_or(false, |fin| ack == fin.wrapping_add(1));
+        let data_end = if fin_acked { ack.wrapping_sub(1) } else { ack };
+        let acked = data_end.wrapping_sub(self.snd_una) as usize;
+        self.unacked.drain(..acked.min(self.unacked.len()));
+        // what was sent before going back may be acknowledged too
+        if ack.wrapping_sub(self.snd_una) > self.snd_nxt.wrapping_sub(self.snd_una) {
+            self.snd_nxt = ack;
+        }
+        self.snd_una = ack;
+        self.retries = 0;
+        self.timer = if self.snd_una == self.snd_nxt {
+            None
+        } else {
+            Some(now)
+        };
+        if fin_acked {
+            let state = match self.state {
+                State::FinWait1 => State::FinWait2,
+                State::Closing => {
+                    self.time_wait = Some(now);
+                    State::TimeWait
+                }
+                State::LastAck => State::Closed,
+                state => state,
+            };
+            self.set_state(state);
+        }
+    }
+
+    fn in_window(&self, seq: u32) -> bool {
+        let window = (RECV_BUFFER - self.incoming.len()) as u32;
+        seq == self.rcv_nxt || between(self.rcv_nxt, seq, self.rcv_nxt.wrapping_add(window))
+    }
+
+    fn send_syn(&mut self, now: Instant, out: &mut Vec<Vec<u8>>) {
+        let mut flags = TcpFlags::SYN | TcpFlags::ACK;
+        if self.ecn.is_negotiated() {
+            flags |= TcpFlags::ECE;
+        }
+        self.send_segment(self.iss, flags, &[], out);
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.snd_max = self.snd_nxt;
+        self.timer = Some(now);
+    }
+
+    fn send_segment(&mut self, seq: u32, mut flags: u8, data: &[u8], out: &mut Vec<Vec<u8>>) {
+        let window = (RECV_BUFFER - self.incoming.len()).min(usize::from(u16::MAX)) as u16;
+        self.ip_id = self.ip_id.wrapping_add(1);
+        // short of the highest sequence number sent, so sent before
+        let retransmission = between(seq.wrapping_sub(1), seq, self.snd_max);
+        let new_data = !data.is_empty() && !retransmission;
+        if flags & (TcpFlags::SYN | TcpFlags::RST) == 0 {
+            flags |= self.ecn.outgoing_flags(new_data);
+        }
+        let codepoint = self
+            .ecn
+            .outgoing_codepoint(!data.is_empty(), retransmission);
+        let mut packet = segment(
+            self.quad,
+            self.ip_id,
+            seq,
+            self.rcv_nxt,
+            flags,
+            window,
+            data,
+        );
+        let mut ip = MutableIpv4Packet::new(&mut packet).unwrap();
+        ip.set_dscp(self.dscp.0);
+        ip.set_ecn(codepoint.into());
+        ip.set_ttl(self.ttl);
+        ip.update_checksum();
+        out.push(packet);
+    }
+}
+
+/// The RST answering `tcp`, sent from `remote` to `local` where nothing listens.
+pub fn reset(local: SocketAddrV4, remote: SocketAddrV4, tcp: &TcpPacket) -> Option<Vec<u8>> {
+    let flags = tcp.get_flags();
+    if flags & TcpFlags::RST != 0 {
+        return None;
+    }
+    let quad = Quad { local, remote };
+    Some(if flags & TcpFlags::ACK != 0 {
+        segment(quad, 0, tcp.get_acknowledgement(), 0, TcpFlags::RST, 0, &[])
+    } else {
+        // the SYN and FIN take up a sequence number each
+        let len = tcp.payload().len() as u32
+            + u32::from(flags & TcpFlags::SYN != 0)
+            + u32::from(flags & TcpFlags::FIN != 0);
+        let ack = tcp.get_sequence().wrapping_add(len);
+        segment(quad, 0, 0, ack, TcpFlags::RST | TcpFlags::ACK, 0, &[])
+    })
+}
+
+// the maximum segment size the SYN `tcp` asks for, up to ours
+fn peer_mss(tcp: &TcpPacket) -> usize {
+    tcp.get_options_iter()
+        .map_while(Result::ok)
+        .find_map(|option| match option {
+            TcpOption::Mss(mss) if mss > 0 => Some(usize::from(mss).min(MSS)),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT_MSS)
+}
+
+// an IPv4 packet carrying a segment from the local end to the remote one
+pub(super) fn segment(
+    quad: Quad,
+    id: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; 20 + 20 + data.len()];
+    {
+        let mut tcp = MutableTcpPacket::new(&mut buffer[20..]).unwrap();
+        tcp.set_source(quad.local.port());
+        tcp.set_destination(quad.remote.port());
+        tcp.set_sequence(seq);
+        tcp.set_acknowledgement(ack);
+        tcp.set_data_offset(5);
+        tcp.set_flags(flags);
+        tcp.set_window(window);
+        tcp.set_payload(data);
+        let sum = ipv4_checksum(&tcp.to_immutable(), *quad.local.ip(), *quad.remote.ip());
+        tcp.set_checksum(sum);
+    }
+    let total_len = buffer.len() as u16;
+    let mut ip = MutableIpv4Packet::new(&mut buffer).unwrap();
+    ip.set_version(4);
+    ip.set_header_length(5);
+    ip.set_total_length(total_len);
+    ip.set_identification(id);
+    ip.set_flags(Ipv4Flags::DontFragment);
+    ip.set_ttl(DEFAULT_TTL);
+    ip.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
+    ip.set_source(*quad.local.ip());
+    ip.set_destination(*quad.remote.ip());
+    ip.update_checksum();
+    buffer
+}
+
+// start < x < end, in sequence number space
+fn between(start: u32, x: u32, end: u32) -> bool {
+    let offset = x.wrapping_sub(start);
+    offset != 0 && offset < end.wrapping_sub(start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipv4::Ipv4Packet;
+    use std::net::Ipv4Addr;
+
+    fn quad(remote_port: u16) -> Quad {
+        Quad {
+            local: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 80),
+            remote: SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), remote_port),
+        }
+    }
+
+    // an IPv4 packet carrying a segment from the peer of `quad`
+    fn from_peer(quad: Quad, seq: u32, ack: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        let reversed = Quad {
+            local: quad.remote,
+            remote: quad.local,
+        };
+        segment(reversed, 0, seq, ack, flags, 8192, data)
+    }
+
+    fn tcp(packet: &[u8]) -> TcpPacket {
+        TcpPacket::new(&packet[20..]).unwrap()
+    }
+
+    // a connection through its handshake, and the next sequence number it sends
+    fn established(now: Instant) -> (Connection, u32) {
+        let (connection, syn_ack) = handshake(now, TcpFlags::SYN);
+        (connection, tcp(&syn_ack).get_sequence().wrapping_add(1))
+    }
+
+    // a connection through its handshake opened by a SYN with `flags`, and its SYN-ACK
+    fn handshake(now: Instant, flags: u8) -> (Connection, Vec<u8>) {
+        let quad = quad(40000);
+        let mut out = Vec::new();
+        let syn = from_peer(quad, 1000, 0, flags, &[]);
+        let sequences = SequenceGenerator::new(now);
+        let mut connection = Connection::accept(
+            quad.local,
+            quad.remote,
+            &tcp(&syn),
+            Config::default(),
+            &sequences,
+            now,
+            &mut out,
+        )
+        .unwrap();
+        let syn_ack = tcp(&out[0]).get_sequence();
+        let ack = from_peer(quad, 1001, syn_ack.wrapping_add(1), TcpFlags::ACK, &[]);
+        connection.on_segment(&tcp(&ack), Ecn::NotEct, now, &mut out);
+        assert_eq!(connection.state(), State::Established);
+        (connection, out.swap_remove(0))
+    }
+
+    fn codepoint(packet: &[u8]) -> Ecn {
+        Ecn::from(Ipv4Packet::new(packet).unwrap().get_ecn())
+    }
+
+    #[test]
+    fn reset_in_window_is_challenged() {
+        let now = Instant::now();
+        let (mut connection, snd_nxt) = established(now);
+        let quad = connection.quad();
+        let mut out = Vec::new();
+        let blind = from_peer(quad, 1001 + 100, 0, TcpFlags::RST, &[]);
+        connection.on_segment(&tcp(&blind), Ecn::NotEct, now, &mut out);
+        assert_eq!(connection.state(), State::Established);
+        assert_eq!(out.len(), 1);
+        let challenge = tcp(&out[0]);
+        assert_eq!(challenge.get_flags(), TcpFlags::ACK);
+        assert_eq!(challenge.get_sequence(), snd_nxt);
+        assert_eq!(challenge.get_acknowledgement(), 1001);
+
+        // out of the window: dropped without a word
+        out.clear();
+        let stray = from_peer(quad, 1001u32.wrapping_sub(100), 0, TcpFlags::RST, &[]);
+        connection.on_segment(&tcp(&stray), Ecn::NotEct, now, &mut out);
+        assert!(out.is_empty());
+
+        let exact = from_peer(quad, 1001, 0, TcpFlags::RST, &[]);
+        connection.on_segment(&tcp(&exact), Ecn::NotEct, now, &mut out);
+        assert_eq!(connection.state(), State::Closed);
+        assert!(connection.was_reset());
+    }
+
+    #[test]
+    fn zero_window_is_probed() {
+        let start = Instant::now();
+        let (mut connection, snd_nxt) = established(start);
+        let quad = connection.quad();
+        let reversed = Quad {
+            local: quad.remote,
+            remote: quad.local,
+        };
+        let mut out = Vec::new();
+        let closed = segment(reversed, 0, 1001, snd_nxt, TcpFlags::ACK, 0, &[]);
+        connection.on_segment(&tcp(&closed), Ecn::NotEct, start, &mut out);
+        assert_eq!(connection.write(b"waiting"), 7);
+
+        let mut probes = Vec::new();
+        for second in 0..8 {
+            out.clear();
+            connection.on_tick(start + Duration::from_secs(second), &mut out);
+            for packet in &out {
+                let probe = tcp(packet);
+                assert!(probe.payload().is_empty());
+                assert_eq!(probe.get_sequence(), snd_nxt.wrapping_sub(1));
+                probes.push(second);
+            }
+        }
+        // one RTO, then backing off
+        assert_eq!(probes, vec![1, 3, 7]);
+
+        // the window opens: the data goes and the probes stop
+        let open = from_peer(quad, 1001, snd_nxt, TcpFlags::ACK, &[]);
+        connection.on_segment(
+            &tcp(&open),
+            Ecn::NotEct,
+            start + Duration::from_secs(8),
+            &mut out,
+        );
+        out.clear();
+        connection.on_tick(start + Duration::from_secs(8), &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(tcp(&out[0]).payload(), b"waiting");
+    }
+
+    #[test]
+    fn unacknowledged_data_is_sent_again() {
+        let start = Instant::now();
+        let (mut connection, snd_nxt) = established(start);
+        let mut out = Vec::new();
+        assert_eq!(connection.write(b"data"), 4);
+        connection.on_tick(start, &mut out);
+        connection.on_tick(start + RTO / 2, &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(connection.retransmissions(), 0);
+
+        connection.on_tick(start + RTO, &mut out);
+        assert_eq!(out.len(), 2);
+        assert_eq!(tcp(&out[1]).get_sequence(), snd_nxt);
+        assert_eq!(tcp(&out[1]).payload(), b"data");
+        assert_eq!(connection.retransmissions(), 1);
+    }
+
+    #[test]
+    fn segments_fit_the_peer_mss() {
+        let now = Instant::now();
+        let quad = quad(40000);
+        let sequences = SequenceGenerator::new(now);
+        let mut out = Vec::new();
+        // a SYN whose options ask for 1000 bytes a segment
+        let mut syn = from_peer(quad, 1000, 0, TcpFlags::SYN, &[2, 4, 0x03, 0xe8]);
+        {
+            let mut tcp = MutableTcpPacket::new(&mut syn[20..]).unwrap();
+            tcp.set_data_offset(6);
+            let sum = ipv4_checksum(&tcp.to_immutable(), *quad.remote.ip(), *quad.local.ip());
+            tcp.set_checksum(sum);
+        }
+        let mut connection = Connection::accept(
+            quad.local,
+            quad.remote,
+            &tcp(&syn),
+            Config::default(),
+            &sequences,
+            now,
+            &mut out,
+        )
+        .unwrap();
+        let syn_ack = tcp(&out[0]).get_sequence();
+        let ack = from_peer(quad, 1001, syn_ack.wrapping_add(1), TcpFlags::ACK, &[]);
+        connection.on_segment(&tcp(&ack), Ecn::NotEct, now, &mut out);
+
+        out.clear();
+        connection.write(&[0; 2500]);
+        connection.on_tick(now, &mut out);
+        let lens: Vec<usize> = out
+            .iter()
+            .map(|packet| tcp(packet).payload().len())
+            .collect();
+        assert_eq!(lens, [1000, 1000, 500]);
+
+        // no option, the default of RFC 9293
+        let (mut connection, _) = established(now);
+        out.clear();
+        connection.write(&[0; 600]);
+        connection.on_tick(now, &mut out);
+        let lens: Vec<usize> = out
+            .iter()
+            .map(|packet| tcp(packet).payload().len())
+            .collect();
+        assert_eq!(lens, [DEFAULT_MSS, 600 - DEFAULT_MSS]);
+    }
+
+    #[test]
+    fn ecn_is_negotiated() {
+        let now = Instant::now();
+        let ecn_setup = TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR;
+        let (mut connection, syn_ack) = handshake(now, ecn_setup);
+        let syn_ack_flags = TcpFlags::SYN | TcpFlags::ACK | TcpFlags::ECE;
+        assert_eq!(tcp(&syn_ack).get_flags(), syn_ack_flags);
+        assert_eq!(codepoint(&syn_ack), Ecn::NotEct);
+        assert!(connection.is_ecn_capable());
+
+        // data is sent ECN-capable, acknowledgments aren't
+        let mut out = Vec::new();
+        connection.write(b"data");
+        connection.on_tick(now, &mut out);
+        assert_eq!(codepoint(&out[0]), Ecn::Ect0);
+        let snd_nxt = tcp(&out[0]).get_sequence().wrapping_add(4);
+        let data = from_peer(connection.quad(), 1001, snd_nxt, TcpFlags::ACK, b"hi");
+        connection.on_segment(&tcp(&data), Ecn::Ect0, now, &mut out);
+        assert_eq!(codepoint(&out[1]), Ecn::NotEct);
+
+        let (mut connection, syn_ack) = handshake(now, TcpFlags::SYN);
+        assert_eq!(tcp(&syn_ack).get_flags(), TcpFlags::SYN | TcpFlags::ACK);
+        assert!(!connection.is_ecn_capable());
+        out.clear();
+        connection.write(b"data");
+        connection.on_tick(now, &mut out);
+        assert_eq!(codepoint(&out[0]), Ecn::NotEct);
+    }
+
+    #[test]
+    fn congestion_experienced_is_echoed_until_cwr() {
+        let now = Instant::now();
+        let ecn_setup = TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR;
+        let (mut connection, syn_ack) = handshake(now, ecn_setup);
+        let snd_nxt = tcp(&syn_ack).get_sequence().wrapping_add(1);
+        let quad = connection.quad();
+        let mut out = Vec::new();
+        let data = TcpFlags::ACK | TcpFlags::PSH;
+
+        let marked = from_peer(quad, 1001, snd_nxt, data, b"a");
+        connection.on_segment(&tcp(&marked), Ecn::Ce, now, &mut out);
+        let next = from_peer(quad, 1002, snd_nxt, data, b"b");
+        connection.on_segment(&tcp(&next), Ecn::Ect0, now, &mut out);
+        let reduced = from_peer(quad, 1003, snd_nxt, data | TcpFlags::CWR, b"c");
+        connection.on_segment(&tcp(&reduced), Ecn::Ect0, now, &mut out);
+        let flags: Vec<u8> = out.iter().map(|packet| tcp(packet).get_flags()).collect();
+        assert_eq!(
+            flags,
+            [
+                TcpFlags::ACK | TcpFlags::ECE,
+                TcpFlags::ACK | TcpFlags::ECE,
+                TcpFlags::ACK
+            ]
+        );
+    }
+
+    #[test]
+    fn ecn_echo_reduces_the_window_once_per_window() {
+        let now = Instant::now();
+        let ecn_setup = TcpFlags::SYN | TcpFlags::ECE | TcpFlags::CWR;
+        let (mut connection, syn_ack) = handshake(now, ecn_setup);
+        let snd_nxt = tcp(&syn_ack).get_sequence().wrapping_add(1);
+        let quad = connection.quad();
+        let echo = TcpFlags::ACK | TcpFlags::ECE;
+        let mut out = Vec::new();
+        connection.write(&[0; 20 * DEFAULT_MSS]);
+        connection.on_tick(now, &mut out);
+        assert_eq!(out.len(), INITIAL_WINDOW);
+        let flight = INITIAL_WINDOW * DEFAULT_MSS;
+        let recover = snd_nxt.wrapping_add(flight as u32);
+
+        let ack = from_peer(quad, 1001, snd_nxt.wrapping_add(536), echo, &[]);
+        connection.on_segment(&tcp(&ack), Ecn::NotEct, now, &mut out);
+        assert_eq!(connection.ssthresh, flight / 2);
+        assert!(connection.cwnd < flight);
+        // the rest of the window was sent before the reduction
+        let ack = from_peer(quad, 1001, recover, echo, &[]);
+        connection.on_segment(&tcp(&ack), Ecn::NotEct, now, &mut out);
+        assert_eq!(connection.ssthresh, flight / 2);
+
+        // the first new data tells the peer the window was reduced
+        out.clear();
+        connection.on_tick(now, &mut out);
+        assert_eq!(codepoint(&out[0]), Ecn::Ect0);
+        assert_ne!(tcp(&out[0]).get_flags() & TcpFlags::CWR, 0);
+        assert_eq!(tcp(&out[1]).get_flags() & TcpFlags::CWR, 0);
+        let sent: usize = out.iter().map(|packet| tcp(packet).payload().len()).sum();
+
+        // an echo of what was sent since is congestion again
+        let ack = from_peer(quad, 1001, recover.wrapping_add(536), echo, &[]);
+        connection.on_segment(&tcp(&ack), Ecn::NotEct, now, &mut out);
+        assert_eq!(connection.ssthresh, sent / 2);
+    }
+
+    #[test]
+    fn probes_are_answered() {
+        let now = Instant::now();
+        let (mut connection, snd_nxt) = established(now);
+        let mut out = Vec::new();
+        let probe = from_peer(connection.quad(), 1000, snd_nxt, TcpFlags::ACK, &[]);
+        connection.on_segment(&tcp(&probe), Ecn::NotEct, now, &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(tcp(&out[0]).get_acknowledgement(), 1001);
+    }
+
+    #[test]
+    fn sequence_numbers_tick_per_connection() {
+        let start = Instant::now();
+        let sequences = SequenceGenerator::new(start);
+        let first = sequences.generate(quad(40000), start);
+        assert_ne!(first, sequences.generate(quad(40001), start));
+        let later = sequences.generate(quad(40000), start + Duration::from_millis(1));
+        assert_eq!(later.wrapping_sub(first), 250);
+        // another key, other numbers
+        assert_ne!(
+            first,
+            SequenceGenerator::new(start).generate(quad(40000), start)
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! TCP segments.
+//!
+//! The checksum covers a pseudo-header of the enclosing IP packet, so [`ipv4_checksum`]
+//! and [`ipv6_checksum`] take the addresses alongside the segment.
+//!
+//! [`stack`] accepts connections on a tun device or an Ethernet channel with a state
+//! machine of its own, so applications can be tried against this crate instead of the
+//! kernel.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod connection;
+pub mod options;
+pub mod packet;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stack;
+
+pub use options::{OptionKinds, Options, TcpOption};
+pub use packet::{MutableTcpPacket, Tcp, TcpFlags, TcpPacket};
+#[cfg(not(target_arch = "wasm32"))]
+pub use stack::{Interface, TcpListener, TcpStream, UdpSocket};
+
+use crate::{arp::ether::Packet, checksum, ipproto::IPPROTO_TCP};
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+// the segment with the checksum field taken out
+fn sum(packet: &TcpPacket, pseudo: u32) -> u16 {
+    let data = packet.packet();
+    let sum = checksum::add(pseudo, &data[..16]);
+    checksum::finish(checksum::add(sum, &data[18..]))
+}
+
+/// The checksum of `packet` carried in an IPv4 packet from `source` to `destination`.
+pub fn ipv4_checksum(packet: &TcpPacket, source: Ipv4Addr, destination: Ipv4Addr) -> u16 {
+    let len = packet.packet().len() as u16;
+    sum(
+        packet,
+        checksum::ipv4_pseudo_header(source, destination, IPPROTO_TCP, len),
+    )
+}
+
+/// The checksum of `packet` carried in an IPv6 packet from `source` to `destination`.
+pub fn ipv6_checksum(packet: &TcpPacket, source: Ipv6Addr, destination: Ipv6Addr) -> u16 {
+    let len = packet.packet().len() as u32;
+    sum(
+        packet,
+        checksum::ipv6_pseudo_header(source, destination, IPPROTO_TCP, len),
+    )
+}
@@ -0,0 +1,218 @@
+//! TCP options (RFC 793, RFC 7323, RFC 2018).
+//!
+//! The layout is the one IPv4 options have: End of Option List and No Operation are a
+//! single byte, every other option is a kind byte, a length byte covering the whole option,
+//! and data, and the options area is padded to a multiple of 4 bytes.
+
+use crate::arp::arp::{Error, Result};
+
+/// Most bytes of options a header can carry.
+pub const MAX_OPTIONS_LEN: usize = 40;
+
+/// Option kind numbers.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod OptionKinds {
+    /// End of Option List.
+    pub const EndOfList: u8 = 0;
+    /// No Operation, used to align options.
+    pub const NoOperation: u8 = 1;
+    /// Maximum Segment Size.
+    pub const Mss: u8 = 2;
+    /// Window Scale.
+    pub const WindowScale: u8 = 3;
+    /// Selective Acknowledgment Permitted.
+    pub const SackPermitted: u8 = 4;
+    /// Selective Acknowledgment.
+    pub const Sack: u8 = 5;
+    /// Timestamps.
+    pub const Timestamps: u8 = 8;
+}
+
+/// A TCP option.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TcpOption {
+    /// End of the options, only followed by padding
+    EndOfList,
+    /// Padding between options
+    NoOperation,
+    /// Largest segment the sender can receive, only sent with SYN
+    Mss(u16),
+    /// Shift count applied to the window field, only sent with SYN
+    WindowScale(u8),
+    /// The sender understands selective acknowledgments, only sent with SYN
+    SackPermitted,
+    /// Blocks of sequence numbers received beyond the acknowledged one, as left and right
+    /// edges
+    Sack(Vec<(u32, u32)>),
+    /// Timestamp of the sender and the last one received from the peer
+    Timestamps { value: u32, echo_reply: u32 },
+    /// An option this module does not know about, without its kind and length bytes
+    Unknown { kind: u8, data: Vec<u8> },
+}
+
+impl TcpOption {
+    /// The option kind number.
+    pub fn kind(&self) -> u8 {
+        match self {
+            TcpOption::EndOfList => OptionKinds::EndOfList,
+            TcpOption::NoOperation => OptionKinds::NoOperation,
+            TcpOption::Mss(_) => OptionKinds::Mss,
+            TcpOption::WindowScale(_) => OptionKinds::WindowScale,
+            TcpOption::SackPermitted => OptionKinds::SackPermitted,
+            TcpOption::Sack(_) => OptionKinds::Sack,
+            TcpOption::Timestamps { .. } => OptionKinds::Timestamps,
+            TcpOption::Unknown { kind, .. } => *kind,
+        }
+    }
+
+    /// Number of bytes the option takes on the wire.
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            TcpOption::EndOfList | TcpOption::NoOperation => 1,
+            TcpOption::Mss(_) => 4,
+            TcpOption::WindowScale(_) => 3,
+            TcpOption::SackPermitted => 2,
+            TcpOption::Sack(blocks) => 2 + 8 * blocks.len(),
+            TcpOption::Timestamps { .. } => 10,
+            TcpOption::Unknown { data, .. } => 2 + data.len(),
+        }
+    }
+
+    /// Append the option to `out`.
+    ///
+    /// Fails with `Malformed` if it is longer than a length byte can say.
+    pub fn write(&self, out: &mut Vec<u8>) -> Result<()> {
+        let len = self.encoded_len();
+        if len > usize::from(u8::MAX) {
+            return Err(Error::malformed("option length", 1));
+        }
+        out.push(self.kind());
+        if len > 1 {
+            out.push(len as u8);
+        }
+        match self {
+            TcpOption::EndOfList | TcpOption::NoOperation | TcpOption::SackPermitted => {}
+            TcpOption::Mss(mss) => out.extend_from_slice(&mss.to_be_bytes()),
+            TcpOption::WindowScale(shift) => out.push(*shift),
+            TcpOption::Sack(blocks) => {
+                for (left, right) in blocks {
+                    out.extend_from_slice(&left.to_be_bytes());
+                    out.extend_from_slice(&right.to_be_bytes());
+                }
+            }
+            TcpOption::Timestamps { value, echo_reply } => {
+                out.extend_from_slice(&value.to_be_bytes());
+                out.extend_from_slice(&echo_reply.to_be_bytes());
+            }
+            TcpOption::Unknown { data, .. } => out.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    fn parse(kind: u8, data: &[u8]) -> Result<TcpOption> {
+        let word = |data: &[u8]| u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        Ok(match (kind, data) {
+            (OptionKinds::Mss, [high, low]) => TcpOption::Mss(u16::from_be_bytes([*high, *low])),
+            (OptionKinds::WindowScale, [shift]) => TcpOption::WindowScale(*shift),
+            (OptionKinds::SackPermitted, []) => TcpOption::SackPermitted,
+            (OptionKinds::Sack, _) if data.len() % 8 == 0 => TcpOption::Sack(
+                data.chunks(8)
+                    .map(|block| (word(&block[..4]), word(&block[4..])))
+                    .collect(),
+            ),
+            (OptionKinds::Timestamps, _) if data.len() == 8 => TcpOption::Timestamps {
+                value: word(&data[..4]),
+                echo_reply: word(&data[4..]),
+            },
+            (OptionKinds::Mss, _)
+            | (OptionKinds::WindowScale, _)
+            | (OptionKinds::SackPermitted, _)
+            | (OptionKinds::Sack, _)
+            | (OptionKinds::Timestamps, _) => return Err(Error::malformed("option data", 0)),
+            _ => TcpOption::Unknown {
+                kind,
+                data: data.to_vec(),
+            },
+        })
+    }
+}
+
+/// Iterator over the options area of a header.
+///
+/// Stops after End of Option List, and after the first option that is truncated or
+/// malformed, which it yields as an error with its offset in the options area.
+#[derive(Clone, Debug)]
+pub struct Options<'a> {
+    data: &'a [u8],
+    // of `data` in what was passed to `new`
+    offset: usize,
+}
+
+impl<'a> Options<'a> {
+    /// Iterate over `data`, the bytes between the fixed header and the payload.
+    pub fn new(data: &'a [u8]) -> Options<'a> {
+        Options { data, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = Result<TcpOption>;
+
+    fn next(&mut self) -> Option<Result<TcpOption>> {
+        let kind = *self.data.first()?;
+        match kind {
+            OptionKinds::EndOfList => {
+                self.data = &[];
+                return Some(Ok(TcpOption::EndOfList));
+            }
+            OptionKinds::NoOperation => {
+                self.data = &self.data[1..];
+                self.offset += 1;
+                return Some(Ok(TcpOption::NoOperation));
+            }
+            _ => {}
+        }
+        let len = match self.data.get(1) {
+            Some(&len) => usize::from(len),
+            None => {
+                self.data = &[];
+                return Some(Err(Error::truncated("option length", self.offset + 1)));
+            }
+        };
+        if len < 2 {
+            self.data = &[];
+            return Some(Err(Error::malformed("option length", self.offset + 1)));
+        }
+        if len > self.data.len() {
+            self.data = &[];
+            return Some(Err(Error::truncated("option data", self.offset + 2)));
+        }
+        let option =
+            TcpOption::parse(kind, &self.data[2..len]).map_err(|e| e.shifted(self.offset + 2));
+        self.data = if option.is_ok() {
+            self.offset += len;
+            &self.data[len..]
+        } else {
+            &[]
+        };
+        Some(option)
+    }
+}
+
+/// Encode `options` for a header, padded with End of Option List to a multiple of 4 bytes.
+///
+/// Fails with `Exhausted` if they take more than [`MAX_OPTIONS_LEN`] bytes.
+pub fn encode(options: &[TcpOption]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for option in options {
+        option.write(&mut out)?;
+    }
+    while out.len() % 4 != 0 {
+        out.push(OptionKinds::EndOfList);
+    }
+    if out.len() > MAX_OPTIONS_LEN {
+        return Err(Error::Exhausted);
+    }
+    Ok(out)
+}
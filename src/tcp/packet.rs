@@ -0,0 +1,276 @@
+//! TCP segments in the style of `ether::EthernetPacket`.
+
+use super::options::{self, Options, TcpOption};
+use crate::arp::ether::{FromPacket, Packet};
+use std::fmt;
+
+/// The control bits.
+#[allow(non_snake_case)]
+#[allow(non_upper_case_globals)]
+pub mod TcpFlags {
+    /// No more data from the sender
+    pub const FIN: u8 = 0x01;
+    /// Synchronize sequence numbers
+    pub const SYN: u8 = 0x02;
+    /// Reset the connection
+    pub const RST: u8 = 0x04;
+    /// Push the data to the application
+    pub const PSH: u8 = 0x08;
+    /// The acknowledgment number is valid
+    pub const ACK: u8 = 0x10;
+    /// The urgent pointer is valid
+    pub const URG: u8 = 0x20;
+    /// ECN echo
+    pub const ECE: u8 = 0x40;
+    /// Congestion window reduced
+    pub const CWR: u8 = 0x80;
+}
+
+fn header_len(data: &[u8]) -> usize {
+    usize::from(data[12] >> 4) * 4
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    data.len()
+}
+
+packet_types! {
+    /// A TCP segment. Its length is the one the IP header gives, so the payload runs to
+    /// the end of the data.
+    pub struct TcpPacket / MutableTcpPacket;
+    minimum_size = 20;
+    header_len = header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the source port
+        #[inline]
+        pub fn get_source(&self) -> u16 {
+            u16::from_be_bytes([self.packet[0], self.packet[1]])
+        }
+        /// Get the destination port
+        #[inline]
+        pub fn get_destination(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+        /// Get the sequence number
+        #[inline]
+        pub fn get_sequence(&self) -> u32 {
+            u32::from_be_bytes([self.packet[4], self.packet[5], self.packet[6], self.packet[7]])
+        }
+        /// Get the acknowledgment number
+        #[inline]
+        pub fn get_acknowledgement(&self) -> u32 {
+            u32::from_be_bytes([self.packet[8], self.packet[9], self.packet[10], self.packet[11]])
+        }
+        /// Get the data offset, the header length in 32 bit words
+        #[inline]
+        pub fn get_data_offset(&self) -> u8 {
+            self.packet[12] >> 4
+        }
+        /// Get the reserved bits
+        #[inline]
+        pub fn get_reserved(&self) -> u8 {
+            self.packet[12] & 0x0f
+        }
+        /// Get the control bits, see `TcpFlags`
+        #[inline]
+        pub fn get_flags(&self) -> u8 {
+            self.packet[13]
+        }
+        /// Get the window size
+        #[inline]
+        pub fn get_window(&self) -> u16 {
+            u16::from_be_bytes([self.packet[14], self.packet[15]])
+        }
+        /// Get the checksum field
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[16], self.packet[17]])
+        }
+        /// Get the urgent pointer
+        #[inline]
+        pub fn get_urgent_ptr(&self) -> u16 {
+            u16::from_be_bytes([self.packet[18], self.packet[19]])
+        }
+        /// Get the bytes of the options, between the fixed header and the payload
+        pub fn get_options_raw(&self) -> &[u8] {
+            let end = header_len(&self.packet[..]).max(20).min(self.packet.len());
+            &self.packet[20..end]
+        }
+        /// Iterate over the options
+        pub fn get_options_iter(&self) -> Options {
+            Options::new(self.get_options_raw())
+        }
+        /// Get the options, up to the first malformed one
+        pub fn get_options(&self) -> Vec<TcpOption> {
+            self.get_options_iter().map_while(Result::ok).collect()
+        }
+    }
+    setters {
+        /// Set the source port
+        #[inline]
+        pub fn set_source(&mut self, val: u16) {
+            self.packet[0..2].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the destination port
+        #[inline]
+        pub fn set_destination(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the sequence number
+        #[inline]
+        pub fn set_sequence(&mut self, val: u32) {
+            self.packet[4..8].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the acknowledgment number
+        #[inline]
+        pub fn set_acknowledgement(&mut self, val: u32) {
+            self.packet[8..12].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the data offset, in 32 bit words
+        #[inline]
+        pub fn set_data_offset(&mut self, val: u8) {
+            self.packet[12] = (self.packet[12] & 0x0f) | (val << 4);
+        }
+        /// Set the reserved bits
+        #[inline]
+        pub fn set_reserved(&mut self, val: u8) {
+            self.packet[12] = (self.packet[12] & 0xf0) | (val & 0x0f);
+        }
+        /// Set the control bits
+        #[inline]
+        pub fn set_flags(&mut self, val: u8) {
+            self.packet[13] = val;
+        }
+        /// Set the window size
+        #[inline]
+        pub fn set_window(&mut self, val: u16) {
+            self.packet[14..16].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[16..18].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the urgent pointer
+        #[inline]
+        pub fn set_urgent_ptr(&mut self, val: u16) {
+            self.packet[18..20].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Copy `vals` into the options area, as far as the data offset leaves room
+        pub fn set_options_raw(&mut self, vals: &[u8]) {
+            let end = header_len(&self.packet[..]).max(20).min(self.packet.len());
+            let len = vals.len().min(end - 20);
+            self.packet[20..20 + len].copy_from_slice(&vals[..len]);
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let start = header_len(&self.packet[..]).min(self.packet.len());
+            let len = vals.len().min(self.packet.len() - start);
+            self.packet[start..start + len].copy_from_slice(&vals[..len]);
+        }
+        /// Populates the packet using a `Tcp` structure. The buffer has to hold the
+        /// header, with `data_offset` words, and the payload
+        pub fn populate(&mut self, packet: &Tcp) {
+            self.set_source(packet.source);
+            self.set_destination(packet.destination);
+            self.set_sequence(packet.sequence);
+            self.set_acknowledgement(packet.acknowledgement);
+            self.set_data_offset(packet.data_offset);
+            self.set_reserved(packet.reserved);
+            self.set_flags(packet.flags);
+            self.set_window(packet.window);
+            self.set_checksum(packet.checksum);
+            self.set_urgent_ptr(packet.urgent_ptr);
+            // options that don't fit the data offset are left out
+            self.set_options_raw(&options::encode(&packet.options).unwrap_or_default());
+            self.set_payload(&packet.payload);
+        }
+    }
+}
+
+impl<'a> TcpPacket<'a> {
+    /// The size (in bytes) of a Tcp instance when converted into a byte-array.
+    pub fn packet_size(packet: &Tcp) -> usize {
+        usize::from(packet.data_offset) * 4 + packet.payload.len()
+    }
+}
+
+impl<'a> MutableTcpPacket<'a> {
+    /// The size (in bytes) of a Tcp instance when converted into a byte-array.
+    pub fn packet_size(packet: &Tcp) -> usize {
+        usize::from(packet.data_offset) * 4 + packet.payload.len()
+    }
+}
+
+/// A TCP segment, owned.
+#[derive(Clone, Debug)]
+pub struct Tcp {
+    pub source: u16,
+    pub destination: u16,
+    pub sequence: u32,
+    pub acknowledgement: u32,
+    pub data_offset: u8,
+    pub reserved: u8,
+    pub flags: u8,
+    pub window: u16,
+    pub checksum: u16,
+    pub urgent_ptr: u16,
+    pub options: Vec<TcpOption>,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for TcpPacket<'p> {
+    type T = Tcp;
+    fn from_packet(&self) -> Tcp {
+        Tcp {
+            source: self.get_source(),
+            destination: self.get_destination(),
+            sequence: self.get_sequence(),
+            acknowledgement: self.get_acknowledgement(),
+            data_offset: self.get_data_offset(),
+            reserved: self.get_reserved(),
+            flags: self.get_flags(),
+            window: self.get_window(),
+            checksum: self.get_checksum(),
+            urgent_ptr: self.get_urgent_ptr(),
+            options: self.get_options(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableTcpPacket<'p> {
+    type T = Tcp;
+    fn from_packet(&self) -> Tcp {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for TcpPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "TcpPacket {{ source : {}, destination : {}, sequence : {}, \
+             acknowledgement : {}, data_offset : {}, reserved : {}, flags : {:#04x}, \
+             window : {}, checksum : {:#06x}, urgent_ptr : {}, options : {:?} }}",
+            self.get_source(),
+            self.get_destination(),
+            self.get_sequence(),
+            self.get_acknowledgement(),
+            self.get_data_offset(),
+            self.get_reserved(),
+            self.get_flags(),
+            self.get_window(),
+            self.get_checksum(),
+            self.get_urgent_ptr(),
+            self.get_options()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableTcpPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
@@ -0,0 +1,1429 @@
+//! Listening and accepting TCP connections with the state machine of
+//! [`connection`](super::connection) instead of the kernel's.
+//!
+//! An [`Interface`] owns a link and a thread feeding it the segments received. On Linux the
+//! link can be a tun device, which the kernel routes packets of the device's network to,
+//! so with
+//!
+//! ```text
+//! ip addr add 192.168.0.1/24 dev tun0
+//! ip link set up dev tun0
+//! ```
+//!
+//! `nc 192.168.0.2 8000` reaches a [`TcpListener`] bound to port 8000. Anywhere, the link
+//! can be an Ethernet channel opened through a [`DatalinkBackend`], on which the stack
+//! owns an IPv4 address of the interface and answers ARP requests for it.
+//!
+//! [`UdpSocket`]s exchange datagrams on the same link. A [`Poller`] tells which sockets
+//! are ready, for event loops serving many on one thread. With the `tokio` feature, the
+//! sockets have async counterparts woken by the serving thread.
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+mod device;
+mod poller;
+
+#[cfg(feature = "tokio")]
+pub use self::asynchronous::{AsyncTcpListener, AsyncTcpStream, AsyncUdpSocket};
+pub use self::poller::{Event, Interest, Poller, Source};
+
+use self::device::Device;
+use super::{
+    connection::{self, Connection, Quad, SequenceGenerator, State},
+    ipv4_checksum, TcpFlags, TcpPacket,
+};
+use crate::{
+    arp::{
+        channel::{Channel, Config as ChannelConfig, DatalinkBackend},
+        ether::Packet,
+        network_interface::NetworkInterface,
+    },
+    clock::{self, Clock},
+    dscp::Dscp,
+    ecn::{self, Ecn},
+    ipv4::{IpNextHeaderProtocols, Ipv4Flags, Ipv4Packet, MutableIpv4Packet},
+    metrics::{Counter, Gauge, Metrics},
+    neighbor::{Arp, CacheConfig, NeighborCache},
+    ttl::DEFAULT_TTL,
+    udp::{self, MutableUdpPacket, UdpPacket},
+};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    io::{self, Read, Write},
+    mem,
+    net::{IpAddr, Ipv4Addr, Shutdown, SocketAddrV4},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    task::Waker,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Log target of the stack.
+const LOG_TARGET: &str = "myox::tcp";
+
+/// How long the link is waited on before timers are looked at.
+const TICK: Duration = Duration::from_millis(10);
+
+/// How long the MAC addresses of peers on an Ethernet link are remembered.
+const NEIGHBOR_TTL: Duration = Duration::from_secs(60);
+
+/// Connections a listener holds before they are accepted, unless told otherwise.
+pub const DEFAULT_BACKLOG: usize = 128;
+
+/// Datagrams a UDP socket holds before newer ones are dropped.
+pub const UDP_QUEUE_LEN: usize = 64;
+
+/// The largest datagram payload sent, as the stack doesn't fragment.
+const MAX_DATAGRAM: usize = 1500 - 20 - 8;
+
+#[derive(Debug)]
+struct Listener {
+    /// Connections not accepted yet, through their handshake or not
+    pending: VecDeque<Quad>,
+    /// The most connections pending, SYNs beyond are dropped
+    backlog: usize,
+    /// Settings of the connections accepted
+    config: connection::Config,
+}
+
+#[derive(Debug)]
+struct UdpBinding {
+    /// The address bound, unspecified for every address
+    ip: Ipv4Addr,
+    /// Datagrams received, with their sender and what their IP header said
+    datagrams: VecDeque<(SocketAddrV4, RecvMeta, Vec<u8>)>,
+    /// Marks the datagrams sent
+    dscp: Dscp,
+    /// Time to live of the datagrams sent
+    ttl: u8,
+}
+
+/// What the IP header of a datagram received tells, besides its addresses.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RecvMeta {
+    /// The time to live left to the datagram
+    pub ttl: u8,
+    /// The differentiated services the datagram was marked for
+    pub dscp: Dscp,
+    /// The ECN codepoint, as the routers on the way left it
+    pub ecn: Ecn,
+}
+
+#[derive(Debug)]
+struct Connections {
+    connections: HashMap<Quad, Connection>,
+    /// Connections handed out as streams, kept once over until the stream is dropped so
+    /// that it can tell a close from a reset
+    streams: HashSet<Quad>,
+    sequences: SequenceGenerator,
+    /// By listening port
+    listeners: HashMap<u16, Listener>,
+    /// UDP sockets by port
+    udp: HashMap<u16, UdpBinding>,
+    /// Packets sent by UDP sockets, for the serving thread to pass on
+    outgoing: Vec<Vec<u8>>,
+    /// IP identification of the next datagram
+    next_id: u16,
+    /// Tasks to wake on the next tick
+    wakers: Vec<Waker>,
+    /// The device failed and the stack stopped
+    failed: bool,
+    counters: StackCounters,
+}
+
+/// Counters of the stack, detached from any [`Metrics`] unless given some.
+#[derive(Debug, Default)]
+struct StackCounters {
+    segments: Counter,
+    checksum_errors: Counter,
+    accepted: Counter,
+    syns_dropped: Counter,
+    refused: Counter,
+    retransmissions: Counter,
+    connections: Gauge,
+}
+
+// The `try_` methods do what sockets ask for if they can, and fail with `WouldBlock` if
+// they have to wait for the stack.
+impl Connections {
+    fn try_accept(&mut self, port: u16) -> io::Result<Quad> {
+        let connections = &self.connections;
+        let accepted = self.listeners.get_mut(&port).and_then(|listener| {
+            let index = listener
+                .pending
+                .iter()
+                .position(|quad| handshaken(connections, quad))?;
+            listener.pending.remove(index)
+        });
+        match accepted {
+            Some(quad) => {
+                self.streams.insert(quad);
+                Ok(quad)
+            }
+            None => self.blocked(),
+        }
+    }
+
+    // the connection of a stream, unless it was reset
+    fn stream(&mut self, quad: &Quad) -> io::Result<&mut Connection> {
+        match self.connections.get_mut(quad) {
+            Some(connection) if !connection.was_reset() => Ok(connection),
+            _ => Err(reset()),
+        }
+    }
+
+    fn try_read(&mut self, quad: &Quad, buf: &mut [u8]) -> io::Result<usize> {
+        let connection = self.stream(quad)?;
+        if connection.available() > 0 || buf.is_empty() {
+            return Ok(connection.read(buf));
+        }
+        if connection.is_recv_closed() {
+            return Ok(0);
+        }
+        self.blocked()
+    }
+
+    fn try_write(&mut self, quad: &Quad, buf: &[u8]) -> io::Result<usize> {
+        let connection = self.stream(quad)?;
+        if connection.is_send_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Writes were shut down",
+            ));
+        }
+        let written = connection.write(buf);
+        if written > 0 || buf.is_empty() {
+            return Ok(written);
+        }
+        self.blocked()
+    }
+
+    // everything written was acknowledged
+    fn try_flush(&mut self, quad: &Quad) -> io::Result<()> {
+        if self.stream(quad)?.unacknowledged() > 0 {
+            return self.blocked();
+        }
+        Ok(())
+    }
+
+    fn try_recv_from(
+        &mut self,
+        port: u16,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddrV4, RecvMeta)> {
+        let binding = self.udp.get_mut(&port).ok_or_else(stopped)?;
+        match binding.datagrams.pop_front() {
+            Some((source, meta, datagram)) => {
+                // like the kernel's, the rest of a datagram too long for `buf` is lost
+                let len = datagram.len().min(buf.len());
+                buf[..len].copy_from_slice(&datagram[..len]);
+                Ok((len, source, meta))
+            }
+            None => self.blocked(),
+        }
+    }
+
+    fn blocked<T>(&self) -> io::Result<T> {
+        if self.failed {
+            Err(stopped())
+        } else {
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    connections: Mutex<Connections>,
+    /// Signalled whenever connections change
+    changed: Condvar,
+    stop: AtomicBool,
+    clock: Arc<dyn Clock>,
+    /// The address owned on the link, if any
+    address: Option<Ipv4Addr>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<Connections> {
+        self.connections.lock().unwrap()
+    }
+
+    // `attempt` again whenever connections change, until it doesn't have to wait
+    fn wait_for<T, F>(&self, mut attempt: F) -> io::Result<T>
+    where
+        F: FnMut(&mut Connections) -> io::Result<T>,
+    {
+        let mut connections = self.lock();
+        loop {
+            match attempt(&mut connections) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    connections = self.changed.wait(connections).unwrap()
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// A link served by the userspace TCP stack.
+#[derive(Debug)]
+pub struct Interface {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Interface {
+    /// Create the tun device `name` and start serving it.
+    #[cfg(target_os = "linux")]
+    pub fn open(name: &str) -> io::Result<Interface> {
+        Interface::with_clock(name, clock::system())
+    }
+
+    /// Create the tun device `name` and start serving it, timing connections out with
+    /// `clock`.
+    #[cfg(target_os = "linux")]
+    pub fn with_clock(name: &str, clock: Arc<dyn Clock>) -> io::Result<Interface> {
+        let device = device::Tun {
+            device: tun_tap::Iface::without_packet_info(name, tun_tap::Mode::Tun)?,
+            buffer: [0; 1504],
+        };
+        Ok(Interface::start(Box::new(device), clock, None))
+    }
+
+    /// Serve the first IPv4 address of `interface` on a channel opened through `backend`.
+    pub fn open_with_backend(
+        backend: &dyn DatalinkBackend,
+        interface: &NetworkInterface,
+    ) -> io::Result<Interface> {
+        Interface::with_backend_and_clock(backend, interface, clock::system())
+    }
+
+    /// [`Interface::open_with_backend`], timing connections out with `clock`.
+    pub fn with_backend_and_clock(
+        backend: &dyn DatalinkBackend,
+        interface: &NetworkInterface,
+        clock: Arc<dyn Clock>,
+    ) -> io::Result<Interface> {
+        let mac = interface.mac.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("interface {} has no MAC address", interface.name),
+            )
+        })?;
+        let ip = interface
+            .ips
+            .iter()
+            .flatten()
+            .find_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(*ip),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("interface {} has no IPv4 address", interface.name),
+                )
+            })?;
+        let channel_config = ChannelConfig {
+            read_timeout: Some(TICK),
+            ..Default::default()
+        };
+        let (tx, rx) = match backend.channel(interface, channel_config)? {
+            Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+        };
+        let device = device::Ethernet {
+            tx,
+            rx,
+            arp: Arp { mac, ip },
+            neighbors: NeighborCache::with_clock(
+                CacheConfig {
+                    ttl: NEIGHBOR_TTL,
+                    ..Default::default()
+                },
+                clock.clone(),
+            ),
+            packet: Vec::new(),
+        };
+        Ok(Interface::start(Box::new(device), clock, Some(ip)))
+    }
+
+    fn start(
+        mut device: Box<dyn Device>,
+        clock: Arc<dyn Clock>,
+        address: Option<Ipv4Addr>,
+    ) -> Interface {
+        let shared = Arc::new(Shared {
+            connections: Mutex::new(Connections {
+                connections: HashMap::new(),
+                streams: HashSet::new(),
+                sequences: SequenceGenerator::new(clock.now()),
+                listeners: HashMap::new(),
+                udp: HashMap::new(),
+                outgoing: Vec::new(),
+                next_id: 0,
+                wakers: Vec::new(),
+                failed: false,
+                counters: StackCounters::default(),
+            }),
+            changed: Condvar::new(),
+            stop: AtomicBool::new(false),
+            clock,
+            address,
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                if let Err(e) = serve(&mut *device, &shared) {
+                    log::error!(target: LOG_TARGET, "{}", e);
+                }
+                let wakers = {
+                    let mut connections = shared.lock();
+                    connections.failed = true;
+                    mem::take(&mut connections.wakers)
+                };
+                shared.changed.notify_all();
+                wakers.into_iter().for_each(Waker::wake);
+            })
+        };
+        Interface {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Count into `metrics` the TCP segments received as `<prefix>.segments` and dropped
+    /// for a bad checksum as `<prefix>.checksum_errors`, the connections accepted as
+    /// `<prefix>.accepted`, the SYNs dropped for a full backlog as `<prefix>.syns_dropped`,
+    /// the segments for no connection or listener as `<prefix>.refused` and the
+    /// retransmission timeouts as `<prefix>.retransmissions`. `<prefix>.connections` is
+    /// the number of connections open.
+    pub fn with_metrics(self, metrics: &Metrics, prefix: &str) -> Interface {
+        let counter = |name: &str| metrics.counter(&format!("{}.{}", prefix, name));
+        let counters = StackCounters {
+            segments: counter("segments"),
+            checksum_errors: counter("checksum_errors"),
+            accepted: counter("accepted"),
+            syns_dropped: counter("syns_dropped"),
+            refused: counter("refused"),
+            retransmissions: counter("retransmissions"),
+            connections: metrics.gauge(&format!("{}.connections", prefix)),
+        };
+        let mut connections = self.shared.lock();
+        counters
+            .connections
+            .set(connections.connections.len() as f64);
+        connections.counters = counters;
+        drop(connections);
+        self
+    }
+
+    /// Listen on `port`.
+    pub fn bind(&self, port: u16) -> io::Result<TcpListener> {
+        TcpListener::bind(self, port)
+    }
+
+    /// Bind a UDP socket to `local`.
+    pub fn bind_udp(&self, local: SocketAddrV4) -> io::Result<UdpSocket> {
+        UdpSocket::bind(self, local)
+    }
+
+    /// Watch sockets of the interface for readiness.
+    pub fn poller(&self) -> Poller {
+        Poller::new(self)
+    }
+}
+
+impl Drop for Interface {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Connections made to a port of an [`Interface`].
+#[derive(Debug)]
+pub struct TcpListener {
+    shared: Arc<Shared>,
+    port: u16,
+}
+
+impl TcpListener {
+    /// Listen on `port` of `interface`. Fails with `AddrInUse` if something already does.
+    pub fn bind(interface: &Interface, port: u16) -> io::Result<TcpListener> {
+        TcpListener::bind_with_backlog(interface, port, DEFAULT_BACKLOG)
+    }
+
+    /// Listen on `port` of `interface`, holding up to `backlog` connections until they are
+    /// accepted. SYNs coming while it is full are dropped, for the peer to send again.
+    pub fn bind_with_backlog(
+        interface: &Interface,
+        port: u16,
+        backlog: usize,
+    ) -> io::Result<TcpListener> {
+        let shared = Arc::clone(&interface.shared);
+        match shared.lock().listeners.entry(port) {
+            Entry::Occupied(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "Port already bound",
+                ))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Listener {
+                    pending: VecDeque::new(),
+                    backlog,
+                    config: connection::Config::default(),
+                });
+            }
+        }
+        Ok(TcpListener { shared, port })
+    }
+
+    /// The port listened on.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Negotiate ECN on the connections made from now on as `config` says.
+    pub fn set_ecn(&self, config: ecn::Config) {
+        if let Some(listener) = self.shared.lock().listeners.get_mut(&self.port) {
+            listener.config.ecn = config;
+        }
+    }
+
+    /// Wait for a connection.
+    pub fn accept(&self) -> io::Result<(TcpStream, SocketAddrV4)> {
+        let quad = self
+            .shared
+            .wait_for(|connections| connections.try_accept(self.port))?;
+        Ok((self.stream(quad), quad.remote))
+    }
+
+    fn stream(&self, quad: Quad) -> TcpStream {
+        TcpStream {
+            shared: Arc::clone(&self.shared),
+            quad,
+        }
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let mut connections = self.shared.lock();
+        // connections nobody will accept are closed
+        let pending = connections
+            .listeners
+            .remove(&self.port)
+            .map(|listener| listener.pending)
+            .unwrap_or_default();
+        for quad in pending {
+            if let Some(connection) = connections.connections.get_mut(&quad) {
+                connection.close();
+            }
+        }
+    }
+}
+
+/// A connection accepted by a [`TcpListener`].
+#[derive(Debug)]
+pub struct TcpStream {
+    shared: Arc<Shared>,
+    quad: Quad,
+}
+
+impl TcpStream {
+    /// The address of the peer.
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.quad.remote
+    }
+
+    /// The address the peer connected to.
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.quad.local
+    }
+
+    /// Whether ECN was negotiated with the peer.
+    pub fn is_ecn_capable(&self) -> io::Result<bool> {
+        Ok(self.shared.lock().stream(&self.quad)?.is_ecn_capable())
+    }
+
+    /// The DSCP the segments sent are marked with.
+    pub fn dscp(&self) -> io::Result<Dscp> {
+        Ok(self.shared.lock().stream(&self.quad)?.dscp())
+    }
+
+    /// Mark the segments sent from now on with `dscp`.
+    pub fn set_dscp(&self, dscp: Dscp) -> io::Result<()> {
+        self.shared.lock().stream(&self.quad)?.set_dscp(dscp);
+        Ok(())
+    }
+
+    /// The time to live of the segments sent.
+    pub fn ttl(&self) -> io::Result<u8> {
+        Ok(self.shared.lock().stream(&self.quad)?.ttl())
+    }
+
+    /// Send the segments from now on with a time to live of `ttl`. Fails with
+    /// `InvalidInput` for 0.
+    pub fn set_ttl(&self, ttl: u8) -> io::Result<()> {
+        check_ttl(ttl)?;
+        self.shared.lock().stream(&self.quad)?.set_ttl(ttl);
+        Ok(())
+    }
+
+    /// Shut down writes, sending a FIN after the data written so far. Reads can't be shut
+    /// down.
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if how == Shutdown::Read {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Reads can't be shut down",
+            ));
+        }
+        self.shared.lock().stream(&self.quad)?.close();
+        Ok(())
+    }
+}
+
+impl Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let quad = self.quad;
+        self.shared
+            .wait_for(|connections| connections.try_read(&quad, buf))
+    }
+}
+
+impl Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let quad = self.quad;
+        self.shared
+            .wait_for(|connections| connections.try_write(&quad, buf))
+    }
+
+    /// Wait for the peer to acknowledge everything written.
+    fn flush(&mut self) -> io::Result<()> {
+        let quad = self.quad;
+        self.shared
+            .wait_for(|connections| connections.try_flush(&quad))
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut connections = self.shared.lock();
+        connections.streams.remove(&self.quad);
+        if let Some(connection) = connections.connections.get_mut(&self.quad) {
+            connection.close();
+        }
+    }
+}
+
+/// A UDP socket on an [`Interface`].
+#[derive(Debug)]
+pub struct UdpSocket {
+    shared: Arc<Shared>,
+    local: SocketAddrV4,
+}
+
+impl UdpSocket {
+    /// Bind to `local` on `interface`, or to every address with an unspecified address.
+    /// Fails with `AddrInUse` if the port is bound already.
+    ///
+    /// Up to [`UDP_QUEUE_LEN`] datagrams wait to be received, newer ones are dropped.
+    pub fn bind(interface: &Interface, local: SocketAddrV4) -> io::Result<UdpSocket> {
+        let shared = Arc::clone(&interface.shared);
+        match shared.lock().udp.entry(local.port()) {
+            Entry::Occupied(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "Port already bound",
+                ))
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(UdpBinding {
+                    ip: *local.ip(),
+                    datagrams: VecDeque::new(),
+                    dscp: Dscp::default(),
+                    ttl: DEFAULT_TTL,
+                });
+            }
+        }
+        Ok(UdpSocket { shared, local })
+    }
+
+    /// The address bound to.
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.local
+    }
+
+    /// Send `buf` to `target`, from the address bound to or else the address the interface
+    /// owns. Datagrams are sent as they are, so they must fit in an Ethernet frame.
+    pub fn send_to(&self, buf: &[u8], target: SocketAddrV4) -> io::Result<usize> {
+        if buf.len() > MAX_DATAGRAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Datagram too large",
+            ));
+        }
+        let source = match (*self.local.ip(), self.shared.address) {
+            (ip, _) if !ip.is_unspecified() => ip,
+            (_, Some(ip)) => ip,
+            (_, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "No address to send from",
+                ))
+            }
+        };
+        let mut connections = self.shared.lock();
+        if connections.failed {
+            return Err(stopped());
+        }
+        let id = connections.next_id;
+        connections.next_id = id.wrapping_add(1);
+        let binding = self.binding(&mut connections)?;
+        let (dscp, ttl) = (binding.dscp, binding.ttl);
+        let source = SocketAddrV4::new(source, self.local.port());
+        let packet = datagram(source, target, id, dscp, ttl, buf);
+        connections.outgoing.push(packet);
+        Ok(buf.len())
+    }
+
+    /// Wait for a datagram and copy it into `buf`, returning its length and sender. The
+    /// part of the datagram that doesn't fit is discarded.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddrV4)> {
+        let (len, source, _) = self.recv_with_meta(buf)?;
+        Ok((len, source))
+    }
+
+    /// [`UdpSocket::recv_from`], also returning what the IP header of the datagram told.
+    pub fn recv_with_meta(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddrV4, RecvMeta)> {
+        let port = self.local.port();
+        self.shared
+            .wait_for(|connections| connections.try_recv_from(port, buf))
+    }
+
+    /// The DSCP the datagrams sent are marked with.
+    pub fn dscp(&self) -> io::Result<Dscp> {
+        Ok(self.binding(&mut self.shared.lock())?.dscp)
+    }
+
+    /// Mark the datagrams sent from now on with `dscp`.
+    pub fn set_dscp(&self, dscp: Dscp) -> io::Result<()> {
+        self.binding(&mut self.shared.lock())?.dscp = dscp;
+        Ok(())
+    }
+
+    /// The time to live of the datagrams sent.
+    pub fn ttl(&self) -> io::Result<u8> {
+        Ok(self.binding(&mut self.shared.lock())?.ttl)
+    }
+
+    /// Send the datagrams from now on with a time to live of `ttl`. Fails with
+    /// `InvalidInput` for 0.
+    pub fn set_ttl(&self, ttl: u8) -> io::Result<()> {
+        check_ttl(ttl)?;
+        self.binding(&mut self.shared.lock())?.ttl = ttl;
+        Ok(())
+    }
+
+    fn binding<'a>(&self, connections: &'a mut Connections) -> io::Result<&'a mut UdpBinding> {
+        connections
+            .udp
+            .get_mut(&self.local.port())
+            .ok_or_else(stopped)
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        self.shared.lock().udp.remove(&self.local.port());
+    }
+}
+
+// an IPv4 packet carrying a UDP datagram, marked with a DSCP and sent with a TTL
+fn datagram(
+    source: SocketAddrV4,
+    destination: SocketAddrV4,
+    id: u16,
+    dscp: Dscp,
+    ttl: u8,
+    data: &[u8],
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; 20 + 8 + data.len()];
+    {
+        let mut udp = MutableUdpPacket::new(&mut buffer[20..]).unwrap();
+        udp.set_source(source.port());
+        udp.set_destination(destination.port());
+        udp.set_length(8 + data.len() as u16);
+        udp.set_payload(data);
+        let sum = udp::ipv4_checksum(&udp.to_immutable(), *source.ip(), *destination.ip());
+        udp.set_checksum(sum);
+    }
+    let total_len = buffer.len() as u16;
+    let mut ip = MutableIpv4Packet::new(&mut buffer).unwrap();
+    ip.set_version(4);
+    ip.set_header_length(5);
+    ip.set_total_length(total_len);
+    ip.set_identification(id);
+    ip.set_flags(Ipv4Flags::DontFragment);
+    ip.set_dscp(dscp.0);
+    ip.set_ttl(ttl);
+    ip.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+    ip.set_source(*source.ip());
+    ip.set_destination(*destination.ip());
+    ip.update_checksum();
+    buffer
+}
+
+fn reset() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "Connection reset")
+}
+
+// a packet sent with a TTL of 0 is dropped by the first router
+fn check_ttl(ttl: u8) -> io::Result<()> {
+    if ttl == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "TTL must be at least 1",
+        ));
+    }
+    Ok(())
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "TCP stack stopped")
+}
+
+// whether the connection of `quad` is through its handshake, and may be accepted
+fn handshaken(connections: &HashMap<Quad, Connection>, quad: &Quad) -> bool {
+    connections
+        .get(quad)
+        .map_or(false, |connection| connection.state() != State::SynReceived)
+}
+
+// hand the segments received to their connection, and send what they have to
+fn serve(device: &mut dyn Device, shared: &Shared) -> io::Result<()> {
+    let mut out = Vec::new();
+    while !shared.stop.load(Ordering::SeqCst) {
+        let received = device.recv()?;
+        let now = shared.clock.now();
+        let mut connections = shared.lock();
+        if let Some(packet) = received {
+            receive(packet, now, &mut connections, &mut out);
+        }
+        let mut retransmissions = 0;
+        for connection in connections.connections.values_mut() {
+            let before = connection.retransmissions();
+            connection.on_tick(now, &mut out);
+            retransmissions += connection.retransmissions() - before;
+        }
+        let Connections {
+            connections: open,
+            streams,
+            listeners,
+            counters,
+            ..
+        } = &mut *connections;
+        // what is over is forgotten, once its stream is gone
+        open.retain(|quad, connection| !connection.is_done() || streams.contains(quad));
+        counters.retransmissions.add(u64::from(retransmissions));
+        let active = open.values().filter(|connection| !connection.is_done());
+        counters.connections.set(active.count() as f64);
+        // connections reset before they were accepted leave room in the backlog
+        for listener in listeners.values_mut() {
+            listener.pending.retain(|quad| open.contains_key(quad));
+        }
+        out.append(&mut connections.outgoing);
+        let wakers = mem::take(&mut connections.wakers);
+        drop(connections);
+
+        for packet in out.drain(..) {
+            device.send(&packet)?;
+        }
+        shared.changed.notify_all();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+    Ok(())
+}
+
+fn receive(packet: &[u8], now: Instant, connections: &mut Connections, out: &mut Vec<Vec<u8>>) {
+    let ip = match Ipv4Packet::new(packet) {
+        Some(ip) if ip.get_version() == 4 => ip,
+        _ => return,
+    };
+    match ip.get_next_level_protocol() {
+        IpNextHeaderProtocols::Tcp => {}
+        IpNextHeaderProtocols::Udp => return receive_datagram(&ip, connections),
+        _ => return,
+    }
+    let tcp = match TcpPacket::new(ip.payload()) {
+        Some(tcp) => tcp,
+        None => return,
+    };
+    if ipv4_checksum(&tcp, ip.get_source(), ip.get_destination()) != tcp.get_checksum() {
+        log::debug!(target: LOG_TARGET, "segment from {} with bad checksum", ip.get_source());
+        connections.counters.checksum_errors.increment();
+        return;
+    }
+    connections.counters.segments.increment();
+    let local = SocketAddrV4::new(ip.get_destination(), tcp.get_destination());
+    let remote = SocketAddrV4::new(ip.get_source(), tcp.get_source());
+    let quad = Quad { local, remote };
+
+    if let Some(connection) = connections.connections.get_mut(&quad) {
+        if connection.is_done() {
+            // its stream is still around, the ends can't be taken again yet
+            connections.counters.refused.increment();
+            out.extend(connection::reset(local, remote, &tcp));
+        } else {
+            let codepoint = Ecn::from(ip.get_ecn());
+            connection.on_segment(&tcp, codepoint, now, out);
+        }
+        return;
+    }
+    let listener = match connections.listeners.get_mut(&local.port()) {
+        Some(listener) => listener,
+        None => {
+            connections.counters.refused.increment();
+            out.extend(connection::reset(local, remote, &tcp));
+            return;
+        }
+    };
+    if listener.pending.len() >= listener.backlog && tcp.get_flags() & TcpFlags::SYN != 0 {
+        log::debug!(
+            target: LOG_TARGET,
+            "backlog of port {} full, SYN from {} dropped",
+            local.port(),
+            remote
+        );
+        connections.counters.syns_dropped.increment();
+        return;
+    }
+    let config = listener.config;
+    match Connection::accept(
+        local,
+        remote,
+        &tcp,
+        config,
+        &connections.sequences,
+        now,
+        out,
+    ) {
+        Some(connection) => {
+            log::debug!(target: LOG_TARGET, "connection from {} to {}", remote, local);
+            listener.pending.push_back(quad);
+            connections.connections.insert(quad, connection);
+            connections.counters.accepted.increment();
+        }
+        None => {
+            connections.counters.refused.increment();
+            out.extend(connection::reset(local, remote, &tcp));
+        }
+    }
+}
+
+fn receive_datagram(ip: &Ipv4Packet, connections: &mut Connections) {
+    let udp = match UdpPacket::new(ip.payload()) {
+        Some(udp) => udp,
+        None => return,
+    };
+    let sum = udp.get_checksum();
+    if sum != 0 && udp::ipv4_checksum(&udp, ip.get_source(), ip.get_destination()) != sum {
+        log::debug!(target: LOG_TARGET, "datagram from {} with bad checksum", ip.get_source());
+        return;
+    }
+    let binding = match connections.udp.get_mut(&udp.get_destination()) {
+        Some(binding) if binding.ip.is_unspecified() || binding.ip == ip.get_destination() => {
+            binding
+        }
+        _ => return,
+    };
+    if binding.datagrams.len() >= UDP_QUEUE_LEN {
+        log::debug!(
+            target: LOG_TARGET,
+            "queue of port {} full, datagram from {} dropped",
+            udp.get_destination(),
+            ip.get_source()
+        );
+        return;
+    }
+    let source = SocketAddrV4::new(ip.get_source(), udp.get_source());
+    let meta = RecvMeta {
+        ttl: ip.get_ttl(),
+        dscp: Dscp::new(ip.get_dscp()),
+        ecn: Ecn::from(ip.get_ecn()),
+    };
+    binding
+        .datagrams
+        .push_back((source, meta, udp.payload().to_vec()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        arp::{
+            arp_new::{ArpOperations, ArpPacket},
+            channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+            ether::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+            loopback::Loopback,
+            network_interface::MacAddr,
+            other::build_arp_packet,
+        },
+        dscp::{self, Dscps},
+        flows::FlowPacket,
+        generate, ipv4,
+        scan::ports::{syn_frame, Ipv4Mac},
+        sim::Network,
+        ttl,
+    };
+
+    pub(super) const STACK: Ipv4Mac = Ipv4Mac {
+        ip: Ipv4Addr::new(10, 0, 0, 1),
+        mac: MacAddr(0x02, 0, 0, 0, 0, 1),
+    };
+
+    pub(super) const PEER: Ipv4Mac = Ipv4Mac {
+        ip: Ipv4Addr::new(10, 0, 0, 2),
+        mac: MacAddr(0x02, 0, 0, 0, 0, 2),
+    };
+
+    pub(super) struct Peer {
+        tx: Box<dyn EthernetDataLinkSender>,
+        rx: Box<dyn EthernetDataLinkReceiver>,
+    }
+
+    impl Peer {
+        pub(super) fn send(&mut self, frame: &[u8]) {
+            self.tx.send_to(&EthernetPacket::new(frame).unwrap(), None);
+        }
+
+        // the next frame the stack sends
+        pub(super) fn receive(&mut self) -> Vec<u8> {
+            let mut iter = self.rx.iter();
+            loop {
+                let frame = iter.next().unwrap();
+                if frame.get_source() == STACK.mac {
+                    return frame.packet().to_vec();
+                }
+            }
+        }
+
+        // connect from 40000 to `port` of the stack, returning the stack's first sequence
+        // number
+        pub(super) fn connect(&mut self, port: u16) -> u32 {
+            let isn = self.syn(port);
+            self.send_segment(port, 1001, isn.wrapping_add(1), TcpFlags::ACK, &[]);
+            isn
+        }
+
+        // send the SYN from 40000 to `port` and take the SYN-ACK, returning the stack's
+        // first sequence number
+        pub(super) fn syn(&mut self, port: u16) -> u32 {
+            let syn = syn_frame(PEER, STACK.mac, STACK.ip, 40000, port, 1000, 1);
+            self.send(&syn);
+            let syn_ack = self.receive();
+            assert_eq!(
+                EthernetPacket::new(&syn_ack).unwrap().get_destination(),
+                PEER.mac
+            );
+            let segment = TcpPacket::new(&syn_ack[34..]).unwrap();
+            assert_eq!(segment.get_flags(), TcpFlags::SYN | TcpFlags::ACK);
+            assert_eq!(segment.get_acknowledgement(), 1001);
+            segment.get_sequence()
+        }
+
+        // send `data` on the connection from 40000 to `port`
+        pub(super) fn send_data(&mut self, port: u16, seq: u32, ack: u32, data: &[u8]) {
+            self.send_segment(port, seq, ack, TcpFlags::ACK | TcpFlags::PSH, data);
+        }
+
+        // send a segment with `flags` on the connection from 40000 to `port`
+        pub(super) fn send_segment(
+            &mut self,
+            port: u16,
+            seq: u32,
+            ack: u32,
+            flags: u8,
+            data: &[u8],
+        ) {
+            let quad = Quad {
+                local: SocketAddrV4::new(PEER.ip, 40000),
+                remote: SocketAddrV4::new(STACK.ip, port),
+            };
+            let packet = connection::segment(quad, 2, seq, ack, flags, 1024, data);
+            let mut frame = vec![0u8; 14 + packet.len()];
+            let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+            ethernet.set_destination(STACK.mac);
+            ethernet.set_source(PEER.mac);
+            ethernet.set_ethertype(EtherTypes::Ipv4);
+            ethernet.set_payload(&packet);
+            self.send(&frame);
+        }
+    }
+
+    // a stack on a loopback device, and the peer it talks to
+    pub(super) fn link() -> (Interface, Peer) {
+        let device = Loopback::new().with_address(STACK.mac, vec![IpAddr::V4(STACK.ip)]);
+        let (tx, rx) = match device.channel(ChannelConfig {
+            read_timeout: Some(Duration::from_secs(2)),
+            ..Default::default()
+        }) {
+            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let interface = Interface::open_with_backend(&device, device.interface()).unwrap();
+        (interface, Peer { tx, rx })
+    }
+
+    // a stack on a node of `network` and the peer on the node linked to it, both timed by
+    // the network's clock
+    fn sim_link(network: &Network) -> (Interface, Peer) {
+        let stack = network.add_node("stack", STACK.mac);
+        network.set_addresses(stack, vec![IpAddr::V4(STACK.ip)]);
+        let peer = network.add_node("peer", PEER.mac);
+        network.link(stack, peer, Default::default());
+        let (tx, rx) = match network.channel(
+            peer,
+            ChannelConfig {
+                read_timeout: Some(Duration::from_secs(2)),
+                ..Default::default()
+            },
+        ) {
+            Ok(Channel::Ethernet(tx, rx)) => (tx, rx),
+            _ => unreachable!(),
+        };
+        let interface = Interface::with_backend_and_clock(
+            network,
+            &network.interface(stack),
+            Arc::new(network.clone()),
+        )
+        .unwrap();
+        (interface, Peer { tx, rx })
+    }
+
+    #[test]
+    fn answers_arp_requests() {
+        let (_interface, mut peer) = link();
+        peer.send(&build_arp_packet(
+            MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+            PEER.mac,
+            PEER.ip,
+            MacAddr::new(0, 0, 0, 0, 0, 0),
+            STACK.ip,
+            ArpOperations::Request,
+        ));
+        let reply = peer.receive();
+        let reply = EthernetPacket::new(&reply).unwrap();
+        assert_eq!(reply.get_ethertype(), EtherTypes::Arp);
+        let arp = ArpPacket::new(reply.payload()).unwrap();
+        assert_eq!(arp.get_operation(), ArpOperations::Reply);
+        assert_eq!(arp.get_sender_hw_addr(), STACK.mac);
+    }
+
+    #[test]
+    fn accepts_over_a_backend() {
+        let (interface, mut peer) = link();
+        let listener = interface.bind(8000).unwrap();
+        let isn = peer.connect(8000);
+        let (mut stream, remote) = listener.accept().unwrap();
+        assert_eq!(remote, SocketAddrV4::new(PEER.ip, 40000));
+        assert_eq!(stream.local_addr(), SocketAddrV4::new(STACK.ip, 8000));
+
+        peer.send_data(8000, 1001, isn.wrapping_add(1), b"hello");
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+    }
+
+    // wait for `done` to hold of the stack's connections
+    fn wait_until<F>(interface: &Interface, done: F)
+    where
+        F: Fn(&Connections) -> bool,
+    {
+        for _ in 0..200 {
+            if done(&interface.shared.lock()) {
+                return;
+            }
+            thread::sleep(TICK);
+        }
+        panic!("the stack didn't get there");
+    }
+
+    fn quad(port: u16) -> Quad {
+        Quad {
+            local: SocketAddrV4::new(STACK.ip, port),
+            remote: SocketAddrV4::new(PEER.ip, 40000),
+        }
+    }
+
+    #[test]
+    fn retransmits_on_virtual_time() {
+        let network = Network::new(1);
+        let (interface, mut peer) = sim_link(&network);
+        let listener = interface.bind(8000).unwrap();
+        peer.connect(8000);
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"hello").unwrap();
+        let sent = peer.receive();
+        let retransmissions = |interface: &Interface| {
+            interface.shared.lock().connections[&quad(8000)].retransmissions()
+        };
+
+        // left unacknowledged, the segment goes out again once the network's clock says
+        // the 1 s timeout is over, however long that takes for real
+        network.advance(Duration::from_millis(999));
+        thread::sleep(TICK * 5);
+        assert_eq!(retransmissions(&interface), 0);
+        network.advance(Duration::from_millis(1));
+        let again = peer.receive();
+        assert_eq!(retransmissions(&interface), 1);
+        let (sent, again) = (
+            TcpPacket::new(&sent[34..]).unwrap(),
+            TcpPacket::new(&again[34..]).unwrap(),
+        );
+        assert_eq!(again.get_sequence(), sent.get_sequence());
+        assert_eq!(again.payload(), b"hello");
+    }
+
+    #[test]
+    fn neighbors_expire_on_virtual_time() {
+        let network = Network::new(1);
+        let (interface, mut peer) = sim_link(&network);
+        let listener = interface.bind(8000).unwrap();
+        peer.connect(8000);
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // the address learned from the handshake is forgotten and asked for again
+        network.advance(NEIGHBOR_TTL);
+        stream.write_all(b"hello").unwrap();
+        let request = peer.receive();
+        let request = EthernetPacket::new(&request).unwrap();
+        assert_eq!(request.get_ethertype(), EtherTypes::Arp);
+        let arp = ArpPacket::new(request.payload()).unwrap();
+        assert_eq!(arp.get_operation(), ArpOperations::Request);
+        assert_eq!(arp.get_target_proto_addr(), PEER.ip);
+
+        // once answered, the segment dropped meanwhile is sent again
+        peer.send(&build_arp_packet(
+            STACK.mac,
+            PEER.mac,
+            PEER.ip,
+            STACK.mac,
+            STACK.ip,
+            ArpOperations::Reply,
+        ));
+        for _ in 0..3 {
+            network.advance(Duration::from_secs(1));
+            let frame = peer.receive();
+            let frame = EthernetPacket::new(&frame).unwrap();
+            if frame.get_ethertype() == EtherTypes::Ipv4 {
+                assert_eq!(
+                    TcpPacket::new(&frame.payload()[20..]).unwrap().payload(),
+                    b"hello"
+                );
+                return;
+            }
+        }
+        panic!("the segment wasn't sent again");
+    }
+
+    #[test]
+    fn accepts_once_handshaken() {
+        let (interface, mut peer) = link();
+        let listener = interface.bind(8000).unwrap();
+        let isn = peer.syn(8000);
+        let pending = interface.shared.lock().try_accept(8000);
+        assert_eq!(pending.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        peer.send_segment(8000, 1001, isn.wrapping_add(1), TcpFlags::ACK, &[]);
+        let (_stream, remote) = listener.accept().unwrap();
+        assert_eq!(remote, SocketAddrV4::new(PEER.ip, 40000));
+    }
+
+    #[test]
+    fn reads_end_once_closed() {
+        let (interface, mut peer) = link();
+        let listener = interface.bind(8000).unwrap();
+        let isn = peer.connect(8000);
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let fin = TcpFlags::FIN | TcpFlags::ACK;
+        peer.send_segment(8000, 1001, isn.wrapping_add(1), fin, b"bye");
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        stream.shutdown(Shutdown::Write).unwrap();
+        loop {
+            let frame = peer.receive();
+            if TcpPacket::new(&frame[34..]).unwrap().get_flags() & TcpFlags::FIN != 0 {
+                break;
+            }
+        }
+        peer.send_segment(8000, 1005, isn.wrapping_add(2), TcpFlags::ACK, &[]);
+        wait_until(&interface, |connections| {
+            connections.connections[&quad(8000)].is_done()
+        });
+        // ticks go by, the connection stays over rather than reset
+        thread::sleep(TICK * 5);
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        let written = stream.write(b"more").unwrap_err();
+        assert_eq!(written.kind(), io::ErrorKind::BrokenPipe);
+
+        drop(stream);
+        wait_until(&interface, |connections| connections.connections.is_empty());
+    }
+
+    #[test]
+    fn reads_fail_once_reset() {
+        let (interface, mut peer) = link();
+        let listener = interface.bind(8000).unwrap();
+        let isn = peer.connect(8000);
+        let (mut stream, _) = listener.accept().unwrap();
+
+        peer.send_segment(8000, 1001, isn.wrapping_add(1), TcpFlags::RST, &[]);
+        let mut buf = [0u8; 16];
+        let read = stream.read(&mut buf).unwrap_err();
+        assert_eq!(read.kind(), io::ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn counts_into_metrics() {
+        let (interface, mut peer) = link();
+        let metrics = Metrics::new();
+        let interface = interface.with_metrics(&metrics, "tcp");
+        let listener = interface.bind(8000).unwrap();
+        peer.connect(8000);
+        listener.accept().unwrap();
+
+        peer.send(&syn_frame(PEER, STACK.mac, STACK.ip, 40001, 9000, 1000, 1));
+        let reset = peer.receive();
+        assert_ne!(
+            TcpPacket::new(&reset[34..]).unwrap().get_flags() & TcpFlags::RST,
+            0
+        );
+        let counters: HashMap<_, _> = metrics.counters().into_iter().collect();
+        assert_eq!(counters["tcp.segments"], 3);
+        assert_eq!(counters["tcp.accepted"], 1);
+        assert_eq!(counters["tcp.refused"], 1);
+        assert_eq!(counters["tcp.syns_dropped"], 0);
+        assert_eq!(metrics.gauges(), [("tcp.connections".to_owned(), 1.0)]);
+    }
+
+    #[test]
+    fn marks_with_dscp() {
+        let (interface, mut peer) = link();
+        let socket = interface
+            .bind_udp(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 5353))
+            .unwrap();
+        let from = SocketAddrV4::new(PEER.ip, 4000);
+        let to = SocketAddrV4::new(STACK.ip, 5353);
+        let mut frame = generate::udp_frame(PEER.mac, STACK.mac, from, to, b"ping");
+        dscp::set_frame_dscp(
+            &mut MutableEthernetPacket::new(&mut frame).unwrap(),
+            Dscps::Af41,
+        );
+        peer.send(&frame);
+        let mut buf = [0u8; 4];
+        let (_, _, meta) = socket.recv_with_meta(&mut buf).unwrap();
+        assert_eq!(meta.dscp, Dscps::Af41);
+        assert_eq!(meta.ecn, Ecn::NotEct);
+
+        socket.set_dscp(Dscps::Ef).unwrap();
+        socket.send_to(b"pong", from).unwrap();
+        let reply = peer.receive();
+        let reply = EthernetPacket::new(&reply).unwrap();
+        assert_eq!(dscp::frame_dscp(&reply), Some(Dscps::Ef));
+        let ip = Ipv4Packet::new(reply.payload()).unwrap();
+        assert_eq!(ipv4::packet::header_checksum(&ip), ip.get_checksum());
+
+        let listener = interface.bind(8000).unwrap();
+        let isn = peer.connect(8000);
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_dscp(Dscps::Cs5).unwrap();
+        assert_eq!(stream.dscp().unwrap(), Dscps::Cs5);
+        stream.write_all(b"hello").unwrap();
+        let segment = peer.receive();
+        let segment = EthernetPacket::new(&segment).unwrap();
+        assert_eq!(dscp::frame_dscp(&segment), Some(Dscps::Cs5));
+        let tcp = TcpPacket::new(&segment.payload()[20..]).unwrap();
+        assert_eq!(tcp.get_sequence(), isn.wrapping_add(1));
+    }
+
+    #[test]
+    fn sends_with_ttl() {
+        let (interface, mut peer) = link();
+        let socket = interface
+            .bind_udp(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 5353))
+            .unwrap();
+        let from = SocketAddrV4::new(PEER.ip, 4000);
+        let to = SocketAddrV4::new(STACK.ip, 5353);
+        let mut frame = generate::udp_frame(PEER.mac, STACK.mac, from, to, b"ping");
+        ttl::set_frame_ttl(&mut MutableEthernetPacket::new(&mut frame).unwrap(), 17);
+        peer.send(&frame);
+        let mut buf = [0u8; 4];
+        let (_, _, meta) = socket.recv_with_meta(&mut buf).unwrap();
+        assert_eq!(meta.ttl, 17);
+
+        assert_eq!(socket.ttl().unwrap(), DEFAULT_TTL);
+        let zero = socket.set_ttl(0).unwrap_err();
+        assert_eq!(zero.kind(), io::ErrorKind::InvalidInput);
+        socket.set_ttl(5).unwrap();
+        socket.send_to(b"pong", from).unwrap();
+        let reply = peer.receive();
+        assert_eq!(
+            ttl::frame_ttl(&EthernetPacket::new(&reply).unwrap()),
+            Some(5)
+        );
+
+        let listener = interface.bind(8000).unwrap();
+        peer.connect(8000);
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_ttl(3).unwrap();
+        assert_eq!(stream.ttl().unwrap(), 3);
+        stream.write_all(b"hello").unwrap();
+        let segment = peer.receive();
+        assert_eq!(
+            ttl::frame_ttl(&EthernetPacket::new(&segment).unwrap()),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn exchanges_datagrams() {
+        let (interface, mut peer) = link();
+        let socket = interface
+            .bind_udp(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 5353))
+            .unwrap();
+        let taken = interface.bind_udp(SocketAddrV4::new(STACK.ip, 5353));
+        assert_eq!(taken.unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+        let from = SocketAddrV4::new(PEER.ip, 4000);
+        let to = SocketAddrV4::new(STACK.ip, 5353);
+        peer.send(&generate::udp_frame(PEER.mac, STACK.mac, from, to, b"ping"));
+        let mut buf = [0u8; 2];
+        assert_eq!(socket.recv_from(&mut buf).unwrap(), (2, from));
+        assert_eq!(&buf, b"pi");
+
+        assert_eq!(socket.send_to(b"pong", from).unwrap(), 4);
+        let reply = peer.receive();
+        assert_eq!(
+            EthernetPacket::new(&reply).unwrap().get_destination(),
+            PEER.mac
+        );
+        let parsed = FlowPacket::parse(&EthernetPacket::new(&reply).unwrap()).unwrap();
+        assert_eq!((parsed.src, parsed.dst), (to.into(), from.into()));
+        assert_eq!(&reply[parsed.payload_offset..], b"pong");
+        let udp = UdpPacket::new(&reply[34..]).unwrap();
+        assert_eq!(
+            udp::ipv4_checksum(&udp, STACK.ip, PEER.ip),
+            udp.get_checksum()
+        );
+    }
+}
@@ -0,0 +1,263 @@
+//! Sockets of the stack for tokio.
+//!
+//! The thread serving the link is the reactor: tasks waiting on a socket are woken on its
+//! next tick, and try again.
+
+use super::{Connections, Interface, Quad, Shared, TcpListener, TcpStream, UdpSocket};
+use std::{
+    future::poll_fn,
+    io,
+    net::SocketAddrV4,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// `attempt` once, waking the task on the next tick if it has to wait
+fn poll<T, F>(shared: &Shared, cx: &mut Context, attempt: F) -> Poll<io::Result<T>>
+where
+    F: FnOnce(&mut Connections) -> io::Result<T>,
+{
+    let mut connections = shared.lock();
+    match attempt(&mut connections) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            if !connections.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                connections.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+        result => Poll::Ready(result),
+    }
+}
+
+/// A [`TcpListener`] accepting [`AsyncTcpStream`]s.
+#[derive(Debug)]
+pub struct AsyncTcpListener {
+    inner: TcpListener,
+}
+
+impl AsyncTcpListener {
+    /// Listen on `port` of `interface`, like [`TcpListener::bind`].
+    pub fn bind(interface: &Interface, port: u16) -> io::Result<AsyncTcpListener> {
+        TcpListener::bind(interface, port).map(AsyncTcpListener::from)
+    }
+
+    /// The port listened on.
+    pub fn port(&self) -> u16 {
+        self.inner.port()
+    }
+
+    /// Wait for a connection.
+    pub async fn accept(&self) -> io::Result<(AsyncTcpStream, SocketAddrV4)> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    /// Accept a connection if one is pending, or wake the task when one may be.
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context,
+    ) -> Poll<io::Result<(AsyncTcpStream, SocketAddrV4)>> {
+        let port = self.inner.port;
+        poll(&self.inner.shared, cx, |connections| {
+            connections.try_accept(port)
+        })
+        .map_ok(|quad: Quad| {
+            let stream = AsyncTcpStream::from(self.inner.stream(quad));
+            (stream, quad.remote)
+        })
+    }
+}
+
+impl From<TcpListener> for AsyncTcpListener {
+    fn from(inner: TcpListener) -> AsyncTcpListener {
+        AsyncTcpListener { inner }
+    }
+}
+
+/// A [`TcpStream`] that is read and written without blocking the thread.
+#[derive(Debug)]
+pub struct AsyncTcpStream {
+    inner: TcpStream,
+}
+
+impl AsyncTcpStream {
+    /// The address of the peer.
+    pub fn peer_addr(&self) -> SocketAddrV4 {
+        self.inner.peer_addr()
+    }
+
+    /// The address the peer connected to.
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.inner.local_addr()
+    }
+
+    fn shared(&self) -> &Arc<Shared> {
+        &self.inner.shared
+    }
+}
+
+impl From<TcpStream> for AsyncTcpStream {
+    fn from(inner: TcpStream) -> AsyncTcpStream {
+        AsyncTcpStream { inner }
+    }
+}
+
+impl AsyncRead for AsyncTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let quad = self.inner.quad;
+        poll(self.shared(), cx, |connections| {
+            let read = connections.try_read(&quad, buf.initialize_unfilled())?;
+            buf.advance(read);
+            Ok(())
+        })
+    }
+}
+
+impl AsyncWrite for AsyncTcpStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let quad = self.inner.quad;
+        poll(self.shared(), cx, |connections| {
+            connections.try_write(&quad, buf)
+        })
+    }
+
+    /// Wait for the peer to acknowledge everything written.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let quad = self.inner.quad;
+        poll(self.shared(), cx, |connections| {
+            connections.try_flush(&quad)
+        })
+    }
+
+    /// Send a FIN after the data written so far.
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(self.inner.shutdown(std::net::Shutdown::Write))
+    }
+}
+
+/// A [`UdpSocket`] that receives without blocking the thread.
+#[derive(Debug)]
+pub struct AsyncUdpSocket {
+    inner: UdpSocket,
+}
+
+impl AsyncUdpSocket {
+    /// Bind to `local` on `interface`, like [`UdpSocket::bind`].
+    pub fn bind(interface: &Interface, local: SocketAddrV4) -> io::Result<AsyncUdpSocket> {
+        UdpSocket::bind(interface, local).map(AsyncUdpSocket::from)
+    }
+
+    /// The address bound to.
+    pub fn local_addr(&self) -> SocketAddrV4 {
+        self.inner.local_addr()
+    }
+
+    /// Send `buf` to `target`, like [`UdpSocket::send_to`]. Datagrams are queued for the
+    /// link right away.
+    pub async fn send_to(&self, buf: &[u8], target: SocketAddrV4) -> io::Result<usize> {
+        self.inner.send_to(buf, target)
+    }
+
+    /// Wait for a datagram, like [`UdpSocket::recv_from`].
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddrV4)> {
+        poll_fn(|cx| self.poll_recv_from(cx, buf)).await
+    }
+
+    /// Receive a datagram if one is queued, or wake the task when one may be.
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddrV4)>> {
+        let port = self.inner.local.port();
+        poll(&self.inner.shared, cx, |connections| {
+            let (len, source, _) = connections.try_recv_from(port, buf)?;
+            Ok((len, source))
+        })
+    }
+}
+
+impl From<UdpSocket> for AsyncUdpSocket {
+    fn from(inner: UdpSocket) -> AsyncUdpSocket {
+        AsyncUdpSocket { inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::tests::{link, PEER, STACK},
+        *,
+    };
+    use crate::generate;
+    use std::{
+        future::Future,
+        task::{Wake, Waker},
+        thread,
+        time::Duration,
+    };
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // run `future` on this thread, parked while it waits
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn tasks_are_woken() {
+        let (interface, mut peer) = link();
+        let listener = AsyncTcpListener::bind(&interface, 8000).unwrap();
+        let socket = AsyncUdpSocket::bind(&interface, SocketAddrV4::new(STACK.ip, 5353)).unwrap();
+        let from = SocketAddrV4::new(PEER.ip, 4000);
+
+        // the peer acts once the tasks wait
+        let peer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            let isn = peer.connect(8000);
+            thread::sleep(Duration::from_millis(50));
+            peer.send_data(8000, 1001, isn.wrapping_add(1), b"hello");
+            thread::sleep(Duration::from_millis(50));
+            let to = SocketAddrV4::new(STACK.ip, 5353);
+            peer.send(&generate::udp_frame(PEER.mac, STACK.mac, from, to, b"ping"));
+        });
+
+        let (mut stream, remote) = block_on(listener.accept()).unwrap();
+        assert_eq!(remote, SocketAddrV4::new(PEER.ip, 40000));
+        let mut buf = [0u8; 16];
+        let read = block_on(poll_fn(|cx| {
+            let mut read_buf = ReadBuf::new(&mut buf);
+            Pin::new(&mut stream)
+                .poll_read(cx, &mut read_buf)
+                .map_ok(|()| read_buf.filled().len())
+        }));
+        assert_eq!(&buf[..read.unwrap()], b"hello");
+        let received = block_on(socket.recv_from(&mut buf)).unwrap();
+        assert_eq!(received, (4, from));
+        peer.join().unwrap();
+
+        // nothing to wait for when the stack is gone
+        drop(interface);
+        let stopped = block_on(socket.recv_from(&mut buf));
+        assert_ne!(stopped.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+    }
+}
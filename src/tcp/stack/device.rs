@@ -0,0 +1,148 @@
+//! Where the stack's IPv4 packets come from and go to.
+
+use crate::{
+    arp::{
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EtherTypes, EthernetPacket, MutableEthernetPacket, Packet},
+        responder,
+    },
+    ipv4::Ipv4Packet,
+    neighbor::{Arp, NeighborCache, Protocol},
+};
+use std::{collections::HashMap, io, net::IpAddr};
+
+/// A link carrying IPv4 packets.
+pub(super) trait Device: Send {
+    /// The next packet for the stack, or None if none came within a tick.
+    fn recv(&mut self) -> io::Result<Option<&[u8]>>;
+
+    /// Send the IPv4 packet `packet`.
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+}
+
+/// A tun device, handing over IPv4 packets as they are.
+#[cfg(target_os = "linux")]
+pub(super) struct Tun {
+    pub(super) device: tun_tap::Iface,
+    pub(super) buffer: [u8; 1504],
+}
+
+#[cfg(target_os = "linux")]
+impl Device for Tun {
+    fn recv(&mut self) -> io::Result<Option<&[u8]>> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut poll = libc::pollfd {
+            fd: self.device.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, super::TICK.as_millis() as libc::c_int) };
+        if ready == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+        let len = self.device.recv(&mut self.buffer)?;
+        Ok(Some(&self.buffer[..len]))
+    }
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.device.send(packet).map(|_| ())
+    }
+}
+
+/// An Ethernet channel, on which the stack owns one address.
+///
+/// ARP requests for the address are answered, and the MAC addresses of peers are learned
+/// from the frames they send and from the replies to the stack's ARP requests. Packets to
+/// peers not known yet are dropped after asking for them, for TCP to send them again.
+pub(super) struct Ethernet {
+    pub(super) tx: Box<dyn EthernetDataLinkSender>,
+    pub(super) rx: Box<dyn EthernetDataLinkReceiver>,
+    pub(super) arp: Arp,
+    pub(super) neighbors: NeighborCache,
+    pub(super) packet: Vec<u8>,
+}
+
+impl Ethernet {
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.tx
+            .send_to(&EthernetPacket::new(frame).unwrap(), None)
+            .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+    }
+}
+
+impl Device for Ethernet {
+    fn recv(&mut self) -> io::Result<Option<&[u8]>> {
+        let mut reply = None;
+        {
+            let mut iter = self.rx.iter();
+            let frame = match iter.next() {
+                Ok(frame) => frame,
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if frame.get_source() == self.arp.mac {
+                return Ok(None);
+            }
+            match frame.get_ethertype() {
+                EtherTypes::Arp => {
+                    if let Some((ip, mac)) = self.arp.binding(&frame) {
+                        self.neighbors.learn(ip, mac);
+                    }
+                    let mut bindings = HashMap::new();
+                    bindings.insert(self.arp.ip, self.arp.mac);
+                    reply = responder::reply_to(&bindings, &frame);
+                }
+                EtherTypes::Ipv4 => {
+                    if frame.get_destination() != self.arp.mac {
+                        return Ok(None);
+                    }
+                    let ip = match Ipv4Packet::new(frame.payload()) {
+                        Some(ip) if ip.get_destination() == self.arp.ip => ip,
+                        _ => return Ok(None),
+                    };
+                    self.neighbors
+                        .insert(IpAddr::V4(ip.get_source()), frame.get_source());
+                    self.packet.clear();
+                    self.packet.extend_from_slice(frame.payload());
+                }
+                _ => return Ok(None),
+            }
+        }
+        if let Some(reply) = reply {
+            self.send_frame(&reply)?;
+            return Ok(None);
+        }
+        Ok(Some(&self.packet[..]))
+    }
+
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        let destination = match Ipv4Packet::new(packet) {
+            Some(ip) => IpAddr::V4(ip.get_destination()),
+            None => return Ok(()),
+        };
+        let mac = match self.neighbors.get(destination) {
+            Some(mac) => mac,
+            None => {
+                self.neighbors.requested(destination);
+                trace_event!(debug, ip = %destination, "resolving next hop, packet dropped");
+                let request = self.arp.request(destination).unwrap();
+                return self.send_frame(&request);
+            }
+        };
+        let mut frame = vec![0u8; 14 + packet.len()];
+        let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+        ethernet.set_destination(mac);
+        ethernet.set_source(self.arp.mac);
+        ethernet.set_ethertype(EtherTypes::Ipv4);
+        ethernet.set_payload(packet);
+        self.send_frame(&frame)
+    }
+}
@@ -0,0 +1,321 @@
+//! Readiness of the sockets of an interface, for event loops serving many sockets on one
+//! thread.
+
+use super::{handshaken, Connections, Interface, Quad, Shared, TcpListener, TcpStream, UdpSocket};
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The operations a [`Poller`] watches a socket for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Interest {
+    /// Reads, or accepts for a listener
+    pub readable: bool,
+    /// Writes
+    pub writable: bool,
+}
+
+impl Interest {
+    /// Reads only.
+    pub const READABLE: Interest = Interest {
+        readable: true,
+        writable: false,
+    };
+
+    /// Writes only.
+    pub const WRITABLE: Interest = Interest {
+        readable: false,
+        writable: true,
+    };
+
+    /// Reads and writes.
+    pub const BOTH: Interest = Interest {
+        readable: true,
+        writable: true,
+    };
+}
+
+/// A socket ready for some of the operations it is watched for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Event {
+    /// The token the socket was registered with
+    pub token: usize,
+    /// A read or accept won't block
+    pub readable: bool,
+    /// A write won't block
+    pub writable: bool,
+}
+
+/// Sockets a [`Poller`] can watch.
+pub trait Source: sealed::Source {}
+
+impl Source for TcpListener {}
+impl Source for TcpStream {}
+impl Source for UdpSocket {}
+
+mod sealed {
+    use super::Key;
+
+    pub trait Source {
+        // the interface, by the address of its state, and the socket on it
+        fn key(&self) -> (usize, Key);
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Key {
+    Listener(u16),
+    Stream(Quad),
+    Udp(u16),
+}
+
+impl sealed::Source for TcpListener {
+    fn key(&self) -> (usize, Key) {
+        (interface_id(&self.shared), Key::Listener(self.port))
+    }
+}
+
+impl sealed::Source for TcpStream {
+    fn key(&self) -> (usize, Key) {
+        (interface_id(&self.shared), Key::Stream(self.quad))
+    }
+}
+
+impl sealed::Source for UdpSocket {
+    fn key(&self) -> (usize, Key) {
+        (interface_id(&self.shared), Key::Udp(self.local.port()))
+    }
+}
+
+fn interface_id(shared: &Arc<Shared>) -> usize {
+    Arc::as_ptr(shared) as usize
+}
+
+impl Connections {
+    // an accept, read or receive wouldn't block
+    fn readable(&self, key: Key) -> bool {
+        self.failed
+            || match key {
+                Key::Listener(port) => self.listeners.get(&port).map_or(true, |listener| {
+                    listener
+                        .pending
+                        .iter()
+                        .any(|quad| handshaken(&self.connections, quad))
+                }),
+                Key::Stream(quad) => self.connections.get(&quad).map_or(true, |connection| {
+                    connection.available() > 0 || connection.is_recv_closed()
+                }),
+                Key::Udp(port) => self
+                    .udp
+                    .get(&port)
+                    .map_or(true, |binding| !binding.datagrams.is_empty()),
+            }
+    }
+
+    // a write or send wouldn't block
+    fn writable(&self, key: Key) -> bool {
+        self.failed
+            || match key {
+                Key::Listener(_) => false,
+                Key::Stream(quad) => self.connections.get(&quad).map_or(true, |connection| {
+                    connection.is_done()
+                        || connection.is_send_closed()
+                        || connection.send_space() > 0
+                }),
+                Key::Udp(_) => true,
+            }
+    }
+}
+
+/// Watches sockets of one [`Interface`] for readiness.
+///
+/// Readiness is level-triggered, like the default of epoll: a socket is reported for as
+/// long as the operation it is ready for wouldn't block. A reset connection or a stopped
+/// stack count as ready, for the operation to report the error, and so does the end of the
+/// peer's data.
+#[derive(Debug)]
+pub struct Poller {
+    shared: Arc<Shared>,
+    /// By token
+    sources: BTreeMap<usize, (Key, Interest)>,
+}
+
+impl Poller {
+    /// Watch sockets of `interface`.
+    pub fn new(interface: &Interface) -> Poller {
+        Poller {
+            shared: Arc::clone(&interface.shared),
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Watch `socket` for `interest`, reporting it with `token`. Fails with
+    /// `AlreadyExists` if the token is taken, and with `InvalidInput` if the socket belongs
+    /// to another interface.
+    pub fn register<S: Source>(
+        &mut self,
+        socket: &S,
+        token: usize,
+        interest: Interest,
+    ) -> io::Result<()> {
+        if self.sources.contains_key(&token) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "Token already registered",
+            ));
+        }
+        let key = self.key(socket)?;
+        self.sources.insert(token, (key, interest));
+        Ok(())
+    }
+
+    /// Watch the socket registered with `token` as `socket` for `interest` instead.
+    pub fn reregister<S: Source>(
+        &mut self,
+        socket: &S,
+        token: usize,
+        interest: Interest,
+    ) -> io::Result<()> {
+        let key = self.key(socket)?;
+        match self.sources.get_mut(&token) {
+            Some(source) => {
+                *source = (key, interest);
+                Ok(())
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "Token not registered",
+            )),
+        }
+    }
+
+    /// Stop watching the socket registered with `token`.
+    pub fn deregister(&mut self, token: usize) {
+        self.sources.remove(&token);
+    }
+
+    /// Wait until a socket is ready or `timeout` passes, and replace the contents of
+    /// `events` with the sockets ready, in token order. `events` is left empty on a
+    /// timeout.
+    pub fn poll(&self, events: &mut Vec<Event>, timeout: Option<Duration>) -> io::Result<()> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut connections = self.shared.lock();
+        loop {
+            events.clear();
+            events.extend(
+                self.sources
+                    .iter()
+                    .filter_map(|(&token, &(key, interest))| {
+                        let event = Event {
+                            token,
+                            readable: interest.readable && connections.readable(key),
+                            writable: interest.writable && connections.writable(key),
+                        };
+                        if event.readable || event.writable {
+                            Some(event)
+                        } else {
+                            None
+                        }
+                    }),
+            );
+            if !events.is_empty() {
+                return Ok(());
+            }
+            connections = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(());
+                    }
+                    self.shared
+                        .changed
+                        .wait_timeout(connections, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.shared.changed.wait(connections).unwrap(),
+            };
+        }
+    }
+
+    fn key<S: Source>(&self, socket: &S) -> io::Result<Key> {
+        let (interface, key) = socket.key();
+        if interface != interface_id(&self.shared) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Socket of another interface",
+            ));
+        }
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::tests::{link, PEER, STACK},
+        *,
+    };
+    use crate::generate;
+    use std::net::SocketAddrV4;
+
+    fn event(token: usize, readable: bool, writable: bool) -> Event {
+        Event {
+            token,
+            readable,
+            writable,
+        }
+    }
+
+    #[test]
+    fn reports_ready_sockets() {
+        let (interface, mut peer) = link();
+        let listener = interface.bind(8000).unwrap();
+        let socket = interface
+            .bind_udp(SocketAddrV4::new(STACK.ip, 5353))
+            .unwrap();
+        let mut poller = interface.poller();
+        poller.register(&listener, 0, Interest::READABLE).unwrap();
+        poller.register(&socket, 1, Interest::READABLE).unwrap();
+        let taken = poller.register(&socket, 0, Interest::WRITABLE);
+        assert_eq!(taken.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        let (other, _) = link();
+        let elsewhere = other.bind(8000).unwrap();
+        let foreign = poller.register(&elsewhere, 2, Interest::READABLE);
+        assert_eq!(foreign.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let mut events = Vec::new();
+        let timeout = Some(Duration::from_secs(2));
+        poller
+            .poll(&mut events, Some(Duration::from_millis(20)))
+            .unwrap();
+        assert!(events.is_empty());
+
+        let isn = peer.connect(8000);
+        poller.poll(&mut events, timeout).unwrap();
+        assert_eq!(events, [event(0, true, false)]);
+        let (stream, _) = listener.accept().unwrap();
+        poller.register(&stream, 2, Interest::BOTH).unwrap();
+        poller.poll(&mut events, timeout).unwrap();
+        assert_eq!(events, [event(2, false, true)]);
+
+        poller.reregister(&stream, 2, Interest::READABLE).unwrap();
+        peer.send_data(8000, 1001, isn.wrapping_add(1), b"hello");
+        let from = SocketAddrV4::new(PEER.ip, 4000);
+        let to = SocketAddrV4::new(STACK.ip, 5353);
+        peer.send(&generate::udp_frame(PEER.mac, STACK.mac, from, to, b"ping"));
+        // the two may become ready on different ticks
+        while events.len() < 2 {
+            poller.poll(&mut events, timeout).unwrap();
+        }
+        assert_eq!(events, [event(1, true, false), event(2, true, false)]);
+
+        poller.deregister(1);
+        drop(interface);
+        poller.poll(&mut events, timeout).unwrap();
+        assert_eq!(events, [event(0, true, false), event(2, true, false)]);
+    }
+}
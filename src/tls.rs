@@ -0,0 +1,340 @@
+//! TLS handshake inspection.
+//!
+//! Parses the ClientHello and ServerHello messages at the start of a TLS connection and
+//! computes their JA3 and JA3S fingerprints, which identify client and server TLS stacks
+//! without decrypting anything.
+
+use std::fmt::Write;
+
+const CONTENT_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const HANDSHAKE_SERVER_HELLO: u8 = 2;
+const EXTENSION_SUPPORTED_GROUPS: u16 = 10;
+const EXTENSION_EC_POINT_FORMATS: u16 = 11;
+
+/// The fields of a ClientHello that make up its JA3 fingerprint.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientHello {
+    /// Protocol version offered in the hello
+    pub version: u16,
+    /// Offered cipher suites
+    pub cipher_suites: Vec<u16>,
+    /// Extension types, in order
+    pub extensions: Vec<u16>,
+    /// Supported groups (elliptic curves)
+    pub groups: Vec<u16>,
+    /// EC point formats
+    pub point_formats: Vec<u8>,
+}
+
+impl ClientHello {
+    /// Parse the ClientHello carried by the TLS record at the start of `data`.
+    pub fn parse(data: &[u8]) -> Option<ClientHello> {
+        let mut body = Reader(handshake(data, HANDSHAKE_CLIENT_HELLO)?);
+        let mut hello = ClientHello {
+            version: body.u16()?,
+            ..Default::default()
+        };
+        body.skip(32)?;
+        let session_id = body.u8()? as usize;
+        body.skip(session_id)?;
+        let mut ciphers = Reader(body.bytes_u16()?);
+        while let Some(cipher) = ciphers.u16() {
+            hello.cipher_suites.push(cipher);
+        }
+        let compression = body.u8()? as usize;
+        body.skip(compression)?;
+
+        // hellos without extensions are valid
+        let mut extensions = Reader(body.bytes_u16().unwrap_or(&[]));
+        while let Some(extension) = extensions.u16() {
+            let mut value = Reader(extensions.bytes_u16()?);
+            hello.extensions.push(extension);
+            match extension {
+                EXTENSION_SUPPORTED_GROUPS => {
+                    let mut groups = Reader(value.bytes_u16()?);
+                    while let Some(group) = groups.u16() {
+                        hello.groups.push(group);
+                    }
+                }
+                EXTENSION_EC_POINT_FORMATS => {
+                    let len = value.u8()? as usize;
+                    hello.point_formats.extend_from_slice(value.take(len)?);
+                }
+                _ => {}
+            }
+        }
+        Some(hello)
+    }
+
+    /// The JA3 string: version, ciphers, extensions, groups and point formats, with GREASE
+    /// values left out.
+    pub fn ja3_string(&self) -> String {
+        let point_formats: Vec<u16> = self.point_formats.iter().map(|&f| f.into()).collect();
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            join(&self.cipher_suites),
+            join(&self.extensions),
+            join(&self.groups),
+            join(&point_formats)
+        )
+    }
+
+    /// The JA3 fingerprint, the MD5 digest of the JA3 string.
+    pub fn ja3(&self) -> [u8; 16] {
+        md5(self.ja3_string().as_bytes())
+    }
+}
+
+/// The fields of a ServerHello that make up its JA3S fingerprint.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ServerHello {
+    /// Protocol version selected in the hello
+    pub version: u16,
+    /// Selected cipher suite
+    pub cipher_suite: u16,
+    /// Extension types, in order
+    pub extensions: Vec<u16>,
+}
+
+impl ServerHello {
+    /// Parse the ServerHello carried by the TLS record at the start of `data`.
+    pub fn parse(data: &[u8]) -> Option<ServerHello> {
+        let mut body = Reader(handshake(data, HANDSHAKE_SERVER_HELLO)?);
+        let version = body.u16()?;
+        body.skip(32)?;
+        let session_id = body.u8()? as usize;
+        body.skip(session_id)?;
+        let mut hello = ServerHello {
+            version,
+            cipher_suite: body.u16()?,
+            extensions: Vec::new(),
+        };
+        body.skip(1)?;
+
+        let mut extensions = Reader(body.bytes_u16().unwrap_or(&[]));
+        while let Some(extension) = extensions.u16() {
+            extensions.bytes_u16()?;
+            hello.extensions.push(extension);
+        }
+        Some(hello)
+    }
+
+    /// The JA3S string: version, cipher and extensions.
+    pub fn ja3s_string(&self) -> String {
+        format!(
+            "{},{},{}",
+            self.version,
+            self.cipher_suite,
+            join(&self.extensions)
+        )
+    }
+
+    /// The JA3S fingerprint, the MD5 digest of the JA3S string.
+    pub fn ja3s(&self) -> [u8; 16] {
+        md5(self.ja3s_string().as_bytes())
+    }
+}
+
+/// Format a fingerprint as lowercase hex, the way JA3 hashes are usually shown.
+pub fn to_hex(digest: &[u8; 16]) -> String {
+    let mut out = String::with_capacity(32);
+    for byte in digest {
+        let _ = write!(out, "{:02x}", byte);
+    }
+    out
+}
+
+/// Length of the TLS record at the start of `data`, header included, or None until the
+/// header is complete.
+pub fn record_len(data: &[u8]) -> Option<usize> {
+    let len = data.get(3..5)?;
+    Some(5 + u16::from_be_bytes([len[0], len[1]]) as usize)
+}
+
+/// Whether `value` is one of the reserved GREASE values (RFC 8701) clients sprinkle into
+/// their hellos, which JA3 ignores.
+pub fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
+fn join(values: &[u16]) -> String {
+    let values: Vec<String> = values
+        .iter()
+        .filter(|&&value| !is_grease(value))
+        .map(u16::to_string)
+        .collect();
+    values.join("-")
+}
+
+/// Body of the handshake message of type `kind` at the start of the TLS record `data`.
+fn handshake(data: &[u8], kind: u8) -> Option<&[u8]> {
+    let mut record = Reader(data);
+    if record.u8()? != CONTENT_HANDSHAKE {
+        return None;
+    }
+    // record version, checked loosely as it differs from the hello version
+    if record.u8()? != 3 {
+        return None;
+    }
+    record.skip(1)?;
+    let mut fragment = Reader(record.bytes_u16()?);
+    if fragment.u8()? != kind {
+        return None;
+    }
+    let len = fragment.take(3)?;
+    let len = (len[0] as usize) << 16 | (len[1] as usize) << 8 | len[2] as usize;
+    fragment.take(len)
+}
+
+/// Cursor over big endian wire data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.take(len).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn bytes_u16(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()? as usize;
+        self.take(len)
+    }
+}
+
+/// MD5 digest of `data` (RFC 1321), only used for fingerprints.
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    let constants: Vec<u32> = (0..64)
+        .map(|i| ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32)
+        .collect();
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+    for block in message.chunks(64) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i / 16 * 4 + i % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0u8; 16];
+    for (bytes, word) in digest.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // a ClientHello record with GREASE values among the ciphers, extensions and groups
+    pub(crate) fn client_hello() -> Vec<u8> {
+        let extensions: &[(u16, &[u8])] = &[
+            (0x2a2a, &[]),
+            (0, &[0, 3, b'a', b'b', b'c']),
+            (
+                EXTENSION_SUPPORTED_GROUPS,
+                &[0, 6, 0x1a, 0x1a, 0, 29, 0, 23],
+            ),
+            (EXTENSION_EC_POINT_FORMATS, &[2, 0, 1]),
+            (13, &[0, 2, 4, 3]),
+        ];
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[0; 32]);
+        // no session id, four ciphers and null compression
+        body.push(0);
+        body.extend_from_slice(&[0, 8, 0x0a, 0x0a, 0x13, 0x01, 0xc0, 0x2b, 0x00, 0x2f]);
+        body.extend_from_slice(&[1, 0]);
+        let mut encoded = Vec::new();
+        for (kind, value) in extensions {
+            encoded.extend_from_slice(&kind.to_be_bytes());
+            encoded.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            encoded.extend_from_slice(value);
+        }
+        body.extend_from_slice(&(encoded.len() as u16).to_be_bytes());
+        body.extend(encoded);
+
+        let mut record = vec![CONTENT_HANDSHAKE, 3, 1];
+        record.extend_from_slice(&(body.len() as u16 + 4).to_be_bytes());
+        record.extend_from_slice(&[HANDSHAKE_CLIENT_HELLO, 0]);
+        record.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        record.extend(body);
+        record
+    }
+
+    #[test]
+    fn md5_matches_rfc_1321() {
+        let digest = |data: &str| to_hex(&md5(data.as_bytes()));
+        assert_eq!(digest(""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(digest("abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digest("message digest"), "f96b697d7cb7938d525a2f31aaf161d0");
+        assert_eq!(
+            digest("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789"),
+            "d174ab98d277d9f5a5611c2c9f419d9f"
+        );
+        assert_eq!(
+            digest(&"1234567890".repeat(8)),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn fingerprints_client_hello() {
+        let record = client_hello();
+        assert_eq!(record_len(&record), Some(record.len()));
+        assert_eq!(record_len(&record[..4]), None);
+
+        let hello = ClientHello::parse(&record).unwrap();
+        assert_eq!(hello.ja3_string(), "771,4865-49195-47,0-10-11-13,29-23,0-1");
+        assert_eq!(to_hex(&hello.ja3()), "bb557700b9c47e6759c8b8394da35248");
+        assert_eq!(ClientHello::parse(&record[..record.len() - 1]), None);
+        assert_eq!(ServerHello::parse(&record), None);
+    }
+}
@@ -0,0 +1,31 @@
+//! Optional instrumentation with `tracing`.
+//!
+//! With the `tracing` feature enabled the crate emits spans and events for channel setup,
+//! sending, receiving, ARP resolution and TCP state changes, using the module path as
+//! target. Without it the macros below expand to nothing, so instrumented code costs nothing.
+
+/// Emit a `tracing` event, e.g. `trace_event!(debug, len, "sent frame")`.
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::$level!($($arg)+);
+        }
+    };
+}
+
+/// Enter a `tracing` span until the returned guard is dropped, e.g.
+/// `let _span = trace_span!(DEBUG, "channel", interface = %name);`.
+macro_rules! trace_span {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::span!(tracing::Level::$level, $($arg)+).entered();
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::NoSpan;
+        span
+    }};
+}
+
+/// Stands in for an entered span when tracing is disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
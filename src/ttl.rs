@@ -0,0 +1,353 @@
+//! IPv4 TTL / IPv6 hop limit handling.
+//!
+//! Besides reading and writing the field on captured or outgoing frames this module
+//! implements the forwarding-path rule of RFC 1812 / RFC 4443: a router decrements the
+//! TTL and answers packets whose TTL runs out with an ICMP Time Exceeded message, which
+//! is what makes traceroute work.
+
+use crate::{
+    arp::{
+        channel::EthernetDataLinkSender,
+        ether::{
+            EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket, MutablePacket, Packet,
+        },
+        network_interface::{MacAddr, NetworkInterface},
+    },
+    checksum,
+    ipproto::{IPPROTO_ICMP, IPPROTO_ICMPV6},
+};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Default TTL used for locally originated packets.
+pub const DEFAULT_TTL: u8 = 64;
+
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+
+/// Minimum IPv6 MTU; ICMPv6 errors must not exceed it.
+const IPV6_MIN_MTU: usize = 1280;
+
+/// Return the TTL / hop limit of the IPv4 or IPv6 packet carried by `frame`.
+pub fn frame_ttl(frame: &EthernetPacket) -> Option<u8> {
+    let payload = frame.payload();
+    match frame.get_ethertype() {
+        EtherTypes::Ipv4 if payload.len() >= 20 => Some(payload[8]),
+        EtherTypes::Ipv6 if payload.len() >= 40 => Some(payload[7]),
+        _ => None,
+    }
+}
+
+/// Rewrite the TTL / hop limit of the IP packet carried by `frame`, fixing up the IPv4
+/// header checksum.
+///
+/// Returns false if the frame doesn't carry an IP packet.
+pub fn set_frame_ttl(frame: &mut MutableEthernetPacket, ttl: u8) -> bool {
+    let ethertype = frame.get_ethertype();
+    let payload = frame.payload_mut();
+    match ethertype {
+        EtherTypes::Ipv4 if payload.len() >= 20 => {
+            payload[8] = ttl;
+            update_ipv4_checksum(payload);
+            true
+        }
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            payload[7] = ttl;
+            true
+        }
+        _ => false,
+    }
+}
+
+// recompute the header checksum of the IPv4 `packet`
+pub(crate) fn update_ipv4_checksum(packet: &mut [u8]) {
+    let ihl = ((packet[0] & 0x0f) as usize * 4).max(20).min(packet.len());
+    packet[10] = 0;
+    packet[11] = 0;
+    let sum = checksum::checksum(&packet[..ihl]);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+}
+
+/// Outcome of forwarding a frame by one hop.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Hop {
+    /// The TTL was decremented in place and the frame may be forwarded.
+    Forward,
+    /// The TTL expired. The frame must be dropped and the contained Time Exceeded message
+    /// (a complete Ethernet frame) sent back to the originator.
+    Expired(Vec<u8>),
+    /// The TTL expired but no ICMP error may be generated for this packet
+    /// (e.g. it is itself an ICMP error or was sent to a multicast address).
+    Dropped,
+    /// The frame doesn't carry an IP packet.
+    NotIp,
+}
+
+/// Addresses a router uses as the source of its ICMP errors.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RouterAddr {
+    /// MAC address of the interface the frame arrived on
+    pub mac: MacAddr,
+    /// IPv4 address of that interface
+    pub ipv4: Option<Ipv4Addr>,
+    /// IPv6 address of that interface
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+/// Decrement the TTL / hop limit of `frame` on the forwarding path.
+pub fn forward(frame: &mut MutableEthernetPacket, router: &RouterAddr) -> Hop {
+    let ethertype = frame.get_ethertype();
+    let source_mac = frame.get_source();
+    let payload = frame.payload_mut();
+    match ethertype {
+        EtherTypes::Ipv4 if payload.len() >= 20 => {
+            if payload[8] > 1 {
+                payload[8] -= 1;
+                update_ipv4_checksum(payload);
+                return Hop::Forward;
+            }
+            match router.ipv4 {
+                Some(ip) if may_answer_ipv4(payload) => {
+                    Hop::Expired(ipv4_time_exceeded(payload, source_mac, router.mac, ip))
+                }
+                _ => Hop::Dropped,
+            }
+        }
+        EtherTypes::Ipv6 if payload.len() >= 40 => {
+            if payload[7] > 1 {
+                payload[7] -= 1;
+                return Hop::Forward;
+            }
+            match router.ipv6 {
+                Some(ip) if may_answer_ipv6(payload) => {
+                    Hop::Expired(ipv6_time_exceeded(payload, source_mac, router.mac, ip))
+                }
+                _ => Hop::Dropped,
+            }
+        }
+        _ => Hop::NotIp,
+    }
+}
+
+fn may_answer_ipv4(packet: &[u8]) -> bool {
+    let ihl = (packet[0] & 0x0f) as usize * 4;
+    let fragment_offset = u16::from_be_bytes([packet[6], packet[7]]) & 0x1fff;
+    let dst = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
+    let is_icmp_error = packet[9] == IPPROTO_ICMP
+        && packet.len() > ihl
+        && !matches!(packet[ihl], 0 | 8 | 13 | 14 | 15 | 16 | 17 | 18);
+    fragment_offset == 0 && !dst.is_multicast() && !dst.is_broadcast() && !is_icmp_error
+}
+
+fn may_answer_ipv6(packet: &[u8]) -> bool {
+    let is_icmp_error = packet[6] == IPPROTO_ICMPV6 && packet.len() > 40 && packet[40] < 128;
+    packet[24] != 0xff && !is_icmp_error
+}
+
+fn ipv4_time_exceeded(
+    packet: &[u8],
+    dst_mac: MacAddr,
+    src_mac: MacAddr,
+    src_ip: Ipv4Addr,
+) -> Vec<u8> {
+    let ihl = ((packet[0] & 0x0f) as usize * 4).max(20);
+    let quoted = &packet[..(ihl + 8).min(packet.len())];
+    let total_len = 20 + 8 + quoted.len();
+
+    let mut frame = vec![0u8; 14 + total_len];
+    write_ethernet_header(&mut frame, dst_mac, src_mac, EtherTypes::Ipv4);
+
+    let ip = &mut frame[14..];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    ip[8] = DEFAULT_TTL;
+    ip[9] = IPPROTO_ICMP;
+    ip[12..16].copy_from_slice(&src_ip.octets());
+    ip[16..20].copy_from_slice(&packet[12..16]);
+    update_ipv4_checksum(ip);
+
+    let icmp = &mut ip[20..];
+    icmp[0] = ICMP_TIME_EXCEEDED;
+    icmp[8..].copy_from_slice(quoted);
+    let sum = checksum::checksum(icmp);
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    frame
+}
+
+fn ipv6_time_exceeded(
+    packet: &[u8],
+    dst_mac: MacAddr,
+    src_mac: MacAddr,
+    src_ip: Ipv6Addr,
+) -> Vec<u8> {
+    let quoted = &packet[..packet.len().min(IPV6_MIN_MTU - 40 - 8)];
+    let icmp_len = 8 + quoted.len();
+
+    let mut frame = vec![0u8; 14 + 40 + icmp_len];
+    write_ethernet_header(&mut frame, dst_mac, src_mac, EtherTypes::Ipv6);
+
+    let mut dst = [0u8; 16];
+    dst.copy_from_slice(&packet[8..24]);
+    let dst_ip = Ipv6Addr::from(dst);
+
+    let ip = &mut frame[14..];
+    ip[0] = 0x60;
+    ip[4..6].copy_from_slice(&(icmp_len as u16).to_be_bytes());
+    ip[6] = IPPROTO_ICMPV6;
+    ip[7] = DEFAULT_TTL;
+    ip[8..24].copy_from_slice(&src_ip.octets());
+    ip[24..40].copy_from_slice(&dst);
+
+    let icmp = &mut ip[40..];
+    icmp[0] = ICMPV6_TIME_EXCEEDED;
+    icmp[8..].copy_from_slice(quoted);
+    let pseudo = checksum::ipv6_pseudo_header(src_ip, dst_ip, IPPROTO_ICMPV6, icmp_len as u32);
+    let sum = checksum::finish(checksum::add(pseudo, icmp));
+    icmp[2..4].copy_from_slice(&sum.to_be_bytes());
+
+    frame
+}
+
+fn write_ethernet_header(frame: &mut [u8], dst: MacAddr, src: MacAddr, ethertype: EtherType) {
+    let mut ethernet = MutableEthernetPacket::new(frame).unwrap();
+    ethernet.set_destination(dst);
+    ethernet.set_source(src);
+    ethernet.set_ethertype(ethertype);
+}
+
+/// An `EthernetDataLinkSender` that sets the TTL / hop limit of every outgoing IP packet.
+pub struct TtlSender {
+    inner: Box<dyn EthernetDataLinkSender>,
+    ttl: u8,
+    buffer: Vec<u8>,
+}
+
+impl TtlSender {
+    /// Wrap `inner`, setting the TTL of outgoing packets to `ttl`.
+    pub fn new(inner: Box<dyn EthernetDataLinkSender>, ttl: u8) -> TtlSender {
+        TtlSender {
+            inner,
+            ttl,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Return the TTL applied to outgoing packets.
+    pub fn ttl(&self) -> u8 {
+        self.ttl
+    }
+
+    /// Change the TTL applied to outgoing packets.
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl;
+    }
+}
+
+impl EthernetDataLinkSender for TtlSender {
+    fn send_to(
+        &mut self,
+        packet: &EthernetPacket,
+        dst: Option<NetworkInterface>,
+    ) -> Option<io::Result<()>> {
+        self.buffer.clear();
+        self.buffer.extend_from_slice(packet.packet());
+        let mut rewritten = MutableEthernetPacket::new(&mut self.buffer[..])?;
+        set_frame_ttl(&mut rewritten, self.ttl);
+        self.inner.send_to(&rewritten.to_immutable(), dst)
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        self.inner.raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate;
+    use std::net::SocketAddrV4;
+
+    const HOST: MacAddr = MacAddr(0x02, 0, 0, 0, 0, 2);
+
+    fn router() -> RouterAddr {
+        RouterAddr {
+            mac: MacAddr(0x02, 0, 0, 0, 0, 1),
+            ipv4: Some(Ipv4Addr::new(10, 0, 0, 1)),
+            ipv6: None,
+        }
+    }
+
+    // a datagram from the host to a far address, with `ttl` hops left
+    fn datagram(ttl: u8) -> Vec<u8> {
+        let from = SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 2), 33434);
+        let to = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 33434);
+        let mut frame = generate::udp_frame(HOST, router().mac, from, to, b"probe");
+        set_frame_ttl(&mut MutableEthernetPacket::new(&mut frame).unwrap(), ttl);
+        frame
+    }
+
+    #[test]
+    fn forwarding_decrements() {
+        let mut frame = datagram(5);
+        let hop = forward(
+            &mut MutableEthernetPacket::new(&mut frame).unwrap(),
+            &router(),
+        );
+        assert_eq!(hop, Hop::Forward);
+        assert_eq!(frame_ttl(&EthernetPacket::new(&frame).unwrap()), Some(4));
+        assert_eq!(checksum::checksum(&frame[14..34]), 0);
+    }
+
+    #[test]
+    fn expiry_is_answered_with_time_exceeded() {
+        let mut frame = datagram(1);
+        let original = frame.clone();
+        let error = match forward(
+            &mut MutableEthernetPacket::new(&mut frame).unwrap(),
+            &router(),
+        ) {
+            Hop::Expired(error) => error,
+            hop => panic!("{:?}", hop),
+        };
+        let ethernet = EthernetPacket::new(&error).unwrap();
+        assert_eq!(ethernet.get_destination(), HOST);
+        assert_eq!(ethernet.get_source(), router().mac);
+        let ip = ethernet.payload();
+        assert_eq!(checksum::checksum(&ip[..20]), 0);
+        assert_eq!(ip[9], IPPROTO_ICMP);
+        assert_eq!(ip[12..16], [10, 0, 0, 1]);
+        assert_eq!(ip[16..20], [10, 0, 0, 2]);
+        let icmp = &ip[20..];
+        assert_eq!((icmp[0], icmp[1]), (ICMP_TIME_EXCEEDED, 0));
+        assert_eq!(checksum::checksum(icmp), 0);
+        // the header and first 8 bytes of what expired
+        assert_eq!(icmp[8..], original[14..14 + 28]);
+    }
+
+    #[test]
+    fn errors_are_not_answered() {
+        let mut frame = datagram(1);
+        // an ICMP Destination Unreachable
+        frame[14 + 9] = IPPROTO_ICMP;
+        frame[34] = 3;
+        let hop = forward(
+            &mut MutableEthernetPacket::new(&mut frame).unwrap(),
+            &router(),
+        );
+        assert_eq!(hop, Hop::Dropped);
+
+        let mut frame = datagram(1);
+        frame[30..34].copy_from_slice(&[224, 0, 0, 251]);
+        let hop = forward(
+            &mut MutableEthernetPacket::new(&mut frame).unwrap(),
+            &router(),
+        );
+        assert_eq!(hop, Hop::Dropped);
+    }
+}
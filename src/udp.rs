@@ -0,0 +1,186 @@
+//! UDP datagrams.
+//!
+//! The checksum covers a pseudo-header of the enclosing IP packet, so [`ipv4_checksum`]
+//! and [`ipv6_checksum`] take the addresses alongside the datagram. A zero checksum field
+//! means the sender computed none, which IPv4 allows and IPv6 doesn't.
+
+use crate::{
+    arp::ether::{FromPacket, Packet},
+    checksum,
+    ipproto::IPPROTO_UDP,
+};
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Length of the header.
+pub const HEADER_LEN: usize = 8;
+
+fn header_len(_data: &[u8]) -> usize {
+    HEADER_LEN
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    match usize::from(u16::from_be_bytes([data[4], data[5]])) {
+        // jumbograms and offloaded datagrams leave the length to the IP layer
+        len if len < HEADER_LEN => data.len(),
+        len => len,
+    }
+}
+
+packet_types! {
+    /// A UDP datagram. The payload ends where the length field says.
+    pub struct UdpPacket / MutableUdpPacket;
+    minimum_size = HEADER_LEN;
+    header_len = header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the source port
+        #[inline]
+        pub fn get_source(&self) -> u16 {
+            u16::from_be_bytes([self.packet[0], self.packet[1]])
+        }
+        /// Get the destination port
+        #[inline]
+        pub fn get_destination(&self) -> u16 {
+            u16::from_be_bytes([self.packet[2], self.packet[3]])
+        }
+        /// Get the length field, header included
+        #[inline]
+        pub fn get_length(&self) -> u16 {
+            u16::from_be_bytes([self.packet[4], self.packet[5]])
+        }
+        /// Get the checksum field, zero if the sender computed none
+        #[inline]
+        pub fn get_checksum(&self) -> u16 {
+            u16::from_be_bytes([self.packet[6], self.packet[7]])
+        }
+    }
+    setters {
+        /// Set the source port
+        #[inline]
+        pub fn set_source(&mut self, val: u16) {
+            self.packet[0..2].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the destination port
+        #[inline]
+        pub fn set_destination(&mut self, val: u16) {
+            self.packet[2..4].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the length field
+        #[inline]
+        pub fn set_length(&mut self, val: u16) {
+            self.packet[4..6].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Set the checksum field
+        #[inline]
+        pub fn set_checksum(&mut self, val: u16) {
+            self.packet[6..8].copy_from_slice(&val.to_be_bytes());
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let len = vals.len().min(self.packet.len() - HEADER_LEN);
+            self.packet[HEADER_LEN..HEADER_LEN + len].copy_from_slice(&vals[..len]);
+        }
+        /// Populates the packet using a `Udp` structure.
+        pub fn populate(&mut self, packet: &Udp) {
+            self.set_source(packet.source);
+            self.set_destination(packet.destination);
+            self.set_length(packet.length);
+            self.set_checksum(packet.checksum);
+            self.set_payload(&packet.payload);
+        }
+    }
+}
+
+impl<'a> UdpPacket<'a> {
+    /// The size (in bytes) of a Udp instance when converted into a byte-array.
+    pub fn packet_size(packet: &Udp) -> usize {
+        HEADER_LEN + packet.payload.len()
+    }
+}
+
+impl<'a> MutableUdpPacket<'a> {
+    /// The size (in bytes) of a Udp instance when converted into a byte-array.
+    pub fn packet_size(packet: &Udp) -> usize {
+        HEADER_LEN + packet.payload.len()
+    }
+}
+
+// the datagram with the checksum field taken out, a zero result sent as all ones
+fn sum(packet: &UdpPacket, pseudo: u32) -> u16 {
+    let data = &packet.packet()[..packet_len(packet.packet()).min(packet.packet().len())];
+    let sum = checksum::add(pseudo, &data[..6]);
+    match checksum::finish(checksum::add(sum, &data[8..])) {
+        0 => 0xffff,
+        value => value,
+    }
+}
+
+/// The checksum of `packet` carried in an IPv4 packet from `source` to `destination`.
+pub fn ipv4_checksum(packet: &UdpPacket, source: Ipv4Addr, destination: Ipv4Addr) -> u16 {
+    let len = packet.get_length();
+    sum(
+        packet,
+        checksum::ipv4_pseudo_header(source, destination, IPPROTO_UDP, len),
+    )
+}
+
+/// The checksum of `packet` carried in an IPv6 packet from `source` to `destination`.
+pub fn ipv6_checksum(packet: &UdpPacket, source: Ipv6Addr, destination: Ipv6Addr) -> u16 {
+    let len = u32::from(packet.get_length());
+    sum(
+        packet,
+        checksum::ipv6_pseudo_header(source, destination, IPPROTO_UDP, len),
+    )
+}
+
+/// A UDP datagram, owned.
+#[derive(Clone, Debug)]
+pub struct Udp {
+    pub source: u16,
+    pub destination: u16,
+    pub length: u16,
+    pub checksum: u16,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for UdpPacket<'p> {
+    type T = Udp;
+    fn from_packet(&self) -> Udp {
+        Udp {
+            source: self.get_source(),
+            destination: self.get_destination(),
+            length: self.get_length(),
+            checksum: self.get_checksum(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableUdpPacket<'p> {
+    type T = Udp;
+    fn from_packet(&self) -> Udp {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for UdpPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "UdpPacket {{ source : {}, destination : {}, length : {}, checksum : {:#06x} }}",
+            self.get_source(),
+            self.get_destination(),
+            self.get_length(),
+            self.get_checksum()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableUdpPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
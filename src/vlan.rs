@@ -0,0 +1,218 @@
+//! 802.1Q VLAN tags.
+//!
+//! A tag sits between the source address and the ethertype of an Ethernet frame: a TPID
+//! taking the place of the ethertype, then the tag control information and the ethertype
+//! of what follows, which may be another tag. [`VlanPacket`] starts after the TPID, like
+//! the payload of a tagged `EthernetPacket`. QinQ frames stack an 802.1ad service tag on a
+//! customer tag; [`EthernetPacket::vlan_tags`] walks the stack, outermost first.
+
+use crate::arp::ether::{EtherType, EtherTypes, EthernetPacket, FromPacket, Packet};
+use std::fmt;
+
+/// Length of a tag after its TPID.
+pub const HEADER_LEN: usize = 4;
+
+fn header_len(_data: &[u8]) -> usize {
+    HEADER_LEN
+}
+
+fn packet_len(data: &[u8]) -> usize {
+    data.len()
+}
+
+/// Whether `ethertype` is the TPID of a VLAN tag: 802.1Q, 802.1ad, or the 0x9100 of
+/// pre-standard QinQ.
+pub fn is_tag(ethertype: EtherType) -> bool {
+    ethertype == EtherTypes::Vlan
+        || ethertype == EtherTypes::PBridge
+        || ethertype == EtherTypes::QinQ
+}
+
+packet_types! {
+    /// A VLAN tag and what follows it.
+    pub struct VlanPacket / MutableVlanPacket;
+    minimum_size = HEADER_LEN;
+    header_len = header_len;
+    packet_len = packet_len;
+    getters {
+        /// Get the priority code point, the 802.1p class of service
+        #[inline]
+        pub fn get_priority_code_point(&self) -> u8 {
+            self.packet[0] >> 5
+        }
+        /// Get the drop eligible indicator
+        #[inline]
+        pub fn get_drop_eligible_indicator(&self) -> bool {
+            self.packet[0] & 0x10 != 0
+        }
+        /// Get the VLAN identifier
+        #[inline]
+        pub fn get_vlan_identifier(&self) -> u16 {
+            u16::from_be_bytes([self.packet[0], self.packet[1]]) & 0x0fff
+        }
+        /// Get the ethertype of what follows the tag
+        #[inline]
+        pub fn get_ethertype(&self) -> EtherType {
+            EtherType::new(u16::from_be_bytes([self.packet[2], self.packet[3]]))
+        }
+    }
+    setters {
+        /// Set the priority code point; only the three low bits of `val` are kept
+        #[inline]
+        pub fn set_priority_code_point(&mut self, val: u8) {
+            self.packet[0] = (self.packet[0] & 0x1f) | (val & 0x07) << 5;
+        }
+        /// Set the drop eligible indicator
+        #[inline]
+        pub fn set_drop_eligible_indicator(&mut self, val: bool) {
+            self.packet[0] = (self.packet[0] & !0x10) | if val { 0x10 } else { 0 };
+        }
+        /// Set the VLAN identifier; only the twelve low bits of `val` are kept
+        #[inline]
+        pub fn set_vlan_identifier(&mut self, val: u16) {
+            let tci = u16::from_be_bytes([self.packet[0], self.packet[1]]) & 0xf000 | val & 0x0fff;
+            self.packet[0..2].copy_from_slice(&tci.to_be_bytes());
+        }
+        /// Set the ethertype of what follows the tag
+        #[inline]
+        pub fn set_ethertype(&mut self, val: EtherType) {
+            self.packet[2..4].copy_from_slice(&val.value().to_be_bytes());
+        }
+        /// Copy `vals` into the payload
+        pub fn set_payload(&mut self, vals: &[u8]) {
+            let len = vals.len().min(self.packet.len() - HEADER_LEN);
+            self.packet[HEADER_LEN..HEADER_LEN + len].copy_from_slice(&vals[..len]);
+        }
+        /// Populates the packet using a `Vlan` structure.
+        pub fn populate(&mut self, packet: &Vlan) {
+            self.set_priority_code_point(packet.priority_code_point);
+            self.set_drop_eligible_indicator(packet.drop_eligible_indicator);
+            self.set_vlan_identifier(packet.vlan_identifier);
+            self.set_ethertype(packet.ethertype);
+            self.set_payload(&packet.payload);
+        }
+    }
+}
+
+impl<'a> VlanPacket<'a> {
+    /// The size (in bytes) of a Vlan instance when converted into a byte-array.
+    pub fn packet_size(packet: &Vlan) -> usize {
+        HEADER_LEN + packet.payload.len()
+    }
+}
+
+impl<'a> MutableVlanPacket<'a> {
+    /// The size (in bytes) of a Vlan instance when converted into a byte-array.
+    pub fn packet_size(packet: &Vlan) -> usize {
+        HEADER_LEN + packet.payload.len()
+    }
+}
+
+/// A VLAN tag, owned.
+#[derive(Clone, Debug)]
+pub struct Vlan {
+    pub priority_code_point: u8,
+    pub drop_eligible_indicator: bool,
+    pub vlan_identifier: u16,
+    pub ethertype: EtherType,
+    pub payload: Vec<u8>,
+}
+
+impl<'p> FromPacket for VlanPacket<'p> {
+    type T = Vlan;
+    fn from_packet(&self) -> Vlan {
+        Vlan {
+            priority_code_point: self.get_priority_code_point(),
+            drop_eligible_indicator: self.get_drop_eligible_indicator(),
+            vlan_identifier: self.get_vlan_identifier(),
+            ethertype: self.get_ethertype(),
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+impl<'p> FromPacket for MutableVlanPacket<'p> {
+    type T = Vlan;
+    fn from_packet(&self) -> Vlan {
+        self.to_immutable().from_packet()
+    }
+}
+
+impl<'p> fmt::Debug for VlanPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "VlanPacket {{ priority_code_point : {}, drop_eligible_indicator : {}, \
+             vlan_identifier : {}, ethertype : {} }}",
+            self.get_priority_code_point(),
+            self.get_drop_eligible_indicator(),
+            self.get_vlan_identifier(),
+            self.get_ethertype()
+        )
+    }
+}
+
+impl<'p> fmt::Debug for MutableVlanPacket<'p> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Mutable{:?}", self.to_immutable())
+    }
+}
+
+/// The VLAN tags of a frame, outermost first. See [`EthernetPacket::vlan_tags`].
+#[derive(Clone, Debug)]
+pub struct VlanTags<'a> {
+    /// Ethertype, or TPID, of `rest`
+    ethertype: EtherType,
+    rest: &'a [u8],
+}
+
+impl<'a> VlanTags<'a> {
+    /// The ethertype of what follows the tags iterated so far.
+    pub fn ethertype(&self) -> EtherType {
+        self.ethertype
+    }
+
+    /// What follows the tags iterated so far.
+    pub fn rest(&self) -> &'a [u8] {
+        self.rest
+    }
+}
+
+impl<'a> Iterator for VlanTags<'a> {
+    type Item = VlanPacket<'a>;
+
+    fn next(&mut self) -> Option<VlanPacket<'a>> {
+        if !is_tag(self.ethertype) {
+            return None;
+        }
+        let tag = VlanPacket::new(self.rest)?;
+        self.ethertype = tag.get_ethertype();
+        self.rest = &self.rest[HEADER_LEN..];
+        Some(tag)
+    }
+}
+
+impl<'p> EthernetPacket<'p> {
+    /// The VLAN tags of the frame, outermost first; none if it isn't tagged.
+    pub fn vlan_tags(&self) -> VlanTags<'_> {
+        VlanTags {
+            ethertype: self.get_ethertype(),
+            rest: self.payload(),
+        }
+    }
+
+    /// The ethertype following the VLAN tags, the outer one for untagged frames. A tag cut
+    /// short leaves its TPID.
+    pub fn inner_ethertype(&self) -> EtherType {
+        let mut tags = self.vlan_tags();
+        tags.by_ref().for_each(drop);
+        tags.ethertype()
+    }
+
+    /// The payload following the VLAN tags, of the type `inner_ethertype` tells.
+    pub fn inner_payload(&self) -> &[u8] {
+        let mut tags = self.vlan_tags();
+        tags.by_ref().for_each(drop);
+        tags.rest()
+    }
+}
@@ -0,0 +1,500 @@
+//! VLAN hopping tests.
+//!
+//! Checks whether a switch port keeps its traffic in its VLAN. [`run`] sends ICMP echo
+//! requests to a host of a victim VLAN in a number of crafted variants: double tagged
+//! frames whose outer tag matches the native VLAN of the port, frames tagged with the victim
+//! VLAN directly, and optionally DTP frames trying to turn the port into a trunk. Every
+//! variant carries its own marker, so an echo reply coming back, or the request showing up
+//! on a monitor interface in the victim VLAN, tells which variant got through. Only run it
+//! against switches you are responsible for.
+
+use crate::{
+    arp::{
+        channel::{EthernetDataLinkReceiver, EthernetDataLinkSender},
+        ether::{EtherType, EtherTypes, EthernetPacket, MutableEthernetPacket, Packet},
+        network_interface::MacAddr,
+    },
+    checksum,
+    icmp::{self, EchoPacket, IcmpType, IcmpTypes},
+    ipproto::IPPROTO_ICMP,
+    scan::ports::Ipv4Mac,
+    ttl::DEFAULT_TTL,
+};
+use std::{
+    fmt, io,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const DTP_ADDRESS: MacAddr = MacAddr(0x01, 0x00, 0x0c, 0xcc, 0xcc, 0xcc);
+/// LLC/SNAP header of DTP frames: SNAP, Cisco OUI, protocol 0x2004.
+const DTP_SNAP: [u8; 8] = [0xaa, 0xaa, 0x03, 0x00, 0x00, 0x0c, 0x20, 0x04];
+const DTP_STATUS: u16 = 0x0002;
+/// Bit of the DTP status telling that the port is trunking.
+const DTP_TRUNK: u8 = 0x80;
+
+/// A way of trying to reach the victim VLAN.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Variant {
+    /// An outer tag for the native VLAN, stripped by the first switch, and an inner 802.1Q
+    /// tag for the victim VLAN
+    DoubleTagged {
+        /// TPID of the outer tag
+        outer_tpid: u16,
+    },
+    /// A single 802.1Q tag for the victim VLAN, accepted by misconfigured access ports
+    Tagged,
+    /// Negotiate a trunk with DTP, then send with a single tag for the victim VLAN. The
+    /// probes wait for the port to turn into a trunk and are dropped if it doesn't
+    Dtp,
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variant::DoubleTagged { outer_tpid } => {
+                write!(f, "double tagged (outer {:#06x})", outer_tpid)
+            }
+            Variant::Tagged => write!(f, "tagged"),
+            Variant::Dtp => write!(f, "DTP trunk"),
+        }
+    }
+}
+
+/// Parameters of a VLAN hopping test.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// Native (untagged) VLAN of the port the test runs from. Defaults to 1
+    pub native_vlan: u16,
+
+    /// VLAN the probes try to reach. Defaults to 2
+    pub victim_vlan: u16,
+
+    /// Host in the victim VLAN the echo requests are sent to
+    pub target: Ipv4Addr,
+
+    /// MAC address of the target. Defaults to broadcast, which floods the victim VLAN
+    pub target_mac: MacAddr,
+
+    /// Variants to try, in order. Defaults to double tagging with 0x8100, 0x88a8 and 0x9100
+    /// outer tags and a single tag; DTP has to be asked for
+    pub variants: Vec<Variant>,
+
+    /// Probes sent per variant. Defaults to 3
+    pub count: u32,
+
+    /// Time between probes. Defaults to 200 milliseconds
+    pub interval: Duration,
+
+    /// Time to keep listening after the last probe. Defaults to 3 seconds
+    pub listen: Duration,
+
+    /// Time the switch is given to negotiate a trunk before the DTP variant is given up.
+    /// Defaults to 5 seconds
+    pub negotiation: Duration,
+}
+
+impl Config {
+    /// Default test sending to `target`.
+    pub fn new(target: Ipv4Addr) -> Config {
+        Config {
+            native_vlan: 1,
+            victim_vlan: 2,
+            target,
+            target_mac: MacAddr(0xff, 0xff, 0xff, 0xff, 0xff, 0xff),
+            variants: vec![
+                Variant::DoubleTagged {
+                    outer_tpid: EtherTypes::Vlan.value(),
+                },
+                Variant::DoubleTagged {
+                    outer_tpid: EtherTypes::PBridge.value(),
+                },
+                Variant::DoubleTagged {
+                    outer_tpid: EtherTypes::QinQ.value(),
+                },
+                Variant::Tagged,
+            ],
+            count: 3,
+            interval: Duration::from_millis(200),
+            listen: Duration::from_secs(3),
+            negotiation: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What one variant achieved.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Outcome {
+    /// The variant
+    pub variant: Variant,
+    /// Probes sent
+    pub sent: u32,
+    /// Echo replies received from the target
+    pub replies: u32,
+    /// Probes seen on the monitor interface
+    pub delivered: u32,
+}
+
+impl Outcome {
+    /// Whether anything shows the variant reached the victim VLAN.
+    pub fn hopped(&self) -> bool {
+        self.replies > 0 || self.delivered > 0
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:<28} {} sent, {} replies, {} seen on victim VLAN{}",
+            self.variant.to_string(),
+            self.sent,
+            self.replies,
+            self.delivered,
+            if self.hopped() { "  HOPPED" } else { "" }
+        )
+    }
+}
+
+/// Result of a VLAN hopping test.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// One outcome per variant, in the order they were tried
+    pub outcomes: Vec<Outcome>,
+    /// Whether the port turned into a trunk after DTP negotiation, told by the switch
+    /// announcing it or by tagged frames coming in
+    pub trunk: bool,
+}
+
+impl Report {
+    /// Whether any variant reached the victim VLAN.
+    pub fn isolated(&self) -> bool {
+        !self.trunk && !self.outcomes.iter().any(Outcome::hopped)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for outcome in &self.outcomes {
+            writeln!(f, "{}", outcome)?;
+        }
+        if self.trunk {
+            writeln!(f, "port negotiated a trunk")?;
+        }
+        let verdict = if self.isolated() {
+            "port isolation held"
+        } else {
+            "port isolation BROKEN"
+        };
+        write!(f, "{}", verdict)
+    }
+}
+
+/// Build an ICMP echo request from `source` to `target` wrapped in the VLAN tags given as
+/// (TPID, VLAN ID) pairs, outermost first.
+pub fn tagged_echo_request(
+    source: Ipv4Mac,
+    target: Ipv4Addr,
+    target_mac: MacAddr,
+    tags: &[(u16, u16)],
+    identifier: u16,
+    sequence: u16,
+) -> Vec<u8> {
+    let icmp = icmp::echo_request(identifier, sequence, b"myox vlan hop probe");
+
+    let mut frame = vec![0u8; 14 + 20];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+    ethernet.set_destination(target_mac);
+    ethernet.set_source(source.mac);
+    ethernet.set_ethertype(EtherTypes::Ipv4);
+
+    let ip = &mut frame[14..];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&((20 + icmp.len()) as u16).to_be_bytes());
+    ip[8] = DEFAULT_TTL;
+    ip[9] = IPPROTO_ICMP;
+    ip[12..16].copy_from_slice(&source.ip.octets());
+    ip[16..20].copy_from_slice(&target.octets());
+    let header_checksum = checksum::checksum(&ip[..20]);
+    ip[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+    frame.extend_from_slice(&icmp);
+
+    let mut header = Vec::with_capacity(tags.len() * 4);
+    for &(tpid, vlan) in tags {
+        header.extend_from_slice(&tpid.to_be_bytes());
+        header.extend_from_slice(&(vlan & 0x0fff).to_be_bytes());
+    }
+    frame.splice(12..12, header);
+    frame
+}
+
+/// Build a DTP frame from `source` announcing a desirable trunk for the VTP `domain`.
+pub fn dtp_frame(source: MacAddr, domain: &str) -> Vec<u8> {
+    let tlv = |kind: u16, value: &[u8]| {
+        let mut tlv = kind.to_be_bytes().to_vec();
+        tlv.extend_from_slice(&(4 + value.len() as u16).to_be_bytes());
+        tlv.extend_from_slice(value);
+        tlv
+    };
+    let mut domain = domain.as_bytes().to_vec();
+    domain.push(0);
+
+    let mut payload = DTP_SNAP.to_vec();
+    // version
+    payload.push(1);
+    payload.extend(tlv(0x0001, &domain));
+    // trunk status: desirable
+    payload.extend(tlv(DTP_STATUS, &[0x03]));
+    // trunk type: 802.1Q, negotiated
+    payload.extend(tlv(0x0003, &[0xa5]));
+    payload.extend(tlv(
+        0x0004,
+        &[source.0, source.1, source.2, source.3, source.4, source.5],
+    ));
+
+    let mut frame = vec![0u8; 14];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+    ethernet.set_destination(DTP_ADDRESS);
+    ethernet.set_source(source);
+    // 802.3 length field
+    ethernet.set_ethertype(EtherType::new(payload.len() as u16));
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Try every variant of `config` from `source` and report which of them got through.
+///
+/// Replies are looked for on `rx`, on the port under test. `monitor`, if given, should be
+/// attached to the victim VLAN and is watched for the probes themselves. Both receivers
+/// should be configured with a short read timeout, around 10 milliseconds.
+pub fn run(
+    tx: &mut dyn EthernetDataLinkSender,
+    rx: &mut dyn EthernetDataLinkReceiver,
+    mut monitor: Option<&mut dyn EthernetDataLinkReceiver>,
+    source: Ipv4Mac,
+    config: &Config,
+    stop: &AtomicBool,
+) -> io::Result<Report> {
+    let identifier = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u16;
+    let mut report = Report {
+        outcomes: config
+            .variants
+            .iter()
+            .map(|&variant| Outcome {
+                variant,
+                sent: 0,
+                replies: 0,
+                delivered: 0,
+            })
+            .collect(),
+        trunk: false,
+    };
+    let dtp = config.variants.contains(&Variant::Dtp);
+    if dtp {
+        send(tx, &dtp_frame(source.mac, ""))?;
+    }
+    let negotiation_end = Instant::now() + config.negotiation;
+
+    let mut rx_iter = rx.iter();
+    let mut monitor_iter = monitor.as_mut().map(|monitor| monitor.iter());
+    let mut remaining = vec![config.count; config.variants.len()];
+    let mut next = Instant::now();
+    let mut done_at = None;
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        match done_at {
+            Some(done_at) if now.duration_since(done_at) >= config.listen => break,
+            Some(_) => {}
+            None if remaining.iter().all(|&left| left == 0) => done_at = Some(now),
+            None if now >= next => {
+                // variants take turns so that a single dropped burst doesn't hide one
+                for (index, &variant) in config.variants.iter().enumerate() {
+                    if remaining[index] == 0 {
+                        continue;
+                    }
+                    // without a trunk the probes would be those of `Tagged`
+                    if variant == Variant::Dtp && !report.trunk {
+                        if now >= negotiation_end {
+                            remaining[index] = 0;
+                        }
+                        continue;
+                    }
+                    let frame = tagged_echo_request(
+                        source,
+                        config.target,
+                        config.target_mac,
+                        &tags(config, variant),
+                        identifier,
+                        index as u16,
+                    );
+                    send(tx, &frame)?;
+                    report.outcomes[index].sent += 1;
+                    remaining[index] -= 1;
+                }
+                next = now + config.interval;
+                // DTP is refreshed regularly by real switches too
+                if dtp {
+                    send(tx, &dtp_frame(source.mac, ""))?;
+                }
+            }
+            None => {}
+        }
+
+        match rx_iter.next() {
+            Ok(packet) => {
+                // the probes of the other variants may come back in the capture
+                if dtp
+                    && packet.get_source() != source.mac
+                    && (is_tagged(&packet) || is_trunk_dtp(&packet))
+                {
+                    report.trunk = true;
+                }
+                if let Some((IcmpTypes::EchoReply, id, seq)) = echo(&packet) {
+                    if id == identifier {
+                        if let Some(outcome) = report.outcomes.get_mut(seq as usize) {
+                            outcome.replies += 1;
+                        }
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+        if let Some(monitor_iter) = monitor_iter.as_mut() {
+            match monitor_iter.next() {
+                Ok(packet) => {
+                    if let Some((IcmpTypes::EchoRequest, id, seq)) = echo(&packet) {
+                        if id == identifier {
+                            if let Some(outcome) = report.outcomes.get_mut(seq as usize) {
+                                outcome.delivered += 1;
+                            }
+                        }
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// VLAN tags of a variant, outermost first.
+fn tags(config: &Config, variant: Variant) -> Vec<(u16, u16)> {
+    match variant {
+        Variant::DoubleTagged { outer_tpid } => vec![
+            (outer_tpid, config.native_vlan),
+            (EtherTypes::Vlan.value(), config.victim_vlan),
+        ],
+        Variant::Tagged | Variant::Dtp => vec![(EtherTypes::Vlan.value(), config.victim_vlan)],
+    }
+}
+
+fn send(tx: &mut dyn EthernetDataLinkSender, frame: &[u8]) -> io::Result<()> {
+    tx.send_to(&EthernetPacket::new(frame).unwrap(), None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+}
+
+fn is_tagged(packet: &EthernetPacket) -> bool {
+    match packet.get_ethertype() {
+        EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ => true,
+        _ => false,
+    }
+}
+
+/// Whether `packet` is a DTP frame of a port that is trunking.
+fn is_trunk_dtp(packet: &EthernetPacket) -> bool {
+    let frame = packet.packet();
+    if packet.get_destination() != DTP_ADDRESS || frame.get(14..22) != Some(&DTP_SNAP[..]) {
+        return false;
+    }
+    // TLVs follow the version
+    let mut tlvs = frame.get(23..).unwrap_or(&[]);
+    while tlvs.len() >= 4 {
+        let kind = u16::from_be_bytes([tlvs[0], tlvs[1]]);
+        let len = u16::from_be_bytes([tlvs[2], tlvs[3]]) as usize;
+        if len < 4 || len > tlvs.len() {
+            return false;
+        }
+        if kind == DTP_STATUS {
+            return len > 4 && tlvs[4] & DTP_TRUNK != 0;
+        }
+        tlvs = &tlvs[len..];
+    }
+    false
+}
+
+/// ICMP type, identifier and sequence number of an echo message, tagged or not.
+fn echo(packet: &EthernetPacket) -> Option<(IcmpType, u16, u16)> {
+    let mut frame = packet.packet();
+    let mut offset = 12;
+    loop {
+        let ethertype = u16::from_be_bytes([*frame.get(offset)?, *frame.get(offset + 1)?]);
+        match EtherType::new(ethertype) {
+            EtherTypes::Vlan | EtherTypes::PBridge | EtherTypes::QinQ => offset += 4,
+            EtherTypes::Ipv4 => break,
+            _ => return None,
+        }
+    }
+    frame = frame.get(offset + 2..)?;
+    let ihl = (*frame.first()? & 0x0f) as usize * 4;
+    if ihl < 20 || frame.len() < ihl + 8 || frame[9] != IPPROTO_ICMP {
+        return None;
+    }
+    let echo = EchoPacket::new(&frame[ihl..])?;
+    if echo.is_request() || echo.is_reply() {
+        Some((
+            echo.get_icmp_type(),
+            echo.get_identifier(),
+            echo.get_sequence_number(),
+        ))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: Ipv4Mac = Ipv4Mac {
+        mac: MacAddr(0x02, 0, 0, 0, 0, 0x01),
+        ip: Ipv4Addr::new(10, 0, 0, 1),
+    };
+
+    #[test]
+    fn echo_through_tags() {
+        let tags = [(0x88a8, 1), (0x8100, 2)];
+        let frame =
+            tagged_echo_request(SOURCE, Ipv4Addr::new(10, 0, 0, 2), DTP_ADDRESS, &tags, 7, 3);
+        let packet = EthernetPacket::new(&frame).unwrap();
+        assert!(is_tagged(&packet));
+        assert_eq!(echo(&packet), Some((IcmpTypes::EchoRequest, 7, 3)));
+    }
+
+    #[test]
+    fn echo_rejects_short_header_length() {
+        let mut frame =
+            tagged_echo_request(SOURCE, Ipv4Addr::new(10, 0, 0, 2), DTP_ADDRESS, &[], 7, 3);
+        frame[14] = 0x40;
+        assert_eq!(echo(&EthernetPacket::new(&frame).unwrap()), None);
+        // long enough for an echo header, too short for an IPv4 one
+        frame.truncate(14 + 8);
+        assert_eq!(echo(&EthernetPacket::new(&frame).unwrap()), None);
+    }
+
+    #[test]
+    fn trunk_status() {
+        let mut frame = dtp_frame(SOURCE.mac, "lab");
+        assert!(!is_trunk_dtp(&EthernetPacket::new(&frame).unwrap()));
+        // the status follows the version and the domain TLV
+        frame[23 + 8 + 4] = 0x81;
+        assert!(is_trunk_dtp(&EthernetPacket::new(&frame).unwrap()));
+        frame.truncate(23 + 8 + 2);
+        assert!(!is_trunk_dtp(&EthernetPacket::new(&frame).unwrap()));
+    }
+}
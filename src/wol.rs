@@ -0,0 +1,55 @@
+//! Wake-on-LAN.
+//!
+//! A magic packet is six `0xff` bytes followed by sixteen repetitions of the target's MAC
+//! address. It is sent as a broadcast Ethernet frame with the Wake-on-LAN ethertype, which
+//! NICs in a low power state look for regardless of the upper layers.
+
+use crate::arp::{
+    channel::{channel, Channel},
+    ether::{EtherTypes, EthernetPacket, MutableEthernetPacket},
+    network_interface::{MacAddr, NetworkInterface},
+};
+use std::io;
+
+/// Length of the magic packet payload.
+pub const MAGIC_PACKET_LEN: usize = 6 + 16 * 6;
+
+/// Build the payload of a magic packet waking `target`.
+pub fn magic_packet(target: MacAddr) -> [u8; MAGIC_PACKET_LEN] {
+    let mac = [target.0, target.1, target.2, target.3, target.4, target.5];
+    let mut payload = [0xffu8; MAGIC_PACKET_LEN];
+    for chunk in payload[6..].chunks_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    payload
+}
+
+/// Build a complete Ethernet frame carrying a magic packet for `target`.
+pub fn magic_frame(source: MacAddr, target: MacAddr) -> Vec<u8> {
+    let mut frame = vec![0u8; 14 + MAGIC_PACKET_LEN];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame[..]).unwrap();
+    ethernet.set_destination(MacAddr::new(0xff, 0xff, 0xff, 0xff, 0xff, 0xff));
+    ethernet.set_source(source);
+    ethernet.set_ethertype(EtherTypes::WakeOnLan);
+    ethernet.set_payload(&magic_packet(target));
+    frame
+}
+
+/// Broadcast a magic packet for `target` on `interface`.
+pub fn wake(interface: &NetworkInterface, target: MacAddr) -> io::Result<()> {
+    let source = interface.mac.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("interface {} has no MAC address", interface.name),
+        )
+    })?;
+    let (mut tx, _) = match channel(interface, Default::default())? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Err(io::Error::new(io::ErrorKind::Other, "Unknown channel type")),
+    };
+
+    let frame = magic_frame(source, target);
+    let packet = EthernetPacket::new(&frame[..]).unwrap();
+    tx.send_to(&packet, None)
+        .unwrap_or_else(|| Err(io::Error::new(io::ErrorKind::Other, "Frame not sent")))
+}